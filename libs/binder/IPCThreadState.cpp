@@ -1123,6 +1123,9 @@ status_t IPCThreadState::waitForResponse(Parcel *reply, status_t *acquireResult)
                             reinterpret_cast<const binder_size_t*>(tr.data.ptr.offsets),
                             tr.offsets_size/sizeof(binder_size_t),
                             freeBuffer);
+                        // The reply was sent with TF_CLEAR_BUF, so our copies of it
+                        // should be zero'd as well.
+                        if (tr.flags & TF_CLEAR_BUF) reply->markSensitive();
                     } else {
                         err = *reinterpret_cast<const status_t*>(tr.data.ptr.buffer);
                         freeBuffer(reinterpret_cast<const uint8_t*>(tr.data.ptr.buffer),
@@ -1493,7 +1496,9 @@ status_t IPCThreadState::executeCommand(int32_t cmd)
                 buffer.setDataSize(0);
 
                 constexpr uint32_t kForwardReplyFlags = TF_CLEAR_BUF;
-                sendReply(reply, (tr.flags & kForwardReplyFlags));
+                uint32_t replyFlags = tr.flags & kForwardReplyFlags;
+                if (reply.isSensitive()) replyFlags |= TF_CLEAR_BUF;
+                sendReply(reply, replyFlags);
             } else {
                 if (error != OK) {
                     std::ostringstream logStream;
@@ -181,9 +181,19 @@ private:
 [[clang::no_destroy]] static std::mutex gAccessorProvidersMutex;
 [[clang::no_destroy]] static std::vector<AccessorProviderEntry> gAccessorProviders;
 
+// Connection info providers only fill in the sockaddr, so recover the length the address would
+// have been bound with. An abstract name (leading NUL byte) ends at its last non-NUL byte,
+// anything else is a NUL-terminated filesystem path.
+static socklen_t unixAddrLen(const sockaddr_un& addr) {
+    if (addr.sun_path[0] != '\0') return sizeof(sockaddr_un);
+    size_t nameLen = sizeof(addr.sun_path) - 1;
+    while (nameLen > 0 && addr.sun_path[nameLen] == '\0') nameLen--;
+    return offsetof(sockaddr_un, sun_path) + 1 + nameLen;
+}
+
 class LocalAccessor : public android::os::BnAccessor {
 public:
-    LocalAccessor(const String16& instance, RpcSocketAddressProvider&& connectionInfoProvider)
+    LocalAccessor(const String16& instance, RpcConnectionProvider&& connectionInfoProvider)
           : mInstance(instance), mConnectionInfoProvider(std::move(connectionInfoProvider)) {
         LOG_ALWAYS_FATAL_IF(!mConnectionInfoProvider,
                             "LocalAccessor object needs a valid connection info provider");
@@ -198,9 +208,10 @@ public:
         sockaddr_storage addrStorage;
         std::unique_ptr<FdTrigger> trigger = FdTrigger::make();
         RpcTransportFd fd;
+        binder::unique_fd preconnectedFd;
         status_t status =
                 mConnectionInfoProvider(mInstance, reinterpret_cast<sockaddr*>(&addrStorage),
-                                        sizeof(addrStorage));
+                                        sizeof(addrStorage), &preconnectedFd);
         if (status != OK) {
             const std::string error = "The connection info provider was unable to provide "
                                       "connection info for instance " +
@@ -210,13 +221,18 @@ public:
             return Status::fromServiceSpecificError(IAccessor::ERROR_CONNECTION_INFO_NOT_FOUND,
                                                     error.c_str());
         }
+        if (preconnectedFd.ok()) {
+            *outFd = os::ParcelFileDescriptor(std::move(preconnectedFd));
+            return Status::ok();
+        }
         if (addrStorage.ss_family == AF_VSOCK) {
             sockaddr_vm* addr = reinterpret_cast<sockaddr_vm*>(&addrStorage);
             status = singleSocketConnection(VsockSocketAddress(addr->svm_cid, addr->svm_port),
                                             trigger, &fd);
         } else if (addrStorage.ss_family == AF_UNIX) {
             sockaddr_un* addr = reinterpret_cast<sockaddr_un*>(&addrStorage);
-            status = singleSocketConnection(UnixSocketAddress(addr->sun_path), trigger, &fd);
+            status = singleSocketConnection(UnixSocketAddress(*addr, unixAddrLen(*addr)), trigger,
+                                            &fd);
         } else if (addrStorage.ss_family == AF_INET) {
             sockaddr_in* addr = reinterpret_cast<sockaddr_in*>(&addrStorage);
             status = singleSocketConnection(InetSocketAddress(reinterpret_cast<sockaddr*>(addr),
@@ -224,6 +240,14 @@ public:
                                                               inet_ntoa(addr->sin_addr),
                                                               ntohs(addr->sin_port)),
                                             trigger, &fd);
+        } else if (addrStorage.ss_family == AF_INET6) {
+            sockaddr_in6* addr = reinterpret_cast<sockaddr_in6*>(&addrStorage);
+            char addrString[INET6_ADDRSTRLEN] = {};
+            inet_ntop(AF_INET6, &addr->sin6_addr, addrString, sizeof(addrString));
+            status = singleSocketConnection(InetSocketAddress(reinterpret_cast<sockaddr*>(addr),
+                                                              sizeof(sockaddr_in6), addrString,
+                                                              ntohs(addr->sin6_port)),
+                                            trigger, &fd);
         } else {
             const std::string error =
                     "Unsupported socket family type or the ConnectionInfoProvider failed to find a "
@@ -258,10 +282,31 @@ public:
 private:
     LocalAccessor() = delete;
     String16 mInstance;
-    RpcSocketAddressProvider mConnectionInfoProvider;
+    RpcConnectionProvider mConnectionInfoProvider;
     std::function<void()> mOnDelete;
 };
 
+// Reports its own instance name, but gets connections from another Accessor.
+class AccessorDelegator : public android::os::BnAccessor {
+public:
+    AccessorDelegator(const String16& instance, const sp<IAccessor>& accessor)
+          : mInstance(instance), mAccessor(accessor) {}
+
+    ::android::binder::Status addConnection(::android::os::ParcelFileDescriptor* outFd) {
+        return mAccessor->addConnection(outFd);
+    }
+
+    ::android::binder::Status getInstanceName(String16* instance) {
+        *instance = mInstance;
+        return Status::ok();
+    }
+
+private:
+    AccessorDelegator() = delete;
+    String16 mInstance;
+    sp<IAccessor> mAccessor;
+};
+
 android::binder::Status getInjectedAccessor(const std::string& name,
                                             android::os::Service* service) {
     std::vector<AccessorProviderEntry> copiedProviders;
@@ -375,6 +420,21 @@ status_t validateAccessor(const String16& instance, const sp<IBinder>& binder) {
     return OK;
 }
 
+status_t delegateAccessor(const String16& name, const sp<IBinder>& accessor,
+                          sp<IBinder>* delegator) {
+    if (accessor == nullptr || delegator == nullptr) {
+        ALOGE("The accessor and delegator must not be null");
+        return BAD_VALUE;
+    }
+    // interface_cast never fails for a remote binder, so check the descriptor instead.
+    if (accessor->getInterfaceDescriptor() != IAccessor::descriptor) {
+        ALOGE("This binder for %s is not an IAccessor binder", String8(name).c_str());
+        return BAD_TYPE;
+    }
+    *delegator = sp<AccessorDelegator>::make(name, interface_cast<IAccessor>(accessor));
+    return OK;
+}
+
 sp<IBinder> createAccessor(const String16& instance,
                            RpcSocketAddressProvider&& connectionInfoProvider) {
     // Try to create a new accessor
@@ -384,7 +444,20 @@ sp<IBinder> createAccessor(const String16& instance,
               String8(instance).c_str());
         return nullptr;
     }
-    sp<IBinder> binder = sp<LocalAccessor>::make(instance, std::move(connectionInfoProvider));
+    return createAccessor(instance,
+                          [provider = std::move(connectionInfoProvider)](
+                                  const String16& name, sockaddr* outAddr, socklen_t addrSize,
+                                  binder::unique_fd*) { return provider(name, outAddr, addrSize); });
+}
+
+sp<IBinder> createAccessor(const String16& instance, RpcConnectionProvider&& connectionProvider) {
+    if (!connectionProvider) {
+        ALOGE("Could not find an Accessor for %s and no ConnectionProvider provided to create a "
+              "new one",
+              String8(instance).c_str());
+        return nullptr;
+    }
+    sp<IBinder> binder = sp<LocalAccessor>::make(instance, std::move(connectionProvider));
     return binder;
 }
 
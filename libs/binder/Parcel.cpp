@@ -111,6 +111,71 @@ static_assert(sizeof(Parcel) == 60);
 static std::atomic<size_t> gParcelGlobalAllocCount;
 static std::atomic<size_t> gParcelGlobalAllocSize;
 
+// Data buffers of Parcels freed on a thread which enabled its buffer pool, kept
+// for the next Parcels which start writing on that thread.
+namespace {
+#ifndef __TRUSTY__
+constexpr size_t kBufferPoolMaxCapacity = 4096;
+constexpr size_t kBufferPoolMaxBuffers = 8;
+
+struct BufferPool {
+    struct Buffer {
+        uint8_t* data;
+        size_t capacity;
+    };
+
+    bool enabled = false;
+    size_t count = 0;
+    Buffer buffers[kBufferPoolMaxBuffers];
+
+    // Parcels can still be freed on this thread after this runs, e.g. from the destructors of
+    // pthread keys, which run after those of thread_locals. The storage stays around until the
+    // thread exits, so disable the pool to have them freed instead of kept.
+    ~BufferPool() {
+        enabled = false;
+        clear();
+    }
+
+    void clear() {
+        while (count > 0) free(buffers[--count].data);
+    }
+
+    // Takes a buffer of at least `desired` bytes, if there is one.
+    uint8_t* take(size_t desired, size_t* outCapacity) {
+        for (size_t i = count; i > 0; i--) {
+            Buffer buffer = buffers[i - 1];
+            if (buffer.capacity < desired) continue;
+            buffers[i - 1] = buffers[--count];
+            *outCapacity = buffer.capacity;
+            return buffer.data;
+        }
+        return nullptr;
+    }
+
+    // Keeps `data` for reuse, or returns false if it should be freed.
+    bool put(uint8_t* data, size_t capacity) {
+        if (!enabled || capacity > kBufferPoolMaxCapacity || count == kBufferPoolMaxBuffers) {
+            return false;
+        }
+        buffers[count++] = {data, capacity};
+        return true;
+    }
+};
+
+thread_local BufferPool tBufferPool;
+#else
+// Trusty has no thread-local storage for the pool, so it is never enabled.
+struct BufferPool {
+    bool enabled = false;
+    void clear() {}
+    uint8_t* take(size_t, size_t*) { return nullptr; }
+    bool put(uint8_t*, size_t) { return false; }
+};
+
+BufferPool tBufferPool;
+#endif // __TRUSTY__
+} // namespace
+
 // Maximum number of file descriptors per Parcel.
 constexpr size_t kMaxFds = 1024;
 
@@ -409,6 +474,11 @@ size_t Parcel::getGlobalAllocCount() {
     return gParcelGlobalAllocCount.load();
 }
 
+void Parcel::setThreadBufferPoolEnabled(bool enabled) {
+    tBufferPool.enabled = enabled;
+    if (!enabled) tBufferPool.clear();
+}
+
 const uint8_t* Parcel::data() const
 {
     return mData;
@@ -2933,7 +3003,7 @@ void Parcel::freeDataNoInit()
             if (mDeallocZero) {
                 zeroMemory(mData, mDataSize);
             }
-            free(mData);
+            if (!tBufferPool.put(mData, mDataCapacity)) free(mData);
         }
         auto* kernelFields = maybeKernelFields();
         if (kernelFields && kernelFields->mObjects) free(kernelFields->mObjects);
@@ -3200,7 +3270,9 @@ status_t Parcel::continueWrite(size_t desired)
 
     } else {
         // This is the first data.  Easy!
-        uint8_t* data = (uint8_t*)malloc(desired);
+        size_t capacity = desired;
+        uint8_t* data = tBufferPool.take(desired, &capacity);
+        if (!data) data = (uint8_t*)malloc(desired);
         if (!data) {
             mError = NO_MEMORY;
             return NO_MEMORY;
@@ -3214,15 +3286,15 @@ status_t Parcel::continueWrite(size_t desired)
                   kernelFields ? kernelFields->mObjectsCapacity : 0, desired);
         }
 
-        LOG_ALLOC("Parcel %p: allocating with %zu capacity", this, desired);
-        gParcelGlobalAllocSize += desired;
+        LOG_ALLOC("Parcel %p: allocating with %zu capacity", this, capacity);
+        gParcelGlobalAllocSize += capacity;
         gParcelGlobalAllocCount++;
 
         mData = data;
         mDataSize = mDataPos = 0;
         ALOGV("continueWrite Setting data size of %p to %zu", this, mDataSize);
         ALOGV("continueWrite Setting data pos of %p to %zu", this, mDataPos);
-        mDataCapacity = desired;
+        mDataCapacity = capacity;
     }
 
     return NO_ERROR;
@@ -188,6 +188,15 @@ void RpcServer::setServerSocketModifier(std::function<void(borrowed_fd)>&& modif
     mServerSocketModifier = std::move(modifier);
 }
 
+void RpcServer::setSessionListener(
+        std::function<void(const sp<RpcSession>&, const void*, size_t)>&& onSessionStarted,
+        std::function<void(const sp<RpcSession>&)>&& onSessionEnded) {
+    RpcMutexLockGuard _l(mLock);
+    LOG_ALWAYS_FATAL_IF(mShutdownTrigger != nullptr, "Already joined");
+    mOnSessionStarted = std::move(onSessionStarted);
+    mOnSessionEnded = std::move(onSessionEnded);
+}
+
 sp<IBinder> RpcServer::getRootObject() {
     RpcMutexLockGuard _l(mLock);
     bool hasWeak = mRootObjectWeak.unsafe_get();
@@ -382,6 +391,33 @@ size_t RpcServer::numUninitializedSessions() {
     return mConnectingThreads.size();
 }
 
+// Reads the credentials of the peer of a Unix domain socket.
+static std::optional<RpcSession::PeerCredentials> readPeerCredentials(borrowed_fd fd) {
+#ifdef __linux__
+    int domain;
+    socklen_t domainLen = sizeof(domain);
+    if (getsockopt(fd.get(), SOL_SOCKET, SO_DOMAIN, &domain, &domainLen) != 0 ||
+        domain != AF_UNIX) {
+        return std::nullopt;
+    }
+    ucred cred;
+    socklen_t credLen = sizeof(cred);
+    if (getsockopt(fd.get(), SOL_SOCKET, SO_PEERCRED, &cred, &credLen) != 0 ||
+        credLen != sizeof(cred)) {
+        ALOGW("Could not read peer credentials of fd %d: %s", fd.get(), strerror(errno));
+        return std::nullopt;
+    }
+    return RpcSession::PeerCredentials{
+            .pid = cred.pid,
+            .uid = cred.uid,
+            .gid = cred.gid,
+    };
+#else
+    (void)fd;
+    return std::nullopt;
+#endif // __linux__
+}
+
 void RpcServer::establishConnection(
         sp<RpcServer>&& server, RpcTransportFd clientFd, std::array<uint8_t, kRpcAddressSize> addr,
         size_t addrLen,
@@ -393,6 +429,9 @@ void RpcServer::establishConnection(
 
     status_t status = OK;
 
+    // Read before the fd is moved into the transport, in case this starts a new session.
+    std::optional<RpcSession::PeerCredentials> peerCredentials = readPeerCredentials(clientFd.fd);
+
     int clientFdForLog = clientFd.fd.get();
     auto client = server->mCtx->newTransport(std::move(clientFd), server->mShutdownTrigger.get());
     if (client == nullptr) {
@@ -437,11 +476,15 @@ void RpcServer::establishConnection(
     }
 
     bool incoming = false;
+    auto integrityMode = RpcSession::IntegrityMode::NONE;
     uint32_t protocolVersion = 0;
     bool requestingNewSession = false;
 
     if (status == OK) {
         incoming = header.options & RPC_CONNECTION_OPTION_INCOMING;
+        if (header.options & RPC_CONNECTION_OPTION_INTEGRITY_CRC32) {
+            integrityMode = RpcSession::IntegrityMode::CRC32;
+        }
         protocolVersion = std::min(header.version,
                                    server->mProtocolVersion.value_or(RPC_WIRE_PROTOCOL_VERSION));
         requestingNewSession = sessionId.empty();
@@ -449,6 +492,8 @@ void RpcServer::establishConnection(
         if (requestingNewSession) {
             RpcNewSessionResponse response{
                     .version = protocolVersion,
+                    .options = static_cast<uint8_t>(header.options &
+                                                    RPC_CONNECTION_OPTION_INTEGRITY_CRC32),
             };
 
             iovec iov{&response, sizeof(response)};
@@ -463,6 +508,8 @@ void RpcServer::establishConnection(
 
     RpcMaybeThread thisThread;
     sp<RpcSession> session;
+    // Set if this connection started a new session, to be reported once the lock is released.
+    std::function<void(const sp<RpcSession>&, const void*, size_t)> onSessionStarted;
     {
         RpcMutexUniqueLock _l(server->mLock);
 
@@ -511,6 +558,8 @@ void RpcServer::establishConnection(
             session = sp<RpcSession>::make(nullptr);
             session->setMaxIncomingThreads(server->mMaxThreads);
             if (!session->setProtocolVersion(protocolVersion)) return;
+            session->setIntegrityMode(integrityMode);
+            session->mPeerCredentials = peerCredentials;
 
             if (header.fileDescriptorTransportMode <
                         server->mSupportedFileDescriptorTransportModes.size() &&
@@ -545,6 +594,7 @@ void RpcServer::establishConnection(
             }
 
             server->mSessions[sessionId] = session;
+            onSessionStarted = server->mOnSessionStarted;
         } else {
             auto it = server->mSessions.find(sessionId);
             if (it == server->mSessions.end()) {
@@ -553,6 +603,11 @@ void RpcServer::establishConnection(
                 return;
             }
             session = it->second;
+            if (session->getIntegrityMode() != integrityMode) {
+                ALOGE("Cannot add thread, integrity mode differs from the rest of session %s",
+                      HexString(sessionId.data(), sessionId.size()).c_str());
+                return;
+            }
         }
 
         if (incoming) {
@@ -565,6 +620,10 @@ void RpcServer::establishConnection(
         session->preJoinThreadOwnership(std::move(thisThread));
     }
 
+    if (onSessionStarted != nullptr) {
+        onSessionStarted(session, addr.data(), addrLen);
+    }
+
     auto setupResult = session->preJoinSetup(std::move(client));
 
     // avoid strong cycle
@@ -636,13 +695,21 @@ void RpcServer::onSessionAllIncomingThreadsEnded(const sp<RpcSession>& session)
     LOG_ALWAYS_FATAL_IF(id.empty(), "Server sessions must be initialized with ID");
     LOG_RPC_DETAIL("Dropping session with address %s", HexString(id.data(), id.size()).c_str());
 
-    RpcMutexLockGuard _l(mLock);
-    auto it = mSessions.find(id);
-    LOG_ALWAYS_FATAL_IF(it == mSessions.end(), "Bad state, unknown session id %s",
-                        HexString(id.data(), id.size()).c_str());
-    LOG_ALWAYS_FATAL_IF(it->second != session, "Bad state, session has id mismatch %s",
-                        HexString(id.data(), id.size()).c_str());
-    (void)mSessions.erase(it);
+    std::function<void(const sp<RpcSession>&)> onSessionEnded;
+    {
+        RpcMutexLockGuard _l(mLock);
+        auto it = mSessions.find(id);
+        LOG_ALWAYS_FATAL_IF(it == mSessions.end(), "Bad state, unknown session id %s",
+                            HexString(id.data(), id.size()).c_str());
+        LOG_ALWAYS_FATAL_IF(it->second != session, "Bad state, session has id mismatch %s",
+                            HexString(id.data(), id.size()).c_str());
+        (void)mSessions.erase(it);
+        onSessionEnded = mOnSessionEnded;
+    }
+
+    if (onSessionEnded != nullptr) {
+        onSessionEnded(session);
+    }
 }
 
 void RpcServer::onSessionIncomingThreadEnded() {
@@ -102,6 +102,17 @@ size_t RpcSession::getMaxOutgoingThreads() {
     return mMaxOutgoingConnections;
 }
 
+void RpcSession::setSessionListener(std::function<void()>&& onConnected,
+                                    std::function<void()>&& onDisconnected,
+                                    std::function<void()>&& onShutdown) {
+    RpcMutexLockGuard _l(mMutex);
+    LOG_ALWAYS_FATAL_IF(mStartedSetup,
+                        "Must set session listener before setting up connections");
+    mOnConnected = std::move(onConnected);
+    mOnDisconnected = std::move(onDisconnected);
+    mOnShutdown = std::move(onShutdown);
+}
+
 bool RpcSession::setProtocolVersionInternal(uint32_t version, bool checkStarted) {
     if (!RpcState::validateProtocolVersion(version)) {
         return false;
@@ -140,6 +151,16 @@ RpcSession::FileDescriptorTransportMode RpcSession::getFileDescriptorTransportMo
     return mFileDescriptorTransportMode;
 }
 
+void RpcSession::setIntegrityMode(IntegrityMode mode) {
+    RpcMutexLockGuard _l(mMutex);
+    LOG_ALWAYS_FATAL_IF(mStartedSetup, "Must set integrity mode before setting up connections");
+    mIntegrityMode = mode;
+}
+
+RpcSession::IntegrityMode RpcSession::getIntegrityMode() {
+    return mIntegrityMode;
+}
+
 status_t RpcSession::setupUnixDomainClient(const char* path) {
     return setupSocketClient(UnixSocketAddress(path));
 }
@@ -240,11 +261,26 @@ status_t RpcSession::getRemoteMaxThreads(size_t* maxThreads) {
 }
 
 bool RpcSession::shutdownAndWait(bool wait) {
+    return shutdown(wait, false /*disconnected*/);
+}
+
+bool RpcSession::shutdownOnDisconnect() {
+    return shutdown(false /*wait*/, true /*disconnected*/);
+}
+
+bool RpcSession::shutdown(bool wait, bool disconnected) {
     RpcMutexUniqueLock _l(mMutex);
     LOG_ALWAYS_FATAL_IF(mShutdownTrigger == nullptr, "Shutdown trigger not installed");
 
     mShutdownTrigger->trigger();
 
+    // only the first shutdown of a connected session is reported
+    std::function<void()> onEnded;
+    if (mConnected) {
+        mConnected = false;
+        onEnded = disconnected ? mOnDisconnected : mOnShutdown;
+    }
+
     if (wait) {
         LOG_ALWAYS_FATAL_IF(mShutdownListener == nullptr, "Shutdown listener not installed");
         mShutdownListener->waitForShutdown(_l, sp<RpcSession>::fromExisting(this));
@@ -261,6 +297,10 @@ bool RpcSession::shutdownAndWait(bool wait) {
 
     mRpcBinderState->clear();
 
+    if (onEnded != nullptr) {
+        onEnded();
+    }
+
     return true;
 }
 
@@ -528,12 +568,19 @@ status_t RpcSession::setupClient(const std::function<status_t(const std::vector<
             return status;
 
         uint32_t version;
+        uint8_t options;
         if (status_t status =
                     state()->readNewSessionResponse(connection.get(),
-                                                    sp<RpcSession>::fromExisting(this), &version);
+                                                    sp<RpcSession>::fromExisting(this), &version,
+                                                    &options);
             status != OK)
             return status;
         if (!setProtocolVersionInternal(version, false)) return BAD_VALUE;
+        if (mIntegrityMode == IntegrityMode::CRC32 &&
+            !(options & RPC_CONNECTION_OPTION_INTEGRITY_CRC32)) {
+            ALOGE("Server does not support the CRC32 integrity mode requested by this session");
+            return INVALID_OPERATION;
+        }
     }
 
     // TODO(b/189955605): we should add additional sessions dynamically
@@ -577,6 +624,18 @@ status_t RpcSession::setupClient(const std::function<status_t(const std::vector<
 
     cleanup.release();
 
+    std::function<void()> onConnected;
+    {
+        RpcMutexLockGuard _l(mMutex);
+        // a session which already shut down again, e.g. because an incoming
+        // connection failed, is reported as neither connected nor ended
+        mConnected = !mShutdownTrigger->isTriggered();
+        if (mConnected) onConnected = mOnConnected;
+    }
+    if (onConnected != nullptr) {
+        onConnected();
+    }
+
     return OK;
 }
 
@@ -703,6 +762,9 @@ status_t RpcSession::initAndAddConnection(RpcTransportFd fd, const std::vector<u
     if (incoming) {
         header.options |= RPC_CONNECTION_OPTION_INCOMING;
     }
+    if (mIntegrityMode == IntegrityMode::CRC32) {
+        header.options |= RPC_CONNECTION_OPTION_INTEGRITY_CRC32;
+    }
 
     iovec headerIov{&header, sizeof(header)};
     auto sendHeaderStatus = server->interruptableWriteFully(mShutdownTrigger.get(), &headerIov, 1,
@@ -884,6 +946,10 @@ std::vector<uint8_t> RpcSession::getCertificate(RpcCertificateFormat format) {
     return mCtx->getCertificate(format);
 }
 
+std::optional<RpcSession::PeerCredentials> RpcSession::getPeerCredentials() const {
+    return mPeerCredentials;
+}
+
 status_t RpcSession::ExclusiveConnection::find(const sp<RpcSession>& session, ConnectionUse use,
                                                ExclusiveConnection* connection) {
     connection->mSession = session;
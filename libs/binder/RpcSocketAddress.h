@@ -44,17 +44,34 @@ public:
                             pathLen, path);
         memcpy(mAddr.sun_path, path, pathLen);
     }
+    // Takes an already populated address along with its length, which is what distinguishes
+    // an abstract socket name (leading NUL byte) from a filesystem path.
+    UnixSocketAddress(const sockaddr_un& addr, socklen_t addrLen) : mAddr(addr), mAddrLen(addrLen) {
+        LOG_ALWAYS_FATAL_IF(addrLen < offsetof(sockaddr_un, sun_path) || addrLen > sizeof(mAddr),
+                            "Invalid unix socket address length: %u", addrLen);
+    }
     virtual ~UnixSocketAddress() {}
     std::string toString() const override {
+        if (isAbstract()) {
+            return String8::format("abstract '@%.*s'", static_cast<int>(abstractNameLen()),
+                                   mAddr.sun_path + 1)
+                    .c_str();
+        }
         return String8::format("path '%.*s'", static_cast<int>(sizeof(mAddr.sun_path)),
                                mAddr.sun_path)
                 .c_str();
     }
     const sockaddr* addr() const override { return reinterpret_cast<const sockaddr*>(&mAddr); }
-    size_t addrSize() const override { return sizeof(mAddr); }
+    size_t addrSize() const override { return mAddrLen; }
 
 private:
+    bool isAbstract() const {
+        return mAddrLen > offsetof(sockaddr_un, sun_path) && mAddr.sun_path[0] == '\0';
+    }
+    size_t abstractNameLen() const { return mAddrLen - offsetof(sockaddr_un, sun_path) - 1; }
+
     sockaddr_un mAddr;
+    socklen_t mAddrLen = sizeof(sockaddr_un);
 };
 
 class VsockSocketAddress : public RpcSocketAddress {
@@ -27,6 +27,7 @@
 #include "RpcWireFormat.h"
 #include "Utils.h"
 
+#include <array>
 #include <random>
 #include <sstream>
 
@@ -374,7 +375,7 @@ status_t RpcState::rpcSend(const sp<RpcSession::RpcConnection>& connection,
         status != OK) {
         LOG_RPC_DETAIL("Failed to write %s (%d iovs) on RpcTransport %p, error: %s", what, niovs,
                        connection->rpcTransport.get(), statusToString(status).c_str());
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return status;
     }
 
@@ -391,7 +392,7 @@ status_t RpcState::rpcRec(const sp<RpcSession::RpcConnection>& connection,
         status != OK) {
         LOG_RPC_DETAIL("Failed to read %s (%d iovs) on RpcTransport %p, error: %s", what, niovs,
                        connection->rpcTransport.get(), statusToString(status).c_str());
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return status;
     }
 
@@ -403,6 +404,48 @@ status_t RpcState::rpcRec(const sp<RpcSession::RpcConnection>& connection,
     return OK;
 }
 
+static uint32_t crc32(const iovec* iovs, int niovs) {
+    static const auto kTable = [] {
+        std::array<uint32_t, 256> table;
+        for (uint32_t i = 0; i < table.size(); i++) {
+            uint32_t c = i;
+            for (int k = 0; k < 8; k++) {
+                c = (c & 1) ? 0xEDB88320 ^ (c >> 1) : c >> 1;
+            }
+            table[i] = c;
+        }
+        return table;
+    }();
+
+    uint32_t crc = 0xFFFFFFFF;
+    for (int i = 0; i < niovs; i++) {
+        const uint8_t* data = static_cast<const uint8_t*>(iovs[i].iov_base);
+        for (size_t j = 0; j < iovs[i].iov_len; j++) {
+            crc = kTable[(crc ^ data[j]) & 0xFF] ^ (crc >> 8);
+        }
+    }
+    return crc ^ 0xFFFFFFFF;
+}
+
+void RpcState::sealCommand(const sp<RpcSession>& session, iovec* iovs, int niovs) {
+    if (session->getIntegrityMode() != RpcSession::IntegrityMode::CRC32) return;
+    LOG_ALWAYS_FATAL_IF(niovs < 1 || iovs[0].iov_len != sizeof(RpcWireHeader));
+    static_cast<RpcWireHeader*>(iovs[0].iov_base)->checksum = crc32(iovs + 1, niovs - 1);
+}
+
+status_t RpcState::verifyCommand(const sp<RpcSession>& session, const RpcWireHeader& command,
+                                 const iovec* iovs, int niovs) {
+    if (session->getIntegrityMode() != RpcSession::IntegrityMode::CRC32) return OK;
+    if (uint32_t actual = crc32(iovs, niovs); actual != command.checksum) {
+        ALOGE("Checksum mismatch for command %" PRIu32 ": expected %" PRIx32 " but got %" PRIx32
+              ". Terminating!",
+              command.command, command.checksum, actual);
+        (void)session->shutdownOnDisconnect();
+        return BAD_VALUE;
+    }
+    return OK;
+}
+
 bool RpcState::validateProtocolVersion(uint32_t version) {
     if (version == RPC_WIRE_PROTOCOL_VERSION_EXPERIMENTAL) {
 #if defined(__ANDROID__)
@@ -428,7 +471,8 @@ bool RpcState::validateProtocolVersion(uint32_t version) {
 }
 
 status_t RpcState::readNewSessionResponse(const sp<RpcSession::RpcConnection>& connection,
-                                          const sp<RpcSession>& session, uint32_t* version) {
+                                          const sp<RpcSession>& session, uint32_t* version,
+                                          uint8_t* options) {
     RpcNewSessionResponse response;
     iovec iov{&response, sizeof(response)};
     if (status_t status = rpcRec(connection, session, "new session response", &iov, 1, nullptr);
@@ -436,6 +480,7 @@ status_t RpcState::readNewSessionResponse(const sp<RpcSession::RpcConnection>& c
         return status;
     }
     *version = response.version;
+    *options = response.options;
     return OK;
 }
 
@@ -556,7 +601,7 @@ status_t RpcState::transactAddress(const sp<RpcSession::RpcConnection>& connecti
             asyncNumber = it->second.asyncNumber;
             if (!nodeProgressAsyncNumber(&it->second)) {
                 _l.unlock();
-                (void)session->shutdownAndWait(false);
+                (void)session->shutdownOnDisconnect();
                 return DEAD_OBJECT;
             }
         }
@@ -601,6 +646,7 @@ status_t RpcState::transactAddress(const sp<RpcSession::RpcConnection>& connecti
             {const_cast<uint8_t*>(data.data()), data.dataSize()},
             objectTableSpan.toIovec(),
     };
+    sealCommand(session, iovs, countof(iovs));
     auto altPoll = [&] {
         if (waitUs > kWaitLogUs) {
             ALOGE("Cannot send command, trying to process pending refcounts. Waiting "
@@ -620,7 +666,7 @@ status_t RpcState::transactAddress(const sp<RpcSession::RpcConnection>& connecti
     if (status_t status = rpcSend(connection, session, "transaction", iovs, countof(iovs),
                                   std::ref(altPoll), rpcFields->mFds.get());
         status != OK) {
-        // rpcSend calls shutdownAndWait, so all refcounts should be reset. If we ever tolerate
+        // rpcSend calls shutdownOnDisconnect, so all refcounts should be reset. If we ever tolerate
         // errors here, then we may need to undo the binder-sent counts for the transaction as
         // well as for the binder objects in the Parcel
         return status;
@@ -676,7 +722,7 @@ status_t RpcState::waitForReply(const sp<RpcSession::RpcConnection>& connection,
     if (command.bodySize < rpcReplyWireSize) {
         ALOGE("Expecting %zu but got %" PRId32 " bytes for RpcWireReply. Terminating!",
               sizeof(RpcWireReply), command.bodySize);
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return BAD_VALUE;
     }
 
@@ -693,6 +739,8 @@ status_t RpcState::waitForReply(const sp<RpcSession::RpcConnection>& connection,
     if (status_t status = rpcRec(connection, session, "reply body", iovs, countof(iovs), nullptr);
         status != OK)
         return status;
+    if (status_t status = verifyCommand(session, command, iovs, countof(iovs)); status != OK)
+        return status;
 
     if (rpcReply.status != OK) return rpcReply.status;
 
@@ -705,7 +753,7 @@ status_t RpcState::waitForReply(const sp<RpcSession::RpcConnection>& connection,
         if (!objectTableBytes.has_value()) {
             ALOGE("Parcel size larger than available bytes: %" PRId32 " vs %zu. Terminating!",
                   rpcReply.parcelDataSize, parcelSpan.byteSize());
-            (void)session->shutdownAndWait(false);
+            (void)session->shutdownOnDisconnect();
             return BAD_VALUE;
         }
         std::optional<Span<const uint32_t>> maybeSpan =
@@ -760,6 +808,7 @@ status_t RpcState::sendDecStrongToTarget(const sp<RpcSession::RpcConnection>& co
             .bodySize = sizeof(RpcDecStrong),
     };
     iovec iovs[]{{&cmd, sizeof(cmd)}, {&body, sizeof(body)}};
+    sealCommand(session, iovs, countof(iovs));
     return rpcSend(connection, session, "dec ref", iovs, countof(iovs), std::nullopt);
 }
 
@@ -830,7 +879,7 @@ status_t RpcState::processCommand(
     // also can't consider it a fatal error because this would allow any client
     // to kill us, so ending the session for misbehaving client.
     ALOGE("Unknown RPC command %d - terminating session", command.command);
-    (void)session->shutdownAndWait(false);
+    (void)session->shutdownOnDisconnect();
     return DEAD_OBJECT;
 }
 status_t RpcState::processTransact(
@@ -847,6 +896,7 @@ status_t RpcState::processTransact(
     if (status_t status = rpcRec(connection, session, "transaction body", &iov, 1, nullptr);
         status != OK)
         return status;
+    if (status_t status = verifyCommand(session, command, &iov, 1); status != OK) return status;
 
     return processTransactInternal(connection, session, std::move(transactionData),
                                    std::move(ancillaryFds));
@@ -873,7 +923,7 @@ processTransactInternalTailCall:
     if (transactionData.size() < sizeof(RpcWireTransaction)) {
         ALOGE("Expecting %zu but got %zu bytes for RpcWireTransaction. Terminating!",
               sizeof(RpcWireTransaction), transactionData.size());
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return BAD_VALUE;
     }
     RpcWireTransaction* transaction = reinterpret_cast<RpcWireTransaction*>(transactionData.data());
@@ -898,13 +948,13 @@ processTransactInternalTailCall:
             // session.
             ALOGE("While transacting, binder has been deleted at address %" PRIu64 ". Terminating!",
                   addr);
-            (void)session->shutdownAndWait(false);
+            (void)session->shutdownOnDisconnect();
             replyStatus = BAD_VALUE;
         } else if (target->localBinder() == nullptr) {
             ALOGE("Unknown binder address or non-local binder, not address %" PRIu64
                   ". Terminating!",
                   addr);
-            (void)session->shutdownAndWait(false);
+            (void)session->shutdownOnDisconnect();
             replyStatus = BAD_VALUE;
         } else if (oneway) {
             RpcMutexUniqueLock _l(mNodeMutex);
@@ -934,7 +984,7 @@ processTransactInternalTailCall:
                     if (numPending >= kArbitraryOnewayCallTerminateLevel) {
                         ALOGE("WARNING: %zu pending oneway transactions. Terminating!", numPending);
                         _l.unlock();
-                        (void)session->shutdownAndWait(false);
+                        (void)session->shutdownOnDisconnect();
                         return FAILED_TRANSACTION;
                     }
 
@@ -963,7 +1013,7 @@ processTransactInternalTailCall:
             if (!objectTableBytes.has_value()) {
                 ALOGE("Parcel size (%" PRId32 ") greater than available bytes (%zu). Terminating!",
                       transaction->parcelDataSize, parcelSpan.byteSize());
-                (void)session->shutdownAndWait(false);
+                (void)session->shutdownOnDisconnect();
                 return BAD_VALUE;
             }
             std::optional<Span<const uint32_t>> maybeSpan =
@@ -1063,7 +1113,7 @@ processTransactInternalTailCall:
 
             if (!nodeProgressAsyncNumber(&it->second)) {
                 _l.unlock();
-                (void)session->shutdownAndWait(false);
+                (void)session->shutdownOnDisconnect();
                 return DEAD_OBJECT;
             }
 
@@ -1143,6 +1193,7 @@ processTransactInternalTailCall:
             {const_cast<uint8_t*>(reply.data()), reply.dataSize()},
             objectTableSpan.toIovec(),
     };
+    sealCommand(session, iovs, countof(iovs));
     return rpcSend(connection, session, "reply", iovs, countof(iovs), std::nullopt,
                    rpcFields->mFds.get());
 }
@@ -1154,7 +1205,7 @@ status_t RpcState::processDecStrong(const sp<RpcSession::RpcConnection>& connect
     if (command.bodySize != sizeof(RpcDecStrong)) {
         ALOGE("Expecting %zu but got %" PRId32 " bytes for RpcDecStrong. Terminating!",
               sizeof(RpcDecStrong), command.bodySize);
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return BAD_VALUE;
     }
 
@@ -1163,6 +1214,7 @@ status_t RpcState::processDecStrong(const sp<RpcSession::RpcConnection>& connect
     if (status_t status = rpcRec(connection, session, "dec ref body", &iov, 1, nullptr);
         status != OK)
         return status;
+    if (status_t status = verifyCommand(session, command, &iov, 1); status != OK) return status;
 
     uint64_t addr = RpcWireAddress::toRaw(body.address);
     RpcMutexUniqueLock _l(mNodeMutex);
@@ -1178,7 +1230,7 @@ status_t RpcState::processDecStrong(const sp<RpcSession::RpcConnection>& connect
               ". Terminating!",
               addr);
         _l.unlock();
-        (void)session->shutdownAndWait(false);
+        (void)session->shutdownOnDisconnect();
         return BAD_VALUE;
     }
 
@@ -67,7 +67,8 @@ public:
     [[nodiscard]] static bool validateProtocolVersion(uint32_t version);
 
     [[nodiscard]] status_t readNewSessionResponse(const sp<RpcSession::RpcConnection>& connection,
-                                                  const sp<RpcSession>& session, uint32_t* version);
+                                                  const sp<RpcSession>& session, uint32_t* version,
+                                                  uint8_t* options);
     [[nodiscard]] status_t sendConnectionInit(const sp<RpcSession::RpcConnection>& connection,
                                               const sp<RpcSession>& session);
     [[nodiscard]] status_t readConnectionInit(const sp<RpcSession::RpcConnection>& connection,
@@ -200,6 +201,15 @@ private:
                                   std::vector<std::variant<binder::unique_fd, binder::borrowed_fd>>*
                                           ancillaryFds = nullptr);
 
+    // Fill in the checksum of the command in iovs[0] over the body in the
+    // other iovs, if the session has integrity checks.
+    static void sealCommand(const sp<RpcSession>& session, iovec* iovs, int niovs);
+    // Check the checksum in 'command' against the body in iovs, if the session
+    // has integrity checks, terminating the session on a mismatch.
+    [[nodiscard]] static status_t verifyCommand(const sp<RpcSession>& session,
+                                                const RpcWireHeader& command, const iovec* iovs,
+                                                int niovs);
+
     [[nodiscard]] status_t waitForReply(const sp<RpcSession::RpcConnection>& connection,
                                         const sp<RpcSession>& session, Parcel* reply);
     [[nodiscard]] status_t processCommand(
@@ -21,6 +21,9 @@ namespace android {
 #pragma clang diagnostic error "-Wpadded"
 
 constexpr uint8_t RPC_CONNECTION_OPTION_INCOMING = 0x1; // default is outgoing
+// Every command of the session carries a CRC32 of its body, see RpcWireHeader.
+// Acknowledged in RpcNewSessionResponse::options by servers which support it.
+constexpr uint8_t RPC_CONNECTION_OPTION_INTEGRITY_CRC32 = 0x2;
 
 constexpr uint32_t RPC_WIRE_ADDRESS_OPTION_CREATED = 1 << 0; // distinguish from '0' address
 constexpr uint32_t RPC_WIRE_ADDRESS_OPTION_FOR_SERVER = 1 << 1;
@@ -59,7 +62,8 @@ static_assert(sizeof(RpcConnectionHeader) == 16);
  */
 struct RpcNewSessionResponse {
     uint32_t version; // maximum supported by callee <= maximum supported by caller
-    uint8_t reserved[4];
+    uint8_t options;  // RPC_CONNECTION_OPTION_* accepted by callee, except INCOMING
+    uint8_t reserved[3];
 };
 static_assert(sizeof(RpcNewSessionResponse) == 8);
 
@@ -118,7 +122,9 @@ struct RpcWireHeader {
     uint32_t command; // RPC_COMMAND_*
     uint32_t bodySize;
 
-    uint32_t reserved[2];
+    // CRC32 of the body, if the session has integrity checks, or else zero.
+    uint32_t checksum;
+    uint32_t reserved;
 };
 static_assert(sizeof(RpcWireHeader) == 16);
 
@@ -69,6 +69,12 @@ bool Stability::requiresVintfDeclaration(const sp<IBinder>& binder) {
     return check(getRepr(binder.get()), Level::VINTF);
 }
 
+bool Stability::allowsTransactionsFrom(const sp<IBinder>& binder, bool vendor) {
+    if (binder == nullptr) return false;
+    if (binder->localBinder() != nullptr) return true;
+    return check(getRepr(binder.get()), vendor ? Level::VENDOR : Level::SYSTEM);
+}
+
 void Stability::tryMarkCompilationUnit(IBinder* binder) {
     std::ignore = setRepr(binder, getLocalLevel(), REPR_NONE);
 }
@@ -224,6 +224,15 @@ LIBBINDER_EXPORTED bool checkPermission(const String16& permission, pid_t pid, u
 typedef std::function<status_t(const String16& name, sockaddr* outAddr, socklen_t addrSize)>
         RpcSocketAddressProvider;
 
+/**
+ * Like RpcSocketAddressProvider, but may instead give out a socket which is
+ * already connected to the service, by setting outFd, in which case outAddr
+ * is ignored. Each call must give out a new socket.
+ */
+typedef std::function<status_t(const String16& name, sockaddr* outAddr, socklen_t addrSize,
+                               binder::unique_fd* outFd)>
+        RpcConnectionProvider;
+
 typedef std::function<sp<IBinder>(const String16& name)> RpcAccessorProvider;
 
 class AccessorProvider;
@@ -261,6 +270,19 @@ removeAccessorProvider(std::weak_ptr<AccessorProvider> provider);
 LIBBINDER_EXPORTED sp<IBinder> createAccessor(const String16& instance,
                                               RpcSocketAddressProvider&& connectionInfoProvider);
 
+/**
+ * Create an Accessor associated with a service, which gives out connections
+ * from the supplied RpcConnectionProvider: either sockets it connects to the
+ * addresses given, or sockets that are already connected.
+ *
+ * \param instance name of the service that this Accessor is associated with
+ * \param connectionProvider a callback that returns connection info or a
+ *        connected socket for the service.
+ * \return the binder of the IAccessor implementation from libbinder
+ */
+LIBBINDER_EXPORTED sp<IBinder> createAccessor(const String16& instance,
+                                              RpcConnectionProvider&& connectionProvider);
+
 /**
  * Check to make sure this binder is the expected binder that is an IAccessor
  * associated with a specific instance.
@@ -274,6 +296,21 @@ LIBBINDER_EXPORTED sp<IBinder> createAccessor(const String16& instance,
  * \return OK if the binder is an IAccessor for `instance`
  */
 LIBBINDER_EXPORTED status_t validateAccessor(const String16& instance, const sp<IBinder>& binder);
+
+/**
+ * Create an Accessor for a new instance name which forwards connection
+ * requests to an existing Accessor, e.g. to republish an RPC service received
+ * from another process under a name of this process's choosing.
+ *
+ * \param name instance name that the new Accessor reports
+ * \param accessor binder of the existing IAccessor to forward to
+ * \param delegator the new Accessor's binder, on success
+ *
+ * \return OK on success, BAD_VALUE if accessor or delegator is null, or
+ *         BAD_TYPE if accessor is not an IAccessor
+ */
+LIBBINDER_EXPORTED status_t delegateAccessor(const String16& name, const sp<IBinder>& accessor,
+                                             sp<IBinder>* delegator);
 #endif // __TRUSTY__
 
 #ifndef __ANDROID__
@@ -119,6 +119,9 @@ public:
     // WARNING: some read methods may make additional copies of data.
     // In order to verify this, heap dumps should be used.
     LIBBINDER_EXPORTED void markSensitive() const;
+    // Whether markSensitive() has been called. A sensitive reply is sent with
+    // TF_CLEAR_BUF, even if the transaction was not.
+    LIBBINDER_EXPORTED bool isSensitive() const { return mDeallocZero; }
 
     // For a 'data' Parcel, this should mark the Parcel as being prepared for a
     // transaction on this specific binder object. Based on this, the format of
@@ -644,6 +647,12 @@ public:
     LIBBINDER_EXPORTED static size_t getGlobalAllocSize();
     LIBBINDER_EXPORTED static size_t getGlobalAllocCount();
 
+    // Keeps the data buffers of small Parcels freed on the calling thread, and
+    // reuses them for the next Parcels written on it, instead of going back to
+    // the allocator for every transaction. Disabling the pool frees the
+    // buffers it holds. Buffers of sensitive Parcels are zeroed before reuse.
+    LIBBINDER_EXPORTED static void setThreadBufferPoolEnabled(bool enabled);
+
     LIBBINDER_EXPORTED bool replaceCallingWorkSourceUid(uid_t uid);
     // Returns the work source provided by the caller. This can only be trusted for trusted calling
     // uid.
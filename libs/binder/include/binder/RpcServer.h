@@ -203,6 +203,19 @@ public:
     LIBBINDER_EXPORTED void setServerSocketModifier(
             std::function<void(binder::borrowed_fd)>&& modifier);
 
+    /**
+     * Set optional listener of sessions starting and ending.
+     *
+     * |onSessionStarted| is invoked once for each new session, with the same
+     * address arguments as the callable given to setPerSessionRootObject().
+     * |onSessionEnded| is invoked once all the incoming threads of a session
+     * have ended, because the client disconnected or the server shut down.
+     * Either may be null. They are invoked without any lock of the server held.
+     */
+    LIBBINDER_EXPORTED void setSessionListener(
+            std::function<void(const sp<RpcSession>&, const void*, size_t)>&& onSessionStarted,
+            std::function<void(const sp<RpcSession>&)>&& onSessionEnded);
+
     /**
      * See RpcTransportCtx::getCertificate
      */
@@ -288,6 +301,8 @@ private:
     std::function<sp<IBinder>(wp<RpcSession>, const void*, size_t)> mRootObjectFactory;
     std::function<bool(const void*, size_t)> mConnectionFilter;
     std::function<void(binder::borrowed_fd)> mServerSocketModifier;
+    std::function<void(const sp<RpcSession>&, const void*, size_t)> mOnSessionStarted;
+    std::function<void(const sp<RpcSession>&)> mOnSessionEnded;
     std::map<std::vector<uint8_t>, sp<RpcSession>> mSessions;
     std::unique_ptr<FdTrigger> mShutdownTrigger;
     RpcConditionVariable mShutdownCv;
@@ -95,6 +95,22 @@ public:
     LIBBINDER_EXPORTED void setMaxOutgoingConnections(size_t connections);
     LIBBINDER_EXPORTED size_t getMaxOutgoingThreads();
 
+    /**
+     * Set optional listener of this client session connecting and ending.
+     *
+     * |onConnected| is invoked once setting up this session as a client
+     * succeeds. After that, exactly one of the others is invoked, once the
+     * session ends: |onDisconnected| if it ends because the connection to the
+     * server failed, e.g. because the server went away or sent bad data, and
+     * |onShutdown| if it ends because shutdownAndWait() was called, or because
+     * it holds no binders anymore. Any of them may be null. They are invoked
+     * without any lock of the session held. This must be called before
+     * setting up this session as a client.
+     */
+    LIBBINDER_EXPORTED void setSessionListener(std::function<void()>&& onConnected,
+                                               std::function<void()>&& onDisconnected,
+                                               std::function<void()>&& onShutdown);
+
     /**
      * By default, the minimum of the supported versions of the client and the
      * server will be used. Usually, this API should only be used for debugging.
@@ -116,6 +132,23 @@ public:
     LIBBINDER_EXPORTED void setFileDescriptorTransportMode(FileDescriptorTransportMode mode);
     LIBBINDER_EXPORTED FileDescriptorTransportMode getFileDescriptorTransportMode();
 
+    enum class IntegrityMode : uint8_t {
+        NONE = 0,
+        // Every command carries a CRC32 of its body, which the receiver checks
+        // before using it. A mismatch terminates the session.
+        CRC32 = 1,
+    };
+
+    /**
+     * Check every command of the session for corruption, for transports where
+     * it is plausible and would otherwise go unnoticed, like shared memory or
+     * serial links. The server must support the mode, or setting up the
+     * session fails. This also detects accidental corruption only; it is not
+     * a replacement for TLS.
+     */
+    LIBBINDER_EXPORTED void setIntegrityMode(IntegrityMode mode);
+    LIBBINDER_EXPORTED IntegrityMode getIntegrityMode();
+
     /**
      * This should be called once per thread, matching 'join' in the remote
      * process.
@@ -177,6 +210,22 @@ public:
      */
     LIBBINDER_EXPORTED std::vector<uint8_t> getCertificate(RpcCertificateFormat);
 
+    /**
+     * Credentials of a process at the other end of a Unix domain socket.
+     */
+    struct PeerCredentials {
+        int32_t pid;
+        uint32_t uid;
+        uint32_t gid;
+    };
+
+    /**
+     * For server sessions over Unix domain sockets, the credentials of the
+     * client, as reported by SO_PEERCRED for the connection which started the
+     * session. Otherwise, or if they couldn't be read, returns std::nullopt.
+     */
+    LIBBINDER_EXPORTED std::optional<PeerCredentials> getPeerCredentials() const;
+
     /**
      * Shuts down the service.
      *
@@ -242,6 +291,10 @@ private:
     // for 'target', see RpcState::sendDecStrongToTarget
     [[nodiscard]] status_t sendDecStrongToTarget(uint64_t address, size_t target);
 
+    // shutdownAndWait(false), for when the connection to the other side failed
+    [[nodiscard]] bool shutdownOnDisconnect();
+    [[nodiscard]] bool shutdown(bool wait, bool disconnected);
+
     class EventListener : public virtual RefBase {
     public:
         virtual void onSessionAllIncomingThreadsEnded(const sp<RpcSession>& session) = 0;
@@ -373,6 +426,7 @@ private:
     // session-specific root object (if a different root is used for each
     // session)
     sp<IBinder> mSessionSpecificRootObject;
+    std::optional<PeerCredentials> mPeerCredentials;
 
     std::vector<uint8_t> mId;
 
@@ -383,10 +437,16 @@ private:
     RpcMutex mMutex; // for all below
 
     bool mStartedSetup = false;
+    // whether the session was set up as a client and hasn't ended yet
+    bool mConnected = false;
+    std::function<void()> mOnConnected;
+    std::function<void()> mOnDisconnected;
+    std::function<void()> mOnShutdown;
     size_t mMaxIncomingThreads = 0;
     size_t mMaxOutgoingConnections = kDefaultMaxOutgoingConnections;
     std::optional<uint32_t> mProtocolVersion;
     FileDescriptorTransportMode mFileDescriptorTransportMode = FileDescriptorTransportMode::NONE;
+    IntegrityMode mIntegrityMode = IntegrityMode::NONE;
 
     RpcConditionVariable mAvailableConnectionCv; // for mWaitingThreads
 
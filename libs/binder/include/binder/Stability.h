@@ -116,6 +116,12 @@ public:
     // else false if the binder is local to the current partition.
     LIBBINDER_EXPORTED static bool requiresVintfDeclaration(const sp<IBinder>& binder);
 
+    // Returns true if user transactions on binder pass the stability check of
+    // BpBinder::transact from a context with the stability of the vendor image
+    // (if vendor is true) or else of the system image. Transactions on local
+    // binders aren't checked, so this is always true for them.
+    LIBBINDER_EXPORTED static bool allowsTransactionsFrom(const sp<IBinder>& binder, bool vendor);
+
 private:
     // Parcel needs to read/write stability level in an unstable format.
     friend ::android::Parcel;
@@ -0,0 +1,231 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#pragma once
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/socket.h>
+
+extern "C" {
+
+struct AIBinder;
+
+/**
+ * An Accessor is a binder object which gives out connections to an RPC binder
+ * service. Registering one with the service manager, or returning one from an
+ * accessor provider, makes the RPC service available through the regular
+ * service lookup functions.
+ *
+ * These APIs are unstable, and only available to the platform.
+ */
+typedef struct ABinderRpc_Accessor ABinderRpc_Accessor;
+
+/**
+ * The address of an RPC binder service, as a socket address.
+ */
+typedef struct ABinderRpc_ConnectionInfo ABinderRpc_ConnectionInfo;
+
+/**
+ * Callback which returns the connection info for the RPC service with the
+ * given instance name.
+ *
+ * \param instance name of the service to connect to.
+ * \param data the user data given to ABinderRpc_Accessor_new.
+ *
+ * \return the connection info, which the caller takes ownership of, or null if
+ * there is none.
+ */
+typedef ABinderRpc_ConnectionInfo* _Nullable (*ABinderRpc_ConnectionInfoProvider)(
+        const char* _Nonnull instance, void* _Nullable data);
+
+/**
+ * Callback which deletes the user data given to ABinderRpc_Accessor_new.
+ *
+ * \param data the user data given to ABinderRpc_Accessor_new.
+ */
+typedef void (*ABinderRpc_ConnectionInfoProviderUserData_delete)(void* _Nullable data);
+
+/**
+ * Create an Accessor for the RPC service with the given instance name.
+ *
+ * The provider is called, possibly at the same time on several threads, each
+ * time a client connects to the service.
+ *
+ * \param instance name of the service the Accessor is for.
+ * \param provider callback which returns the connection info for the service.
+ * \param data user data passed to the provider.
+ * \param onDelete called with data once the Accessor binder object has been
+ *        destroyed, or before this function returns if it fails. May be null.
+ *
+ * \return the Accessor, which must be deleted with ABinderRpc_Accessor_delete,
+ * or null if either argument is null.
+ */
+ABinderRpc_Accessor* _Nullable ABinderRpc_Accessor_new(
+        const char* _Nonnull instance, ABinderRpc_ConnectionInfoProvider _Nonnull provider,
+        void* _Nullable data, ABinderRpc_ConnectionInfoProviderUserData_delete _Nullable onDelete);
+
+/**
+ * Delete an Accessor. Its binder object stays alive as long as there are
+ * references to it, e.g. from ABinderRpc_Accessor_asBinder.
+ *
+ * \param accessor the Accessor to delete. May be null.
+ */
+void ABinderRpc_Accessor_delete(ABinderRpc_Accessor* _Nullable accessor);
+
+/**
+ * Get the binder object of an Accessor, e.g. to register it with the service
+ * manager.
+ *
+ * \param accessor the Accessor.
+ *
+ * \return a new strong reference to the binder object, which the caller owns.
+ */
+AIBinder* _Nullable ABinderRpc_Accessor_asBinder(ABinderRpc_Accessor* _Nonnull accessor);
+
+/**
+ * Wrap the binder object of an Accessor, e.g. one received from another
+ * process through the service manager, so that it can be managed or
+ * delegated in this process.
+ *
+ * \param instance name of the service the Accessor should be for.
+ * \param binder the binder object of the Accessor. This does not take
+ *        ownership of the caller's reference.
+ *
+ * \return the Accessor, which must be deleted with ABinderRpc_Accessor_delete,
+ * or null if either argument is null, or binder is not an Accessor for
+ * instance.
+ */
+ABinderRpc_Accessor* _Nullable ABinderRpc_Accessor_fromBinder(const char* _Nonnull instance,
+                                                              AIBinder* _Nonnull binder);
+
+/**
+ * Create an Accessor binder for a new instance name, which gives out
+ * connections from an existing Accessor, e.g. so that a process which
+ * received an Accessor can republish the service under a name of its own.
+ *
+ * \param instance name of the service the new Accessor is for.
+ * \param accessor the binder object of the existing Accessor. This does not
+ *        take ownership of the caller's reference.
+ * \param outDelegator set to a new strong reference to the binder object of
+ *        the new Accessor, which the caller owns, on success.
+ *
+ * \return STATUS_OK (0) on success, STATUS_UNEXPECTED_NULL if any argument is null,
+ * or STATUS_BAD_TYPE if accessor is not an Accessor.
+ */
+int32_t ABinderRpc_Accessor_delegateAccessor(const char* _Nonnull instance,
+                                             AIBinder* _Nonnull accessor,
+                                             AIBinder* _Nullable* _Nonnull outDelegator);
+
+/**
+ * A registration of an accessor provider, which libbinder asks for Accessors
+ * when this process looks up one of its instances.
+ */
+typedef struct ABinderRpc_AccessorProvider ABinderRpc_AccessorProvider;
+
+/**
+ * Callback which returns an Accessor for the given instance name.
+ *
+ * \param instance name of the service being looked up, which is one of the
+ *        instances the provider was registered for.
+ * \param data the user data given to ABinderRpc_registerAccessorProvider.
+ *
+ * \return an Accessor for instance, which the caller takes ownership of, or
+ * null if there is none.
+ */
+typedef ABinderRpc_Accessor* _Nullable (*ABinderRpc_AccessorProvider_getAccessorCallback)(
+        const char* _Nonnull instance, void* _Nullable data);
+
+/**
+ * Callback which deletes the user data given to
+ * ABinderRpc_registerAccessorProvider.
+ *
+ * \param data the user data given to ABinderRpc_registerAccessorProvider.
+ */
+typedef void (*ABinderRpc_AccessorProviderUserData_deleteCallback)(void* _Nullable data);
+
+/**
+ * Register a callback which provides Accessors for a set of instances, so
+ * that looking up any of them in this process connects to the RPC service
+ * the Accessor gives out, without a service manager entry.
+ *
+ * The provider is called, possibly at the same time on several threads, each
+ * time this process looks up one of the instances.
+ *
+ * \param provider callback which returns Accessors.
+ * \param instances names of the instances the provider serves. The names are
+ *        copied.
+ * \param numInstances the number of names in instances. Must be at least 1.
+ * \param data user data passed to the provider.
+ * \param onDelete called with data once the provider has been unregistered
+ *        and any calls of it in progress have returned, or before this
+ *        function returns if it fails. May be null.
+ *
+ * \return the registration, which must be passed to
+ * ABinderRpc_unregisterAccessorProvider, or null if provider or any of the
+ * instances are null, or there are no instances.
+ */
+ABinderRpc_AccessorProvider* _Nullable ABinderRpc_registerAccessorProvider(
+        ABinderRpc_AccessorProvider_getAccessorCallback _Nonnull provider,
+        const char* _Nullable const* const _Nonnull instances, size_t numInstances,
+        void* _Nullable data, ABinderRpc_AccessorProviderUserData_deleteCallback _Nullable onDelete);
+
+/**
+ * Unregister an accessor provider, so that it is not called for later
+ * lookups, and delete the registration. Calls in progress are not waited for.
+ *
+ * \param provider the registration to delete. May be null.
+ */
+void ABinderRpc_unregisterAccessorProvider(ABinderRpc_AccessorProvider* _Nullable provider);
+
+/**
+ * Create connection info from a socket address. AF_VSOCK, AF_UNIX, AF_INET and
+ * AF_INET6 addresses are supported.
+ *
+ * \param addr the socket address, which is copied.
+ * \param len the size of addr in bytes.
+ *
+ * \return the connection info, which must be deleted with
+ * ABinderRpc_ConnectionInfo_delete unless it is returned from an
+ * ABinderRpc_ConnectionInfoProvider, or null if the address has an unsupported
+ * family or the wrong size for it.
+ */
+ABinderRpc_ConnectionInfo* _Nullable ABinderRpc_ConnectionInfo_new(const sockaddr* _Nonnull addr,
+                                                                   socklen_t len);
+
+/**
+ * Create connection info from a socket which is already connected to the
+ * service, e.g. one end of a socketpair handed out by the process hosting it.
+ * Each connection needs a socket of its own, so the provider must give out a
+ * new one on every call.
+ *
+ * \param fd the connected socket, which the connection info takes ownership
+ * of, even on failure.
+ *
+ * \return the connection info, which must be deleted with
+ * ABinderRpc_ConnectionInfo_delete unless it is returned from an
+ * ABinderRpc_ConnectionInfoProvider, or null if fd is negative.
+ */
+ABinderRpc_ConnectionInfo* _Nullable ABinderRpc_ConnectionInfo_newPreconnected(int fd);
+
+/**
+ * Delete connection info.
+ *
+ * \param info the connection info to delete. May be null.
+ */
+void ABinderRpc_ConnectionInfo_delete(ABinderRpc_ConnectionInfo* _Nullable info);
+
+}
@@ -17,6 +17,7 @@
 #pragma once
 
 #include <sys/socket.h>
+#include <stddef.h>
 #include <stdint.h>
 
 extern "C" {
@@ -24,6 +25,8 @@ extern "C" {
 struct AIBinder;
 struct ARpcServer;
 struct ARpcSession;
+struct ARpcTlsConfig;
+struct ARpcTlsCredentials;
 
 enum class ARpcSession_FileDescriptorTransportMode {
     None,
@@ -31,6 +34,11 @@ enum class ARpcSession_FileDescriptorTransportMode {
     Trusty,
 };
 
+enum class ARpcSession_IntegrityMode {
+    None,
+    Crc32,
+};
+
 // Starts an RPC server on a given port and a given root IBinder object.
 // The server will only accept connections from the given CID.
 // Set `cid` to VMADDR_CID_ANY to accept connections from any client.
@@ -67,6 +75,97 @@ enum class ARpcSession_FileDescriptorTransportMode {
 [[nodiscard]] ARpcServer* ARpcServer_newInet(AIBinder* service, const char* address,
                                              unsigned int port);
 
+// Called each time a TLS context is created for a server or session, to give it
+// a certificate and private key with ARpcTlsCredentials_set().
+// Returns false if there are no credentials to give, which fails the context.
+typedef bool (*ARpcTlsConfig_ProvideCredentials)(void* param, ARpcTlsCredentials* credentials);
+
+// Called with the DER-encoded certificate of each peer during the TLS handshake.
+// Returns whether the peer is trusted; the handshake fails if it isn't.
+typedef bool (*ARpcTlsConfig_VerifyPeer)(void* param, const uint8_t* certificate,
+                                         size_t certificateLen);
+
+// Called once neither callback of an ARpcTlsConfig will be called again.
+typedef void (*ARpcTlsConfig_DeleteParam)(void* param);
+
+// Creates the TLS configuration for RPC servers and sessions, whose callbacks
+// are called with `param` from any thread, until `onDelete` is called with it.
+// `onDelete` may be null, and is called right away if this fails.
+// TLS is not available on Trusty.
+// Returns an opaque handle, which must be freed with ARpcTlsConfig_free(), or
+// null if either callback is null.
+[[nodiscard]] ARpcTlsConfig* ARpcTlsConfig_new(ARpcTlsConfig_ProvideCredentials provide,
+                                               ARpcTlsConfig_VerifyPeer verify, void* param,
+                                               ARpcTlsConfig_DeleteParam onDelete);
+
+// Frees a TLS configuration. Servers and sessions created with it keep using
+// its callbacks.
+void ARpcTlsConfig_free(ARpcTlsConfig* config);
+
+// Gives a TLS context the PEM-encoded certificate and unencrypted private key
+// to authenticate with, from an ARpcTlsConfig_ProvideCredentials callback.
+// Returns false if either can't be parsed, or they don't match.
+bool ARpcTlsCredentials_set(ARpcTlsCredentials* credentials, const uint8_t* certificate,
+                            size_t certificateLen, const uint8_t* privateKey,
+                            size_t privateKeyLen);
+
+// Like ARpcServer_newVsock(), but connections are encrypted with TLS.
+[[nodiscard]] ARpcServer* ARpcServer_newVsockTls(AIBinder* service, unsigned int cid,
+                                                 unsigned int port, const ARpcTlsConfig* config);
+
+// Like ARpcServer_newBoundSocket(), but connections are encrypted with TLS.
+[[nodiscard]] ARpcServer* ARpcServer_newBoundSocketTls(AIBinder* service, int socketFd,
+                                                       const ARpcTlsConfig* config);
+
+// Like ARpcServer_newInet(), but connections are encrypted with TLS.
+[[nodiscard]] ARpcServer* ARpcServer_newInetTls(AIBinder* service, const char* address,
+                                                unsigned int port, const ARpcTlsConfig* config);
+
+// The credentials of the client of an RPC server session over a Unix domain
+// socket, as reported by SO_PEERCRED for the connection which started it.
+struct ARpcPeerCredentials {
+    int32_t pid;
+    uint32_t uid;
+    uint32_t gid;
+};
+
+// Called when a client starts a new session with an RPC server, with an ID for
+// the session which isn't reused, and the address of the client as returned by
+// accept(), e.g. a sockaddr_vm for vsock. `addressLen` is 0 if it has none.
+// `credentials` is null unless the client connected over a Unix domain socket.
+typedef void (*ARpcServer_SessionStarted)(void* param, uint64_t sessionId, const void* address,
+                                          size_t addressLen,
+                                          const ARpcPeerCredentials* credentials);
+
+// Called when a session ends, because the client disconnected or the server
+// shut down.
+typedef void (*ARpcServer_SessionEnded)(void* param, uint64_t sessionId);
+
+// Sets the callbacks called as sessions of this RPC server start and end, which
+// are called with `param` from the server's threads until `onDelete` is called
+// with it. Either callback and `onDelete` may be null.
+// Must be called before the server is started. Not available on Trusty.
+void ARpcServer_setSessionListener(ARpcServer* server, ARpcServer_SessionStarted onStarted,
+                                   ARpcServer_SessionEnded onEnded, void* param,
+                                   void (*onDelete)(void* param));
+
+// Called for each new session of an RPC server, before it has started, with the
+// address and credentials of the client as for ARpcServer_SessionStarted, to
+// return the root object of the session with a strong reference for the
+// server to take over, or null to serve the session the server's own root
+// object instead.
+typedef AIBinder* (*ARpcServer_RootObjectFactory)(void* param, const void* address,
+                                                  size_t addressLen,
+                                                  const ARpcPeerCredentials* credentials);
+
+// Makes this RPC server ask `factory` for the root object of each session,
+// instead of serving the same one to all of them. `factory` is called with
+// `param` from the server's threads until `onDelete` is called with it, which
+// may be null. Must be called before the server is started. Not available on
+// Trusty.
+void ARpcServer_setPerSessionRootObject(ARpcServer* server, ARpcServer_RootObjectFactory factory,
+                                        void* param, void (*onDelete)(void* param));
+
 // Sets the list of supported file descriptor transport modes of this RPC server.
 void ARpcServer_setSupportedFileDescriptorTransportModes(
         ARpcServer* handle,
@@ -84,6 +183,9 @@ void ARpcServer_setSupportedFileDescriptorTransportModes(
 // If this is not specified, this will be a single-threaded server.
 void ARpcServer_setMaxThreads(ARpcServer* server, size_t threads);
 
+// Returns the maximum number of threads set with ARpcServer_setMaxThreads().
+size_t ARpcServer_getMaxThreads(ARpcServer* server);
+
 // Runs ARpcServer_join() in a background thread. Immediately returns.
 void ARpcServer_start(ARpcServer* server);
 
@@ -104,6 +206,10 @@ void ARpcServer_free(ARpcServer* server);
 // Allocates a new RpcSession object and returns an opaque handle to it.
 [[nodiscard]] ARpcSession* ARpcSession_new();
 
+// Allocates a new RpcSession object whose connections are encrypted with TLS,
+// and returns an opaque handle to it. The server must use TLS too.
+[[nodiscard]] ARpcSession* ARpcSession_newTls(const ARpcTlsConfig* config);
+
 // Connects to an RPC server over vsock at a given CID on a given port.
 // Returns the root Binder object of the server.
 AIBinder* ARpcSession_setupVsockClient(ARpcSession* session, unsigned int cid,
@@ -138,12 +244,51 @@ AIBinder* ARpcSession_setupPreconnectedClient(ARpcSession* session,
 void ARpcSession_setFileDescriptorTransportMode(ARpcSession* session,
                                                 ARpcSession_FileDescriptorTransportMode mode);
 
+// Sets the integrity mode for this session. With Crc32, every command carries a
+// checksum of its body, and a mismatch terminates the session. The server must
+// support the mode, or setting up the session fails.
+void ARpcSession_setIntegrityMode(ARpcSession* session, ARpcSession_IntegrityMode mode);
+
 // Sets the maximum number of incoming threads, to service connections.
 void ARpcSession_setMaxIncomingThreads(ARpcSession* session, size_t threads);
 
+// Returns the maximum number of incoming threads.
+size_t ARpcSession_getMaxIncomingThreads(ARpcSession* session);
+
 // Sets the maximum number of outgoing connections.
 void ARpcSession_setMaxOutgoingConnections(ARpcSession* session, size_t connections);
 
+// Returns the maximum number of outgoing connections.
+size_t ARpcSession_getMaxOutgoingConnections(ARpcSession* session);
+
+// Called once a session has been set up as a client of an RPC server.
+typedef void (*ARpcSession_Connected)(void* param);
+
+// Called when a connected session ends because its connection to the server
+// failed, e.g. because the server went away or sent bad data.
+typedef void (*ARpcSession_Disconnected)(void* param);
+
+// Called when a connected session ends because it was shut down with
+// RpcSession::shutdownAndWait(), or because it holds no binders of the server
+// anymore.
+typedef void (*ARpcSession_Shutdown)(void* param);
+
+// Sets the callbacks called as this session connects and ends, which are called
+// with `param` until `onDelete` is called with it. After `onConnected`, exactly
+// one of `onDisconnected` and `onShutdown` is called. Any callback and
+// `onDelete` may be null. They may be called from the session's threads, or
+// from the thread setting up or shutting down the session.
+// Must be called before the session is set up. Not available on Trusty.
+void ARpcSession_setSessionListener(ARpcSession* session, ARpcSession_Connected onConnected,
+                                    ARpcSession_Disconnected onDisconnected,
+                                    ARpcSession_Shutdown onShutdown, void* param,
+                                    void (*onDelete)(void* param));
+
+// Sets the RPC protocol version to use, instead of the newest one supported by
+// both the client and the server. Usually only useful for debugging and testing.
+// Returns false if the version isn't supported, or the session is already set up.
+bool ARpcSession_setProtocolVersion(ARpcSession* session, uint32_t version);
+
 // Decrements the refcount of the underlying RpcSession object.
 void ARpcSession_free(ARpcSession* session);
 }
@@ -0,0 +1,232 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include <binder_rpc_accessor.hpp>
+
+#include <android/binder_libbinder.h>
+#include <android/binder_status.h>
+#include <binder/IServiceManager.h>
+#include <linux/vm_sockets.h>
+#include <log/log.h>
+#include <netinet/in.h>
+#include <sys/un.h>
+#include <utils/Errors.h>
+
+#include <cstring>
+#include <memory>
+#include <set>
+#include <string>
+
+using ::android::IBinder;
+using ::android::NAME_NOT_FOUND;
+using ::android::OK;
+using ::android::RpcConnectionProvider;
+using ::android::sp;
+using ::android::status_t;
+using ::android::String16;
+using ::android::String8;
+
+struct ABinderRpc_ConnectionInfo {
+    sockaddr_storage addr;
+    socklen_t len;
+    // A socket already connected to the service, in which case there is no
+    // address.
+    ::android::binder::unique_fd fd;
+};
+
+struct ABinderRpc_Accessor {
+    sp<IBinder> binder;
+};
+
+struct ABinderRpc_AccessorProvider {
+    std::weak_ptr<::android::AccessorProvider> receipt;
+};
+
+namespace {
+
+// Calls the user data's delete callback when the last copy of the provider
+// holding it is destroyed, which is when the accessor binder is destroyed, or
+// when an accessor provider is unregistered and no longer in use.
+class ProviderUserData {
+public:
+    ProviderUserData(void* data, ABinderRpc_ConnectionInfoProviderUserData_delete onDelete)
+          : mData(data), mOnDelete(onDelete) {}
+    ~ProviderUserData() {
+        if (mOnDelete != nullptr) mOnDelete(mData);
+    }
+    void* data() const { return mData; }
+
+private:
+    void* mData;
+    ABinderRpc_ConnectionInfoProviderUserData_delete mOnDelete;
+};
+
+bool isValidAddress(const sockaddr* addr, socklen_t len) {
+    if (len < sizeof(sa_family_t)) return false;
+    switch (addr->sa_family) {
+        case AF_VSOCK:
+            return len == sizeof(sockaddr_vm);
+        case AF_UNIX:
+            return len > offsetof(sockaddr_un, sun_path) && len <= sizeof(sockaddr_un);
+        case AF_INET:
+            return len == sizeof(sockaddr_in);
+        case AF_INET6:
+            return len == sizeof(sockaddr_in6);
+        default:
+            return false;
+    }
+}
+
+} // namespace
+
+ABinderRpc_Accessor* ABinderRpc_Accessor_new(
+        const char* instance, ABinderRpc_ConnectionInfoProvider provider, void* data,
+        ABinderRpc_ConnectionInfoProviderUserData_delete onDelete) {
+    auto userData = std::make_shared<ProviderUserData>(data, onDelete);
+    if (instance == nullptr || provider == nullptr) {
+        ALOGE("%s: instance and provider must not be null", __func__);
+        return nullptr;
+    }
+    RpcConnectionProvider connectionProvider =
+            [provider, userData](const String16& name, sockaddr* outAddr, socklen_t addrSize,
+                                 ::android::binder::unique_fd* outFd) -> status_t {
+        std::unique_ptr<ABinderRpc_ConnectionInfo, decltype(&ABinderRpc_ConnectionInfo_delete)>
+                info(provider(String8(name).c_str(), userData->data()),
+                     ABinderRpc_ConnectionInfo_delete);
+        if (info == nullptr) return NAME_NOT_FOUND;
+        if (info->fd.ok()) {
+            *outFd = std::move(info->fd);
+            return OK;
+        }
+        if (info->len > addrSize) return ::android::BAD_VALUE;
+        memset(outAddr, 0, addrSize);
+        memcpy(outAddr, &info->addr, info->len);
+        return OK;
+    };
+    sp<IBinder> binder =
+            ::android::createAccessor(String16(instance), std::move(connectionProvider));
+    if (binder == nullptr) return nullptr;
+    return new ABinderRpc_Accessor{binder};
+}
+
+void ABinderRpc_Accessor_delete(ABinderRpc_Accessor* accessor) {
+    delete accessor;
+}
+
+ABinderRpc_Accessor* ABinderRpc_Accessor_fromBinder(const char* instance, AIBinder* binder) {
+    if (instance == nullptr || binder == nullptr) {
+        ALOGE("%s: instance and binder must not be null", __func__);
+        return nullptr;
+    }
+    sp<IBinder> accessorBinder = AIBinder_toPlatformBinder(binder);
+    if (status_t status = ::android::validateAccessor(String16(instance), accessorBinder);
+        status != OK) {
+        ALOGE("%s: binder is not an Accessor for %s: %s", __func__, instance,
+              ::android::statusToString(status).c_str());
+        return nullptr;
+    }
+    return new ABinderRpc_Accessor{std::move(accessorBinder)};
+}
+
+int32_t ABinderRpc_Accessor_delegateAccessor(const char* instance, AIBinder* accessor,
+                                             AIBinder** outDelegator) {
+    if (instance == nullptr || accessor == nullptr || outDelegator == nullptr) {
+        ALOGE("%s: instance, accessor and outDelegator must not be null", __func__);
+        return STATUS_UNEXPECTED_NULL;
+    }
+    sp<IBinder> delegator;
+    if (status_t status =
+                ::android::delegateAccessor(String16(instance), AIBinder_toPlatformBinder(accessor),
+                                            &delegator);
+        status != OK) {
+        ALOGE("%s: failed to delegate an Accessor for %s: %s", __func__, instance,
+              ::android::statusToString(status).c_str());
+        return status;
+    }
+    *outDelegator = AIBinder_fromPlatformBinder(delegator);
+    return STATUS_OK;
+}
+
+AIBinder* ABinderRpc_Accessor_asBinder(ABinderRpc_Accessor* accessor) {
+    if (accessor == nullptr) return nullptr;
+    return AIBinder_fromPlatformBinder(accessor->binder);
+}
+
+ABinderRpc_AccessorProvider* ABinderRpc_registerAccessorProvider(
+        ABinderRpc_AccessorProvider_getAccessorCallback provider, const char* const* instances,
+        size_t numInstances, void* data,
+        ABinderRpc_AccessorProviderUserData_deleteCallback onDelete) {
+    auto userData = std::make_shared<ProviderUserData>(data, onDelete);
+    if (provider == nullptr || instances == nullptr || numInstances == 0) {
+        ALOGE("%s: provider and instances must not be null or empty", __func__);
+        return nullptr;
+    }
+    std::set<std::string> instanceSet;
+    for (size_t i = 0; i < numInstances; i++) {
+        if (instances[i] == nullptr) {
+            ALOGE("%s: instance %zu is null", __func__, i);
+            return nullptr;
+        }
+        instanceSet.emplace(instances[i]);
+    }
+    ::android::RpcAccessorProvider accessorProvider =
+            [provider, userData, instanceSet = std::move(instanceSet)](
+                    const String16& name) -> sp<IBinder> {
+        std::string instance(String8(name).c_str());
+        if (instanceSet.count(instance) == 0) return nullptr;
+        std::unique_ptr<ABinderRpc_Accessor, decltype(&ABinderRpc_Accessor_delete)>
+                accessor(provider(instance.c_str(), userData->data()), ABinderRpc_Accessor_delete);
+        if (accessor == nullptr) return nullptr;
+        return accessor->binder;
+    };
+    return new ABinderRpc_AccessorProvider{
+            ::android::addAccessorProvider(std::move(accessorProvider))};
+}
+
+void ABinderRpc_unregisterAccessorProvider(ABinderRpc_AccessorProvider* provider) {
+    if (provider == nullptr) return;
+    if (status_t status = ::android::removeAccessorProvider(provider->receipt); status != OK) {
+        ALOGE("%s: failed to remove the accessor provider: %s", __func__,
+              ::android::statusToString(status).c_str());
+    }
+    delete provider;
+}
+
+ABinderRpc_ConnectionInfo* ABinderRpc_ConnectionInfo_new(const sockaddr* addr, socklen_t len) {
+    if (addr == nullptr || !isValidAddress(addr, len)) {
+        ALOGE("%s: unsupported socket address", __func__);
+        return nullptr;
+    }
+    auto* info = new ABinderRpc_ConnectionInfo{};
+    memcpy(&info->addr, addr, len);
+    info->len = len;
+    return info;
+}
+
+ABinderRpc_ConnectionInfo* ABinderRpc_ConnectionInfo_newPreconnected(int fd) {
+    ::android::binder::unique_fd ownedFd(fd);
+    if (!ownedFd.ok()) {
+        ALOGE("%s: invalid file descriptor", __func__);
+        return nullptr;
+    }
+    auto* info = new ABinderRpc_ConnectionInfo{};
+    info->fd = std::move(ownedFd);
+    return info;
+}
+
+void ABinderRpc_ConnectionInfo_delete(ABinderRpc_ConnectionInfo* info) {
+    delete info;
+}
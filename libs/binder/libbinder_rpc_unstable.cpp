@@ -21,7 +21,12 @@
 #include <binder/RpcSession.h>
 #include <binder/unique_fd.h>
 
+#include <map>
+#include <mutex>
+
 #ifndef __TRUSTY__
+#include <binder/RpcTlsUtils.h>
+#include <binder/RpcTransportTls.h>
 #include <cutils/sockets.h>
 #endif
 
@@ -29,20 +34,67 @@
 #include <linux/vm_sockets.h>
 #endif // __linux__
 
+using android::IBinder;
+using android::NO_INIT;
 using android::OK;
+using android::PERMISSION_DENIED;
 using android::RpcServer;
 using android::RpcSession;
+using android::UNKNOWN_ERROR;
 using android::sp;
 using android::status_t;
 using android::statusToString;
+using android::wp;
 using android::binder::unique_fd;
 
+#ifndef __TRUSTY__
+using android::deserializeCertificate;
+using android::deserializeUnencryptedPrivatekey;
+using android::RpcAuth;
+using android::RpcCertificateFormat;
+using android::RpcCertificateVerifier;
+using android::RpcKeyFormat;
+using android::RpcTransportCtxFactory;
+using android::RpcTransportCtxFactoryTls;
+using android::serializeCertificate;
+#endif // __TRUSTY__
+
 // Opaque handle for RpcServer.
 struct ARpcServer {};
 
 // Opaque handle for RpcSession.
 struct ARpcSession {};
 
+#ifndef __TRUSTY__
+// Owns the parameter of a set of callbacks, which the objects calling them may
+// share, e.g. the TLS contexts created from an ARpcTlsConfig.
+class CallbackParam {
+public:
+    CallbackParam(void* param, void (*onDelete)(void* param))
+          : mParam(param), mOnDelete(onDelete) {}
+    ~CallbackParam() {
+        if (mOnDelete != nullptr) mOnDelete(mParam);
+    }
+    void* get() const { return mParam; }
+
+private:
+    void* mParam;
+    void (*mOnDelete)(void* param);
+};
+
+struct ARpcTlsConfig {
+    ARpcTlsConfig_ProvideCredentials provide;
+    ARpcTlsConfig_VerifyPeer verify;
+    std::shared_ptr<CallbackParam> param;
+};
+
+// The TLS context being configured by a credentials provider.
+struct ARpcTlsCredentials {
+    SSL_CTX* ctx;
+    bool set;
+};
+#endif // __TRUSTY__
+
 template <typename A, typename T>
 static A* createObjectHandle(sp<T>& server) {
     auto ref = server.get();
@@ -78,12 +130,120 @@ RpcSession::FileDescriptorTransportMode toTransportMode(
     }
 }
 
-extern "C" {
+RpcSession::IntegrityMode toIntegrityMode(ARpcSession_IntegrityMode mode) {
+    switch (mode) {
+        case ARpcSession_IntegrityMode::None:
+            return RpcSession::IntegrityMode::NONE;
+        case ARpcSession_IntegrityMode::Crc32:
+            return RpcSession::IntegrityMode::CRC32;
+        default:
+            return RpcSession::IntegrityMode::NONE;
+    }
+}
 
 #ifndef __TRUSTY__
-ARpcServer* ARpcServer_newVsock(AIBinder* service, unsigned int cid, unsigned int port) {
-    auto server = RpcServer::make();
+// Configures TLS contexts with the credentials from the provisioning callback.
+class CallbackAuth : public RpcAuth {
+public:
+    CallbackAuth(ARpcTlsConfig_ProvideCredentials provide, std::shared_ptr<CallbackParam> param)
+          : mProvide(provide), mParam(std::move(param)) {}
+    status_t configure(SSL_CTX* ctx) override {
+        ARpcTlsCredentials credentials{ctx, false};
+        if (!mProvide(mParam->get(), &credentials) || !credentials.set) {
+            ALOGE("The TLS credentials provider did not provide any credentials");
+            return UNKNOWN_ERROR;
+        }
+        return OK;
+    }
+
+private:
+    ARpcTlsConfig_ProvideCredentials mProvide;
+    std::shared_ptr<CallbackParam> mParam;
+};
+
+// Verifies peer certificates with the verification callback.
+class CallbackVerifier : public RpcCertificateVerifier {
+public:
+    CallbackVerifier(ARpcTlsConfig_VerifyPeer verify, std::shared_ptr<CallbackParam> param)
+          : mVerify(verify), mParam(std::move(param)) {}
+    status_t verify(const SSL* ssl, uint8_t* outAlert) override {
+        bssl::UniquePtr<X509> cert(SSL_get_peer_certificate(ssl));
+        if (cert == nullptr) {
+            *outAlert = SSL_AD_CERTIFICATE_REQUIRED;
+            return NO_INIT;
+        }
+        std::vector<uint8_t> der = serializeCertificate(cert.get(), RpcCertificateFormat::DER);
+        if (!mVerify(mParam->get(), der.data(), der.size())) {
+            *outAlert = SSL_AD_BAD_CERTIFICATE;
+            return PERMISSION_DENIED;
+        }
+        return OK;
+    }
+
+private:
+    ARpcTlsConfig_VerifyPeer mVerify;
+    std::shared_ptr<CallbackParam> mParam;
+};
+
+static std::optional<ARpcPeerCredentials> toPeerCredentials(const sp<RpcSession>& session) {
+    std::optional<RpcSession::PeerCredentials> credentials = session->getPeerCredentials();
+    if (!credentials.has_value()) return std::nullopt;
+    return ARpcPeerCredentials{credentials->pid, credentials->uid, credentials->gid};
+}
+
+// Reports sessions starting and ending to the session listener callbacks, with
+// IDs which aren't reused, unlike the addresses of the sessions.
+class SessionListener {
+public:
+    SessionListener(ARpcServer_SessionStarted onStarted, ARpcServer_SessionEnded onEnded,
+                    std::shared_ptr<CallbackParam> param)
+          : mOnStarted(onStarted), mOnEnded(onEnded), mParam(std::move(param)) {}
+
+    void started(const sp<RpcSession>& session, const void* addr, size_t addrLen) {
+        uint64_t id;
+        {
+            std::lock_guard<std::mutex> _l(mLock);
+            id = mNextId++;
+            mIds[session.get()] = id;
+        }
+        if (mOnStarted != nullptr) {
+            std::optional<ARpcPeerCredentials> credentials = toPeerCredentials(session);
+            mOnStarted(mParam->get(), id, addr, addrLen,
+                       credentials.has_value() ? &*credentials : nullptr);
+        }
+    }
 
+    void ended(const sp<RpcSession>& session) {
+        uint64_t id;
+        {
+            std::lock_guard<std::mutex> _l(mLock);
+            auto it = mIds.find(session.get());
+            if (it == mIds.end()) return;
+            id = it->second;
+            mIds.erase(it);
+        }
+        if (mOnEnded != nullptr) mOnEnded(mParam->get(), id);
+    }
+
+private:
+    ARpcServer_SessionStarted mOnStarted;
+    ARpcServer_SessionEnded mOnEnded;
+    std::shared_ptr<CallbackParam> mParam;
+    std::mutex mLock; // for below
+    uint64_t mNextId = 0;
+    std::map<const RpcSession*, uint64_t> mIds;
+};
+
+static std::unique_ptr<RpcTransportCtxFactory> makeTlsFactory(const ARpcTlsConfig* config) {
+    LOG_ALWAYS_FATAL_IF(config == nullptr, "TLS config cannot be null");
+    return RpcTransportCtxFactoryTls::make(std::make_shared<CallbackVerifier>(config->verify,
+                                                                              config->param),
+                                           std::make_unique<CallbackAuth>(config->provide,
+                                                                          config->param));
+}
+
+static ARpcServer* setupVsockServer(sp<RpcServer> server, AIBinder* service,
+                                    unsigned int cid, unsigned int port) {
     unsigned int bindCid = VMADDR_CID_ANY; // bind to the remote interface
     if (cid == VMADDR_CID_LOCAL) {
         bindCid = VMADDR_CID_LOCAL; // bind to the local interface
@@ -111,8 +271,8 @@ ARpcServer* ARpcServer_newVsock(AIBinder* service, unsigned int cid, unsigned in
     return createObjectHandle<ARpcServer>(server);
 }
 
-ARpcServer* ARpcServer_newBoundSocket(AIBinder* service, int socketFd) {
-    auto server = RpcServer::make();
+static ARpcServer* setupBoundSocketServer(sp<RpcServer> server, AIBinder* service,
+                                          int socketFd) {
     auto fd = unique_fd(socketFd);
     if (!fd.ok()) {
         ALOGE("Invalid socket fd %d", socketFd);
@@ -127,8 +287,8 @@ ARpcServer* ARpcServer_newBoundSocket(AIBinder* service, int socketFd) {
     return createObjectHandle<ARpcServer>(server);
 }
 
-ARpcServer* ARpcServer_newUnixDomainBootstrap(AIBinder* service, int bootstrapFd) {
-    auto server = RpcServer::make();
+static ARpcServer* setupUnixDomainBootstrapServer(sp<RpcServer> server, AIBinder* service,
+                                                  int bootstrapFd) {
     auto fd = unique_fd(bootstrapFd);
     if (!fd.ok()) {
         ALOGE("Invalid bootstrap fd %d", bootstrapFd);
@@ -144,8 +304,8 @@ ARpcServer* ARpcServer_newUnixDomainBootstrap(AIBinder* service, int bootstrapFd
     return createObjectHandle<ARpcServer>(server);
 }
 
-ARpcServer* ARpcServer_newInet(AIBinder* service, const char* address, unsigned int port) {
-    auto server = RpcServer::make();
+static ARpcServer* setupInetServer(sp<RpcServer> server, AIBinder* service,
+                                   const char* address, unsigned int port) {
     if (status_t status = server->setupInetServer(address, port, nullptr); status != OK) {
         ALOGE("Failed to set up inet RPC server with address %s and port %u error: %s", address,
               port, statusToString(status).c_str());
@@ -156,6 +316,113 @@ ARpcServer* ARpcServer_newInet(AIBinder* service, const char* address, unsigned
 }
 #endif // __TRUSTY__
 
+extern "C" {
+
+#ifndef __TRUSTY__
+ARpcServer* ARpcServer_newVsock(AIBinder* service, unsigned int cid, unsigned int port) {
+    return setupVsockServer(RpcServer::make(), service, cid, port);
+}
+
+ARpcServer* ARpcServer_newBoundSocket(AIBinder* service, int socketFd) {
+    return setupBoundSocketServer(RpcServer::make(), service, socketFd);
+}
+
+ARpcServer* ARpcServer_newUnixDomainBootstrap(AIBinder* service, int bootstrapFd) {
+    return setupUnixDomainBootstrapServer(RpcServer::make(), service, bootstrapFd);
+}
+
+ARpcServer* ARpcServer_newInet(AIBinder* service, const char* address, unsigned int port) {
+    return setupInetServer(RpcServer::make(), service, address, port);
+}
+
+ARpcTlsConfig* ARpcTlsConfig_new(ARpcTlsConfig_ProvideCredentials provide,
+                                 ARpcTlsConfig_VerifyPeer verify, void* param,
+                                 ARpcTlsConfig_DeleteParam onDelete) {
+    auto callbackParam = std::make_shared<CallbackParam>(param, onDelete);
+    if (provide == nullptr || verify == nullptr) {
+        ALOGE("%s: provide and verify must not be null", __func__);
+        return nullptr;
+    }
+    return new ARpcTlsConfig{provide, verify, std::move(callbackParam)};
+}
+
+void ARpcTlsConfig_free(ARpcTlsConfig* config) {
+    delete config;
+}
+
+bool ARpcTlsCredentials_set(ARpcTlsCredentials* credentials, const uint8_t* certificate,
+                            size_t certificateLen, const uint8_t* privateKey,
+                            size_t privateKeyLen) {
+    LOG_ALWAYS_FATAL_IF(credentials == nullptr, "Credentials cannot be null");
+    auto cert = deserializeCertificate(std::vector<uint8_t>(certificate,
+                                                            certificate + certificateLen),
+                                       RpcCertificateFormat::PEM);
+    auto key =
+            deserializeUnencryptedPrivatekey(std::vector<uint8_t>(privateKey,
+                                                                  privateKey + privateKeyLen),
+                                             RpcKeyFormat::PEM);
+    if (cert == nullptr || key == nullptr) {
+        ALOGE("%s: failed to parse the PEM certificate or private key", __func__);
+        return false;
+    }
+    if (!SSL_CTX_use_certificate(credentials->ctx, cert.get()) ||
+        !SSL_CTX_use_PrivateKey(credentials->ctx, key.get())) {
+        ALOGE("%s: the certificate and private key don't match", __func__);
+        return false;
+    }
+    credentials->set = true;
+    return true;
+}
+
+ARpcServer* ARpcServer_newVsockTls(AIBinder* service, unsigned int cid, unsigned int port,
+                                   const ARpcTlsConfig* config) {
+    return setupVsockServer(RpcServer::make(makeTlsFactory(config)), service, cid, port);
+}
+
+ARpcServer* ARpcServer_newBoundSocketTls(AIBinder* service, int socketFd,
+                                         const ARpcTlsConfig* config) {
+    return setupBoundSocketServer(RpcServer::make(makeTlsFactory(config)), service, socketFd);
+}
+
+ARpcServer* ARpcServer_newInetTls(AIBinder* service, const char* address, unsigned int port,
+                                  const ARpcTlsConfig* config) {
+    return setupInetServer(RpcServer::make(makeTlsFactory(config)), service, address, port);
+}
+
+void ARpcServer_setSessionListener(ARpcServer* handle, ARpcServer_SessionStarted onStarted,
+                                   ARpcServer_SessionEnded onEnded, void* param,
+                                   void (*onDelete)(void* param)) {
+    auto listener =
+            std::make_shared<SessionListener>(onStarted, onEnded,
+                                              std::make_shared<CallbackParam>(param, onDelete));
+    handleToStrongPointer<RpcServer>(handle)->setSessionListener(
+            [listener](const sp<RpcSession>& session, const void* addr, size_t addrLen) {
+                listener->started(session, addr, addrLen);
+            },
+            [listener](const sp<RpcSession>& session) { listener->ended(session); });
+}
+
+void ARpcServer_setPerSessionRootObject(ARpcServer* handle, ARpcServer_RootObjectFactory factory,
+                                        void* param, void (*onDelete)(void* param)) {
+    LOG_ALWAYS_FATAL_IF(factory == nullptr, "Root object factory cannot be null");
+    auto factoryParam = std::make_shared<CallbackParam>(param, onDelete);
+    handleToStrongPointer<RpcServer>(handle)->setPerSessionRootObject(
+            [factory, factoryParam](wp<RpcSession> weakSession, const void* addr, size_t addrLen) {
+                std::optional<ARpcPeerCredentials> credentials;
+                if (sp<RpcSession> session = weakSession.promote(); session != nullptr) {
+                    credentials = toPeerCredentials(session);
+                }
+                AIBinder* root = factory(factoryParam->get(), addr, addrLen,
+                                         credentials.has_value() ? &*credentials : nullptr);
+                if (root == nullptr) return sp<IBinder>();
+                auto binder = AIBinder_toPlatformBinder(root);
+                // The server holds the root object through the new sp<IBinder> from here on.
+                AIBinder_decStrong(root);
+                return binder;
+            });
+}
+#endif // __TRUSTY__
+
 void ARpcServer_setSupportedFileDescriptorTransportModes(
         ARpcServer* handle, const ARpcSession_FileDescriptorTransportMode modes[],
         size_t modes_len) {
@@ -171,6 +438,10 @@ void ARpcServer_setMaxThreads(ARpcServer* handle, size_t threads) {
     handleToStrongPointer<RpcServer>(handle)->setMaxThreads(threads);
 }
 
+size_t ARpcServer_getMaxThreads(ARpcServer* handle) {
+    return handleToStrongPointer<RpcServer>(handle)->getMaxThreads();
+}
+
 void ARpcServer_start(ARpcServer* handle) {
     handleToStrongPointer<RpcServer>(handle)->start();
 }
@@ -195,6 +466,13 @@ ARpcSession* ARpcSession_new() {
     return createObjectHandle<ARpcSession>(session);
 }
 
+#ifndef __TRUSTY__
+ARpcSession* ARpcSession_newTls(const ARpcTlsConfig* config) {
+    auto session = RpcSession::make(makeTlsFactory(config));
+    return createObjectHandle<ARpcSession>(session);
+}
+#endif // __TRUSTY__
+
 void ARpcSession_free(ARpcSession* handle) {
     freeObjectHandle<RpcSession>(handle);
 }
@@ -247,6 +525,20 @@ AIBinder* ARpcSession_setupInet(ARpcSession* handle, const char* address, unsign
     }
     return AIBinder_fromPlatformBinder(session->getRootObject());
 }
+
+void ARpcSession_setSessionListener(ARpcSession* handle, ARpcSession_Connected onConnected,
+                                    ARpcSession_Disconnected onDisconnected,
+                                    ARpcSession_Shutdown onShutdown, void* param,
+                                    void (*onDelete)(void* param)) {
+    auto listenerParam = std::make_shared<CallbackParam>(param, onDelete);
+    auto callback = [listenerParam](void (*fn)(void* param)) -> std::function<void()> {
+        if (fn == nullptr) return nullptr;
+        return [fn, listenerParam] { fn(listenerParam->get()); };
+    };
+    handleToStrongPointer<RpcSession>(handle)->setSessionListener(callback(onConnected),
+                                                                  callback(onDisconnected),
+                                                                  callback(onShutdown));
+}
 #endif // __TRUSTY__
 
 AIBinder* ARpcSession_setupPreconnectedClient(ARpcSession* handle, int (*requestFd)(void* param),
@@ -266,13 +558,33 @@ void ARpcSession_setFileDescriptorTransportMode(ARpcSession* handle,
     session->setFileDescriptorTransportMode(toTransportMode(mode));
 }
 
+void ARpcSession_setIntegrityMode(ARpcSession* handle, ARpcSession_IntegrityMode mode) {
+    auto session = handleToStrongPointer<RpcSession>(handle);
+    session->setIntegrityMode(toIntegrityMode(mode));
+}
+
 void ARpcSession_setMaxIncomingThreads(ARpcSession* handle, size_t threads) {
     auto session = handleToStrongPointer<RpcSession>(handle);
     session->setMaxIncomingThreads(threads);
 }
 
+size_t ARpcSession_getMaxIncomingThreads(ARpcSession* handle) {
+    auto session = handleToStrongPointer<RpcSession>(handle);
+    return session->getMaxIncomingThreads();
+}
+
 void ARpcSession_setMaxOutgoingConnections(ARpcSession* handle, size_t connections) {
     auto session = handleToStrongPointer<RpcSession>(handle);
     session->setMaxOutgoingConnections(connections);
 }
+
+size_t ARpcSession_getMaxOutgoingConnections(ARpcSession* handle) {
+    auto session = handleToStrongPointer<RpcSession>(handle);
+    return session->getMaxOutgoingThreads();
+}
+
+bool ARpcSession_setProtocolVersion(ARpcSession* handle, uint32_t version) {
+    auto session = handleToStrongPointer<RpcSession>(handle);
+    return session->setProtocolVersion(version);
+}
 }
@@ -18,6 +18,7 @@
 #include <android/binder_ibinder_platform.h>
 #include <android/binder_stability.h>
 #include <android/binder_status.h>
+#include <binder/BpBinder.h>
 #include <binder/IPCThreadState.h>
 #include <binder/IResultReceiver.h>
 #if __has_include(<private/android_filesystem_config.h>)
@@ -873,3 +874,86 @@ void AIBinder_setInheritRt(AIBinder* binder, bool inheritRt) {
 
     localBinder->setInheritRt(inheritRt);
 }
+
+binder_status_t AIBinder_getDebugPid(AIBinder* binder, pid_t* outPid) {
+    if (binder == nullptr || outPid == nullptr) {
+        return STATUS_UNEXPECTED_NULL;
+    }
+
+    return PruneStatusT(binder->getBinder()->getDebugPid(outPid));
+}
+
+void* AIBinder_attachObject(AIBinder* binder, const void* id, void* object, void* cookie,
+                            AIBinder_Object_cleanup cleanup) {
+    LOG_ALWAYS_FATAL_IF(binder == nullptr || id == nullptr,
+                        "AIBinder_attachObject requires a binder and an id");
+    return binder->getBinder()->attachObject(id, object, cookie, cleanup);
+}
+
+void* AIBinder_findObject(AIBinder* binder, const void* id) {
+    LOG_ALWAYS_FATAL_IF(binder == nullptr || id == nullptr,
+                        "AIBinder_findObject requires a binder and an id");
+    return binder->getBinder()->findObject(id);
+}
+
+struct AIBinder_FrozenStateCallback : public IBinder::FrozenStateChangeCallback {
+    AIBinder_FrozenStateCallback(const sp<IBinder>& binder,
+                                 AIBinder_FrozenStateCallback_onStateChanged onStateChanged,
+                                 void* cookie, AIBinder_FrozenStateCallback_onDelete onDelete)
+          : mBinder(binder),
+            mOnStateChanged(onStateChanged),
+            mCookie(cookie),
+            mOnDelete(onDelete) {}
+    ~AIBinder_FrozenStateCallback() {
+        if (mOnDelete != nullptr) {
+            mOnDelete(mCookie);
+        }
+    }
+
+    void onStateChanged(const wp<IBinder>& /*who*/, State state) override {
+        mOnStateChanged(mCookie, state == State::FROZEN);
+    }
+
+    wp<IBinder> mBinder;
+    AIBinder_FrozenStateCallback_onStateChanged mOnStateChanged;
+    void* mCookie;
+    AIBinder_FrozenStateCallback_onDelete mOnDelete;
+};
+
+binder_status_t AIBinder_addFrozenStateChangeCallback(
+        AIBinder* binder, AIBinder_FrozenStateCallback_onStateChanged onStateChanged,
+        void* cookie, AIBinder_FrozenStateCallback_onDelete onDelete,
+        AIBinder_FrozenStateCallback** outCallback) {
+    if (binder == nullptr || onStateChanged == nullptr || outCallback == nullptr) {
+        return STATUS_UNEXPECTED_NULL;
+    }
+    sp<IBinder> target = binder->getBinder();
+    ::android::BpBinder* proxy = target->remoteBinder();
+    // libbinder aborts on RPC binders rather than failing.
+    if (proxy == nullptr || proxy->isRpcBinder()) {
+        return STATUS_INVALID_OPERATION;
+    }
+
+    auto callback =
+            sp<AIBinder_FrozenStateCallback>::make(target, onStateChanged, cookie, onDelete);
+    status_t status = target->addFrozenStateChangeCallback(callback);
+    if (status != ::android::OK) {
+        // The caller keeps ownership of the cookie if registration fails.
+        callback->mOnDelete = nullptr;
+        return PruneStatusT(status);
+    }
+    // The binder only holds a weak reference, so the caller owns this one.
+    callback->incStrong(nullptr);
+    *outCallback = callback.get();
+    return STATUS_OK;
+}
+
+void AIBinder_removeFrozenStateChangeCallback(AIBinder_FrozenStateCallback* callback) {
+    if (callback == nullptr) {
+        return;
+    }
+    if (sp<IBinder> binder = callback->mBinder.promote(); binder != nullptr) {
+        binder->removeFrozenStateChangeCallback(callback);
+    }
+    callback->decStrong(nullptr);
+}
@@ -80,4 +80,122 @@ void AIBinder_setMinSchedulerPolicy(AIBinder* binder, int policy, int priority)
  */
 void AIBinder_setInheritRt(AIBinder* binder, bool inheritRt) __INTRODUCED_IN(33);
 
+/**
+ * Gets the PID of the process hosting a binder object, for debugging.
+ *
+ * For a remote binder, this makes a transaction to the hosting process, which
+ * reports its own PID. The result must not be used for security decisions:
+ * the remote process may lie, and the PID may have been reused by the time it
+ * is returned.
+ *
+ * \param binder the binder object to get the PID of.
+ * \param outPid set to the PID of the hosting process on success.
+ *
+ * \return STATUS_OK on success, STATUS_UNEXPECTED_NULL if either argument is
+ * null, or the error of the transaction to the remote process.
+ */
+binder_status_t AIBinder_getDebugPid(AIBinder* binder, pid_t* outPid) __INTRODUCED_IN(36);
+
+/**
+ * Called with an object attached to a binder object with AIBinder_attachObject
+ * when the binder object is destroyed.
+ *
+ * \param id the ID the object was attached with.
+ * \param object the attached object.
+ * \param cookie the cookie given to AIBinder_attachObject.
+ */
+typedef void (*AIBinder_Object_cleanup)(const void* _Nonnull id, void* _Nullable object,
+                                        void* _Nullable cookie);
+
+/**
+ * Attaches an object to the binder object, for the lifetime of the binder
+ * object. This is the same binder object for every AIBinder referring to it in
+ * this process, so this associates state with e.g. a remote service without a
+ * separate map keyed by binder identity.
+ *
+ * Each ID can only be used once per binder object, and IDs are compared by
+ * address, so they are usually the address of a static variable.
+ *
+ * \param binder the binder object to attach the object to.
+ * \param id the ID to attach the object with.
+ * \param object the object to attach.
+ * \param cookie passed to cleanup.
+ * \param cleanup called with the object when the binder object is destroyed.
+ * May be null.
+ *
+ * \return null if the object was attached, or else the object already
+ * attached with id, in which case cleanup is not called for the new object.
+ */
+void* _Nullable AIBinder_attachObject(AIBinder* _Nonnull binder, const void* _Nonnull id,
+                                      void* _Nullable object, void* _Nullable cookie,
+                                      AIBinder_Object_cleanup _Nullable cleanup)
+        __INTRODUCED_IN(36);
+
+/**
+ * Gets the object attached to the binder object with AIBinder_attachObject.
+ *
+ * \param binder the binder object the object is attached to.
+ * \param id the ID the object was attached with.
+ *
+ * \return the object, or null if there is none. It stays valid at least as
+ * long as the caller holds a strong reference to the binder object.
+ */
+void* _Nullable AIBinder_findObject(AIBinder* _Nonnull binder, const void* _Nonnull id)
+        __INTRODUCED_IN(36);
+
+
+/**
+ * Registered with AIBinder_addFrozenStateChangeCallback, and owns the callback
+ * until it is removed with AIBinder_removeFrozenStateChangeCallback.
+ */
+typedef struct AIBinder_FrozenStateCallback AIBinder_FrozenStateCallback;
+
+/**
+ * Called when the process hosting a binder object is frozen or unfrozen.
+ *
+ * \param cookie the cookie given to AIBinder_addFrozenStateChangeCallback.
+ * \param isFrozen whether the process is now frozen.
+ */
+typedef void (*AIBinder_FrozenStateCallback_onStateChanged)(void* _Nullable cookie,
+                                                            bool isFrozen);
+
+/**
+ * Called with the cookie given to AIBinder_addFrozenStateChangeCallback once
+ * the callback will no longer be called.
+ */
+typedef void (*AIBinder_FrozenStateCallback_onDelete)(void* _Nullable cookie);
+
+/**
+ * Calls onStateChanged whenever the process hosting a remote binder object is
+ * frozen or unfrozen, and once with its current state when it is registered.
+ *
+ * Changes may be combined, e.g. if the calling process is frozen itself, so
+ * only the latest state is reliable, not the number of calls.
+ *
+ * \param binder the remote binder object to watch.
+ * \param onStateChanged called with the new state.
+ * \param cookie passed to onStateChanged and onDelete.
+ * \param onDelete called once the callback is removed. May be null.
+ * \param outCallback set to the registered callback on success.
+ *
+ * \return STATUS_OK on success, STATUS_UNEXPECTED_NULL if a required argument
+ * is null, or STATUS_INVALID_OPERATION if binder is local or the kernel doesn't
+ * support freeze notifications. On failure, onDelete is not called.
+ */
+binder_status_t AIBinder_addFrozenStateChangeCallback(
+        AIBinder* _Nonnull binder,
+        AIBinder_FrozenStateCallback_onStateChanged _Nonnull onStateChanged,
+        void* _Nullable cookie, AIBinder_FrozenStateCallback_onDelete _Nullable onDelete,
+        AIBinder_FrozenStateCallback* _Nullable* _Nonnull outCallback) __INTRODUCED_IN(36);
+
+/**
+ * Removes a callback registered with AIBinder_addFrozenStateChangeCallback and
+ * deletes it. onDelete is called once any call to onStateChanged in progress
+ * has returned.
+ *
+ * \param callback the callback to remove. May be null.
+ */
+void AIBinder_removeFrozenStateChangeCallback(AIBinder_FrozenStateCallback* _Nullable callback)
+        __INTRODUCED_IN(36);
+
 __END_DECLS
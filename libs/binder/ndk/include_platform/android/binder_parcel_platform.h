@@ -44,4 +44,80 @@ bool AParcel_getAllowFds(const AParcel*);
  */
 void AParcel_markSensitive(const AParcel* parcel);
 
+/**
+ * Reads an array of bytes written by AParcel_writeByteArray, without copying
+ * it out of the parcel.
+ *
+ * \param parcel the parcel to read from.
+ * \param outArray set to point at the bytes within the parcel's data, or to
+ * null if the array is null or empty. This stays valid until the parcel is
+ * written to, reset or deleted.
+ * \param outLength set to the length of the array, or -1 if it is null.
+ *
+ * \return STATUS_OK on successful read.
+ */
+binder_status_t AParcel_readByteArrayInPlace(const AParcel* parcel, const int8_t** outArray,
+                                             int32_t* outLength) __INTRODUCED_IN(36);
+
+/**
+ * Enables or disables a pool of parcel data buffers for the calling thread.
+ *
+ * While the pool is enabled, the buffers of small parcels deleted on this
+ * thread are kept, and reused by the next parcels written on it, e.g. those
+ * from AIBinder_prepareTransaction. This saves an allocation and a free for
+ * each transaction of a thread making many small calls. Disabling the pool
+ * frees the buffers it holds.
+ *
+ * \param enabled whether to pool buffers on this thread.
+ */
+void AParcel_setThreadBufferPoolEnabled(bool enabled) __INTRODUCED_IN(36);
+
+/**
+ * Writes a file descriptor as a bare binder object, as Java's
+ * Parcel.writeFileDescriptor does, rather than as a nullable
+ * ParcelFileDescriptor. This is the format of android.os.SharedMemory.
+ *
+ * \param parcel the parcel to write to.
+ * \param fd the file descriptor to write, which is duplicated. It must not be
+ * negative.
+ *
+ * \return STATUS_OK on successful write.
+ */
+binder_status_t AParcel_writeRawFileDescriptor(AParcel* parcel, int fd) __INTRODUCED_IN(36);
+
+/**
+ * Reads a file descriptor written by AParcel_writeRawFileDescriptor, or by
+ * Java's Parcel.writeFileDescriptor.
+ *
+ * \param parcel the parcel to read from.
+ * \param fd set to a duplicate of the file descriptor, owned by the caller.
+ *
+ * \return STATUS_OK on successful read.
+ */
+binder_status_t AParcel_readRawFileDescriptor(const AParcel* parcel, int* fd) __INTRODUCED_IN(36);
+
+/**
+ * Shrinks the data of the parcel to \p size bytes, dropping everything after
+ * it, including the objects written there.
+ *
+ * \param parcel the parcel to shrink.
+ * \param size the new size of the parcel's data, which must not be more than
+ * its current size.
+ *
+ * \return STATUS_OK on success, or STATUS_BAD_VALUE if \p size is negative or
+ * more than the current size.
+ */
+binder_status_t AParcel_truncate(AParcel* parcel, int32_t size) __INTRODUCED_IN(36);
+
+/**
+ * Whether data written to the parcel will be zero'd before being deleted or
+ * realloced, because of AParcel_markSensitive, or because the parcel is part
+ * of a transaction which either side sent with FLAG_CLEAR_BUF.
+ *
+ * \param parcel the parcel to check.
+ *
+ * \return true if the parcel is sensitive.
+ */
+bool AParcel_isSensitive(const AParcel* parcel) __INTRODUCED_IN(36);
+
 __END_DECLS
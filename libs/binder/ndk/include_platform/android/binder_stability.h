@@ -64,6 +64,17 @@ static inline void AIBinder_forceDowngradeToLocalStability(AIBinder* binder) {
     AIBinder_forceDowngradeToVendorStability(binder);
 }
 
+/**
+ * Whether user transactions on this binder from vendor code pass its stability
+ * check, i.e. whether it has at least the stability of the vendor image.
+ * Transactions on local binders aren't checked, so this is always true for them.
+ */
+bool AIBinder_isVendorStable(AIBinder* binder) __INTRODUCED_IN(36);
+
+static inline bool AIBinder_isCompilationUnitStable(AIBinder* binder) {
+    return AIBinder_isVendorStable(binder);
+}
+
 #else  // defined(__ANDROID_VENDOR__)
 
 enum {
@@ -102,6 +113,17 @@ static inline void AIBinder_forceDowngradeToLocalStability(AIBinder* binder) {
     AIBinder_forceDowngradeToSystemStability(binder);
 }
 
+/**
+ * Whether user transactions on this binder from system code pass its stability
+ * check, i.e. whether it has at least the stability of the system image.
+ * Transactions on local binders aren't checked, so this is always true for them.
+ */
+bool AIBinder_isSystemStable(AIBinder* binder) __INTRODUCED_IN(36);
+
+static inline bool AIBinder_isCompilationUnitStable(AIBinder* binder) {
+    return AIBinder_isSystemStable(binder);
+}
+
 #endif  // defined(__ANDROID_VENDOR__)
 
 /**
@@ -309,6 +309,29 @@ binder_status_t AParcel_readParcelFileDescriptor(const AParcel* parcel, int* fd)
     return STATUS_OK;
 }
 
+binder_status_t AParcel_writeRawFileDescriptor(AParcel* parcel, int fd) {
+    if (fd < 0) return STATUS_BAD_VALUE;
+    return PruneStatusT(parcel->get()->writeDupFileDescriptor(fd));
+}
+
+binder_status_t AParcel_readRawFileDescriptor(const AParcel* parcel, int* fd) {
+    unique_fd out;
+    status_t status = parcel->get()->readUniqueFileDescriptor(&out);
+    if (status != STATUS_OK) return PruneStatusT(status);
+
+    *fd = out.release();
+    return STATUS_OK;
+}
+
+binder_status_t AParcel_truncate(AParcel* parcel, int32_t size) {
+    if (size < 0 || static_cast<size_t>(size) > parcel->get()->dataSize()) return STATUS_BAD_VALUE;
+    return PruneStatusT(parcel->get()->setDataSize(size));
+}
+
+bool AParcel_isSensitive(const AParcel* parcel) {
+    return parcel->get()->isSensitive();
+}
+
 binder_status_t AParcel_writeStatusHeader(AParcel* parcel, const AStatus* status) {
     return PruneStatusT(status->get().writeToParcel(parcel->get()));
 }
@@ -675,6 +698,28 @@ binder_status_t AParcel_readByteArray(const AParcel* parcel, void* arrayData,
     return ReadArray<int8_t>(parcel, arrayData, allocator);
 }
 
+binder_status_t AParcel_readByteArrayInPlace(const AParcel* parcel, const int8_t** outArray,
+                                             int32_t* outLength) {
+    int32_t length;
+    if (binder_status_t status = ReadAndValidateArraySize(parcel, &length); status != STATUS_OK) {
+        return status;
+    }
+
+    *outArray = nullptr;
+    *outLength = length;
+    if (length <= 0) return STATUS_OK;
+
+    const void* data = parcel->get()->readInplace(length);
+    if (data == nullptr) return STATUS_NO_MEMORY;
+
+    *outArray = static_cast<const int8_t*>(data);
+    return STATUS_OK;
+}
+
+void AParcel_setThreadBufferPoolEnabled(bool enabled) {
+    Parcel::setThreadBufferPoolEnabled(enabled);
+}
+
 bool AParcel_getAllowFds(const AParcel* parcel) {
     return parcel->get()->allowFds();
 }
@@ -56,3 +56,12 @@ extern "C" void AIBinder_forceDowngradeToVendorStability(AIBinder* binder) {
 void AIBinder_forceDowngradeToSystemStability(AIBinder* binder) {
     Stability::forceDowngradeToSystemStability(binder->getBinder());
 }
+
+// explicit extern because symbol is only declared in header when __ANDROID_VENDOR__
+extern "C" bool AIBinder_isVendorStable(AIBinder* binder) {
+    return Stability::allowsTransactionsFrom(binder->getBinder(), true /*vendor*/);
+}
+
+bool AIBinder_isSystemStable(AIBinder* binder) {
+    return Stability::allowsTransactionsFrom(binder->getBinder(), false /*vendor*/);
+}
@@ -35,6 +35,7 @@
 #include <binder/IShellCallback.h>
 #include <sys/prctl.h>
 #include <sys/socket.h>
+#include <unistd.h>
 
 #include <chrono>
 #include <condition_variable>
@@ -801,6 +802,18 @@ TEST(NdkBinder, SetInheritRt) {
     AIBinder_decStrong(binder);
 }
 
+TEST(NdkBinder, GetDebugPid) {
+    sp<IFoo> foo = sp<MyTestFoo>::make();
+    AIBinder* binder = foo->getBinder();
+
+    pid_t pid = 0;
+    EXPECT_EQ(STATUS_OK, AIBinder_getDebugPid(binder, &pid));
+    EXPECT_EQ(getpid(), pid);
+    EXPECT_EQ(STATUS_UNEXPECTED_NULL, AIBinder_getDebugPid(binder, nullptr));
+
+    AIBinder_decStrong(binder);
+}
+
 TEST(NdkBinder, SetInheritRtNonLocal) {
     LIBBINDER_IGNORE("-Wdeprecated-declarations")
     AIBinder* binder = AServiceManager_getService(kExistingNonNdkService);
@@ -0,0 +1,629 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Attribute and derive macros for binder services.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Expr, Fields, Ident,
+    ImplItem, ImplItemFn, ItemImpl, LitStr, Variant,
+};
+
+/// Require the caller of a service method to hold an Android permission.
+///
+/// The method fails with a `SECURITY` exception, before any of its body runs,
+/// if the caller doesn't hold the permission, as checked by
+/// `binder::enforce_calling_permission`. The permission is also added to the
+/// method's documentation, so that the generated docs list which permission
+/// each method needs, and `#[permission_table]` on the `impl` block collects
+/// them into a table. Use the attribute several times to require several
+/// permissions.
+///
+/// ```ignore
+/// impl IFoo for FooService {
+///     #[requires_permission("android.permission.DUMP")]
+///     fn dumpState(&self) -> binder::Result<String> {
+///         Ok(self.state.lock().unwrap().to_string())
+///     }
+/// }
+/// ```
+///
+/// The method must return `binder::Result`, and must not be `async`, as only
+/// the binder thread handling the transaction knows who the caller is.
+#[proc_macro_attribute]
+pub fn requires_permission(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let permission = parse_macro_input!(attr as LitStr);
+    let mut method = parse_macro_input!(item as ImplItemFn);
+
+    let value = permission.value();
+    if value.is_empty() || value.contains(char::is_whitespace) || !value.contains('.') {
+        let message = "expected a permission such as \"android.permission.FOO\"";
+        return Error::new(permission.span(), message).into_compile_error().into();
+    }
+    if let Some(asyncness) = method.sig.asyncness {
+        return Error::new(
+            asyncness.span,
+            "#[requires_permission] can't be used on async methods, which may not run on the \
+             binder thread that knows the caller",
+        )
+        .into_compile_error()
+        .into();
+    }
+
+    let name = method.sig.ident.to_string();
+    let doc = format!(" Requires the `{value}` permission.");
+    method.attrs.push(parse_quote!(#[doc = ""]));
+    method.attrs.push(parse_quote!(#[doc = #doc]));
+    let stmts = &method.block.stmts;
+    method.block = parse_quote!({
+        ::binder::enforce_calling_permission(#name, #permission)?;
+        #(#stmts)*
+    });
+    quote!(#method).into()
+}
+
+/// Record the permissions the methods of a service need, from their
+/// `#[requires_permission("...")]` attributes, in one place.
+///
+/// On an `impl IFoo for FooService` block, this implements
+/// `binder::PermissionTable<dyn IFoo>` for `FooService`, whose `PERMISSIONS`
+/// list each method which requires a permission along with the permission, so
+/// that the permissions a service enforces can be audited or checked in tests
+/// without reading every method. The methods themselves are unchanged.
+///
+/// ```ignore
+/// #[permission_table]
+/// impl IFoo for FooService {
+///     #[requires_permission("android.permission.DUMP")]
+///     fn dumpState(&self) -> binder::Result<String> {
+///         Ok(self.state.lock().unwrap().to_string())
+///     }
+/// }
+///
+/// assert_eq!(
+///     <FooService as PermissionTable<dyn IFoo>>::PERMISSIONS,
+///     &[MethodPermission { method: "dumpState", permission: "android.permission.DUMP" }],
+/// );
+/// ```
+#[proc_macro_attribute]
+pub fn permission_table(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let message = "#[permission_table] takes no arguments";
+        return Error::new(proc_macro2::Span::call_site(), message).into_compile_error().into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+    match permission_table_impl(&item) {
+        Ok(table) => quote!(#item #table).into(),
+        Err(error) => error.into_compile_error().into(),
+    }
+}
+
+fn permission_table_impl(item: &ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    let Some((None, interface, _)) = &item.trait_ else {
+        return Err(Error::new_spanned(
+            &item.self_ty,
+            "#[permission_table] can only be used on an implementation of a binder interface",
+        ));
+    };
+    let mut entries = Vec::new();
+    for item in &item.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let name = method.sig.ident.to_string();
+        for attr in &method.attrs {
+            if attr
+                .path()
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "requires_permission")
+            {
+                let permission: LitStr = attr.parse_args()?;
+                entries.push(quote! {
+                    ::binder::MethodPermission { method: #name, permission: #permission }
+                });
+            }
+        }
+    }
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::binder::PermissionTable<dyn #interface> for #self_ty #where_clause {
+            const PERMISSIONS: &'static [::binder::MethodPermission] = &[#(#entries),*];
+        }
+    })
+}
+
+/// Derive `binder::Parcelable` for a struct, along with the `Serialize` and
+/// `Deserialize` traits and their array and nullable variants, as the AIDL
+/// compiler generates them for a structured parcelable.
+///
+/// The fields are written in the order they are declared, inside a size
+/// header, so the struct has the wire format of an AIDL parcelable with the
+/// same fields. As for AIDL parcelables, new fields can be added at the end:
+/// fields missing from data written by an older version keep their default
+/// values, and fields added by a newer version are skipped.
+///
+/// ```ignore
+/// #[derive(Default, Parcelable)]
+/// struct Config {
+///     name: String,
+///     retries: i32,
+///     tags: Vec<String>,
+/// }
+/// ```
+///
+/// Fields must implement `Serialize` and `Deserialize`, and the struct must
+/// implement `Default`, which gives the value of missing fields and of a
+/// struct read in place. Generic structs aren't supported.
+#[proc_macro_derive(Parcelable)]
+pub fn derive_parcelable(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            let message = "#[derive(Parcelable)] can only be used on structs";
+            return Error::new_spanned(&input.ident, message).into_compile_error().into();
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Error::new_spanned(
+            &input.generics,
+            "#[derive(Parcelable)] can't be used on generic structs; implement `Parcelable` by \
+             hand and use `impl_serialize_for_parcelable!` and `impl_deserialize_for_parcelable!`",
+        )
+        .into_compile_error()
+        .into();
+    }
+
+    let members: Vec<_> = fields.members().collect();
+    // Unit structs are written as an empty parcelable, without using the subparcel.
+    let subparcel = if members.is_empty() { quote!(_subparcel) } else { quote!(subparcel) };
+
+    let name = &input.ident;
+    quote! {
+        impl ::binder::Parcelable for #name {
+            fn write_to_parcel(
+                &self,
+                parcel: &mut ::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                parcel.sized_write(|#subparcel| {
+                    #(subparcel.write(&self.#members)?;)*
+                    Ok(())
+                })
+            }
+
+            fn read_from_parcel(
+                &mut self,
+                parcel: &::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                parcel.sized_read(|#subparcel| {
+                    #(
+                        if subparcel.has_more_data() {
+                            self.#members = subparcel.read()?;
+                        }
+                    )*
+                    Ok(())
+                })
+            }
+        }
+
+        ::binder::impl_serialize_for_parcelable!(#name);
+        ::binder::impl_deserialize_for_parcelable!(#name);
+    }
+    .into()
+}
+
+/// Derive the parcel `Serialize` and `Deserialize` traits, and their array
+/// variants, for an enum with explicit discriminants, writing it as its
+/// backing type as for an AIDL enum.
+///
+/// The enum must have a `#[repr(i8)]`, `#[repr(i32)]` or `#[repr(i64)]`
+/// attribute, for an AIDL `byte`, `int` or `long` enum, and arrays of it are
+/// written as arrays of that type. The derive also implements
+/// `From<&Enum>` and `From<Enum>` for the backing type, and `TryFrom` of the
+/// backing type for the enum.
+///
+/// Reading a value which matches none of the discriminants fails with
+/// `BAD_VALUE`, unless one variant holding just the backing type is marked
+/// with `#[parcel(unknown)]`, in which case the raw value is kept in that
+/// variant, and written back out unchanged:
+///
+/// ```ignore
+/// #[derive(Clone, Copy, Debug, ParcelableEnum)]
+/// #[repr(i32)]
+/// enum Mode {
+///     Off = 0,
+///     On = 1,
+///     #[parcel(unknown)]
+///     Unknown(i32),
+/// }
+/// ```
+#[proc_macro_derive(ParcelableEnum, attributes(parcel))]
+pub fn derive_parcelable_enum(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match parcelable_enum(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.into_compile_error().into(),
+    }
+}
+
+/// Derive `From<Error> for binder::Status` for an error type, typically one
+/// using `thiserror`, so that service methods can use `?` on it.
+///
+/// Each variant, or the whole type, says which status it becomes:
+///
+/// * `#[binder_status(code = EXPR)]` is a service-specific error with the
+///   `i32` code `EXPR`, e.g. a constant from an AIDL enum.
+/// * `#[binder_status(exception = NAME)]` is the exception
+///   `binder::ExceptionCode::NAME`, e.g. `ILLEGAL_ARGUMENT`.
+/// * `#[binder_status(transparent)]`, only on a variant with a single field,
+///   converts that field with `binder::Status::from`, e.g. to pass on a
+///   `binder::Status` from another service.
+///
+/// The message of the status is the error formatted for display, and the
+/// error is kept as its [`source`](std::error::Error::source). An attribute on
+/// the type applies to variants without their own.
+///
+/// ```ignore
+/// #[derive(Debug, thiserror::Error, BinderStatus)]
+/// #[binder_status(exception = ILLEGAL_STATE)]
+/// enum StoreError {
+///     #[error("no key named {0}")]
+///     #[binder_status(code = ErrorCode::NOT_FOUND.0)]
+///     NotFound(String),
+///     #[error("invalid key {0:?}")]
+///     #[binder_status(exception = ILLEGAL_ARGUMENT)]
+///     InvalidKey(String),
+///     #[error(transparent)]
+///     #[binder_status(transparent)]
+///     Binder(#[from] binder::Status),
+///     #[error("database error")]
+///     Database(#[from] rusqlite::Error),
+/// }
+/// ```
+///
+/// The type must implement `std::error::Error`, `Send` and `Sync`, and must
+/// not be generic.
+#[proc_macro_derive(BinderStatus, attributes(binder_status))]
+pub fn derive_binder_status(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match binder_status(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.into_compile_error().into(),
+    }
+}
+
+/// The status an error becomes, from a `#[binder_status]` attribute.
+enum StatusKind {
+    ServiceSpecific(Expr),
+    Exception(Ident),
+    Transparent,
+}
+
+fn binder_status(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "#[derive(BinderStatus)] can't be used on generic types",
+        ));
+    }
+    let name = &input.ident;
+    let default = status_kind(&input.attrs)?;
+    if matches!(default, Some(StatusKind::Transparent)) {
+        return Err(Error::new_spanned(
+            name,
+            "#[binder_status(transparent)] can only be used on enum variants",
+        ));
+    }
+
+    let body = match &input.data {
+        Data::Enum(data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let kind = status_kind(&variant.attrs)?;
+                    match kind.as_ref().or(default.as_ref()) {
+                        Some(StatusKind::Transparent) => {
+                            let mut fields = variant.fields.members();
+                            let (Some(field), None) = (fields.next(), fields.next()) else {
+                                return Err(Error::new_spanned(
+                                    ident,
+                                    "#[binder_status(transparent)] needs a variant with a \
+                                     single field",
+                                ));
+                            };
+                            Ok(quote!(Self::#ident { #field: inner } => {
+                                ::binder::Status::from(inner)
+                            }))
+                        }
+                        Some(kind) => {
+                            let status = new_status(kind);
+                            Ok(quote!(error @ Self::#ident { .. } => #status))
+                        }
+                        None => Err(Error::new_spanned(
+                            ident,
+                            "missing #[binder_status(code = ...)] or \
+                             #[binder_status(exception = ...)] on the variant or the enum",
+                        )),
+                    }
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote!(match error { #(#arms)* })
+        }
+        Data::Struct(_) => match &default {
+            Some(kind) => new_status(kind),
+            None => {
+                return Err(Error::new_spanned(
+                    name,
+                    "missing #[binder_status(code = ...)] or #[binder_status(exception = ...)]",
+                ))
+            }
+        },
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                name,
+                "#[derive(BinderStatus)] can only be used on enums and structs",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl ::std::convert::From<#name> for ::binder::Status {
+            fn from(error: #name) -> Self {
+                #body
+            }
+        }
+    })
+}
+
+fn new_status(kind: &StatusKind) -> proc_macro2::TokenStream {
+    match kind {
+        StatusKind::ServiceSpecific(code) => quote! {
+            ::binder::Status::new_service_specific_error_with_source(
+                #code,
+                ::std::option::Option::Some(::std::string::ToString::to_string(&error)),
+                error,
+            )
+        },
+        StatusKind::Exception(exception) => quote! {
+            ::binder::Status::new_exception_with_source(
+                ::binder::ExceptionCode::#exception,
+                ::std::option::Option::Some(::std::string::ToString::to_string(&error)),
+                error,
+            )
+        },
+        StatusKind::Transparent => unreachable!("transparent is handled per variant"),
+    }
+}
+
+fn status_kind(attrs: &[Attribute]) -> syn::Result<Option<StatusKind>> {
+    let mut kind = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("binder_status")) {
+        attr.parse_nested_meta(|meta| {
+            if kind.is_some() {
+                return Err(meta.error("only one #[binder_status] kind can be given"));
+            }
+            if meta.path.is_ident("code") {
+                kind = Some(StatusKind::ServiceSpecific(meta.value()?.parse()?));
+            } else if meta.path.is_ident("exception") {
+                kind = Some(StatusKind::Exception(meta.value()?.parse()?));
+            } else if meta.path.is_ident("transparent") {
+                kind = Some(StatusKind::Transparent);
+            } else {
+                return Err(meta.error("expected `code`, `exception` or `transparent`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(kind)
+}
+
+fn parcelable_enum(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "#[derive(ParcelableEnum)] can only be used on enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "#[derive(ParcelableEnum)] can't be used on generic enums",
+        ));
+    }
+    let name = &input.ident;
+    let backing = enum_repr(input)?;
+
+    let mut to_backing = Vec::new();
+    let mut from_backing = Vec::new();
+    let mut unknown = None;
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        if is_unknown_variant(variant)? {
+            if unknown.is_some() {
+                return Err(Error::new_spanned(
+                    ident,
+                    "only one variant can be #[parcel(unknown)]",
+                ));
+            }
+            if !matches!(&variant.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1) {
+                return Err(Error::new_spanned(
+                    ident,
+                    format!("the #[parcel(unknown)] variant must hold just an {backing}"),
+                ));
+            }
+            to_backing.push(quote!(#name::#ident(value) => *value));
+            unknown = Some(ident);
+            continue;
+        }
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                ident,
+                "variants of a #[derive(ParcelableEnum)] enum can't have fields, except for the \
+                 #[parcel(unknown)] one",
+            ));
+        }
+        let Some((_, discriminant)) = &variant.discriminant else {
+            return Err(Error::new_spanned(ident, "expected an explicit discriminant"));
+        };
+        to_backing.push(quote!(#name::#ident => #discriminant));
+        from_backing.push(quote!(value if value == (#discriminant) => Ok(Self::#ident)));
+    }
+    let fallback = match unknown {
+        Some(ident) => quote!(value => Ok(Self::#ident(value))),
+        None => quote!(_ => Err(::binder::StatusCode::BAD_VALUE)),
+    };
+
+    Ok(quote! {
+        impl ::std::convert::From<&#name> for #backing {
+            fn from(value: &#name) -> Self {
+                match value {
+                    #(#to_backing,)*
+                }
+            }
+        }
+
+        impl ::std::convert::From<#name> for #backing {
+            fn from(value: #name) -> Self {
+                Self::from(&value)
+            }
+        }
+
+        impl ::std::convert::TryFrom<#backing> for #name {
+            type Error = ::binder::StatusCode;
+
+            fn try_from(value: #backing) -> ::std::result::Result<Self, ::binder::StatusCode> {
+                match value {
+                    #(#from_backing,)*
+                    #fallback,
+                }
+            }
+        }
+
+        impl ::binder::binder_impl::Serialize for #name {
+            fn serialize(
+                &self,
+                parcel: &mut ::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                parcel.write(&#backing::from(self))
+            }
+        }
+
+        impl ::binder::binder_impl::SerializeArray for #name {
+            fn serialize_array(
+                slice: &[Self],
+                parcel: &mut ::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                let v: ::std::vec::Vec<#backing> = slice.iter().map(#backing::from).collect();
+                <#backing as ::binder::binder_impl::SerializeArray>::serialize_array(&v[..], parcel)
+            }
+
+            fn serialize_ref_array(
+                slice: &[&Self],
+                parcel: &mut ::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                let v: ::std::vec::Vec<#backing> =
+                    slice.iter().map(|x| #backing::from(*x)).collect();
+                <#backing as ::binder::binder_impl::SerializeArray>::serialize_array(&v[..], parcel)
+            }
+
+            fn serialize_iter<I: ::std::iter::Iterator<Item = Self>>(
+                values: I,
+                parcel: &mut ::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<(), ::binder::StatusCode> {
+                <#backing as ::binder::binder_impl::SerializeArray>::serialize_iter(
+                    values.map(#backing::from),
+                    parcel,
+                )
+            }
+        }
+
+        impl ::binder::binder_impl::Deserialize for #name {
+            type UninitType = ::std::option::Option<Self>;
+            fn uninit() -> Self::UninitType {
+                None
+            }
+            fn from_init(value: Self) -> Self::UninitType {
+                Some(value)
+            }
+
+            fn deserialize(
+                parcel: &::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<Self, ::binder::StatusCode> {
+                <Self as ::std::convert::TryFrom<#backing>>::try_from(parcel.read()?)
+            }
+        }
+
+        impl ::binder::binder_impl::DeserializeArray for #name {
+            fn deserialize_array(
+                parcel: &::binder::binder_impl::BorrowedParcel<'_>,
+            ) -> ::std::result::Result<
+                ::std::option::Option<::std::vec::Vec<Self>>,
+                ::binder::StatusCode,
+            > {
+                let v: ::std::option::Option<::std::vec::Vec<#backing>> =
+                    <#backing as ::binder::binder_impl::DeserializeArray>::deserialize_array(
+                        parcel,
+                    )?;
+                v.map(|v| {
+                    v.into_iter()
+                        .map(<Self as ::std::convert::TryFrom<#backing>>::try_from)
+                        .collect::<::std::result::Result<_, _>>()
+                })
+                .transpose()
+            }
+        }
+    })
+}
+
+/// Returns the backing type from the `#[repr]` of an enum.
+fn enum_repr(input: &DeriveInput) -> syn::Result<Ident> {
+    let mut repr = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if ["i8", "i32", "i64"].iter().any(|backing| meta.path.is_ident(backing)) {
+                repr = meta.path.get_ident().cloned();
+            }
+            Ok(())
+        })?;
+    }
+    repr.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "#[derive(ParcelableEnum)] needs #[repr(i8)], #[repr(i32)] or #[repr(i64)]",
+        )
+    })
+}
+
+fn is_unknown_variant(variant: &Variant) -> syn::Result<bool> {
+    let mut unknown = false;
+    for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("parcel")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("unknown") {
+                unknown = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected #[parcel(unknown)]"))
+            }
+        })?;
+    }
+    Ok(unknown)
+}
@@ -107,6 +107,28 @@ pub async fn wait_for_interface<T: FromIBinder + ?Sized + 'static>(
     }
 }
 
+/// Wait until the binder driver and the service manager are usable, or until
+/// `timeout` has elapsed.
+///
+/// See [`binder::wait_for_service_manager`].
+pub async fn wait_for_service_manager(
+    timeout: std::time::Duration,
+) -> Result<(), binder::ServiceManagerUnavailable> {
+    let start = std::time::Instant::now();
+    let res = tokio::task::spawn_blocking(move || binder::wait_for_service_manager(timeout)).await;
+
+    // The `is_panic` branch is not actually reachable in Android as we compile
+    // with `panic = abort`.
+    match res {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // The runtime is shutting down, so the wait was cancelled. Report why
+        // binder is unavailable at this point, without waiting any longer.
+        Err(_) => binder::wait_for_service_manager(std::time::Duration::ZERO)
+            .map_err(|e| binder::ServiceManagerUnavailable { waited: start.elapsed(), ..e }),
+    }
+}
+
 /// Use the Tokio `spawn_blocking` pool with AIDL.
 pub enum Tokio {}
 
@@ -0,0 +1,95 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#pragma once
+
+#include <android/binder_ibinder.h>
+#include <android/binder_parcel.h>
+#include <android/binder_status.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <sys/socket.h>
+
+__BEGIN_DECLS
+
+// C ABI over the Rust binder crate (libbinder_rs), for other languages
+// sharing a process with Rust binder code. AIBinder and AParcel are the
+// libbinder_ndk types and can be mixed freely with libbinder_ndk calls.
+//
+// Creating accessors is only available to the system partition, where
+// libbinder_rpc_unstable is. Accessors registered in the process are used by
+// the service lookups below either way.
+
+// Registers `binder` under `name`. Does not take ownership of `binder`.
+binder_status_t ABinderFfi_addService(AIBinder* binder, const char* name);
+
+// Registers `binder` as a lazy service under `name`. Does not take ownership
+// of `binder`.
+binder_status_t ABinderFfi_registerLazyService(AIBinder* binder, const char* name);
+
+// Returns the service registered under `name`, or null if it is not
+// available. The caller owns the returned reference.
+AIBinder* ABinderFfi_checkService(const char* name);
+
+// Returns the service registered under `name`, starting it if it is lazy, or
+// null if it cannot be found. The caller owns the returned reference.
+AIBinder* ABinderFfi_waitForService(const char* name);
+
+// Writes whether `name` is declared (e.g. in a VINTF manifest) to `out`.
+binder_status_t ABinderFfi_isDeclared(const char* name, bool* out);
+
+// Sends a transaction to `binder` on the interface `descriptor`, first
+// associating `binder` with it as AIBinder_associateClass does. Fails with
+// STATUS_BAD_TYPE if `binder` is for a different interface. The interface
+// token is written before the contents of `data`, which are copied, so `data`
+// holds only the arguments. On success, `*reply` is set to a new parcel owned
+// by the caller.
+binder_status_t ABinderFfi_transact(AIBinder* binder, const char* descriptor, uint32_t code,
+                                    const AParcel* data, AParcel** reply, uint32_t flags);
+
+#if !defined(__ANDROID_VENDOR__) && !defined(__ANDROID_PRODUCT__)
+
+typedef struct ABinderFfi_Accessor ABinderFfi_Accessor;
+
+// Called with the instance name each time a client connects, to write the
+// address of the service to `addr`, which has room for `*len` bytes, and its
+// length to `*len`. Returns false if the service is unavailable. vsock, unix
+// domain and IP addresses are supported.
+typedef bool (*ABinderFfi_AccessorCallback)(const char* instance, void* cookie,
+                                            struct sockaddr* addr, socklen_t* len);
+
+// Called once an accessor's callback is no longer used, to release `cookie`.
+typedef void (*ABinderFfi_AccessorCookieDelete)(void* cookie);
+
+// Creates an accessor for the RPC service `instance`, which gives clients the
+// address `callback` returns. `callback` may be called from several threads at
+// once. `onDelete`, if not null, is called with `cookie` once `callback` is no
+// longer used, which may be after the accessor is deleted. Returns null on
+// failure, after calling `onDelete`.
+ABinderFfi_Accessor* ABinderFfi_Accessor_new(const char* instance,
+                                             ABinderFfi_AccessorCallback callback, void* cookie,
+                                             ABinderFfi_AccessorCookieDelete onDelete);
+
+// Returns the binder of `accessor`, to register with ABinderFfi_addService
+// under its instance name. The caller owns the returned reference.
+AIBinder* ABinderFfi_Accessor_asBinder(const ABinderFfi_Accessor* accessor);
+
+// Deletes `accessor`. Its binder keeps working for as long as it is in use.
+void ABinderFfi_Accessor_delete(ABinderFfi_Accessor* accessor);
+
+#endif  // !defined(__ANDROID_VENDOR__) && !defined(__ANDROID_PRODUCT__)
+
+__END_DECLS
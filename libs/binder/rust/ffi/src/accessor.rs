@@ -0,0 +1,201 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Accessors for RPC binder services, over `rpcbinder::Accessor`.
+//!
+//! The accessor API is in `libbinder_rpc_unstable`, which is only available to
+//! the system partition, so this is left out of vendor and product builds.
+
+use crate::{binder_into_raw, name_from_ptr};
+use binder::unstable_api::AIBinder;
+use rpcbinder::{Accessor, ConnectionInfo, UnixAddress, VsockAddress};
+use std::ffi::{c_char, c_void, CString};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// Called with the instance name each time a client connects, to write the
+/// address of the service to `addr`, which has room for `*len` bytes, and its
+/// length to `*len`. Returns false if the service is unavailable.
+#[allow(non_camel_case_types)]
+pub type ABinderFfi_AccessorCallback = unsafe extern "C" fn(
+    instance: *const c_char,
+    cookie: *mut c_void,
+    addr: *mut libc::sockaddr,
+    len: *mut libc::socklen_t,
+) -> bool;
+
+/// Called once the callback is no longer used, to release the cookie.
+#[allow(non_camel_case_types)]
+pub type ABinderFfi_AccessorCookieDelete = unsafe extern "C" fn(cookie: *mut c_void);
+
+/// An accessor created by [`ABinderFfi_Accessor_new`].
+#[allow(non_camel_case_types)]
+pub struct ABinderFfi_Accessor(Accessor);
+
+/// The C callback of an accessor and its cookie.
+struct Callback {
+    callback: ABinderFfi_AccessorCallback,
+    cookie: *mut c_void,
+    on_delete: Option<ABinderFfi_AccessorCookieDelete>,
+}
+
+// Safety: The caller of `ABinderFfi_Accessor_new` guarantees that the callback
+// and its cookie can be used from any thread.
+unsafe impl Send for Callback {}
+
+// Safety: The caller of `ABinderFfi_Accessor_new` guarantees that the callback
+// can be called from several threads at once.
+unsafe impl Sync for Callback {}
+
+impl Callback {
+    fn connection_info(&self, instance: &str) -> Option<ConnectionInfo> {
+        let instance = CString::new(instance).ok()?;
+        // Safety: All zeroes is a valid `sockaddr_storage`.
+        let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of_val(&storage) as libc::socklen_t;
+        // Safety: `instance` is a valid C string, and `storage` has room for
+        // `len` bytes. The caller of `ABinderFfi_Accessor_new` guarantees that
+        // the callback is valid with its cookie.
+        let available = unsafe {
+            (self.callback)(
+                instance.as_ptr(),
+                self.cookie,
+                (&mut storage as *mut libc::sockaddr_storage).cast(),
+                &mut len,
+            )
+        };
+        if !available || len as usize > std::mem::size_of_val(&storage) {
+            return None;
+        }
+        // Safety: `sockaddr_storage` is large enough and aligned for every
+        // kind of socket address, and the family says which one it holds.
+        unsafe { connection_info_from_storage(&storage, len) }
+    }
+}
+
+impl Drop for Callback {
+    fn drop(&mut self) {
+        if let Some(on_delete) = self.on_delete {
+            // Safety: The callback is no longer used, so its cookie can be
+            // released as the caller of `ABinderFfi_Accessor_new` asked.
+            unsafe { on_delete(self.cookie) };
+        }
+    }
+}
+
+/// # Safety
+///
+/// `storage` must hold a socket address of `len` bytes of the type its family
+/// says.
+unsafe fn connection_info_from_storage(
+    storage: &libc::sockaddr_storage,
+    len: libc::socklen_t,
+) -> Option<ConnectionInfo> {
+    let ptr = storage as *const libc::sockaddr_storage;
+    let fits = |size: usize| len as usize >= size;
+    match i32::from(storage.ss_family) {
+        libc::AF_VSOCK if fits(std::mem::size_of::<libc::sockaddr_vm>()) => {
+            // Safety: Our caller guarantees that this is a `sockaddr_vm`.
+            let addr = unsafe { *ptr.cast::<libc::sockaddr_vm>() };
+            Some(ConnectionInfo::Vsock(VsockAddress::from(addr)))
+        }
+        libc::AF_UNIX => {
+            // Safety: Our caller guarantees that this is a `sockaddr_un`.
+            let addr = unsafe { &*ptr.cast::<libc::sockaddr_un>() };
+            UnixAddress::from_sockaddr(addr, len).ok().map(ConnectionInfo::Unix)
+        }
+        libc::AF_INET if fits(std::mem::size_of::<libc::sockaddr_in>()) => {
+            // Safety: Our caller guarantees that this is a `sockaddr_in`.
+            let addr = unsafe { *ptr.cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            let addr = SocketAddrV4::new(ip, u16::from_be(addr.sin_port));
+            Some(ConnectionInfo::Inet(SocketAddr::V4(addr)))
+        }
+        libc::AF_INET6 if fits(std::mem::size_of::<libc::sockaddr_in6>()) => {
+            // Safety: Our caller guarantees that this is a `sockaddr_in6`.
+            let addr = unsafe { *ptr.cast::<libc::sockaddr_in6>() };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            let port = u16::from_be(addr.sin6_port);
+            let addr = SocketAddrV6::new(ip, port, addr.sin6_flowinfo, addr.sin6_scope_id);
+            Some(ConnectionInfo::Inet(SocketAddr::V6(addr)))
+        }
+        _ => None,
+    }
+}
+
+/// Creates an accessor for the RPC service `instance`, which gives clients the
+/// address `callback` returns. The accessor's binder, from
+/// [`ABinderFfi_Accessor_asBinder`], can be registered with
+/// [`ABinderFfi_addService`](crate::ABinderFfi_addService) under `instance`.
+///
+/// `on_delete`, if not null, is called with `cookie` once `callback` is no
+/// longer used, which may be after the accessor is deleted if its binder is
+/// still in use. Returns null if `instance` is invalid or the accessor can't
+/// be created, in which case `on_delete` has already been called.
+///
+/// # Safety
+///
+/// `instance` must be a valid nul-terminated C string, which need only be
+/// valid for the duration of the call. `callback` must be safe to call with
+/// `cookie` from any thread, including from several at once, until
+/// `on_delete` is called.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_Accessor_new(
+    instance: *const c_char,
+    callback: ABinderFfi_AccessorCallback,
+    cookie: *mut c_void,
+    on_delete: Option<ABinderFfi_AccessorCookieDelete>,
+) -> *mut ABinderFfi_Accessor {
+    let callback = Callback { callback, cookie, on_delete };
+    // Safety: Guaranteed by our caller.
+    let Ok(instance) = (unsafe { name_from_ptr(instance) }) else {
+        return std::ptr::null_mut();
+    };
+    match Accessor::new(instance, move |instance| callback.connection_info(instance)) {
+        Ok(accessor) => Box::into_raw(Box::new(ABinderFfi_Accessor(accessor))),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Returns a new strong reference to the binder of `accessor`, or null if it
+/// can't be created. The caller owns the returned reference.
+///
+/// # Safety
+///
+/// `accessor` must be a valid pointer from [`ABinderFfi_Accessor_new`].
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_Accessor_asBinder(
+    accessor: *const ABinderFfi_Accessor,
+) -> *mut AIBinder {
+    // Safety: Guaranteed by our caller.
+    match unsafe { accessor.as_ref() } {
+        Some(accessor) => binder_into_raw(accessor.0.as_binder()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Deletes `accessor`. Its binder keeps working for as long as it is in use.
+///
+/// # Safety
+///
+/// `accessor` must be null or a valid pointer from [`ABinderFfi_Accessor_new`],
+/// which is not used again.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_Accessor_delete(accessor: *mut ABinderFfi_Accessor) {
+    if !accessor.is_null() {
+        // Safety: Guaranteed by our caller.
+        drop(unsafe { Box::from_raw(accessor) });
+    }
+}
@@ -0,0 +1,381 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Stable C ABI over the Rust binder crate.
+//!
+//! This lets code written in other languages within the same build (e.g. Go
+//! or Kotlin/Native) share service registration and transaction handling
+//! with Rust code in the same process, rather than each going directly to
+//! `libbinder_ndk`. The declarations are in `include/binder_ffi.h`.
+//!
+//! All `AIBinder` and `AParcel` handles are the NDK types, so they can be
+//! passed freely between this library and `libbinder_ndk`.
+//!
+//! Creating accessors is only available to the system partition, where
+//! `libbinder_rpc_unstable` is, so it is left out of vendor and product builds.
+//! Lookups through this library go through any accessors registered in the
+//! process either way.
+
+#[cfg(not(android_vndk))]
+mod accessor;
+
+#[cfg(not(android_vndk))]
+pub use accessor::{
+    ABinderFfi_Accessor, ABinderFfi_AccessorCallback, ABinderFfi_AccessorCookieDelete,
+    ABinderFfi_Accessor_asBinder, ABinderFfi_Accessor_delete, ABinderFfi_Accessor_new,
+};
+
+use binder::binder_impl::{BorrowedParcel, IBinderInternal, Parcel, TransactionCode};
+use binder::unstable_api::{new_spibinder, AIBinder, AParcel, AsNative};
+use binder::{SpIBinder, StatusCode};
+use binder_ndk_sys as sys;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::mem::ManuallyDrop;
+use std::sync::Mutex;
+
+/// Raw status value returned across the C ABI; same as `binder_status_t`.
+#[allow(non_camel_case_types)]
+pub type binder_status_t = i32;
+
+fn to_status(result: Result<(), StatusCode>) -> binder_status_t {
+    match result {
+        Ok(()) => StatusCode::OK as binder_status_t,
+        Err(e) => e as binder_status_t,
+    }
+}
+
+/// # Safety
+///
+/// `name` must be null or a valid nul-terminated C string that outlives `'a`.
+unsafe fn name_from_ptr<'a>(name: *const c_char) -> Result<&'a str, StatusCode> {
+    if name.is_null() {
+        return Err(StatusCode::UNEXPECTED_NULL);
+    }
+    // Safety: Our caller guarantees that a non-null `name` is a valid C
+    // string, and we checked for null above.
+    unsafe { CStr::from_ptr(name) }.to_str().map_err(|_| StatusCode::BAD_VALUE)
+}
+
+/// Takes a new strong reference to a binder that the caller keeps ownership of.
+///
+/// # Safety
+///
+/// `binder` must be null or a valid pointer to an `AIBinder`.
+unsafe fn borrow_binder(binder: *mut AIBinder) -> Option<SpIBinder> {
+    // Safety: Our caller guarantees `binder` is null or valid. The caller's
+    // reference is not ours to release, so the temporary `SpIBinder` is never
+    // dropped; cloning it takes a new strong reference for us.
+    let borrowed = ManuallyDrop::new(unsafe { new_spibinder(binder) }?);
+    Some(SpIBinder::clone(&borrowed))
+}
+
+/// Transfers ownership of a strong reference to the C caller.
+fn binder_into_raw(binder: Option<SpIBinder>) -> *mut AIBinder {
+    match binder {
+        Some(binder) => {
+            let mut binder = ManuallyDrop::new(binder);
+            binder.as_native_mut()
+        }
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Registers `binder` with the default service manager under `name`.
+///
+/// The caller keeps its reference to `binder`.
+///
+/// # Safety
+///
+/// `binder` must be a valid `AIBinder` pointer and `name` a valid
+/// nul-terminated C string. Both need only be valid for the duration of the
+/// call.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_addService(
+    binder: *mut AIBinder,
+    name: *const c_char,
+) -> binder_status_t {
+    // Safety: Guaranteed by our caller.
+    let result = unsafe { name_from_ptr(name) }.and_then(|name| {
+        // Safety: Guaranteed by our caller.
+        let binder = unsafe { borrow_binder(binder) }.ok_or(StatusCode::UNEXPECTED_NULL)?;
+        binder::add_service(name, binder)
+    });
+    to_status(result)
+}
+
+/// Registers `binder` as a lazy service under `name`.
+///
+/// The caller keeps its reference to `binder`.
+///
+/// # Safety
+///
+/// Same requirements as [`ABinderFfi_addService`].
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_registerLazyService(
+    binder: *mut AIBinder,
+    name: *const c_char,
+) -> binder_status_t {
+    // Safety: Guaranteed by our caller.
+    let result = unsafe { name_from_ptr(name) }.and_then(|name| {
+        // Safety: Guaranteed by our caller.
+        let binder = unsafe { borrow_binder(binder) }.ok_or(StatusCode::UNEXPECTED_NULL)?;
+        binder::register_lazy_service(name, binder)
+    });
+    to_status(result)
+}
+
+/// Returns a strong reference to the service registered under `name`, or null
+/// if it is not currently available.
+///
+/// The caller owns the returned reference and must release it with
+/// `AIBinder_decStrong`.
+///
+/// # Safety
+///
+/// `name` must be a valid nul-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_checkService(name: *const c_char) -> *mut AIBinder {
+    // Safety: Guaranteed by our caller.
+    match unsafe { name_from_ptr(name) } {
+        Ok(name) => binder_into_raw(binder::check_service(name)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Returns a strong reference to the service registered under `name`, starting
+/// it if it is a lazy service. Returns null if the service cannot be found.
+///
+/// The caller owns the returned reference and must release it with
+/// `AIBinder_decStrong`.
+///
+/// # Safety
+///
+/// `name` must be a valid nul-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_waitForService(name: *const c_char) -> *mut AIBinder {
+    // Safety: Guaranteed by our caller.
+    match unsafe { name_from_ptr(name) } {
+        Ok(name) => binder_into_raw(binder::wait_for_service(name)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Writes whether `name` is declared (e.g. in a VINTF manifest) to `out`.
+///
+/// # Safety
+///
+/// `name` must be a valid nul-terminated C string and `out` a valid pointer to
+/// a `bool`.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_isDeclared(
+    name: *const c_char,
+    out: *mut bool,
+) -> binder_status_t {
+    if out.is_null() {
+        return StatusCode::UNEXPECTED_NULL as binder_status_t;
+    }
+    // Safety: Guaranteed by our caller.
+    let result = unsafe { name_from_ptr(name) }.and_then(binder::is_declared).map(|declared| {
+        // Safety: We checked `out` for null above, and our caller guarantees
+        // it is otherwise valid.
+        unsafe { *out = declared };
+    });
+    to_status(result)
+}
+
+/// Classes for the interfaces transactions have been sent on, by descriptor.
+/// Classes are never deleted, so they are stored as addresses.
+static CLASSES: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+unsafe extern "C" fn on_create(args: *mut c_void) -> *mut c_void {
+    args
+}
+
+unsafe extern "C" fn on_destroy(_object: *mut c_void) {}
+
+unsafe extern "C" fn on_transact(
+    _binder: *mut sys::AIBinder,
+    _code: u32,
+    _data: *const sys::AParcel,
+    _reply: *mut sys::AParcel,
+) -> binder_status_t {
+    // Binders are only associated with these classes to send transactions,
+    // so there are never local objects of them.
+    StatusCode::UNKNOWN_TRANSACTION as binder_status_t
+}
+
+/// Returns the class this library uses for the interface `descriptor`.
+fn class_for(descriptor: &str) -> Result<*const sys::AIBinder_Class, StatusCode> {
+    let mut classes = CLASSES.lock().unwrap();
+    if let Some(&class) = classes.get(descriptor) {
+        return Ok(class as *const sys::AIBinder_Class);
+    }
+    let c_descriptor = CString::new(descriptor).map_err(|_| StatusCode::BAD_VALUE)?;
+    // Safety: `c_descriptor` is a valid C string, which is copied, and the
+    // callbacks are valid functions.
+    let class = unsafe {
+        sys::AIBinder_Class_define(
+            c_descriptor.as_ptr(),
+            Some(on_create),
+            Some(on_destroy),
+            Some(on_transact),
+        )
+    };
+    if class.is_null() {
+        return Err(StatusCode::NO_MEMORY);
+    }
+    classes.insert(descriptor.to_owned(), class as usize);
+    Ok(class)
+}
+
+/// Associates `binder` with a class for the interface `descriptor`, unless it
+/// already has a class with that descriptor. Fails with `BAD_TYPE` if the
+/// binder implements a different interface.
+fn associate(binder: &mut SpIBinder, descriptor: &str) -> Result<(), StatusCode> {
+    if binder.get_class().is_some_and(|class| class.has_descriptor(descriptor)) {
+        return Ok(());
+    }
+    let class = class_for(descriptor)?;
+    // Safety: `SpIBinder` always contains a valid `AIBinder` pointer, and
+    // `class` was returned by `AIBinder_Class_define`.
+    if unsafe { sys::AIBinder_associateClass(binder.as_native_mut(), class) } {
+        Ok(())
+    } else {
+        Err(StatusCode::BAD_TYPE)
+    }
+}
+
+/// Sends a transaction to `binder` on the interface `descriptor`.
+///
+/// Binders from the lookups above aren't associated with any interface yet, so
+/// `binder` is first associated with `descriptor`, as with
+/// `AIBinder_associateClass`. This fails with `BAD_TYPE` if `binder` is for a
+/// different interface.
+///
+/// As for any NDK transaction, the interface token of the interface is written
+/// at the start of the transaction, so `data` holds only the arguments. Its
+/// contents are copied into the outgoing transaction; the caller keeps
+/// ownership of `data`. On success, `*reply` is set to a new parcel owned by
+/// the caller, which must be released with `AParcel_delete`.
+///
+/// # Safety
+///
+/// `binder` must be a valid `AIBinder` pointer, `descriptor` a valid
+/// nul-terminated C string, `data` a valid `AParcel` pointer and `reply` a
+/// valid pointer to writable storage for an `AParcel*`.
+#[no_mangle]
+pub unsafe extern "C" fn ABinderFfi_transact(
+    binder: *mut AIBinder,
+    descriptor: *const c_char,
+    code: TransactionCode,
+    data: *const AParcel,
+    reply: *mut *mut AParcel,
+    flags: u32,
+) -> binder_status_t {
+    if reply.is_null() {
+        return StatusCode::UNEXPECTED_NULL as binder_status_t;
+    }
+    let result = (|| {
+        // Safety: Guaranteed by our caller.
+        let descriptor = unsafe { name_from_ptr(descriptor) }?;
+        // Safety: Guaranteed by our caller.
+        let mut binder = unsafe { borrow_binder(binder) }.ok_or(StatusCode::UNEXPECTED_NULL)?;
+        // Safety: Our caller guarantees `data` is valid for the duration of
+        // this call, and we only read from it.
+        let data = unsafe { BorrowedParcel::from_raw(data as *mut AParcel) }
+            .ok_or(StatusCode::UNEXPECTED_NULL)?;
+
+        associate(&mut binder, descriptor)?;
+        let mut parcel: Parcel = binder.prepare_transact()?;
+        parcel.append_all_from(&data)?;
+        let mut out = ManuallyDrop::new(binder.submit_transact(code, parcel, flags)?);
+        // Safety: We checked `reply` for null above, and our caller
+        // guarantees it is otherwise valid. Ownership of the reply parcel
+        // passes to the caller, so we never drop `out`.
+        unsafe { *reply = out.as_native_mut() };
+        Ok(())
+    })();
+    to_status(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binder::binder_impl::FIRST_CALL_TRANSACTION;
+    use binder::unstable_api::status_result;
+    use binder::Status;
+
+    const SERVICE_MANAGER_DESCRIPTOR: &str = "android.os.IServiceManager";
+    const DUMP_FLAG_PRIORITY_ALL: i32 = 15;
+    // The fifth method of IServiceManager.aidl.
+    const LIST_SERVICES: TransactionCode = FIRST_CALL_TRANSACTION + 4;
+
+    fn service_manager() -> *mut AIBinder {
+        let name = CString::new("manager").unwrap();
+        // SAFETY: `name` is a valid C string.
+        let binder = unsafe { ABinderFfi_checkService(name.as_ptr()) };
+        assert!(!binder.is_null());
+        binder
+    }
+
+    fn list_services(binder: *mut AIBinder, descriptor: &str) -> Result<Vec<String>, StatusCode> {
+        let descriptor = CString::new(descriptor).unwrap();
+        let mut data = Parcel::new();
+        data.write(&DUMP_FLAG_PRIORITY_ALL).unwrap();
+        let mut reply = std::ptr::null_mut();
+        // SAFETY: `binder` is a valid binder, `descriptor` a valid C string,
+        // `data` a valid parcel and `reply` valid to write the reply to.
+        let status = unsafe {
+            ABinderFfi_transact(
+                binder,
+                descriptor.as_ptr(),
+                LIST_SERVICES,
+                data.as_native(),
+                &mut reply,
+                0,
+            )
+        };
+        status_result(status)?;
+        // SAFETY: On success, `reply` is a new parcel which we own.
+        let reply = unsafe { Parcel::from_raw(reply) }.unwrap();
+        let status: Status = reply.read().unwrap();
+        assert!(status.is_ok(), "{status}");
+        Ok(reply.read().unwrap())
+    }
+
+    #[test]
+    fn transact_on_looked_up_service() {
+        let binder = service_manager();
+        let services = list_services(binder, SERVICE_MANAGER_DESCRIPTOR).unwrap();
+        assert!(services.iter().any(|name| name == "manager"), "{services:?}");
+        // Once associated, later transactions work the same way.
+        assert!(!list_services(binder, SERVICE_MANAGER_DESCRIPTOR).unwrap().is_empty());
+        // SAFETY: We own the reference from `ABinderFfi_checkService`.
+        drop(unsafe { new_spibinder(binder) });
+    }
+
+    #[test]
+    fn transact_with_wrong_descriptor() {
+        let binder = service_manager();
+        assert_eq!(
+            list_services(binder, "android.os.INotServiceManager"),
+            Err(StatusCode::BAD_TYPE)
+        );
+        // SAFETY: We own the reference from `ABinderFfi_checkService`.
+        drop(unsafe { new_spibinder(binder) });
+    }
+}
@@ -1 +1,2 @@
+#include <binder_rpc_accessor.hpp>
 #include <binder_rpc_unstable.hpp>
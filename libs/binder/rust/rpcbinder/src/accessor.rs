@@ -0,0 +1,1568 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Accessors, which hand out connections to RPC Binder services through the regular service
+//! lookups.
+
+#[cfg(feature = "test-accessor-stubs")]
+use crate::accessor_stubs as rpc;
+#[cfg(not(feature = "test-accessor-stubs"))]
+use binder_rpc_unstable_bindgen as rpc;
+
+use binder::unstable_api::{new_spibinder, status_result, AsNative};
+use binder::{SpIBinder, StatusCode};
+
+use libc::{sockaddr_in, sockaddr_in6, sockaddr_storage, sockaddr_un, sockaddr_vm, socklen_t};
+use nix::sys::socket::{UnixAddr, VsockAddr};
+use std::ffi::{c_char, c_void, CStr, CString, OsStr};
+use std::fmt;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::mem::{offset_of, size_of};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, StatusCode>;
+
+/// How to connect to an RPC binder service, which an [`Accessor`] gives out.
+#[derive(Debug)]
+pub enum ConnectionInfo {
+    /// A vsock address, for services in another VM or on the host.
+    Vsock(VsockAddress),
+    /// A unix domain socket address.
+    Unix(UnixAddress),
+    /// A TCP/IP address, e.g. for services on the host of an emulator or on a
+    /// test bench.
+    Inet(SocketAddr),
+    /// A socket which is already connected to the service, e.g. one end of a
+    /// socketpair from the process hosting it, which libbinder sets up the
+    /// session over instead of connecting to an address.
+    ///
+    /// Each connection needs a socket of its own, so an accessor callback
+    /// must give out a new one every time it is called.
+    PreconnectedFd(OwnedFd),
+}
+
+/// A vsock address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VsockAddress {
+    /// The context ID of the VM, or of the host.
+    pub cid: u32,
+    /// The port.
+    pub port: u32,
+}
+
+impl VsockAddress {
+    /// The address of `port` in the VM or host with context ID `cid`.
+    pub const fn new(cid: u32, port: u32) -> Self {
+        Self { cid, port }
+    }
+}
+
+impl From<sockaddr_vm> for VsockAddress {
+    fn from(addr: sockaddr_vm) -> Self {
+        Self::new(addr.svm_cid, addr.svm_port)
+    }
+}
+
+impl From<VsockAddress> for sockaddr_vm {
+    fn from(addr: VsockAddress) -> Self {
+        // SAFETY: All zeroes is a valid `sockaddr_vm`.
+        let mut vm: sockaddr_vm = unsafe { std::mem::zeroed() };
+        vm.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        vm.svm_cid = addr.cid;
+        vm.svm_port = addr.port;
+        vm
+    }
+}
+
+impl From<VsockAddr> for VsockAddress {
+    fn from(addr: VsockAddr) -> Self {
+        Self::new(addr.cid(), addr.port())
+    }
+}
+
+impl From<VsockAddress> for VsockAddr {
+    fn from(addr: VsockAddress) -> Self {
+        VsockAddr::new(addr.cid, addr.port)
+    }
+}
+
+/// The size of `sockaddr_un::sun_path`.
+const SUN_PATH_LEN: usize = size_of::<sockaddr_un>() - offset_of!(sockaddr_un, sun_path);
+
+/// A unix domain socket address, either a path in the file system or a name
+/// in the abstract namespace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnixAddress {
+    // The first `len` bytes of `sun_path`: a path without its terminating NUL,
+    // or a NUL followed by an abstract name.
+    bytes: [u8; SUN_PATH_LEN],
+    len: usize,
+}
+
+impl fmt::Debug for UnixAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.abstract_name() {
+            Some(name) => write!(f, "\"@{}\"", String::from_utf8_lossy(name).escape_debug()),
+            None => fmt::Debug::fmt(&String::from_utf8_lossy(&self.bytes[..self.len]), f),
+        }
+    }
+}
+
+impl UnixAddress {
+    /// The socket bound to `path` in the file system.
+    ///
+    /// Fails with `BAD_VALUE` if `path` is empty, contains a NUL byte or is
+    /// too long for a `sockaddr_un` with its terminating NUL.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().as_os_str().as_bytes();
+        if path.is_empty() || path.contains(&0) || path.len() >= SUN_PATH_LEN {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        Ok(Self::from_bytes(path))
+    }
+
+    /// The socket in the abstract namespace named `name`, given without the
+    /// leading NUL byte, e.g. `b"foo"` for the socket tools like `ss` list as
+    /// `@foo`. On-device RPC servers often use these.
+    ///
+    /// Abstract names aren't NUL terminated, and a socket only matches if its
+    /// name has the same length, so the address is given out with the exact
+    /// length of the name.
+    ///
+    /// Fails with `BAD_VALUE` if `name` is empty or too long for a
+    /// `sockaddr_un`.
+    pub fn new_abstract(name: &[u8]) -> Result<Self> {
+        if name.is_empty() || name.len() >= SUN_PATH_LEN {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        Ok(Self::from_bytes(&[&[0], name].concat()))
+    }
+
+    /// Convert a C socket address of `len` bytes, e.g. from `getsockname`.
+    ///
+    /// Fails with `BAD_VALUE` if `addr` is not a unix domain socket address,
+    /// `len` is out of range, or the address is unnamed.
+    pub fn from_sockaddr(addr: &sockaddr_un, len: socklen_t) -> Result<Self> {
+        let offset = offset_of!(sockaddr_un, sun_path);
+        let len = (len as usize).checked_sub(offset).ok_or(StatusCode::BAD_VALUE)?;
+        if i32::from(addr.sun_family) != libc::AF_UNIX || len == 0 || len > SUN_PATH_LEN {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        let bytes: Vec<u8> = addr.sun_path[..len].iter().map(|&c| c as u8).collect();
+        match bytes.split_first() {
+            Some((0, name)) => Self::new_abstract(name),
+            // Paths end at their terminating NUL, if it is included.
+            _ => Self::new(OsStr::from_bytes(bytes.split(|&c| c == 0).next().unwrap())),
+        }
+    }
+
+    fn from_bytes(path: &[u8]) -> Self {
+        let mut bytes = [0; SUN_PATH_LEN];
+        bytes[..path.len()].copy_from_slice(path);
+        Self { bytes, len: path.len() }
+    }
+
+    /// The path of the socket, or `None` if it is in the abstract namespace.
+    pub fn path(&self) -> Option<&Path> {
+        (self.bytes[0] != 0).then(|| Path::new(OsStr::from_bytes(&self.bytes[..self.len])))
+    }
+
+    /// The name of the socket in the abstract namespace, without the leading
+    /// NUL byte, or `None` if it has a path.
+    pub fn abstract_name(&self) -> Option<&[u8]> {
+        (self.bytes[0] == 0).then(|| &self.bytes[1..self.len])
+    }
+
+    /// The address as a `sockaddr_un`, and its size. Paths are sent with the
+    /// whole structure, and abstract names with their exact length.
+    pub fn to_sockaddr(&self) -> (sockaddr_un, socklen_t) {
+        // SAFETY: All zeroes is a valid `sockaddr_un`.
+        let mut addr: sockaddr_un = unsafe { std::mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, &src) in addr.sun_path.iter_mut().zip(&self.bytes[..self.len]) {
+            *dst = src as c_char;
+        }
+        let len = match self.abstract_name() {
+            Some(_) => offset_of!(sockaddr_un, sun_path) + self.len,
+            None => size_of::<sockaddr_un>(),
+        };
+        (addr, len as socklen_t)
+    }
+}
+
+impl TryFrom<&UnixAddr> for UnixAddress {
+    type Error = StatusCode;
+
+    /// Fails with `BAD_VALUE` if `addr` is unnamed.
+    fn try_from(addr: &UnixAddr) -> Result<Self> {
+        if let Some(name) = addr.as_abstract() {
+            Self::new_abstract(name)
+        } else if let Some(path) = addr.path() {
+            Self::new(path)
+        } else {
+            Err(StatusCode::BAD_VALUE)
+        }
+    }
+}
+
+impl From<UnixAddress> for UnixAddr {
+    fn from(addr: UnixAddress) -> Self {
+        // Both constructors only fail for names which are too long, and those
+        // were already rejected when `addr` was created.
+        match addr.abstract_name() {
+            Some(name) => UnixAddr::new_abstract(name),
+            None => UnixAddr::new(&addr.bytes[..addr.len]),
+        }
+        .expect("UnixAddress is always a valid sockaddr_un")
+    }
+}
+
+impl ConnectionInfo {
+    /// The unix domain socket bound to `path` in the file system. See
+    /// [`UnixAddress::new`].
+    pub fn unix(path: impl AsRef<Path>) -> Result<Self> {
+        UnixAddress::new(path).map(Self::Unix)
+    }
+
+    /// The unix domain socket in the abstract namespace named `name`. See
+    /// [`UnixAddress::new_abstract`].
+    pub fn unix_abstract(name: &[u8]) -> Result<Self> {
+        UnixAddress::new_abstract(name).map(Self::Unix)
+    }
+
+    /// The vsock address of `port` in the VM or host with context ID `cid`.
+    pub const fn vsock(cid: u32, port: u32) -> Self {
+        Self::Vsock(VsockAddress::new(cid, port))
+    }
+
+    /// Duplicate the connection info. A preconnected socket is duplicated
+    /// with `dup`, so both copies refer to the same connection.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Vsock(addr) => Self::Vsock(*addr),
+            Self::Unix(addr) => Self::Unix(*addr),
+            Self::Inet(addr) => Self::Inet(*addr),
+            Self::PreconnectedFd(fd) => {
+                Self::PreconnectedFd(fd.try_clone().map_err(|_| StatusCode::BAD_VALUE)?)
+            }
+        })
+    }
+
+    /// The address as a C socket address, and its size, or `None` for a
+    /// preconnected socket.
+    fn to_sockaddr(&self) -> Option<(sockaddr_storage, socklen_t)> {
+        // SAFETY: All zeroes is a valid `sockaddr_storage`.
+        let mut storage: sockaddr_storage = unsafe { std::mem::zeroed() };
+        let ptr: *mut sockaddr_storage = &mut storage;
+        let len = match self {
+            Self::Vsock(addr) => {
+                // SAFETY: A `sockaddr_storage` is big enough and aligned for
+                // any socket address.
+                unsafe { ptr.cast::<sockaddr_vm>().write((*addr).into()) };
+                size_of::<sockaddr_vm>()
+            }
+            Self::Unix(addr) => {
+                let (sun, len) = addr.to_sockaddr();
+                // SAFETY: As above.
+                unsafe { ptr.cast::<sockaddr_un>().write(sun) };
+                len as usize
+            }
+            Self::Inet(SocketAddr::V4(addr)) => {
+                // SAFETY: All zeroes is a valid `sockaddr_in`.
+                let mut sin: sockaddr_in = unsafe { std::mem::zeroed() };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                // SAFETY: As above.
+                unsafe { ptr.cast::<sockaddr_in>().write(sin) };
+                size_of::<sockaddr_in>()
+            }
+            Self::Inet(SocketAddr::V6(addr)) => {
+                // SAFETY: All zeroes is a valid `sockaddr_in6`.
+                let mut sin6: sockaddr_in6 = unsafe { std::mem::zeroed() };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                // SAFETY: As above.
+                unsafe { ptr.cast::<sockaddr_in6>().write(sin6) };
+                size_of::<sockaddr_in6>()
+            }
+            Self::PreconnectedFd(_) => return None,
+        };
+        Some((storage, len as socklen_t))
+    }
+
+    /// Move the connection info into a new C connection info object, which
+    /// the caller owns.
+    fn to_raw(self) -> *mut rpc::ABinderRpc_ConnectionInfo {
+        if let Self::PreconnectedFd(fd) = self {
+            // SAFETY: `ABinderRpc_ConnectionInfo_newPreconnected` takes
+            // ownership of the socket, which we give up here.
+            return unsafe { rpc::ABinderRpc_ConnectionInfo_newPreconnected(fd.into_raw_fd()) };
+        }
+        let (addr, len) = self.to_sockaddr().expect("Addresses have a socket address");
+        let addr: *const sockaddr_storage = &addr;
+        // SAFETY: `addr` points to a socket address of `len` bytes, which
+        // `ABinderRpc_ConnectionInfo_new` copies rather than keeping.
+        unsafe { rpc::ABinderRpc_ConnectionInfo_new(addr.cast(), len) }
+    }
+
+    /// Whether a stream socket can currently connect to the address. The
+    /// connection is closed straight away, which RPC binder servers treat as
+    /// a client which went away before setting up a session.
+    ///
+    /// A preconnected socket can be connected to as long as the other end
+    /// hasn't hung up.
+    pub fn can_connect(&self) -> bool {
+        let family = match self {
+            Self::Vsock(_) => libc::AF_VSOCK,
+            Self::Unix(_) => libc::AF_UNIX,
+            Self::Inet(SocketAddr::V4(_)) => libc::AF_INET,
+            Self::Inet(SocketAddr::V6(_)) => libc::AF_INET6,
+            Self::PreconnectedFd(fd) => return is_connected(fd),
+        };
+        // SAFETY: `socket` has no memory safety requirements.
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return false;
+        }
+        // SAFETY: `fd` is a new socket which nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let Some((addr, len)) = self.to_sockaddr() else { return false };
+        let addr: *const sockaddr_storage = &addr;
+        // SAFETY: `addr` points to a socket address of `len` bytes, which
+        // outlives the call.
+        unsafe { libc::connect(fd.as_raw_fd(), addr.cast(), len) == 0 }
+    }
+}
+
+/// Whether the peer of a connected socket is still there.
+fn is_connected(fd: &OwnedFd) -> bool {
+    // With no events requested, the socket is only ready if it has hung up,
+    // failed or been closed.
+    let mut pollfd = libc::pollfd { fd: fd.as_raw_fd(), events: 0, revents: 0 };
+    // SAFETY: `pollfd` is a single valid `pollfd`, which outlives the call.
+    unsafe { libc::poll(&mut pollfd, 1, 0) == 0 }
+}
+
+/// A set of candidate endpoints for one instance, with failover between them.
+///
+/// Each time a client connects, the endpoints are health checked in order of
+/// weight, highest first, and the first healthy one is given out. Endpoints
+/// of equal weight are tried in the order they were added. An endpoint which
+/// fails its health check is skipped for [`retry_after`](Self::retry_after)
+/// before being checked again, unless all endpoints are failing. This suits
+/// services with a primary endpoint in a VM and a fallback on the host:
+///
+/// ```ignore
+/// let endpoints = Endpoints::new()
+///     .endpoint(ConnectionInfo::Vsock(vm_address), 10)
+///     .endpoint(ConnectionInfo::Vsock(host_address), 1);
+/// let accessor = Accessor::with_endpoints("android.hardware.foo.IFoo/vm", endpoints)?;
+/// ```
+///
+/// The selected endpoint is given out as a copy, so endpoints should be
+/// addresses: a [`ConnectionInfo::PreconnectedFd`] would be shared by every
+/// connection.
+pub struct Endpoints {
+    candidates: Vec<Candidate>,
+    health_check: Box<dyn Fn(&ConnectionInfo) -> bool + Send + Sync>,
+    retry_after: Duration,
+}
+
+struct Candidate {
+    info: ConnectionInfo,
+    weight: u32,
+    failing_since: Mutex<Option<Instant>>,
+}
+
+impl fmt::Debug for Endpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Endpoints")
+            .field(
+                "candidates",
+                &self.candidates.iter().map(|c| (&c.info, c.weight)).collect::<Vec<_>>(),
+            )
+            .field("retry_after", &self.retry_after)
+            .finish()
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Endpoints {
+    /// The default time a failing endpoint is skipped for.
+    pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+    /// Create an empty set of endpoints, checked with
+    /// [`ConnectionInfo::can_connect`].
+    pub fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            health_check: Box::new(ConnectionInfo::can_connect),
+            retry_after: Self::DEFAULT_RETRY_AFTER,
+        }
+    }
+
+    /// Add a candidate endpoint with the given weight.
+    pub fn endpoint(mut self, info: ConnectionInfo, weight: u32) -> Self {
+        let candidate = Candidate { info, weight, failing_since: Mutex::new(None) };
+        let index = self.candidates.partition_point(|c| c.weight >= weight);
+        self.candidates.insert(index, candidate);
+        self
+    }
+
+    /// Replace the health check, which is called on the connecting thread and
+    /// so should be quick.
+    pub fn health_check<F>(mut self, health_check: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) -> bool + Send + Sync + 'static,
+    {
+        self.health_check = Box::new(health_check);
+        self
+    }
+
+    /// Set how long an endpoint which failed its health check is skipped for.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Health check the candidates and return the endpoint to connect to, or
+    /// `None` if none of them are healthy.
+    pub fn select(&self) -> Option<ConnectionInfo> {
+        let now = Instant::now();
+        let is_skipped = |candidate: &Candidate| {
+            candidate
+                .failing_since
+                .lock()
+                .unwrap()
+                .is_some_and(|since| now.duration_since(since) < self.retry_after)
+        };
+        let (ready, skipped): (Vec<_>, Vec<_>) =
+            self.candidates.iter().partition(|candidate| !is_skipped(candidate));
+        // Endpoints which failed recently are a last resort, rather than being
+        // ignored, so that a brief outage of every endpoint doesn't leave the
+        // service unavailable until they are retried.
+        ready.into_iter().chain(skipped).find_map(|candidate| {
+            let healthy = (self.health_check)(&candidate.info);
+            *candidate.failing_since.lock().unwrap() = if healthy { None } else { Some(now) };
+            if healthy {
+                candidate.info.try_clone().ok()
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// The last address an accessor callback gave out, for
+/// [`Accessor::new_cached`].
+struct ConnectionCache {
+    ttl: Duration,
+    health_check: Box<dyn Fn(&ConnectionInfo) -> bool + Send + Sync>,
+    state: Mutex<CacheState>,
+    // Signalled when a lookup finishes.
+    looked_up: Condvar,
+}
+
+#[derive(Default)]
+struct CacheState {
+    cached: Option<(ConnectionInfo, Instant)>,
+    // Whether a caller is looking the instance up, without holding the lock.
+    looking_up: bool,
+}
+
+impl ConnectionCache {
+    fn new(ttl: Duration, health_check: fn(&ConnectionInfo) -> bool) -> Self {
+        Self {
+            ttl,
+            health_check: Box::new(health_check),
+            state: Mutex::default(),
+            looked_up: Condvar::new(),
+        }
+    }
+
+    /// The cached address, if it is fresh and healthy, or otherwise the one
+    /// `lookup` gives out for `instance`.
+    ///
+    /// Neither the health check nor the lookup runs with the lock held, as
+    /// either may block for a while. Concurrent callers wait for a lookup
+    /// which is already running rather than starting their own.
+    fn get<F>(&self, instance: &str, lookup: F) -> Option<ConnectionInfo>
+    where
+        F: Fn(&str) -> Option<ConnectionInfo>,
+    {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            state = self.looked_up.wait_while(state, |state| state.looking_up).unwrap();
+            let Some((info, since)) = &state.cached else {
+                break;
+            };
+            let since = *since;
+            if since.elapsed() >= self.ttl {
+                state.cached = None;
+                break;
+            }
+            let copy = info.try_clone().ok()?;
+            drop(state);
+            if (self.health_check)(&copy) {
+                return Some(copy);
+            }
+            state = self.state.lock().unwrap();
+            // Forget the address, unless another caller has already replaced
+            // it, in which case its replacement is checked instead.
+            if state.cached.as_ref().is_some_and(|(_, cached)| *cached == since) {
+                state.cached = None;
+                break;
+            }
+        }
+
+        state.looking_up = true;
+        drop(state);
+        let looking_up = LookingUp(self);
+        let info = lookup(instance)?;
+        if !matches!(info, ConnectionInfo::PreconnectedFd(_)) {
+            self.state.lock().unwrap().cached =
+                info.try_clone().ok().map(|copy| (copy, Instant::now()));
+        }
+        drop(looking_up);
+        Some(info)
+    }
+}
+
+/// Lets other callers of a [`ConnectionCache`] go on once a lookup finishes,
+/// including by panicking.
+struct LookingUp<'a>(&'a ConnectionCache);
+
+impl Drop for LookingUp<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().looking_up = false;
+        self.0.looked_up.notify_all();
+    }
+}
+
+/// A binder object which gives out connections to an RPC binder service,
+/// making it available through the service manager like any other service.
+///
+/// The binder from [`as_binder`](Self::as_binder) can be registered with
+/// [`add_service`](binder::add_service) under the service's instance name;
+/// clients which look the instance up are then connected to the address the
+/// callback returns.
+///
+/// ```ignore
+/// let accessor = Accessor::new("android.hardware.foo.IFoo/vm", |_instance| {
+///     Some(ConnectionInfo::vsock(VM_CID, FOO_PORT))
+/// })?;
+/// binder::add_service("android.hardware.foo.IFoo/vm", accessor.as_binder().unwrap())?;
+/// ```
+///
+/// The binder object may outlive the `Accessor`, so dropping it is not a
+/// reliable way to stop giving out connections. To move the service, e.g.
+/// when its VM restarts with a new address, use [`replace`](Self::replace)
+/// instead; to take it down, use [`unregister`](Self::unregister). Both wait
+/// for callbacks which are already running, so once they return no client is
+/// given an address from the old callback.
+pub struct Accessor {
+    accessor: *mut rpc::ABinderRpc_Accessor,
+    instance: String,
+    // `None` for accessors wrapped by `from_binder`, whose callbacks belong to
+    // another process.
+    slot: Option<Arc<CallbackSlot>>,
+}
+
+type Callback =
+    Box<dyn Fn(&str) -> std::result::Result<ConnectionInfo, AccessorError> + Send + Sync>;
+
+/// Why an accessor callback didn't give out an address, from
+/// [`Accessor::new_fallible`].
+///
+/// Clients see the service as unavailable either way, but errors other than
+/// [`Unavailable`](Self::Unavailable) are logged with the instance name, so
+/// that failed lookups can be told apart from services which are turned off.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccessorError {
+    /// The service is unavailable on purpose, e.g. because the VM hosting it
+    /// isn't running. This is not logged.
+    Unavailable,
+    /// The address should be known, but looking it up failed for the given
+    /// reason, e.g. because a discovery service returned an error.
+    LookupFailed(String),
+    /// Looking the address up took too long.
+    TimedOut,
+}
+
+impl fmt::Display for AccessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable => f.write_str("service unavailable"),
+            Self::LookupFailed(reason) => write!(f, "lookup failed: {reason}"),
+            Self::TimedOut => f.write_str("lookup timed out"),
+        }
+    }
+}
+
+impl std::error::Error for AccessorError {}
+
+/// Adapts a callback which returns `None` for unavailable services.
+fn optional_callback<F>(callback: F) -> Callback
+where
+    F: Fn(&str) -> Option<ConnectionInfo> + Send + Sync + 'static,
+{
+    Box::new(move |instance| callback(instance).ok_or(AccessorError::Unavailable))
+}
+
+/// The callback of an accessor, shared with libbinder as its user data.
+///
+/// Callbacks run with the lock held for reading, so that taking it for
+/// writing waits for any which are running.
+struct CallbackSlot(RwLock<Option<Callback>>);
+
+impl fmt::Debug for Accessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Accessor").field("instance", &self.instance).finish()
+    }
+}
+
+/// SAFETY: The C accessor object is immutable once created, and safe to use
+/// from any thread.
+unsafe impl Send for Accessor {}
+
+/// SAFETY: The C accessor object is immutable once created, and safe to use
+/// from any thread.
+unsafe impl Sync for Accessor {}
+
+impl Accessor {
+    /// Create an accessor for the RPC service with the given instance name,
+    /// registered with `callback`.
+    ///
+    /// `callback` is called with the instance name each time a client
+    /// connects, possibly from several threads at once, and returns the
+    /// address of the service, or `None` if it is unavailable. It lives until
+    /// it is replaced or unregistered, or otherwise as long as the accessor's
+    /// binder object, which may be longer than the returned `Accessor`.
+    ///
+    /// Fails with `BAD_VALUE` if `instance` contains a NUL byte, or
+    /// `NO_MEMORY` if libbinder fails to create the accessor.
+    pub fn new<F>(instance: &str, callback: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<ConnectionInfo> + Send + Sync + 'static,
+    {
+        Self::with_callback(instance, optional_callback(callback))
+    }
+
+    /// Create an accessor whose callback says why it has no address for a
+    /// client, as an [`AccessorError`]:
+    ///
+    /// ```ignore
+    /// let accessor = Accessor::new_fallible("android.hardware.foo.IFoo/vm", |instance| {
+    ///     match discovery.lookup(instance) {
+    ///         Ok(Some(address)) => Ok(ConnectionInfo::Vsock(address)),
+    ///         Ok(None) => Err(AccessorError::Unavailable),
+    ///         Err(e) => Err(AccessorError::LookupFailed(e.to_string())),
+    ///     }
+    /// })?;
+    /// ```
+    ///
+    /// Otherwise the same as [`new`](Self::new).
+    pub fn new_fallible<F>(instance: &str, callback: F) -> Result<Self>
+    where
+        F: Fn(&str) -> std::result::Result<ConnectionInfo, AccessorError> + Send + Sync + 'static,
+    {
+        Self::with_callback(instance, Box::new(callback))
+    }
+
+    fn with_callback(instance: &str, callback: Callback) -> Result<Self> {
+        let c_instance = CString::new(instance).map_err(|_| StatusCode::BAD_VALUE)?;
+        let slot = Arc::new(CallbackSlot(RwLock::new(Some(callback))));
+        let data = Arc::into_raw(slot.clone());
+        // SAFETY: `c_instance` is a valid C string, which is copied. The
+        // reference to the slot which `data` holds stays valid until
+        // `on_delete` is called with it, which happens exactly once,
+        // including if this call fails.
+        let accessor = unsafe {
+            rpc::ABinderRpc_Accessor_new(
+                c_instance.as_ptr(),
+                Some(connection_info),
+                data.cast_mut().cast(),
+                Some(on_delete),
+            )
+        };
+        if accessor.is_null() {
+            return Err(StatusCode::NO_MEMORY);
+        }
+        Ok(Self { accessor, instance: instance.to_owned(), slot: Some(slot) })
+    }
+
+    /// Create an accessor whose callback is async, e.g. because the addresses
+    /// come from a discovery service with an async client.
+    ///
+    /// Each time a client connects, the future `callback` returns is run to
+    /// completion on `runtime` with [`Handle::block_on`] on the thread
+    /// libbinder calls the accessor from. That is normally a binder thread
+    /// rather than one of the runtime's worker threads, so the runtime isn't
+    /// stalled, but the binder thread is blocked until the future finishes,
+    /// and the future should give up after a short time:
+    ///
+    /// ```ignore
+    /// let discovery = discovery.clone();
+    /// let runtime = tokio::runtime::Handle::current();
+    /// let instance = "android.hardware.foo.IFoo/vm";
+    /// let accessor = Accessor::new_async(instance, runtime, move |instance| {
+    ///     let (discovery, instance) = (discovery.clone(), instance.to_owned());
+    ///     async move {
+    ///         let lookup = discovery.lookup(&instance);
+    ///         tokio::time::timeout(Duration::from_secs(1), lookup).await.ok().flatten()
+    ///     }
+    /// })?;
+    /// ```
+    ///
+    /// The accessor can also be called on a thread in a Tokio runtime, e.g.
+    /// if a task looks the instance up through an [`AccessorProvider`]
+    /// without `spawn_blocking`. `block_on` would panic there, so the future
+    /// isn't run at all, and the service is treated as unavailable.
+    ///
+    /// [`Handle::block_on`]: tokio::runtime::Handle::block_on
+    #[cfg(feature = "tokio")]
+    pub fn new_async<F, Fut>(
+        instance: &str,
+        runtime: tokio::runtime::Handle,
+        callback: F,
+    ) -> Result<Self>
+    where
+        F: Fn(&str) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<ConnectionInfo>>,
+    {
+        Self::new(instance, move |instance| {
+            if tokio::runtime::Handle::try_current().is_ok() {
+                log::error!("Accessor for {instance} can't block on its lookup in a Tokio runtime");
+                return None;
+            }
+            runtime.block_on(callback(instance))
+        })
+    }
+
+    /// Create an accessor which reuses the last address `callback` gave out
+    /// for up to `ttl`, for callbacks which are too slow to call for every
+    /// connection, e.g. when many clients reconnect at once.
+    ///
+    /// libbinder doesn't tell the accessor whether connecting to an address
+    /// worked, so before a cached address is given out again it is checked
+    /// with [`ConnectionInfo::can_connect`], and `callback` is called again if
+    /// that fails. Callers wait for a lookup which is already running rather
+    /// than starting their own. Preconnected sockets can't be shared, so they
+    /// are never cached.
+    pub fn new_cached<F>(instance: &str, ttl: Duration, callback: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<ConnectionInfo> + Send + Sync + 'static,
+    {
+        let cache = ConnectionCache::new(ttl, ConnectionInfo::can_connect);
+        Self::new(instance, move |instance| cache.get(instance, &callback))
+    }
+
+    /// Create an accessor which fails over between `endpoints`, as described
+    /// for [`Endpoints`].
+    pub fn with_endpoints(instance: &str, endpoints: Endpoints) -> Result<Self> {
+        Self::new(instance, move |_instance| endpoints.select())
+    }
+
+    /// Wrap the binder object of an accessor for `instance`, e.g. one received
+    /// from another process through the service manager, so that this process
+    /// can manage or delegate it.
+    ///
+    /// Returns `None` if `binder` is not an accessor for `instance`, which
+    /// libbinder checks by asking it for its instance name.
+    pub fn from_binder(instance: &str, mut binder: SpIBinder) -> Option<Self> {
+        let c_instance = CString::new(instance).ok()?;
+        // SAFETY: `c_instance` is a valid C string and `binder` a valid
+        // binder, both of which outlive the call. libbinder takes its own
+        // reference to the binder rather than taking ours.
+        let accessor = unsafe {
+            rpc::ABinderRpc_Accessor_fromBinder(c_instance.as_ptr(), binder.as_native_mut())
+        };
+        (!accessor.is_null()).then(|| Self { accessor, instance: instance.to_owned(), slot: None })
+    }
+
+    /// Register `callback` to give out connections, after
+    /// [`unregister`](Self::unregister).
+    ///
+    /// Fails with `ALREADY_EXISTS` if a callback is registered already, in
+    /// which case `callback` is dropped, or `INVALID_OPERATION` for accessors
+    /// from [`from_binder`](Self::from_binder).
+    pub fn register<F>(&self, callback: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<ConnectionInfo> + Send + Sync + 'static,
+    {
+        let mut current = self.slot()?.0.write().unwrap();
+        if current.is_some() {
+            return Err(StatusCode::ALREADY_EXISTS);
+        }
+        *current = Some(optional_callback(callback));
+        Ok(())
+    }
+
+    /// Stop giving out connections, so that clients which connect find the
+    /// service unavailable until a callback is registered again.
+    ///
+    /// Waits for callbacks which are already running to return, and then
+    /// drops the callback. Returns whether a callback was registered. Must
+    /// not be called from the callback, which would deadlock.
+    ///
+    /// Fails with `INVALID_OPERATION` for accessors from
+    /// [`from_binder`](Self::from_binder).
+    pub fn unregister(&self) -> Result<bool> {
+        let old = self.slot()?.0.write().unwrap().take();
+        Ok(old.is_some())
+    }
+
+    /// Replace the callback, whether or not one is registered.
+    ///
+    /// Clients which connect once this returns get addresses from `callback`.
+    /// Calls of the old callback which are already running finish first, and
+    /// the connections they give out are not affected, so this doesn't
+    /// disconnect existing clients. Must not be called from the callback,
+    /// which would deadlock.
+    ///
+    /// Fails with `INVALID_OPERATION` for accessors from
+    /// [`from_binder`](Self::from_binder).
+    pub fn replace<F>(&self, callback: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<ConnectionInfo> + Send + Sync + 'static,
+    {
+        let old = self.slot()?.0.write().unwrap().replace(optional_callback(callback));
+        // Drop the old callback without the lock held, in case dropping it
+        // blocks.
+        drop(old);
+        Ok(())
+    }
+
+    /// Whether a callback is registered to give out connections.
+    ///
+    /// Accessors from [`from_binder`](Self::from_binder) are always
+    /// considered registered, as that is up to the process which created
+    /// them.
+    pub fn is_registered(&self) -> bool {
+        match &self.slot {
+            Some(slot) => slot.0.read().unwrap().is_some(),
+            None => true,
+        }
+    }
+
+    fn slot(&self) -> Result<&CallbackSlot> {
+        self.slot.as_deref().ok_or(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Create an accessor for `instance` which gives out connections from
+    /// this one, e.g. so that a process which received an accessor from
+    /// another domain can republish the service under a name of its own:
+    ///
+    /// ```ignore
+    /// let vm_foo = binder::get_service("android.hardware.foo.IFoo/vm").unwrap();
+    /// let accessor = Accessor::from_binder("android.hardware.foo.IFoo/vm", vm_foo).unwrap();
+    /// let delegator = accessor.delegate("android.hardware.foo.IFoo/default")?;
+    /// binder::add_service("android.hardware.foo.IFoo/default", delegator.as_binder().unwrap())?;
+    /// ```
+    ///
+    /// The new accessor doesn't have a callback of its own, so it can't be
+    /// [`replace`](Self::replace)d. It stops working if this accessor's
+    /// process dies.
+    ///
+    /// Fails with `BAD_VALUE` if `instance` contains a NUL byte, or
+    /// `INVALID_OPERATION` if this accessor has no binder object.
+    pub fn delegate(&self, instance: &str) -> Result<Self> {
+        let c_instance = CString::new(instance).map_err(|_| StatusCode::BAD_VALUE)?;
+        let mut binder = self.as_binder().ok_or(StatusCode::INVALID_OPERATION)?;
+        let mut delegator = ptr::null_mut();
+        // SAFETY: `c_instance` is a valid C string and `binder` a valid binder,
+        // both of which outlive the call. On success, libbinder sets `delegator`
+        // to a new strong reference, which we take ownership of below.
+        let status = unsafe {
+            rpc::ABinderRpc_Accessor_delegateAccessor(
+                c_instance.as_ptr(),
+                binder.as_native_mut(),
+                &mut delegator,
+            )
+        };
+        status_result(status)?;
+        // SAFETY: `delegator` is null or a strong reference which we own.
+        let delegator =
+            unsafe { new_spibinder(delegator.cast()) }.ok_or(StatusCode::UNEXPECTED_NULL)?;
+        Self::from_binder(instance, delegator).ok_or(StatusCode::BAD_TYPE)
+    }
+
+    /// The instance name of the service this accessor is for.
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// The binder object of the accessor, to register with the service
+    /// manager or return from an accessor provider.
+    pub fn as_binder(&self) -> Option<SpIBinder> {
+        // SAFETY: `self.accessor` is a valid accessor until we delete it.
+        // `ABinderRpc_Accessor_asBinder` returns null or a new strong
+        // reference, which `new_spibinder` takes ownership of.
+        unsafe { new_spibinder(rpc::ABinderRpc_Accessor_asBinder(self.accessor).cast()) }
+    }
+
+    #[cfg(feature = "test-accessor-stubs")]
+    pub(crate) fn as_raw(&self) -> *mut rpc::ABinderRpc_Accessor {
+        self.accessor
+    }
+
+    /// Give up ownership of the C accessor, e.g. to return it from an
+    /// accessor provider.
+    fn into_raw(mut self) -> *mut rpc::ABinderRpc_Accessor {
+        std::mem::replace(&mut self.accessor, ptr::null_mut())
+    }
+}
+
+impl Drop for Accessor {
+    fn drop(&mut self) {
+        // SAFETY: `self.accessor` is null or a valid accessor, which is not
+        // used again.
+        unsafe { rpc::ABinderRpc_Accessor_delete(self.accessor) }
+    }
+}
+
+/// A callback registered with libbinder to provide [`Accessor`]s for a set of
+/// instances, so that this process can look them up without the service
+/// manager knowing about them.
+///
+/// ```ignore
+/// let provider = AccessorProvider::new(&["android.hardware.foo.IFoo/vm"], |instance| {
+///     Accessor::new(instance, |_| Some(ConnectionInfo::Vsock(vm_address()))).ok()
+/// })?;
+/// let foo: Strong<dyn IFoo> = binder::get_interface("android.hardware.foo.IFoo/vm")?;
+/// ```
+///
+/// The provider is unregistered when this is dropped, after which later
+/// lookups don't call it. The callback itself is dropped once any lookups
+/// which were already calling it have returned.
+pub struct AccessorProvider {
+    provider: *mut rpc::ABinderRpc_AccessorProvider,
+    instances: Vec<String>,
+}
+
+impl fmt::Debug for AccessorProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessorProvider").field("instances", &self.instances).finish()
+    }
+}
+
+/// SAFETY: The C registration is only used to unregister the provider,
+/// which can be done from any thread.
+unsafe impl Send for AccessorProvider {}
+
+/// SAFETY: The C registration is never used through a shared reference.
+unsafe impl Sync for AccessorProvider {}
+
+impl AccessorProvider {
+    /// Register `provider` to give out accessors for `instances`.
+    ///
+    /// `provider` is called with the instance name each time this process
+    /// looks up one of the instances, possibly from several threads at once,
+    /// and returns an accessor for it, or `None` if it is unavailable. Other
+    /// providers, and then the service manager, are tried if it returns
+    /// `None`.
+    ///
+    /// Fails with `BAD_VALUE` if `instances` is empty or any of them contain a
+    /// NUL byte, or `NO_MEMORY` if libbinder fails to register the provider.
+    pub fn new<S, F>(instances: &[S], provider: F) -> Result<Self>
+    where
+        S: AsRef<str>,
+        F: Fn(&str) -> Option<Accessor> + Send + Sync + 'static,
+    {
+        let instances: Vec<String> = instances.iter().map(|s| s.as_ref().to_owned()).collect();
+        if instances.is_empty() {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        let c_instances = instances
+            .iter()
+            .map(|instance| CString::new(instance.as_str()))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| StatusCode::BAD_VALUE)?;
+        let c_instance_ptrs: Vec<*const c_char> = c_instances.iter().map(|c| c.as_ptr()).collect();
+        let data = Box::into_raw(Box::new(provider));
+        // SAFETY: The instance names are valid C strings, which are copied.
+        // The provider pointer stays valid until `on_delete_provider::<F>` is
+        // called with it, which happens exactly once, including if this call
+        // fails.
+        let provider = unsafe {
+            rpc::ABinderRpc_registerAccessorProvider(
+                Some(provide_accessor::<F>),
+                c_instance_ptrs.as_ptr(),
+                c_instance_ptrs.len(),
+                data.cast(),
+                Some(on_delete_provider::<F>),
+            )
+        };
+        if provider.is_null() {
+            return Err(StatusCode::NO_MEMORY);
+        }
+        Ok(Self { provider, instances })
+    }
+
+    /// The instance names this provider gives out accessors for.
+    pub fn instances(&self) -> &[String] {
+        &self.instances
+    }
+}
+
+impl Drop for AccessorProvider {
+    fn drop(&mut self) {
+        // SAFETY: `self.provider` is a valid registration, which is not used
+        // again.
+        unsafe { rpc::ABinderRpc_unregisterAccessorProvider(self.provider) }
+    }
+}
+
+/// Called by libbinder with the user data of an accessor, to get the connection
+/// info for `instance`.
+///
+/// # Safety
+///
+/// `instance` must be null or a valid C string, and `data` must be the user
+/// data from `Accessor::new`, which has not been deleted yet.
+unsafe extern "C" fn connection_info(
+    instance: *const c_char,
+    data: *mut c_void,
+) -> *mut rpc::ABinderRpc_ConnectionInfo {
+    if instance.is_null() || data.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller promised that `instance` is a valid C string, which
+    // outlives this call.
+    let Ok(instance) = unsafe { CStr::from_ptr(instance) }.to_str() else {
+        return ptr::null_mut();
+    };
+    // SAFETY: Our caller promised that `data` is a reference to a live slot.
+    let slot = unsafe { &*(data as *const CallbackSlot) };
+    // Keep the lock for the whole call, so that `replace` and `unregister`
+    // wait for it.
+    let callback = slot.0.read().unwrap();
+    let Some(callback) = callback.as_ref() else {
+        return ptr::null_mut();
+    };
+    // A panic must not unwind into libbinder, so treat it as the service being
+    // unavailable.
+    match panic::catch_unwind(AssertUnwindSafe(|| callback(instance))) {
+        Ok(Ok(info)) => info.to_raw(),
+        Ok(Err(AccessorError::Unavailable)) | Err(_) => ptr::null_mut(),
+        Ok(Err(error)) => {
+            log::error!("Accessor for {instance} has no connection info: {error}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Called by libbinder once an accessor's binder object, and therefore its
+/// callback, is no longer needed.
+///
+/// # Safety
+///
+/// `data` must be the user data from `Accessor::new`, and this must only be
+/// called once for it.
+unsafe extern "C" fn on_delete(data: *mut c_void) {
+    // SAFETY: Our caller promised that `data` is the reference we leaked with
+    // `Arc::into_raw`, which is not used again.
+    drop(unsafe { Arc::from_raw(data as *const CallbackSlot) });
+}
+
+/// Called by libbinder with the user data of an accessor provider registered for
+/// a callback of type `F`, to get an accessor for `instance`.
+///
+/// # Safety
+///
+/// `instance` must be null or a valid C string, and `provider` must be the
+/// user data from `AccessorProvider::new::<_, F>`, which has not been deleted
+/// yet.
+unsafe extern "C" fn provide_accessor<F>(
+    instance: *const c_char,
+    provider: *mut c_void,
+) -> *mut rpc::ABinderRpc_Accessor
+where
+    F: Fn(&str) -> Option<Accessor> + Send + Sync + 'static,
+{
+    if instance.is_null() || provider.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller promised that `instance` is a valid C string, which
+    // outlives this call.
+    let Ok(instance) = unsafe { CStr::from_ptr(instance) }.to_str() else {
+        return ptr::null_mut();
+    };
+    // SAFETY: Our caller promised that `provider` points to a live `F`.
+    let provider = unsafe { &*(provider as *const F) };
+    // As for accessor callbacks, a panic means there is no accessor.
+    match panic::catch_unwind(AssertUnwindSafe(|| provider(instance))) {
+        Ok(Some(accessor)) => accessor.into_raw(),
+        Ok(None) | Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Called by libbinder once an accessor provider has been unregistered, and no
+/// calls of it are in progress.
+///
+/// # Safety
+///
+/// `provider` must be the user data from `AccessorProvider::new::<_, F>`, and
+/// this must only be called once for it.
+unsafe extern "C" fn on_delete_provider<F>(provider: *mut c_void)
+where
+    F: Fn(&str) -> Option<Accessor> + Send + Sync + 'static,
+{
+    // SAFETY: Our caller promised that `provider` is the pointer we leaked
+    // from a `Box<F>`, which is not used again.
+    drop(unsafe { Box::from_raw(provider as *mut F) });
+}
+
+#[cfg(all(test, feature = "test-accessor-stubs"))]
+mod tests {
+    use super::*;
+    use crate::accessor_stubs::{connection_info, provided_connection_info};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    /// Counts its drops, to check when libbinder deletes the callback.
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn callback_provides_connection_info() {
+        let accessor = Accessor::new("android.test.IFoo/vm", |instance| {
+            (instance == "android.test.IFoo/vm").then(|| ConnectionInfo::vsock(3, 5678))
+        })
+        .unwrap();
+        assert_eq!(accessor.instance(), "android.test.IFoo/vm");
+
+        let Some(ConnectionInfo::Vsock(addr)) = connection_info(&accessor, "android.test.IFoo/vm")
+        else {
+            panic!("Expected a vsock address");
+        };
+        assert_eq!((addr.cid, addr.port), (3, 5678));
+        assert!(connection_info(&accessor, "android.test.IFoo/other").is_none());
+        // The stubs have no binder objects.
+        assert!(accessor.as_binder().is_none());
+    }
+
+    #[test]
+    fn callback_is_deleted_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counter = DropCounter(drops.clone());
+        let accessor = Accessor::new("android.test.IBar/vm", move |_| {
+            let _ = &counter;
+            None
+        })
+        .unwrap();
+        assert!(connection_info(&accessor, "android.test.IBar/vm").is_none());
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        drop(accessor);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn panicking_callback_is_unavailable() {
+        let accessor = Accessor::new("android.test.IBaz/vm", |_| panic!("Lookup failed")).unwrap();
+        assert!(connection_info(&accessor, "android.test.IBaz/vm").is_none());
+    }
+
+    #[test]
+    fn endpoints_fail_over_by_weight() {
+        let primary_healthy = Arc::new(AtomicBool::new(true));
+        let healthy = primary_healthy.clone();
+        let endpoints = Endpoints::new()
+            .endpoint(ConnectionInfo::vsock(2, 1), 1)
+            .endpoint(ConnectionInfo::vsock(3, 1), 10)
+            .health_check(move |info| match info {
+                ConnectionInfo::Vsock(addr) => addr.cid != 3 || healthy.load(Ordering::SeqCst),
+                _ => false,
+            })
+            .retry_after(Duration::from_secs(3600));
+        let accessor = Accessor::with_endpoints("android.test.IFoo/vm", endpoints).unwrap();
+        let cid = || match connection_info(&accessor, "android.test.IFoo/vm") {
+            Some(ConnectionInfo::Vsock(addr)) => Some(addr.cid),
+            _ => None,
+        };
+
+        assert_eq!(cid(), Some(3));
+        primary_healthy.store(false, Ordering::SeqCst);
+        assert_eq!(cid(), Some(2));
+        // The primary is skipped while it is failing, even once it recovers.
+        primary_healthy.store(true, Ordering::SeqCst);
+        assert_eq!(cid(), Some(2));
+    }
+
+    #[test]
+    fn failing_endpoints_are_a_last_resort() {
+        let endpoints = Endpoints::new()
+            .endpoint(ConnectionInfo::vsock(3, 1), 0)
+            .health_check(|_| true)
+            .retry_after(Duration::from_secs(3600));
+        *endpoints.candidates[0].failing_since.lock().unwrap() = Some(Instant::now());
+        assert!(endpoints.select().is_some());
+        assert!(Endpoints::new().select().is_none());
+    }
+
+    #[test]
+    fn inet_addresses_round_trip() {
+        for addr in ["10.0.2.2:5678", "[fe80::1%2]:5678"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let accessor =
+                Accessor::new("android.test.IFoo/host", move |_| Some(ConnectionInfo::Inet(addr)))
+                    .unwrap();
+            let Some(ConnectionInfo::Inet(decoded)) =
+                connection_info(&accessor, "android.test.IFoo/host")
+            else {
+                panic!("Expected an inet address");
+            };
+            assert_eq!(decoded, addr);
+        }
+    }
+
+    #[test]
+    fn abstract_unix_addresses_round_trip() {
+        let addr = UnixAddress::new_abstract(b"binder.test").unwrap();
+        assert_eq!(addr.abstract_name(), Some(&b"binder.test"[..]));
+        assert_eq!(addr.path(), None);
+        let info = ConnectionInfo::Unix(addr);
+        assert_eq!(format!("{info:?}"), r#"Unix("@binder.test")"#);
+        // The address must not include the padding after the name, or it
+        // would name a different socket.
+        let (_, len) = info.to_sockaddr().unwrap();
+        assert_eq!(len as usize, offset_of!(sockaddr_un, sun_path) + 1 + b"binder.test".len());
+        assert_ne!(UnixAddress::new_abstract(b"binder.test\0").unwrap(), addr);
+
+        let accessor =
+            Accessor::new("android.test.IFoo/local", move |_| Some(ConnectionInfo::Unix(addr)))
+                .unwrap();
+        let Some(ConnectionInfo::Unix(decoded)) =
+            connection_info(&accessor, "android.test.IFoo/local")
+        else {
+            panic!("Expected a unix address");
+        };
+        assert_eq!(decoded, addr);
+    }
+
+    #[test]
+    fn addresses_convert_to_and_from_nix() {
+        let vsock = VsockAddress::new(3, 5000);
+        let nix_vsock = VsockAddr::from(vsock);
+        assert_eq!((nix_vsock.cid(), nix_vsock.port()), (3, 5000));
+        assert_eq!(VsockAddress::from(nix_vsock), vsock);
+
+        let path = UnixAddress::new("/dev/socket/binder.test").unwrap();
+        let nix_path = UnixAddr::from(path);
+        assert_eq!(nix_path.path(), Some(Path::new("/dev/socket/binder.test")));
+        assert_eq!(UnixAddress::try_from(&nix_path), Ok(path));
+
+        let name = UnixAddress::new_abstract(b"binder.test").unwrap();
+        let nix_name = UnixAddr::from(name);
+        assert_eq!(nix_name.as_abstract(), Some(&b"binder.test"[..]));
+        assert_eq!(UnixAddress::try_from(&nix_name), Ok(name));
+
+        assert_eq!(UnixAddress::try_from(&UnixAddr::new_unnamed()), Err(StatusCode::BAD_VALUE));
+    }
+
+    #[test]
+    fn preconnected_fds_are_handed_over() {
+        let accessor = Accessor::new("android.test.IFoo/preconnected", |_| {
+            let (ours, _theirs) = UnixStream::pair().ok()?;
+            Some(ConnectionInfo::PreconnectedFd(ours.into()))
+        })
+        .unwrap();
+        let Some(ConnectionInfo::PreconnectedFd(fd)) =
+            connection_info(&accessor, "android.test.IFoo/preconnected")
+        else {
+            panic!("Expected a preconnected fd");
+        };
+        // The other end was dropped by the callback.
+        assert!(!ConnectionInfo::PreconnectedFd(fd).can_connect());
+
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let info = ConnectionInfo::PreconnectedFd(ours.into());
+        assert!(info.can_connect());
+        assert!(info.try_clone().unwrap().can_connect());
+    }
+
+    #[test]
+    fn unix_addresses_are_checked() {
+        let addr = UnixAddress::new("/dev/socket/binder.test").unwrap();
+        assert_eq!(addr.path(), Some(Path::new("/dev/socket/binder.test")));
+        assert_eq!(addr.abstract_name(), None);
+        assert_eq!(format!("{addr:?}"), r#""/dev/socket/binder.test""#);
+        let (sun, len) = addr.to_sockaddr();
+        assert_eq!(len as usize, size_of::<sockaddr_un>());
+        assert_eq!(UnixAddress::from_sockaddr(&sun, len), Ok(addr));
+        assert_eq!(UnixAddress::from_sockaddr(&sun, 1).err(), Some(StatusCode::BAD_VALUE));
+
+        assert!(UnixAddress::new_abstract(&vec![b'a'; SUN_PATH_LEN - 1]).is_ok());
+        let too_long = UnixAddress::new_abstract(&vec![b'a'; SUN_PATH_LEN]);
+        assert_eq!(too_long.err(), Some(StatusCode::BAD_VALUE));
+        assert_eq!(UnixAddress::new_abstract(b"").err(), Some(StatusCode::BAD_VALUE));
+        assert_eq!(UnixAddress::new("").err(), Some(StatusCode::BAD_VALUE));
+        assert_eq!(UnixAddress::new("a\0b").err(), Some(StatusCode::BAD_VALUE));
+    }
+
+    #[test]
+    fn vsock_addresses_convert_to_sockaddr() {
+        let vm: sockaddr_vm = VsockAddress::new(3, 5678).into();
+        assert_eq!(i32::from(vm.svm_family), libc::AF_VSOCK);
+        assert_eq!((vm.svm_cid, vm.svm_port), (3, 5678));
+        assert_eq!(VsockAddress::from(vm), VsockAddress { cid: 3, port: 5678 });
+    }
+
+    #[test]
+    fn cached_addresses_expire_and_are_health_checked() {
+        static HEALTHY: AtomicBool = AtomicBool::new(true);
+        let lookups = AtomicUsize::new(0);
+        let lookup = |_: &str| {
+            let cid = lookups.fetch_add(1, Ordering::SeqCst) as u32;
+            Some(ConnectionInfo::vsock(cid, 1))
+        };
+        let cid = |info: Option<ConnectionInfo>| match info {
+            Some(ConnectionInfo::Vsock(addr)) => Some(addr.cid),
+            _ => None,
+        };
+
+        let healthy = |_: &ConnectionInfo| HEALTHY.load(Ordering::SeqCst);
+        let cache = ConnectionCache::new(Duration::from_secs(60), healthy);
+        assert_eq!(cid(cache.get("android.test.IFoo/vm", lookup)), Some(0));
+        assert_eq!(cid(cache.get("android.test.IFoo/vm", lookup)), Some(0));
+        HEALTHY.store(false, Ordering::SeqCst);
+        assert_eq!(cid(cache.get("android.test.IFoo/vm", lookup)), Some(1));
+        HEALTHY.store(true, Ordering::SeqCst);
+        assert_eq!(cid(cache.get("android.test.IFoo/vm", lookup)), Some(1));
+
+        let expired = ConnectionCache::new(Duration::ZERO, |_| true);
+        assert_eq!(cid(expired.get("android.test.IFoo/vm", lookup)), Some(2));
+        assert_eq!(cid(expired.get("android.test.IFoo/vm", lookup)), Some(3));
+    }
+
+    #[test]
+    fn concurrent_callers_wait_for_running_lookup() {
+        let cache = ConnectionCache::new(Duration::from_secs(60), |_| true);
+        let lookups = AtomicUsize::new(0);
+        let (started, lookup_started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let lookup = |_: &str| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            started.send(()).unwrap();
+            released.lock().unwrap().recv().unwrap();
+            Some(ConnectionInfo::vsock(3, 1))
+        };
+
+        std::thread::scope(|scope| {
+            let first = scope.spawn(|| cache.get("android.test.IFoo/vm", lookup));
+            lookup_started.recv().unwrap();
+            let second = scope.spawn(|| cache.get("android.test.IFoo/vm", lookup));
+            release.send(()).unwrap();
+            assert!(first.join().unwrap().is_some());
+            assert!(second.join().unwrap().is_some());
+        });
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn replace_and_unregister_switch_callbacks() {
+        let accessor =
+            Accessor::new("android.test.IFoo/vm", |_| Some(ConnectionInfo::vsock(3, 1))).unwrap();
+        let cid = || match connection_info(&accessor, "android.test.IFoo/vm") {
+            Some(ConnectionInfo::Vsock(addr)) => Some(addr.cid),
+            _ => None,
+        };
+        assert_eq!(cid(), Some(3));
+        assert_eq!(accessor.register(|_| None), Err(StatusCode::ALREADY_EXISTS));
+
+        accessor.replace(|_| Some(ConnectionInfo::vsock(4, 1))).unwrap();
+        assert_eq!(cid(), Some(4));
+
+        assert_eq!(accessor.unregister(), Ok(true));
+        assert!(!accessor.is_registered());
+        assert_eq!(cid(), None);
+        assert_eq!(accessor.unregister(), Ok(false));
+
+        accessor.register(|_| Some(ConnectionInfo::vsock(5, 1))).unwrap();
+        assert_eq!(cid(), Some(5));
+    }
+
+    #[test]
+    fn replace_waits_for_running_callbacks() {
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (finish_tx, finish_rx) = std::sync::mpsc::channel::<()>();
+        let finish_rx = Mutex::new(finish_rx);
+        let accessor = Arc::new(
+            Accessor::new("android.test.IFoo/vm", move |_| {
+                started_tx.send(()).unwrap();
+                finish_rx.lock().unwrap().recv().unwrap();
+                Some(ConnectionInfo::vsock(3, 1))
+            })
+            .unwrap(),
+        );
+        let connecting = {
+            let accessor = accessor.clone();
+            std::thread::spawn(move || connection_info(&accessor, "android.test.IFoo/vm"))
+        };
+        started_rx.recv().unwrap();
+
+        let replaced = Arc::new(AtomicBool::new(false));
+        let replacing = {
+            let (accessor, replaced) = (accessor.clone(), replaced.clone());
+            std::thread::spawn(move || {
+                accessor.replace(|_| Some(ConnectionInfo::vsock(4, 1))).unwrap();
+                replaced.store(true, Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!replaced.load(Ordering::SeqCst));
+
+        finish_tx.send(()).unwrap();
+        // The connection which was in flight gets the old address.
+        let Some(ConnectionInfo::Vsock(addr)) = connecting.join().unwrap() else {
+            panic!("Expected a vsock address");
+        };
+        assert_eq!(addr.cid, 3);
+        replacing.join().unwrap();
+        assert!(replaced.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn provider_serves_its_instances_until_dropped() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counter = DropCounter(drops.clone());
+        let instances = ["android.test.IFoo/a", "android.test.IFoo/b"];
+        let provider = AccessorProvider::new(&instances, move |instance| {
+            let _ = &counter;
+            let cid = if instance.ends_with("/a") { 3 } else { 4 };
+            Accessor::new(instance, move |_| Some(ConnectionInfo::vsock(cid, 1))).ok()
+        })
+        .unwrap();
+        assert_eq!(provider.instances(), instances);
+        let cid = |instance| match provided_connection_info(instance) {
+            Some(ConnectionInfo::Vsock(addr)) => Some(addr.cid),
+            _ => None,
+        };
+        assert_eq!(cid("android.test.IFoo/a"), Some(3));
+        assert_eq!(cid("android.test.IFoo/b"), Some(4));
+        assert_eq!(cid("android.test.IFoo/c"), None);
+
+        drop(provider);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(cid("android.test.IFoo/a"), None);
+    }
+
+    #[test]
+    fn provider_needs_valid_instances() {
+        let none: [&str; 0] = [];
+        assert_eq!(AccessorProvider::new(&none, |_| None).unwrap_err(), StatusCode::BAD_VALUE);
+        assert_eq!(
+            AccessorProvider::new(&["android.test\0"], |_| None).unwrap_err(),
+            StatusCode::BAD_VALUE
+        );
+    }
+
+    #[test]
+    fn stub_accessors_cannot_be_delegated() {
+        let accessor = Accessor::new("android.test.IFoo/vm", |_| None).unwrap();
+        assert_eq!(
+            accessor.delegate("android.test.IFoo/default").unwrap_err(),
+            StatusCode::INVALID_OPERATION
+        );
+        assert_eq!(accessor.delegate("android.test\0").unwrap_err(), StatusCode::BAD_VALUE);
+    }
+
+    #[cfg(feature = "tokio")]
+    fn tokio_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap()
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_callback_provides_connection_info() {
+        let runtime = tokio_runtime();
+        let accessor =
+            Accessor::new_async("android.test.IFoo/vm", runtime.handle().clone(), |instance| {
+                let available = instance == "android.test.IFoo/vm";
+                async move {
+                    tokio::task::yield_now().await;
+                    available.then(|| ConnectionInfo::vsock(3, 5678))
+                }
+            })
+            .unwrap();
+        let Some(ConnectionInfo::Vsock(addr)) = connection_info(&accessor, "android.test.IFoo/vm")
+        else {
+            panic!("Expected a vsock address");
+        };
+        assert_eq!((addr.cid, addr.port), (3, 5678));
+        assert!(connection_info(&accessor, "android.test.IFoo/other").is_none());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_callback_is_unavailable_within_runtime() {
+        let runtime = tokio_runtime();
+        let accessor =
+            Accessor::new_async("android.test.IFoo/vm", runtime.handle().clone(), |_| async {
+                Some(ConnectionInfo::vsock(3, 5678))
+            })
+            .unwrap();
+        let info = runtime.block_on(async { connection_info(&accessor, "android.test.IFoo/vm") });
+        assert!(info.is_none());
+    }
+
+    #[test]
+    fn fallible_callback_errors_are_unavailable() {
+        let accessor = Accessor::new_fallible("android.test.IFoo/vm", |instance| match instance {
+            "android.test.IFoo/vm" => Ok(ConnectionInfo::vsock(3, 5678)),
+            "android.test.IFoo/off" => Err(AccessorError::Unavailable),
+            _ => Err(AccessorError::LookupFailed("no such VM".to_owned())),
+        })
+        .unwrap();
+        assert!(connection_info(&accessor, "android.test.IFoo/vm").is_some());
+        assert!(connection_info(&accessor, "android.test.IFoo/off").is_none());
+        assert!(connection_info(&accessor, "android.test.IFoo/other").is_none());
+        assert_eq!(
+            AccessorError::LookupFailed("no such VM".to_owned()).to_string(),
+            "lookup failed: no such VM"
+        );
+    }
+
+    #[test]
+    fn instance_with_nul_is_rejected() {
+        assert_eq!(Accessor::new("android.test\0", |_| None).unwrap_err(), StatusCode::BAD_VALUE);
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pure Rust stand-ins for the accessor entry points of libbinder_rpc_unstable.
+//!
+//! With the `test-accessor-stubs` feature, only the accessor API is built, and [`Accessor`] and
+//! [`ConnectionInfo`] are backed by these rather than by libbinder_rpc_unstable, so that code which
+//! creates accessors can be unit tested on the host without linking libbinder_rpc_unstable, and
+//! without a service manager or RPC server. The stubs keep libbinder's ownership rules: the
+//! callback's user data is deleted exactly once, when the accessor is deleted, and connection info
+//! returned by the callback is deleted after use.
+//!
+//! Stub accessors have no binder object, so [`Accessor::as_binder`] returns
+//! `None`. Use [`connection_info`] to call the callback the way libbinder
+//! would when a client connects, and [`provided_connection_info`] to look an
+//! instance up through the registered [`AccessorProvider`](crate::AccessorProvider)s.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+use crate::{Accessor, ConnectionInfo, UnixAddress};
+
+use binder::StatusCode;
+use binder_ndk_sys::AIBinder;
+
+use libc::{
+    sa_family_t, sockaddr, sockaddr_in, sockaddr_in6, sockaddr_storage, sockaddr_un, sockaddr_vm,
+    socklen_t,
+};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::mem::{offset_of, size_of};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_ConnectionInfoProvider`.
+pub type ABinderRpc_ConnectionInfoProvider = Option<
+    unsafe extern "C" fn(
+        instance: *const c_char,
+        data: *mut c_void,
+    ) -> *mut ABinderRpc_ConnectionInfo,
+>;
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_ConnectionInfoProviderUserData_delete`.
+pub type ABinderRpc_ConnectionInfoProviderUserData_delete =
+    Option<unsafe extern "C" fn(data: *mut c_void)>;
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_Accessor`.
+pub struct ABinderRpc_Accessor {
+    provider: unsafe extern "C" fn(*const c_char, *mut c_void) -> *mut ABinderRpc_ConnectionInfo,
+    data: *mut c_void,
+    on_delete: ABinderRpc_ConnectionInfoProviderUserData_delete,
+}
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_AccessorProvider_getAccessorCallback`.
+pub type ABinderRpc_AccessorProvider_getAccessorCallback = Option<
+    unsafe extern "C" fn(instance: *const c_char, data: *mut c_void) -> *mut ABinderRpc_Accessor,
+>;
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_AccessorProviderUserData_deleteCallback`.
+pub type ABinderRpc_AccessorProviderUserData_deleteCallback =
+    Option<unsafe extern "C" fn(data: *mut c_void)>;
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_AccessorProvider`.
+pub struct ABinderRpc_AccessorProvider {
+    registered: Arc<RegisteredProvider>,
+}
+
+struct RegisteredProvider {
+    instances: Vec<CString>,
+    provider: unsafe extern "C" fn(*const c_char, *mut c_void) -> *mut ABinderRpc_Accessor,
+    data: *mut c_void,
+    on_delete: ABinderRpc_AccessorProviderUserData_deleteCallback,
+}
+
+// SAFETY: libbinder requires the provider and its user data to be usable from
+// any thread.
+unsafe impl Send for RegisteredProvider {}
+
+// SAFETY: As above.
+unsafe impl Sync for RegisteredProvider {}
+
+impl Drop for RegisteredProvider {
+    fn drop(&mut self) {
+        if let Some(on_delete) = self.on_delete {
+            // SAFETY: `ABinderRpc_registerAccessorProvider`'s caller promised
+            // that this is safe to call once.
+            unsafe { on_delete(self.data) };
+        }
+    }
+}
+
+/// The registered providers, which, like libbinder, we copy out to call so
+/// that unregistering one doesn't delete it while it is in use.
+static PROVIDERS: Mutex<Vec<Arc<RegisteredProvider>>> = Mutex::new(Vec::new());
+
+/// Stand-in for libbinder_rpc_unstable's `ABinderRpc_ConnectionInfo`.
+pub struct ABinderRpc_ConnectionInfo {
+    addr: sockaddr_storage,
+    len: socklen_t,
+    fd: Option<OwnedFd>,
+}
+
+/// Stand-in for `ABinderRpc_Accessor_new`.
+///
+/// # Safety
+///
+/// As for the C function: `instance` must be a valid C string, and
+/// `on_delete`, if any, must be safe to call once with `data`. The instance
+/// name is not kept, as libbinder passes it to the provider on each call.
+pub unsafe extern "C" fn ABinderRpc_Accessor_new(
+    instance: *const c_char,
+    provider: ABinderRpc_ConnectionInfoProvider,
+    data: *mut c_void,
+    on_delete: ABinderRpc_ConnectionInfoProviderUserData_delete,
+) -> *mut ABinderRpc_Accessor {
+    match provider {
+        Some(provider) if !instance.is_null() => {
+            Box::into_raw(Box::new(ABinderRpc_Accessor { provider, data, on_delete }))
+        }
+        _ => {
+            if let Some(on_delete) = on_delete {
+                // SAFETY: Our caller promised that this is safe, and libbinder
+                // deletes the user data when it fails.
+                unsafe { on_delete(data) };
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Stand-in for `ABinderRpc_Accessor_delete`, which deletes the user data
+/// straight away as there is no binder object to keep it alive.
+///
+/// # Safety
+///
+/// `accessor` must be null or an accessor from [`ABinderRpc_Accessor_new`]
+/// which has not been deleted.
+pub unsafe extern "C" fn ABinderRpc_Accessor_delete(accessor: *mut ABinderRpc_Accessor) {
+    if accessor.is_null() {
+        return;
+    }
+    // SAFETY: Our caller promised that `accessor` came from `Box::into_raw` in
+    // `ABinderRpc_Accessor_new`, and is not used again.
+    let accessor = unsafe { Box::from_raw(accessor) };
+    if let Some(on_delete) = accessor.on_delete {
+        // SAFETY: `ABinderRpc_Accessor_new`'s caller promised that this is
+        // safe to call once.
+        unsafe { on_delete(accessor.data) };
+    }
+}
+
+/// Stand-in for `ABinderRpc_Accessor_asBinder`, which always returns null.
+///
+/// # Safety
+///
+/// Always safe; `unsafe` only to match the C function.
+pub unsafe extern "C" fn ABinderRpc_Accessor_asBinder(
+    _accessor: *mut ABinderRpc_Accessor,
+) -> *mut AIBinder {
+    ptr::null_mut()
+}
+
+/// Stand-in for `ABinderRpc_Accessor_fromBinder`, which always returns null
+/// as stub accessors have no binder objects to wrap.
+///
+/// # Safety
+///
+/// Always safe; `unsafe` only to match the C function.
+pub unsafe extern "C" fn ABinderRpc_Accessor_fromBinder(
+    _instance: *const c_char,
+    _binder: *mut AIBinder,
+) -> *mut ABinderRpc_Accessor {
+    ptr::null_mut()
+}
+
+/// Stand-in for `ABinderRpc_registerAccessorProvider`.
+///
+/// # Safety
+///
+/// As for the C function: `instances` must point to `num_instances` valid C
+/// strings, and `on_delete`, if any, must be safe to call once with `data`.
+pub unsafe extern "C" fn ABinderRpc_registerAccessorProvider(
+    provider: ABinderRpc_AccessorProvider_getAccessorCallback,
+    instances: *const *const c_char,
+    num_instances: usize,
+    data: *mut c_void,
+    on_delete: ABinderRpc_AccessorProviderUserData_deleteCallback,
+) -> *mut ABinderRpc_AccessorProvider {
+    let delete_data = || {
+        if let Some(on_delete) = on_delete {
+            // SAFETY: Our caller promised that this is safe, and libbinder
+            // deletes the user data when it fails.
+            unsafe { on_delete(data) };
+        }
+    };
+    let Some(provider) = provider else {
+        delete_data();
+        return ptr::null_mut();
+    };
+    if instances.is_null() || num_instances == 0 {
+        delete_data();
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller promised that `instances` points to `num_instances`
+    // pointers.
+    let names = unsafe { std::slice::from_raw_parts(instances, num_instances) };
+    if names.iter().any(|name| name.is_null()) {
+        delete_data();
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller promised that the names are valid C strings.
+    let instances = names.iter().map(|&name| unsafe { CStr::from_ptr(name) }.to_owned()).collect();
+    let registered = Arc::new(RegisteredProvider { instances, provider, data, on_delete });
+    PROVIDERS.lock().unwrap().push(registered.clone());
+    Box::into_raw(Box::new(ABinderRpc_AccessorProvider { registered }))
+}
+
+/// Stand-in for `ABinderRpc_unregisterAccessorProvider`.
+///
+/// # Safety
+///
+/// `provider` must be null or a registration from
+/// [`ABinderRpc_registerAccessorProvider`] which has not been deleted.
+pub unsafe extern "C" fn ABinderRpc_unregisterAccessorProvider(
+    provider: *mut ABinderRpc_AccessorProvider,
+) {
+    if provider.is_null() {
+        return;
+    }
+    // SAFETY: Our caller promised that `provider` came from `Box::into_raw` in
+    // `ABinderRpc_registerAccessorProvider`, and is not used again.
+    let provider = unsafe { Box::from_raw(provider) };
+    PROVIDERS.lock().unwrap().retain(|registered| !Arc::ptr_eq(registered, &provider.registered));
+}
+
+/// Stand-in for `ABinderRpc_Accessor_delegateAccessor`, which always fails
+/// with `INVALID_OPERATION` as stub accessors have no binder objects to
+/// delegate to.
+///
+/// # Safety
+///
+/// Always safe; `unsafe` only to match the C function.
+pub unsafe extern "C" fn ABinderRpc_Accessor_delegateAccessor(
+    _instance: *const c_char,
+    _accessor: *mut AIBinder,
+    _out_delegator: *mut *mut AIBinder,
+) -> i32 {
+    StatusCode::INVALID_OPERATION as i32
+}
+
+/// Stand-in for `ABinderRpc_ConnectionInfo_new`, which accepts the same
+/// addresses as libbinder.
+///
+/// # Safety
+///
+/// `addr` must point to at least `len` readable bytes.
+pub unsafe extern "C" fn ABinderRpc_ConnectionInfo_new(
+    addr: *const sockaddr,
+    len: socklen_t,
+) -> *mut ABinderRpc_ConnectionInfo {
+    let size = len as usize;
+    if addr.is_null() || size < size_of::<sa_family_t>() {
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller promised that `addr` points to at least `len` bytes,
+    // which include the family.
+    let valid = match i32::from(unsafe { (*addr).sa_family }) {
+        libc::AF_VSOCK => size == size_of::<sockaddr_vm>(),
+        libc::AF_UNIX => {
+            size > offset_of!(sockaddr_un, sun_path) && size <= size_of::<sockaddr_un>()
+        }
+        libc::AF_INET => size == size_of::<sockaddr_in>(),
+        libc::AF_INET6 => size == size_of::<sockaddr_in6>(),
+        _ => false,
+    };
+    if !valid {
+        return ptr::null_mut();
+    }
+    // SAFETY: All zeroes is a valid `sockaddr_storage`.
+    let mut info = ABinderRpc_ConnectionInfo { addr: unsafe { std::mem::zeroed() }, len, fd: None };
+    // SAFETY: `addr` has `size` bytes, which fit in a `sockaddr_storage` for all
+    // of the families accepted above.
+    unsafe {
+        ptr::copy_nonoverlapping(addr.cast::<u8>(), ptr::addr_of_mut!(info.addr).cast(), size)
+    };
+    Box::into_raw(Box::new(info))
+}
+
+/// Stand-in for `ABinderRpc_ConnectionInfo_newPreconnected`.
+///
+/// # Safety
+///
+/// `fd` must be negative or an open file descriptor which the caller owns,
+/// and which is given up to the connection info.
+pub unsafe extern "C" fn ABinderRpc_ConnectionInfo_newPreconnected(
+    fd: c_int,
+) -> *mut ABinderRpc_ConnectionInfo {
+    if fd < 0 {
+        return ptr::null_mut();
+    }
+    // SAFETY: Our caller gives us ownership of `fd`.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: All zeroes is a valid `sockaddr_storage`.
+    let addr = unsafe { std::mem::zeroed() };
+    Box::into_raw(Box::new(ABinderRpc_ConnectionInfo { addr, len: 0, fd: Some(fd) }))
+}
+
+/// Stand-in for `ABinderRpc_ConnectionInfo_delete`.
+///
+/// # Safety
+///
+/// `info` must be null or connection info from
+/// [`ABinderRpc_ConnectionInfo_new`] which has not been deleted.
+pub unsafe extern "C" fn ABinderRpc_ConnectionInfo_delete(info: *mut ABinderRpc_ConnectionInfo) {
+    if !info.is_null() {
+        // SAFETY: Our caller promised that `info` came from `Box::into_raw` in
+        // `ABinderRpc_ConnectionInfo_new`, and is not used again.
+        drop(unsafe { Box::from_raw(info) });
+    }
+}
+
+/// Calls the callback of `accessor` for `instance`, as libbinder does when a
+/// client connects, and returns the connection info it provided, if any.
+pub fn connection_info(accessor: &Accessor, instance: &str) -> Option<ConnectionInfo> {
+    // SAFETY: `Accessor` keeps its stub accessor alive.
+    stub_connection_info(unsafe { &*accessor.as_raw() }, instance)
+}
+
+/// Looks `instance` up through the registered accessor providers, as
+/// libbinder does when this process looks up a service, and returns the
+/// address which the first accessor provided for it gives out, if any.
+pub fn provided_connection_info(instance: &str) -> Option<ConnectionInfo> {
+    let name = CString::new(instance).ok()?;
+    let providers = PROVIDERS.lock().unwrap().clone();
+    providers.iter().filter(|provider| provider.instances.contains(&name)).find_map(|provider| {
+        // SAFETY: The provider is called with its user data, which is alive
+        // while we hold a reference to the registration, and returns null or
+        // an accessor which we now own.
+        let accessor = unsafe { (provider.provider)(name.as_ptr(), provider.data) };
+        if accessor.is_null() {
+            return None;
+        }
+        // SAFETY: `accessor` is a valid stub accessor, and deleted only below.
+        let info = stub_connection_info(unsafe { &*accessor }, instance);
+        // SAFETY: `accessor` came from `ABinderRpc_Accessor_new`, as required
+        // of the provider, and is not used again.
+        unsafe { ABinderRpc_Accessor_delete(accessor) };
+        info
+    })
+}
+
+fn stub_connection_info(stub: &ABinderRpc_Accessor, instance: &str) -> Option<ConnectionInfo> {
+    let instance = CString::new(instance).ok()?;
+    // SAFETY: The provider is called with its user data, which is alive until
+    // the accessor is deleted, and returns null or connection info which we
+    // now own.
+    let info = unsafe { (stub.provider)(instance.as_ptr(), stub.data) };
+    if info.is_null() {
+        return None;
+    }
+    // SAFETY: `info` is valid connection info, and deleted only below.
+    if let Some(fd) = unsafe { (*info).fd.take() } {
+        // SAFETY: `info` came from the provider, as below, and is not used
+        // again.
+        unsafe { ABinderRpc_ConnectionInfo_delete(info) };
+        return Some(ConnectionInfo::PreconnectedFd(fd));
+    }
+    // SAFETY: As above.
+    let addr = unsafe { &(*info).addr };
+    let ptr: *const sockaddr_storage = addr;
+    let decoded = match i32::from(addr.ss_family) {
+        libc::AF_VSOCK => {
+            // SAFETY: Connection info of each family has a whole address of
+            // that family, as checked by `ABinderRpc_ConnectionInfo_new`.
+            let vm = unsafe { *ptr.cast::<sockaddr_vm>() };
+            Some(ConnectionInfo::Vsock(vm.into()))
+        }
+        libc::AF_UNIX => {
+            // SAFETY: The storage is zeroed and big enough for a whole
+            // `sockaddr_un`, of which the first `len` bytes were copied.
+            let (sun, len) = unsafe { (&*ptr.cast::<sockaddr_un>(), (*info).len) };
+            UnixAddress::from_sockaddr(sun, len).ok().map(ConnectionInfo::Unix)
+        }
+        libc::AF_INET => {
+            // SAFETY: As for vsock addresses.
+            let sin = unsafe { *ptr.cast::<sockaddr_in>() };
+            let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+            Some(ConnectionInfo::Inet(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into()))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: As for vsock addresses.
+            let sin6 = unsafe { *ptr.cast::<sockaddr_in6>() };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            let port = u16::from_be(sin6.sin6_port);
+            let addr = SocketAddrV6::new(ip, port, sin6.sin6_flowinfo, sin6.sin6_scope_id);
+            Some(ConnectionInfo::Inet(addr.into()))
+        }
+        _ => None,
+    };
+    // SAFETY: `info` came from `ABinderRpc_ConnectionInfo_new`, as required of
+    // the provider, and is not used again.
+    unsafe { ABinderRpc_ConnectionInfo_delete(info) };
+    decoded
+}
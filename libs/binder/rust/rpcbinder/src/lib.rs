@@ -15,11 +15,61 @@
  */
 
 //! API for RPC Binder services.
+//!
+//! With the `wire` feature, this is instead a client-only implementation of the RPC Binder wire
+//! protocol in Rust alone, for environments without libbinder_ndk, starting with
+//! `WireSession`.
+//!
+//! With the `tokio` feature, accessors can also look up their connection info with async
+//! callbacks, run on a Tokio runtime.
+//!
+//! With the `test-accessor-stubs` feature, only the accessor API is built, backed by pure Rust
+//! stand-ins rather than by libbinder_rpc_unstable, so that accessors can be unit tested on the
+//! host.
 
-mod server;
-mod session;
+cfg_if::cfg_if! {
+    if #[cfg(feature = "wire")] {
+        mod wire;
 
-pub use server::RpcServer;
-#[cfg(not(target_os = "trusty"))]
-pub use server::RpcServerRef;
-pub use session::{FileDescriptorTransportMode, RpcSession, RpcSessionRef};
+        pub use wire::{Error, Result, WireBinder, WireParcel, WireSession, FLAG_ONEWAY};
+    } else if #[cfg(feature = "test-accessor-stubs")] {
+        mod accessor;
+        pub mod accessor_stubs;
+
+        pub use accessor::{
+            Accessor, AccessorError, AccessorProvider, ConnectionInfo, Endpoints, UnixAddress,
+            VsockAddress,
+        };
+    } else {
+        #[cfg(not(target_os = "trusty"))]
+        mod accessor;
+        #[cfg(not(target_os = "trusty"))]
+        mod loopback;
+        mod server;
+        mod session;
+        #[cfg(not(target_os = "trusty"))]
+        mod tls;
+
+        #[cfg(not(target_os = "trusty"))]
+        pub use accessor::{
+            Accessor, AccessorError, AccessorProvider, ConnectionInfo, Endpoints, UnixAddress,
+            VsockAddress,
+        };
+        #[cfg(not(target_os = "trusty"))]
+        pub use loopback::Loopback;
+        pub use server::RpcServer;
+        #[cfg(not(target_os = "trusty"))]
+        pub use server::{
+            PeerAddress, PeerCredentials, RootObjectFactory, RpcServerRef, SessionId, SessionInfo,
+            SessionListener,
+        };
+        pub use session::{
+            FileDescriptorTransportMode, IntegrityMode, ReconnectingObject, ReconnectingSession,
+            RpcSession, RpcSessionRef,
+        };
+        #[cfg(not(target_os = "trusty"))]
+        pub use session::ClientSessionListener;
+        #[cfg(not(target_os = "trusty"))]
+        pub use tls::{PeerVerification, TlsConfig, TlsCredentials};
+    }
+}
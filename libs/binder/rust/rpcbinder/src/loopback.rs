@@ -0,0 +1,102 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! An RPC Binder server and client within one process, for tests.
+//!
+//! RPC Binder doesn't need the binder driver, so a service can be tested over
+//! a [`Loopback`] on hosts without `/dev/binder`, while still going through
+//! the parceling and threading of a real remote call:
+//!
+//! ```ignore
+//! let service = BnFoo::new_binder(Foo, BinderFeatures::default());
+//! let loopback = Loopback::<dyn IFoo>::new(service.as_binder())?;
+//! assert_eq!(loopback.root().getBar()?, 42);
+//! ```
+
+use crate::server::{RpcServer, RpcServerRef};
+use crate::session::{FileDescriptorTransportMode, RpcSession, RpcSessionRef};
+use binder::{FromIBinder, SpIBinder, StatusCode, Strong};
+use std::fmt;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+
+/// An RPC Binder server serving a service, and a session connected to it over a Unix domain socket
+/// pair, both in this process.
+///
+/// File descriptors can be sent both ways, as for a Unix domain socket server which supports
+/// [`FileDescriptorTransportMode::Unix`].
+pub struct Loopback<T: FromIBinder + ?Sized> {
+    // Fields are dropped in order, so the client goes away before the server it is connected to.
+    root: Strong<T>,
+    session: RpcSession,
+    _bootstrap: UnixStream,
+    server: RpcServer,
+}
+
+impl<T: FromIBinder + ?Sized> fmt::Debug for Loopback<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Loopback")
+            .field("session", &self.session)
+            .field("server", &self.server)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: FromIBinder + ?Sized> Loopback<T> {
+    /// Serves `service` over a new loopback, and connects to it.
+    pub fn new(service: SpIBinder) -> Result<Self, StatusCode> {
+        Self::new_configured(service, |_, _| {})
+    }
+
+    /// Like [`new`](Self::new), but calls `configure` with the server and the session before
+    /// either is started, e.g. to give both more threads for callbacks.
+    pub fn new_configured(
+        service: SpIBinder,
+        configure: impl FnOnce(&RpcServerRef, &RpcSessionRef),
+    ) -> Result<Self, StatusCode> {
+        let (server_end, client_end) = UnixStream::pair().map_err(|e| {
+            log::error!("Cannot create a socket pair for the loopback: {:?}", e);
+            StatusCode::UNKNOWN_ERROR
+        })?;
+        let server = RpcServer::new_unix_domain_bootstrap(service, server_end.into())
+            .map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+        server.set_supported_file_descriptor_transport_modes(&[
+            FileDescriptorTransportMode::None,
+            FileDescriptorTransportMode::Unix,
+        ]);
+        let session = RpcSession::new();
+        session.set_file_descriptor_transport_mode(FileDescriptorTransportMode::Unix);
+        configure(&server, &session);
+        server.start();
+        let root = session.setup_unix_domain_bootstrap_client(client_end.as_fd())?;
+        Ok(Self { root, session, _bootstrap: client_end, server })
+    }
+
+    /// The service, as seen by the client.
+    pub fn root(&self) -> &Strong<T> {
+        &self.root
+    }
+
+    /// The client's session.
+    pub fn session(&self) -> &RpcSessionRef {
+        &self.session
+    }
+
+    /// The server.
+    pub fn server(&self) -> &RpcServerRef {
+        &self.server
+    }
+}
@@ -20,6 +20,8 @@ cfg_if::cfg_if! {
         pub use trusty::*;
     } else {
         mod android;
+        mod listener;
         pub use android::*;
+        pub use listener::*;
     }
 }
@@ -15,12 +15,17 @@
  */
 
 use crate::session::FileDescriptorTransportMode;
+use crate::tls::TlsConfig;
 use binder::{unstable_api::AsNative, SpIBinder};
 use binder_rpc_unstable_bindgen::ARpcServer;
 use foreign_types::{foreign_type, ForeignType, ForeignTypeRef};
+use std::collections::BTreeSet;
 use std::ffi::CString;
 use std::io::{Error, ErrorKind};
-use std::os::unix::io::{IntoRawFd, OwnedFd};
+use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::Mutex;
 
 foreign_type! {
     type CType = binder_rpc_unstable_bindgen::ARpcServer;
@@ -76,6 +81,32 @@ impl RpcServer {
         }
     }
 
+    /// Creates a binder RPC server, serving the supplied binder service implementation on a new
+    /// Unix domain socket bound to `path`.
+    ///
+    /// Fails if something already exists at `path`, which isn't removed, as it may be the socket
+    /// of a server which is still running. The socket file is left behind when the server shuts
+    /// down.
+    pub fn new_unix_domain(service: SpIBinder, path: impl AsRef<Path>) -> Result<RpcServer, Error> {
+        let listener = UnixListener::bind(path.as_ref()).map_err(|e| {
+            log::error!("Cannot bind to {}: {:?}", path.as_ref().display(), e);
+            e
+        })?;
+        Self::new_bound_socket(service, listener.into())
+    }
+
+    /// Creates a binder RPC server, serving the supplied binder service implementation on the
+    /// Unix domain socket that init created for this service under `socket_name`.
+    ///
+    /// The socket must be declared with a `socket` option in the service's init .rc file, in
+    /// which case init creates it with the declared permissions and passes it to the service
+    /// through the `ANDROID_SOCKET_<socket_name>` environment variable, as for
+    /// `android_get_control_socket`.
+    pub fn new_init_unix_domain(service: SpIBinder, socket_name: &str) -> Result<RpcServer, Error> {
+        let socket_fd = get_control_socket(socket_name)?;
+        Self::new_bound_socket(service, socket_fd)
+    }
+
     /// Creates a binder RPC server that bootstraps sessions using an existing Unix domain socket
     /// pair, with a given root IBinder object. Callers should create a pair of SOCK_STREAM Unix
     /// domain sockets, pass one to the server and the other to the client. Multiple client session
@@ -120,6 +151,78 @@ impl RpcServer {
         }
     }
 
+    /// Like [`new_vsock`](Self::new_vsock), but connections are encrypted with TLS and clients
+    /// are checked as configured by `config`.
+    pub fn new_vsock_tls(
+        mut service: SpIBinder,
+        cid: u32,
+        port: u32,
+        config: &TlsConfig,
+    ) -> Result<RpcServer, Error> {
+        let service = service.as_native_mut();
+
+        // SAFETY: Service ownership is transferring to the server and won't be valid afterward.
+        // Plus the binder objects are threadsafe. The server doesn't keep the config pointer.
+        unsafe {
+            Self::checked_from_ptr(binder_rpc_unstable_bindgen::ARpcServer_newVsockTls(
+                service,
+                cid,
+                port,
+                config.as_ptr(),
+            ))
+        }
+    }
+
+    /// Like [`new_bound_socket`](Self::new_bound_socket), but connections are encrypted with TLS
+    /// and clients are checked as configured by `config`.
+    pub fn new_bound_socket_tls(
+        mut service: SpIBinder,
+        socket_fd: OwnedFd,
+        config: &TlsConfig,
+    ) -> Result<RpcServer, Error> {
+        let service = service.as_native_mut();
+
+        // SAFETY: Service ownership is transferring to the server and won't be valid afterward.
+        // Plus the binder objects are threadsafe. The server doesn't keep the config pointer.
+        // The server takes ownership of the socket FD.
+        unsafe {
+            Self::checked_from_ptr(binder_rpc_unstable_bindgen::ARpcServer_newBoundSocketTls(
+                service,
+                socket_fd.into_raw_fd(),
+                config.as_ptr(),
+            ))
+        }
+    }
+
+    /// Like [`new_inet`](Self::new_inet), but connections are encrypted with TLS and clients are
+    /// checked as configured by `config`.
+    pub fn new_inet_tls(
+        mut service: SpIBinder,
+        address: &str,
+        port: u32,
+        config: &TlsConfig,
+    ) -> Result<RpcServer, Error> {
+        let address = match CString::new(address) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Cannot convert {} to CString. Error: {:?}", address, e);
+                return Err(Error::from(ErrorKind::InvalidInput));
+            }
+        };
+        let service = service.as_native_mut();
+
+        // SAFETY: Service ownership is transferring to the server and won't be valid afterward.
+        // Plus the binder objects are threadsafe. The server doesn't keep the config pointer.
+        unsafe {
+            Self::checked_from_ptr(binder_rpc_unstable_bindgen::ARpcServer_newInetTls(
+                service,
+                address.as_ptr(),
+                port,
+                config.as_ptr(),
+            ))
+        }
+    }
+
     unsafe fn checked_from_ptr(ptr: *mut ARpcServer) -> Result<RpcServer, Error> {
         if ptr.is_null() {
             return Err(Error::new(ErrorKind::Other, "Failed to start server"));
@@ -161,6 +264,12 @@ impl RpcServerRef {
         unsafe { binder_rpc_unstable_bindgen::ARpcServer_setMaxThreads(self.as_ptr(), count) };
     }
 
+    /// Returns the max number of threads set with [`set_max_threads`](Self::set_max_threads).
+    pub fn max_threads(&self) -> usize {
+        // SAFETY: RpcServerRef wraps a valid pointer to an ARpcServer.
+        unsafe { binder_rpc_unstable_bindgen::ARpcServer_getMaxThreads(self.as_ptr()) }
+    }
+
     /// Starts a new background thread and calls join(). Returns immediately.
     pub fn start(&self) {
         // SAFETY: RpcServerRef wraps a valid pointer to an ARpcServer.
@@ -185,3 +294,93 @@ impl RpcServerRef {
         }
     }
 }
+
+/// The keys of the init sockets which have been taken with `get_control_socket`.
+static ADOPTED_CONTROL_SOCKETS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Takes ownership of the socket that init passed to this process for `name`.
+///
+/// Each socket can only be taken once, and fails with `AlreadyExists` after
+/// that, as the first `OwnedFd` still owns it.
+fn get_control_socket(name: &str) -> Result<OwnedFd, Error> {
+    // Matches the key mangling done by init and libcutils.
+    let key: String = format!("ANDROID_SOCKET_{}", name)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    // Hold the lock until the fd is adopted, so that two threads can't both
+    // adopt it.
+    let mut adopted = ADOPTED_CONTROL_SOCKETS.lock().unwrap();
+    if adopted.contains(&key) {
+        log::error!("Init socket {} has already been taken", name);
+        return Err(Error::from(ErrorKind::AlreadyExists));
+    }
+    let value = std::env::var(&key).map_err(|e| {
+        log::error!("Cannot find init socket {}: {:?}", name, e);
+        Error::from(ErrorKind::NotFound)
+    })?;
+    let fd: RawFd = value.parse().map_err(|e| {
+        log::error!("Invalid file descriptor {:?} in {}: {:?}", value, key, e);
+        Error::from(ErrorKind::InvalidData)
+    })?;
+    if fd < 0 {
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    // Check that the fd is the socket init created for `name`, as
+    // `android_get_control_socket` does, rather than anything else which
+    // happens to have the number.
+    // SAFETY: All zeroes is a valid `sockaddr_un`.
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&addr) as libc::socklen_t;
+    let addr_ptr = (&mut addr as *mut libc::sockaddr_un).cast();
+    // SAFETY: getsockname doesn't modify any state but `addr` and `len`, which are valid for
+    // writes of `len` bytes, and is valid to call on any integer.
+    if unsafe { libc::getsockname(fd, addr_ptr, &mut len) } < 0 {
+        let e = Error::last_os_error();
+        log::error!("Init socket {} (fd {}) is not a socket: {:?}", name, fd, e);
+        return Err(e);
+    }
+    let expected = format!("/dev/socket/{}", name);
+    let path_len = (len as usize).saturating_sub(std::mem::offset_of!(libc::sockaddr_un, sun_path));
+    let path: Vec<u8> = addr.sun_path[..path_len.min(addr.sun_path.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect();
+    if i32::from(addr.sun_family) != libc::AF_UNIX || path != expected.as_bytes() {
+        log::error!("Fd {} in {} is not the socket {}", fd, key, expected);
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    adopted.insert(key);
+    // SAFETY: init hands the socket over to this process, the fd was checked to be the socket
+    // above, and it hasn't been adopted before, so we can take ownership of it.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn control_socket_must_be_the_init_socket() {
+        assert_eq!(
+            get_control_socket("rpcbinder_test_missing").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        // A socket which init didn't create for the name is left alone.
+        let (socket, _other) = UnixStream::pair().unwrap();
+        std::env::set_var("ANDROID_SOCKET_rpcbinder_test_unbound", socket.as_raw_fd().to_string());
+        let error = get_control_socket("rpcbinder_test_unbound").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // As is anything which isn't a socket.
+        let file = std::fs::File::open("/dev/null").unwrap();
+        std::env::set_var("ANDROID_SOCKET_rpcbinder_test_file", file.as_raw_fd().to_string());
+        assert!(get_control_socket("rpcbinder_test_file").is_err());
+        // SAFETY: F_GETFD doesn't modify any state.
+        assert!(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) } >= 0);
+    }
+}
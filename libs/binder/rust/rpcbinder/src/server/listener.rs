@@ -0,0 +1,405 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Callbacks for the sessions of an RPC server, as they start and end.
+
+use crate::server::RpcServerRef;
+use binder::{unstable_api::AsNative, SpIBinder};
+use binder_ndk_sys::AIBinder;
+use binder_rpc_unstable_bindgen::ARpcPeerCredentials;
+use foreign_types::ForeignTypeRef;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::raw::c_void;
+
+/// Identifies a session of a client with an [`RpcServer`](crate::RpcServer). The IDs of sessions
+/// which have ended aren't reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId(u64);
+
+/// The address a client connected to an [`RpcServer`](crate::RpcServer) from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeerAddress {
+    /// A vsock address, e.g. of a VM.
+    Vsock {
+        /// The CID of the client.
+        cid: u32,
+        /// The port the client connected from.
+        port: u32,
+    },
+    /// An IP address and port.
+    Inet(SocketAddr),
+    /// A Unix domain socket, which usually has no address.
+    Unix,
+    /// The transport has no address, e.g. for bootstrap servers, or it has one of another kind.
+    Unknown,
+}
+
+/// The credentials of a client process connected over a Unix domain socket, as reported by
+/// `SO_PEERCRED` when it connected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// The process ID of the client.
+    pub pid: libc::pid_t,
+    /// The effective user ID of the client.
+    pub uid: libc::uid_t,
+    /// The effective group ID of the client.
+    pub gid: libc::gid_t,
+}
+
+/// What an [`RpcServer`](crate::RpcServer) knows about the client of a new session.
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    address: PeerAddress,
+    credentials: Option<PeerCredentials>,
+}
+
+impl SessionInfo {
+    /// The address the client connected from.
+    pub fn peer_address(&self) -> PeerAddress {
+        self.address
+    }
+
+    /// The credentials of the client, if it connected over a Unix domain socket.
+    ///
+    /// These are the credentials of the process which made the connection that started the
+    /// session, so they should be checked before serving it anything, e.g. with
+    /// [`set_per_session_root_object`](RpcServerRef::set_per_session_root_object).
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.credentials
+    }
+
+    /// # Safety
+    ///
+    /// `address` must be null or point to `address_len` bytes, and `credentials` must be null or
+    /// valid, for the duration of the call.
+    unsafe fn from_raw(
+        address: *const c_void,
+        address_len: usize,
+        credentials: *const ARpcPeerCredentials,
+    ) -> Self {
+        let address = if address.is_null() || address_len == 0 {
+            &[]
+        } else {
+            // SAFETY: Our caller guarantees that `address` points to `address_len` readable bytes.
+            unsafe { std::slice::from_raw_parts(address.cast::<u8>(), address_len) }
+        };
+        // SAFETY: Our caller guarantees that `credentials` is null or valid.
+        let credentials = unsafe { credentials.as_ref() }.map(|credentials| PeerCredentials {
+            pid: credentials.pid,
+            uid: credentials.uid,
+            gid: credentials.gid,
+        });
+        Self { address: PeerAddress::from_raw(address), credentials }
+    }
+}
+
+impl PeerAddress {
+    /// Parses the address of a client, as given by libbinder.
+    fn from_raw(address: &[u8]) -> Self {
+        // SAFETY: Every address starts with its family, and any bytes are a valid family.
+        let Some(family) = (unsafe { read::<libc::sa_family_t>(address) }) else {
+            return Self::Unknown;
+        };
+        match family as libc::c_int {
+            libc::AF_VSOCK => {
+                // SAFETY: Any bytes are a valid `sockaddr_vm`.
+                let Some(addr) = (unsafe { read::<libc::sockaddr_vm>(address) }) else {
+                    return Self::Unknown;
+                };
+                Self::Vsock { cid: addr.svm_cid, port: addr.svm_port }
+            }
+            libc::AF_INET => {
+                // SAFETY: Any bytes are a valid `sockaddr_in`.
+                let Some(addr) = (unsafe { read::<libc::sockaddr_in>(address) }) else {
+                    return Self::Unknown;
+                };
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                Self::Inet(SocketAddrV4::new(ip, u16::from_be(addr.sin_port)).into())
+            }
+            libc::AF_INET6 => {
+                // SAFETY: Any bytes are a valid `sockaddr_in6`.
+                let Some(addr) = (unsafe { read::<libc::sockaddr_in6>(address) }) else {
+                    return Self::Unknown;
+                };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                let port = u16::from_be(addr.sin6_port);
+                let scope_id = addr.sin6_scope_id;
+                Self::Inet(SocketAddrV6::new(ip, port, addr.sin6_flowinfo, scope_id).into())
+            }
+            libc::AF_UNIX => Self::Unix,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Reads a `T` from the start of `bytes`, if there are enough of them.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid `T`.
+unsafe fn read<T: Copy>(bytes: &[u8]) -> Option<T> {
+    if bytes.len() < std::mem::size_of::<T>() {
+        return None;
+    }
+    // SAFETY: There are enough bytes for a `T`, and our caller guarantees that any bytes are a
+    // valid `T`.
+    Some(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+}
+
+/// Callbacks for clients starting and ending sessions with an [`RpcServer`](crate::RpcServer),
+/// e.g. to log them, or to notice that a VM has gone away without waiting for a call to it to
+/// fail.
+///
+/// The callbacks are called on the server's threads, which can't serve calls until they return.
+pub trait SessionListener: Send + Sync + 'static {
+    /// Called when a client starts a new session with the server.
+    fn on_session_started(&self, _session: SessionId, _info: &SessionInfo) {}
+
+    /// Called when a session ends, because the client disconnected or the server shut down.
+    fn on_session_ended(&self, _session: SessionId) {}
+}
+
+/// A function choosing the root object of each session of an RPC server.
+pub trait RootObjectFactory: Fn(&SessionInfo) -> Option<SpIBinder> + Send + Sync + 'static {}
+impl<T> RootObjectFactory for T where
+    T: Fn(&SessionInfo) -> Option<SpIBinder> + Send + Sync + 'static
+{
+}
+
+impl RpcServerRef {
+    /// Serves the root object returned by `factory` to each new session, instead of the service
+    /// the server was created with. Returning `None` serves that service to the session instead,
+    /// so to turn a client away, e.g. based on its
+    /// [`peer_credentials`](SessionInfo::peer_credentials), return a root object which fails its
+    /// calls.
+    ///
+    /// This must be called before the server is started.
+    pub fn set_per_session_root_object<F: RootObjectFactory>(&self, factory: F) {
+        // SAFETY: The callback is given an owned `F` as its `param`, which is only freed by
+        // `delete_param` once it won't be called again.
+        unsafe {
+            binder_rpc_unstable_bindgen::ARpcServer_setPerSessionRootObject(
+                self.as_ptr(),
+                Some(make_root_object::<F>),
+                Box::into_raw(Box::new(factory)).cast(),
+                Some(delete_param::<F>),
+            )
+        }
+    }
+
+    /// Sets the listener told about sessions starting and ending, replacing any set before.
+    ///
+    /// This must be called before the server is started.
+    pub fn set_session_listener<L: SessionListener>(&self, listener: L) {
+        // SAFETY: The callbacks are given an owned `L` as their `param`, which is only freed by
+        // `delete_param` once neither of them will be called again.
+        unsafe {
+            binder_rpc_unstable_bindgen::ARpcServer_setSessionListener(
+                self.as_ptr(),
+                Some(session_started::<L>),
+                Some(session_ended::<L>),
+                Box::into_raw(Box::new(listener)).cast(),
+                Some(delete_param::<L>),
+            )
+        }
+    }
+}
+
+/// # Safety
+///
+/// `param` must be a live `L` given to `ARpcServer_setSessionListener`, and the other arguments
+/// must be as for [`SessionInfo::from_raw`].
+unsafe extern "C" fn session_started<L: SessionListener>(
+    param: *mut c_void,
+    session_id: u64,
+    address: *const c_void,
+    address_len: usize,
+    credentials: *const ARpcPeerCredentials,
+) {
+    // SAFETY: Our caller guarantees that `param` is a live `L`.
+    let listener = unsafe { &*(param as *const L) };
+    // SAFETY: Our caller guarantees that the address and credentials are valid.
+    let info = unsafe { SessionInfo::from_raw(address, address_len, credentials) };
+    listener.on_session_started(SessionId(session_id), &info);
+}
+
+/// # Safety
+///
+/// `param` must be a live `L` given to `ARpcServer_setSessionListener`.
+unsafe extern "C" fn session_ended<L: SessionListener>(param: *mut c_void, session_id: u64) {
+    // SAFETY: Our caller guarantees that `param` is a live `L`.
+    let listener = unsafe { &*(param as *const L) };
+    listener.on_session_ended(SessionId(session_id));
+}
+
+/// # Safety
+///
+/// `param` must be a live `F` given to `ARpcServer_setPerSessionRootObject`, and the other
+/// arguments must be as for [`SessionInfo::from_raw`].
+unsafe extern "C" fn make_root_object<F: RootObjectFactory>(
+    param: *mut c_void,
+    address: *const c_void,
+    address_len: usize,
+    credentials: *const ARpcPeerCredentials,
+) -> *mut AIBinder {
+    // SAFETY: Our caller guarantees that `param` is a live `F`.
+    let factory = unsafe { &*(param as *const F) };
+    // SAFETY: Our caller guarantees that the address and credentials are valid.
+    let info = unsafe { SessionInfo::from_raw(address, address_len, credentials) };
+    factory(&info).map_or(std::ptr::null_mut(), |root| {
+        // The server takes over the strong reference of `root`.
+        std::mem::ManuallyDrop::new(root).as_native_mut().cast()
+    })
+}
+
+/// # Safety
+///
+/// `param` must be a `Box<T>` given to libbinder as the parameter of callbacks, which isn't used
+/// again.
+unsafe extern "C" fn delete_param<T>(param: *mut c_void) {
+    // SAFETY: Our caller gives us back ownership of the parameter.
+    drop(unsafe { Box::from_raw(param as *mut T) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Loopback;
+    use binder::binder_impl::{Binder, BorrowedParcel, TransactionCode};
+    use binder::{declare_binder_interface, BinderFeatures, IBinder, Interface, StatusCode};
+    use std::sync::mpsc::{self, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    pub trait IServerRoot: Interface {}
+
+    declare_binder_interface! {
+        IServerRoot["android.rpcbinder.test.IServerRoot"] {
+            native: BnServerRoot(on_transact),
+            proxy: BpServerRoot,
+        }
+    }
+
+    pub trait ISessionRoot: Interface {}
+
+    declare_binder_interface! {
+        ISessionRoot["android.rpcbinder.test.ISessionRoot"] {
+            native: BnSessionRoot(on_transact),
+            proxy: BpSessionRoot,
+        }
+    }
+
+    fn on_transact<T: ?Sized>(
+        _service: &T,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    struct Service;
+
+    impl Interface for Service {}
+    impl IServerRoot for Service {}
+    impl IServerRoot for BpServerRoot {}
+    impl IServerRoot for Binder<BnServerRoot> {}
+    impl ISessionRoot for Service {}
+    impl ISessionRoot for BpSessionRoot {}
+    impl ISessionRoot for Binder<BnSessionRoot> {}
+
+    fn server_root() -> SpIBinder {
+        BnServerRoot::new_binder(Service, BinderFeatures::default()).as_binder()
+    }
+
+    #[test]
+    fn per_session_root_object_is_served() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let factory_seen = seen.clone();
+        let loopback = Loopback::<dyn ISessionRoot>::new_configured(server_root(), |server, _| {
+            server.set_per_session_root_object(move |info| {
+                factory_seen.lock().unwrap().push(info.clone());
+                Some(BnSessionRoot::new_binder(Service, BinderFeatures::default()).as_binder())
+            });
+        })
+        .expect("The session should be given the per-session root object");
+        assert!(loopback.root().as_binder().is_binder_alive());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].peer_address(), PeerAddress::Unix);
+        let credentials = seen[0].peer_credentials().expect("Unix sockets have peer credentials");
+        // SAFETY: getuid is always safe to call.
+        assert_eq!(credentials.uid, unsafe { libc::getuid() });
+        // SAFETY: getpid is always safe to call.
+        assert_eq!(credentials.pid, unsafe { libc::getpid() });
+    }
+
+    #[test]
+    fn no_per_session_root_object_falls_back_to_server_root() {
+        let loopback = Loopback::<dyn IServerRoot>::new_configured(server_root(), |server, _| {
+            server.set_per_session_root_object(|_| None);
+        })
+        .expect("The session should be given the server's root object");
+        assert!(loopback.root().as_binder().is_binder_alive());
+        assert!(Loopback::<dyn ISessionRoot>::new_configured(server_root(), |server, _| {
+            server.set_per_session_root_object(|_| None);
+        })
+        .is_err());
+    }
+
+    #[derive(Debug)]
+    enum Event {
+        Started(SessionId, PeerAddress),
+        Ended(SessionId),
+    }
+
+    struct Recorder(Mutex<Sender<Event>>);
+
+    impl SessionListener for Recorder {
+        fn on_session_started(&self, session: SessionId, info: &SessionInfo) {
+            self.0.lock().unwrap().send(Event::Started(session, info.peer_address())).unwrap();
+        }
+
+        fn on_session_ended(&self, session: SessionId) {
+            self.0.lock().unwrap().send(Event::Ended(session)).unwrap();
+        }
+    }
+
+    #[test]
+    fn session_listener_is_told_about_sessions_starting_and_ending() {
+        let (events, received) = mpsc::channel();
+        let next = || received.recv_timeout(Duration::from_secs(10)).expect("Expected an event");
+        let loopback = Loopback::<dyn IServerRoot>::new_configured(server_root(), |server, _| {
+            server.set_session_listener(Recorder(Mutex::new(events)));
+        })
+        .unwrap();
+        let Event::Started(started, address) = next() else {
+            panic!("The session should have started first");
+        };
+        assert_eq!(address, PeerAddress::Unix);
+        assert!(loopback.root().as_binder().is_binder_alive());
+
+        // Dropping the client's last binder ends its session, and the server notices the
+        // connection closing.
+        drop(loopback);
+        let Event::Ended(ended) = next() else {
+            panic!("Only one session should have started");
+        };
+        assert_eq!(ended, started);
+    }
+}
@@ -23,6 +23,9 @@ use tipc::{ConnectResult, Handle, MessageResult, PortCfg, TipcError, UnbufferedS
 pub trait PerSessionCallback: Fn(Uuid) -> Option<SpIBinder> + Send + Sync + 'static {}
 impl<T> PerSessionCallback for T where T: Fn(Uuid) -> Option<SpIBinder> + Send + Sync + 'static {}
 
+/// An RPC Binder server for Trusty, which accepts connections on the tipc ports
+/// it is registered for as an [`UnbufferedService`] with a `tipc::Manager`.
+/// Clients connect with `RpcSession::setup_trusty_client`.
 pub struct RpcServer {
     inner: *mut binder_rpc_server_bindgen::ARpcServerTrusty,
 }
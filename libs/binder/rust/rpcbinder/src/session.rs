@@ -14,13 +14,24 @@
  * limitations under the License.
  */
 
+#[cfg(not(target_os = "trusty"))]
+use crate::tls::TlsConfig;
 use binder::unstable_api::new_spibinder;
 use binder::{FromIBinder, SpIBinder, StatusCode, Strong};
 use foreign_types::{foreign_type, ForeignType, ForeignTypeRef};
-use std::os::fd::RawFd;
+use std::os::fd::{IntoRawFd, OwnedFd, RawFd};
 use std::os::raw::{c_int, c_void};
 
+#[cfg(not(target_os = "trusty"))]
+mod listener;
+mod reconnecting;
+
+#[cfg(not(target_os = "trusty"))]
+pub use listener::ClientSessionListener;
+pub use reconnecting::{ReconnectingObject, ReconnectingSession};
+
 pub use binder_rpc_unstable_bindgen::ARpcSession_FileDescriptorTransportMode as FileDescriptorTransportMode;
+pub use binder_rpc_unstable_bindgen::ARpcSession_IntegrityMode as IntegrityMode;
 
 foreign_type! {
     type CType = binder_rpc_unstable_bindgen::ARpcSession;
@@ -44,6 +55,17 @@ impl RpcSession {
         // SAFETY: Takes ownership of the returned handle, which has correct refcount.
         unsafe { RpcSession::from_ptr(binder_rpc_unstable_bindgen::ARpcSession_new()) }
     }
+
+    /// Allocates a new RpcSession object whose connections are encrypted with TLS, and which
+    /// checks the server as configured by `config`. The server must be set up with TLS too.
+    #[cfg(not(target_os = "trusty"))]
+    pub fn new_tls(config: &TlsConfig) -> RpcSession {
+        // SAFETY: Takes ownership of the returned handle, which has correct refcount. The session
+        // doesn't keep the config pointer.
+        unsafe {
+            RpcSession::from_ptr(binder_rpc_unstable_bindgen::ARpcSession_newTls(config.as_ptr()))
+        }
+    }
 }
 
 impl Default for RpcSession {
@@ -64,7 +86,28 @@ impl RpcSessionRef {
         };
     }
 
-    /// Sets the maximum number of incoming threads.
+    /// Sets the integrity mode for this session.
+    ///
+    /// With [`IntegrityMode::Crc32`], every command carries a checksum of its
+    /// body, and the session is terminated if one arrives corrupted. This is
+    /// for transports where corruption is plausible and would otherwise go
+    /// unnoticed. Connecting fails if the server doesn't support the mode.
+    pub fn set_integrity_mode(&self, mode: IntegrityMode) {
+        // SAFETY: Only passes the 'self' pointer as an opaque handle.
+        unsafe { binder_rpc_unstable_bindgen::ARpcSession_setIntegrityMode(self.as_ptr(), mode) };
+    }
+
+    /// Sets the maximum number of incoming threads, which serve calls the server makes back to
+    /// objects of this process, such as callbacks.
+    ///
+    /// There are none by default, so a client which passes binders to the server must set this,
+    /// or the server's calls to them fail. If a callback can call back into the server while the
+    /// server is waiting for another callback, more than one thread is needed to avoid a
+    /// deadlock. Must be called before the session is set up.
+    ///
+    /// The incoming threads are also what notices the server going away: with at least one,
+    /// [`DeathRecipient`](binder::DeathRecipient)s linked to the binders of the session are
+    /// notified when it does, rather than only the next call to it failing.
     pub fn set_max_incoming_threads(&self, threads: usize) {
         // SAFETY: Only passes the 'self' pointer as an opaque handle.
         unsafe {
@@ -72,7 +115,17 @@ impl RpcSessionRef {
         };
     }
 
-    /// Sets the maximum number of outgoing connections.
+    /// Returns the maximum number of incoming threads.
+    pub fn max_incoming_threads(&self) -> usize {
+        // SAFETY: Only passes the 'self' pointer as an opaque handle.
+        unsafe { binder_rpc_unstable_bindgen::ARpcSession_getMaxIncomingThreads(self.as_ptr()) }
+    }
+
+    /// Sets the maximum number of outgoing connections, which limits how many calls to the server
+    /// can be made at once.
+    ///
+    /// The session makes as many connections as the server has threads, up to this limit, which
+    /// is 10 by default. Must be called before the session is set up.
     pub fn set_max_outgoing_connections(&self, connections: usize) {
         // SAFETY: Only passes the 'self' pointer as an opaque handle.
         unsafe {
@@ -83,6 +136,28 @@ impl RpcSessionRef {
         };
     }
 
+    /// Returns the maximum number of outgoing connections.
+    pub fn max_outgoing_connections(&self) -> usize {
+        // SAFETY: Only passes the 'self' pointer as an opaque handle.
+        unsafe { binder_rpc_unstable_bindgen::ARpcSession_getMaxOutgoingConnections(self.as_ptr()) }
+    }
+
+    /// Sets the RPC protocol version to use, instead of the newest one supported by both the
+    /// client and the server. This is usually only useful for debugging and testing.
+    ///
+    /// Must be called before the session is set up. Fails with `BAD_VALUE` if the version isn't
+    /// supported, or the session is already set up.
+    pub fn set_protocol_version(&self, version: u32) -> Result<(), StatusCode> {
+        // SAFETY: Only passes the 'self' pointer as an opaque handle.
+        if unsafe {
+            binder_rpc_unstable_bindgen::ARpcSession_setProtocolVersion(self.as_ptr(), version)
+        } {
+            Ok(())
+        } else {
+            Err(StatusCode::BAD_VALUE)
+        }
+    }
+
     /// Connects to an RPC Binder server over vsock for a particular interface.
     #[cfg(not(target_os = "trusty"))]
     pub fn setup_vsock_client<T: FromIBinder + ?Sized>(
@@ -175,14 +250,20 @@ impl RpcSessionRef {
         Self::get_interface(service)
     }
 
+    /// Connects to an RPC Binder server over tipc, from a Trusty app to the
+    /// service another app serves on `port` with an [`RpcServer`](crate::RpcServer).
+    ///
+    /// Fails with `BAD_VALUE` if one of the session's connections can't be
+    /// made, e.g. because `port` doesn't exist or doesn't accept this app.
     #[cfg(target_os = "trusty")]
     pub fn setup_trusty_client<T: FromIBinder + ?Sized>(
         &self,
         port: &std::ffi::CStr,
     ) -> Result<Strong<T>, StatusCode> {
         self.setup_preconnected_client(|| {
-            let h = tipc::Handle::connect(port)
-                .expect("Failed to connect to service port {SERVICE_PORT}");
+            // Connecting may fail for some of the connections after the first one too, so this
+            // reports the failure to libbinder rather than panicking.
+            let h = tipc::Handle::connect(port).ok()?;
 
             // Do not close the handle at the end of the scope
             let fd = h.as_raw_fd();
@@ -193,6 +274,12 @@ impl RpcSessionRef {
 
     /// Connects to an RPC Binder server, using the given callback to get (and
     /// take ownership of) file descriptors already connected to it.
+    ///
+    /// The callback is called once for each connection the session makes:
+    /// one for each thread of the server, up to
+    /// [`max_outgoing_connections`](Self::max_outgoing_connections), and one
+    /// for each of its [incoming threads](Self::set_max_incoming_threads).
+    /// Setting up fails with `BAD_VALUE` if it returns `None`.
     pub fn setup_preconnected_client<T: FromIBinder + ?Sized>(
         &self,
         mut request_fd: impl FnMut() -> Option<RawFd>,
@@ -214,6 +301,20 @@ impl RpcSessionRef {
         Self::get_interface(service)
     }
 
+    /// Connects to an RPC Binder server over file descriptors already
+    /// connected to it, e.g. ones the server handed out over another binder.
+    ///
+    /// The session takes descriptors from `fds` as it needs them, as for
+    /// [`setup_preconnected_client`](Self::setup_preconnected_client), and
+    /// closes the ones it doesn't need.
+    pub fn setup_preconnected_fds<T: FromIBinder + ?Sized>(
+        &self,
+        fds: impl IntoIterator<Item = OwnedFd>,
+    ) -> Result<Strong<T>, StatusCode> {
+        let mut fds = fds.into_iter();
+        self.setup_preconnected_client(|| fds.next().map(IntoRawFd::into_raw_fd))
+    }
+
     fn get_interface<T: FromIBinder + ?Sized>(
         service: Option<SpIBinder>,
     ) -> Result<Strong<T>, StatusCode> {
@@ -0,0 +1,210 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Callbacks for a client session of an RPC server, as it connects and ends.
+
+use crate::session::RpcSessionRef;
+use foreign_types::ForeignTypeRef;
+use std::os::raw::c_void;
+
+/// Callbacks for an [`RpcSession`](crate::RpcSession) connecting to a server and ending, e.g. to
+/// log it, update metrics, or look for the server again once it has gone away, rather than only
+/// learning about it from a failed call.
+///
+/// Once [`on_connected`](Self::on_connected) has been called, exactly one of
+/// [`on_disconnected`](Self::on_disconnected) and [`on_shutdown`](Self::on_shutdown) follows.
+/// Without incoming threads (see [`RpcSessionRef::set_max_incoming_threads`]), a session only
+/// notices the server going away when a call to it fails.
+///
+/// The callbacks may be called on the session's threads, or on the thread setting up the session
+/// or making the call that ended it, so they must not block for long or call into the session.
+pub trait ClientSessionListener: Send + Sync + 'static {
+    /// Called once the session has been set up as a client.
+    fn on_connected(&self) {}
+
+    /// Called when the session ends because its connection to the server failed, e.g. because the
+    /// server went away or sent bad data.
+    fn on_disconnected(&self) {}
+
+    /// Called when the session ends because it holds no binders of the server anymore.
+    fn on_shutdown(&self) {}
+}
+
+impl RpcSessionRef {
+    /// Sets the listener told about the session connecting and ending, replacing any set before.
+    ///
+    /// This must be called before the session is set up.
+    pub fn set_session_listener<L: ClientSessionListener>(&self, listener: L) {
+        // SAFETY: The callbacks are given an owned `L` as their `param`, which is only freed by
+        // `delete_listener` once none of them will be called again.
+        unsafe {
+            binder_rpc_unstable_bindgen::ARpcSession_setSessionListener(
+                self.as_ptr(),
+                Some(connected::<L>),
+                Some(disconnected::<L>),
+                Some(shutdown::<L>),
+                Box::into_raw(Box::new(listener)).cast(),
+                Some(delete_listener::<L>),
+            )
+        }
+    }
+}
+
+/// # Safety
+///
+/// `param` must be a live `L` given to `ARpcSession_setSessionListener`.
+unsafe extern "C" fn connected<L: ClientSessionListener>(param: *mut c_void) {
+    // SAFETY: Our caller guarantees that `param` is a live `L`.
+    let listener = unsafe { &*(param as *const L) };
+    listener.on_connected();
+}
+
+/// # Safety
+///
+/// `param` must be a live `L` given to `ARpcSession_setSessionListener`.
+unsafe extern "C" fn disconnected<L: ClientSessionListener>(param: *mut c_void) {
+    // SAFETY: Our caller guarantees that `param` is a live `L`.
+    let listener = unsafe { &*(param as *const L) };
+    listener.on_disconnected();
+}
+
+/// # Safety
+///
+/// `param` must be a live `L` given to `ARpcSession_setSessionListener`.
+unsafe extern "C" fn shutdown<L: ClientSessionListener>(param: *mut c_void) {
+    // SAFETY: Our caller guarantees that `param` is a live `L`.
+    let listener = unsafe { &*(param as *const L) };
+    listener.on_shutdown();
+}
+
+/// # Safety
+///
+/// `param` must be the `L` given to `ARpcSession_setSessionListener`, which isn't used again.
+unsafe extern "C" fn delete_listener<L: ClientSessionListener>(param: *mut c_void) {
+    // SAFETY: Our caller gives us back ownership of the listener.
+    drop(unsafe { Box::from_raw(param as *mut L) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::RpcServer;
+    use crate::session::RpcSession;
+    use binder::binder_impl::{Binder, BorrowedParcel, TransactionCode};
+    use binder::{
+        declare_binder_interface, BinderFeatures, IBinder, Interface, StatusCode, Strong,
+    };
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    pub trait IListenedRoot: Interface {}
+
+    declare_binder_interface! {
+        IListenedRoot["android.rpcbinder.test.IListenedRoot"] {
+            native: BnListenedRoot(on_transact),
+            proxy: BpListenedRoot,
+        }
+    }
+
+    fn on_transact<T: ?Sized>(
+        _service: &T,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    struct Service;
+
+    impl Interface for Service {}
+    impl IListenedRoot for Service {}
+    impl IListenedRoot for BpListenedRoot {}
+    impl IListenedRoot for Binder<BnListenedRoot> {}
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        Connected,
+        Disconnected,
+        Shutdown,
+    }
+
+    struct Recorder(Mutex<Sender<Event>>);
+
+    impl ClientSessionListener for Recorder {
+        fn on_connected(&self) {
+            self.0.lock().unwrap().send(Event::Connected).unwrap();
+        }
+
+        fn on_disconnected(&self) {
+            self.0.lock().unwrap().send(Event::Disconnected).unwrap();
+        }
+
+        fn on_shutdown(&self) {
+            self.0.lock().unwrap().send(Event::Shutdown).unwrap();
+        }
+    }
+
+    /// A server, and a session with a listener connected to it.
+    fn connect() -> (RpcServer, RpcSession, Strong<dyn IListenedRoot>, Receiver<Event>) {
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let service = BnListenedRoot::new_binder(Service, BinderFeatures::default());
+        let server =
+            RpcServer::new_unix_domain_bootstrap(service.as_binder(), server_end.into()).unwrap();
+        server.start();
+        let (events, received) = mpsc::channel();
+        let session = RpcSession::new();
+        session.set_session_listener(Recorder(Mutex::new(events)));
+        let root = session.setup_unix_domain_bootstrap_client(client_end.as_fd()).unwrap();
+        (server, session, root, received)
+    }
+
+    fn next(events: &Receiver<Event>) -> Event {
+        events.recv_timeout(Duration::from_secs(10)).expect("The listener should be called")
+    }
+
+    #[test]
+    fn session_reports_connecting() {
+        let (_server, _session, _root, events) = connect();
+        assert_eq!(next(&events), Event::Connected);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn session_reports_disconnection_when_server_goes_away() {
+        let (server, _session, root, events) = connect();
+        assert_eq!(next(&events), Event::Connected);
+
+        server.shutdown().unwrap();
+        assert!(root.as_binder().ping_binder().is_err());
+        assert_eq!(next(&events), Event::Disconnected);
+        assert!(root.as_binder().ping_binder().is_err());
+        assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn session_reports_shutdown_when_last_binder_is_dropped() {
+        let (_server, _session, root, events) = connect();
+        assert_eq!(next(&events), Event::Connected);
+
+        drop(root);
+        assert_eq!(next(&events), Event::Shutdown);
+        assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
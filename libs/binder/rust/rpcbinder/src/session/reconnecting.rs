@@ -0,0 +1,499 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reconnecting to an RPC Binder server after the transport drops.
+//!
+//! An RPC Binder server only holds the objects it has handed to a session for
+//! as long as that session lasts, so once the transport drops, everything
+//! fetched through it is gone. The old session can't be resumed: a new one
+//! has to be set up, which the server sees as a new client, and which starts
+//! again from the root object. [`ReconnectingSession`] does this on demand,
+//! and [`ReconnectingObject`] keeps objects fetched from the root for as long
+//! as the server still holds them, fetching them again only once they are
+//! gone:
+//!
+//! ```ignore
+//! let session = ReconnectingSession::new(|session| {
+//!     session.set_max_outgoing_connections(2);
+//!     session.setup_vsock_client::<dyn IVmService>(cid, port)
+//! });
+//! let config = session.object(|vm| vm.getConfigService());
+//!
+//! // Reconnects and fetches the config service again if the link dropped
+//! // since the last call. A read is safe to repeat, so it is retried if the
+//! // link drops during the call, too.
+//! let value = config.call_idempotent(|config| config.get("key"))?;
+//! ```
+//!
+//! A call which fails because the transport dropped may or may not have been
+//! handled by the server before it did, so calls are only retried on a new
+//! session if the caller says they are safe to repeat, with `call_idempotent`.
+//! Otherwise the error is returned, and only the next call reconnects.
+
+use crate::session::{RpcSession, RpcSessionRef};
+use binder::{ExceptionCode, FromIBinder, IBinder, Interface, Status, StatusCode, Strong};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+type Connect<T> = dyn Fn(&RpcSessionRef) -> Result<Strong<T>, StatusCode> + Send + Sync;
+type Fetch<T, U> = dyn Fn(&Strong<T>) -> binder::Result<Strong<U>> + Send + Sync;
+
+/// An RPC Binder session to a server's root object of type `T`, which is
+/// replaced by a new session whenever it is needed after the transport has
+/// dropped.
+///
+/// Clones share the same session.
+pub struct ReconnectingSession<T: FromIBinder + ?Sized> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T: FromIBinder + ?Sized> {
+    connect: Box<Connect<T>>,
+    state: Mutex<State<T>>,
+    // Signalled when a thread has finished connecting, successfully or not.
+    connected: Condvar,
+}
+
+struct State<T: FromIBinder + ?Sized> {
+    connection: Option<Connection<T>>,
+    // How many sessions have been set up so far.
+    generation: u64,
+    // Whether a thread is setting up a session, without holding the lock.
+    connecting: bool,
+}
+
+struct Connection<T: FromIBinder + ?Sized> {
+    // Kept for as long as the root object, which it serves.
+    _session: RpcSession,
+    root: Strong<T>,
+}
+
+impl<T: FromIBinder + ?Sized> Clone for ReconnectingSession<T> {
+    fn clone(&self) -> Self {
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T: FromIBinder + ?Sized> fmt::Debug for ReconnectingSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("ReconnectingSession")
+            .field("connected", &state.connection.is_some())
+            .field("generation", &state.generation)
+            .finish()
+    }
+}
+
+impl<T: FromIBinder + ?Sized> ReconnectingSession<T> {
+    /// Creates a session which sets itself up with `connect`, which should
+    /// configure the fresh [`RpcSession`] it is given and connect it with one
+    /// of its `setup_*_client` methods.
+    ///
+    /// Nothing is connected until the root object is first needed. Only one
+    /// thread calls `connect` at a time, and others which need the root
+    /// object meanwhile wait for it.
+    pub fn new(
+        connect: impl Fn(&RpcSessionRef) -> Result<Strong<T>, StatusCode> + Send + Sync + 'static,
+    ) -> Self {
+        let state = State { connection: None, generation: 0, connecting: false };
+        Self {
+            shared: Arc::new(Shared {
+                connect: Box::new(connect),
+                state: Mutex::new(state),
+                connected: Condvar::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Returns the root object, setting up a new session first if the
+    /// transport has dropped.
+    pub fn root(&self) -> Result<Strong<T>, StatusCode> {
+        self.current().map(|(_, root)| root)
+    }
+
+    /// Checks that the server can still be reached by pinging the root
+    /// object, and sets up a new session if it can't.
+    ///
+    /// A dropped transport is normally only noticed once a call fails, so
+    /// this is for clients which would rather find out before then, e.g.
+    /// after the device resumes.
+    pub fn revalidate(&self) -> Result<(), StatusCode> {
+        let (generation, root) = self.current()?;
+        if root.as_binder().ping_binder().is_err() {
+            self.disconnect(generation);
+            self.current()?;
+        }
+        Ok(())
+    }
+
+    /// How many times a session has been set up, which changes whenever the
+    /// objects fetched from the old root are no longer valid.
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Calls `f` with the root object.
+    ///
+    /// If that fails because the transport dropped, the error is returned
+    /// and the next call sets up a new session. `f` isn't called again, as
+    /// the server may have handled it before the transport dropped; see
+    /// [`call_idempotent`](Self::call_idempotent).
+    pub fn call<R>(&self, f: impl FnOnce(&Strong<T>) -> binder::Result<R>) -> binder::Result<R> {
+        let (generation, root) = self.current()?;
+        let result = f(&root);
+        if result.as_ref().is_err_and(is_dead) {
+            self.disconnect(generation);
+        }
+        result
+    }
+
+    /// Like [`call`](Self::call), but if `f` fails because the transport
+    /// dropped, sets up a new session and calls it once more.
+    ///
+    /// Only use this for calls which are safe to repeat, as the server may
+    /// have handled the first one before the transport dropped.
+    pub fn call_idempotent<R>(
+        &self,
+        f: impl Fn(&Strong<T>) -> binder::Result<R>,
+    ) -> binder::Result<R> {
+        match self.call(&f) {
+            Err(status) if is_dead(&status) => f(&self.root()?),
+            result => result,
+        }
+    }
+
+    /// Returns a handle to an object fetched from the root with `fetch`,
+    /// typically with a getter method of the root interface.
+    pub fn object<U: FromIBinder + ?Sized>(
+        &self,
+        fetch: impl Fn(&Strong<T>) -> binder::Result<Strong<U>> + Send + Sync + 'static,
+    ) -> ReconnectingObject<T, U> {
+        ReconnectingObject {
+            session: self.clone(),
+            fetch: Box::new(fetch),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The root object and the generation of the session it belongs to.
+    fn current(&self) -> Result<(u64, Strong<T>), StatusCode> {
+        let mut state = self.lock();
+        loop {
+            if let Some(connection) = &state.connection {
+                if connection.root.as_binder().is_binder_alive() {
+                    return Ok((state.generation, connection.root.clone()));
+                }
+            }
+            if !state.connecting {
+                break;
+            }
+            state = self.shared.connected.wait(state).unwrap();
+        }
+
+        // Connect without holding the lock, which other callers only need
+        // briefly. Drop the old session first so that its connections are
+        // closed before new ones are made.
+        let old = state.connection.take();
+        state.connecting = true;
+        drop(state);
+        // Declared before `state` below, so that it is dropped after it.
+        let _connecting = Connecting(&self.shared);
+        drop(old);
+        let session = RpcSession::new();
+        let result = (self.shared.connect)(&session);
+
+        let mut state = self.lock();
+        let root = result?;
+        state.generation += 1;
+        state.connection = Some(Connection { _session: session, root: root.clone() });
+        Ok((state.generation, root))
+    }
+
+    /// Forget the connection of the given generation, if it is still the
+    /// current one, so that the next caller sets up a new session.
+    fn disconnect(&self, generation: u64) {
+        let mut state = self.lock();
+        if state.generation == generation {
+            state.connection = None;
+        }
+    }
+}
+
+/// Marks a thread as no longer connecting when dropped, and wakes the threads
+/// waiting for it, even if `connect` panics.
+struct Connecting<'a, T: FromIBinder + ?Sized>(&'a Shared<T>);
+
+impl<T: FromIBinder + ?Sized> Drop for Connecting<'_, T> {
+    fn drop(&mut self) {
+        // The lock isn't held while connecting, so a panic there doesn't
+        // poison it, but don't panic again while unwinding if it is.
+        let mut state = self.0.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.connecting = false;
+        self.0.connected.notify_all();
+    }
+}
+
+/// An object of type `U` fetched from the root object of a
+/// [`ReconnectingSession`], which is fetched again once a new session has
+/// been set up.
+pub struct ReconnectingObject<T: FromIBinder + ?Sized, U: FromIBinder + ?Sized> {
+    session: ReconnectingSession<T>,
+    fetch: Box<Fetch<T, U>>,
+    cached: Mutex<Option<(u64, Strong<U>)>>,
+}
+
+impl<T: FromIBinder + ?Sized, U: FromIBinder + ?Sized> fmt::Debug for ReconnectingObject<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cached = self.cached.lock().unwrap();
+        f.debug_struct("ReconnectingObject")
+            .field("session", &self.session)
+            .field("generation", &cached.as_ref().map(|(generation, _)| *generation))
+            .finish()
+    }
+}
+
+impl<T: FromIBinder + ?Sized, U: FromIBinder + ?Sized> ReconnectingObject<T, U> {
+    /// Returns the object, reusing the one fetched before if the server
+    /// still holds it.
+    pub fn get(&self) -> binder::Result<Strong<U>> {
+        let (generation, root) = self.session.current()?;
+        if let Some((cached_generation, object)) = &*self.cached.lock().unwrap() {
+            if *cached_generation == generation && object.as_binder().is_binder_alive() {
+                return Ok(object.clone());
+            }
+        }
+        // Fetch without holding the lock, as it's a call to the server.
+        let object = (self.fetch)(&root)?;
+        *self.cached.lock().unwrap() = Some((generation, object.clone()));
+        Ok(object)
+    }
+
+    /// Calls `f` with the object.
+    ///
+    /// If that fails because the transport dropped, the error is returned,
+    /// and the next call sets up a new session and fetches the object again.
+    /// `f` isn't called again; see [`call_idempotent`](Self::call_idempotent).
+    pub fn call<R>(&self, f: impl FnOnce(&Strong<U>) -> binder::Result<R>) -> binder::Result<R> {
+        let object = self.get()?;
+        let result = f(&object);
+        if result.as_ref().is_err_and(is_dead) {
+            if let Some((generation, _)) = self.cached.lock().unwrap().take() {
+                self.session.disconnect(generation);
+            }
+        }
+        result
+    }
+
+    /// Like [`call`](Self::call), but if `f` fails because the transport
+    /// dropped, sets up a new session, fetches the object again and calls it
+    /// once more.
+    ///
+    /// Only use this for calls which are safe to repeat, as the server may
+    /// have handled the first one before the transport dropped.
+    pub fn call_idempotent<R>(
+        &self,
+        f: impl Fn(&Strong<U>) -> binder::Result<R>,
+    ) -> binder::Result<R> {
+        match self.call(&f) {
+            Err(status) if is_dead(&status) => f(&self.get()?),
+            result => result,
+        }
+    }
+}
+
+/// Whether a call failed because the session is gone.
+fn is_dead(status: &Status) -> bool {
+    status.exception_code() == ExceptionCode::TRANSACTION_FAILED
+        && status.transaction_error() == StatusCode::DEAD_OBJECT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::RpcServer;
+    use binder::binder_impl::{Binder, BorrowedParcel, TransactionCode};
+    use binder::{declare_binder_interface, BinderFeatures};
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    pub trait IRoot: Interface {}
+
+    declare_binder_interface! {
+        IRoot["android.rpcbinder.test.IReconnectingRoot"] {
+            native: BnRoot(on_transact),
+            proxy: BpRoot,
+        }
+    }
+
+    fn on_transact<T: ?Sized>(
+        _service: &T,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    struct Service;
+
+    impl Interface for Service {}
+    impl IRoot for Service {}
+    impl IRoot for BpRoot {}
+    impl IRoot for Binder<BnRoot> {}
+
+    /// A session which connects to a new server each time, counting how many
+    /// times it has connected.
+    fn counting_session() -> (ReconnectingSession<dyn IRoot>, Arc<AtomicUsize>) {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let servers = Mutex::new(Vec::new());
+        let counter = connects.clone();
+        let session = ReconnectingSession::new(move |session| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let (server_end, client_end) =
+                UnixStream::pair().map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+            let service = BnRoot::new_binder(Service, BinderFeatures::default());
+            let server =
+                RpcServer::new_unix_domain_bootstrap(service.as_binder(), server_end.into())
+                    .map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+            server.start();
+            let root = session.setup_unix_domain_bootstrap_client(client_end.as_fd())?;
+            servers.lock().unwrap().push((server, client_end));
+            Ok(root)
+        });
+        (session, connects)
+    }
+
+    fn dead() -> Status {
+        Status::from(StatusCode::DEAD_OBJECT)
+    }
+
+    #[test]
+    fn connects_lazily_and_once() {
+        let (session, connects) = counting_session();
+        assert_eq!(session.generation(), 0);
+        assert_eq!(connects.load(Ordering::SeqCst), 0);
+
+        session.root().expect("The session should connect");
+        session.root().expect("The session should stay connected");
+        session.revalidate().expect("The server should still be reachable");
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert_eq!(session.generation(), 1);
+    }
+
+    #[test]
+    fn calls_are_not_retried_unless_idempotent() {
+        let (session, connects) = counting_session();
+        let calls = AtomicUsize::new(0);
+        let result = session.call(|_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(dead())
+        });
+        assert!(result.is_err_and(|status| is_dead(&status)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // The next call sets up a new session.
+        session.call(|root| root.as_binder().ping_binder().map_err(Status::from)).unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(session.generation(), 2);
+    }
+
+    #[test]
+    fn idempotent_calls_are_retried_once() {
+        let (session, connects) = counting_session();
+        let calls = AtomicUsize::new(0);
+        let result = session.call_idempotent(|_| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Err(dead()),
+            n => Ok(n),
+        });
+        assert_eq!(result, Ok(1));
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        calls.store(0, Ordering::SeqCst);
+        let result = session.call_idempotent(|_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(dead())
+        });
+        assert!(result.is_err_and(|status| is_dead(&status)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn objects_are_fetched_again_on_a_new_session() {
+        let (session, _) = counting_session();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let object = session.object(move |root| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(root.clone())
+        });
+
+        object.get().unwrap();
+        object.get().unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        assert!(object.call(|_| Err::<(), _>(dead())).is_err());
+        object.get().unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(session.generation(), 2);
+    }
+
+    #[test]
+    fn connecting_does_not_hold_the_lock() {
+        let (connecting, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let session = ReconnectingSession::<dyn IRoot>::new(move |_| {
+            connecting.send(()).unwrap();
+            released.lock().unwrap().recv().unwrap();
+            Err(StatusCode::DEAD_OBJECT)
+        });
+
+        let waiter = session.clone();
+        let connect = thread::spawn(move || waiter.root());
+        started.recv().unwrap();
+        // Neither of these waits for the connection.
+        assert_eq!(session.generation(), 0);
+        assert!(format!("{session:?}").contains("connected: false"));
+
+        release.send(()).unwrap();
+        assert_eq!(connect.join().unwrap().err(), Some(StatusCode::DEAD_OBJECT));
+        assert_eq!(session.generation(), 0);
+    }
+
+    #[test]
+    fn connect_panicking_does_not_block_later_callers() {
+        let panicked = AtomicUsize::new(0);
+        let session = ReconnectingSession::<dyn IRoot>::new(move |_| {
+            if panicked.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("connect failed");
+            }
+            Err(StatusCode::DEAD_OBJECT)
+        });
+
+        let panicking = session.clone();
+        assert!(thread::spawn(move || panicking.root()).join().is_err());
+        // This would wait forever if the panic had left the session connecting.
+        assert_eq!(session.root().err(), Some(StatusCode::DEAD_OBJECT));
+    }
+}
@@ -0,0 +1,396 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! TLS for RPC Binder connections.
+//!
+//! RPC Binder connections are plain text by default, which is fine between
+//! processes on one device or a VM and its host, but not over a network. A
+//! [`TlsConfig`] given to both the server and the session encrypts their
+//! connections, and has each side check the certificate of the other:
+//!
+//! ```ignore
+//! let config = TlsConfig::new(
+//!     || Ok(TlsCredentials { certificate_pem: read(CERT)?, private_key_pem: read(KEY)? }),
+//!     PeerVerification::Pinned(vec![client_certificate_der]),
+//! )?;
+//! let server = RpcServer::new_inet_tls(service, "0.0.0.0", port, &config)?;
+//! ```
+
+use binder_rpc_unstable_bindgen::{ARpcTlsConfig, ARpcTlsCredentials};
+use std::fmt;
+use std::io::Error;
+use std::os::raw::c_void;
+
+type ProvideCredentials = dyn Fn() -> Result<TlsCredentials, Error> + Send + Sync;
+
+/// The certificate and private key a server or session authenticates itself
+/// with.
+#[derive(Clone)]
+pub struct TlsCredentials {
+    /// The PEM-encoded X.509 certificate.
+    pub certificate_pem: Vec<u8>,
+    /// The PEM-encoded private key of the certificate, unencrypted.
+    pub private_key_pem: Vec<u8>,
+}
+
+impl fmt::Debug for TlsCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep the private key out of logs.
+        f.debug_struct("TlsCredentials").finish_non_exhaustive()
+    }
+}
+
+/// How the certificate of the other side of a connection is checked.
+pub enum PeerVerification {
+    /// Trust any peer. The connection is encrypted, but anyone can be on the
+    /// other end, so this is only for when the peer is authenticated some
+    /// other way.
+    AcceptAny,
+    /// Trust only peers with one of these DER-encoded certificates.
+    Pinned(Vec<Vec<u8>>),
+    /// Trust the peers whose DER-encoded certificate this returns true for.
+    Custom(Box<dyn Fn(&[u8]) -> bool + Send + Sync>),
+}
+
+impl fmt::Debug for PeerVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AcceptAny => f.write_str("AcceptAny"),
+            Self::Pinned(certificates) => {
+                f.debug_tuple("Pinned").field(&certificates.len()).finish()
+            }
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl PeerVerification {
+    fn verify(&self, certificate: &[u8]) -> bool {
+        match self {
+            Self::AcceptAny => true,
+            Self::Pinned(certificates) => certificates.iter().any(|pinned| pinned == certificate),
+            Self::Custom(verify) => verify(certificate),
+        }
+    }
+}
+
+/// The TLS configuration of RPC Binder servers and sessions, for
+/// [`RpcServer::new_inet_tls`](crate::RpcServer::new_inet_tls) and friends and
+/// [`RpcSession::new_tls`](crate::RpcSession::new_tls).
+///
+/// Servers and sessions keep what they need of it, so it can be dropped once
+/// they have been created.
+pub struct TlsConfig {
+    ptr: *mut ARpcTlsConfig,
+}
+
+/// SAFETY: The callbacks are `Send + Sync`, and the C++ side only reads the
+/// config.
+unsafe impl Send for TlsConfig {}
+/// SAFETY: As above.
+unsafe impl Sync for TlsConfig {}
+
+struct Callbacks {
+    credentials: Box<ProvideCredentials>,
+    verification: PeerVerification,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+impl TlsConfig {
+    /// Creates a TLS configuration which authenticates with the credentials
+    /// returned by `credentials`, and checks peers with `verification`.
+    ///
+    /// `credentials` is called whenever a server or session is created with
+    /// this configuration, so it can pick up rotated certificates.
+    ///
+    /// Fails if libbinder can't create the configuration.
+    pub fn new(
+        credentials: impl Fn() -> Result<TlsCredentials, Error> + Send + Sync + 'static,
+        verification: PeerVerification,
+    ) -> Result<Self, Error> {
+        let callbacks = Box::new(Callbacks { credentials: Box::new(credentials), verification });
+        // SAFETY: The callbacks are given an owned `Callbacks` as their `param`, which is only
+        // freed by `delete_callbacks` once neither of them will be called again, including right
+        // away if this fails.
+        let ptr = unsafe {
+            binder_rpc_unstable_bindgen::ARpcTlsConfig_new(
+                Some(provide_credentials),
+                Some(verify_peer),
+                Box::into_raw(callbacks).cast(),
+                Some(delete_callbacks),
+            )
+        };
+        if ptr.is_null() {
+            log::error!("Failed to create TLS config");
+            return Err(Error::other("ARpcTlsConfig_new failed"));
+        }
+        Ok(Self { ptr })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const ARpcTlsConfig {
+        self.ptr
+    }
+}
+
+impl Drop for TlsConfig {
+    fn drop(&mut self) {
+        // SAFETY: `self.ptr` was returned by `ARpcTlsConfig_new` and is freed only here.
+        unsafe { binder_rpc_unstable_bindgen::ARpcTlsConfig_free(self.ptr) }
+    }
+}
+
+/// # Safety
+///
+/// `param` must be a live `Callbacks` given to `ARpcTlsConfig_new`, and `out` the credentials
+/// being set up by libbinder.
+unsafe extern "C" fn provide_credentials(param: *mut c_void, out: *mut ARpcTlsCredentials) -> bool {
+    // SAFETY: Our caller guarantees that `param` is a live `Callbacks`.
+    let callbacks = unsafe { &*(param as *const Callbacks) };
+    let credentials = match (callbacks.credentials)() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            log::error!("Failed to get TLS credentials: {:?}", e);
+            return false;
+        }
+    };
+    // SAFETY: `out` is valid for the duration of this call, and the certificate and key are only
+    // read within it.
+    unsafe {
+        binder_rpc_unstable_bindgen::ARpcTlsCredentials_set(
+            out,
+            credentials.certificate_pem.as_ptr(),
+            credentials.certificate_pem.len(),
+            credentials.private_key_pem.as_ptr(),
+            credentials.private_key_pem.len(),
+        )
+    }
+}
+
+/// # Safety
+///
+/// `param` must be a live `Callbacks` given to `ARpcTlsConfig_new`, and `certificate` must point
+/// to `len` bytes which are valid for the duration of the call, unless `len` is 0, in which case
+/// it may be null.
+unsafe extern "C" fn verify_peer(param: *mut c_void, certificate: *const u8, len: usize) -> bool {
+    // SAFETY: Our caller guarantees that `param` is a live `Callbacks`.
+    let callbacks = unsafe { &*(param as *const Callbacks) };
+    let certificate = if certificate.is_null() || len == 0 {
+        &[]
+    } else {
+        // SAFETY: Our caller guarantees that `certificate` points to `len` readable bytes.
+        unsafe { std::slice::from_raw_parts(certificate, len) }
+    };
+    callbacks.verification.verify(certificate)
+}
+
+/// # Safety
+///
+/// `param` must be the `Callbacks` given to `ARpcTlsConfig_new`, which isn't used again.
+unsafe extern "C" fn delete_callbacks(param: *mut c_void) {
+    // SAFETY: Our caller gives us back ownership of the `Callbacks`.
+    drop(unsafe { Box::from_raw(param as *mut Callbacks) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RpcServer, RpcSession};
+    use binder::binder_impl::{BorrowedParcel, TransactionCode};
+    use binder::{declare_binder_interface, BinderFeatures, IBinder, Interface, StatusCode};
+    use std::os::fd::IntoRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    const CERTIFICATE: &[u8] = b"\x30\x82\x01\x0a certificate";
+    const OTHER_CERTIFICATE: &[u8] = b"\x30\x82\x01\x0a other certificate";
+
+    #[test]
+    fn accept_any_accepts_any_certificate() {
+        assert!(PeerVerification::AcceptAny.verify(CERTIFICATE));
+        assert!(PeerVerification::AcceptAny.verify(&[]));
+    }
+
+    #[test]
+    fn pinned_accepts_only_pinned_certificates() {
+        let verification =
+            PeerVerification::Pinned(vec![CERTIFICATE.to_vec(), b"another pinned".to_vec()]);
+        assert!(verification.verify(CERTIFICATE));
+        assert!(!verification.verify(OTHER_CERTIFICATE));
+        // Nothing short of the whole certificate matches.
+        assert!(!verification.verify(&CERTIFICATE[..CERTIFICATE.len() - 1]));
+        let mut longer = CERTIFICATE.to_vec();
+        longer.push(0);
+        assert!(!verification.verify(&longer));
+        assert!(!verification.verify(&[]));
+
+        assert!(!PeerVerification::Pinned(vec![]).verify(CERTIFICATE));
+    }
+
+    #[test]
+    fn custom_is_given_the_certificate() {
+        let verification = PeerVerification::Custom(Box::new(|certificate| {
+            certificate.starts_with(b"\x30") && certificate.ends_with(b" certificate")
+        }));
+        assert!(verification.verify(CERTIFICATE));
+        assert!(verification.verify(OTHER_CERTIFICATE));
+        assert!(!verification.verify(b"certificate"));
+    }
+
+    #[test]
+    fn verify_peer_checks_the_certificate_from_libbinder() {
+        let mut callbacks = Callbacks {
+            credentials: Box::new(|| Err(Error::other("unused"))),
+            verification: PeerVerification::Pinned(vec![CERTIFICATE.to_vec()]),
+        };
+        let param = &mut callbacks as *mut Callbacks as *mut c_void;
+        // SAFETY: `param` is a live `Callbacks`, and the certificates are valid for the calls.
+        unsafe {
+            assert!(verify_peer(param, CERTIFICATE.as_ptr(), CERTIFICATE.len()));
+            assert!(!verify_peer(param, OTHER_CERTIFICATE.as_ptr(), OTHER_CERTIFICATE.len()));
+        }
+    }
+
+    #[test]
+    fn verify_peer_handles_an_empty_certificate() {
+        let mut callbacks = Callbacks {
+            credentials: Box::new(|| Err(Error::other("unused"))),
+            verification: PeerVerification::Custom(Box::new(|certificate| certificate.is_empty())),
+        };
+        let param = &mut callbacks as *mut Callbacks as *mut c_void;
+        // SAFETY: `param` is a live `Callbacks`, and a null certificate is allowed with no bytes.
+        unsafe {
+            assert!(verify_peer(param, std::ptr::null(), 0));
+            assert!(verify_peer(param, CERTIFICATE.as_ptr(), 0));
+        }
+    }
+
+    pub trait ITlsTest: Interface {}
+
+    declare_binder_interface! {
+        ITlsTest["android.rpcbinder.test.ITlsTest"] {
+            native: BnTlsTest(on_transact),
+            proxy: BpTlsTest,
+        }
+    }
+
+    fn on_transact(
+        _service: &dyn ITlsTest,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    struct Service;
+
+    impl Interface for Service {}
+    impl ITlsTest for Service {}
+
+    fn credentials(name: &str) -> TlsCredentials {
+        let (certificate_pem, private_key_pem): (&[u8], &[u8]) = match name {
+            "server" => (
+                include_bytes!("../testdata/server.cert.pem"),
+                include_bytes!("../testdata/server.key.pem"),
+            ),
+            "client" => (
+                include_bytes!("../testdata/client.cert.pem"),
+                include_bytes!("../testdata/client.key.pem"),
+            ),
+            _ => (
+                include_bytes!("../testdata/untrusted.cert.pem"),
+                include_bytes!("../testdata/untrusted.key.pem"),
+            ),
+        };
+        TlsCredentials {
+            certificate_pem: certificate_pem.to_vec(),
+            private_key_pem: private_key_pem.to_vec(),
+        }
+    }
+
+    const SERVER_CERTIFICATE_DER: &[u8] = include_bytes!("../testdata/server.cert.der");
+    const CLIENT_CERTIFICATE_DER: &[u8] = include_bytes!("../testdata/client.cert.der");
+
+    fn config(name: &'static str, peer: &[u8]) -> TlsConfig {
+        TlsConfig::new(move || Ok(credentials(name)), PeerVerification::Pinned(vec![peer.to_vec()]))
+            .unwrap()
+    }
+
+    /// A TLS server on a Unix domain socket, which trusts only the client certificate and is
+    /// removed when it is dropped.
+    struct Server {
+        server: RpcServer,
+        path: PathBuf,
+    }
+
+    impl Server {
+        fn start(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            let service = BnTlsTest::new_binder(Service, BinderFeatures::default()).as_binder();
+            let server = RpcServer::new_bound_socket_tls(
+                service,
+                listener.into(),
+                &config("server", CLIENT_CERTIFICATE_DER),
+            )
+            .unwrap();
+            server.start();
+            Self { server, path }
+        }
+
+        /// Connects to the server with a session configured by `config`.
+        fn connect(&self, config: &TlsConfig) -> Result<binder::Strong<dyn ITlsTest>, StatusCode> {
+            RpcSession::new_tls(config).setup_preconnected_client(|| {
+                UnixStream::connect(&self.path).ok().map(IntoRawFd::into_raw_fd)
+            })
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.server.shutdown();
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn tls_session_transacts_with_tls_server() {
+        let server = Server::start("rpcbinder_tls_trusted");
+        let root = server
+            .connect(&config("client", SERVER_CERTIFICATE_DER))
+            .expect("The server should trust the client");
+        assert_eq!(root.as_binder().ping_binder(), Ok(()));
+    }
+
+    #[test]
+    fn tls_server_rejects_untrusted_client() {
+        let server = Server::start("rpcbinder_tls_untrusted_client");
+        assert!(server.connect(&config("untrusted", SERVER_CERTIFICATE_DER)).is_err());
+    }
+
+    #[test]
+    fn tls_session_rejects_untrusted_server() {
+        let server = Server::start("rpcbinder_tls_untrusted_server");
+        // The client expects the server to have its own certificate, which it doesn't.
+        assert!(server.connect(&config("client", CLIENT_CERTIFICATE_DER)).is_err());
+    }
+}
@@ -0,0 +1,836 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A client of RPC Binder servers written in Rust alone, for environments
+//! where libbinder_ndk isn't available.
+//!
+//! This speaks the RPC Binder wire protocol (see `RpcWireFormat.h`) over any
+//! connected stream, such as a Unix domain or vsock socket, without the binder
+//! driver or libbinder:
+//!
+//! ```ignore
+//! let session = WireSession::connect(UnixStream::connect(path)?)?;
+//! let root = session.root()?.ok_or(Error::Status(NAME_NOT_FOUND))?;
+//! let mut data = WireParcel::new();
+//! data.write_interface_token("android.os.IFoo");
+//! data.write_i32(42);
+//! let mut reply = root.transact(FIRST_CALL_TRANSACTION, 0, &data)?;
+//! ```
+//!
+//! It is much more limited than the `RpcSession` of the library built without
+//! the `wire` feature:
+//!
+//! - Each session has a single connection, so calls to it are serialized, and
+//!   the server can't call back into the client. Binders can be received from
+//!   the server, but not sent to it.
+//! - File descriptors can't be sent either way, and there is no TLS.
+//! - Parcels are handled at the level of their encoding, so AIDL interfaces
+//!   have to be called by hand rather than through generated proxies. In
+//!   particular, AIDL replies start with an exception code to check.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// The newest version of the protocol this client speaks.
+const PROTOCOL_VERSION: u32 = 1;
+/// The first version in which replies say how big their parcel is.
+const PROTOCOL_VERSION_EXPLICIT_PARCEL_SIZE: u32 = 1;
+
+const CONNECTION_INIT_OKAY: [u8; 4] = *b"cci\0";
+
+const COMMAND_TRANSACT: u32 = 0;
+const COMMAND_REPLY: u32 = 1;
+const COMMAND_DEC_STRONG: u32 = 2;
+
+/// The address special transactions are sent to, rather than to a binder.
+const SPECIAL_ADDRESS: u64 = 0;
+const SPECIAL_GET_ROOT: u32 = 0;
+
+const TYPE_BINDER_NULL: i32 = 0;
+const TYPE_BINDER: i32 = 1;
+
+const WIRE_HEADER_SIZE: usize = 16;
+const WIRE_TRANSACTION_SIZE: usize = 40;
+const WIRE_REPLY_SIZE: usize = 20;
+const WIRE_REPLY_SIZE_V0: usize = 4;
+
+/// The biggest command body accepted from the server, as libbinder's `kMaxTransactionAllocation`,
+/// so that a server can't make us allocate more than that.
+const MAX_COMMAND_BODY_SIZE: usize = 100 * 1000;
+
+/// `status_t` for success.
+const OK: i32 = 0;
+/// `status_t` for a transaction too big to send.
+const FAILED_TRANSACTION: i32 = i32::MIN + 2;
+
+/// Marks a transaction as one way, so that it doesn't wait for a reply.
+pub const FLAG_ONEWAY: u32 = 0x01;
+
+/// An error talking to an RPC Binder server.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the connection failed. The session can't be used any more.
+    Io(io::Error),
+    /// The server sent something this client doesn't understand. The session can't be used any
+    /// more.
+    Protocol(&'static str),
+    /// The session has already failed with one of the errors above.
+    DeadObject,
+    /// The server failed the transaction with this `status_t`.
+    Status(i32),
+    /// A parcel ended before the value being read.
+    NotEnoughData,
+    /// A parcel had a malformed value where the one being read should be.
+    BadValue,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "RPC connection failed: {}", e),
+            Self::Protocol(reason) => write!(f, "RPC protocol error: {}", reason),
+            Self::DeadObject => f.write_str("RPC session is dead"),
+            Self::Status(status) => write!(f, "RPC transaction failed with status {}", status),
+            Self::NotEnoughData => f.write_str("not enough data in parcel"),
+            Self::BadValue => f.write_str("bad value in parcel"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A specialized [`Result`](std::result::Result) for this module.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A session with an RPC Binder server, over a single connection.
+pub struct WireSession {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl fmt::Debug for WireSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireSession").finish_non_exhaustive()
+    }
+}
+
+impl WireSession {
+    /// Starts a new session over `stream`, which must be connected to an RPC Binder server.
+    pub fn connect(mut stream: impl Read + Write + Send + 'static) -> Result<Self> {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        // The options, file descriptor mode, reserved bytes and session ID size are all zero,
+        // asking for a new session with no file descriptor support.
+        stream.write_all(&header)?;
+        // The connection is outgoing, so the server reads requests from it rather than waiting
+        // for us to serve it.
+        let mut init = [0u8; 8];
+        init[0..4].copy_from_slice(&CONNECTION_INIT_OKAY);
+        stream.write_all(&init)?;
+        stream.flush()?;
+
+        let mut response = [0u8; 8];
+        stream.read_exact(&mut response)?;
+        let version = read_u32_le(&response[0..4]);
+        if version > PROTOCOL_VERSION {
+            return Err(Error::Protocol("server chose a newer protocol version than offered"));
+        }
+
+        let connection = Connection {
+            stream: Box::new(stream),
+            version,
+            dead: false,
+            async_numbers: HashMap::new(),
+            received: HashMap::new(),
+        };
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// The protocol version agreed with the server.
+    pub fn protocol_version(&self) -> u32 {
+        self.connection.lock().unwrap().version
+    }
+
+    /// Gets the root object of the server, or `None` if it has none.
+    pub fn root(&self) -> Result<Option<WireBinder>> {
+        let mut reply =
+            transact(&self.connection, SPECIAL_ADDRESS, SPECIAL_GET_ROOT, 0, &WireParcel::new())?
+                .ok_or(Error::Protocol("no reply to request for root object"))?;
+        reply.read_binder()
+    }
+}
+
+/// A binder in the server, which transactions can be sent to.
+///
+/// The server is told when the last clone of a `WireBinder` is dropped, so that it can let the
+/// binder go.
+#[derive(Clone)]
+pub struct WireBinder {
+    inner: Arc<BinderRef>,
+}
+
+/// A reference to a binder which the server counted as sent to us.
+struct BinderRef {
+    connection: Arc<Mutex<Connection>>,
+    address: u64,
+}
+
+impl fmt::Debug for WireBinder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireBinder").field("address", &self.inner.address).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for WireBinder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner.connection, &other.inner.connection)
+            && self.inner.address == other.inner.address
+    }
+}
+
+impl Eq for WireBinder {}
+
+impl WireBinder {
+    /// Sends a transaction with the given code and data to the binder.
+    ///
+    /// Returns the reply, or `None` if `flags` includes [`FLAG_ONEWAY`].
+    pub fn transact(&self, code: u32, flags: u32, data: &WireParcel) -> Result<Option<WireParcel>> {
+        transact(&self.inner.connection, self.inner.address, code, flags, data)
+    }
+}
+
+impl Drop for BinderRef {
+    fn drop(&mut self) {
+        let mut connection = self.connection.lock().unwrap();
+        match connection.dec_strong(self.address) {
+            // The server has already let go of everything it sent to a dead session.
+            Ok(()) | Err(Error::DeadObject) => {}
+            Err(e) => log::error!("Failed to release RPC binder {}: {}", self.address, e),
+        }
+    }
+}
+
+/// The data of a transaction or reply, as encoded by an RPC Binder `Parcel`.
+///
+/// Values are written to the end of the parcel, and read from a separate position which starts at
+/// the beginning.
+pub struct WireParcel {
+    data: Vec<u8>,
+    position: usize,
+    // The session replies came from, to resolve the binders in them.
+    connection: Option<Arc<Mutex<Connection>>>,
+}
+
+impl fmt::Debug for WireParcel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireParcel")
+            .field("data_size", &self.data.len())
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for WireParcel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WireParcel {
+    /// Creates an empty parcel.
+    pub fn new() -> Self {
+        Self { data: Vec::new(), position: 0, connection: None }
+    }
+
+    /// The encoded contents of the parcel.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Writes an `i32`.
+    pub fn write_i32(&mut self, value: i32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a `u32`.
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes an `i64`.
+    pub fn write_i64(&mut self, value: i64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a `u64`.
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a `bool`, as an `i32`.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_i32(value.into());
+    }
+
+    /// Writes a UTF-16 string, or null.
+    pub fn write_string16(&mut self, value: Option<&str>) {
+        let Some(value) = value else {
+            self.write_i32(-1);
+            return;
+        };
+        let start = self.data.len();
+        self.write_i32(0);
+        let mut len = 0i32;
+        for unit in value.encode_utf16() {
+            self.data.extend_from_slice(&unit.to_le_bytes());
+            len += 1;
+        }
+        self.data.extend_from_slice(&[0, 0]);
+        self.data[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        self.pad();
+    }
+
+    /// Writes the interface token an AIDL service checks at the start of each transaction.
+    pub fn write_interface_token(&mut self, descriptor: &str) {
+        // RPC Binder parcels have no strict mode or work source header before the descriptor.
+        self.write_string16(Some(descriptor));
+    }
+
+    /// Writes a byte array, or null.
+    pub fn write_byte_array(&mut self, value: Option<&[u8]>) {
+        let Some(value) = value else {
+            self.write_i32(-1);
+            return;
+        };
+        // Parcels can't hold more than `i32::MAX` bytes anyway.
+        self.write_i32(value.len().try_into().unwrap_or(i32::MAX));
+        self.data.extend_from_slice(value);
+        self.pad();
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    /// Reads an `i32`.
+    pub fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a `u32`.
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads an `i64`.
+    pub fn read_i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a `u64`.
+    pub fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a `bool`, written as an `i32`.
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_i32()? != 0)
+    }
+
+    /// Reads a UTF-16 string, or null.
+    pub fn read_string16(&mut self) -> Result<Option<String>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        let bytes = self.read_padded(len.checked_add(1).ok_or(Error::BadValue)? * 2)?;
+        let (terminator, units) = bytes.split_last_chunk::<2>().ok_or(Error::BadValue)?;
+        if *terminator != [0, 0] {
+            return Err(Error::BadValue);
+        }
+        let units = units.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+        char::decode_utf16(units)
+            .collect::<std::result::Result<String, _>>()
+            .map(Some)
+            .map_err(|_| Error::BadValue)
+    }
+
+    /// Reads a byte array, or null.
+    pub fn read_byte_array(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        Ok(Some(self.read_padded(len)?.to_vec()))
+    }
+
+    /// Reads a binder, or null. Only replies from a [`WireBinder`] have binders in them.
+    pub fn read_binder(&mut self) -> Result<Option<WireBinder>> {
+        let binder = match self.read_i32()? {
+            TYPE_BINDER_NULL => None,
+            TYPE_BINDER => {
+                let address = self.read_u64()?;
+                let connection = self.connection.clone().ok_or(Error::BadValue)?;
+                // The server counted the binder as sent to us when it wrote it, so we take
+                // over that reference whether or not the stability below is valid.
+                *connection.lock().unwrap().received.entry(address).or_default() += 1;
+                Some(WireBinder { inner: Arc::new(BinderRef { connection, address }) })
+            }
+            _ => return Err(Error::BadValue),
+        };
+        // This client doesn't enforce stability, so the stability level after it is ignored.
+        self.read_i32()?;
+        Ok(binder)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self.data.get(self.position..self.position + N).ok_or(Error::NotEnoughData)?;
+        self.position += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    /// Reads the length before a string or array, which is negative for null.
+    fn read_len(&mut self) -> Result<Option<usize>> {
+        let len = self.read_i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        Ok(Some(len as usize))
+    }
+
+    /// Reads `len` bytes, and skips the padding after them.
+    fn read_padded(&mut self, len: usize) -> Result<&[u8]> {
+        let padded = len.checked_next_multiple_of(4).ok_or(Error::NotEnoughData)?;
+        if padded > self.remaining() {
+            return Err(Error::NotEnoughData);
+        }
+        let start = self.position;
+        self.position += padded;
+        Ok(&self.data[start..start + len])
+    }
+
+    /// Pads the data so the next value is aligned to 4 bytes, as `Parcel` does.
+    fn pad(&mut self) {
+        let padded = self.data.len().next_multiple_of(4);
+        self.data.resize(padded, 0);
+    }
+}
+
+/// The connection to a server, of whatever kind it is.
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    version: u32,
+    dead: bool,
+    /// The number of the next one way transaction to each address, which the server uses to
+    /// process them in order.
+    async_numbers: HashMap<u64, u64>,
+    /// The number of times each address has been received, and not yet given back to the server.
+    received: HashMap<u64, u32>,
+}
+
+impl Connection {
+    /// Runs `f` on the connection, marking it dead if it fails in a way which leaves the stream
+    /// out of step with the server.
+    fn with_stream<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.dead {
+            return Err(Error::DeadObject);
+        }
+        let result = f(self);
+        if let Err(Error::Io(_) | Error::Protocol(_)) = result {
+            self.dead = true;
+        }
+        result
+    }
+
+    fn write_command(&mut self, command: u32, body: &[&[u8]]) -> Result<()> {
+        let body_size: usize = body.iter().map(|part| part.len()).sum();
+        let body_size = u32::try_from(body_size).map_err(|_| Error::Status(FAILED_TRANSACTION))?;
+        let mut header = [0u8; WIRE_HEADER_SIZE];
+        header[0..4].copy_from_slice(&command.to_le_bytes());
+        header[4..8].copy_from_slice(&body_size.to_le_bytes());
+        // The checksum and reserved fields are left as zero.
+        self.stream.write_all(&header)?;
+        for part in body {
+            self.stream.write_all(part)?;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Reads the next command from the server, and its body.
+    fn read_command(&mut self) -> Result<(u32, Vec<u8>)> {
+        let mut header = [0u8; WIRE_HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        let command = read_u32_le(&header[0..4]);
+        let body_size = read_u32_le(&header[4..8]) as usize;
+        if body_size > MAX_COMMAND_BODY_SIZE {
+            return Err(Error::Protocol("command too big to receive"));
+        }
+        let mut body = vec![0; body_size];
+        self.stream.read_exact(&mut body)?;
+        Ok((command, body))
+    }
+
+    fn send_transaction(&mut self, address: u64, code: u32, flags: u32, data: &[u8]) -> Result<()> {
+        let async_number = if flags & FLAG_ONEWAY != 0 {
+            let next = self.async_numbers.entry(address).or_default();
+            let number = *next;
+            *next += 1;
+            number
+        } else {
+            0
+        };
+        let data_size = u32::try_from(data.len()).map_err(|_| Error::Status(FAILED_TRANSACTION))?;
+        let mut transaction = [0u8; WIRE_TRANSACTION_SIZE];
+        transaction[0..8].copy_from_slice(&address.to_le_bytes());
+        transaction[8..12].copy_from_slice(&code.to_le_bytes());
+        transaction[12..16].copy_from_slice(&flags.to_le_bytes());
+        transaction[16..24].copy_from_slice(&async_number.to_le_bytes());
+        transaction[24..28].copy_from_slice(&data_size.to_le_bytes());
+        // There is no object table, as there are no file descriptors or binders to send.
+        self.write_command(COMMAND_TRANSACT, &[&transaction, data])
+    }
+
+    /// Waits for the reply to a transaction, returning its parcel data.
+    fn wait_for_reply(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let (command, body) = self.read_command()?;
+            match command {
+                COMMAND_REPLY => return self.parse_reply(body),
+                // We never send binders to the server, so it has none of ours to release.
+                COMMAND_DEC_STRONG => continue,
+                COMMAND_TRANSACT => {
+                    return Err(Error::Protocol("server called into a client-only session"));
+                }
+                _ => return Err(Error::Protocol("unknown command from server")),
+            }
+        }
+    }
+
+    fn parse_reply(&self, mut body: Vec<u8>) -> Result<Vec<u8>> {
+        let header_size = if self.version >= PROTOCOL_VERSION_EXPLICIT_PARCEL_SIZE {
+            WIRE_REPLY_SIZE
+        } else {
+            WIRE_REPLY_SIZE_V0
+        };
+        if body.len() < header_size {
+            return Err(Error::Protocol("reply too small for its header"));
+        }
+        let status = read_u32_le(&body[0..4]) as i32;
+        if status != OK {
+            return Err(Error::Status(status));
+        }
+        let data_size = if header_size == WIRE_REPLY_SIZE {
+            read_u32_le(&body[4..8]) as usize
+        } else {
+            body.len() - header_size
+        };
+        if data_size > body.len() - header_size {
+            return Err(Error::Protocol("reply too small for its parcel"));
+        }
+        if body.len() - header_size > data_size {
+            // Without file descriptor support, replies shouldn't have an object table.
+            return Err(Error::Protocol("unexpected objects in reply"));
+        }
+        body.drain(..header_size);
+        Ok(body)
+    }
+
+    /// Gives one reference to `address` back to the server.
+    fn dec_strong(&mut self, address: u64) -> Result<()> {
+        let Some(count) = self.received.get_mut(&address) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.received.remove(&address);
+            self.async_numbers.remove(&address);
+        }
+        self.with_stream(|connection| {
+            let mut body = [0u8; 16];
+            body[0..8].copy_from_slice(&address.to_le_bytes());
+            body[8..12].copy_from_slice(&1u32.to_le_bytes());
+            connection.write_command(COMMAND_DEC_STRONG, &[&body])
+        })
+    }
+}
+
+fn transact(
+    connection: &Arc<Mutex<Connection>>,
+    address: u64,
+    code: u32,
+    flags: u32,
+    data: &WireParcel,
+) -> Result<Option<WireParcel>> {
+    let mut guard = connection.lock().unwrap();
+    guard.with_stream(|inner| {
+        inner.send_transaction(address, code, flags, &data.data)?;
+        if flags & FLAG_ONEWAY != 0 {
+            return Ok(None);
+        }
+        let data = inner.wait_for_reply()?;
+        Ok(Some(WireParcel { data, position: 0, connection: Some(connection.clone()) }))
+    })
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A stream which reads what the server is scripted to send, and records what the client
+    /// writes.
+    struct FakeServer {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Builds what the server sends, starting with its response to the connection header.
+    struct Script(Vec<u8>);
+
+    impl Script {
+        fn new(version: u32) -> Self {
+            let mut response = version.to_le_bytes().to_vec();
+            response.extend_from_slice(&[0; 4]);
+            Self(response)
+        }
+
+        fn command(mut self, command: u32, body: &[u8]) -> Self {
+            self.0.extend_from_slice(&command.to_le_bytes());
+            self.0.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            self.0.extend_from_slice(&[0; 8]);
+            self.0.extend_from_slice(body);
+            self
+        }
+
+        /// A reply in the layout of `version`, with the given status and parcel data.
+        fn reply(self, version: u32, status: i32, data: &[u8]) -> Self {
+            let mut body = status.to_le_bytes().to_vec();
+            if version >= PROTOCOL_VERSION_EXPLICIT_PARCEL_SIZE {
+                body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                body.extend_from_slice(&[0; 12]);
+            }
+            body.extend_from_slice(data);
+            self.command(COMMAND_REPLY, &body)
+        }
+
+        fn connect(self) -> (Result<WireSession>, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let stream = FakeServer { input: Cursor::new(self.0), output: output.clone() };
+            (WireSession::connect(stream), output)
+        }
+    }
+
+    /// The length of the connection header and init the client sends when connecting.
+    const HANDSHAKE_SIZE: usize = 24;
+
+    fn parcel_data(values: &[i32]) -> Vec<u8> {
+        let mut parcel = WireParcel::new();
+        for value in values {
+            parcel.write_i32(*value);
+        }
+        parcel.data
+    }
+
+    fn binder_data(address: u64) -> Vec<u8> {
+        let mut parcel = WireParcel::new();
+        parcel.write_i32(TYPE_BINDER);
+        parcel.write_u64(address);
+        // The stability, which is ignored.
+        parcel.write_i32(0);
+        parcel.data
+    }
+
+    #[test]
+    fn handshake() {
+        let (session, output) = Script::new(0).connect();
+        assert_eq!(session.unwrap().protocol_version(), 0);
+
+        let output = output.lock().unwrap();
+        assert_eq!(output.len(), HANDSHAKE_SIZE);
+        assert_eq!(read_u32_le(&output[0..4]), PROTOCOL_VERSION);
+        assert_eq!(output[4..16], [0; 12]);
+        assert_eq!(output[16..20], CONNECTION_INIT_OKAY);
+        assert_eq!(output[20..24], [0; 4]);
+    }
+
+    #[test]
+    fn handshake_rejects_newer_version() {
+        let (session, _) = Script::new(PROTOCOL_VERSION + 1).connect();
+        assert!(matches!(session, Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn handshake_fails_on_short_response() {
+        let (session, _) = Script(vec![1, 0]).connect();
+        assert!(matches!(session, Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn transact_and_reply() {
+        for version in [0, PROTOCOL_VERSION] {
+            let (session, output) = Script::new(version)
+                .reply(version, OK, &binder_data(0x1234))
+                .reply(version, OK, &parcel_data(&[42, 7]))
+                .connect();
+            let session = session.unwrap();
+            let root = session.root().unwrap().expect("The server should have a root object");
+
+            let mut data = WireParcel::new();
+            data.write_i32(5);
+            let mut reply = root.transact(3, 0, &data).unwrap().unwrap();
+            assert_eq!(reply.read_i32().unwrap(), 42);
+            assert_eq!(reply.read_i32().unwrap(), 7);
+            assert_eq!(reply.remaining(), 0);
+
+            // The transaction is the last command sent, after the request for the root object.
+            let output = output.lock().unwrap();
+            let sent = &output[HANDSHAKE_SIZE + WIRE_HEADER_SIZE + WIRE_TRANSACTION_SIZE..];
+            assert_eq!(read_u32_le(&sent[0..4]), COMMAND_TRANSACT);
+            assert_eq!(read_u32_le(&sent[4..8]) as usize, WIRE_TRANSACTION_SIZE + 4);
+            let transaction = &sent[WIRE_HEADER_SIZE..];
+            assert_eq!(transaction[0..8], 0x1234u64.to_le_bytes());
+            assert_eq!(read_u32_le(&transaction[8..12]), 3);
+            assert_eq!(read_u32_le(&transaction[12..16]), 0);
+            assert_eq!(read_u32_le(&transaction[24..28]), 4);
+            assert_eq!(transaction[WIRE_TRANSACTION_SIZE..], 5i32.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn failed_status_is_returned() {
+        let (session, _) = Script::new(PROTOCOL_VERSION)
+            .reply(PROTOCOL_VERSION, FAILED_TRANSACTION, &[])
+            .reply(PROTOCOL_VERSION, OK, &binder_data(1))
+            .connect();
+        let session = session.unwrap();
+        assert!(matches!(session.root(), Err(Error::Status(FAILED_TRANSACTION))));
+        // The session is still usable after the server fails a transaction.
+        assert!(session.root().unwrap().is_some());
+    }
+
+    #[test]
+    fn dropping_the_last_clone_releases_the_binder() {
+        let (session, output) =
+            Script::new(PROTOCOL_VERSION).reply(PROTOCOL_VERSION, OK, &binder_data(0x42)).connect();
+        let root = session.unwrap().root().unwrap().unwrap();
+        let clone = root.clone();
+        drop(root);
+        let sent = output.lock().unwrap().len();
+        drop(clone);
+
+        let output = output.lock().unwrap();
+        let command = &output[sent..];
+        assert_eq!(command.len(), WIRE_HEADER_SIZE + 16);
+        assert_eq!(read_u32_le(&command[0..4]), COMMAND_DEC_STRONG);
+        assert_eq!(read_u32_le(&command[4..8]), 16);
+        assert_eq!(command[16..24], 0x42u64.to_le_bytes());
+        assert_eq!(read_u32_le(&command[24..28]), 1);
+    }
+
+    #[test]
+    fn reply_too_small_for_its_header_is_rejected() {
+        let (session, _) = Script::new(PROTOCOL_VERSION).command(COMMAND_REPLY, &[0; 4]).connect();
+        let session = session.unwrap();
+        assert!(matches!(session.root(), Err(Error::Protocol(_))));
+        // The stream is out of step with the server, so the session can't be used again.
+        assert!(matches!(session.root(), Err(Error::DeadObject)));
+    }
+
+    #[test]
+    fn reply_too_small_for_its_parcel_is_rejected() {
+        let mut body = OK.to_le_bytes().to_vec();
+        body.extend_from_slice(&8u32.to_le_bytes());
+        body.extend_from_slice(&[0; 12]);
+        body.extend_from_slice(&[0; 4]);
+        let (session, _) = Script::new(PROTOCOL_VERSION).command(COMMAND_REPLY, &body).connect();
+        assert!(matches!(session.unwrap().root(), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn short_command_header_is_rejected() {
+        let mut script = Script::new(PROTOCOL_VERSION);
+        script.0.extend_from_slice(&COMMAND_REPLY.to_le_bytes());
+        let (session, _) = script.connect();
+        let session = session.unwrap();
+        assert!(matches!(session.root(), Err(Error::Io(_))));
+        assert!(matches!(session.root(), Err(Error::DeadObject)));
+    }
+
+    #[test]
+    fn oversized_command_is_rejected_before_reading_its_body() {
+        let mut script = Script::new(PROTOCOL_VERSION);
+        script.0.extend_from_slice(&COMMAND_REPLY.to_le_bytes());
+        script.0.extend_from_slice(&u32::MAX.to_le_bytes());
+        script.0.extend_from_slice(&[0; 8]);
+        let (session, _) = script.connect();
+        let session = session.unwrap();
+        assert!(matches!(session.root(), Err(Error::Protocol(_))));
+        assert!(matches!(session.root(), Err(Error::DeadObject)));
+    }
+
+    #[test]
+    fn command_of_the_maximum_size_is_accepted() {
+        let (session, _) = Script::new(PROTOCOL_VERSION)
+            .command(COMMAND_DEC_STRONG, &[0; MAX_COMMAND_BODY_SIZE])
+            .reply(PROTOCOL_VERSION, OK, &binder_data(1))
+            .connect();
+        assert!(session.unwrap().root().unwrap().is_some());
+    }
+
+    #[test]
+    fn unknown_command_is_rejected() {
+        let (session, _) = Script::new(PROTOCOL_VERSION).command(99, &[]).connect();
+        assert!(matches!(session.unwrap().root(), Err(Error::Protocol(_))));
+    }
+}
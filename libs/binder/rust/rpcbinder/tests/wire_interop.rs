@@ -0,0 +1,124 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests the Rust-only client of `librpcbinder_wire_rs` against libbinder's `RpcServer`.
+
+use binder::binder_impl::{BorrowedParcel, TransactionCode, FIRST_CALL_TRANSACTION};
+use binder::{declare_binder_interface, BinderFeatures, Interface, StatusCode};
+use rpcbinder::RpcServer;
+use rpcbinder_wire::{Error, WireParcel, WireSession};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+const DESCRIPTOR: &str = "android.rpcbinder.test.IAdder";
+const ADD: TransactionCode = FIRST_CALL_TRANSACTION;
+
+pub trait IAdder: Interface {}
+
+declare_binder_interface! {
+    IAdder["android.rpcbinder.test.IAdder"] {
+        native: BnAdder(on_transact),
+        proxy: BpAdder,
+    }
+}
+
+fn on_transact(
+    _service: &dyn IAdder,
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+    reply: &mut BorrowedParcel<'_>,
+) -> Result<(), StatusCode> {
+    match code {
+        ADD => {
+            let a: i32 = data.read()?;
+            let b: i32 = data.read()?;
+            reply.write(&(a + b))
+        }
+        _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+    }
+}
+
+struct Adder;
+
+impl Interface for Adder {}
+impl IAdder for Adder {}
+
+/// An `RpcServer` serving an `Adder` on a Unix domain socket, which is removed when it is dropped.
+struct Server {
+    server: RpcServer,
+    path: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let service = BnAdder::new_binder(Adder, BinderFeatures::default()).as_binder();
+        let server = RpcServer::new_unix_domain(service, &path).expect("Failed to create server");
+        server.start();
+        Self { server, path }
+    }
+
+    fn connect(&self) -> WireSession {
+        let stream = UnixStream::connect(&self.path).expect("Failed to connect to server");
+        WireSession::connect(stream).expect("Failed to start session")
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.server.shutdown();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn transact_with_libbinder_server() {
+    let server = Server::start("wire_interop_transact");
+    let session = server.connect();
+    let root = session.root().unwrap().expect("The server should have a root object");
+
+    let mut data = WireParcel::new();
+    data.write_interface_token(DESCRIPTOR);
+    data.write_i32(2);
+    data.write_i32(40);
+    let mut reply = root.transact(ADD, 0, &data).unwrap().expect("ADD isn't one way");
+    assert_eq!(reply.read_i32().unwrap(), 42);
+    assert_eq!(reply.remaining(), 0);
+
+    // The session can be used again after a transaction.
+    let mut reply = root.transact(ADD, 0, &data).unwrap().unwrap();
+    assert_eq!(reply.read_i32().unwrap(), 42);
+}
+
+#[test]
+fn libbinder_server_status_is_returned() {
+    let server = Server::start("wire_interop_status");
+    let session = server.connect();
+    let root = session.root().unwrap().unwrap();
+
+    let mut data = WireParcel::new();
+    data.write_interface_token(DESCRIPTOR);
+    let status = StatusCode::UNKNOWN_TRANSACTION as i32;
+    assert!(matches!(root.transact(ADD + 1, 0, &data), Err(Error::Status(s)) if s == status));
+
+    // A parcel with the wrong interface token is rejected before reaching the service.
+    let mut data = WireParcel::new();
+    data.write_interface_token("android.rpcbinder.test.IOther");
+    data.write_i32(2);
+    data.write_i32(40);
+    assert!(matches!(root.transact(ADD, 0, &data), Err(Error::Status(_))));
+}
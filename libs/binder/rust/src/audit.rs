@@ -0,0 +1,229 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per-interface audit trails of incoming transactions.
+
+use crate::binder::TransactionCode;
+use crate::error::{parse_exception_code, ExceptionCode, StatusCode};
+use crate::parcel::BorrowedParcel;
+use crate::state::ThreadState;
+
+use libc::{pid_t, uid_t};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single incoming transaction recorded by an [`AuditTrail`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the service finished handling the transaction.
+    pub time: SystemTime,
+    /// The UID of the calling process.
+    pub uid: uid_t,
+    /// The PID of the calling process, or 0 for oneway transactions.
+    pub pid: pid_t,
+    /// The transaction code, which identifies the method called.
+    pub code: TransactionCode,
+    /// The status returned by the service's `on_transact`.
+    pub status: StatusCode,
+    /// The exception code at the start of the reply, for AIDL interfaces.
+    ///
+    /// AIDL services report their errors to the caller in the reply, so
+    /// `status` is `OK` even for calls that were rejected with e.g. a
+    /// `SECURITY` exception. This is `None` if there was no reply, such as for
+    /// oneway transactions or when `status` is not `OK`.
+    pub exception: Option<ExceptionCode>,
+}
+
+/// A bounded log of the most recent transactions handled by services of one
+/// interface in this process.
+///
+/// A service enables auditing for its interface with [`AuditTrail::enable`],
+/// keeps the returned trail and writes it out from its `dump` handler:
+///
+/// ```ignore
+/// struct MyService {
+///     audit_trail: Arc<AuditTrail>,
+/// }
+///
+/// impl Interface for MyService {
+///     fn dump(&self, writer: &mut dyn Write, _args: &[&CStr]) -> binder::Result<()> {
+///         self.audit_trail.dump(writer).map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+///         Ok(())
+///     }
+/// }
+///
+/// let audit_trail = AuditTrail::enable(BnMyInterface::get_descriptor(), 256);
+/// ```
+#[derive(Debug)]
+pub struct AuditTrail {
+    descriptor: String,
+    capacity: usize,
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+static AUDIT_ENABLED: AtomicBool = AtomicBool::new(false);
+static AUDIT_TRAILS: RwLock<BTreeMap<String, Arc<AuditTrail>>> = RwLock::new(BTreeMap::new());
+
+impl AuditTrail {
+    /// Start recording the last `capacity` transactions handled by local
+    /// services with interface `descriptor`.
+    ///
+    /// If auditing is already enabled for the interface, the existing trail
+    /// is replaced with an empty one.
+    pub fn enable(descriptor: &str, capacity: usize) -> Arc<AuditTrail> {
+        let trail = Arc::new(AuditTrail {
+            descriptor: descriptor.to_owned(),
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        });
+        let mut trails = AUDIT_TRAILS.write().unwrap();
+        trails.insert(descriptor.to_owned(), trail.clone());
+        AUDIT_ENABLED.store(true, Ordering::Release);
+        trail
+    }
+
+    /// Stop recording transactions for interface `descriptor`.
+    ///
+    /// Existing references to the trail keep their entries.
+    pub fn disable(descriptor: &str) {
+        let mut trails = AUDIT_TRAILS.write().unwrap();
+        trails.remove(descriptor);
+        AUDIT_ENABLED.store(!trails.is_empty(), Ordering::Release);
+    }
+
+    /// The interface descriptor this trail records transactions for.
+    pub fn descriptor(&self) -> &str {
+        &self.descriptor
+    }
+
+    /// Returns the recorded entries, oldest first.
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Writes the recorded entries to `writer`, one per line, oldest first.
+    ///
+    /// This is meant to be called from the service's `dump` handler.
+    pub fn dump(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Audit trail for {}:", self.descriptor)?;
+        for entry in self.entries.lock().unwrap().iter() {
+            let time = entry.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            write!(
+                writer,
+                "  {}.{:03} uid={} pid={} code={} status={:?}",
+                time.as_secs(),
+                time.subsec_millis(),
+                entry.uid,
+                entry.pid,
+                entry.code,
+                entry.status,
+            )?;
+            if let Some(exception) = entry.exception {
+                write!(writer, " exception={:?}", exception)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    fn push(&self, entry: AuditEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// Called by local services after `on_transact` returns.
+pub(crate) fn on_transaction_handled(
+    descriptor: &str,
+    code: TransactionCode,
+    reply: &BorrowedParcel<'_>,
+    status: StatusCode,
+) {
+    if !AUDIT_ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let Some(trail) = AUDIT_TRAILS.read().unwrap().get(descriptor).cloned() else {
+        return;
+    };
+    trail.push(AuditEntry {
+        time: SystemTime::now(),
+        uid: ThreadState::get_calling_uid(),
+        pid: ThreadState::get_calling_pid(),
+        code,
+        status,
+        exception: reply_exception(reply, status),
+    });
+}
+
+fn reply_exception(reply: &BorrowedParcel<'_>, status: StatusCode) -> Option<ExceptionCode> {
+    if status != StatusCode::OK || reply.get_data_size() < 4 {
+        return None;
+    }
+    let position = reply.get_data_position();
+    // Safety: 0 is always a valid position.
+    unsafe { reply.set_data_position(0) }.ok()?;
+    let exception = reply.read::<i32>().ok().map(parse_exception_code);
+    // Safety: `position` was the data position before we read from the parcel,
+    // so it is within its bounds.
+    let _ = unsafe { reply.set_data_position(position) };
+    exception
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn trail_keeps_most_recent_entries() {
+        let trail = AuditTrail::enable("android.test.IAuditTrail", 2);
+        let mut reply = Parcel::new();
+        reply.write(&(ExceptionCode::SECURITY as i32)).unwrap();
+
+        for code in 1..=3 {
+            on_transaction_handled(
+                "android.test.IAuditTrail",
+                code,
+                reply.borrowed_ref(),
+                StatusCode::OK,
+            );
+        }
+        on_transaction_handled("android.test.IOther", 4, reply.borrowed_ref(), StatusCode::OK);
+        AuditTrail::disable("android.test.IAuditTrail");
+        on_transaction_handled("android.test.IAuditTrail", 5, reply.borrowed_ref(), StatusCode::OK);
+
+        let entries = trail.entries();
+        assert_eq!(entries.iter().map(|e| e.code).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(entries[0].exception, Some(ExceptionCode::SECURITY));
+        assert_eq!(reply.get_data_position(), 4);
+
+        let mut dump = Vec::new();
+        trail.dump(&mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with("Audit trail for android.test.IAuditTrail:\n"));
+        assert_eq!(dump.lines().count(), 3);
+        assert!(dump.contains("code=3 status=OK exception=SECURITY"));
+    }
+}
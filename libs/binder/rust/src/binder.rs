@@ -16,9 +16,10 @@
 
 //! Trait definitions for binder objects
 
+use crate::debug::InterfaceMetadata;
 use crate::error::{status_t, Result, StatusCode};
 use crate::parcel::{BorrowedParcel, Parcel};
-use crate::proxy::{DeathRecipient, SpIBinder, WpIBinder};
+use crate::proxy::{BinderId, DeathRecipient, SpIBinder, TransactionLayer, WpIBinder};
 use crate::sys;
 
 use downcast_rs::{impl_downcast, DowncastSync};
@@ -33,6 +34,7 @@ use std::ops::Deref;
 use std::os::fd::AsRawFd;
 use std::os::raw::c_char;
 use std::ptr;
+use std::sync::Arc;
 
 /// Binder action to perform.
 ///
@@ -107,6 +109,8 @@ where
 pub enum Stability {
     /// Default stability, visible to other modules in the same compilation
     /// context (e.g. modules on system.img)
+    ///
+    /// In vendor code this is the stability of the vendor image.
     #[default]
     Local,
 
@@ -172,6 +176,15 @@ pub trait Remotable: Send + Sync + 'static {
     /// This method should always return the same InterfaceClass for the same
     /// type.
     fn get_class() -> InterfaceClass;
+
+    /// Describe the methods of this interface for debugging tools, see
+    /// [`debug::export_schema`](crate::debug::export_schema).
+    ///
+    /// [`declare_binder_interface!`](crate::declare_binder_interface) returns
+    /// its `metadata` parameter here, if it was given one.
+    fn get_metadata() -> Option<InterfaceMetadata> {
+        None
+    }
 }
 
 /// First transaction code available for user commands (inclusive)
@@ -182,6 +195,9 @@ pub const LAST_CALL_TRANSACTION: TransactionCode = sys::LAST_CALL_TRANSACTION;
 /// Corresponds to TF_ONE_WAY -- an asynchronous call.
 pub const FLAG_ONEWAY: TransactionFlags = sys::FLAG_ONEWAY;
 /// Corresponds to TF_CLEAR_BUF -- clear transaction buffers after call is made.
+///
+/// The data and reply parcels of a transaction sent with this flag are also
+/// marked sensitive, see [`Parcel::mark_sensitive`](crate::binder_impl::Parcel::mark_sensitive).
 pub const FLAG_CLEAR_BUF: TransactionFlags = sys::FLAG_CLEAR_BUF;
 /// Set to the vendor flag if we are building for the VNDK, 0 otherwise
 pub const FLAG_PRIVATE_LOCAL: TransactionFlags = sys::FLAG_PRIVATE_LOCAL;
@@ -324,6 +340,7 @@ impl InterfaceClass {
             sys::AIBinder_Class_setHandleShellCommand(class, None);
             class
         };
+        crate::debug::on_class_defined(I::get_descriptor(), I::get_metadata);
         InterfaceClass(ptr)
     }
 
@@ -353,6 +370,18 @@ impl InterfaceClass {
                 .into()
         }
     }
+
+    /// Whether this class has the interface descriptor `descriptor`.
+    ///
+    /// Unlike comparing with [`get_descriptor`](Self::get_descriptor), this
+    /// doesn't allocate, so it is cheap enough to call for every binder
+    /// received in a transaction.
+    pub fn has_descriptor(&self, descriptor: &str) -> bool {
+        // SAFETY: AIBinder_Class_getDescriptor returns a null terminated
+        // string owned by the class, which is never deleted.
+        let raw_descriptor = unsafe { CStr::from_ptr(sys::AIBinder_Class_getDescriptor(self.0)) };
+        raw_descriptor.to_bytes() == descriptor.as_bytes()
+    }
 }
 
 impl From<InterfaceClass> for *const sys::AIBinder_Class {
@@ -370,9 +399,34 @@ impl<I: FromIBinder + ?Sized> Strong<I> {
         Self(binder)
     }
 
+    /// Returns the ID of the binder object behind this interface.
+    ///
+    /// See [`SpIBinder::id`].
+    pub fn id(this: &Strong<I>) -> BinderId {
+        this.as_binder().id()
+    }
+
     /// Construct a new weak reference to this binder
+    #[cfg(not(feature = "strict"))]
     pub fn downgrade(this: &Strong<I>) -> Weak<I> {
-        Weak::new(this)
+        Weak { weak_binder: this.as_binder().downgrade(), interface_type: PhantomData }
+    }
+
+    /// Construct a new weak reference to this binder, or fail with
+    /// `NO_MEMORY` if the NDK can't allocate it.
+    pub fn try_downgrade(this: &Strong<I>) -> Result<Weak<I>> {
+        let weak_binder = this.as_binder().try_downgrade()?;
+        Ok(Weak { weak_binder, interface_type: PhantomData })
+    }
+
+    /// Return a new handle to the same interface whose transactions pass
+    /// through `layer`, in addition to any layers already attached.
+    ///
+    /// See [`TransactionLayer`] for details. Layers have no effect if the
+    /// interface is implemented by a local service object, as calls on it are
+    /// never sent as transactions.
+    pub fn with_layer(&self, layer: Arc<dyn TransactionLayer>) -> Result<Strong<I>> {
+        FromIBinder::try_from(self.0.as_binder().with_layer(layer))
     }
 
     /// Convert this synchronous binder handle into an asynchronous one.
@@ -405,6 +459,12 @@ impl<I: FromIBinder + ?Sized> Clone for Strong<I> {
     }
 }
 
+impl<I: FromIBinder + ?Sized> From<&Strong<I>> for BinderId {
+    fn from(strong: &Strong<I>) -> Self {
+        Strong::id(strong)
+    }
+}
+
 impl<I: FromIBinder + ?Sized> Borrow<I> for Strong<I> {
     fn borrow(&self) -> &I {
         &self.0
@@ -459,10 +519,11 @@ pub struct Weak<I: FromIBinder + ?Sized> {
 }
 
 impl<I: FromIBinder + ?Sized> Weak<I> {
-    /// Construct a new weak reference from a strong reference
-    fn new(binder: &Strong<I>) -> Self {
-        let weak_binder = binder.as_binder().downgrade();
-        Weak { weak_binder, interface_type: PhantomData }
+    /// Returns the ID of the binder object, or `None` if it no longer exists.
+    ///
+    /// See [`SpIBinder::id`].
+    pub fn id(&self) -> Option<BinderId> {
+        self.weak_binder.id()
     }
 
     /// Upgrade this weak reference to a strong reference if the binder object
@@ -562,6 +623,14 @@ pub trait InterfaceClassMethods {
     where
         Self: Sized;
 
+    /// Get the metadata describing the interface of this object type, if any.
+    fn get_metadata() -> Option<InterfaceMetadata>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
     /// Called during construction of a new `AIBinder` object of this interface
     /// class.
     ///
@@ -643,6 +712,16 @@ pub trait FromIBinder: Interface {
     fn try_from(ibinder: SpIBinder) -> Result<Strong<Self>>;
 }
 
+/// An interface whose descriptor is known without a binder object, e.g. to
+/// list the declared instances of a HAL before connecting to any of them.
+///
+/// [`declare_binder_interface!`] implements this for the trait objects of the
+/// interfaces it declares.
+pub trait InterfaceDescriptor {
+    /// The descriptor of the interface, e.g. `android.hardware.foo.IFoo`.
+    fn descriptor() -> &'static str;
+}
+
 /// Trait for transparent Rust wrappers around android C++ native types.
 ///
 /// The pointer return by this trait's methods should be immediately passed to
@@ -661,6 +740,14 @@ pub unsafe trait AsNative<T> {
 
     /// Return a mutable pointer to the native version of `self`
     fn as_native_mut(&mut self) -> *mut T;
+
+    /// Return the client-side transaction layers attached to `self`, if any.
+    ///
+    /// This is only meaningful for binder handles.
+    #[doc(hidden)]
+    fn transaction_layers(&self) -> Option<Arc<Vec<Arc<dyn TransactionLayer>>>> {
+        None
+    }
 }
 
 // Safety: If V is a valid Android C++ type then we can either use that or a
@@ -673,6 +760,10 @@ unsafe impl<T, V: AsNative<T>> AsNative<T> for Option<V> {
     fn as_native_mut(&mut self) -> *mut T {
         self.as_mut().map_or(ptr::null_mut(), |v| v.as_native_mut())
     }
+
+    fn transaction_layers(&self) -> Option<Arc<Vec<Arc<dyn TransactionLayer>>>> {
+        self.as_ref().and_then(|v| v.transaction_layers())
+    }
 }
 
 /// The features to enable when creating a native Binder.
@@ -762,6 +853,25 @@ pub struct BinderFeatures {
 ///     // Forward calls to local implementation
 /// }
 /// ```
+///
+/// The stability that native binders of an interface are marked with can be
+/// set with a trailing `stability` parameter, e.g.
+/// `stability: binder::binder_impl::Stability::Vintf,` for a VINTF HAL. This is
+/// applied by `$native::new_binder`, so implementations cannot forget it. The
+/// default, [`Stability::Local`], is the stability of the vendor image when
+/// building vendor code and of the system image otherwise.
+///
+/// A description of the methods of the interface can be given with a
+/// `metadata` parameter after `stability`, e.g.
+/// `metadata: binder::debug::InterfaceMetadata::new("android.os.IServiceManager"),`.
+/// It is evaluated whenever tools ask for it, and is part of the process's
+/// [`debug::export_schema`](crate::debug::export_schema) once the interface
+/// class is first used.
+///
+/// Converting a remote binder to the interface fails with `BAD_TYPE` if its
+/// stability is lower than that of the partition the code is built for, e.g.
+/// for a system binder received by a vendor process, as every call on it would
+/// fail with `BAD_TYPE` anyway.
 #[macro_export]
 macro_rules! declare_binder_interface {
     {
@@ -787,6 +897,7 @@ macro_rules! declare_binder_interface {
             proxy: $proxy:ident,
             $(async: $async_interface:ident $(($try_into_local_async:ident))?,)?
             stability: $stability:expr,
+            $(metadata: $metadata:expr,)?
         }
     } => {
         $crate::declare_binder_interface! {
@@ -795,6 +906,7 @@ macro_rules! declare_binder_interface {
                 proxy: $proxy {},
                 $(async: $async_interface $(($try_into_local_async))?,)?
                 stability: $stability,
+                $(metadata: $metadata,)?
             }
         }
     };
@@ -828,6 +940,7 @@ macro_rules! declare_binder_interface {
             },
             $(async: $async_interface:ident $(($try_into_local_async:ident))?,)?
             stability: $stability:expr,
+            $(metadata: $metadata:expr,)?
         }
     } => {
         $crate::declare_binder_interface! {
@@ -840,6 +953,7 @@ macro_rules! declare_binder_interface {
                 },
                 $(async: $async_interface $(($try_into_local_async))?,)?
                 stability: $stability,
+                $(metadata: $metadata,)?
             }
         }
     };
@@ -857,11 +971,14 @@ macro_rules! declare_binder_interface {
             $(async: $async_interface:ident $(($try_into_local_async:ident))?,)?
 
             stability: $stability:expr,
+
+            $(metadata: $metadata:expr,)?
         }
     } => {
         #[doc = $proxy_doc]
         pub struct $proxy {
             binder: $crate::SpIBinder,
+            _record: $crate::debug::ProxyRecord,
             $($fname: $fty,)*
         }
 
@@ -880,7 +997,9 @@ macro_rules! declare_binder_interface {
             }
 
             fn from_binder(mut binder: $crate::SpIBinder) -> std::result::Result<Self, $crate::StatusCode> {
-                Ok(Self { binder, $($fname: $finit),* })
+                static PROXIES: $crate::debug::ProxyCount = $crate::debug::ProxyCount::new();
+                let _record = $crate::debug::ProxyRecord::new(&PROXIES, $descriptor);
+                Ok(Self { binder, _record, $($fname: $finit),* })
             }
         }
 
@@ -954,16 +1073,33 @@ macro_rules! declare_binder_interface {
                     CLASS.unwrap()
                 }
             }
+
+            $(
+                fn get_metadata() -> Option<$crate::debug::InterfaceMetadata> {
+                    Some($metadata)
+                }
+            )?
+        }
+
+        impl $crate::binder_impl::InterfaceDescriptor for dyn $interface {
+            fn descriptor() -> &'static str {
+                <$native as $crate::binder_impl::Remotable>::get_descriptor()
+            }
         }
 
         impl $crate::FromIBinder for dyn $interface {
             fn try_from(mut ibinder: $crate::SpIBinder) -> std::result::Result<$crate::Strong<dyn $interface>, $crate::StatusCode> {
                 use $crate::binder_impl::AssociateClass;
 
+                if !ibinder.has_partition_stability() {
+                    // Every call on the binder would fail the stability check.
+                    return Err($crate::StatusCode::BAD_TYPE.into());
+                }
+
                 let existing_class = ibinder.get_class();
                 if let Some(class) = existing_class {
                     if class != <$native as $crate::binder_impl::Remotable>::get_class() &&
-                        class.get_descriptor() == <$native as $crate::binder_impl::Remotable>::get_descriptor()
+                        class.has_descriptor(<$native as $crate::binder_impl::Remotable>::get_descriptor())
                     {
                         // The binder object's descriptor string matches what we
                         // expect. We still need to treat this local or already
@@ -1024,14 +1160,25 @@ macro_rules! declare_binder_interface {
 
         $(
         // Async interface trait implementations.
+        impl<P: $crate::BinderAsyncPool + 'static> $crate::binder_impl::InterfaceDescriptor for dyn $async_interface<P> {
+            fn descriptor() -> &'static str {
+                <$native as $crate::binder_impl::Remotable>::get_descriptor()
+            }
+        }
+
         impl<P: $crate::BinderAsyncPool + 'static> $crate::FromIBinder for dyn $async_interface<P> {
             fn try_from(mut ibinder: $crate::SpIBinder) -> std::result::Result<$crate::Strong<dyn $async_interface<P>>, $crate::StatusCode> {
                 use $crate::binder_impl::AssociateClass;
 
+                if !ibinder.has_partition_stability() {
+                    // Every call on the binder would fail the stability check.
+                    return Err($crate::StatusCode::BAD_TYPE.into());
+                }
+
                 let existing_class = ibinder.get_class();
                 if let Some(class) = existing_class {
                     if class != <$native as $crate::binder_impl::Remotable>::get_class() &&
-                        class.get_descriptor() == <$native as $crate::binder_impl::Remotable>::get_descriptor()
+                        class.has_descriptor(<$native as $crate::binder_impl::Remotable>::get_descriptor())
                     {
                         // The binder object's descriptor string matches what we
                         // expect. We still need to treat this local or already
@@ -1149,8 +1296,15 @@ macro_rules! declare_binder_enum {
 
         impl $crate::binder_impl::SerializeArray for $enum {
             fn serialize_array(slice: &[Self], parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
-                let v: Vec<$backing> = slice.iter().map(|x| x.0).collect();
-                <$backing as $crate::binder_impl::SerializeArray>::serialize_array(&v[..], parcel)
+                <$backing as $crate::binder_impl::SerializeArray>::serialize_iter(slice.iter().map(|x| x.0), parcel)
+            }
+
+            fn serialize_ref_array(slice: &[&Self], parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
+                <$backing as $crate::binder_impl::SerializeArray>::serialize_iter(slice.iter().map(|x| x.0), parcel)
+            }
+
+            fn serialize_iter<I: Iterator<Item = Self>>(values: I, parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
+                <$backing as $crate::binder_impl::SerializeArray>::serialize_iter(values.map(|x| x.0), parcel)
             }
         }
 
@@ -0,0 +1,464 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A typed, bounded channel from one process to another.
+//!
+//! The receiving process hosts a [`BinderChannel`] and hands out its binder,
+//! and the sending process connects a [`ChannelSender`] to it. Items are sent
+//! in order, and at most the channel's capacity of them are buffered in the
+//! receiver, after which sending blocks or fails until the receiver catches
+//! up:
+//!
+//! ```ignore
+//! // In the receiver:
+//! let channel = BinderChannel::<Sample>::new(16);
+//! service.setSampleChannel(&channel.as_binder())?;
+//! while let Some(sample) = channel.recv() {
+//!     process(sample);
+//! }
+//!
+//! // In the sender:
+//! let sender = ChannelSender::<Sample>::connect(&binder)?;
+//! for sample in samples {
+//!     sender.send(&sample)?;
+//! }
+//! sender.close()?;
+//! ```
+//!
+//! Only one sender can be connected at a time, and only the process which
+//! connected it can send items or close the channel; others get
+//! `PERMISSION_DENIED`. Once the sender closes the channel, drops its end or
+//! dies, the receiver gets the items still buffered, and then `None`. Once the
+//! receiver closes or drops its end, or dies, sending fails with `DEAD_OBJECT`.
+//!
+//! The channel binder accepts these transactions:
+//!
+//! * `FIRST_CALL_TRANSACTION`: connect the sender, whose `IBinder` token it
+//!   contains;
+//! * `FIRST_CALL_TRANSACTION + 1`: send the item it contains, after a
+//!   `boolean` which is true to wait for room rather than fail with
+//!   `WOULD_BLOCK` if the channel is full;
+//! * `FIRST_CALL_TRANSACTION + 2`: close the channel.
+
+use crate::binder::{
+    IBinder, IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+};
+use crate::error::{Result, StatusCode};
+use crate::native::Binder;
+use crate::parcel::{BorrowedParcel, Deserialize, Serialize};
+use crate::proxy::{DeathRecipient, SpIBinder};
+use crate::state::ThreadState;
+
+use libc::{pid_t, uid_t};
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::fmt;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+
+const CONNECT: TransactionCode = FIRST_CALL_TRANSACTION;
+const SEND: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const CLOSE: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+
+/// The uid and pid of the process making a transaction.
+type Peer = (uid_t, pid_t);
+
+/// The receiving end of a channel, whose items are deserialized as they
+/// arrive.
+trait Inbox: Send + Sync {
+    fn connect(&self, token: SpIBinder, peer: Peer) -> Result<()>;
+    fn check_peer(&self, peer: Peer) -> Result<()>;
+    fn deliver(&self, data: &BorrowedParcel<'_>, wait: bool) -> Result<()>;
+    fn close(&self);
+    fn dump(&self, writer: &mut dyn Write) -> Result<()>;
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    // Signalled when an item arrives or the channel is closed.
+    not_empty: Condvar,
+    // Signalled when an item is received or the channel is closed.
+    not_full: Condvar,
+    capacity: usize,
+    // For death recipients, which mustn't keep the channel alive.
+    weak: Weak<Shared<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    // The sender which connected, if any.
+    peer: Option<Peer>,
+    closed: bool,
+    // Kept alive so that the channel is closed when the sender dies.
+    death_recipient: Option<DeathRecipient>,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl<T: Deserialize + Send + 'static> Inbox for Shared<T> {
+    fn connect(&self, mut token: SpIBinder, peer: Peer) -> Result<()> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+        if state.peer.is_some() {
+            return Err(StatusCode::ALREADY_EXISTS);
+        }
+        if token.is_remote() {
+            let shared = self.weak.clone();
+            let mut death_recipient = DeathRecipient::new(move || {
+                if let Some(shared) = Weak::upgrade(&shared) {
+                    shared.close();
+                }
+            });
+            token.link_to_death(&mut death_recipient)?;
+            state.death_recipient = Some(death_recipient);
+        }
+        state.peer = Some(peer);
+        Ok(())
+    }
+
+    fn check_peer(&self, peer: Peer) -> Result<()> {
+        match self.lock().peer {
+            Some(connected) if connected == peer => Ok(()),
+            Some(_) => Err(StatusCode::PERMISSION_DENIED),
+            None => Err(StatusCode::INVALID_OPERATION),
+        }
+    }
+
+    fn deliver(&self, data: &BorrowedParcel<'_>, wait: bool) -> Result<()> {
+        let item: T = data.read()?;
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return Err(StatusCode::DEAD_OBJECT);
+            }
+            if state.queue.len() < self.capacity {
+                break;
+            }
+            if !wait {
+                return Err(StatusCode::WOULD_BLOCK);
+            }
+            state = self.not_full.wait(state).unwrap();
+        }
+        state.queue.push_back(item);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn dump(&self, writer: &mut dyn Write) -> Result<()> {
+        let state = self.lock();
+        writeln!(
+            writer,
+            "{}/{} items buffered, {}",
+            state.queue.len(),
+            self.capacity,
+            if state.closed { "closed" } else { "open" }
+        )
+        .map_err(|_| StatusCode::FAILED_TRANSACTION)
+    }
+}
+
+/// The remotable object behind a [`BinderChannel`], which receives items.
+struct Endpoint {
+    inbox: Arc<dyn Inbox>,
+}
+
+impl Remotable for Endpoint {
+    fn get_descriptor() -> &'static str {
+        "android.os.IBinderChannel"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        let peer = (ThreadState::get_calling_uid(), ThreadState::get_calling_pid());
+        match code {
+            CONNECT => self.inbox.connect(data.read()?, peer),
+            SEND => {
+                self.inbox.check_peer(peer)?;
+                let wait = data.read()?;
+                self.inbox.deliver(data, wait)
+            }
+            CLOSE => {
+                self.inbox.check_peer(peer)?;
+                self.inbox.close();
+                Ok(())
+            }
+            _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+        }
+    }
+
+    fn on_dump(&self, writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        self.inbox.dump(writer)
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// The receiving end of a channel of `T`s, which buffers up to a fixed number
+/// of them.
+pub struct BinderChannel<T> {
+    shared: Arc<Shared<T>>,
+    endpoint: Binder<Endpoint>,
+}
+
+impl<T> fmt::Debug for BinderChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.lock();
+        f.debug_struct("BinderChannel")
+            .field("capacity", &self.shared.capacity)
+            .field("buffered", &state.queue.len())
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+impl<T: Deserialize + Send + 'static> BinderChannel<T> {
+    /// Create a channel which buffers up to `capacity` items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Channels must have room for an item");
+        let state =
+            State { queue: VecDeque::new(), peer: None, closed: false, death_recipient: None };
+        let shared = Arc::new_cyclic(|weak| Shared {
+            state: Mutex::new(state),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            weak: weak.clone(),
+        });
+        let endpoint = Binder::new(Endpoint { inbox: shared.clone() });
+        Self { shared, endpoint }
+    }
+
+    /// Wait for the next item, or return `None` once the channel is closed
+    /// and every item sent before then has been received.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.take(&mut state) {
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.shared.not_empty.wait(state).unwrap();
+        }
+    }
+
+    /// Return the next item if one has arrived, without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.take(&mut self.shared.lock())
+    }
+
+    /// Close the channel, so that the sender's calls fail from now on. Items
+    /// which have already arrived can still be received.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Whether the channel has been closed by either end.
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+
+    /// The number of items which have arrived but not been received yet.
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    /// Whether there are no items waiting to be received.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn take(&self, state: &mut State<T>) -> Option<T> {
+        let item = state.queue.pop_front()?;
+        self.shared.not_full.notify_one();
+        Some(item)
+    }
+}
+
+impl<T> Drop for BinderChannel<T> {
+    fn drop(&mut self) {
+        // The endpoint lives on for as long as the sender holds its binder.
+        self.endpoint.inbox.close();
+    }
+}
+
+impl<T: Send + 'static> Interface for BinderChannel<T> {
+    fn as_binder(&self) -> SpIBinder {
+        self.endpoint.as_binder()
+    }
+}
+
+/// The remotable object which identifies a [`ChannelSender`] to the
+/// receiver, so that it can tell when the sender dies.
+struct SenderToken;
+
+impl Remotable for SenderToken {
+    fn get_descriptor() -> &'static str {
+        "android.os.IBinderChannelSender"
+    }
+
+    fn on_transact(
+        &self,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        Err(StatusCode::UNKNOWN_TRANSACTION)
+    }
+
+    fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// The sending end of a channel of `T`s. The channel is closed when this is
+/// dropped.
+pub struct ChannelSender<T: ?Sized> {
+    channel: SpIBinder,
+    _token: Binder<SenderToken>,
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<T: ?Sized> fmt::Debug for ChannelSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelSender").field("channel", &self.channel).finish()
+    }
+}
+
+impl<T: Serialize + ?Sized> ChannelSender<T> {
+    /// Connect to the [`BinderChannel`] with the given binder.
+    ///
+    /// Fails with `ALREADY_EXISTS` if another sender is connected to it, and
+    /// with `DEAD_OBJECT` if it has been closed.
+    pub fn connect(channel: &SpIBinder) -> Result<Self> {
+        let token = Binder::new(SenderToken);
+        channel.transact(CONNECT, 0, |mut data| data.write(&token.as_binder()))?;
+        Ok(Self { channel: channel.clone(), _token: token, _marker: PhantomData })
+    }
+
+    /// Send `item`, waiting for room in the channel if it is full.
+    ///
+    /// This ties up a binder thread in the receiving process while it waits.
+    pub fn send(&self, item: &T) -> Result<()> {
+        self.send_item(item, true)
+    }
+
+    /// Send `item`, failing with `WOULD_BLOCK` if the channel is full.
+    pub fn try_send(&self, item: &T) -> Result<()> {
+        self.send_item(item, false)
+    }
+
+    /// Close the channel, returning any error from the receiver.
+    ///
+    /// Dropping the sender also closes the channel, but ignores errors.
+    pub fn close(self) -> Result<()> {
+        self.channel.transact(CLOSE, 0, |_| Ok(())).map(|_| ())
+    }
+
+    fn send_item(&self, item: &T, wait: bool) -> Result<()> {
+        self.channel
+            .transact(SEND, 0, |mut data| {
+                data.write(&wait)?;
+                data.write(item)
+            })
+            .map(|_| ())
+    }
+}
+
+impl<T: ?Sized> Drop for ChannelSender<T> {
+    fn drop(&mut self) {
+        // Closing again after `close` is harmless.
+        let _ = self.channel.transact(CLOSE, 0, |_| Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_arrive_in_order_until_closed() {
+        let channel = BinderChannel::<i32>::new(2);
+        let sender = ChannelSender::<i32>::connect(&channel.as_binder()).unwrap();
+        assert_eq!(sender.try_send(&1), Ok(()));
+        assert_eq!(sender.send(&2), Ok(()));
+        assert_eq!(sender.try_send(&3), Err(StatusCode::WOULD_BLOCK));
+        assert_eq!(channel.try_recv(), Some(1));
+        assert_eq!(sender.try_send(&3), Ok(()));
+
+        sender.close().unwrap();
+        assert!(channel.is_closed());
+        assert_eq!(channel.recv(), Some(2));
+        assert_eq!(channel.recv(), Some(3));
+        assert_eq!(channel.recv(), None);
+    }
+
+    #[test]
+    fn channels_have_one_sender_until_the_receiver_closes() {
+        let channel = BinderChannel::<String>::new(1);
+        let binder = channel.as_binder();
+        let sender = ChannelSender::<str>::connect(&binder).unwrap();
+        assert_eq!(ChannelSender::<str>::connect(&binder).err(), Some(StatusCode::ALREADY_EXISTS));
+        assert_eq!(sender.try_send("hello"), Ok(()));
+
+        drop(channel);
+        assert_eq!(sender.try_send("again"), Err(StatusCode::DEAD_OBJECT));
+    }
+
+    #[test]
+    fn only_the_connected_process_can_send_or_close() {
+        let channel = BinderChannel::<i32>::new(1);
+        let peer = (ThreadState::get_calling_uid(), ThreadState::get_calling_pid());
+        assert_eq!(channel.shared.check_peer(peer), Err(StatusCode::INVALID_OPERATION));
+
+        let sender = ChannelSender::<i32>::connect(&channel.as_binder()).unwrap();
+        assert_eq!(channel.shared.check_peer(peer), Ok(()));
+        assert_eq!(
+            channel.shared.check_peer((peer.0, peer.1 + 1)),
+            Err(StatusCode::PERMISSION_DENIED)
+        );
+        assert_eq!(
+            channel.shared.check_peer((peer.0 + 1, peer.1)),
+            Err(StatusCode::PERMISSION_DENIED)
+        );
+        assert_eq!(sender.try_send(&1), Ok(()));
+    }
+}
@@ -0,0 +1,418 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Sending payloads too large for a single transaction.
+//!
+//! Large payloads are usually sent through a file descriptor or shared
+//! memory, but that is not always possible, e.g. over RPC binder connections
+//! without file descriptor support. A [`ChunkedStreamSender`] instead splits
+//! the payload into a sequence of transactions with the same code, and the
+//! receiving service passes each of them to a [`ChunkedStreamReceiver`],
+//! which reassembles the payload.
+//!
+//! Each chunk transaction contains:
+//!
+//! * `int64` stream ID, unique per payload sent from a process;
+//! * `int32` sequence number, starting at 0;
+//! * `int64` total payload size in bytes;
+//! * `int32` CRC-32 of the chunk data;
+//! * `byte[]` chunk data.
+//!
+//! Streams are kept apart by the calling UID as well as the stream ID, so one
+//! caller can't add chunks to, or cut short, another caller's payload.
+
+use crate::binder::{IBinderInternal, TransactionCode};
+use crate::error::{Result, StatusCode};
+use crate::parcel::BorrowedParcel;
+use crate::proxy::SpIBinder;
+use crate::state::ThreadState;
+
+use libc::uid_t;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static NEXT_STREAM: AtomicU32 = AtomicU32::new(0);
+
+/// Sends payloads to a [`ChunkedStreamReceiver`] as a series of transactions.
+#[derive(Clone, Debug)]
+pub struct ChunkedStreamSender {
+    binder: SpIBinder,
+    code: TransactionCode,
+    chunk_size: usize,
+}
+
+impl ChunkedStreamSender {
+    /// The default amount of payload data sent per transaction.
+    ///
+    /// This is well below the 1MB binder transaction buffer, which is shared
+    /// by all transactions in flight to a process.
+    pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+    /// Create a sender which sends chunks to `binder` as transactions with
+    /// the given `code`.
+    pub fn new(binder: SpIBinder, code: TransactionCode) -> Self {
+        Self { binder, code, chunk_size: Self::DEFAULT_CHUNK_SIZE }
+    }
+
+    /// Send at most `chunk_size` bytes of payload per transaction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Chunks must not be empty");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Send `payload`, blocking until the receiver has accepted every chunk.
+    ///
+    /// If the receiver rejects a chunk, that error is returned and no further
+    /// chunks are sent.
+    pub fn send(&self, payload: &[u8]) -> Result<()> {
+        let stream_id = new_stream_id();
+        for (sequence, chunk) in chunks(payload, self.chunk_size).enumerate() {
+            let mut data = self.binder.prepare_transact()?;
+            write_chunk(&mut data.borrowed(), stream_id, sequence, payload.len(), chunk)?;
+            self.binder.submit_transact(self.code, data, 0)?;
+        }
+        Ok(())
+    }
+}
+
+fn new_stream_id() -> i64 {
+    (i64::from(std::process::id()) << 32) | i64::from(NEXT_STREAM.fetch_add(1, Ordering::Relaxed))
+}
+
+fn chunks(payload: &[u8], chunk_size: usize) -> impl Iterator<Item = &[u8]> {
+    // An empty payload is still sent as a single, empty chunk.
+    let empty = payload.is_empty().then_some(&payload[..0]);
+    payload.chunks(chunk_size).chain(empty)
+}
+
+fn write_chunk(
+    parcel: &mut BorrowedParcel<'_>,
+    stream_id: i64,
+    sequence: usize,
+    total_size: usize,
+    chunk: &[u8],
+) -> Result<()> {
+    parcel.write(&stream_id)?;
+    parcel.write(&i32::try_from(sequence).or(Err(StatusCode::BAD_VALUE))?)?;
+    parcel.write(&i64::try_from(total_size).or(Err(StatusCode::BAD_VALUE))?)?;
+    parcel.write(&(crc32(chunk) as i32))?;
+    parcel.write(chunk)
+}
+
+#[derive(Debug)]
+struct PartialPayload {
+    next_sequence: i32,
+    total_size: usize,
+    data: Vec<u8>,
+    last_chunk: Instant,
+}
+
+/// A stream of chunks, by the calling UID and stream ID.
+type StreamKey = (uid_t, i64);
+
+/// A chunk as read from a transaction, apart from its stream ID.
+struct Chunk {
+    sequence: i32,
+    total_size: usize,
+    checksum: u32,
+    data: Vec<u8>,
+}
+
+/// Reassembles payloads sent by a [`ChunkedStreamSender`].
+///
+/// Payloads from several senders may be received at the same time.
+/// Incomplete payloads are dropped once no chunk of them has arrived for the
+/// idle timeout, so a sender which gives up halfway doesn't keep its data
+/// buffered.
+#[derive(Debug)]
+pub struct ChunkedStreamReceiver {
+    max_payload_size: usize,
+    max_buffered_size: usize,
+    max_streams_per_caller: usize,
+    idle_timeout: Duration,
+    partial: Mutex<HashMap<StreamKey, PartialPayload>>,
+}
+
+impl ChunkedStreamReceiver {
+    /// The default number of incomplete payloads one calling UID may have at
+    /// a time.
+    pub const DEFAULT_MAX_STREAMS_PER_CALLER: usize = 16;
+
+    /// The default time after which an incomplete payload is dropped if no
+    /// more of it arrives.
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Create a receiver which accepts payloads of up to `max_payload_size`
+    /// bytes, and buffers at most `max_buffered_size` bytes of incomplete
+    /// payloads at a time.
+    pub fn new(max_payload_size: usize, max_buffered_size: usize) -> Self {
+        Self {
+            max_payload_size,
+            max_buffered_size,
+            max_streams_per_caller: Self::DEFAULT_MAX_STREAMS_PER_CALLER,
+            idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
+            partial: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accept at most `max_streams` incomplete payloads from each calling UID
+    /// at a time. Once a caller has that many, the first chunk of another
+    /// payload from it fails with `NO_MEMORY`.
+    pub fn with_max_streams_per_caller(mut self, max_streams: usize) -> Self {
+        self.max_streams_per_caller = max_streams;
+        self
+    }
+
+    /// Drop an incomplete payload once no chunk of it has arrived for
+    /// `idle_timeout`.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Handle a chunk transaction.
+    ///
+    /// The service should call this from `on_transact` for the transaction
+    /// code used by the sender, and return any error to the sender. Returns
+    /// the payload once its last chunk has been received.
+    ///
+    /// Chunks must arrive in order. If a chunk is out of order or corrupt,
+    /// the rest of its payload is dropped and an error is returned.
+    pub fn receive(&self, data: &BorrowedParcel<'_>) -> Result<Option<Vec<u8>>> {
+        self.receive_from(ThreadState::get_calling_uid(), Instant::now(), data)
+    }
+
+    fn receive_from(
+        &self,
+        uid: uid_t,
+        now: Instant,
+        data: &BorrowedParcel<'_>,
+    ) -> Result<Option<Vec<u8>>> {
+        let stream_id: i64 = data.read()?;
+        let sequence: i32 = data.read()?;
+        let total_size = usize::try_from(data.read::<i64>()?).or(Err(StatusCode::BAD_VALUE))?;
+        let checksum = data.read::<i32>()? as u32;
+        let chunk: Vec<u8> = data.read()?;
+
+        let key = (uid, stream_id);
+        let mut partial = self.partial.lock().unwrap();
+        partial.retain(|_, payload| now.duration_since(payload.last_chunk) < self.idle_timeout);
+        let chunk = Chunk { sequence, total_size, checksum, data: chunk };
+        let result = self.add_chunk(&mut partial, key, now, chunk);
+        if result.is_err() {
+            partial.remove(&key);
+        }
+        result
+    }
+
+    /// Drop any received data of incomplete payloads.
+    pub fn reset(&self) {
+        self.partial.lock().unwrap().clear();
+    }
+
+    fn add_chunk(
+        &self,
+        partial: &mut HashMap<StreamKey, PartialPayload>,
+        key: StreamKey,
+        now: Instant,
+        chunk: Chunk,
+    ) -> Result<Option<Vec<u8>>> {
+        let Chunk { sequence, total_size, checksum, data: chunk } = chunk;
+        if crc32(&chunk) != checksum {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if total_size > self.max_payload_size {
+            return Err(StatusCode::NO_MEMORY);
+        }
+
+        if sequence == 0 {
+            if partial.contains_key(&key) {
+                return Err(StatusCode::BAD_VALUE);
+            }
+            if chunk.len() == total_size {
+                return Ok(Some(chunk));
+            }
+            let streams = partial.keys().filter(|(uid, _)| *uid == key.0).count();
+            if streams >= self.max_streams_per_caller {
+                return Err(StatusCode::NO_MEMORY);
+            }
+            partial.insert(
+                key,
+                PartialPayload { next_sequence: 0, total_size, data: Vec::new(), last_chunk: now },
+            );
+        }
+
+        let buffered: usize = partial.values().map(|p| p.data.len()).sum();
+        let payload = partial.get_mut(&key).ok_or(StatusCode::BAD_VALUE)?;
+        let new_len = payload.data.len() + chunk.len();
+        if payload.next_sequence != sequence || payload.total_size != total_size {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if new_len > total_size {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if buffered + chunk.len() > self.max_buffered_size {
+            return Err(StatusCode::NO_MEMORY);
+        }
+
+        payload.data.extend_from_slice(&chunk);
+        payload.next_sequence += 1;
+        payload.last_chunk = now;
+        if new_len == total_size {
+            Ok(partial.remove(&key).map(|payload| payload.data))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    fn chunk_parcels(stream_id: i64, payload: &[u8], chunk_size: usize) -> Vec<Parcel> {
+        chunks(payload, chunk_size)
+            .enumerate()
+            .map(|(sequence, chunk)| {
+                let mut parcel = Parcel::new();
+                write_chunk(&mut parcel.borrowed(), stream_id, sequence, payload.len(), chunk)
+                    .unwrap();
+                // SAFETY: 0 is always a valid position.
+                unsafe {
+                    parcel.set_data_position(0).unwrap();
+                }
+                parcel
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_reassemble_interleaved_streams() {
+        let first: Vec<u8> = (0..=255).collect();
+        let second = b"hello binder".to_vec();
+        let receiver = ChunkedStreamReceiver::new(1024, 1024);
+
+        let first_chunks = chunk_parcels(1, &first, 100);
+        let second_chunks = chunk_parcels(2, &second, 5);
+        assert_eq!(first_chunks.len(), 3);
+        assert_eq!(second_chunks.len(), 3);
+
+        for (a, b) in first_chunks.iter().zip(&second_chunks).take(2) {
+            assert_eq!(receiver.receive(a.borrowed_ref()), Ok(None));
+            assert_eq!(receiver.receive(b.borrowed_ref()), Ok(None));
+        }
+        assert_eq!(receiver.receive(second_chunks[2].borrowed_ref()), Ok(Some(second)));
+        assert_eq!(receiver.receive(first_chunks[2].borrowed_ref()), Ok(Some(first)));
+
+        let empty = chunk_parcels(3, &[], 100);
+        assert_eq!(empty.len(), 1);
+        assert_eq!(receiver.receive(empty[0].borrowed_ref()), Ok(Some(vec![])));
+    }
+
+    #[test]
+    fn test_reject_out_of_order_and_oversized() {
+        let payload = vec![7u8; 30];
+        let receiver = ChunkedStreamReceiver::new(1024, 1024);
+        let parcels = chunk_parcels(1, &payload, 10);
+        assert_eq!(receiver.receive(parcels[0].borrowed_ref()), Ok(None));
+        assert_eq!(receiver.receive(parcels[2].borrowed_ref()), Err(StatusCode::BAD_VALUE));
+        // The stream was dropped, so its next chunk is rejected too.
+        assert_eq!(receiver.receive(parcels[1].borrowed_ref()), Err(StatusCode::BAD_VALUE));
+
+        let receiver = ChunkedStreamReceiver::new(20, 1024);
+        let parcels = chunk_parcels(2, &payload, 10);
+        assert_eq!(receiver.receive(parcels[0].borrowed_ref()), Err(StatusCode::NO_MEMORY));
+    }
+
+    fn receive_at(
+        receiver: &ChunkedStreamReceiver,
+        uid: uid_t,
+        now: Instant,
+        parcel: &Parcel,
+    ) -> Result<Option<Vec<u8>>> {
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        receiver.receive_from(uid, now, parcel.borrowed_ref())
+    }
+
+    #[test]
+    fn test_streams_are_per_caller() {
+        let payload = vec![7u8; 20];
+        let receiver = ChunkedStreamReceiver::new(1024, 1024).with_max_streams_per_caller(1);
+        let parcels = chunk_parcels(1, &payload, 10);
+        let now = Instant::now();
+        assert_eq!(receive_at(&receiver, 1000, now, &parcels[0]), Ok(None));
+        // Another caller's chunk with the same stream ID starts a stream of
+        // its own, and cutting that one short leaves the first caller's be.
+        assert_eq!(receive_at(&receiver, 1001, now, &parcels[0]), Ok(None));
+        assert_eq!(receive_at(&receiver, 1001, now, &parcels[0]), Err(StatusCode::BAD_VALUE));
+        assert_eq!(receive_at(&receiver, 1000, now, &parcels[1]), Ok(Some(payload)));
+
+        // The first caller's stream is done, so it has room for one more, but
+        // not for two.
+        let second = chunk_parcels(2, &[1; 20], 10);
+        let third = chunk_parcels(3, &[2; 20], 10);
+        assert_eq!(receive_at(&receiver, 1000, now, &second[0]), Ok(None));
+        assert_eq!(receive_at(&receiver, 1000, now, &third[0]), Err(StatusCode::NO_MEMORY));
+        assert_eq!(receive_at(&receiver, 1000, now, &second[1]), Ok(Some(vec![1; 20])));
+    }
+
+    #[test]
+    fn test_idle_streams_are_dropped() {
+        let receiver = ChunkedStreamReceiver::new(1024, 1024)
+            .with_max_streams_per_caller(1)
+            .with_idle_timeout(Duration::from_secs(10));
+        let first = chunk_parcels(1, &[1; 20], 10);
+        let second = chunk_parcels(2, &[2; 20], 10);
+        let start = Instant::now();
+        assert_eq!(receive_at(&receiver, 1000, start, &first[0]), Ok(None));
+
+        // The first stream has been idle too long, so it no longer counts
+        // towards the caller's streams, and the rest of it is rejected.
+        let later = start + Duration::from_secs(11);
+        assert_eq!(receive_at(&receiver, 1000, later, &second[0]), Ok(None));
+        assert_eq!(receive_at(&receiver, 1000, later, &first[1]), Err(StatusCode::BAD_VALUE));
+        assert_eq!(receive_at(&receiver, 1000, later, &second[1]), Ok(Some(vec![2; 20])));
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A client for a named service that reconnects and retries on its own.
+
+use crate::binder::{FromIBinder, IBinder, Strong};
+use crate::error::{ExceptionCode, Status, StatusCode};
+use crate::proxy::{DeathRecipient, SpIBinder};
+use crate::service::{interface_cast, wait_for_interface, wait_for_service};
+use crate::timeout::with_transaction_timeout;
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How a [`BinderClient`] retries calls that fail because the service went
+/// away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times a call is attempted, including the first.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest to wait between retries. The wait doubles after each retry
+    /// until it reaches this.
+    pub max_backoff: Duration,
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+impl RetryPolicy {
+    /// Never retry; every call is attempted exactly once.
+    pub const NONE: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        _non_exhaustive: (),
+    };
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(1 << retry.min(16)).min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            _non_exhaustive: (),
+        }
+    }
+}
+
+struct Connection<T: FromIBinder + ?Sized> {
+    service: Strong<T>,
+    // Kept alive so that the connection is dropped when the service dies.
+    _death_recipient: DeathRecipient,
+}
+
+/// A client for the service registered under a given name.
+///
+/// The service is looked up on first use. If it dies, the client forgets it
+/// and looks it up again (starting it, if it is a lazy service) on the next
+/// call. Calls that fail because the service died are retried according to
+/// the client's [`RetryPolicy`]; any other error is returned to the caller
+/// unchanged.
+///
+/// ```ignore
+/// let client = BinderClient::<dyn IFoo>::new("foo").with_timeout(Duration::from_secs(2));
+/// let value = client.call(move |foo| foo.get_value(key))?;
+/// ```
+///
+/// Note that retrying is only safe for transactions which may be repeated: a
+/// service can die after it has acted on a transaction but before it replies.
+pub struct BinderClient<T: FromIBinder + ?Sized> {
+    name: String,
+    timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    connection: Arc<Mutex<Option<Connection<T>>>>,
+    lookup: Arc<Lookup>,
+}
+
+/// The lookup of a client with a timeout, which runs `wait_for_service` on a
+/// worker thread so that calls can stop waiting for it at their deadline.
+///
+/// `wait_for_service` can't be cancelled, so a worker whose callers have given
+/// up keeps waiting, and the next call waits for the same worker rather than
+/// starting another.
+#[derive(Default)]
+struct Lookup {
+    state: Mutex<LookupState>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct LookupState {
+    running: bool,
+    // The service the worker found, until a call takes it.
+    result: Option<Option<SpIBinder>>,
+}
+
+impl<T: FromIBinder + ?Sized> fmt::Debug for BinderClient<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BinderClient")
+            .field("name", &self.name)
+            .field("timeout", &self.timeout)
+            .field("retry_policy", &self.retry_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: FromIBinder + ?Sized + 'static> BinderClient<T> {
+    /// Create a client for the service registered under `name`, with the
+    /// default retry policy and no timeout.
+    ///
+    /// This does not look up the service; that happens on the first call.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            timeout: None,
+            retry_policy: RetryPolicy::default(),
+            connection: Arc::new(Mutex::new(None)),
+            lookup: Arc::default(),
+        }
+    }
+
+    /// Fail calls which take longer than `timeout` in total, including the
+    /// time spent looking up the service and retrying.
+    ///
+    /// The transactions a call makes are timed as described for
+    /// [`with_transaction_timeout`](crate::with_transaction_timeout), with
+    /// whatever is left of `timeout`. Looking up the service waits for it to
+    /// be registered, starting it if it is a lazy service, as without a
+    /// timeout, but only until the timeout expires.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Use `retry_policy` for calls that fail because the service died.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// The name the service is looked up under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the service, looking it up if the client is not currently
+    /// connected.
+    pub fn service(&self) -> crate::Result<Strong<T>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.connect(deadline).map_err(Status::from)
+    }
+
+    /// Call `f` with the service, reconnecting and retrying as needed.
+    ///
+    /// If the client has a timeout and the call does not finish in time, this
+    /// returns a `TIMED_OUT` transaction error.
+    pub fn call<R, F>(&self, f: F) -> crate::Result<R>
+    where
+        F: Fn(&Strong<T>) -> crate::Result<R>,
+    {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut attempt = 0;
+        loop {
+            let result = self.connect(deadline).map_err(Status::from).and_then(|service| {
+                let Some(deadline) = deadline else {
+                    return f(&service);
+                };
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(StatusCode::TIMED_OUT.into());
+                }
+                with_transaction_timeout(Some(remaining), || f(&service))
+            });
+            attempt += 1;
+            match result {
+                Err(status) if is_connection_error(&status) => {
+                    // Make sure the next attempt looks the service up again,
+                    // even if the death notification has not arrived yet.
+                    self.connection.lock().unwrap().take();
+                    if attempt >= self.retry_policy.max_attempts {
+                        return Err(status);
+                    }
+                    let backoff = self.retry_policy.backoff(attempt - 1);
+                    if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                        return Err(status);
+                    }
+                    thread::sleep(backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn connect(&self, deadline: Option<Instant>) -> Result<Strong<T>, StatusCode> {
+        if let Some(connected) = self.connection.lock().unwrap().as_ref() {
+            return Ok(connected.service.clone());
+        }
+
+        // Look the service up without holding the lock, which the death
+        // recipient also needs.
+        let service: Strong<T> = match deadline {
+            None => wait_for_interface(&self.name)?,
+            Some(deadline) => self.wait_for_interface_until(deadline)?,
+        };
+        let weak_connection = Arc::downgrade(&self.connection);
+        let mut death_recipient = DeathRecipient::new(move || {
+            if let Some(connection) = weak_connection.upgrade() {
+                connection.lock().unwrap().take();
+            }
+        });
+        service.as_binder().link_to_death(&mut death_recipient)?;
+
+        let mut guard = self.connection.lock().unwrap();
+        // Another call may have connected in the meantime, in which case its
+        // connection is kept and this one is dropped.
+        if let Some(connected) = guard.as_ref() {
+            return Ok(connected.service.clone());
+        }
+        *guard = Some(Connection { service: service.clone(), _death_recipient: death_recipient });
+        Ok(service)
+    }
+}
+
+impl<T: FromIBinder + ?Sized> BinderClient<T> {
+    /// Wait for the service, as `wait_for_interface` does, or fail with
+    /// `TIMED_OUT` once `deadline` has passed.
+    fn wait_for_interface_until(&self, deadline: Instant) -> Result<Strong<T>, StatusCode> {
+        let mut state = self.lookup.state.lock().unwrap();
+        if state.result.is_none() && !state.running {
+            let name = self.name.clone();
+            let lookup = self.lookup.clone();
+            thread::Builder::new()
+                .name("binder_client_lookup".to_owned())
+                .spawn(move || {
+                    let service = wait_for_service(&name);
+                    let mut state = lookup.state.lock().unwrap();
+                    state.running = false;
+                    state.result = Some(service);
+                    lookup.finished.notify_all();
+                })
+                .map_err(|_| StatusCode::NO_MEMORY)?;
+            state.running = true;
+        }
+        loop {
+            if let Some(service) = state.result.take() {
+                drop(state);
+                return interface_cast(service);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(StatusCode::TIMED_OUT);
+            }
+            state = self.lookup.finished.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// Whether `status` means the service could not be reached, as opposed to the
+/// service reporting an error.
+fn is_connection_error(status: &Status) -> bool {
+    status.exception_code() == ExceptionCode::TRANSACTION_FAILED
+        && matches!(
+            status.transaction_error(),
+            StatusCode::DEAD_OBJECT | StatusCode::NAME_NOT_FOUND
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(10));
+        assert_eq!(policy.backoff(1), Duration::from_millis(20));
+        assert_eq!(policy.backoff(2), Duration::from_millis(40));
+        assert_eq!(policy.backoff(3), Duration::from_millis(50));
+        assert_eq!(policy.backoff(100), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn only_connection_errors_are_retried() {
+        assert!(is_connection_error(&StatusCode::DEAD_OBJECT.into()));
+        assert!(is_connection_error(&StatusCode::NAME_NOT_FOUND.into()));
+        assert!(!is_connection_error(&StatusCode::BAD_VALUE.into()));
+        assert!(!is_connection_error(&Status::new_service_specific_error(1, None)));
+        assert!(!is_connection_error(&ExceptionCode::SECURITY.into()));
+    }
+}
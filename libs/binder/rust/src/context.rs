@@ -0,0 +1,419 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Context of the call a thread is working on.
+//!
+//! # Deadlines
+//!
+//! A client which will give up on a call after some time can tell the
+//! service, so that the service, and any services it calls in turn, can give
+//! up too rather than finishing work nobody is waiting for:
+//!
+//! ```ignore
+//! let storage = storage.with_layer(Arc::new(DeadlinePropagation));
+//! let thumbnail = binder::with_deadline(Instant::now() + Duration::from_millis(200), || {
+//!     storage.get_thumbnail(&uri)
+//! })?;
+//!
+//! // In the storage service, which passes the deadline on in the same way:
+//! fn get_thumbnail(&self, uri: &str) -> binder::Result<Vec<u8>> {
+//!     TransactionContext::current().check()?;
+//!     ...
+//! }
+//! ```
+//!
+//! The deadline is sent as a trailer after the transaction data: an `int64`
+//! marker followed by the `int64` number of nanoseconds remaining. AIDL stubs
+//! ignore data after the arguments they read, so [`DeadlinePropagation`] can
+//! be used for calls to services in any language, but only services built
+//! with this crate see the deadline. The trailer is left unread by their
+//! handlers, so services with
+//! [`verify_parcel_consumption`](crate::debug::verify_parcel_consumption)
+//! turned on report it as unconsumed data, which is only a warning.
+//!
+//! # Provided context
+//!
+//! A local service can have a [`ContextProvider`], which computes a value
+//! before each transaction, such as the caller's account or locale, that its
+//! handlers can then get with [`provided_context`] rather than each working
+//! it out from the caller's identity:
+//!
+//! ```ignore
+//! struct CallerLocale;
+//!
+//! impl ContextProvider for CallerLocale {
+//!     type Context = Locale;
+//!
+//!     fn provide(&self, _code: TransactionCode) -> Locale {
+//!         locale_for_uid(ThreadState::get_calling_uid())
+//!     }
+//! }
+//!
+//! binder::set_context_provider(&service.as_binder(), CallerLocale)?;
+//!
+//! // In a handler:
+//! let locale = binder::provided_context::<Locale>().unwrap();
+//! ```
+
+use crate::binder::{AsNative, TransactionCode, TransactionFlags};
+use crate::error::{Result, StatusCode};
+use crate::parcel::{BorrowedParcel, Parcel};
+use crate::proxy::{NextLayer, SpIBinder, TransactionLayer};
+use crate::sys;
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+// "RDEADLNE", chosen to make it unlikely that transaction data ends with it
+// by chance.
+const TRAILER_MARKER: i64 = 0x5244_4541_444c_4e45;
+const TRAILER_SIZE: i32 = 16;
+
+thread_local! {
+    // The deadline of the call this thread is working on, which is that of
+    // the innermost incoming transaction or `with_deadline` scope.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Information about the call the current thread is working on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransactionContext {
+    deadline: Option<Instant>,
+}
+
+impl TransactionContext {
+    /// The context of the current thread.
+    ///
+    /// Within [`with_deadline`], this has the earlier of that deadline and
+    /// any deadline it already had. While handling a transaction, it has the
+    /// deadline the caller sent, if any.
+    pub fn current() -> Self {
+        Self { deadline: DEADLINE.with(Cell::get) }
+    }
+
+    /// The time after which the caller will no longer be waiting for the
+    /// result of the call.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// The time left until the deadline, which is zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Fail with `TIMED_OUT` if the deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if self.is_expired() {
+            Err(StatusCode::TIMED_OUT)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Run `f` with `deadline` as the deadline for the calls it makes.
+///
+/// If the current thread already has an earlier deadline, e.g. from the
+/// transaction it is handling, that one is kept.
+pub fn with_deadline<T>(deadline: Instant, f: impl FnOnce() -> T) -> T {
+    let current = DEADLINE.with(Cell::get);
+    let deadline = current.map_or(deadline, |current| current.min(deadline));
+    let _scope = DeadlineScope::enter(Some(deadline));
+    f()
+}
+
+/// Restores the previous deadline of the thread when dropped.
+pub(crate) struct DeadlineScope {
+    previous: Option<Instant>,
+}
+
+impl DeadlineScope {
+    fn enter(deadline: Option<Instant>) -> Self {
+        Self { previous: DEADLINE.with(|current| current.replace(deadline)) }
+    }
+}
+
+impl Drop for DeadlineScope {
+    fn drop(&mut self) {
+        DEADLINE.with(|current| current.set(self.previous));
+    }
+}
+
+/// Sets the thread's deadline to the one sent with the incoming transaction
+/// `data`, until the returned scope is dropped.
+///
+/// A transaction without a deadline clears the thread's deadline, as it is
+/// not part of the call the thread was previously working on.
+pub(crate) fn enter_transaction(data: &BorrowedParcel<'_>) -> DeadlineScope {
+    DeadlineScope::enter(read_trailer(data).map(|remaining| Instant::now() + remaining))
+}
+
+fn read_trailer(data: &BorrowedParcel<'_>) -> Option<Duration> {
+    let size = data.get_data_size();
+    if size < TRAILER_SIZE {
+        return None;
+    }
+    let start = data.get_data_position();
+    // Safety: The trailer is within the parcel's data, as checked above.
+    unsafe { data.set_data_position(size - TRAILER_SIZE) }.ok()?;
+    let trailer = (data.read::<i64>(), data.read::<i64>());
+    // Safety: We are restoring the position the parcel had before.
+    unsafe { data.set_data_position(start) }.ok()?;
+    match trailer {
+        (Ok(TRAILER_MARKER), Ok(remaining)) if remaining > 0 => {
+            Some(Duration::from_nanos(remaining as u64))
+        }
+        _ => None,
+    }
+}
+
+/// A [`TransactionLayer`] which sends the deadline of the
+/// [current context](TransactionContext::current) with each transaction.
+///
+/// Transactions made once the deadline has passed fail with `TIMED_OUT`
+/// without being sent. See the [module documentation](self) for how the
+/// deadline is sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeadlinePropagation;
+
+impl TransactionLayer for DeadlinePropagation {
+    fn transact(
+        &self,
+        code: TransactionCode,
+        mut data: Parcel,
+        flags: TransactionFlags,
+        next: NextLayer<'_>,
+    ) -> Result<Parcel> {
+        if let Some(remaining) = TransactionContext::current().remaining() {
+            if remaining.is_zero() {
+                return Err(StatusCode::TIMED_OUT);
+            }
+            // Safety: The end of the data is within bounds.
+            unsafe { data.set_data_position(data.get_data_size()) }?;
+            data.write(&TRAILER_MARKER)?;
+            data.write(&i64::try_from(remaining.as_nanos()).unwrap_or(i64::MAX))?;
+        }
+        next.transact(code, data, flags)
+    }
+}
+
+/// Computes the context of each transaction a local service handles, for its
+/// handlers to get with [`provided_context`].
+pub trait ContextProvider: Send + Sync + 'static {
+    /// The type of the context.
+    type Context: Send + Sync + 'static;
+
+    /// Computes the context of a transaction with the given code.
+    ///
+    /// This is called on the binder thread which is about to handle the
+    /// transaction, so can use [`ThreadState`](crate::ThreadState) to find
+    /// out about the caller. It must not panic.
+    fn provide(&self, code: TransactionCode) -> Self::Context;
+}
+
+type ProvideFn = dyn Fn(TransactionCode) -> Arc<dyn Any + Send + Sync> + Send + Sync;
+
+// Context providers by the user data pointer of the local binder object they
+// were set for, with a count to skip the lookup when there are none.
+static PROVIDERS: RwLock<BTreeMap<usize, Arc<ProvideFn>>> = RwLock::new(BTreeMap::new());
+static PROVIDER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static PROVIDED: RefCell<Option<Arc<dyn Any + Send + Sync>>> = const { RefCell::new(None) };
+}
+
+/// Sets the context provider of the local service `binder`, replacing any it
+/// already had.
+///
+/// Fails with `INVALID_OPERATION` if `binder` is a remote binder.
+pub fn set_context_provider<P: ContextProvider>(binder: &SpIBinder, provider: P) -> Result<()> {
+    if binder.is_remote() {
+        return Err(StatusCode::INVALID_OPERATION);
+    }
+    // Safety: `SpIBinder` always contains a valid pointer to an `AIBinder`,
+    // and `AIBinder_getUserData` only reads the pointer it stores for local
+    // objects.
+    let object = unsafe { sys::AIBinder_getUserData(binder.as_native() as *mut sys::AIBinder) };
+    if object.is_null() {
+        return Err(StatusCode::BAD_TYPE);
+    }
+    let provide: Arc<ProvideFn> = Arc::new(move |code| Arc::new(provider.provide(code)));
+    if PROVIDERS.write().unwrap().insert(object as usize, provide).is_none() {
+        PROVIDER_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Returns the context computed by the [`ContextProvider`] of the local
+/// service whose transaction the current thread is handling, if it has one
+/// with context of type `C`.
+pub fn provided_context<C: Send + Sync + 'static>() -> Option<Arc<C>> {
+    PROVIDED.with(|provided| provided.borrow().clone())?.downcast().ok()
+}
+
+/// Restores the previously provided context of the thread when dropped.
+pub(crate) struct ProvidedScope {
+    previous: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Drop for ProvidedScope {
+    fn drop(&mut self) {
+        PROVIDED.with(|provided| *provided.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Computes the provided context for a transaction that the local object with
+/// user data pointer `object` is about to handle, until the returned scope is
+/// dropped.
+pub(crate) fn enter_provided(object: usize, code: TransactionCode) -> ProvidedScope {
+    let provide = if PROVIDER_COUNT.load(Ordering::Relaxed) == 0 {
+        None
+    } else {
+        PROVIDERS.read().unwrap().get(&object).cloned()
+    };
+    let context = provide.map(|provide| provide(code));
+    ProvidedScope { previous: PROVIDED.with(|provided| provided.replace(context)) }
+}
+
+/// Forgets the context provider of a local object which is being destroyed.
+pub(crate) fn on_object_destroyed(object: usize) {
+    if PROVIDER_COUNT.load(Ordering::Relaxed) != 0
+        && PROVIDERS.write().unwrap().remove(&object).is_some()
+    {
+        PROVIDER_COUNT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder::{IBinderInternal, Interface, Remotable, FIRST_CALL_TRANSACTION};
+    use crate::native::Binder;
+    use std::ffi::CStr;
+    use std::io::Write;
+
+    /// Replies with the number of milliseconds left until its caller's
+    /// deadline, or -1 if there is none.
+    struct Remaining;
+
+    impl Remotable for Remaining {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.IRemaining"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            assert_eq!(data.read::<i32>()?, 42);
+            let remaining = TransactionContext::current().remaining();
+            reply.write(&remaining.map_or(-1, |remaining| remaining.as_millis() as i64))
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    #[test]
+    fn deadline_reaches_callee() {
+        let service = Binder::new(Remaining);
+        let binder = service.as_binder().with_layer(Arc::new(DeadlinePropagation));
+        let call = || -> Result<i64> {
+            binder.transact(FIRST_CALL_TRANSACTION, 0, |mut data| data.write(&42))?.read()
+        };
+
+        assert_eq!(call(), Ok(-1));
+        let remaining = with_deadline(Instant::now() + Duration::from_secs(10), call).unwrap();
+        assert!((1..=10_000).contains(&remaining), "{remaining}ms remaining");
+        assert_eq!(TransactionContext::current().deadline(), None);
+
+        let expired = with_deadline(Instant::now(), call);
+        assert_eq!(expired, Err(StatusCode::TIMED_OUT));
+    }
+
+    /// Replies with the context its provider gave it.
+    struct Echo;
+
+    impl Remotable for Echo {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.IEchoContext"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            reply.write(&provided_context::<String>().as_deref().cloned())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    struct CodeName;
+
+    impl ContextProvider for CodeName {
+        type Context = String;
+
+        fn provide(&self, code: TransactionCode) -> String {
+            format!("code {code}")
+        }
+    }
+
+    #[test]
+    fn provided_context_reaches_handler() {
+        let service = Binder::new(Echo);
+        let binder = service.as_binder();
+        let call = || -> Result<Option<String>> {
+            binder.transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(()))?.read()
+        };
+
+        assert_eq!(call(), Ok(None));
+        set_context_provider(&binder, CodeName).unwrap();
+        assert_eq!(call(), Ok(Some(format!("code {FIRST_CALL_TRANSACTION}"))));
+        assert!(provided_context::<String>().is_none());
+    }
+
+    #[test]
+    fn earlier_deadline_is_kept() {
+        let soon = Instant::now() + Duration::from_secs(1);
+        let later = soon + Duration::from_secs(1);
+        with_deadline(soon, || {
+            let deadline = with_deadline(later, || TransactionContext::current().deadline());
+            assert_eq!(deadline, Some(soon));
+        });
+    }
+}
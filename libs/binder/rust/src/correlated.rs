@@ -0,0 +1,339 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Request/response calls made of a pair of oneway transactions.
+//!
+//! A service which must never block on its clients, or a client which must
+//! never block on a slow service, can't use synchronous transactions.
+//! Instead the client sends a oneway request with a callback binder, and the
+//! service sends the response to the callback as another oneway transaction,
+//! whenever it is ready. [`CorrelatedChannel`] implements the client side of
+//! this, matching responses to requests, and [`CorrelatedRequest`] the
+//! service side.
+//!
+//! A request transaction contains:
+//!
+//! * `int64` correlation ID, unique per channel;
+//! * `IBinder` callback;
+//! * the request payload.
+//!
+//! The response is sent to the callback with code `FIRST_CALL_TRANSACTION`,
+//! and contains:
+//!
+//! * `int64` correlation ID of the request;
+//! * `int32` status, `OK` or the error the service failed the request with;
+//! * the response payload, if the status is `OK`.
+
+use crate::binder::{
+    IBinder, IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+    FLAG_ONEWAY,
+};
+use crate::error::{status_result, Result, StatusCode};
+use crate::native::Binder;
+use crate::parcel::{BorrowedParcel, Parcel};
+use crate::proxy::{DeathRecipient, SpIBinder};
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RESPONSE_CODE: TransactionCode = FIRST_CALL_TRANSACTION;
+
+type PendingCalls = Mutex<HashMap<i64, SyncSender<Result<Parcel>>>>;
+
+/// The local binder which receives responses for a channel.
+struct ResponseCallback {
+    pending: Arc<PendingCalls>,
+}
+
+impl Remotable for ResponseCallback {
+    fn get_descriptor() -> &'static str {
+        "android.os.ICorrelatedResponseCallback"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        if code != RESPONSE_CODE {
+            return Err(StatusCode::UNKNOWN_TRANSACTION);
+        }
+        let id: i64 = data.read()?;
+        let status = data.read::<i32>()?;
+        // The call may have timed out and been forgotten, in which case the
+        // response is dropped.
+        let Some(sender) = self.pending.lock().unwrap().remove(&id) else {
+            return Ok(());
+        };
+        let response = status_result(status).and_then(|()| {
+            let mut response = Parcel::try_new()?;
+            let start = data.get_data_position();
+            response.append_from(data, start, data.get_data_size() - start)?;
+            // Appending leaves the position at the end.
+            // Safety: 0 is always a valid position.
+            unsafe {
+                response.set_data_position(0)?;
+            }
+            Ok(response)
+        });
+        // The receiving end may have been dropped since we removed it.
+        let _ = sender.send(response);
+        Ok(())
+    }
+
+    fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// The client side of request/response calls over oneway transactions.
+///
+/// Each channel has its own callback binder, which is sent with every
+/// request. If the service dies, all calls waiting for a response fail with
+/// `DEAD_OBJECT`.
+///
+/// ```ignore
+/// let channel = CorrelatedChannel::new(service.as_binder(), TRANSACTION_GET_THUMBNAIL)?;
+/// let response = channel.call(Duration::from_millis(200), |request| request.write(&uri))?;
+/// let thumbnail: Vec<u8> = response.read()?;
+/// ```
+pub struct CorrelatedChannel {
+    remote: SpIBinder,
+    code: TransactionCode,
+    callback: Binder<ResponseCallback>,
+    next_id: Mutex<i64>,
+    // Kept alive so that pending calls fail when the service dies.
+    _death_recipient: Option<DeathRecipient>,
+}
+
+impl CorrelatedChannel {
+    /// Create a channel which sends requests to `remote` as oneway
+    /// transactions with the given `code`.
+    pub fn new(mut remote: SpIBinder, code: TransactionCode) -> Result<Self> {
+        let pending = Arc::new(PendingCalls::default());
+        let weak_pending = Arc::downgrade(&pending);
+        // Local binders can't die, and don't accept death recipients.
+        let death_recipient = if remote.is_remote() {
+            let mut death_recipient = DeathRecipient::new(move || {
+                if let Some(pending) = weak_pending.upgrade() {
+                    for (_, sender) in pending.lock().unwrap().drain() {
+                        let _ = sender.send(Err(StatusCode::DEAD_OBJECT));
+                    }
+                }
+            });
+            remote.link_to_death(&mut death_recipient)?;
+            Some(death_recipient)
+        } else {
+            None
+        };
+        Ok(Self {
+            remote,
+            code,
+            callback: Binder::new(ResponseCallback { pending }),
+            next_id: Mutex::new(0),
+            _death_recipient: death_recipient,
+        })
+    }
+
+    /// Send a request, with a payload written by `write_request`, without
+    /// waiting for the response.
+    ///
+    /// Dropping the returned [`PendingCall`] without waiting for it discards
+    /// the response when it arrives.
+    pub fn send<F>(&self, write_request: F) -> Result<PendingCall>
+    where
+        F: FnOnce(&mut BorrowedParcel<'_>) -> Result<()>,
+    {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id = next_id.wrapping_add(1);
+            *next_id
+        };
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.callback.pending.lock().unwrap().insert(id, sender);
+        let call = PendingCall { id, receiver, pending: self.callback.pending.clone() };
+
+        let mut data = self.remote.prepare_transact()?;
+        {
+            let mut data = data.borrowed();
+            data.write(&id)?;
+            data.write(&self.callback.as_binder())?;
+            write_request(&mut data)?;
+        }
+        self.remote.submit_transact(self.code, data, FLAG_ONEWAY)?;
+        Ok(call)
+    }
+
+    /// Send a request and wait up to `timeout` for its response.
+    ///
+    /// Fails with `TIMED_OUT` if there is no response in time, or with the
+    /// error the service failed the request with.
+    pub fn call<F>(&self, timeout: Duration, write_request: F) -> Result<Parcel>
+    where
+        F: FnOnce(&mut BorrowedParcel<'_>) -> Result<()>,
+    {
+        self.send(write_request)?.wait(timeout)
+    }
+
+    /// The number of calls which are still waiting for a response.
+    pub fn pending_calls(&self) -> usize {
+        self.callback.pending.lock().unwrap().len()
+    }
+}
+
+/// A request sent by [`CorrelatedChannel::send`] whose response has not been
+/// received yet.
+#[derive(Debug)]
+pub struct PendingCall {
+    id: i64,
+    receiver: Receiver<Result<Parcel>>,
+    pending: Arc<PendingCalls>,
+}
+
+impl PendingCall {
+    /// Wait up to `timeout` for the response.
+    ///
+    /// Fails with `TIMED_OUT` if there is no response in time, in which case
+    /// the call is forgotten and a later response is discarded.
+    pub fn wait(self, timeout: Duration) -> Result<Parcel> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => Err(StatusCode::TIMED_OUT),
+            Err(RecvTimeoutError::Disconnected) => Err(StatusCode::DEAD_OBJECT),
+        }
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// The service side of a request sent by a [`CorrelatedChannel`].
+///
+/// The service reads this from the start of the request transaction, then
+/// reads the request payload, and may respond at any time later, from any
+/// thread.
+#[derive(Debug)]
+pub struct CorrelatedRequest {
+    id: i64,
+    callback: SpIBinder,
+}
+
+impl CorrelatedRequest {
+    /// Read the correlation header of a request.
+    pub fn read(data: &BorrowedParcel<'_>) -> Result<Self> {
+        Ok(Self { id: data.read()?, callback: data.read()? })
+    }
+
+    /// Send the response, with a payload written by `write_response`.
+    pub fn respond<F>(self, write_response: F) -> Result<()>
+    where
+        F: FnOnce(&mut BorrowedParcel<'_>) -> Result<()>,
+    {
+        self.send(StatusCode::OK, write_response)
+    }
+
+    /// Fail the request with `status`, which must not be `OK`.
+    pub fn fail(self, status: StatusCode) -> Result<()> {
+        if status == StatusCode::OK {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        self.send(status, |_| Ok(()))
+    }
+
+    fn send<F>(self, status: StatusCode, write_response: F) -> Result<()>
+    where
+        F: FnOnce(&mut BorrowedParcel<'_>) -> Result<()>,
+    {
+        let mut data = self.callback.prepare_transact()?;
+        {
+            let mut data = data.borrowed();
+            data.write(&self.id)?;
+            data.write(&(status as i32))?;
+            write_response(&mut data)?;
+        }
+        self.callback.submit_transact(RESPONSE_CODE, data, FLAG_ONEWAY)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Doubles the number it is sent, or fails negative numbers.
+    struct Doubler {
+        held: Mutex<Vec<CorrelatedRequest>>,
+    }
+
+    impl Remotable for Doubler {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.IDoubler"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            let request = CorrelatedRequest::read(data)?;
+            match data.read::<i32>()? {
+                0 => self.held.lock().unwrap().push(request),
+                n if n < 0 => request.fail(StatusCode::BAD_VALUE)?,
+                n => request.respond(|response| response.write(&(n * 2)))?,
+            }
+            Ok(())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    #[test]
+    fn responses_match_requests() {
+        let service = Binder::new(Doubler { held: Mutex::new(Vec::new()) });
+        let channel = CorrelatedChannel::new(service.as_binder(), FIRST_CALL_TRANSACTION).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        let response = channel.call(timeout, |request| request.write(&21)).unwrap();
+        assert_eq!(response.read::<i32>(), Ok(42));
+        let error = channel.call(timeout, |request| request.write(&-1)).unwrap_err();
+        assert_eq!(error, StatusCode::BAD_VALUE);
+
+        // The service holds on to this request without responding.
+        let held = channel.send(|request| request.write(&0)).unwrap();
+        assert_eq!(channel.pending_calls(), 1);
+        assert_eq!(held.wait(Duration::from_millis(10)).unwrap_err(), StatusCode::TIMED_OUT);
+        assert_eq!(channel.pending_calls(), 0);
+
+        // A response to a forgotten call is dropped.
+        let request = service.held.lock().unwrap().pop().unwrap();
+        assert_eq!(request.respond(|response| response.write(&0)), Ok(()));
+    }
+}
@@ -0,0 +1,1422 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Debugging aids for binder services.
+
+use crate::binder::{AsNative, TransactionCode};
+use crate::error::StatusCode;
+use crate::parcel::{BorrowedParcel, Parcel};
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// A copy of an incoming transaction that a local service failed to
+/// deserialize.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedTransaction {
+    /// Interface descriptor of the service that received the transaction.
+    pub descriptor: &'static str,
+    /// The transaction code.
+    pub code: TransactionCode,
+    /// The error the service returned.
+    pub status: StatusCode,
+    /// The data position in the parcel when the service returned the error.
+    ///
+    /// This is usually just past the value that failed to deserialize.
+    pub position: i32,
+    /// The total size of the parcel data, in bytes.
+    pub data_size: i32,
+    /// The start of the parcel data.
+    ///
+    /// This is at most the size limit given to
+    /// [`set_failed_transaction_callback`], so it may be shorter than
+    /// `data_size`. The words of binder and file descriptor objects are
+    /// zeroed, so nothing from them is copied out of the parcel.
+    pub data: Vec<u8>,
+    /// The offsets in `data` of the 32-bit words which were zeroed because
+    /// they belong to binder or file descriptor objects.
+    pub object_offsets: Vec<i32>,
+}
+
+type FailedTransactionCallback = Arc<dyn Fn(&FailedTransaction) + Send + Sync>;
+
+static CAPTURE_ENABLED: AtomicBool = AtomicBool::new(false);
+static CAPTURE: RwLock<Option<(usize, FailedTransactionCallback)>> = RwLock::new(None);
+
+/// Register a callback that receives a copy of every incoming transaction
+/// which a local service in this process fails to deserialize.
+///
+/// At most `max_bytes` of parcel data are captured per transaction. Passing
+/// `None` removes the callback. The callback runs on the binder thread that
+/// handled the transaction, before the error is returned to the caller, so it
+/// should be quick; a typical callback logs the capture for bugreports. It may
+/// replace or remove itself.
+///
+/// This is intended for debugging and costs nothing while no callback is set.
+/// Captured parcels may contain sensitive data and should be treated
+/// accordingly.
+pub fn set_failed_transaction_callback<F>(max_bytes: usize, callback: Option<F>)
+where
+    F: Fn(&FailedTransaction) + Send + Sync + 'static,
+{
+    let mut capture = CAPTURE.write().unwrap();
+    *capture = callback.map(|f| (max_bytes, Arc::new(f) as FailedTransactionCallback));
+    CAPTURE_ENABLED.store(capture.is_some(), Ordering::Release);
+}
+
+/// Whether `status` is one that deserialization code returns.
+fn is_deserialization_error(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_VALUE
+            | StatusCode::BAD_TYPE
+            | StatusCode::NOT_ENOUGH_DATA
+            | StatusCode::UNEXPECTED_NULL
+    )
+}
+
+/// Called by local services after `on_transact` fails.
+pub(crate) fn on_transaction_failed(
+    descriptor: &'static str,
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+    status: StatusCode,
+) {
+    if !CAPTURE_ENABLED.load(Ordering::Acquire) || !is_deserialization_error(status) {
+        return;
+    }
+    // Clone the callback out so that it runs without the lock held, and so
+    // can set a new callback without deadlocking.
+    let Some((max_bytes, callback)) = CAPTURE.read().unwrap().clone() else {
+        return;
+    };
+    let position = data.get_data_position();
+    let data_size = data.get_data_size();
+    let (captured, object_offsets) = capture_data(data, max_bytes);
+    // Safety: `position` was the data position before we read from the
+    // parcel, so it is within its bounds.
+    let _ = unsafe { data.set_data_position(position) };
+    callback(&FailedTransaction {
+        descriptor,
+        code,
+        status,
+        position,
+        data_size,
+        data: captured,
+        object_offsets,
+    });
+}
+
+/// Copies up to `max_bytes` of data from the start of `parcel`, and returns it
+/// along with the offsets of the words which belong to objects.
+///
+/// The data is read as plain 32-bit words, rather than as objects, so no file
+/// descriptors or binders are taken from the parcel. Parcels refuse plain
+/// reads of object data, so those words are zeroed and skipped. This leaves
+/// the data position of `parcel` unspecified.
+fn capture_data(parcel: &BorrowedParcel<'_>, max_bytes: usize) -> (Vec<u8>, Vec<i32>) {
+    let size = usize::try_from(parcel.get_data_size()).unwrap_or(0).min(max_bytes);
+    let mut captured = Vec::with_capacity(size);
+    let mut object_offsets = vec![];
+    while captured.len() < size {
+        // `captured.len()` is at most the parcel data size, which is an i32.
+        let offset = captured.len() as i32;
+        // Safety: `offset` is less than `size`, which is at most the current
+        // size of the parcel data buffer.
+        if unsafe { parcel.set_data_position(offset) }.is_err() {
+            break;
+        }
+        let bytes = match parcel.read::<i32>() {
+            Ok(word) => word.to_ne_bytes(),
+            Err(_) => {
+                object_offsets.push(offset);
+                [0; 4]
+            }
+        };
+        let remaining = size - captured.len();
+        captured.extend_from_slice(&bytes[..remaining.min(bytes.len())]);
+    }
+    (captured, object_offsets)
+}
+
+/// Which side of a transaction an [`UnconsumedParcel`] was found on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParcelDirection {
+    /// The request parcel, as read by the local service.
+    Request,
+    /// The reply parcel, as read by the client.
+    Reply,
+}
+
+/// A transaction parcel which was not fully read, found by the checks enabled
+/// with [`verify_parcel_consumption`] or [`with_parcel_consumption_check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnconsumedParcel {
+    /// Interface descriptor of the binder the transaction was sent to.
+    pub descriptor: String,
+    /// The transaction code.
+    pub code: TransactionCode,
+    /// Whether the request or the reply was not fully read.
+    pub direction: ParcelDirection,
+    /// How many bytes were left after the last read.
+    pub unconsumed_bytes: usize,
+}
+
+impl fmt::Display for UnconsumedParcel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = match self.direction {
+            ParcelDirection::Request => "request",
+            ParcelDirection::Reply => "reply",
+        };
+        write!(
+            f,
+            "{} bytes of {} data left unread in transaction {} of {}",
+            self.unconsumed_bytes, direction, self.code, self.descriptor
+        )
+    }
+}
+
+type UnconsumedParcelCallback = Box<dyn Fn(&UnconsumedParcel) + Send + Sync>;
+
+// Number of interfaces with consumption checks enabled plus the number of
+// active `with_parcel_consumption_check` scopes, across all threads.
+static CONSUMPTION_CHECKS: AtomicUsize = AtomicUsize::new(0);
+static CONSUMPTION_CHECKED_INTERFACES: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+static UNCONSUMED_PARCEL_CALLBACK: RwLock<Option<UnconsumedParcelCallback>> = RwLock::new(None);
+// Replies which are checked when they are dropped, keyed by parcel address.
+// Entries are removed whenever the parcel is released, even if the checks have
+// been disabled since, so an address is never matched to a stale entry.
+static TRACKED_REPLIES: Mutex<BTreeMap<usize, (String, TransactionCode)>> =
+    Mutex::new(BTreeMap::new());
+// Number of entries in `TRACKED_REPLIES`, so that releasing a parcel needn't
+// take the lock while there are none.
+static TRACKED_REPLY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CONSUMPTION_CHECK_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Enable or disable checking that transaction parcels for interface
+/// `descriptor` are read to the end.
+///
+/// When enabled, every request handled by a local service with this interface
+/// is checked after the service's `on_transact` succeeds, and every reply
+/// received from a remote binder of this interface is checked when the client
+/// drops it. Data left unread usually means that the two sides disagree about
+/// the interface, e.g. because one side was built against a newer version.
+///
+/// Problems are reported to the callback set with
+/// [`set_unconsumed_parcel_callback`], or printed to stderr if there is none.
+/// These checks cost nothing while no interface has them enabled.
+pub fn verify_parcel_consumption(descriptor: &str, enable: bool) {
+    let mut interfaces = CONSUMPTION_CHECKED_INTERFACES.write().unwrap();
+    if enable {
+        if interfaces.insert(descriptor.to_owned()) {
+            CONSUMPTION_CHECKS.fetch_add(1, Ordering::AcqRel);
+        }
+    } else if interfaces.remove(descriptor) {
+        CONSUMPTION_CHECKS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Call `f`, checking that the reply of every transaction it sends from this
+/// thread is read to the end, whatever the interface.
+///
+/// See [`verify_parcel_consumption`] for how problems are reported.
+pub fn with_parcel_consumption_check<T>(f: impl FnOnce() -> T) -> T {
+    struct ScopeGuard;
+
+    impl Drop for ScopeGuard {
+        fn drop(&mut self) {
+            CONSUMPTION_CHECK_DEPTH.with(|depth| depth.set(depth.get() - 1));
+            CONSUMPTION_CHECKS.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    CONSUMPTION_CHECKS.fetch_add(1, Ordering::AcqRel);
+    CONSUMPTION_CHECK_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let _guard = ScopeGuard;
+    f()
+}
+
+/// Set the callback which receives parcels found by the checks enabled with
+/// [`verify_parcel_consumption`] or [`with_parcel_consumption_check`].
+///
+/// Passing `None` restores the default of printing them to stderr.
+pub fn set_unconsumed_parcel_callback<F>(callback: Option<F>)
+where
+    F: Fn(&UnconsumedParcel) + Send + Sync + 'static,
+{
+    *UNCONSUMED_PARCEL_CALLBACK.write().unwrap() =
+        callback.map(|f| Box::new(f) as UnconsumedParcelCallback);
+}
+
+fn is_consumption_checked(descriptor: &str) -> bool {
+    CONSUMPTION_CHECK_DEPTH.with(|depth| depth.get() > 0)
+        || CONSUMPTION_CHECKED_INTERFACES.read().unwrap().contains(descriptor)
+}
+
+fn report_unconsumed(
+    parcel: &BorrowedParcel<'_>,
+    descriptor: &str,
+    code: TransactionCode,
+    direction: ParcelDirection,
+) {
+    let unconsumed = parcel.get_data_size() - parcel.get_data_position();
+    if unconsumed <= 0 {
+        return;
+    }
+    let unconsumed = UnconsumedParcel {
+        descriptor: descriptor.to_owned(),
+        code,
+        direction,
+        unconsumed_bytes: unconsumed as usize,
+    };
+    match UNCONSUMED_PARCEL_CALLBACK.read().unwrap().as_ref() {
+        Some(callback) => callback(&unconsumed),
+        None => {
+            let calling_uid = match direction {
+                ParcelDirection::Request => Some(crate::ThreadState::get_calling_uid()),
+                ParcelDirection::Reply => None,
+            };
+            log_transaction_failure(descriptor, code, None, calling_uid, &unconsumed)
+        }
+    }
+}
+
+/// Logs a problem with a transaction which the crate reports itself.
+///
+/// With the `log` feature this goes through the `log` crate, with the
+/// interface descriptor, transaction code, status and calling UID as
+/// key-values so that they can be filtered on. Otherwise it is printed to
+/// stderr.
+pub(crate) fn log_transaction_failure(
+    descriptor: &str,
+    code: TransactionCode,
+    status: Option<StatusCode>,
+    calling_uid: Option<u32>,
+    message: &dyn fmt::Display,
+) {
+    #[cfg(feature = "log")]
+    log::error!(
+        interface = descriptor,
+        code = code,
+        status = status.map(|status| status as i32),
+        calling_uid = calling_uid;
+        "{message}"
+    );
+    #[cfg(not(feature = "log"))]
+    {
+        let _ = (descriptor, code, status, calling_uid);
+        eprintln!("{}", message);
+    }
+}
+
+/// Called by local services after `on_transact` succeeds.
+pub(crate) fn on_request_handled(
+    descriptor: &str,
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+) {
+    if CONSUMPTION_CHECKS.load(Ordering::Acquire) == 0
+        || !CONSUMPTION_CHECKED_INTERFACES.read().unwrap().contains(descriptor)
+    {
+        return;
+    }
+    report_unconsumed(data, descriptor, code, ParcelDirection::Request);
+}
+
+/// Called by proxies when they receive a reply. `descriptor` is only called
+/// if any consumption checks are enabled.
+pub(crate) fn on_reply_received(
+    descriptor: impl FnOnce() -> Option<String>,
+    code: TransactionCode,
+    reply: &BorrowedParcel<'_>,
+) {
+    if CONSUMPTION_CHECKS.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let descriptor = descriptor().unwrap_or_default();
+    if is_consumption_checked(&descriptor) {
+        let mut tracked = TRACKED_REPLIES.lock().unwrap();
+        if tracked.insert(reply.as_native() as usize, (descriptor, code)).is_none() {
+            TRACKED_REPLY_COUNT.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Called when an owned parcel is dropped or released to a raw pointer. If it
+/// is a tracked reply, `check` says whether to report unread data.
+pub(crate) fn on_parcel_released(parcel: &BorrowedParcel<'_>, check: bool) {
+    if TRACKED_REPLY_COUNT.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let tracked = TRACKED_REPLIES.lock().unwrap().remove(&(parcel.as_native() as usize));
+    if tracked.is_some() {
+        TRACKED_REPLY_COUNT.fetch_sub(1, Ordering::AcqRel);
+    }
+    if let (Some((descriptor, code)), true) = (tracked, check) {
+        report_unconsumed(parcel, &descriptor, code, ParcelDirection::Reply);
+    }
+}
+
+#[cfg(feature = "track_transactions")]
+pub(crate) use self::outstanding::track_outgoing;
+#[cfg(feature = "track_transactions")]
+pub use self::outstanding::{
+    dump_outstanding_transactions, outstanding_transactions, OutstandingTransaction,
+};
+
+/// Tracking of outgoing synchronous transactions, to find out which threads
+/// are blocked waiting for which remote services, e.g. when a process hangs.
+///
+/// Capturing a backtrace for every transaction is too slow to leave on in
+/// production, hence the feature.
+#[cfg(feature = "track_transactions")]
+mod outstanding {
+    use crate::binder::TransactionCode;
+
+    use std::backtrace::Backtrace;
+    use std::collections::BTreeMap;
+    use std::io::{self, Write};
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+    use std::time::{Duration, Instant};
+
+    struct Outgoing {
+        descriptor: Option<String>,
+        code: TransactionCode,
+        thread_id: ThreadId,
+        thread_name: Option<String>,
+        started: Instant,
+        backtrace: Backtrace,
+    }
+
+    static OUTSTANDING: Mutex<(u64, BTreeMap<u64, Outgoing>)> = Mutex::new((0, BTreeMap::new()));
+
+    /// An outgoing synchronous transaction which has not received its reply
+    /// yet.
+    #[derive(Clone, Debug)]
+    pub struct OutstandingTransaction {
+        /// The interface descriptor of the binder, if it has an interface
+        /// class.
+        pub descriptor: Option<String>,
+        /// The transaction code, which identifies the method called.
+        pub code: TransactionCode,
+        /// The thread which sent the transaction and is waiting for the reply.
+        pub thread_id: ThreadId,
+        /// The name of that thread, if it has one.
+        pub thread_name: Option<String>,
+        /// How long ago the transaction was sent.
+        pub elapsed: Duration,
+        /// Where the transaction was sent from.
+        pub backtrace: String,
+    }
+
+    /// Removes the transaction from the outstanding set when dropped.
+    pub(crate) struct OutgoingGuard(u64);
+
+    impl Drop for OutgoingGuard {
+        fn drop(&mut self) {
+            OUTSTANDING.lock().unwrap().1.remove(&self.0);
+        }
+    }
+
+    /// Called by proxies before sending a synchronous transaction.
+    pub(crate) fn track_outgoing(
+        descriptor: Option<String>,
+        code: TransactionCode,
+    ) -> OutgoingGuard {
+        let thread = thread::current();
+        let outgoing = Outgoing {
+            descriptor,
+            code,
+            thread_id: thread.id(),
+            thread_name: thread.name().map(str::to_owned),
+            started: Instant::now(),
+            backtrace: Backtrace::force_capture(),
+        };
+        let mut outstanding = OUTSTANDING.lock().unwrap();
+        let (next_id, transactions) = &mut *outstanding;
+        let id = *next_id;
+        *next_id += 1;
+        transactions.insert(id, outgoing);
+        OutgoingGuard(id)
+    }
+
+    /// Returns the outgoing synchronous transactions from this process which
+    /// are still waiting for a reply, oldest first.
+    pub fn outstanding_transactions() -> Vec<OutstandingTransaction> {
+        let now = Instant::now();
+        OUTSTANDING
+            .lock()
+            .unwrap()
+            .1
+            .values()
+            .map(|outgoing| OutstandingTransaction {
+                descriptor: outgoing.descriptor.clone(),
+                code: outgoing.code,
+                thread_id: outgoing.thread_id,
+                thread_name: outgoing.thread_name.clone(),
+                elapsed: now.saturating_duration_since(outgoing.started),
+                backtrace: outgoing.backtrace.to_string(),
+            })
+            .collect()
+    }
+
+    /// Writes the outstanding transactions with their backtraces to `writer`,
+    /// e.g. from a service's `dump` handler or a watchdog.
+    pub fn dump_outstanding_transactions(writer: &mut dyn Write) -> io::Result<()> {
+        let transactions = outstanding_transactions();
+        writeln!(writer, "{} outstanding binder transactions:", transactions.len())?;
+        for transaction in transactions {
+            writeln!(
+                writer,
+                "  {} code {} from thread {} ({:?}), waiting for {:?}:",
+                transaction.descriptor.as_deref().unwrap_or("<unknown interface>"),
+                transaction.code,
+                transaction.thread_name.as_deref().unwrap_or("<unnamed>"),
+                transaction.thread_id,
+                transaction.elapsed,
+            )?;
+            for line in transaction.backtrace.lines() {
+                writeln!(writer, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn tracks_until_dropped() {
+            let guard = track_outgoing(Some("android.test.IOutstanding".to_owned()), 7);
+            let outstanding = outstanding_transactions();
+            let transaction = outstanding
+                .iter()
+                .find(|t| t.descriptor.as_deref() == Some("android.test.IOutstanding"))
+                .expect("Transaction not tracked");
+            assert_eq!(transaction.code, 7);
+            assert_eq!(transaction.thread_id, thread::current().id());
+            assert!(transaction.backtrace.contains("tracks_until_dropped"));
+
+            let mut dump = Vec::new();
+            dump_outstanding_transactions(&mut dump).unwrap();
+            assert!(String::from_utf8(dump).unwrap().contains("android.test.IOutstanding code 7"));
+
+            drop(guard);
+            assert!(outstanding_transactions()
+                .iter()
+                .all(|t| t.descriptor.as_deref() != Some("android.test.IOutstanding")));
+        }
+    }
+}
+
+pub use self::topology::{dump_topology, topology, Topology};
+pub(crate) use self::topology::{on_local_created, on_local_destroyed, on_service_registered};
+#[doc(hidden)]
+pub use self::topology::{ProxyCount, ProxyRecord};
+
+/// Tracking of the interfaces this process holds proxies to and the services
+/// it exports, from which tooling can reconstruct the dependency graph
+/// between processes, e.g. from the dumps in a bugreport.
+mod topology {
+    use std::collections::BTreeMap;
+    use std::io::{self, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, Once};
+
+    static PROXIES: Mutex<Vec<(&'static str, &'static ProxyCount)>> = Mutex::new(Vec::new());
+    static LOCAL_SERVICES: Mutex<BTreeMap<&'static str, usize>> = Mutex::new(BTreeMap::new());
+    static REGISTERED: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+    /// The binder objects this process holds and exports, as returned by
+    /// [`topology`].
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Topology {
+        /// The number of interface proxies for remote objects this process
+        /// holds, by interface descriptor.
+        pub proxies: BTreeMap<String, usize>,
+        /// The number of local binder objects this process has created that
+        /// are still alive, by interface descriptor.
+        pub local_services: BTreeMap<String, usize>,
+        /// The services this process has registered with the service
+        /// manager, by name, with their interface descriptors if known.
+        pub registered_services: BTreeMap<String, Option<String>>,
+    }
+
+    fn increment(counts: &Mutex<BTreeMap<&'static str, usize>>, descriptor: &'static str) {
+        *counts.lock().unwrap().entry(descriptor).or_default() += 1;
+    }
+
+    fn decrement(counts: &Mutex<BTreeMap<&'static str, usize>>, descriptor: &'static str) {
+        let mut counts = counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(descriptor) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(descriptor);
+            }
+        }
+    }
+
+    /// The number of live proxies of one proxy type. Each proxy type declared
+    /// with `declare_binder_interface!` has its own static count, so that
+    /// creating and dropping proxies doesn't take a lock.
+    #[derive(Debug)]
+    pub struct ProxyCount {
+        count: AtomicUsize,
+        registered: Once,
+    }
+
+    impl ProxyCount {
+        /// A count with no proxies.
+        pub const fn new() -> Self {
+            Self { count: AtomicUsize::new(0), registered: Once::new() }
+        }
+    }
+
+    impl Default for ProxyCount {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Counts an interface proxy for as long as it is alive. Every proxy
+    /// declared with `declare_binder_interface!` holds one.
+    #[derive(Debug)]
+    pub struct ProxyRecord(&'static ProxyCount);
+
+    impl ProxyRecord {
+        /// Start counting a proxy for the given interface in `count`.
+        pub fn new(count: &'static ProxyCount, descriptor: &'static str) -> Self {
+            count.registered.call_once(|| PROXIES.lock().unwrap().push((descriptor, count)));
+            count.count.fetch_add(1, Ordering::Relaxed);
+            Self(count)
+        }
+    }
+
+    impl Drop for ProxyRecord {
+        fn drop(&mut self) {
+            self.0.count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn on_local_created(descriptor: &'static str) {
+        increment(&LOCAL_SERVICES, descriptor);
+    }
+
+    pub(crate) fn on_local_destroyed(descriptor: &'static str) {
+        decrement(&LOCAL_SERVICES, descriptor);
+    }
+
+    pub(crate) fn on_service_registered(name: &str, descriptor: Option<String>) {
+        REGISTERED.lock().unwrap().insert(name.to_owned(), descriptor);
+    }
+
+    /// Returns the interfaces this process currently holds proxies to and the
+    /// local services it exports.
+    pub fn topology() -> Topology {
+        let owned = |counts: &Mutex<BTreeMap<&'static str, usize>>| {
+            counts.lock().unwrap().iter().map(|(&d, &n)| (d.to_owned(), n)).collect()
+        };
+        let mut proxies = BTreeMap::new();
+        for &(descriptor, count) in PROXIES.lock().unwrap().iter() {
+            let count = count.count.load(Ordering::Relaxed);
+            if count > 0 {
+                *proxies.entry(descriptor.to_owned()).or_default() += count;
+            }
+        }
+        Topology {
+            proxies,
+            local_services: owned(&LOCAL_SERVICES),
+            registered_services: REGISTERED.lock().unwrap().clone(),
+        }
+    }
+
+    /// Writes the [`topology`] of this process to `writer`, e.g. from a
+    /// service's `dump` handler.
+    pub fn dump_topology(writer: &mut dyn Write) -> io::Result<()> {
+        let topology = topology();
+        writeln!(writer, "Registered services:")?;
+        for (name, descriptor) in &topology.registered_services {
+            let descriptor = descriptor.as_deref().unwrap_or("<unknown interface>");
+            writeln!(writer, "  {name}: {descriptor}")?;
+        }
+        writeln!(writer, "Local binder objects:")?;
+        for (descriptor, count) in &topology.local_services {
+            writeln!(writer, "  {descriptor}: {count}")?;
+        }
+        writeln!(writer, "Proxies held:")?;
+        for (descriptor, count) in &topology.proxies {
+            writeln!(writer, "  {descriptor}: {count}")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn counts_live_objects() {
+            static COUNT: ProxyCount = ProxyCount::new();
+            let descriptor = "android.test.ITopology";
+            let first = ProxyRecord::new(&COUNT, descriptor);
+            let second = ProxyRecord::new(&COUNT, descriptor);
+            on_local_created(descriptor);
+            on_service_registered("topology_test", Some(descriptor.to_owned()));
+
+            let current = topology();
+            assert_eq!(current.proxies.get(descriptor), Some(&2));
+            assert_eq!(current.local_services.get(descriptor), Some(&1));
+            assert_eq!(
+                current.registered_services.get("topology_test"),
+                Some(&Some(descriptor.to_owned()))
+            );
+            let mut dump = Vec::new();
+            dump_topology(&mut dump).unwrap();
+            assert!(String::from_utf8(dump).unwrap().contains("topology_test: android.test."));
+
+            drop(first);
+            drop(second);
+            on_local_destroyed(descriptor);
+            let current = topology();
+            assert!(!current.proxies.contains_key(descriptor));
+            assert!(!current.local_services.contains_key(descriptor));
+        }
+    }
+}
+
+/// The type of a method argument or parcelable field, as declared in AIDL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParcelType {
+    /// `boolean`
+    Boolean,
+    /// `byte`
+    Byte,
+    /// `char`
+    Char,
+    /// `int`
+    Int,
+    /// `long`
+    Long,
+    /// `float`
+    Float,
+    /// `double`
+    Double,
+    /// `String`
+    String,
+    /// `IBinder` or an interface. Binder objects are not part of captured
+    /// parcel data, so decoding stops at arguments of this type.
+    Binder,
+    /// `ParcelFileDescriptor`. File descriptors are not part of captured
+    /// parcel data, so decoding stops at arguments of this type.
+    FileDescriptor,
+    /// An array or `List` of the given element type.
+    Array(Box<ParcelType>),
+    /// A `@nullable` value of the given type.
+    Nullable(Box<ParcelType>),
+    /// A structured parcelable with the given name and fields, in
+    /// declaration order.
+    Parcelable(String, Vec<(String, ParcelType)>),
+}
+
+/// Describes one method of an interface, for [`decode`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodMetadata {
+    /// The transaction code of the method.
+    pub code: TransactionCode,
+    /// The name of the method.
+    pub name: String,
+    /// The names and types of the method's `in` and `inout` arguments, in
+    /// order.
+    pub arguments: Vec<(String, ParcelType)>,
+}
+
+/// Describes an interface, for [`decode`] and [`export_schema`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceMetadata {
+    /// The interface descriptor.
+    pub descriptor: String,
+    /// The version of the interface, if it is a versioned AIDL interface.
+    pub version: Option<i32>,
+    /// The methods of the interface which should be decoded.
+    pub methods: Vec<MethodMetadata>,
+}
+
+impl InterfaceMetadata {
+    /// Create metadata for the interface `descriptor`, with no methods.
+    pub fn new(descriptor: &str) -> Self {
+        Self { descriptor: descriptor.to_owned(), version: None, methods: Vec::new() }
+    }
+
+    /// Set the version of the interface.
+    pub fn with_version(mut self, version: i32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Add a method.
+    pub fn with_method(
+        mut self,
+        code: TransactionCode,
+        name: &str,
+        arguments: Vec<(&str, ParcelType)>,
+    ) -> Self {
+        let arguments = arguments.into_iter().map(|(name, ty)| (name.to_owned(), ty)).collect();
+        self.methods.push(MethodMetadata { code, name: name.to_owned(), arguments });
+        self
+    }
+}
+
+/// The metadata getter of every interface class defined in this process, by
+/// descriptor.
+static INTERFACE_CLASSES: RwLock<BTreeMap<&'static str, fn() -> Option<InterfaceMetadata>>> =
+    RwLock::new(BTreeMap::new());
+
+/// Called when an interface class is defined, before it is first used.
+pub(crate) fn on_class_defined(
+    descriptor: &'static str,
+    metadata: fn() -> Option<InterfaceMetadata>,
+) {
+    INTERFACE_CLASSES.write().unwrap().insert(descriptor, metadata);
+}
+
+/// The metadata of the interface class for `descriptor`, if it has been used
+/// in this process and has any. See [`Remotable::get_metadata`].
+///
+/// [`Remotable::get_metadata`]: crate::binder_impl::Remotable::get_metadata
+pub fn interface_metadata(descriptor: &str) -> Option<InterfaceMetadata> {
+    let metadata = *INTERFACE_CLASSES.read().unwrap().get(descriptor)?;
+    metadata()
+}
+
+/// Describe every interface class used in this process which has
+/// [metadata](crate::binder_impl::Remotable::get_metadata) as JSON, sorted by
+/// descriptor, for fuzzers, tracers and decoders which don't have the AIDL
+/// files:
+///
+/// ```json
+/// {"interfaces":[{"descriptor":"android.test.IFoo","version":2,"methods":[
+///   {"code":1,"name":"setName","arguments":[{"name":"name","type":"String"}]},
+///   {"code":2,"name":"setTags","arguments":[
+///     {"name":"tags","type":{"nullable":{"array":"String"}}}]}]}]}
+/// ```
+///
+/// Primitive types, `String`, `IBinder` and `ParcelFileDescriptor` are
+/// written as their AIDL names. Other types are objects with an `array` or
+/// `nullable` key holding the inner type, or a `parcelable` key holding the
+/// parcelable's name next to a `fields` array in the same form as
+/// `arguments`. The version is `null` for unversioned interfaces.
+pub fn export_schema() -> String {
+    // Copy the getters out, so that they run without the lock held.
+    let classes: Vec<_> = INTERFACE_CLASSES.read().unwrap().values().copied().collect();
+    let mut json = String::from("{\"interfaces\":[");
+    for (i, metadata) in classes.iter().filter_map(|metadata| metadata()).enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str("{\"descriptor\":");
+        push_json_string(&mut json, &metadata.descriptor);
+        match metadata.version {
+            Some(version) => json.push_str(&format!(",\"version\":{}", version)),
+            None => json.push_str(",\"version\":null"),
+        }
+        json.push_str(",\"methods\":[");
+        for (j, method) in metadata.methods.iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!("{{\"code\":{},\"name\":", method.code));
+            push_json_string(&mut json, &method.name);
+            json.push_str(",\"arguments\":");
+            push_json_fields(&mut json, &method.arguments);
+            json.push('}');
+        }
+        json.push_str("]}");
+    }
+    json.push_str("]}");
+    json
+}
+
+fn push_json_fields(json: &mut String, fields: &[(String, ParcelType)]) {
+    json.push('[');
+    for (i, (name, ty)) in fields.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str("{\"name\":");
+        push_json_string(json, name);
+        json.push_str(",\"type\":");
+        push_json_type(json, ty);
+        json.push('}');
+    }
+    json.push(']');
+}
+
+fn push_json_type(json: &mut String, ty: &ParcelType) {
+    let name = match ty {
+        ParcelType::Boolean => "boolean",
+        ParcelType::Byte => "byte",
+        ParcelType::Char => "char",
+        ParcelType::Int => "int",
+        ParcelType::Long => "long",
+        ParcelType::Float => "float",
+        ParcelType::Double => "double",
+        ParcelType::String => "String",
+        ParcelType::Binder => "IBinder",
+        ParcelType::FileDescriptor => "ParcelFileDescriptor",
+        ParcelType::Array(element) => {
+            json.push_str("{\"array\":");
+            push_json_type(json, element);
+            json.push('}');
+            return;
+        }
+        ParcelType::Nullable(inner) => {
+            json.push_str("{\"nullable\":");
+            push_json_type(json, inner);
+            json.push('}');
+            return;
+        }
+        ParcelType::Parcelable(name, fields) => {
+            json.push_str("{\"parcelable\":");
+            push_json_string(json, name);
+            json.push_str(",\"fields\":");
+            push_json_fields(json, fields);
+            json.push('}');
+            return;
+        }
+    };
+    push_json_string(json, name);
+}
+
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if u32::from(c) < 0x20 => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// A value decoded by [`decode`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodedValue {
+    /// A `boolean`.
+    Boolean(bool),
+    /// A `byte`.
+    Byte(i8),
+    /// A `char`.
+    Char(u16),
+    /// An `int`.
+    Int(i32),
+    /// A `long`.
+    Long(i64),
+    /// A `float`.
+    Float(f32),
+    /// A `double`.
+    Double(f64),
+    /// A `String`.
+    String(String),
+    /// A null string, array or parcelable.
+    Null,
+    /// An array.
+    Array(Vec<DecodedValue>),
+    /// A parcelable, with its name and fields. Fields missing from the data,
+    /// because it was written by an older version of the parcelable, are left
+    /// out.
+    Parcelable(String, Vec<(String, DecodedValue)>),
+}
+
+impl fmt::Display for DecodedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodedValue::Boolean(v) => write!(f, "{}", v),
+            DecodedValue::Byte(v) => write!(f, "{}", v),
+            DecodedValue::Char(v) => match char::from_u32(u32::from(*v)) {
+                Some(c) => write!(f, "{:?}", c),
+                None => write!(f, "'\\u{{{:04x}}}'", v),
+            },
+            DecodedValue::Int(v) => write!(f, "{}", v),
+            DecodedValue::Long(v) => write!(f, "{}", v),
+            DecodedValue::Float(v) => write!(f, "{}", v),
+            DecodedValue::Double(v) => write!(f, "{}", v),
+            DecodedValue::String(v) => write!(f, "{:?}", v),
+            DecodedValue::Null => write!(f, "null"),
+            DecodedValue::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            DecodedValue::Parcelable(name, fields) => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{}{}: {}", if i > 0 { ", " } else { " " }, field, value)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+/// A transaction decoded by [`decode`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedTransaction {
+    /// The interface descriptor.
+    pub descriptor: String,
+    /// The transaction code.
+    pub code: TransactionCode,
+    /// The name of the method, if the code is in the interface metadata.
+    pub method: Option<String>,
+    /// The arguments that could be decoded, with their names.
+    pub arguments: Vec<(String, DecodedValue)>,
+    /// Why decoding stopped before the last argument, if it did.
+    pub error: Option<StatusCode>,
+}
+
+impl fmt::Display for DecodedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{}.{}(", self.descriptor, method)?,
+            None => write!(f, "{}.<code {}>(", self.descriptor, self.code)?,
+        }
+        for (i, (name, value)) in self.arguments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, value)?;
+        }
+        write!(f, ")")?;
+        if let Some(error) = self.error {
+            write!(f, " <not decoded: {:?}>", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the arguments of a captured incoming transaction, such as a
+/// [`FailedTransaction`], using a description of its interface.
+///
+/// `data` is the raw parcel data from the start of the transaction; the
+/// interface token at its start is skipped if present. Decoding stops at the
+/// first argument which cannot be decoded, which is reported in
+/// [`DecodedTransaction::error`] rather than as an error of this function.
+pub fn decode(
+    code: TransactionCode,
+    data: &[u8],
+    metadata: &InterfaceMetadata,
+) -> Result<DecodedTransaction, StatusCode> {
+    let parcel = Parcel::unmarshall(data)?;
+    let parcel = parcel.borrowed_ref();
+    skip_interface_token(parcel, &metadata.descriptor);
+
+    let method = metadata.methods.iter().find(|method| method.code == code);
+    let mut decoded = DecodedTransaction {
+        descriptor: metadata.descriptor.clone(),
+        code,
+        method: method.map(|method| method.name.clone()),
+        arguments: Vec::new(),
+        error: None,
+    };
+    for (name, ty) in method.iter().flat_map(|method| &method.arguments) {
+        match decode_value(parcel, ty) {
+            Ok(value) => decoded.arguments.push((name.clone(), value)),
+            Err(e) => {
+                decoded.error = Some(e);
+                break;
+            }
+        }
+    }
+    Ok(decoded)
+}
+
+fn skip_interface_token(parcel: &BorrowedParcel<'_>, descriptor: &str) {
+    // Over kernel binder, the token is the strict mode policy, the work
+    // source UID and a header word, followed by the descriptor. Over RPC
+    // binder it is only the descriptor.
+    for header_words in [3, 0] {
+        // Safety: 0 is always a valid position.
+        let _ = unsafe { parcel.set_data_position(0) };
+        let header_read = (0..header_words).all(|_| parcel.read::<i32>().is_ok());
+        if header_read
+            && parcel.read::<Option<String>>().ok().flatten().as_deref() == Some(descriptor)
+        {
+            return;
+        }
+    }
+    // Safety: 0 is always a valid position.
+    let _ = unsafe { parcel.set_data_position(0) };
+}
+
+fn decode_value(parcel: &BorrowedParcel<'_>, ty: &ParcelType) -> Result<DecodedValue, StatusCode> {
+    Ok(match ty {
+        ParcelType::Boolean => DecodedValue::Boolean(parcel.read()?),
+        ParcelType::Byte => DecodedValue::Byte(parcel.read()?),
+        ParcelType::Char => DecodedValue::Char(parcel.read()?),
+        ParcelType::Int => DecodedValue::Int(parcel.read()?),
+        ParcelType::Long => DecodedValue::Long(parcel.read()?),
+        ParcelType::Float => DecodedValue::Float(parcel.read()?),
+        ParcelType::Double => DecodedValue::Double(parcel.read()?),
+        ParcelType::String => {
+            parcel.read::<Option<String>>()?.map_or(DecodedValue::Null, DecodedValue::String)
+        }
+        ParcelType::Binder | ParcelType::FileDescriptor => {
+            return Err(StatusCode::INVALID_OPERATION);
+        }
+        // Strings, arrays and parcelables all carry their own null marker,
+        // and other types cannot be nullable.
+        ParcelType::Nullable(inner) => decode_value(parcel, inner)?,
+        ParcelType::Array(element) => decode_array(parcel, element)?,
+        ParcelType::Parcelable(name, fields) => decode_parcelable(parcel, name, fields)?,
+    })
+}
+
+fn decode_array(
+    parcel: &BorrowedParcel<'_>,
+    element: &ParcelType,
+) -> Result<DecodedValue, StatusCode> {
+    if *element == ParcelType::Byte {
+        // Byte arrays are packed, rather than written element by element.
+        return Ok(parcel.read::<Option<Vec<i8>>>()?.map_or(DecodedValue::Null, |bytes| {
+            DecodedValue::Array(bytes.into_iter().map(DecodedValue::Byte).collect())
+        }));
+    }
+    let len: i32 = parcel.read()?;
+    if len == -1 {
+        return Ok(DecodedValue::Null);
+    }
+    // Every other element takes at least 4 bytes, so a longer array cannot
+    // fit in the remaining data.
+    let remaining = parcel.get_data_size() - parcel.get_data_position();
+    if len < 0 || i64::from(len) * 4 > i64::from(remaining) {
+        return Err(StatusCode::BAD_VALUE);
+    }
+    let values = (0..len).map(|_| decode_value(parcel, element)).collect::<Result<_, _>>()?;
+    Ok(DecodedValue::Array(values))
+}
+
+fn decode_parcelable(
+    parcel: &BorrowedParcel<'_>,
+    name: &str,
+    fields: &[(String, ParcelType)],
+) -> Result<DecodedValue, StatusCode> {
+    let present: i32 = parcel.read()?;
+    if present == 0 {
+        return Ok(DecodedValue::Null);
+    }
+    let start = parcel.get_data_position();
+    let size: i32 = parcel.read()?;
+    let end = start.checked_add(size).ok_or(StatusCode::BAD_VALUE)?;
+    if size < 4 || end > parcel.get_data_size() {
+        return Err(StatusCode::BAD_VALUE);
+    }
+    let mut decoded = Vec::new();
+    for (field, ty) in fields {
+        if parcel.get_data_position() >= end {
+            break;
+        }
+        decoded.push((field.clone(), decode_value(parcel, ty)?));
+    }
+    // Skip any fields added in newer versions of the parcelable.
+    //
+    // Safety: `end` is within the parcel data, as checked above.
+    unsafe { parcel.set_data_position(end)? };
+    Ok(DecodedValue::Parcelable(name.to_owned(), decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn capture_failed_transaction() {
+        let captures = Arc::new(Mutex::new(vec![]));
+        let captures_clone = captures.clone();
+        set_failed_transaction_callback(
+            6,
+            Some(move |t: &FailedTransaction| captures_clone.lock().unwrap().push(t.clone())),
+        );
+
+        let mut parcel = Parcel::new();
+        parcel.write(&1i32).unwrap();
+        parcel.write(&2i32).unwrap();
+        // SAFETY: 4 is less than the current size of the parcel data buffer.
+        unsafe {
+            parcel.set_data_position(4).unwrap();
+        }
+
+        on_transaction_failed("IFoo", 7, parcel.borrowed_ref(), StatusCode::OK);
+        on_transaction_failed("IFoo", 7, parcel.borrowed_ref(), StatusCode::BAD_VALUE);
+        set_failed_transaction_callback(0, None::<fn(&FailedTransaction)>);
+        on_transaction_failed("IFoo", 7, parcel.borrowed_ref(), StatusCode::BAD_VALUE);
+
+        let captures = captures.lock().unwrap();
+        assert_eq!(captures.len(), 1);
+        let capture = &captures[0];
+        assert_eq!(capture.descriptor, "IFoo");
+        assert_eq!(capture.code, 7);
+        assert_eq!(capture.status, StatusCode::BAD_VALUE);
+        assert_eq!(capture.position, 4);
+        assert_eq!(capture.data_size, 8);
+        let mut expected = 1i32.to_ne_bytes().to_vec();
+        expected.extend_from_slice(&2i32.to_ne_bytes()[..2]);
+        assert_eq!(capture.data, expected);
+        assert!(capture.object_offsets.is_empty());
+        assert_eq!(parcel.get_data_position(), 4);
+        drop(captures);
+
+        // Trusty has no pipes to put in the parcel.
+        #[cfg(not(trusty))]
+        {
+            // Every object is zeroed, and the data after them is still captured.
+            // The callback also removes itself, which must not deadlock.
+            let captures = Arc::new(Mutex::new(vec![]));
+            let captures_clone = captures.clone();
+            set_failed_transaction_callback(
+                usize::MAX,
+                Some(move |t: &FailedTransaction| {
+                    captures_clone.lock().unwrap().push(t.clone());
+                    set_failed_transaction_callback(0, None::<fn(&FailedTransaction)>);
+                }),
+            );
+
+            let (read, write) = crate::ParcelFileDescriptor::pipe().unwrap();
+            let mut parcel = Parcel::new();
+            parcel.write(&1i32).unwrap();
+            parcel.write(&read).unwrap();
+            let fd_size = parcel.get_data_size() - 4;
+            parcel.write(&write).unwrap();
+            parcel.write(&2i32).unwrap();
+
+            on_transaction_failed("IFoo", 7, parcel.borrowed_ref(), StatusCode::BAD_VALUE);
+            on_transaction_failed("IFoo", 7, parcel.borrowed_ref(), StatusCode::BAD_VALUE);
+
+            let captures = captures.lock().unwrap();
+            assert_eq!(captures.len(), 1);
+            let capture = &captures[0];
+            assert_eq!(capture.data.len(), usize::try_from(8 + 2 * fd_size).unwrap());
+            assert_eq!(capture.data[..4], 1i32.to_ne_bytes());
+            assert!(capture.data[4..capture.data.len() - 4].iter().all(|&b| b == 0));
+            assert_eq!(capture.data[capture.data.len() - 4..], 2i32.to_ne_bytes());
+            assert_eq!(capture.object_offsets, (4..4 + 2 * fd_size).step_by(4).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn report_unconsumed_parcels() {
+        let reports = Arc::new(Mutex::new(vec![]));
+        let reports_clone = reports.clone();
+        set_unconsumed_parcel_callback(Some(move |u: &UnconsumedParcel| {
+            reports_clone.lock().unwrap().push(u.clone())
+        }));
+        verify_parcel_consumption("android.test.IStrict", true);
+
+        let mut parcel = Parcel::new();
+        parcel.write(&1i32).unwrap();
+        parcel.write(&2i64).unwrap();
+        // SAFETY: 4 is less than the current size of the parcel data buffer.
+        unsafe {
+            parcel.set_data_position(4).unwrap();
+        }
+        on_request_handled("android.test.IStrict", 1, parcel.borrowed_ref());
+        on_request_handled("android.test.INotStrict", 2, parcel.borrowed_ref());
+        verify_parcel_consumption("android.test.IStrict", false);
+        on_request_handled("android.test.IStrict", 3, parcel.borrowed_ref());
+
+        with_parcel_consumption_check(|| {
+            on_reply_received(|| Some("android.test.IScoped".to_owned()), 4, parcel.borrowed_ref());
+            drop(parcel);
+        });
+        assert_eq!(TRACKED_REPLY_COUNT.load(Ordering::Acquire), 0);
+        set_unconsumed_parcel_callback(None::<fn(&UnconsumedParcel)>);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(
+            *reports,
+            vec![
+                UnconsumedParcel {
+                    descriptor: "android.test.IStrict".to_owned(),
+                    code: 1,
+                    direction: ParcelDirection::Request,
+                    unconsumed_bytes: 8,
+                },
+                UnconsumedParcel {
+                    descriptor: "android.test.IScoped".to_owned(),
+                    code: 4,
+                    direction: ParcelDirection::Reply,
+                    unconsumed_bytes: 8,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_transaction() {
+        let point = ParcelType::Parcelable(
+            "Point".to_owned(),
+            vec![("x".to_owned(), ParcelType::Int), ("y".to_owned(), ParcelType::Int)],
+        );
+        let metadata = InterfaceMetadata::new("android.test.IDecode").with_method(
+            1,
+            "draw",
+            vec![
+                ("label", ParcelType::Nullable(Box::new(ParcelType::String))),
+                ("points", ParcelType::Array(Box::new(point))),
+                ("bytes", ParcelType::Array(Box::new(ParcelType::Byte))),
+                ("callback", ParcelType::Binder),
+            ],
+        );
+
+        let mut parcel = Parcel::new();
+        parcel.write(&0i32).unwrap();
+        parcel.write(&0i32).unwrap();
+        parcel.write(&0x53595354i32).unwrap();
+        parcel.write("android.test.IDecode").unwrap();
+        parcel.write(&None::<String>).unwrap();
+        parcel.write(&1i32).unwrap(); // One point.
+        parcel.write(&1i32).unwrap(); // Non-null.
+        parcel
+            .sized_write(|p| {
+                p.write(&3i32)?;
+                p.write(&4i32)?;
+                // A field added in a newer version.
+                p.write(&5i32)
+            })
+            .unwrap();
+        parcel.write(&[7u8, 8][..]).unwrap();
+        let data = capture_data(parcel.borrowed_ref(), usize::MAX);
+
+        let decoded = decode(1, &data, &metadata).unwrap();
+        assert_eq!(decoded.method.as_deref(), Some("draw"));
+        assert_eq!(decoded.error, Some(StatusCode::INVALID_OPERATION));
+        assert_eq!(
+            decoded.to_string(),
+            "android.test.IDecode.draw(label: null, points: [Point { x: 3, y: 4 }], \
+             bytes: [7, 8]) <not decoded: INVALID_OPERATION>"
+        );
+
+        let unknown = decode(2, &data, &metadata).unwrap();
+        assert_eq!(unknown.to_string(), "android.test.IDecode.<code 2>()");
+    }
+
+    pub trait ISchema: crate::Interface {}
+    pub trait ISchemaQuoted: crate::Interface {}
+    pub trait INoSchema: crate::Interface {}
+
+    crate::declare_binder_interface! {
+        ISchema["android.test.ISchema"] {
+            native: BnSchema(no_transactions),
+            proxy: BpSchema,
+            stability: crate::binder_impl::Stability::default(),
+            metadata: schema_metadata(),
+        }
+    }
+
+    crate::declare_binder_interface! {
+        ISchemaQuoted["android.test.ISchema\"Quoted"] {
+            native: BnSchemaQuoted(no_transactions),
+            proxy: BpSchemaQuoted,
+            stability: crate::binder_impl::Stability::default(),
+            metadata: InterfaceMetadata::new("android.test.ISchema\"Quoted"),
+        }
+    }
+
+    crate::declare_binder_interface! {
+        INoSchema["android.test.INoSchema"] {
+            native: BnNoSchema(no_transactions),
+            proxy: BpNoSchema,
+        }
+    }
+
+    impl ISchema for BpSchema {}
+    impl ISchema for crate::binder_impl::Binder<BnSchema> {}
+    impl ISchemaQuoted for BpSchemaQuoted {}
+    impl ISchemaQuoted for crate::binder_impl::Binder<BnSchemaQuoted> {}
+    impl INoSchema for BpNoSchema {}
+    impl INoSchema for crate::binder_impl::Binder<BnNoSchema> {}
+
+    fn no_transactions<T: ?Sized>(
+        _: &T,
+        _: TransactionCode,
+        _: &BorrowedParcel<'_>,
+        _: &mut BorrowedParcel<'_>,
+    ) -> Result<(), StatusCode> {
+        Err(StatusCode::UNKNOWN_TRANSACTION)
+    }
+
+    fn schema_metadata() -> InterfaceMetadata {
+        let point = ParcelType::Parcelable(
+            "Point".to_owned(),
+            vec![("x".to_owned(), ParcelType::Int), ("y".to_owned(), ParcelType::Int)],
+        );
+        InterfaceMetadata::new("android.test.ISchema")
+            .with_version(2)
+            .with_method(1, "move", vec![("to", point)])
+            .with_method(
+                2,
+                "tag",
+                vec![(
+                    "tags",
+                    ParcelType::Nullable(Box::new(ParcelType::Array(Box::new(ParcelType::String)))),
+                )],
+            )
+    }
+
+    #[test]
+    fn export_schema_of_interface_classes() {
+        use crate::binder_impl::Remotable;
+
+        // Interfaces are only part of the schema once their class is defined.
+        assert_eq!(interface_metadata("android.test.ISchema"), None);
+        BnSchema::get_class();
+        BnSchemaQuoted::get_class();
+        BnNoSchema::get_class();
+        assert_eq!(interface_metadata("android.test.ISchema"), Some(schema_metadata()));
+        assert_eq!(interface_metadata("android.test.INoSchema"), None);
+
+        let schema = export_schema();
+        assert!(schema.starts_with("{\"interfaces\":["), "{}", schema);
+        assert!(schema.contains(
+            "{\"descriptor\":\"android.test.ISchema\",\"version\":2,\"methods\":[\
+             {\"code\":1,\"name\":\"move\",\"arguments\":[{\"name\":\"to\",\"type\":\
+             {\"parcelable\":\"Point\",\"fields\":[{\"name\":\"x\",\"type\":\"int\"},\
+             {\"name\":\"y\",\"type\":\"int\"}]}}]},\
+             {\"code\":2,\"name\":\"tag\",\"arguments\":[{\"name\":\"tags\",\"type\":\
+             {\"nullable\":{\"array\":\"String\"}}}]}]}"
+        ));
+        assert!(schema.contains(
+            "{\"descriptor\":\"android.test.ISchema\\\"Quoted\",\"version\":null,\"methods\":[]}"
+        ));
+        assert!(!schema.contains("INoSchema"));
+    }
+}
@@ -14,14 +14,17 @@
  * limitations under the License.
  */
 
-use crate::binder::AsNative;
+use crate::binder::{AsNative, Stability};
+use crate::parcel::{Parcelable, ParcelableHolder, ParcelableHolderError, ParcelableMetadata};
 use crate::sys;
 
+use std::any::Any;
 use std::error;
 use std::ffi::{CStr, CString};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::ptr;
 use std::result;
+use std::sync::Arc;
 
 pub use sys::binder_status_t as status_t;
 
@@ -70,7 +73,7 @@ fn parse_status_code(code: i32) -> StatusCode {
 
 pub use sys::android_c_interface_ExceptionCode as ExceptionCode;
 
-fn parse_exception_code(code: i32) -> ExceptionCode {
+pub(crate) fn parse_exception_code(code: i32) -> ExceptionCode {
     match code {
         e if e == ExceptionCode::NONE as i32 => ExceptionCode::NONE,
         e if e == ExceptionCode::SECURITY as i32 => ExceptionCode::SECURITY,
@@ -93,22 +96,43 @@ fn parse_exception_code(code: i32) -> ExceptionCode {
 /// track of and chain binder errors along with service specific errors.
 ///
 /// Used in AIDL transactions to represent failed transactions.
-pub struct Status(ptr::NonNull<sys::AStatus>);
+pub struct Status {
+    ptr: ptr::NonNull<sys::AStatus>,
+    detail: Option<ParcelableHolder>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+}
 
 // Safety: The `AStatus` that the `Status` points to must have an entirely thread-safe API for the
 // duration of the `Status` object's lifetime. We ensure this by not allowing mutation of a `Status`
 // in Rust, and the NDK API says we're the owner of our `AStatus` objects so outside code should not
-// be mutating them underneath us.
+// be mutating them underneath us. The `detail` and `source` fields are `Sync` themselves: a
+// `ParcelableHolder` guards its contents with a `Mutex`, and the source is required to be `Sync`.
 unsafe impl Sync for Status {}
 
 // Safety: `Status` always contains an owning pointer to a global, immutable, interned `AStatus`.
-// A thread-local `AStatus` would not be valid.
+// A thread-local `AStatus` would not be valid. The `detail` and `source` fields are `Send`
+// themselves: a `ParcelableHolder` only holds `Send` parcelables and parcels, and the source is
+// required to be `Send`.
 unsafe impl Send for Status {}
 
 fn to_cstring<T: AsRef<str>>(message: T) -> Option<CString> {
     CString::new(message.as_ref()).ok()
 }
 
+/// Converts a string argument which must not contain NUL bytes for passing
+/// to the NDK.
+///
+/// Without the `strict` feature, a NUL byte panics; with it, it is reported
+/// as `BAD_VALUE`.
+pub(crate) fn checked_cstring(value: &str) -> Result<CString> {
+    let value = CString::new(value);
+    if cfg!(feature = "strict") {
+        value.or(Err(StatusCode::BAD_VALUE))
+    } else {
+        Ok(value.unwrap())
+    }
+}
+
 impl Status {
     /// Create a status object representing a successful transaction.
     pub fn ok() -> Self {
@@ -118,7 +142,8 @@ impl Status {
         //
         // Rust takes ownership of the returned pointer.
         let ptr = unsafe { sys::AStatus_newOk() };
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+        // Safety: `ptr` is a new, owned `AStatus` pointer from the NDK.
+        unsafe { Self::from_ptr(ptr) }
     }
 
     /// Create a status object from a service specific error
@@ -142,7 +167,8 @@ impl Status {
             // Rust takes ownership of the returned pointer.
             unsafe { sys::AStatus_fromServiceSpecificError(err) }
         };
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+        // Safety: `ptr` is a new, owned `AStatus` pointer from the NDK.
+        unsafe { Self::from_ptr(ptr) }
     }
 
     /// Creates a status object from a service specific error.
@@ -158,7 +184,8 @@ impl Status {
             let ptr = unsafe {
                 sys::AStatus_fromExceptionCodeWithMessage(exception as i32, message.as_ptr())
             };
-            Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+            // Safety: `ptr` is a new, owned `AStatus` pointer from the NDK.
+            unsafe { Self::from_ptr(ptr) }
         } else {
             exception.into()
         }
@@ -172,13 +199,62 @@ impl Status {
         Self::new_exception(exception, message.and_then(to_cstring).as_deref())
     }
 
+    /// Creates a status object from an exception code and message, with
+    /// `source` as the underlying cause of the error.
+    ///
+    /// Only `message` is sent to the client. `source` itself stays in this
+    /// process, for logging, and is returned by
+    /// [`Error::source`](error::Error::source), so it may hold details which
+    /// the client shouldn't see.
+    pub fn new_exception_with_source<T, E>(
+        exception: ExceptionCode,
+        message: Option<T>,
+        source: E,
+    ) -> Status
+    where
+        T: AsRef<str>,
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        Self::new_exception_str(exception, message).with_source(source)
+    }
+
+    /// Creates a status object from a service specific error and message,
+    /// with `source` as the underlying cause of the error.
+    ///
+    /// As for [`new_exception_with_source`](Self::new_exception_with_source),
+    /// only `message` is sent to the client.
+    pub fn new_service_specific_error_with_source<T, E>(
+        err: i32,
+        message: Option<T>,
+        source: E,
+    ) -> Status
+    where
+        T: AsRef<str>,
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        Self::new_service_specific_error_str(err, message).with_source(source)
+    }
+
+    /// Sets the underlying cause of the error, returned by
+    /// [`Error::source`](error::Error::source).
+    ///
+    /// This doesn't change the message, and isn't sent to the client.
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
     /// Create a status object from a raw `AStatus` pointer.
     ///
     /// # Safety
     ///
     /// This constructor is safe iff `ptr` is a valid pointer to an `AStatus`.
     pub(crate) unsafe fn from_ptr(ptr: *mut sys::AStatus) -> Self {
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+        let ptr = ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer");
+        Self { ptr, detail: None, source: None }
     }
 
     /// Returns `true` if this status represents a successful transaction.
@@ -250,6 +326,50 @@ impl Status {
         unsafe { sys::AStatus_getServiceSpecificError(self.as_native()) }
     }
 
+    /// Attaches a parcelable with more detail about the error, e.g. which
+    /// field of a request was invalid or when to retry, to be sent to the
+    /// client along with the status.
+    ///
+    /// Only Rust clients read the detail: it is sent after the parts of the
+    /// status which C++ and Java read, and they ignore it. Nothing is sent for
+    /// a successful status or a transaction failure.
+    pub fn with_detail<T>(mut self, detail: T) -> Self
+    where
+        T: Any + Parcelable + ParcelableMetadata + Debug + Send + Sync,
+    {
+        let mut holder = ParcelableHolder::new(Stability::Local);
+        holder
+            .set_parcelable(Arc::new(detail))
+            .expect("A local ParcelableHolder accepts parcelables of any stability");
+        self.detail = Some(holder);
+        self
+    }
+
+    /// Returns the detail attached with [`with_detail`](Self::with_detail), if
+    /// it is a `T`.
+    ///
+    /// Returns [`ParcelableHolderError::Empty`] if there is no detail, and
+    /// [`ParcelableHolderError::WrongDescriptor`] if it is of another type.
+    pub fn detail<T>(&self) -> result::Result<Arc<T>, ParcelableHolderError>
+    where
+        T: Any + Parcelable + ParcelableMetadata + Default + Debug + Send + Sync,
+    {
+        self.detail.as_ref().ok_or(ParcelableHolderError::Empty)?.get_parcelable_checked()
+    }
+
+    /// Returns the descriptor of the attached detail, if there is one.
+    pub fn detail_descriptor(&self) -> Option<String> {
+        self.detail.as_ref().and_then(|detail| detail.descriptor().ok().flatten())
+    }
+
+    pub(crate) fn detail_holder(&self) -> Option<&ParcelableHolder> {
+        self.detail.as_ref()
+    }
+
+    pub(crate) fn set_detail_holder(&mut self, detail: ParcelableHolder) {
+        self.detail = Some(detail);
+    }
+
     /// Calls `op` if the status was ok, otherwise returns an `Err` value of
     /// `self`.
     pub fn and_then<T, F>(self, op: F) -> result::Result<T, Status>
@@ -261,7 +381,11 @@ impl Status {
     }
 }
 
-impl error::Error for Status {}
+impl error::Error for Status {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_deref().map(|source| source as _)
+    }
+}
 
 impl Display for Status {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
@@ -309,7 +433,8 @@ impl From<status_t> for Status {
         // this is a safe FFI call. Unknown values will be coerced into
         // UNKNOWN_ERROR.
         let ptr = unsafe { sys::AStatus_fromStatus(status) };
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+        // Safety: `ptr` is a new, owned `AStatus` pointer from the NDK.
+        unsafe { Self::from_ptr(ptr) }
     }
 }
 
@@ -319,7 +444,35 @@ impl From<ExceptionCode> for Status {
         // `binder_exception_t` (i32) integer, so this is a safe FFI call.
         // Unknown values will be coerced into EX_TRANSACTION_FAILED.
         let ptr = unsafe { sys::AStatus_fromExceptionCode(code as i32) };
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null AStatus pointer"))
+        // Safety: `ptr` is a new, owned `AStatus` pointer from the NDK.
+        unsafe { Self::from_ptr(ptr) }
+    }
+}
+
+/// Converts an `anyhow::Error` from a service implementation into an
+/// `ILLEGAL_STATE` exception.
+///
+/// The client is only sent a generic message, as the error and its chain of
+/// causes may hold details, such as file paths, which it shouldn't see. The
+/// error itself is kept as the [`source`](error::Error::source) of the status,
+/// to be logged by the service.
+///
+/// If the error is a `Status`, e.g. from a call to another service which is
+/// being passed on, that `Status` is returned instead. To report errors with
+/// other codes or messages, convert them before they become `anyhow::Error`s,
+/// e.g. with [`IntoBinderResult`] or `#[derive(BinderStatus)]` from
+/// `binder_macros`.
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Status {
+    fn from(error: anyhow::Error) -> Status {
+        match error.downcast::<Status>() {
+            Ok(status) => status,
+            Err(error) => Status::new_exception_with_source(
+                ExceptionCode::ILLEGAL_STATE,
+                Some("internal error"),
+                error,
+            ),
+        }
     }
 }
 
@@ -348,7 +501,7 @@ impl Drop for Status {
         // will be valid here since `Status` always contains a valid pointer
         // while it is alive.
         unsafe {
-            sys::AStatus_delete(self.0.as_mut());
+            sys::AStatus_delete(self.ptr.as_mut());
         }
     }
 }
@@ -360,13 +513,13 @@ impl Drop for Status {
 /// `Status` object is still alive.
 unsafe impl AsNative<sys::AStatus> for Status {
     fn as_native(&self) -> *const sys::AStatus {
-        self.0.as_ptr()
+        self.ptr.as_ptr()
     }
 
     fn as_native_mut(&mut self) -> *mut sys::AStatus {
         // Safety: The pointer will be valid here since `Status` always contains
         // a valid and initialized pointer while it is alive.
-        unsafe { self.0.as_mut() }
+        unsafe { self.ptr.as_mut() }
     }
 }
 
@@ -458,9 +611,81 @@ impl<T, E: std::fmt::Debug> IntoBinderResult<T, E> for result::Result<T, E> {
     }
 }
 
+/// Marker returned by [`check_supported`] when the remote service does not
+/// implement the called method.
+///
+/// This is typically the case when the method was added in a later version of
+/// the interface than the one the remote service was built against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Unsupported;
+
+impl Display for Unsupported {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("method is not supported by the remote service")
+    }
+}
+
+impl error::Error for Unsupported {}
+
+/// Separates "the remote side does not know this method" from every other
+/// failure of a binder call.
+///
+/// A `Status` carrying `StatusCode::UNKNOWN_TRANSACTION` becomes
+/// `Ok(Err(Unsupported))`; any other error is passed through unchanged. See
+/// also [`call_if_supported!`](crate::call_if_supported).
+pub fn check_supported<T>(
+    result: result::Result<T, Status>,
+) -> result::Result<result::Result<T, Unsupported>, Status> {
+    match result {
+        Ok(value) => Ok(Ok(value)),
+        Err(status)
+            if status.exception_code() == ExceptionCode::TRANSACTION_FAILED
+                && status.transaction_error() == StatusCode::UNKNOWN_TRANSACTION =>
+        {
+            Ok(Err(Unsupported))
+        }
+        Err(status) => Err(status),
+    }
+}
+
+/// Calls a method that may not be implemented by an older remote service.
+///
+/// With a single argument, evaluates to
+/// `binder::Result<Result<T, binder::Unsupported>>`. With a fallback
+/// expression, evaluates to `binder::Result<T>`, using the fallback (which is
+/// only evaluated if needed) when the remote service returns
+/// `UNKNOWN_TRANSACTION`.
+///
+/// # Examples
+///
+/// ```ignore
+/// // Older services don't have `get_features`, so assume they have none.
+/// let features = binder::call_if_supported!(service.get_features(), vec![])?;
+///
+/// match binder::call_if_supported!(service.new_method(42))? {
+///     Ok(value) => println!("got {value}"),
+///     Err(binder::Unsupported) => println!("service is too old"),
+/// }
+/// ```
+#[macro_export]
+macro_rules! call_if_supported {
+    ($call:expr $(,)?) => {
+        $crate::check_supported($call)
+    };
+
+    ($call:expr, $fallback:expr $(,)?) => {
+        match $crate::check_supported($call) {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err($crate::Unsupported)) => Ok($fallback),
+            Err(status) => Err(status),
+        }
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parcel::{BorrowedParcel, Parcel};
 
     #[test]
     fn make_service_specific_error() {
@@ -556,4 +781,139 @@ mod tests {
             "Status(-5, EX_ILLEGAL_STATE): 'outer message: \"message\"'".to_string()
         );
     }
+
+    #[test]
+    fn unknown_transaction_is_unsupported() {
+        let res: std::result::Result<i32, Status> =
+            Err(Status::from(StatusCode::UNKNOWN_TRANSACTION));
+        assert_eq!(check_supported(res), Ok(Err(Unsupported)));
+
+        let res: std::result::Result<i32, Status> =
+            Err(Status::from(StatusCode::UNKNOWN_TRANSACTION));
+        assert_eq!(call_if_supported!(res, 7), Ok(7));
+    }
+
+    #[test]
+    fn other_errors_are_not_unsupported() {
+        let res: std::result::Result<i32, Status> = Err(Status::from(StatusCode::DEAD_OBJECT));
+        let err = check_supported(res).unwrap_err();
+        assert_eq!(err.transaction_error(), StatusCode::DEAD_OBJECT);
+
+        let res: std::result::Result<i32, Status> = Ok(3);
+        assert_eq!(call_if_supported!(res, 7), Ok(3));
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    struct RetryAfter(i64);
+
+    impl Parcelable for RetryAfter {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+            parcel.write(&self.0)
+        }
+
+        fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<()> {
+            self.0 = parcel.read()?;
+            Ok(())
+        }
+    }
+
+    impl ParcelableMetadata for RetryAfter {
+        fn get_descriptor() -> &'static str {
+            "android.binder.test.RetryAfter"
+        }
+    }
+
+    #[test]
+    fn detail_round_trip() {
+        let status =
+            Status::new_service_specific_error_str(-42, Some("busy")).with_detail(RetryAfter(1500));
+        assert_eq!(status.detail::<RetryAfter>().as_deref(), Ok(&RetryAfter(1500)));
+
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&status).is_ok());
+        assert!(parcel.write(&Status::new_exception(ExceptionCode::SECURITY, None)).is_ok());
+        assert!(parcel.write(&7i32).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+
+        let read: Status = parcel.read().unwrap();
+        assert_eq!(read, status);
+        assert_eq!(read.detail_descriptor().as_deref(), Some(RetryAfter::get_descriptor()));
+        assert_eq!(read.detail::<RetryAfter>().as_deref(), Ok(&RetryAfter(1500)));
+
+        // A status without a detail leaves the following data alone.
+        let read: Status = parcel.read().unwrap();
+        assert_eq!(read.detail::<RetryAfter>(), Err(ParcelableHolderError::Empty));
+        assert_eq!(parcel.read::<i32>(), Ok(7));
+    }
+
+    #[test]
+    fn unreadable_detail_is_left_in_place() {
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&Status::new_exception(ExceptionCode::SECURITY, None)).is_ok());
+        let magic = i32::from_be_bytes(*b"SDTL");
+        // The magic, followed by a truncated parcelable.
+        assert!(parcel.write(&magic).is_ok());
+        assert!(parcel.write(&1000i32).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+
+        let read: Status = parcel.read().unwrap();
+        assert_eq!(read.exception_code(), ExceptionCode::SECURITY);
+        assert_eq!(read.detail::<RetryAfter>(), Err(ParcelableHolderError::Empty));
+        assert_eq!(parcel.read::<i32>(), Ok(magic));
+        assert_eq!(parcel.read::<i32>(), Ok(1000));
+    }
+
+    #[test]
+    fn source_chain() {
+        use std::error::Error;
+
+        let io_error =
+            std::io::Error::new(std::io::ErrorKind::NotFound, "/data/secret: no such file");
+        let status =
+            Status::new_service_specific_error_with_source(-42, Some("not found"), io_error);
+        assert_eq!(status.service_specific_error(), -42);
+        assert_eq!(
+            status.get_description(),
+            "Status(-8, EX_SERVICE_SPECIFIC): '-42: not found'".to_string()
+        );
+        let source = status.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+
+        let status = Status::new_exception_with_source(
+            ExceptionCode::ILLEGAL_ARGUMENT,
+            None::<&str>,
+            "bad id",
+        );
+        assert_eq!(status.source().unwrap().to_string(), "bad id");
+        assert!(!status.get_description().contains("bad id"));
+        assert!(Status::from(StatusCode::DEAD_OBJECT).source().is_none());
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn from_anyhow() {
+        use anyhow::Context;
+        use std::error::Error;
+
+        let error = std::fs::metadata("/nonexistent").context("loading config").unwrap_err();
+        let status = Status::from(error);
+        assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_STATE);
+        assert_eq!(
+            status.get_description(),
+            "Status(-5, EX_ILLEGAL_STATE): 'internal error'".to_string()
+        );
+        let source = status.source().unwrap();
+        assert_eq!(source.to_string(), "loading config");
+        assert!(source.source().is_some());
+
+        let inner = Status::new_service_specific_error_str(3, Some("busy"));
+        let status = Status::from(anyhow::Error::new(inner));
+        assert_eq!(status.service_specific_error(), 3);
+    }
 }
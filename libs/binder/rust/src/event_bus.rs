@@ -0,0 +1,401 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Broadcasting events to listeners in other processes.
+//!
+//! A service which notifies listeners of changes hosts an [`EventBus`] and
+//! hands out its binder. Clients [`subscribe`] to a topic on it, and every
+//! event published to that topic is delivered to each subscriber as a oneway
+//! transaction, so a slow or stuck subscriber never blocks the publisher.
+//! Subscribers which die are removed automatically.
+//!
+//! ```ignore
+//! // In the service:
+//! let bus = EventBus::new();
+//! binder::add_service("battery_events", bus.as_binder())?;
+//! bus.publish("level", &87i32)?;
+//!
+//! // In a client:
+//! let bus = binder::get_service("battery_events").ok_or(StatusCode::NAME_NOT_FOUND)?;
+//! let subscription = binder::event_bus::subscribe(&bus, "level", |level: i32| {
+//!     log::info!("Battery level is now {level}%");
+//! })?;
+//! ```
+//!
+//! The bus binder accepts these transactions, each containing a `String`
+//! topic and an `IBinder` listener:
+//!
+//! * `FIRST_CALL_TRANSACTION`: subscribe the listener to the topic;
+//! * `FIRST_CALL_TRANSACTION + 1`: unsubscribe it.
+//!
+//! Listeners receive each event as a oneway `FIRST_CALL_TRANSACTION`
+//! containing the `String` topic followed by the event.
+
+use crate::binder::{
+    IBinder, IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+    FLAG_ONEWAY,
+};
+use crate::error::{Result, StatusCode};
+use crate::native::Binder;
+use crate::parcel::{BorrowedParcel, Deserialize, Serialize};
+use crate::proxy::{BinderId, DeathRecipient, SpIBinder};
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+
+const SUBSCRIBE: TransactionCode = FIRST_CALL_TRANSACTION;
+const UNSUBSCRIBE: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const EVENT: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// What an [`EventBus`] does with a subscriber when delivering an event to it
+/// fails.
+///
+/// Oneway deliveries only fail if the subscriber has died, or if it has so
+/// many undelivered events queued that its async transaction buffer is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeliveryFailurePolicy {
+    /// Keep the subscriber, in case it catches up.
+    Keep,
+    /// Unsubscribe it after the first failed delivery.
+    #[default]
+    Unsubscribe,
+    /// Unsubscribe it after the given number of consecutive failed
+    /// deliveries.
+    UnsubscribeAfter(u32),
+}
+
+impl DeliveryFailurePolicy {
+    fn should_unsubscribe(self, consecutive_failures: u32) -> bool {
+        match self {
+            Self::Keep => false,
+            Self::Unsubscribe => true,
+            Self::UnsubscribeAfter(limit) => consecutive_failures >= limit,
+        }
+    }
+}
+
+struct Subscriber {
+    listener: SpIBinder,
+    consecutive_failures: u32,
+    // Kept alive so that the subscriber is removed when it dies.
+    _death_recipient: Option<DeathRecipient>,
+}
+
+type Topics = Mutex<HashMap<String, Vec<Subscriber>>>;
+
+fn remove_subscriber(topics: &Topics, topic: &str, listener: BinderId) {
+    let mut topics = topics.lock().unwrap();
+    if let Some(subscribers) = topics.get_mut(topic) {
+        subscribers.retain(|subscriber| subscriber.listener.id() != listener);
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+    }
+}
+
+/// The remotable object behind an [`EventBus`], which handles subscription
+/// requests.
+struct Registry {
+    topics: Arc<Topics>,
+}
+
+impl Registry {
+    fn subscribe(&self, topic: String, mut listener: SpIBinder) -> Result<()> {
+        let id = listener.id();
+        let death_recipient = if listener.is_remote() {
+            let topics = Arc::downgrade(&self.topics);
+            let dead_topic = topic.clone();
+            let mut death_recipient = DeathRecipient::new(move || {
+                if let Some(topics) = Weak::upgrade(&topics) {
+                    remove_subscriber(&topics, &dead_topic, id);
+                }
+            });
+            listener.link_to_death(&mut death_recipient)?;
+            Some(death_recipient)
+        } else {
+            None
+        };
+
+        let mut topics = self.topics.lock().unwrap();
+        let subscribers = topics.entry(topic).or_default();
+        if !subscribers.iter().any(|subscriber| subscriber.listener.id() == id) {
+            subscribers.push(Subscriber {
+                listener,
+                consecutive_failures: 0,
+                _death_recipient: death_recipient,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Remotable for Registry {
+    fn get_descriptor() -> &'static str {
+        "android.os.IEventBus"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        let topic: String = data.read()?;
+        let listener: SpIBinder = data.read()?;
+        match code {
+            SUBSCRIBE => self.subscribe(topic, listener),
+            UNSUBSCRIBE => {
+                remove_subscriber(&self.topics, &topic, listener.id());
+                Ok(())
+            }
+            _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+        }
+    }
+
+    fn on_dump(&self, writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        for (topic, subscribers) in self.topics.lock().unwrap().iter() {
+            writeln!(writer, "{topic}: {} subscribers", subscribers.len())
+                .map_err(|_| StatusCode::FAILED_TRANSACTION)?;
+        }
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// A registry of topics, each with a set of subscribers which receive every
+/// event published to it.
+pub struct EventBus {
+    registry: Binder<Registry>,
+    policy: DeliveryFailurePolicy,
+}
+
+impl EventBus {
+    /// Create an event bus with no subscribers, which unsubscribes
+    /// subscribers that an event can't be delivered to.
+    pub fn new() -> Self {
+        Self::with_policy(DeliveryFailurePolicy::default())
+    }
+
+    /// Create an event bus with no subscribers and the given delivery failure
+    /// policy.
+    pub fn with_policy(policy: DeliveryFailurePolicy) -> Self {
+        Self { registry: Binder::new(Registry { topics: Arc::default() }), policy }
+    }
+
+    /// Deliver `event` to every subscriber of `topic`.
+    ///
+    /// Returns the number of subscribers it was delivered to. Failed
+    /// deliveries are handled according to the bus's
+    /// [`DeliveryFailurePolicy`]; only an error serializing the event is
+    /// returned.
+    pub fn publish<E: Serialize + ?Sized>(&self, topic: &str, event: &E) -> Result<usize> {
+        let topics = &self.registry.topics;
+        // Deliver without holding the lock, as a local subscriber handles the
+        // event synchronously and may publish or unsubscribe in turn.
+        let listeners: Vec<SpIBinder> = match topics.lock().unwrap().get(topic) {
+            Some(subscribers) => subscribers.iter().map(|s| s.listener.clone()).collect(),
+            None => return Ok(0),
+        };
+
+        let mut delivered = Vec::with_capacity(listeners.len());
+        for listener in &listeners {
+            // Preparing a transaction fails if the subscriber has died, which
+            // is a failed delivery like any other.
+            let Ok(mut data) = listener.prepare_transact() else {
+                delivered.push(false);
+                continue;
+            };
+            {
+                let mut data = data.borrowed();
+                data.write(topic)?;
+                data.write(event)?;
+            }
+            delivered.push(listener.submit_transact(EVENT, data, FLAG_ONEWAY).is_ok());
+        }
+
+        let mut topics = topics.lock().unwrap();
+        if let Some(subscribers) = topics.get_mut(topic) {
+            let outcomes: HashMap<BinderId, bool> =
+                listeners.iter().map(SpIBinder::id).zip(delivered.iter().copied()).collect();
+            subscribers.retain_mut(|subscriber| match outcomes.get(&subscriber.listener.id()) {
+                Some(true) => {
+                    subscriber.consecutive_failures = 0;
+                    true
+                }
+                Some(false) => {
+                    subscriber.consecutive_failures += 1;
+                    !self.policy.should_unsubscribe(subscriber.consecutive_failures)
+                }
+                // Subscribed while the event was being delivered.
+                None => true,
+            });
+            if subscribers.is_empty() {
+                topics.remove(topic);
+            }
+        }
+        Ok(delivered.into_iter().filter(|&ok| ok).count())
+    }
+
+    /// The number of subscribers to `topic`.
+    pub fn subscriber_count(&self, topic: &str) -> usize {
+        self.registry.topics.lock().unwrap().get(topic).map_or(0, Vec::len)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interface for EventBus {
+    fn as_binder(&self) -> SpIBinder {
+        self.registry.as_binder()
+    }
+}
+
+type EventHandler = Box<dyn Fn(&BorrowedParcel<'_>) -> Result<()> + Send + Sync>;
+
+/// The local binder which receives events for a [`Subscription`].
+struct Listener {
+    topic: String,
+    handler: EventHandler,
+}
+
+impl Remotable for Listener {
+    fn get_descriptor() -> &'static str {
+        "android.os.IEventListener"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        if code != EVENT {
+            return Err(StatusCode::UNKNOWN_TRANSACTION);
+        }
+        if data.read::<String>()? != self.topic {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        (self.handler)(data)
+    }
+
+    fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// A subscription to a topic on an [`EventBus`], which lasts until it is
+/// dropped.
+pub struct Subscription {
+    bus: SpIBinder,
+    listener: Binder<Listener>,
+}
+
+impl Subscription {
+    /// Unsubscribe from the topic, returning any error from the bus.
+    ///
+    /// Dropping the subscription also unsubscribes, but ignores errors.
+    pub fn unsubscribe(self) -> Result<()> {
+        self.send(UNSUBSCRIBE)
+    }
+
+    /// The topic this subscription is to.
+    pub fn topic(&self) -> &str {
+        &self.listener.topic
+    }
+
+    fn send(&self, code: TransactionCode) -> Result<()> {
+        self.bus.transact(code, 0, |mut data| {
+            data.write(&self.listener.topic)?;
+            data.write(&self.listener.as_binder())
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Unsubscribing again after `unsubscribe` is harmless.
+        let _ = self.send(UNSUBSCRIBE);
+    }
+}
+
+/// Subscribe to `topic` on the event bus `bus`, calling `on_event` with each
+/// event published to it.
+///
+/// Events which can't be deserialized as `E` are dropped. `on_event` is
+/// called on a binder thread, and may be called concurrently if the process
+/// has more than one.
+pub fn subscribe<E, F>(bus: &SpIBinder, topic: &str, on_event: F) -> Result<Subscription>
+where
+    E: Deserialize + 'static,
+    F: Fn(E) + Send + Sync + 'static,
+{
+    let handler: EventHandler = Box::new(move |data| {
+        on_event(data.read()?);
+        Ok(())
+    });
+    let listener = Binder::new(Listener { topic: topic.to_owned(), handler });
+    let subscription = Subscription { bus: bus.clone(), listener };
+    subscription.send(SUBSCRIBE)?;
+    Ok(subscription)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn events_reach_subscribers_of_their_topic() {
+        let bus = EventBus::new();
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let subscription = subscribe(&bus.as_binder(), "level", move |level: i32| {
+            sender.lock().unwrap().send(level).unwrap();
+        })
+        .unwrap();
+        assert_eq!(bus.subscriber_count("level"), 1);
+
+        assert_eq!(bus.publish("level", &87), Ok(1));
+        assert_eq!(bus.publish("charging", &true), Ok(0));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [87]);
+
+        subscription.unsubscribe().unwrap();
+        assert_eq!(bus.subscriber_count("level"), 0);
+        assert_eq!(bus.publish("level", &88), Ok(0));
+    }
+
+    #[test]
+    fn failed_deliveries_follow_policy() {
+        let bus = EventBus::with_policy(DeliveryFailurePolicy::UnsubscribeAfter(2));
+        // Events of the wrong type fail to deserialize, so delivery fails.
+        let _subscription = subscribe(&bus.as_binder(), "name", |_: String| {}).unwrap();
+
+        assert_eq!(bus.publish("name", &1), Ok(0));
+        assert_eq!(bus.subscriber_count("name"), 1);
+        assert_eq!(bus.publish("name", &2), Ok(0));
+        assert_eq!(bus.subscriber_count("name"), 0);
+    }
+}
@@ -0,0 +1,262 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Sharing a service's capacity fairly between calling uids.
+
+use crate::error::{Result, StatusCode};
+use crate::state::ThreadState;
+
+use libc::uid_t;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Condvar, Mutex};
+
+/// Limits how many transactions a service handles at once, and when it is
+/// busy, admits waiting transactions round-robin by calling uid.
+///
+/// Without this, binder threads take transactions in the order they arrive,
+/// so a client which sends many at once can keep every thread busy and delay
+/// other clients indefinitely. With a `FairScheduler` allowing fewer
+/// transactions at once than the service has binder threads, each uid with
+/// waiting transactions gets a turn before any uid gets a second one:
+///
+/// ```ignore
+/// fn on_transact(&self, code, data, reply) -> binder::Result<()> {
+///     self.scheduler.run(|| self.handle(code, data, reply))?
+/// }
+/// ```
+///
+/// A waiting transaction blocks its binder thread, so each uid may only have
+/// a few transactions waiting at once, one by default, and any more are
+/// rejected with `WOULD_BLOCK` rather than waiting, for the client to retry
+/// later. The service should have more binder threads than `max_concurrent`,
+/// to leave threads to receive transactions from other clients while one
+/// client's transactions wait. Transactions must not call back into the same
+/// scheduler, as they could then wait for themselves.
+#[derive(Debug)]
+pub struct FairScheduler {
+    max_concurrent: usize,
+    max_waiting_per_uid: usize,
+    state: Mutex<State>,
+    admitted: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    next_ticket: u64,
+    /// The tickets of waiting transactions, by uid, in order of arrival.
+    waiting: HashMap<uid_t, VecDeque<u64>>,
+    /// The uids with waiting transactions, in the order they get a turn.
+    turns: VecDeque<uid_t>,
+    /// Tickets which have been admitted, but whose threads haven't woken yet.
+    admitted: HashSet<u64>,
+}
+
+impl State {
+    /// Admit waiting transactions while there is capacity.
+    fn admit(&mut self, max_concurrent: usize) -> bool {
+        let mut any = false;
+        while self.running < max_concurrent {
+            let Some(uid) = self.turns.pop_front() else {
+                break;
+            };
+            let queue = self.waiting.get_mut(&uid).expect("uid with a turn has no queue");
+            let ticket = queue.pop_front().expect("uid with a turn has no waiting transactions");
+            if queue.is_empty() {
+                self.waiting.remove(&uid);
+            } else {
+                self.turns.push_back(uid);
+            }
+            self.admitted.insert(ticket);
+            self.running += 1;
+            any = true;
+        }
+        any
+    }
+}
+
+impl FairScheduler {
+    /// Create a scheduler which runs at most `max_concurrent` transactions at
+    /// once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrent` is 0.
+    pub fn new(max_concurrent: usize) -> Self {
+        assert!(max_concurrent > 0, "A scheduler must allow at least one transaction");
+        Self {
+            max_concurrent,
+            max_waiting_per_uid: Self::DEFAULT_MAX_WAITING_PER_UID,
+            state: Mutex::default(),
+            admitted: Condvar::new(),
+        }
+    }
+
+    /// The default number of transactions each uid may have waiting.
+    pub const DEFAULT_MAX_WAITING_PER_UID: usize = 1;
+
+    /// Let each uid have up to `max_waiting_per_uid` transactions waiting for
+    /// their turn, rather than [`DEFAULT_MAX_WAITING_PER_UID`]. With 0,
+    /// transactions are rejected rather than waiting whenever the service is
+    /// busy.
+    ///
+    /// [`DEFAULT_MAX_WAITING_PER_UID`]: Self::DEFAULT_MAX_WAITING_PER_UID
+    pub fn with_max_waiting_per_uid(self, max_waiting_per_uid: usize) -> Self {
+        Self { max_waiting_per_uid, ..self }
+    }
+
+    /// Run `f` for the current transaction once it is the calling uid's turn,
+    /// or fail with `WOULD_BLOCK` if the uid already has as many
+    /// transactions waiting as it may.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> Result<R> {
+        self.run_as(ThreadState::get_calling_uid(), f)
+    }
+
+    /// Run `f` once it is `uid`'s turn, or fail with `WOULD_BLOCK` if `uid`
+    /// already has as many transactions waiting as it may.
+    pub fn run_as<R>(&self, uid: uid_t, f: impl FnOnce() -> R) -> Result<R> {
+        self.acquire(uid)?;
+        let _running = Running(self);
+        Ok(f())
+    }
+
+    /// The number of transactions waiting for their turn.
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.values().map(VecDeque::len).sum()
+    }
+
+    fn acquire(&self, uid: uid_t) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.running < self.max_concurrent && state.turns.is_empty() {
+            state.running += 1;
+            return Ok(());
+        }
+        if state.waiting.get(&uid).map_or(0, VecDeque::len) >= self.max_waiting_per_uid {
+            return Err(StatusCode::WOULD_BLOCK);
+        }
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        let queue = state.waiting.entry(uid).or_default();
+        queue.push_back(ticket);
+        if queue.len() == 1 {
+            state.turns.push_back(uid);
+        }
+        let mut state =
+            self.admitted.wait_while(state, |state| !state.admitted.contains(&ticket)).unwrap();
+        state.admitted.remove(&ticket);
+        Ok(())
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        if state.admit(self.max_concurrent) {
+            self.admitted.notify_all();
+        }
+    }
+}
+
+/// Releases a transaction's place when it finishes, including by panicking.
+struct Running<'a>(&'a FairScheduler);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    fn wait_for_waiting(scheduler: &FairScheduler, count: usize) {
+        while scheduler.waiting() < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn uids_take_turns() {
+        let scheduler = Arc::new(FairScheduler::new(1).with_max_waiting_per_uid(3));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (release, blocked) = mpsc::channel::<()>();
+
+        let blocker = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run_as(0, || blocked.recv().unwrap()).unwrap())
+        };
+        while scheduler.state.lock().unwrap().running == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut threads = Vec::new();
+        let transactions = [(1, "a1"), (1, "a2"), (1, "a3"), (2, "b1")];
+        for (i, (uid, name)) in transactions.into_iter().enumerate() {
+            threads.push({
+                let (scheduler, order) = (scheduler.clone(), order.clone());
+                thread::spawn(move || {
+                    scheduler.run_as(uid, || order.lock().unwrap().push(name)).unwrap();
+                })
+            });
+            wait_for_waiting(&scheduler, i + 1);
+        }
+
+        release.send(()).unwrap();
+        blocker.join().unwrap();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["a1", "b1", "a2", "a3"]);
+        assert_eq!(scheduler.state.lock().unwrap().running, 0);
+    }
+
+    #[test]
+    fn idle_scheduler_runs_immediately() {
+        let scheduler = FairScheduler::new(2);
+        assert_eq!(scheduler.run_as(1, || scheduler.run_as(2, || 42)), Ok(Ok(42)));
+        assert_eq!(scheduler.waiting(), 0);
+    }
+
+    #[test]
+    fn transactions_over_the_waiting_quota_are_rejected() {
+        let scheduler = Arc::new(FairScheduler::new(1));
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocker = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run_as(0, || blocked.recv().unwrap()).unwrap())
+        };
+        while scheduler.state.lock().unwrap().running == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let waiter = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.run_as(1, || ()))
+        };
+        wait_for_waiting(&scheduler, 1);
+        // uid 1 already has a transaction waiting.
+        assert_eq!(scheduler.run_as(1, || ()), Err(StatusCode::WOULD_BLOCK));
+        assert_eq!(scheduler.waiting(), 1);
+
+        release.send(()).unwrap();
+        blocker.join().unwrap();
+        assert_eq!(waiter.join().unwrap(), Ok(()));
+        assert_eq!(FairScheduler::new(1).with_max_waiting_per_uid(0).run_as(1, || 1), Ok(1));
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Holding back oneway calls to processes which are frozen.
+
+use crate::binder::{AsNative, IBinderInternal, TransactionCode, FLAG_ONEWAY};
+use crate::error::{status_result, Result, StatusCode};
+use crate::parcel::{BorrowedParcel, Parcel};
+use crate::proxy::SpIBinder;
+use crate::sys;
+
+use std::collections::VecDeque;
+use std::ffi::c_void;
+use std::fmt;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+/// How many calls a [`FreezeAwareProxy`] queues by default before failing
+/// new ones.
+const DEFAULT_MAX_QUEUED: usize = 64;
+
+/// Sends oneway calls to a remote binder, holding back all but urgent ones
+/// while the process hosting it is frozen, and sending them in order once it
+/// is unfrozen.
+///
+/// Oneway calls to a frozen process pile up in its binder buffer until it is
+/// unfrozen, which can exhaust the buffer so that later calls fail, and every
+/// call wakes the kernel for nothing. This matters most when talking to
+/// cached apps, which are frozen most of the time:
+///
+/// ```ignore
+/// let listener = FreezeAwareProxy::new(listener.as_binder())?;
+/// listener.send_oneway(ON_PROGRESS_TRANSACTION, |parcel| parcel.write(&percent))?;
+/// // Delivered straight away, even if the app is frozen.
+/// listener.send_urgent_oneway(ON_CANCELLED_TRANSACTION, |_| Ok(()))?;
+/// ```
+///
+/// Once the queue is full, new calls fail with `WOULD_BLOCK` until the
+/// process is unfrozen. Calls still queued when the proxy is dropped are
+/// never sent.
+///
+/// Freeze notifications are delivered on a binder thread, so the process must
+/// have started its thread pool.
+pub struct FreezeAwareProxy {
+    inner: Arc<Inner>,
+    callback: *mut sys::AIBinder_FrozenStateCallback,
+}
+
+/// Safety: The callback pointer is only used to remove the callback, which
+/// the NDK allows from any thread.
+unsafe impl Send for FreezeAwareProxy {}
+
+/// Safety: The callback pointer is only used on drop, and everything else is
+/// behind a mutex.
+unsafe impl Sync for FreezeAwareProxy {}
+
+struct Inner {
+    binder: SpIBinder,
+    state: Mutex<State>,
+}
+
+struct State {
+    frozen: bool,
+    queue: VecDeque<QueuedCall>,
+    max_queued: usize,
+}
+
+struct QueuedCall {
+    code: TransactionCode,
+    data: Parcel,
+}
+
+impl fmt::Debug for FreezeAwareProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.inner.state.lock().unwrap();
+        f.debug_struct("FreezeAwareProxy")
+            .field("binder", &self.inner.binder)
+            .field("frozen", &state.frozen)
+            .field("queued", &state.queue.len())
+            .finish()
+    }
+}
+
+impl FreezeAwareProxy {
+    /// Watch the process hosting `binder` for freezes.
+    ///
+    /// Fails with `INVALID_OPERATION` if `binder` is local or an RPC binder,
+    /// or if the kernel doesn't support freeze notifications.
+    pub fn new(binder: SpIBinder) -> Result<Self> {
+        let inner = Arc::new(Inner::new(binder));
+        let cookie = Arc::into_raw(inner.clone());
+        let mut callback = ptr::null_mut();
+        // Safety: `binder` is a valid `AIBinder`, and `cookie` is an owned
+        // `Arc<Inner>`, which the NDK passes to `on_state_changed` for as long
+        // as the callback is registered and then gives back to `on_delete`.
+        let status = unsafe {
+            sys::AIBinder_addFrozenStateChangeCallback(
+                inner.binder.as_native() as *mut sys::AIBinder,
+                Some(on_state_changed),
+                cookie.cast_mut().cast(),
+                Some(on_delete),
+                &mut callback,
+            )
+        };
+        if let Err(error) = status_result(status) {
+            // Safety: The NDK doesn't take ownership of the cookie on failure.
+            drop(unsafe { Arc::from_raw(cookie) });
+            return Err(error);
+        }
+        Ok(Self { inner, callback })
+    }
+
+    /// Queue at most `max_queued` calls while the process is frozen, instead
+    /// of 64.
+    pub fn with_max_queued(self, max_queued: usize) -> Self {
+        self.inner.state.lock().unwrap().max_queued = max_queued;
+        self
+    }
+
+    /// The binder calls are sent to.
+    pub fn binder(&self) -> &SpIBinder {
+        &self.inner.binder
+    }
+
+    /// Whether the process hosting the binder was frozen, as of the latest
+    /// notification.
+    pub fn is_frozen(&self) -> bool {
+        self.inner.state.lock().unwrap().frozen
+    }
+
+    /// The number of calls waiting for the process to be unfrozen.
+    pub fn queued(&self) -> usize {
+        self.inner.state.lock().unwrap().queue.len()
+    }
+
+    /// Send a oneway call, or queue it if the process is frozen.
+    ///
+    /// Calls are sent in the order they are made, so a call made while
+    /// earlier ones are still queued is queued too.
+    pub fn send_oneway<F>(&self, code: TransactionCode, input_callback: F) -> Result<()>
+    where
+        F: FnOnce(BorrowedParcel<'_>) -> Result<()>,
+    {
+        let mut data = self.inner.binder.prepare_transact()?;
+        input_callback(data.borrowed())?;
+        self.inner.send(QueuedCall { code, data })
+    }
+
+    /// Send a oneway call straight away, even if the process is frozen.
+    ///
+    /// Urgent calls overtake any queued ones.
+    pub fn send_urgent_oneway<F>(&self, code: TransactionCode, input_callback: F) -> Result<()>
+    where
+        F: FnOnce(BorrowedParcel<'_>) -> Result<()>,
+    {
+        self.inner.binder.transact(code, FLAG_ONEWAY, input_callback).map(|_| ())
+    }
+
+    /// Drop the queued calls without sending them, returning how many there
+    /// were.
+    pub fn clear_queued(&self) -> usize {
+        let queue = std::mem::take(&mut self.inner.state.lock().unwrap().queue);
+        queue.len()
+    }
+}
+
+impl Drop for FreezeAwareProxy {
+    fn drop(&mut self) {
+        // Safety: `self.callback` was registered by `new`, and is removed only
+        // here.
+        unsafe { sys::AIBinder_removeFrozenStateChangeCallback(self.callback) }
+    }
+}
+
+impl Inner {
+    fn new(binder: SpIBinder) -> Self {
+        let state = State { frozen: false, queue: VecDeque::new(), max_queued: DEFAULT_MAX_QUEUED };
+        Self { binder, state: Mutex::new(state) }
+    }
+
+    fn send(&self, call: QueuedCall) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.frozen || !state.queue.is_empty() {
+            if state.queue.len() >= state.max_queued {
+                return Err(StatusCode::WOULD_BLOCK);
+            }
+            state.queue.push_back(call);
+            return Ok(());
+        }
+        // Send while holding the lock so that a flush can't overtake it.
+        self.binder.submit_transact(call.code, call.data, FLAG_ONEWAY).map(|_| ())
+    }
+
+    fn set_frozen(&self, frozen: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.frozen = frozen;
+        if frozen {
+            return;
+        }
+        for call in std::mem::take(&mut state.queue) {
+            // There is nobody to report a failure to, and the calls after it
+            // may still get through.
+            if let Err(error) = self.binder.submit_transact(call.code, call.data, FLAG_ONEWAY) {
+                let descriptor =
+                    self.binder.clone().get_class().map(|class| class.get_descriptor());
+                crate::debug::log_transaction_failure(
+                    descriptor.as_deref().unwrap_or("<unknown interface>"),
+                    call.code,
+                    Some(error),
+                    None,
+                    &format_args!("Failed to send queued oneway call {}: {error:?}", call.code),
+                );
+            }
+        }
+    }
+}
+
+/// Called by the NDK when the process hosting the binder is frozen or
+/// unfrozen.
+///
+/// # Safety
+///
+/// `cookie` must be a pointer from `Arc::into_raw` for an `Arc<Inner>` which
+/// hasn't been given to `on_delete` yet.
+unsafe extern "C" fn on_state_changed(cookie: *mut c_void, is_frozen: bool) {
+    // Safety: Our caller guarantees that `cookie` is a live `Arc<Inner>`.
+    let inner = unsafe { &*(cookie as *const Inner) };
+    inner.set_frozen(is_frozen);
+}
+
+/// Called by the NDK once the callback has been removed.
+///
+/// # Safety
+///
+/// `cookie` must be a pointer from `Arc::into_raw` for an `Arc<Inner>`, which
+/// is not used again.
+unsafe extern "C" fn on_delete(cookie: *mut c_void) {
+    // Safety: Our caller gives us back ownership of the cookie.
+    drop(unsafe { Arc::from_raw(cookie as *const Inner) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder::{Interface, Remotable};
+    use crate::native::Binder;
+    use std::ffi::CStr;
+    use std::io::Write;
+
+    struct Service(Mutex<Vec<(TransactionCode, i32)>>);
+
+    impl Remotable for Service {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.IFrozen"
+        }
+
+        fn on_transact(
+            &self,
+            code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            self.0.lock().unwrap().push((code, data.read()?));
+            Ok(())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    fn call(inner: &Inner, code: TransactionCode, value: i32) -> Result<()> {
+        let mut data = inner.binder.prepare_transact()?;
+        data.write(&value)?;
+        inner.send(QueuedCall { code, data })
+    }
+
+    #[test]
+    fn local_binders_have_no_freeze_notifications() {
+        let binder = Binder::new(Service(Mutex::default())).as_binder();
+        assert_eq!(FreezeAwareProxy::new(binder).err(), Some(StatusCode::INVALID_OPERATION));
+    }
+
+    #[test]
+    fn calls_are_held_back_while_frozen() {
+        let service = Binder::new(Service(Mutex::default()));
+        let inner = Inner::new(service.as_binder());
+        inner.state.lock().unwrap().max_queued = 2;
+
+        call(&inner, 1, 10).unwrap();
+        inner.set_frozen(true);
+        call(&inner, 2, 20).unwrap();
+        call(&inner, 3, 30).unwrap();
+        assert_eq!(call(&inner, 4, 40), Err(StatusCode::WOULD_BLOCK));
+        assert_eq!(*service.0.lock().unwrap(), [(1, 10)]);
+
+        inner.set_frozen(false);
+        assert_eq!(*service.0.lock().unwrap(), [(1, 10), (2, 20), (3, 30)]);
+        assert!(inner.state.lock().unwrap().queue.is_empty());
+    }
+}
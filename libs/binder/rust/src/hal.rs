@@ -0,0 +1,142 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Finding every instance of a HAL interface.
+
+use crate::binder::{
+    FromIBinder, IBinderInternal, InterfaceDescriptor, Strong, FIRST_CALL_TRANSACTION,
+};
+use crate::error::{ExceptionCode, Result, StatusCode};
+use crate::proxy::SpIBinder;
+use crate::service::{check_service, get_declared_instances, wait_for_service};
+use crate::workers::WorkerPool;
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The transaction code of `getInterfaceVersion`, which AIDL generates for
+/// every versioned interface.
+const GET_INTERFACE_VERSION_TRANSACTION: u32 = FIRST_CALL_TRANSACTION + 16777214;
+
+/// The threads which wait for instances to start.
+static WAITERS: WorkerPool = WorkerPool::new("binder_hal_wait", 8);
+
+/// How [`instances_of_with`] finds instances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstanceQuery {
+    /// How long to wait for all declared instances to start. Instances which
+    /// haven't started by then are left out.
+    pub timeout: Duration,
+    /// The lowest interface version to accept. Instances with an older
+    /// version, or which fail to report one, are left out.
+    pub min_version: Option<i32>,
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+impl Default for InstanceQuery {
+    fn default() -> Self {
+        Self { timeout: Duration::from_secs(5), min_version: None, _non_exhaustive: () }
+    }
+}
+
+/// Returns every declared instance of the HAL interface `T` which starts
+/// within five seconds, by instance name, sorted by name.
+///
+/// ```ignore
+/// for (instance, light) in binder::hal::instances_of::<dyn ILights>()? {
+///     log::info!("{instance}: {:?}", light.getLights()?);
+/// }
+/// ```
+pub fn instances_of<T>() -> Result<Vec<(String, Strong<T>)>>
+where
+    T: FromIBinder + InterfaceDescriptor + ?Sized,
+{
+    instances_of_with(&InstanceQuery::default())
+}
+
+/// Returns every declared instance of the HAL interface `T` which starts in
+/// time and has a new enough version, as described by `query`, by instance
+/// name, sorted by name.
+///
+/// Fails only if the declared instances can't be listed; instances which
+/// are unavailable are left out rather than failing the whole query.
+pub fn instances_of_with<T>(query: &InstanceQuery) -> Result<Vec<(String, Strong<T>)>>
+where
+    T: FromIBinder + InterfaceDescriptor + ?Sized,
+{
+    let descriptor = T::descriptor();
+    let mut instances = get_declared_instances(descriptor)?;
+    instances.sort();
+    instances.dedup();
+
+    // Lazy HALs may take a while to start, so wait for several at once. A
+    // wait which doesn't finish in time keeps its thread until it does, so
+    // once every thread is busy, instances are only checked for, without
+    // waiting for them to start.
+    let (sender, receiver) = mpsc::channel();
+    for instance in &instances {
+        let name = format!("{descriptor}/{instance}");
+        let waiter = {
+            let (sender, instance, name) = (sender.clone(), instance.clone(), name.clone());
+            WAITERS.execute(move || {
+                // The receiver may have timed out and gone away, which is fine.
+                let _ = sender.send((instance, wait_for_service(&name)));
+            })
+        };
+        if waiter.is_err() {
+            let _ = sender.send((instance.clone(), check_service(&name)));
+        }
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + query.timeout;
+    let mut found = Vec::new();
+    while found.len() < instances.len() {
+        let Ok((instance, binder)) =
+            receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        else {
+            break;
+        };
+        let Some(binder) = binder else {
+            continue;
+        };
+        let Ok(service) = FromIBinder::try_from(binder) else {
+            continue;
+        };
+        found.push((instance, service));
+    }
+    if let Some(min_version) = query.min_version {
+        found.retain(|(_, service): &(String, Strong<T>)| {
+            interface_version(&service.as_binder()).is_ok_and(|version| version >= min_version)
+        });
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+/// Asks a service for the version of its AIDL interface, as reported by the
+/// `getInterfaceVersion` method AIDL generates for versioned interfaces.
+///
+/// `binder` must be associated with the interface class, e.g. by coming from
+/// [`Strong::as_binder`], so that the transaction carries the interface token.
+pub fn interface_version(binder: &SpIBinder) -> Result<i32> {
+    let reply = binder.transact(GET_INTERFACE_VERSION_TRANSACTION, 0, |_| Ok(()))?;
+    if reply.read::<i32>()? != ExceptionCode::NONE as i32 {
+        return Err(StatusCode::UNKNOWN_TRANSACTION);
+    }
+    reply.read()
+}
@@ -0,0 +1,243 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Client-side transaction latency histograms.
+//!
+//! The bucket layout matches `com.android.internal.os.BinderLatencyBuckets`,
+//! which is what the binder latency statsd puller aggregates, so snapshots
+//! from Rust clients can be reported alongside those from Java.
+
+use crate::binder::{TransactionCode, TransactionFlags};
+use crate::error::Result;
+use crate::parcel::Parcel;
+use crate::proxy::{NextLayer, TransactionLayer};
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Exponentially growing histogram buckets, in milliseconds.
+///
+/// The first bucket covers `[0, first_bucket_size)`, each following bucket
+/// starts at `scale_factor` times the unrounded start of the previous one,
+/// rounded down (but at least 1ms after the previous start), and the last
+/// bucket is unbounded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyBuckets {
+    // Start of every bucket except the first, which always starts at 0.
+    starts: Vec<u32>,
+}
+
+impl LatencyBuckets {
+    /// Number of buckets used by the binder latency statsd atom.
+    pub const DEFAULT_BUCKET_COUNT: usize = 100;
+    /// Size of the first bucket used by the binder latency statsd atom, in ms.
+    pub const DEFAULT_FIRST_BUCKET_SIZE_MS: u32 = 5;
+    /// Growth factor used by the binder latency statsd atom.
+    pub const DEFAULT_SCALE_FACTOR: f32 = 1.125;
+
+    /// Create a bucket layout with `bucket_count` buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is less than 2 or `first_bucket_size_ms` is 0.
+    pub fn new(bucket_count: usize, first_bucket_size_ms: u32, scale_factor: f32) -> Self {
+        assert!(bucket_count >= 2, "A latency histogram needs at least two buckets");
+        assert!(first_bucket_size_ms > 0, "The first latency bucket must not be empty");
+
+        // This follows BinderLatencyBuckets step by step, including its truncation of each target
+        // and its use of Java's int range, so that the boundaries agree exactly.
+        let mut starts = Vec::with_capacity(bucket_count - 1);
+        starts.push(first_bucket_size_ms);
+        let mut last_target = f64::from(first_bucket_size_ms);
+        while starts.len() < bucket_count - 1 {
+            let next_target = last_target * f64::from(scale_factor);
+            if next_target > f64::from(i32::MAX) {
+                // The remaining buckets would be unreachable.
+                break;
+            }
+            let previous = *starts.last().unwrap();
+            let truncated = next_target as u32;
+            starts.push(if truncated > previous { truncated } else { previous + 1 });
+            last_target = next_target;
+        }
+        Self { starts }
+    }
+
+    /// Total number of buckets.
+    pub fn bucket_count(&self) -> usize {
+        self.starts.len() + 1
+    }
+
+    /// The start of each bucket after the first, in milliseconds.
+    pub fn bucket_starts_ms(&self) -> &[u32] {
+        &self.starts
+    }
+
+    /// Returns the index of the bucket that `latency` falls into.
+    pub fn bucket_for(&self, latency: Duration) -> usize {
+        let ms = u32::try_from(latency.as_millis()).unwrap_or(u32::MAX);
+        self.starts.partition_point(|&start| start <= ms)
+    }
+}
+
+impl Default for LatencyBuckets {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_BUCKET_COUNT,
+            Self::DEFAULT_FIRST_BUCKET_SIZE_MS,
+            Self::DEFAULT_SCALE_FACTOR,
+        )
+    }
+}
+
+/// A point-in-time copy of a [`LatencyHistogram`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencySnapshot {
+    /// The interface descriptor the histogram was created for.
+    pub interface: String,
+    /// The bucket layout the counts refer to.
+    pub buckets: LatencyBuckets,
+    /// Per transaction code, the number of transactions in each bucket.
+    pub counts: BTreeMap<TransactionCode, Vec<u32>>,
+}
+
+/// A [`TransactionLayer`] that records how long each outgoing transaction
+/// takes, per transaction code.
+///
+/// Failed transactions are recorded too, since slow failures matter as much
+/// as slow successes.
+///
+/// ```ignore
+/// let histogram = Arc::new(LatencyHistogram::new(<BpFoo as Proxy>::get_descriptor()));
+/// let foo = foo.with_layer(histogram.clone())?;
+/// // ... later, from the metrics puller:
+/// let snapshot = histogram.take_snapshot();
+/// ```
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    interface: String,
+    buckets: LatencyBuckets,
+    counts: Mutex<BTreeMap<TransactionCode, Vec<u32>>>,
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram for `interface`, using the default statsd
+    /// bucket layout.
+    pub fn new(interface: &str) -> Self {
+        Self::with_buckets(interface, LatencyBuckets::default())
+    }
+
+    /// Create an empty histogram for `interface` with a custom bucket layout.
+    pub fn with_buckets(interface: &str, buckets: LatencyBuckets) -> Self {
+        Self { interface: interface.to_owned(), buckets, counts: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Record a single transaction.
+    pub fn record(&self, code: TransactionCode, latency: Duration) {
+        let bucket = self.buckets.bucket_for(latency);
+        let mut counts = self.counts.lock().unwrap();
+        let code_counts =
+            counts.entry(code).or_insert_with(|| vec![0; self.buckets.bucket_count()]);
+        code_counts[bucket] = code_counts[bucket].saturating_add(1);
+    }
+
+    /// Return a copy of the current counts.
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            interface: self.interface.clone(),
+            buckets: self.buckets.clone(),
+            counts: self.counts.lock().unwrap().clone(),
+        }
+    }
+
+    /// Return the current counts and reset them to zero, as a single atomic
+    /// operation.
+    pub fn take_snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            interface: self.interface.clone(),
+            buckets: self.buckets.clone(),
+            counts: std::mem::take(&mut *self.counts.lock().unwrap()),
+        }
+    }
+
+    /// Reset all counts to zero.
+    pub fn reset(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+}
+
+impl TransactionLayer for LatencyHistogram {
+    fn transact(
+        &self,
+        code: TransactionCode,
+        data: Parcel,
+        flags: TransactionFlags,
+        next: NextLayer<'_>,
+    ) -> Result<Parcel> {
+        let start = Instant::now();
+        let reply = next.transact(code, data, flags);
+        self.record(code, start.elapsed());
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_buckets_match_statsd() {
+        // The boundaries of com.android.internal.os.BinderLatencyBuckets(100, 5, 1.125f).
+        const JAVA_BUCKET_STARTS: [u32; 99] = [
+            5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 16, 18, 20, 23, 26, 29, 32, 37, 41, 46, 52, 59, 66,
+            75, 84, 95, 106, 120, 135, 152, 171, 192, 216, 243, 274, 308, 347, 390, 439, 494, 555,
+            625, 703, 791, 890, 1001, 1127, 1268, 1426, 1604, 1805, 2031, 2285, 2570, 2892, 3253,
+            3660, 4117, 4632, 5211, 5863, 6595, 7420, 8347, 9391, 10565, 11886, 13371, 15043,
+            16923, 19039, 21418, 24096, 27108, 30496, 34309, 38597, 43422, 48850, 54956, 61826,
+            69554, 78248, 88029, 99033, 111412, 125339, 141006, 158632, 178461, 200769, 225865,
+            254098, 285860, 321593, 361792, 407016, 457893, 515130,
+        ];
+        let buckets = LatencyBuckets::default();
+        assert_eq!(buckets.bucket_count(), 100);
+        assert_eq!(buckets.bucket_starts_ms(), &JAVA_BUCKET_STARTS);
+    }
+
+    #[test]
+    fn bucket_for_latency() {
+        let buckets = LatencyBuckets::new(4, 10, 2.0);
+        assert_eq!(buckets.bucket_starts_ms(), &[10, 20, 40]);
+        assert_eq!(buckets.bucket_for(Duration::from_millis(0)), 0);
+        assert_eq!(buckets.bucket_for(Duration::from_millis(9)), 0);
+        assert_eq!(buckets.bucket_for(Duration::from_millis(10)), 1);
+        assert_eq!(buckets.bucket_for(Duration::from_millis(39)), 2);
+        assert_eq!(buckets.bucket_for(Duration::from_secs(3600)), 3);
+    }
+
+    #[test]
+    fn record_and_take_snapshot() {
+        let histogram = LatencyHistogram::with_buckets("IFoo", LatencyBuckets::new(4, 10, 2.0));
+        histogram.record(1, Duration::from_millis(5));
+        histogram.record(1, Duration::from_millis(25));
+        histogram.record(2, Duration::from_millis(100));
+
+        let snapshot = histogram.take_snapshot();
+        assert_eq!(snapshot.interface, "IFoo");
+        assert_eq!(snapshot.counts[&1], vec![1, 0, 1, 0]);
+        assert_eq!(snapshot.counts[&2], vec![0, 0, 0, 1]);
+        assert!(histogram.snapshot().counts.is_empty());
+    }
+}
@@ -92,34 +92,133 @@
 //!     }
 //! }
 //! ```
+//!
+//! # The `strict` feature
+//!
+//! With the `strict` feature, entry points which would otherwise panic on bad
+//! input or when the NDK fails to allocate an object return an error instead,
+//! and those which can't report an error are removed in favour of fallible
+//! variants, e.g. [`Parcel::try_new`](binder_impl::Parcel::try_new) instead of
+//! `Parcel::new`.
+//!
+//! A few panics remain: the `Clone` implementations of parcels and weak
+//! references (which generated code relies on) and the [`Status`]
+//! constructors panic if the NDK can't allocate a copy, in the same way that
+//! Rust itself aborts when an allocation fails. Declaring an interface with a
+//! descriptor containing a NUL byte panics the first time its class is used.
 
+mod audit;
 #[macro_use]
 mod binder;
 mod binder_async;
+mod channel;
+mod chunked;
+#[cfg(not(trusty))]
+mod client;
+mod context;
+mod correlated;
+pub mod debug;
 mod error;
+pub mod event_bus;
+mod fairness;
+#[cfg(not(trusty))]
+mod freeze;
+#[cfg(not(trusty))]
+pub mod hal;
+mod latency;
 mod native;
+#[macro_use]
+mod ndk_api;
+mod paged;
 mod parcel;
+#[cfg(not(trusty))]
+mod permission;
+#[cfg(not(trusty))]
+mod persistable_bundle;
 mod proxy;
 #[cfg(not(trusty))]
 mod service;
 #[cfg(not(trusty))]
+mod service_cache;
+mod service_name;
+#[cfg(not(trusty))]
+mod shared_memory;
+#[cfg(not(trusty))]
+pub mod shutdown;
+#[cfg(not(trusty))]
 mod state;
+mod swappable;
+#[cfg(not(trusty))]
+mod timeout;
+#[cfg(trusty)]
+mod unsupported;
+#[cfg(not(trusty))]
+mod watchdog;
+#[cfg(not(trusty))]
+mod workers;
+
+#[cfg(trusty)]
+use unsupported::{service, state};
 
 use binder_ndk_sys as sys;
 
 pub use crate::binder_async::{BinderAsyncPool, BoxFuture};
+pub use audit::{AuditEntry, AuditTrail};
 pub use binder::{BinderFeatures, FromIBinder, IBinder, Interface, Strong, Weak};
-pub use error::{ExceptionCode, IntoBinderResult, Status, StatusCode};
-pub use parcel::{ParcelFileDescriptor, Parcelable, ParcelableHolder};
-pub use proxy::{DeathRecipient, SpIBinder, WpIBinder};
+pub use channel::{BinderChannel, ChannelSender};
+pub use chunked::{ChunkedStreamReceiver, ChunkedStreamSender};
+#[cfg(not(trusty))]
+pub use client::{BinderClient, RetryPolicy};
+pub use context::{
+    provided_context, set_context_provider, with_deadline, ContextProvider, DeadlinePropagation,
+    TransactionContext,
+};
+pub use correlated::{CorrelatedChannel, CorrelatedRequest, PendingCall};
+pub use error::{
+    check_supported, ExceptionCode, IntoBinderResult, Status, StatusCode, Unsupported,
+};
+pub use fairness::FairScheduler;
+#[cfg(not(trusty))]
+pub use freeze::FreezeAwareProxy;
+pub use latency::{LatencyBuckets, LatencyHistogram, LatencySnapshot};
+pub use native::{set_panic_policy, PanicPolicy};
+pub use paged::{
+    collect_pages, PageIterator, PageStream, PagedReplies, PagedRequest, PagedResponse,
+};
+#[cfg(feature = "serde")]
+pub use parcel::Serde;
+pub use parcel::{
+    deserialization_limits, set_deserialization_limits, DeserializationLimits, Millis,
+    ParcelFileDescriptor, Parcelable, ParcelableHolder, ParcelableHolderError,
+};
+#[cfg(not(trusty))]
+pub use permission::{
+    check_calling_permission, check_permission, clear_permission_checker,
+    enforce_calling_permission, set_permission_checker, MethodPermission, PermissionTable,
+};
 #[cfg(not(trusty))]
+pub use persistable_bundle::PersistableBundle;
+pub use proxy::{same_binder, BinderId, DeathRecipient, SpIBinder, WpIBinder};
 pub use service::{
-    add_service, check_interface, check_service, force_lazy_services_persist,
-    get_declared_instances, get_interface, get_service, is_declared, is_handling_transaction,
-    register_lazy_service, wait_for_interface, wait_for_service, LazyServiceGuard,
+    add_service, add_service_with_options, add_services, check_interface, check_service,
+    force_lazy_services_persist, get_declared_instances, get_interface, get_service, is_declared,
+    is_handling_transaction, register_lazy_service, wait_for_interface, wait_for_service,
+    wait_for_service_manager, AddServiceOptions, DumpPriority, LazyServiceGuard,
+    ServiceManagerUnavailable,
 };
 #[cfg(not(trusty))]
+pub use service_cache::WeakServiceCache;
+pub use service_name::{ServiceName, MAX_SERVICE_NAME_LEN};
+#[cfg(not(trusty))]
+pub use shared_memory::{MemoryMapping, MemoryMappingMut, SharedMemory};
 pub use state::{ProcessState, ThreadState};
+pub use swappable::SwappableBinder;
+#[cfg(not(trusty))]
+pub use timeout::{
+    default_transaction_timeout, set_default_transaction_timeout, with_transaction_timeout,
+};
+#[cfg(not(trusty))]
+pub use watchdog::{HealthState, ServiceWatchdog};
 
 /// Binder result containing a [`Status`] on error.
 pub type Result<T> = std::result::Result<T, Status>;
@@ -128,19 +227,21 @@ pub type Result<T> = std::result::Result<T, Status>;
 /// without AIDL.
 pub mod binder_impl {
     pub use crate::binder::{
-        IBinderInternal, InterfaceClass, Remotable, Stability, ToAsyncInterface, ToSyncInterface,
-        TransactionCode, TransactionFlags, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY,
-        FLAG_PRIVATE_LOCAL, LAST_CALL_TRANSACTION,
+        IBinderInternal, InterfaceClass, InterfaceDescriptor, Remotable, Stability,
+        ToAsyncInterface, ToSyncInterface, TransactionCode, TransactionFlags,
+        FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY, FLAG_PRIVATE_LOCAL,
+        LAST_CALL_TRANSACTION,
     };
     pub use crate::binder_async::BinderAsyncRuntime;
     pub use crate::error::status_t;
     pub use crate::native::Binder;
     pub use crate::parcel::{
         BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel,
-        ParcelableMetadata, Serialize, SerializeArray, SerializeOption, UnstructuredParcelable,
-        NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
+        ParcelableMetadata, SavedPosition, Serialize, SerializeArray, SerializeOption,
+        StringTableReader, StringTableWriter, UnstructuredParcelable, NON_NULL_PARCELABLE_FLAG,
+        NULL_PARCELABLE_FLAG,
     };
-    pub use crate::proxy::{AssociateClass, Proxy};
+    pub use crate::proxy::{AssociateClass, NextLayer, Proxy, TransactionLayer};
 }
 
 /// Unstable, in-development API that only allowlisted clients are allowed to use.
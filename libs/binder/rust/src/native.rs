@@ -15,9 +15,11 @@
  */
 
 use crate::binder::{
-    AsNative, Interface, InterfaceClassMethods, Remotable, Stability, TransactionCode,
+    AsNative, Interface, InterfaceClass, InterfaceClassMethods, Remotable, Stability,
+    TransactionCode,
 };
-use crate::error::{status_result, status_t, Result, StatusCode};
+use crate::debug::InterfaceMetadata;
+use crate::error::{status_result, status_t, Result, Status, StatusCode};
 use crate::parcel::{BorrowedParcel, Serialize};
 use crate::proxy::SpIBinder;
 use crate::sys;
@@ -28,6 +30,75 @@ use std::io::Write;
 use std::mem::ManuallyDrop;
 use std::ops::Deref;
 use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::RwLock;
+
+/// What a local service does when its transaction handler panics.
+///
+/// Unwinding out of the transaction handler into `libbinder_ndk` is not
+/// allowed, so the panic is caught at that boundary. This only works in
+/// binaries built with `panic=unwind`: with `panic=abort`, a panic aborts the
+/// process before it can be caught, whatever the policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Abort the process. This is the default.
+    #[default]
+    Abort,
+    /// Reply to the caller with a service-specific error with the given code,
+    /// and the panic message as its description, and keep serving. Needs
+    /// `panic=unwind`, like any policy other than aborting.
+    ///
+    /// This is only meaningful for AIDL interfaces, whose replies start with
+    /// a status. Any state the handler shared with other transactions may be
+    /// left inconsistent by the panic, so this is best suited to handlers
+    /// which only panic on bugs that don't affect other callers.
+    ServiceSpecificError(i32),
+}
+
+static PANIC_POLICY: RwLock<PanicPolicy> = RwLock::new(PanicPolicy::Abort);
+
+/// Set what local services in this process do when their transaction handler
+/// panics.
+pub fn set_panic_policy(policy: PanicPolicy) {
+    *PANIC_POLICY.write().unwrap() = policy;
+}
+
+fn handle_panic(
+    descriptor: &str,
+    code: TransactionCode,
+    payload: Box<dyn std::any::Any + Send>,
+    reply: &mut BorrowedParcel<'_>,
+) -> Result<()> {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let message = format!("{} transaction {} panicked: {}", descriptor, code, message);
+    // A poisoned lock still holds a valid policy.
+    let policy = *PANIC_POLICY.read().unwrap_or_else(|e| e.into_inner());
+    match policy {
+        PanicPolicy::Abort => {
+            crate::debug::log_transaction_failure(
+                descriptor,
+                code,
+                None,
+                Some(crate::ThreadState::get_calling_uid()),
+                &message,
+            );
+            std::process::abort()
+        }
+        PanicPolicy::ServiceSpecificError(error_code) => {
+            // Replace whatever the handler wrote before it panicked with just
+            // the status.
+            //
+            // Safety: 0 is always a valid position.
+            unsafe { reply.set_data_position(0)? };
+            reply.write(&Status::new_service_specific_error_str(error_code, Some(message)))?;
+            reply.truncate(reply.get_data_position())
+        }
+    }
+}
 
 /// Rust wrapper around Binder remotable objects.
 ///
@@ -95,6 +166,7 @@ impl<T: Remotable> Binder<T> {
         let ibinder = unsafe { sys::AIBinder_new(class.into(), rust_object as *mut c_void) };
         let mut binder = Binder { ibinder, rust_object };
         binder.mark_stability(stability);
+        crate::debug::on_local_created(T::get_descriptor());
         binder
     }
 
@@ -251,6 +323,10 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
         <T as Remotable>::get_descriptor()
     }
 
+    fn get_metadata() -> Option<InterfaceMetadata> {
+        <T as Remotable>::get_metadata()
+    }
+
     /// Called whenever a transaction needs to be processed by a local
     /// implementation.
     ///
@@ -268,6 +344,10 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
         data: *const sys::AParcel,
         reply: *mut sys::AParcel,
     ) -> status_t {
+        #[cfg(not(trusty))]
+        let Some(_in_flight) = crate::shutdown::start_transaction() else {
+            return StatusCode::DEAD_OBJECT as status_t;
+        };
         let res = {
             // Safety: The caller must give us a parcel pointer which is either
             // null or valid at least for the duration of this function call. We
@@ -277,13 +357,38 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
             // null or valid at least for the duration of this function call. We
             // don't keep the resulting value beyond the function.
             let data = unsafe { BorrowedParcel::from_raw(data as *mut sys::AParcel).unwrap() };
+            let _deadline = crate::context::enter_transaction(&data);
+            // Safety: Our caller promised that `binder` is a non-null, valid
+            // pointer to a local `AIBinder`, which always has a class.
+            let class = unsafe { InterfaceClass::from_ptr(sys::AIBinder_getClass(binder)) };
+            // The user data is only known to be a `T` if the binder is of its
+            // class. This doesn't allocate, so is cheap enough for every call.
+            if !class.has_descriptor(T::get_descriptor()) {
+                return StatusCode::BAD_TYPE as status_t;
+            }
             // Safety: Our caller promised that `binder` is a non-null, valid
             // pointer to a local `AIBinder`.
             let object = unsafe { sys::AIBinder_getUserData(binder) };
+            let _provided = crate::context::enter_provided(object as usize, code);
             // Safety: Our caller promised that the binder has a `T` pointer in
             // its user data.
             let binder: &T = unsafe { &*(object as *const T) };
-            binder.on_transact(code, &data, &mut reply)
+            let on_transact = AssertUnwindSafe(|| binder.on_transact(code, &data, &mut reply));
+            let res = match panic::catch_unwind(on_transact) {
+                Ok(res) => res,
+                Err(payload) => handle_panic(T::get_descriptor(), code, payload, &mut reply),
+            };
+            match res {
+                Ok(()) => crate::debug::on_request_handled(T::get_descriptor(), code, &data),
+                Err(e) => crate::debug::on_transaction_failed(T::get_descriptor(), code, &data, e),
+            }
+            crate::audit::on_transaction_handled(
+                T::get_descriptor(),
+                code,
+                &reply,
+                res.err().unwrap_or(StatusCode::OK),
+            );
+            res
         };
         match res {
             Ok(()) => 0i32,
@@ -302,6 +407,8 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
         // Safety: Our caller promised that `object` is a valid pointer to a
         // `T`.
         drop(unsafe { Box::from_raw(object as *mut T) });
+        crate::context::on_object_destroyed(object as usize);
+        crate::debug::on_local_destroyed(T::get_descriptor());
     }
 
     /// Called whenever a new, local `AIBinder` object is needed of a specific
@@ -0,0 +1,112 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! NDK functions which are newer than our `min_sdk_version`, or in libraries
+//! we don't link against.
+//!
+//! libbinder_rs is available to APEXes which run on Android releases back to
+//! Tiramisu, so it can't link directly against NDK functions which were added
+//! after that: an APEX using it would fail to load on devices whose libraries
+//! don't have them. Such functions are declared with [`ndk_api!`] and looked
+//! up when first used instead, so that callers can fall back to something else
+//! on older devices. The same goes for functions in libraries which aren't
+//! available to every user of libbinder_rs, such as `libandroid`.
+
+#[cfg(not(trusty))]
+use std::ffi::{c_void, CStr};
+
+/// Declares accessors for NDK functions introduced after our
+/// `min_sdk_version`.
+///
+/// For each `fn name(Args...) -> Ret;` in the block, this defines a function
+/// `name() -> Option<unsafe extern "C" fn(Args...) -> Ret>`, which looks up
+/// `name` in `library` the first time it is called and returns `None` if the
+/// device's copy of the library doesn't have it. The signature must match the
+/// declaration in the NDK header.
+///
+/// Trusty links everything statically, so there the functions are used
+/// directly.
+macro_rules! ndk_api {
+    (
+        in $library:literal;
+        $($(#[$meta:meta])* fn $name:ident($($arg:ty),* $(,)?) $(-> $ret:ty)?;)*
+    ) => {
+        $(
+            $(#[$meta])*
+            #[allow(non_snake_case)]
+            #[cfg(not(trusty))]
+            pub(crate) fn $name() -> Option<unsafe extern "C" fn($($arg),*) $(-> $ret)?> {
+                static FUNCTION: std::sync::OnceLock<
+                    Option<unsafe extern "C" fn($($arg),*) $(-> $ret)?>,
+                > = std::sync::OnceLock::new();
+                *FUNCTION.get_or_init(|| {
+                    let symbol = $crate::ndk_api::lookup(
+                        concat!($library, "\0"),
+                        concat!(stringify!($name), "\0"),
+                    );
+                    // Safety: The caller of `ndk_api!` promises that the
+                    // function of this name in `$library` has this signature.
+                    (!symbol.is_null()).then(|| unsafe {
+                        std::mem::transmute::<
+                            *mut std::ffi::c_void,
+                            unsafe extern "C" fn($($arg),*) $(-> $ret)?,
+                        >(symbol)
+                    })
+                })
+            }
+
+            $(#[$meta])*
+            #[allow(non_snake_case)]
+            #[cfg(trusty)]
+            pub(crate) fn $name() -> Option<unsafe extern "C" fn($($arg),*) $(-> $ret)?> {
+                Some($crate::sys::$name)
+            }
+        )*
+    };
+}
+
+/// Returns the address of the function called `name` in `library`, loading
+/// the library if necessary, or null if either can't be found. Both names must
+/// be NUL-terminated.
+#[cfg(not(trusty))]
+pub(crate) fn lookup(library: &str, name: &str) -> *mut c_void {
+    let library = CStr::from_bytes_with_nul(library.as_bytes()).expect("library isn't terminated");
+    let name = CStr::from_bytes_with_nul(name.as_bytes()).expect("name isn't terminated");
+    // Safety: `library` is a valid C string. The handle is never closed, so
+    // functions looked up from it stay valid for the life of the process.
+    let handle = unsafe { libc::dlopen(library.as_ptr(), libc::RTLD_NOW) };
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    // Safety: `handle` is a valid handle from `dlopen`, and `name` is a valid
+    // C string.
+    unsafe { libc::dlsym(handle, name.as_ptr()) }
+}
+
+#[cfg(all(test, not(trusty)))]
+mod tests {
+    ndk_api! {
+        in "libbinder_ndk.so";
+        fn AIBinder_isSystemStable(*mut crate::sys::AIBinder) -> bool;
+        fn AIBinder_doesNotExist();
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert!(AIBinder_isSystemStable().is_some());
+        assert!(AIBinder_doesNotExist().is_none());
+    }
+}
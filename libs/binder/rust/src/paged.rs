@@ -0,0 +1,645 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Sending lists too large for a single transaction, one page at a time.
+//!
+//! Pages are requested with a [`PagedRequest`] and sent as a
+//! [`PagedResponse`], which are parcelables equivalent to:
+//!
+//! ```aidl
+//! parcelable PagedRequest {
+//!     long pageToken;
+//!     int maxItems;
+//! }
+//!
+//! parcelable PagedResponse<T> {
+//!     T[] items;
+//!     long nextPageToken;
+//! }
+//! ```
+//!
+//! A page token of 0 asks for the first page in a request, and marks the last
+//! page in a response; other tokens are chosen by the service. A `maxItems`
+//! of 0 leaves the page size to the service.
+//!
+//! Interfaces which let clients page through a list explicitly, one call per
+//! page, take a `PagedRequest` and return a `PagedResponse`. [`PageIterator`]
+//! and [`PageStream`] walk such an interface on the client, fetching each page
+//! when the previous one runs out.
+//!
+//! Alternatively, a service which returns a list with [`PagedReplies::reply`]
+//! sends as much of it as fits in the reply as its first `PagedResponse`, and
+//! keeps the rest until the client asks for it with follow-up transactions,
+//! which contain a `PagedRequest` and are answered with the next
+//! `PagedResponse`. [`collect_pages`] does this on the client, so callers get
+//! the whole list from one call however large it grows, and small lists
+//! still take a single transaction.
+
+use crate::binder::{IBinderInternal, TransactionCode};
+use crate::binder_async::BoxFuture;
+use crate::error::{Result, StatusCode};
+use crate::parcel::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel, Parcelable,
+    Serialize, SerializeArray, SerializeOption, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
+};
+use crate::proxy::SpIBinder;
+use crate::state::ThreadState;
+
+use libc::uid_t;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The rest of a list, after the pages which have been sent.
+struct Remainder<T> {
+    uid: uid_t,
+    items: Vec<T>,
+}
+
+/// Sends lists of `T` from a service one page at a time.
+pub struct PagedReplies<T> {
+    page_size: usize,
+    max_pending: usize,
+    // Keyed by continuation token, which increases so that the first entry is
+    // the oldest.
+    pending: Mutex<(i64, BTreeMap<i64, Remainder<T>>)>,
+}
+
+impl<T> fmt::Debug for PagedReplies<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PagedReplies")
+            .field("page_size", &self.page_size)
+            .field("max_pending", &self.max_pending)
+            .field("pending", &self.pending.lock().unwrap().1.len())
+            .finish()
+    }
+}
+
+impl<T: SerializeArray> Default for PagedReplies<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: SerializeArray> PagedReplies<T> {
+    /// The default size of the items sent in a page, in bytes.
+    pub const DEFAULT_PAGE_SIZE: usize = 64 * 1024;
+
+    /// The default number of incomplete lists kept for clients.
+    pub const DEFAULT_MAX_PENDING: usize = 16;
+
+    /// Create a sender with the default page size and number of pending
+    /// lists.
+    pub fn new() -> Self {
+        Self::with_limits(Self::DEFAULT_PAGE_SIZE, Self::DEFAULT_MAX_PENDING)
+    }
+
+    /// Create a sender which sends about `page_size` bytes of items per page,
+    /// and keeps at most `max_pending` incomplete lists, forgetting the
+    /// oldest once there are more. A page always has at least one item, so
+    /// an item larger than `page_size` is sent in a page of its own.
+    pub fn with_limits(page_size: usize, max_pending: usize) -> Self {
+        Self { page_size, max_pending, pending: Mutex::new((0, BTreeMap::new())) }
+    }
+
+    /// Write the first page of `items` to `reply`, keeping the rest for
+    /// [`next_page`](Self::next_page).
+    pub fn reply(&self, mut items: Vec<T>, reply: &mut BorrowedParcel<'_>) -> Result<()> {
+        let len = self.page_len(&items, 0)?;
+        if len == items.len() {
+            return write_page(reply, &items, 0);
+        }
+        let rest = items.split_off(len);
+        let token = {
+            let mut pending = self.pending.lock().unwrap();
+            let (last_token, remainders) = &mut *pending;
+            *last_token += 1;
+            remainders.insert(
+                *last_token,
+                Remainder { uid: ThreadState::get_calling_uid(), items: rest },
+            );
+            while remainders.len() > self.max_pending {
+                remainders.pop_first();
+            }
+            *last_token
+        };
+        write_page(reply, &items, token)
+    }
+
+    /// Handle a follow-up transaction, writing the page of the list it
+    /// requests to `reply`.
+    ///
+    /// The service should call this from `on_transact` for the transaction
+    /// code the client passes to [`collect_pages`]. Fails with `BAD_VALUE` if
+    /// the token is unknown or belongs to another uid, e.g. because the list
+    /// was forgotten to make room for newer ones.
+    pub fn next_page(
+        &self,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        let request: PagedRequest = data.read()?;
+        let token = request.page_token;
+        let uid = ThreadState::get_calling_uid();
+        let mut items = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.1.remove(&token) {
+                Some(remainder) if remainder.uid == uid => remainder.items,
+                Some(remainder) => {
+                    pending.1.insert(token, remainder);
+                    return Err(StatusCode::BAD_VALUE);
+                }
+                None => return Err(StatusCode::BAD_VALUE),
+            }
+        };
+        let len = self.page_len(&items, request.max_items)?;
+        if len == items.len() {
+            return write_page(reply, &items, 0);
+        }
+        let rest = items.split_off(len);
+        // The list keeps its token, as the client only has one page in flight.
+        self.pending.lock().unwrap().1.insert(token, Remainder { uid, items: rest });
+        write_page(reply, &items, token)
+    }
+
+    /// The number of items from the start of `items` which fit in a page of
+    /// at most `max_items` items, or any number if it is 0.
+    fn page_len(&self, items: &[T], max_items: i32) -> Result<usize> {
+        let max_items = match usize::try_from(max_items) {
+            Ok(0) | Err(_) => items.len(),
+            Ok(max_items) => max_items.min(items.len()),
+        };
+        let mut scratch = Parcel::try_new()?;
+        for (i, item) in items[..max_items].iter().enumerate() {
+            T::serialize_array(std::slice::from_ref(item), &mut scratch.borrowed())?;
+            if i > 0 && scratch.get_data_size() as usize > self.page_size {
+                return Ok(i);
+            }
+        }
+        Ok(max_items)
+    }
+}
+
+/// Read a list sent with [`PagedReplies::reply`], starting from `reply`, the
+/// reply to the original transaction, and fetching any further pages from
+/// `binder` with transactions of the given `code`.
+pub fn collect_pages<T: SerializeArray + DeserializeArray>(
+    binder: &SpIBinder,
+    code: TransactionCode,
+    reply: &BorrowedParcel<'_>,
+) -> Result<Vec<T>> {
+    let mut request = PagedRequest::first(0);
+    let mut page: PagedResponse<T> = reply.read()?;
+    let mut items = Vec::new();
+    loop {
+        let next = request.after(&page);
+        items.extend(page.items);
+        match next {
+            Some(next) => request = next,
+            None => return Ok(items),
+        }
+        page = binder.transact(code, 0, |mut data| data.write(&request))?.read()?;
+    }
+}
+
+/// Write a page in the layout of a non-null [`PagedResponse`], without
+/// needing to own its items.
+fn write_page<T: SerializeArray>(
+    parcel: &mut BorrowedParcel<'_>,
+    items: &[T],
+    next_page_token: i64,
+) -> Result<()> {
+    parcel.write(&NON_NULL_PARCELABLE_FLAG)?;
+    write_page_fields(parcel, items, next_page_token)
+}
+
+fn write_page_fields<T: SerializeArray>(
+    parcel: &mut BorrowedParcel<'_>,
+    items: &[T],
+    next_page_token: i64,
+) -> Result<()> {
+    parcel.sized_write(|subparcel| {
+        subparcel.write(items)?;
+        subparcel.write(&next_page_token)
+    })
+}
+
+/// A request for one page of a list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PagedRequest {
+    /// The `next_page_token` of the previous page, or 0 for the first page.
+    pub page_token: i64,
+    /// The most items the page should hold, or 0 to let the service decide.
+    pub max_items: i32,
+}
+
+impl PagedRequest {
+    /// A request for the first page, of at most `max_items` items.
+    pub fn first(max_items: i32) -> Self {
+        Self { page_token: 0, max_items }
+    }
+
+    /// The request for the page after `response`, or `None` if `response` is
+    /// the last page.
+    pub fn after<T>(&self, response: &PagedResponse<T>) -> Option<Self> {
+        (!response.is_last())
+            .then_some(Self { page_token: response.next_page_token, max_items: self.max_items })
+    }
+}
+
+impl Parcelable for PagedRequest {
+    fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        parcel.sized_write(|subparcel| {
+            subparcel.write(&self.page_token)?;
+            subparcel.write(&self.max_items)
+        })
+    }
+
+    fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<()> {
+        parcel.sized_read(|subparcel| {
+            if subparcel.has_more_data() {
+                self.page_token = subparcel.read()?;
+            }
+            if subparcel.has_more_data() {
+                self.max_items = subparcel.read()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+crate::impl_serialize_for_parcelable!(PagedRequest);
+crate::impl_deserialize_for_parcelable!(PagedRequest);
+
+/// One page of a list, in reply to a [`PagedRequest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PagedResponse<T> {
+    /// The items of the page.
+    pub items: Vec<T>,
+    /// The token to request the next page with, or 0 if this is the last
+    /// page.
+    pub next_page_token: i64,
+}
+
+impl<T> Default for PagedResponse<T> {
+    fn default() -> Self {
+        Self { items: Vec::new(), next_page_token: 0 }
+    }
+}
+
+impl<T> PagedResponse<T> {
+    /// A page followed by the page with the given token.
+    pub fn new(items: Vec<T>, next_page_token: i64) -> Self {
+        Self { items, next_page_token }
+    }
+
+    /// The last page of a list.
+    pub fn last(items: Vec<T>) -> Self {
+        Self { items, next_page_token: 0 }
+    }
+
+    /// Whether this is the last page of the list.
+    pub fn is_last(&self) -> bool {
+        self.next_page_token == 0
+    }
+}
+
+impl<T: SerializeArray + DeserializeArray> Parcelable for PagedResponse<T> {
+    fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        write_page_fields(parcel, &self.items, self.next_page_token)
+    }
+
+    fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<()> {
+        parcel.sized_read(|subparcel| {
+            if subparcel.has_more_data() {
+                self.items = subparcel.read()?;
+            }
+            if subparcel.has_more_data() {
+                self.next_page_token = subparcel.read()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// `impl_serialize_for_parcelable!` can't add the bounds the items need, so
+// this is what it would generate, with them.
+impl<T: SerializeArray + DeserializeArray> Serialize for PagedResponse<T> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        <Self as SerializeOption>::serialize_option(Some(self), parcel)
+    }
+}
+
+impl<T: SerializeArray + DeserializeArray> SerializeArray for PagedResponse<T> {}
+
+impl<T: SerializeArray + DeserializeArray> SerializeOption for PagedResponse<T> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        if let Some(this) = this {
+            parcel.write(&NON_NULL_PARCELABLE_FLAG)?;
+            this.write_to_parcel(parcel)
+        } else {
+            parcel.write(&NULL_PARCELABLE_FLAG)
+        }
+    }
+}
+
+impl<T: SerializeArray + DeserializeArray> Deserialize for PagedResponse<T> {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Self::default()
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        DeserializeOption::deserialize_option(parcel)
+            .transpose()
+            .unwrap_or(Err(StatusCode::UNEXPECTED_NULL))
+    }
+    fn deserialize_from(&mut self, parcel: &BorrowedParcel<'_>) -> Result<()> {
+        let status: i32 = parcel.read()?;
+        if status == NULL_PARCELABLE_FLAG {
+            Err(StatusCode::UNEXPECTED_NULL)
+        } else {
+            self.read_from_parcel(parcel)
+        }
+    }
+}
+
+impl<T: SerializeArray + DeserializeArray> DeserializeArray for PagedResponse<T> {}
+
+impl<T: SerializeArray + DeserializeArray> DeserializeOption for PagedResponse<T> {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        let mut result = None;
+        Self::deserialize_option_from(&mut result, parcel)?;
+        Ok(result)
+    }
+    fn deserialize_option_from(this: &mut Option<Self>, parcel: &BorrowedParcel<'_>) -> Result<()> {
+        let status: i32 = parcel.read()?;
+        if status == NULL_PARCELABLE_FLAG {
+            *this = None;
+            Ok(())
+        } else {
+            this.get_or_insert_with(Self::default).read_from_parcel(parcel)
+        }
+    }
+}
+
+/// The items of a paged list, fetching each page with a call to `fetch` when
+/// the previous one runs out.
+///
+/// ```ignore
+/// let names = PageIterator::new(PagedRequest::first(100), |request| service.listNames(request));
+/// for name in names {
+///     println!("{}", name?);
+/// }
+/// ```
+///
+/// Iteration stops after the last page, or after yielding the first error.
+pub struct PageIterator<T, F> {
+    fetch: F,
+    next: Option<PagedRequest>,
+    items: VecDeque<T>,
+}
+
+impl<T, F> fmt::Debug for PageIterator<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageIterator")
+            .field("next", &self.next)
+            .field("buffered", &self.items.len())
+            .finish()
+    }
+}
+
+impl<T, F> PageIterator<T, F>
+where
+    F: FnMut(&PagedRequest) -> crate::Result<PagedResponse<T>>,
+{
+    /// Iterate over the list starting from the page `first` asks for.
+    pub fn new(first: PagedRequest, fetch: F) -> Self {
+        Self { fetch, next: Some(first), items: VecDeque::new() }
+    }
+}
+
+impl<T, F> Iterator for PageIterator<T, F>
+where
+    F: FnMut(&PagedRequest) -> crate::Result<PagedResponse<T>>,
+{
+    type Item = crate::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.items.pop_front() {
+                return Some(Ok(item));
+            }
+            let request = self.next.take()?;
+            match (self.fetch)(&request) {
+                Ok(response) => {
+                    self.next = request.after(&response);
+                    self.items.extend(response.items);
+                }
+                Err(status) => return Some(Err(status)),
+            }
+        }
+    }
+}
+
+/// The async equivalent of [`PageIterator`], fetching each page with the
+/// future `fetch` returns.
+///
+/// ```ignore
+/// let mut names = PageStream::new(PagedRequest::first(100), move |request| {
+///     let service = service.clone();
+///     Box::pin(async move { service.listNames(&request).await })
+/// });
+/// while let Some(name) = names.next().await {
+///     println!("{}", name?);
+/// }
+/// ```
+pub struct PageStream<T, F> {
+    fetch: F,
+    next: Option<PagedRequest>,
+    items: VecDeque<T>,
+}
+
+impl<T, F> fmt::Debug for PageStream<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageStream")
+            .field("next", &self.next)
+            .field("buffered", &self.items.len())
+            .finish()
+    }
+}
+
+impl<T, F> PageStream<T, F>
+where
+    F: FnMut(PagedRequest) -> BoxFuture<'static, crate::Result<PagedResponse<T>>>,
+{
+    /// Stream the list starting from the page `first` asks for.
+    pub fn new(first: PagedRequest, fetch: F) -> Self {
+        Self { fetch, next: Some(first), items: VecDeque::new() }
+    }
+
+    /// The next item of the list, or `None` after the last page or the first
+    /// error.
+    pub async fn next(&mut self) -> Option<crate::Result<T>> {
+        loop {
+            if let Some(item) = self.items.pop_front() {
+                return Some(Ok(item));
+            }
+            let request = self.next.take()?;
+            match (self.fetch)(request).await {
+                Ok(response) => {
+                    self.next = request.after(&response);
+                    self.items.extend(response.items);
+                }
+                Err(status) => return Some(Err(status)),
+            }
+        }
+    }
+
+    /// Fetch the rest of the list.
+    pub async fn collect(mut self) -> crate::Result<Vec<T>> {
+        let mut items = Vec::new();
+        while let Some(item) = self.next().await {
+            items.push(item?);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewound(parcel: Parcel) -> Parcel {
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        parcel
+    }
+
+    #[test]
+    fn small_list_is_one_page() {
+        let replies = PagedReplies::<i32>::new();
+        let mut reply = Parcel::new();
+        replies.reply(vec![1, 2, 3], &mut reply.borrowed()).unwrap();
+        let reply = rewound(reply);
+        assert_eq!(reply.read::<PagedResponse<i32>>(), Ok(PagedResponse::last(vec![1, 2, 3])));
+    }
+
+    #[test]
+    fn large_list_is_split_into_pages() {
+        let items: Vec<String> = (0..100).map(|i| format!("item {i:03}")).collect();
+        let replies = PagedReplies::with_limits(256, 4);
+        let mut reply = Parcel::new();
+        replies.reply(items.clone(), &mut reply.borrowed()).unwrap();
+
+        let mut reply = rewound(reply);
+        let mut collected = Vec::new();
+        loop {
+            let page: PagedResponse<String> = reply.read().unwrap();
+            assert!(!page.items.is_empty());
+            let token = page.next_page_token;
+            collected.extend(page.items);
+            if token == 0 {
+                break;
+            }
+            let mut data = Parcel::new();
+            data.write(&PagedRequest { page_token: token, max_items: 0 }).unwrap();
+            let data = rewound(data);
+            let mut next = Parcel::new();
+            replies.next_page(data.borrowed_ref(), &mut next.borrowed()).unwrap();
+            reply = rewound(next);
+        }
+        assert_eq!(collected, items);
+        assert_eq!(replies.pending.lock().unwrap().1.len(), 0);
+    }
+
+    #[test]
+    fn unknown_token_is_rejected() {
+        let replies = PagedReplies::<i32>::new();
+        let mut data = Parcel::new();
+        data.write(&PagedRequest { page_token: 42, max_items: 0 }).unwrap();
+        let data = rewound(data);
+        let mut reply = Parcel::new();
+        assert_eq!(
+            replies.next_page(data.borrowed_ref(), &mut reply.borrowed()),
+            Err(StatusCode::BAD_VALUE)
+        );
+    }
+
+    #[test]
+    fn follow_up_pages_respect_max_items() {
+        let replies = PagedReplies::with_limits(64, 4);
+        let mut reply = Parcel::new();
+        replies.reply((0..10).collect::<Vec<i32>>(), &mut reply.borrowed()).unwrap();
+        let first: PagedResponse<i32> = rewound(reply).read().unwrap();
+        assert!(!first.is_last());
+
+        let mut data = Parcel::new();
+        data.write(&PagedRequest::first(1).after(&first).unwrap()).unwrap();
+        let data = rewound(data);
+        let mut next = Parcel::new();
+        replies.next_page(data.borrowed_ref(), &mut next.borrowed()).unwrap();
+        let next: PagedResponse<i32> = rewound(next).read().unwrap();
+        assert_eq!(next.items.len(), 1);
+        assert_eq!(next.next_page_token, first.next_page_token);
+    }
+
+    #[test]
+    fn paged_response_round_trips() {
+        let response = PagedResponse::new(vec!["a".to_owned(), "b".to_owned()], 7);
+        let mut parcel = Parcel::new();
+        parcel.write(&response).unwrap();
+        parcel.write(&PagedRequest { page_token: 7, max_items: 2 }).unwrap();
+        let parcel = rewound(parcel);
+        assert_eq!(parcel.read::<PagedResponse<String>>(), Ok(response));
+        assert_eq!(parcel.read::<PagedRequest>(), Ok(PagedRequest { page_token: 7, max_items: 2 }));
+    }
+
+    #[test]
+    fn page_iterator_follows_tokens() {
+        let mut requests = Vec::new();
+        let items: Vec<i32> = PageIterator::new(PagedRequest::first(2), |request| {
+            requests.push(*request);
+            let start = request.page_token as i32;
+            let end = (start + request.max_items).min(5);
+            let next = if end == 5 { 0 } else { end as i64 };
+            Ok(PagedResponse::new((start..end).collect(), next))
+        })
+        .collect::<crate::Result<_>>()
+        .unwrap();
+        assert_eq!(items, [0, 1, 2, 3, 4]);
+        let tokens: Vec<i64> = requests.iter().map(|request| request.page_token).collect();
+        assert_eq!(tokens, [0, 2, 4]);
+    }
+
+    #[test]
+    fn page_iterator_stops_after_error() {
+        let mut pages = PageIterator::new(PagedRequest::first(0), |request| {
+            if request.page_token == 0 {
+                Ok(PagedResponse::new(vec![1], 1))
+            } else {
+                Err(StatusCode::DEAD_OBJECT.into())
+            }
+        });
+        assert_eq!(pages.next(), Some(Ok(1)));
+        assert_eq!(pages.next(), Some(Err(StatusCode::DEAD_OBJECT.into())));
+        assert_eq!(pages.next(), None);
+    }
+}
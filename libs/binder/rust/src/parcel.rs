@@ -27,16 +27,29 @@ use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
 use std::ptr::{self, NonNull};
 
+mod datetime;
 mod file_descriptor;
+mod limits;
+mod map;
+#[cfg(feature = "serde")]
+mod parcel_serde;
+#[cfg(feature = "uuid")]
+mod parcel_uuid;
 mod parcelable;
 mod parcelable_holder;
+mod string_table;
 
+pub use self::datetime::Millis;
 pub use self::file_descriptor::ParcelFileDescriptor;
+pub use self::limits::{deserialization_limits, set_deserialization_limits, DeserializationLimits};
+#[cfg(feature = "serde")]
+pub use self::parcel_serde::Serde;
 pub use self::parcelable::{
     Deserialize, DeserializeArray, DeserializeOption, Parcelable, Serialize, SerializeArray,
     SerializeOption, UnstructuredParcelable, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
 };
-pub use self::parcelable_holder::{ParcelableHolder, ParcelableMetadata};
+pub use self::parcelable_holder::{ParcelableHolder, ParcelableHolderError, ParcelableMetadata};
+pub use self::string_table::{StringTableReader, StringTableWriter};
 
 /// Container for a message (data and object references) that can be sent
 /// through Binder.
@@ -73,11 +86,49 @@ pub struct BorrowedParcel<'a> {
 
 impl Parcel {
     /// Create a new empty `Parcel`.
+    #[cfg(not(feature = "strict"))]
     pub fn new() -> Parcel {
+        Self::try_new().expect("AParcel_create returned null pointer")
+    }
+
+    /// Create a new empty `Parcel`, or fail with `NO_MEMORY` if the NDK can't
+    /// allocate one.
+    pub fn try_new() -> Result<Parcel> {
         // Safety: If `AParcel_create` succeeds, it always returns
-        // a valid pointer. If it fails, the process will crash.
+        // a valid pointer, and otherwise null.
         let ptr = unsafe { sys::AParcel_create() };
-        Self { ptr: NonNull::new(ptr).expect("AParcel_create returned null pointer") }
+        NonNull::new(ptr).map(|ptr| Self { ptr }).ok_or(StatusCode::NO_MEMORY)
+    }
+
+    /// Create a new parcel holding a copy of raw parcel data, as returned by
+    /// [`marshall`](BorrowedParcel::marshall).
+    ///
+    /// The data cannot contain binder or file descriptor objects; any that
+    /// were in the original parcel are only plain data in the copy.
+    pub fn unmarshall(data: &[u8]) -> Result<Parcel> {
+        let mut parcel = Parcel::try_new()?;
+        // Safety: `Parcel` always contains a valid pointer to an `AParcel`,
+        // and `data` is valid for reads of `data.len()` bytes. The data is
+        // copied, so it need not outlive this call.
+        let status =
+            unsafe { sys::AParcel_unmarshal(parcel.as_native_mut(), data.as_ptr(), data.len()) };
+        status_result(status)?;
+        Ok(parcel)
+    }
+
+    /// Enable or disable reusing parcel buffers on the calling thread.
+    ///
+    /// While enabled, the data buffers of small parcels dropped on this thread
+    /// are kept and handed to the next parcels written on it, such as the
+    /// ones from [`IBinderInternal::prepare_transact`]. A thread making many
+    /// small calls then saves an allocation and a free on each of them.
+    /// Disabling the pool frees the buffers it holds.
+    ///
+    /// [`IBinderInternal::prepare_transact`]: crate::binder_impl::IBinderInternal::prepare_transact
+    pub fn set_thread_buffer_pool_enabled(enabled: bool) {
+        // Safety: This only changes state of the calling thread, and has no
+        // preconditions.
+        unsafe { sys::AParcel_setThreadBufferPoolEnabled(enabled) }
     }
 
     /// Create an owned reference to a parcel object from a raw pointer.
@@ -102,6 +153,7 @@ impl Parcel {
 
     /// Consume the parcel, transferring ownership to the caller.
     pub(crate) fn into_raw(self) -> *mut sys::AParcel {
+        crate::debug::on_parcel_released(self.borrowed_ref(), false);
         let ptr = self.ptr.as_ptr();
         let _ = ManuallyDrop::new(self);
         ptr
@@ -125,6 +177,7 @@ impl Parcel {
     }
 }
 
+#[cfg(not(feature = "strict"))]
 impl Default for Parcel {
     fn default() -> Self {
         Self::new()
@@ -133,7 +186,7 @@ impl Default for Parcel {
 
 impl Clone for Parcel {
     fn clone(&self) -> Self {
-        let mut new_parcel = Self::new();
+        let mut new_parcel = Self::try_new().expect("AParcel_create returned null pointer");
         new_parcel
             .borrowed()
             .append_all_from(self.borrowed_ref())
@@ -197,16 +250,77 @@ unsafe impl<'a> AsNative<sys::AParcel> for BorrowedParcel<'a> {
 // Data serialization methods
 impl<'a> BorrowedParcel<'a> {
     /// Data written to parcelable is zero'd before being deleted or reallocated.
+    ///
+    /// Marking a reply parcel as sensitive in `on_transact` also sends it with
+    /// [`FLAG_CLEAR_BUF`](crate::binder_impl::FLAG_CLEAR_BUF), so that the
+    /// kernel zeroes its copy once the client has read it.
     pub fn mark_sensitive(&mut self) {
         // Safety: guaranteed to have a parcel object, and this method never fails
         unsafe { sys::AParcel_markSensitive(self.as_native()) }
     }
 
+    /// Returns whether the parcel's data is zero'd before being deleted or
+    /// reallocated, because of [`mark_sensitive`](Self::mark_sensitive), or
+    /// because it belongs to a transaction sent with
+    /// [`FLAG_CLEAR_BUF`](crate::binder_impl::FLAG_CLEAR_BUF).
+    pub fn is_sensitive(&self) -> bool {
+        // Safety: guaranteed to have a parcel object, and this method never fails
+        unsafe { sys::AParcel_isSensitive(self.as_native()) }
+    }
+
     /// Write a type that implements [`Serialize`] to the parcel.
+    ///
+    /// If the parcel's buffer can't grow to fit the data, this fails with
+    /// `NO_MEMORY` rather than aborting the process. So does any temporary
+    /// buffer which this crate's own [`Serialize`] implementations, and those
+    /// generated by its macros, allocate along the way. Allocations made by
+    /// other implementations of [`Serialize`] are up to them.
     pub fn write<S: Serialize + ?Sized>(&mut self, parcelable: &S) -> Result<()> {
         parcelable.serialize(self)
     }
 
+    /// Write an array given references to its elements.
+    ///
+    /// This writes the same data as writing a slice of the referenced values,
+    /// but does not need them to be cloned into a contiguous `Vec` first.
+    pub fn write_array_of_refs<'t, T: SerializeArray + 't>(
+        &mut self,
+        values: impl IntoIterator<Item = &'t T>,
+    ) -> Result<()> {
+        let refs: Vec<&T> = try_collect(values)?;
+        T::serialize_ref_array(&refs, self)
+    }
+
+    /// Write the values of an iterator as an array, in the same format as a
+    /// slice of them, without collecting them into a `Vec` first.
+    ///
+    /// Byte arrays are packed, so `u8` and `i8` values are still collected
+    /// before writing.
+    pub fn write_iter<T: SerializeArray>(
+        &mut self,
+        values: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        T::serialize_iter(values.into_iter(), self)
+    }
+
+    /// Write the values of an iterator as an array, like
+    /// [`write_iter`](Self::write_iter), but fail with `BAD_VALUE` unless it
+    /// yields exactly `len` of them, e.g. for AIDL fixed-size arrays.
+    pub fn write_iter_exact<T: SerializeArray>(
+        &mut self,
+        len: usize,
+        values: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        let mut count = 0;
+        // One more value than expected is enough to know there are too many.
+        let values = values.into_iter().take(len.saturating_add(1)).inspect(|_| count += 1);
+        T::serialize_iter(values, self)?;
+        if count != len {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        Ok(())
+    }
+
     /// Writes the length of a slice to the parcel.
     ///
     /// This is used in AIDL-generated client side code to indicate the
@@ -302,10 +416,44 @@ impl<'a> BorrowedParcel<'a> {
         status_result(unsafe { sys::AParcel_setDataPosition(self.as_native(), pos) })
     }
 
+    /// Returns the number of bytes between the current position and the end of
+    /// the parcel data.
+    pub fn get_data_avail(&self) -> i32 {
+        (self.get_data_size() - self.get_data_position()).max(0)
+    }
+
+    /// Move the current read/write position in the parcel, or fail with
+    /// `BAD_VALUE` if `pos` is not less than the current size of the parcel
+    /// data buffer.
+    ///
+    /// This is the safe counterpart of [`set_data_position`](Self::set_data_position), and
+    /// accepts exactly the positions which that documents as safe.
+    pub fn try_set_data_position(&self, pos: i32) -> Result<()> {
+        if !(0..self.get_data_size()).contains(&pos) {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        // Safety: We just checked that `pos` is less than the current size of
+        // the parcel data buffer.
+        unsafe { self.set_data_position(pos) }
+    }
+
+    /// Drop the data after the first `size` bytes of the parcel, or fail with
+    /// `BAD_VALUE` if the parcel is smaller than that.
+    pub(crate) fn truncate(&mut self, size: i32) -> Result<()> {
+        // Safety: `BorrowedParcel` always contains a valid pointer to an
+        // `AParcel`, which we have exclusive access to, and the size is
+        // checked against the parcel data.
+        status_result(unsafe { sys::AParcel_truncate(self.as_native_mut(), size) })
+    }
+
     /// Append a subset of another parcel.
     ///
     /// This appends `size` bytes of data from `other` starting at offset
     /// `start` to the current parcel, or returns an error if not possible.
+    ///
+    /// Binders and file descriptors within the range are copied along with
+    /// the data. Both parcels must be for the same kind of transport, and RPC
+    /// parcels for the same session, or this fails with `BAD_TYPE`.
     pub fn append_from(
         &mut self,
         other: &impl AsNative<sys::AParcel>,
@@ -328,6 +476,42 @@ impl<'a> BorrowedParcel<'a> {
         let size = unsafe { sys::AParcel_getDataSize(other.as_native()) };
         self.append_from(other, 0, size)
     }
+
+    /// Append the contents of another parcel after its current data position.
+    ///
+    /// This lets a relay read the fields it needs from the start of a parcel,
+    /// and forward the rest untouched. The data position of `other` isn't
+    /// moved.
+    pub fn append_remaining_from(&mut self, other: &impl AsNative<sys::AParcel>) -> Result<()> {
+        // Safety: `BorrowedParcel` always contains a valid pointer to an
+        // `AParcel`, and these calls are otherwise safe.
+        let (start, size) = unsafe {
+            let other = other.as_native();
+            (sys::AParcel_getDataPosition(other), sys::AParcel_getDataSize(other))
+        };
+        self.append_from(other, start, size - start)
+    }
+
+    /// Copy out the raw data of the parcel, e.g. to compare the contents of
+    /// transactions in tests or to seed a fuzzer.
+    ///
+    /// This fails with `INVALID_OPERATION` if the parcel contains binders or
+    /// file descriptors. The format of the data is specific to the platform
+    /// build which wrote it, so it mustn't be persisted or sent elsewhere.
+    pub fn marshall(&self) -> Result<Vec<u8>> {
+        let size = usize::try_from(self.get_data_size()).or(Err(StatusCode::BAD_VALUE))?;
+        let mut data = Vec::new();
+        if size == 0 {
+            return Ok(data);
+        }
+        data.try_reserve_exact(size).or(Err(StatusCode::NO_MEMORY))?;
+        data.resize(size, 0);
+        // Safety: `BorrowedParcel` always contains a valid pointer to an
+        // `AParcel`, and `data` is valid for writes of `size` bytes.
+        let status = unsafe { sys::AParcel_marshal(self.as_native(), data.as_mut_ptr(), 0, size) };
+        status_result(status)?;
+        Ok(data)
+    }
 }
 
 /// A segment of a writable parcel, used for [`BorrowedParcel::sized_write`].
@@ -346,11 +530,45 @@ impl Parcel {
         self.borrowed().mark_sensitive()
     }
 
+    /// Returns whether the parcel's data is zero'd before being deleted or
+    /// reallocated. See [`BorrowedParcel::is_sensitive`].
+    pub fn is_sensitive(&self) -> bool {
+        self.borrowed_ref().is_sensitive()
+    }
+
     /// Write a type that implements [`Serialize`] to the parcel.
     pub fn write<S: Serialize + ?Sized>(&mut self, parcelable: &S) -> Result<()> {
         self.borrowed().write(parcelable)
     }
 
+    /// Write an array given references to its elements. See
+    /// [`BorrowedParcel::write_array_of_refs`].
+    pub fn write_array_of_refs<'t, T: SerializeArray + 't>(
+        &mut self,
+        values: impl IntoIterator<Item = &'t T>,
+    ) -> Result<()> {
+        self.borrowed().write_array_of_refs(values)
+    }
+
+    /// Write the values of an iterator as an array. See
+    /// [`BorrowedParcel::write_iter`].
+    pub fn write_iter<T: SerializeArray>(
+        &mut self,
+        values: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        self.borrowed().write_iter(values)
+    }
+
+    /// Write exactly `len` values of an iterator as an array. See
+    /// [`BorrowedParcel::write_iter_exact`].
+    pub fn write_iter_exact<T: SerializeArray>(
+        &mut self,
+        len: usize,
+        values: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        self.borrowed().write_iter_exact(len, values)
+    }
+
     /// Writes the length of a slice to the parcel.
     ///
     /// This is used in AIDL-generated client side code to indicate the
@@ -418,6 +636,19 @@ impl Parcel {
         unsafe { self.borrowed_ref().set_data_position(pos) }
     }
 
+    /// Returns the number of bytes between the current position and the end of
+    /// the parcel data.
+    pub fn get_data_avail(&self) -> i32 {
+        self.borrowed_ref().get_data_avail()
+    }
+
+    /// Move the current read/write position in the parcel, or fail with
+    /// `BAD_VALUE` if `pos` is not less than the current size of the parcel
+    /// data buffer. See [`BorrowedParcel::try_set_data_position`].
+    pub fn try_set_data_position(&self, pos: i32) -> Result<()> {
+        self.borrowed_ref().try_set_data_position(pos)
+    }
+
     /// Append a subset of another parcel.
     ///
     /// This appends `size` bytes of data from `other` starting at offset
@@ -435,6 +666,17 @@ impl Parcel {
     pub fn append_all_from(&mut self, other: &impl AsNative<sys::AParcel>) -> Result<()> {
         self.borrowed().append_all_from(other)
     }
+
+    /// Append the contents of another parcel after its current data position.
+    /// See [`BorrowedParcel::append_remaining_from`].
+    pub fn append_remaining_from(&mut self, other: &impl AsNative<sys::AParcel>) -> Result<()> {
+        self.borrowed().append_remaining_from(other)
+    }
+
+    /// Copy out the raw data of the parcel. See [`BorrowedParcel::marshall`].
+    pub fn marshall(&self) -> Result<Vec<u8>> {
+        self.borrowed_ref().marshall()
+    }
 }
 
 // Data deserialization methods
@@ -451,6 +693,62 @@ impl<'a> BorrowedParcel<'a> {
         x.deserialize_from(self)
     }
 
+    /// Read a byte array, as written for `[u8]` or `Vec<u8>`, without copying
+    /// it out of the parcel.
+    ///
+    /// The returned slice points into the parcel's data buffer, so large
+    /// payloads can be parsed in place. A null array fails with
+    /// `UNEXPECTED_NULL`, as reading a `Vec<u8>` does.
+    pub fn read_byte_slice(&self) -> Result<&[u8]> {
+        self.read_nullable_byte_slice()?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+
+    /// Read an array with at most `max_len` elements, or fail with
+    /// `BAD_VALUE` before allocating it if its length is longer.
+    ///
+    /// This bounds the allocation for one array read from an untrusted
+    /// parcel, on top of any process-wide [`DeserializationLimits`]. The
+    /// elements of the array are only bounded by the latter. If the array is
+    /// too long, the data position is left at its start.
+    pub fn read_vec_bounded<T: DeserializeArray>(&self, max_len: usize) -> Result<Vec<T>> {
+        self.read_nullable_vec_bounded(max_len)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+
+    /// Read an array which may be null with at most `max_len` elements. See
+    /// [`read_vec_bounded`](Self::read_vec_bounded).
+    pub fn read_nullable_vec_bounded<T: DeserializeArray>(
+        &self,
+        max_len: usize,
+    ) -> Result<Option<Vec<T>>> {
+        let len = parcelable::peek_array_len(self)?;
+        if len > 0 && len as usize > max_len {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        T::deserialize_array(self)
+    }
+
+    /// Read a byte array which may be null, as written for `Option<&[u8]>` or
+    /// `Option<Vec<u8>>`, without copying it out of the parcel. See
+    /// [`read_byte_slice`](Self::read_byte_slice).
+    pub fn read_nullable_byte_slice(&self) -> Result<Option<&[u8]>> {
+        let mut data = ptr::null();
+        let mut len = 0;
+        // Safety: `BorrowedParcel` always contains a valid pointer to an
+        // `AParcel`, and both out pointers are valid for writes.
+        let status =
+            unsafe { sys::AParcel_readByteArrayInPlace(self.as_native(), &mut data, &mut len) };
+        status_result(status)?;
+        match len {
+            ..=-1 => Ok(None),
+            0 => Ok(Some(&[])),
+            // Safety: The NDK points `data` at `len` bytes in the parcel's
+            // data buffer, which stays valid until the parcel is written to.
+            // That needs a mutable borrow of the parcel, so it can't happen
+            // while the slice borrows `self`.
+            _ => Ok(Some(unsafe { std::slice::from_raw_parts(data.cast::<u8>(), len as usize) })),
+        }
+    }
+
     /// Safely read a sized parcelable.
     ///
     /// Read the size of a parcelable, compute the end position
@@ -483,6 +781,7 @@ impl<'a> BorrowedParcel<'a> {
     where
         for<'b> F: FnOnce(ReadableSubParcel<'b>) -> Result<()>,
     {
+        let _nesting = limits::NestingGuard::enter()?;
         let start = self.get_data_position();
         let parcelable_size: i32 = self.read()?;
         if parcelable_size < 4 {
@@ -512,6 +811,36 @@ impl<'a> BorrowedParcel<'a> {
         Ok(())
     }
 
+    /// Speculatively read from the parcel.
+    ///
+    /// Calls `f` with this parcel and, if it returns an error, moves the data
+    /// position back to where it was before the call. This makes it easy to
+    /// try reading something that may not be there, such as a trailing field
+    /// added in a newer version of a parcelable:
+    ///
+    /// ```ignore
+    /// let extra: Option<i32> = parcel.scoped(|p| p.read()).ok();
+    /// ```
+    pub fn scoped<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Self) -> std::result::Result<T, E>,
+    {
+        let saved = self.save_position();
+        let result = f(self);
+        if result.is_ok() {
+            saved.commit();
+        }
+        result
+    }
+
+    /// Remember the current data position, to return to it later.
+    ///
+    /// The position is restored when the returned guard is dropped, unless
+    /// [`SavedPosition::commit`] is called first.
+    pub fn save_position(&self) -> SavedPosition<'_, 'a> {
+        SavedPosition { parcel: self, position: Some(self.get_data_position()) }
+    }
+
     /// Read a vector size from the parcel and resize the given output vector to
     /// be correctly sized for that amount of data.
     ///
@@ -526,6 +855,7 @@ impl<'a> BorrowedParcel<'a> {
 
         // usize in Rust may be 16-bit, so i32 may not fit
         let len = len.try_into().unwrap();
+        limits::check_elements(len)?;
         out_vec.resize_with(len, Default::default);
 
         Ok(())
@@ -548,6 +878,7 @@ impl<'a> BorrowedParcel<'a> {
         } else {
             // usize in Rust may be 16-bit, so i32 may not fit
             let len = len.try_into().unwrap();
+            limits::check_elements(len)?;
             let mut vec = Vec::with_capacity(len);
             vec.resize_with(len, Default::default);
             *out_vec = Some(vec);
@@ -573,6 +904,72 @@ impl<'a> ReadableSubParcel<'a> {
     pub fn has_more_data(&self) -> bool {
         self.parcel.get_data_position() < self.end_position
     }
+
+    /// Returns the current position in the parcel data.
+    pub fn get_data_position(&self) -> i32 {
+        self.parcel.get_data_position()
+    }
+
+    /// Returns the number of bytes left in the sub-parcel.
+    pub fn get_data_avail(&self) -> i32 {
+        (self.end_position - self.parcel.get_data_position()).max(0)
+    }
+
+    /// Speculatively read from the sub-parcel, moving the data position back
+    /// if `f` fails. See [`BorrowedParcel::scoped`].
+    pub fn scoped<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Self) -> std::result::Result<T, E>,
+    {
+        let saved = self.parcel.save_position();
+        let result = f(self);
+        if result.is_ok() {
+            saved.commit();
+        }
+        result
+    }
+}
+
+/// A saved data position of a parcel, created by
+/// [`BorrowedParcel::save_position`].
+///
+/// Dropping this moves the parcel's data position back to the saved position,
+/// unless it has been committed.
+#[must_use = "the position is restored as soon as this is dropped"]
+#[derive(Debug)]
+pub struct SavedPosition<'p, 'a> {
+    parcel: &'p BorrowedParcel<'a>,
+    position: Option<i32>,
+}
+
+impl<'p, 'a> SavedPosition<'p, 'a> {
+    /// The saved data position.
+    pub fn position(&self) -> i32 {
+        // Only `commit` and `restore` take the position, and both consume
+        // `self`.
+        self.position.unwrap()
+    }
+
+    /// Move the parcel's data position back to the saved position now.
+    pub fn restore(self) {
+        // Dropping `self` restores the position.
+    }
+
+    /// Keep the parcel's current data position.
+    pub fn commit(mut self) {
+        self.position = None;
+    }
+}
+
+impl<'p, 'a> Drop for SavedPosition<'p, 'a> {
+    fn drop(&mut self) {
+        if let Some(position) = self.position.take() {
+            // Safety: `position` was the data position of the parcel when it
+            // was saved, and reading never shrinks the parcel data, so it is
+            // still within bounds.
+            let _ = unsafe { self.parcel.set_data_position(position) };
+        }
+    }
 }
 
 impl Parcel {
@@ -588,6 +985,33 @@ impl Parcel {
         self.borrowed_ref().read_onto(x)
     }
 
+    /// Read a byte array without copying it out of the parcel. See
+    /// [`BorrowedParcel::read_byte_slice`].
+    pub fn read_byte_slice(&self) -> Result<&[u8]> {
+        self.borrowed_ref().read_byte_slice()
+    }
+
+    /// Read an array with at most `max_len` elements. See
+    /// [`BorrowedParcel::read_vec_bounded`].
+    pub fn read_vec_bounded<T: DeserializeArray>(&self, max_len: usize) -> Result<Vec<T>> {
+        self.borrowed_ref().read_vec_bounded(max_len)
+    }
+
+    /// Read an array which may be null with at most `max_len` elements. See
+    /// [`BorrowedParcel::read_vec_bounded`].
+    pub fn read_nullable_vec_bounded<T: DeserializeArray>(
+        &self,
+        max_len: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.borrowed_ref().read_nullable_vec_bounded(max_len)
+    }
+
+    /// Read a byte array which may be null without copying it out of the
+    /// parcel. See [`BorrowedParcel::read_nullable_byte_slice`].
+    pub fn read_nullable_byte_slice(&self) -> Result<Option<&[u8]>> {
+        self.borrowed_ref().read_nullable_byte_slice()
+    }
+
     /// Safely read a sized parcelable.
     ///
     /// Read the size of a parcelable, compute the end position
@@ -623,6 +1047,21 @@ impl Parcel {
         self.borrowed_ref().sized_read(f)
     }
 
+    /// Speculatively read from the parcel, moving the data position back if
+    /// `f` fails. See [`BorrowedParcel::scoped`].
+    pub fn scoped<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&BorrowedParcel<'_>) -> std::result::Result<T, E>,
+    {
+        self.borrowed_ref().scoped(f)
+    }
+
+    /// Remember the current data position, to return to it later. See
+    /// [`BorrowedParcel::save_position`].
+    pub fn save_position(&self) -> SavedPosition<'_, '_> {
+        self.borrowed_ref().save_position()
+    }
+
     /// Read a vector size from the parcel and resize the given output vector to
     /// be correctly sized for that amount of data.
     ///
@@ -683,8 +1122,27 @@ impl<'a> BorrowedParcel<'a> {
     }
 }
 
+/// Collects `values` into a new `Vec`, failing with `NO_MEMORY` instead of
+/// aborting if it can't be allocated.
+///
+/// Serialization code should use this for any temporary buffers, so that
+/// writing to a parcel degrades gracefully in low-memory processes.
+pub(crate) fn try_collect<T>(values: impl IntoIterator<Item = T>) -> Result<Vec<T>> {
+    let values = values.into_iter();
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(values.size_hint().0).or(Err(StatusCode::NO_MEMORY))?;
+    for value in values {
+        if vec.len() == vec.capacity() {
+            vec.try_reserve(1).or(Err(StatusCode::NO_MEMORY))?;
+        }
+        vec.push(value);
+    }
+    Ok(vec)
+}
+
 impl Drop for Parcel {
     fn drop(&mut self) {
+        crate::debug::on_parcel_released(self.borrowed_ref(), true);
         // Run the C++ Parcel complete object destructor
         //
         // Safety: `Parcel` always contains a valid pointer to an
@@ -706,6 +1164,70 @@ impl<'a> fmt::Debug for BorrowedParcel<'a> {
     }
 }
 
+#[test]
+fn test_write_array_of_refs() {
+    struct Record {
+        name: String,
+        id: i32,
+    }
+
+    let records = [
+        Record { name: "foo".to_string(), id: 1 },
+        Record { name: "bar".to_string(), id: 2 },
+        Record { name: "baz".to_string(), id: 3 },
+    ];
+    let mut parcel = Parcel::new();
+    let names = records.iter().filter(|r| r.id != 2).map(|r| &r.name);
+    assert!(parcel.write_array_of_refs(names).is_ok());
+
+    // SAFETY: 0 is less than the current size of the parcel data buffer.
+    unsafe {
+        parcel.set_data_position(0).unwrap();
+    }
+    assert_eq!(parcel.read::<Vec<String>>().unwrap(), ["foo", "baz"]);
+}
+
+#[test]
+fn test_try_collect() {
+    assert_eq!(try_collect(1..4), Ok(vec![1, 2, 3]));
+    // The lower bound of the size hint is 0, so the vector has to grow.
+    assert_eq!(try_collect((0..100).filter(|i| i % 10 == 0)).unwrap().len(), 10);
+}
+
+#[test]
+fn test_scoped_read() {
+    let mut parcel = Parcel::new();
+    parcel.write(&1i32).unwrap();
+    parcel.write(&2i32).unwrap();
+
+    // SAFETY: 0 is less than the current size of the parcel data buffer.
+    unsafe {
+        parcel.set_data_position(0).unwrap();
+    }
+
+    let result = parcel.scoped(|p| {
+        p.read::<i32>()?;
+        Err::<(), _>(StatusCode::BAD_VALUE)
+    });
+    assert_eq!(result, Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.get_data_position(), 0);
+    assert_eq!(parcel.scoped(|p| p.read::<i32>()), Ok(1));
+    assert_eq!(parcel.get_data_position(), 4);
+    assert_eq!(parcel.scoped(|p| p.read::<i64>()), Err(StatusCode::NOT_ENOUGH_DATA));
+    assert_eq!(parcel.get_data_position(), 4);
+
+    let saved = parcel.save_position();
+    assert_eq!(parcel.read::<i32>(), Ok(2));
+    assert_eq!(saved.position(), 4);
+    saved.restore();
+    assert_eq!(parcel.get_data_position(), 4);
+
+    let saved = parcel.save_position();
+    assert_eq!(parcel.read::<i32>(), Ok(2));
+    saved.commit();
+    assert_eq!(parcel.get_data_position(), 8);
+}
+
 #[test]
 fn test_read_write() {
     let mut parcel = Parcel::new();
@@ -957,3 +1479,178 @@ fn test_append_from() {
     assert_eq!(Err(StatusCode::BAD_VALUE), parcel2.append_from(&parcel1, -1, 4));
     assert_eq!(Err(StatusCode::BAD_VALUE), parcel2.append_from(&parcel1, 2, -1));
 }
+
+#[test]
+fn test_append_remaining_from() {
+    let mut parcel1 = Parcel::new();
+    parcel1.write(&1i32).unwrap();
+    parcel1.write(&2i32).unwrap();
+    parcel1.write(&3i32).unwrap();
+    // SAFETY: 0 is less than the current size of the parcel data buffer, because the parcel is not
+    // empty.
+    unsafe {
+        parcel1.set_data_position(0).unwrap();
+    }
+    assert_eq!(Ok(1), parcel1.read::<i32>());
+
+    let mut parcel2 = Parcel::new();
+    assert_eq!(Ok(()), parcel2.append_remaining_from(&parcel1));
+    assert_eq!(8, parcel2.get_data_size());
+    assert_eq!(4, parcel1.get_data_position());
+    // SAFETY: 0 is less than the current size of the parcel data buffer, because the parcel is not
+    // empty.
+    unsafe {
+        parcel2.set_data_position(0).unwrap();
+    }
+    assert_eq!(Ok(2), parcel2.read::<i32>());
+    assert_eq!(Ok(3), parcel2.read::<i32>());
+}
+
+#[test]
+fn test_read_byte_slice() {
+    let mut parcel = Parcel::new();
+    parcel.write(&[1u8, 2, 3, 4, 5][..]).unwrap();
+    parcel.write(&Vec::<u8>::new()).unwrap();
+    parcel.write(&None::<Vec<u8>>).unwrap();
+    parcel.write(&42i32).unwrap();
+
+    // SAFETY: 0 is less than the current size of the parcel data buffer, because the parcel is not
+    // empty.
+    unsafe {
+        parcel.set_data_position(0).unwrap();
+    }
+    assert_eq!(parcel.read_byte_slice(), Ok(&[1u8, 2, 3, 4, 5][..]));
+    assert_eq!(parcel.read_byte_slice(), Ok(&[][..]));
+    assert_eq!(parcel.read_byte_slice(), Err(StatusCode::UNEXPECTED_NULL));
+    // The padding after the array is skipped.
+    assert_eq!(parcel.read::<i32>(), Ok(42));
+    assert_eq!(parcel.read_byte_slice(), Err(StatusCode::NOT_ENOUGH_DATA));
+}
+
+#[test]
+fn test_read_nullable_byte_slice() {
+    let mut parcel = Parcel::new();
+    parcel.write(&Some(&[1u8, 2, 3][..])).unwrap();
+    parcel.write(&None::<&[u8]>).unwrap();
+    parcel.write(&[255u8][..]).unwrap();
+
+    // SAFETY: 0 is less than the current size of the parcel data buffer, because the parcel is not
+    // empty.
+    unsafe {
+        parcel.set_data_position(0).unwrap();
+    }
+    assert_eq!(parcel.read_nullable_byte_slice(), Ok(Some(&[1u8, 2, 3][..])));
+    assert_eq!(parcel.read_nullable_byte_slice(), Ok(None));
+    // Bytes are read back unsigned, as they were written.
+    assert_eq!(parcel.read::<Vec<u8>>(), Ok(vec![255]));
+}
+
+#[test]
+fn test_marshall() {
+    let mut parcel = Parcel::new();
+    assert_eq!(parcel.marshall(), Ok(vec![]));
+    parcel.write(&42i32).unwrap();
+    parcel.write("hello").unwrap();
+
+    let data = parcel.marshall().unwrap();
+    assert_eq!(data.len(), parcel.get_data_size() as usize);
+    let copy = Parcel::unmarshall(&data).unwrap();
+    assert_eq!(copy.marshall().unwrap(), data);
+    assert_eq!(copy.read::<i32>(), Ok(42));
+    assert_eq!(copy.read::<String>().unwrap(), "hello");
+}
+
+#[test]
+fn test_try_set_data_position() {
+    let mut parcel = Parcel::new();
+    parcel.write(&1i32).unwrap();
+    parcel.write(&2i32).unwrap();
+    assert_eq!(parcel.get_data_avail(), 0);
+
+    assert_eq!(parcel.try_set_data_position(4), Ok(()));
+    assert_eq!(parcel.get_data_avail(), 4);
+    assert_eq!(parcel.read::<i32>(), Ok(2));
+    assert_eq!(parcel.try_set_data_position(7), Ok(()));
+    assert_eq!(parcel.try_set_data_position(8), Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.try_set_data_position(9), Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.try_set_data_position(-1), Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.get_data_position(), 7);
+
+    assert_eq!(Parcel::new().try_set_data_position(0), Err(StatusCode::BAD_VALUE));
+}
+
+#[test]
+fn test_write_iter() {
+    let mut expected = Parcel::new();
+    expected.write(&[1i32, 2, 3][..]).unwrap();
+    expected.write(&[4u8, 5, 6][..]).unwrap();
+    expected.write(&["a".to_string(), "bc".to_string()][..]).unwrap();
+    expected.write(&[7i64, 8][..]).unwrap();
+
+    let mut parcel = Parcel::new();
+    parcel.write_iter(1..=3i32).unwrap();
+    parcel.write_iter(4..=6u8).unwrap();
+    parcel.write_iter(["a", "bc"].iter().map(|s| s.to_string())).unwrap();
+    parcel.write_iter_exact(2, [7i64, 8]).unwrap();
+    assert_eq!(parcel.marshall(), expected.marshall());
+
+    assert_eq!(parcel.write_iter_exact(3, [1i32, 2]), Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.write_iter_exact(1, [1i32, 2]), Err(StatusCode::BAD_VALUE));
+}
+
+#[test]
+fn test_read_vec_bounded() {
+    let mut parcel = Parcel::new();
+    parcel.write(&[1i32, 2, 3][..]).unwrap();
+    parcel.write(&None::<Vec<String>>).unwrap();
+
+    // SAFETY: 0 is less than the current size of the parcel data buffer, because the parcel is not
+    // empty.
+    unsafe {
+        parcel.set_data_position(0).unwrap();
+    }
+    assert_eq!(parcel.read_vec_bounded::<i32>(2), Err(StatusCode::BAD_VALUE));
+    assert_eq!(parcel.get_data_position(), 0);
+    assert_eq!(parcel.read_vec_bounded::<i32>(3), Ok(vec![1, 2, 3]));
+    assert_eq!(parcel.read_nullable_vec_bounded::<String>(0), Ok(None));
+}
+
+#[test]
+fn test_thread_buffer_pool() {
+    Parcel::set_thread_buffer_pool_enabled(true);
+    for i in 0..4i32 {
+        let mut parcel = Parcel::new();
+        parcel.write(&i).unwrap();
+        parcel.write("pooled").unwrap();
+
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        assert_eq!(parcel.read::<i32>(), Ok(i));
+        assert_eq!(parcel.read::<String>(), Ok("pooled".to_string()));
+    }
+
+    // Returns where the data of a parcel written with `bytes` starts.
+    fn write_and_locate(bytes: &[u8]) -> (Parcel, *const u8) {
+        let mut parcel = Parcel::new();
+        parcel.write(bytes).unwrap();
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        let data = parcel.read_byte_slice().unwrap().as_ptr();
+        (parcel, data)
+    }
+
+    let bytes = [0x5au8; 64];
+    let (parcel, first) = write_and_locate(&bytes);
+    drop(parcel);
+    // Without the pool, this would be likely to take the buffer which was just freed.
+    let other = vec![0u8; 128].into_boxed_slice();
+    let (_parcel, second) = write_and_locate(&bytes);
+    assert_eq!(first, second, "The buffer of the dropped parcel should have been reused");
+    drop(other);
+
+    Parcel::set_thread_buffer_pool_enabled(false);
+}
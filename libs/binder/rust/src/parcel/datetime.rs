@@ -0,0 +1,357 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parcel support for `std::time` and the `chrono` and `time` date/time types.
+//!
+//! Points in time are written as a single `int64` holding the number of
+//! nanoseconds since the Unix epoch (1970-01-01T00:00:00Z), i.e. as a `long`
+//! in AIDL, and a `Duration` as an `int64` number of nanoseconds. This covers
+//! the years 1677 to 2262, or durations of up to 292 years; writing a value
+//! outside that range fails with `BAD_VALUE`. Time zone offsets are not
+//! preserved: values are always read back in UTC.
+//!
+//! Many Java interfaces use milliseconds instead, e.g. for values from
+//! `System.currentTimeMillis()`. Wrap a `Duration` or `SystemTime` in
+//! [`Millis`] to write and read it in milliseconds, which can be any `int64`
+//! value other than a negative duration. A point in time before the epoch is
+//! rounded down to a whole millisecond, as `Instant.toEpochMilli()` does in
+//! Java.
+
+use super::{BorrowedParcel, Deserialize, DeserializeArray, Serialize, SerializeArray};
+use crate::error::{Result, StatusCode};
+
+use std::time::{Duration, SystemTime};
+
+const NANOS_PER_MILLI: i128 = 1_000_000;
+
+/// Wrapper which writes and reads a `Duration` or `SystemTime` as an `int64`
+/// count of milliseconds, rather than nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Millis<T>(pub T);
+
+fn duration_from_nanos(nanos: i128) -> Result<Duration> {
+    let nanos = u64::try_from(nanos).or(Err(StatusCode::BAD_VALUE))?;
+    Ok(Duration::from_nanos(nanos))
+}
+
+fn nanos_since_epoch(time: &SystemTime) -> i128 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(after) => after.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+fn system_time_from_nanos(nanos: i128) -> Result<SystemTime> {
+    let offset = duration_from_nanos(nanos.abs())?;
+    if nanos < 0 {
+        SystemTime::UNIX_EPOCH.checked_sub(offset)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_add(offset)
+    }
+    .ok_or(StatusCode::BAD_VALUE)
+}
+
+fn write_i64(value: i128, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+    parcel.write(&i64::try_from(value).or(Err(StatusCode::BAD_VALUE))?)
+}
+
+impl Serialize for Duration {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        write_i64(self.as_nanos() as i128, parcel)
+    }
+}
+
+impl Deserialize for Duration {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Duration::ZERO
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        duration_from_nanos(parcel.read::<i64>()?.into())
+    }
+}
+
+impl SerializeArray for Duration {}
+impl DeserializeArray for Duration {}
+
+impl Serialize for SystemTime {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        write_i64(nanos_since_epoch(self), parcel)
+    }
+}
+
+impl Deserialize for SystemTime {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        SystemTime::UNIX_EPOCH
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        system_time_from_nanos(parcel.read::<i64>()?.into())
+    }
+}
+
+impl SerializeArray for SystemTime {}
+impl DeserializeArray for SystemTime {}
+
+impl Serialize for Millis<Duration> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        write_i64(self.0.as_millis() as i128, parcel)
+    }
+}
+
+impl Deserialize for Millis<Duration> {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Millis(Duration::ZERO)
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        let millis: i64 = parcel.read()?;
+        let millis = u64::try_from(millis).or(Err(StatusCode::BAD_VALUE))?;
+        Ok(Millis(Duration::from_millis(millis)))
+    }
+}
+
+impl SerializeArray for Millis<Duration> {}
+impl DeserializeArray for Millis<Duration> {}
+
+impl Serialize for Millis<SystemTime> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        write_i64(nanos_since_epoch(&self.0).div_euclid(NANOS_PER_MILLI), parcel)
+    }
+}
+
+impl Deserialize for Millis<SystemTime> {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Millis(SystemTime::UNIX_EPOCH)
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        let millis: i64 = parcel.read()?;
+        // Going through nanoseconds would overflow for far away times which
+        // `SystemTime` can still hold.
+        let offset = Duration::from_millis(millis.unsigned_abs());
+        if millis < 0 {
+            SystemTime::UNIX_EPOCH.checked_sub(offset)
+        } else {
+            SystemTime::UNIX_EPOCH.checked_add(offset)
+        }
+        .map(Millis)
+        .ok_or(StatusCode::BAD_VALUE)
+    }
+}
+
+impl SerializeArray for Millis<SystemTime> {}
+impl DeserializeArray for Millis<SystemTime> {}
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+    impl Serialize for DateTime<Utc> {
+        fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+            let nanos = self.timestamp_nanos_opt().ok_or(StatusCode::BAD_VALUE)?;
+            parcel.write(&nanos)
+        }
+    }
+
+    impl Deserialize for DateTime<Utc> {
+        type UninitType = Self;
+        fn uninit() -> Self::UninitType {
+            DateTime::UNIX_EPOCH
+        }
+        fn from_init(value: Self) -> Self::UninitType {
+            value
+        }
+
+        fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+            let nanos: i64 = parcel.read()?;
+            DateTime::from_timestamp(
+                nanos.div_euclid(NANOS_PER_SEC),
+                nanos.rem_euclid(NANOS_PER_SEC) as u32,
+            )
+            .ok_or(StatusCode::BAD_VALUE)
+        }
+    }
+
+    impl SerializeArray for DateTime<Utc> {}
+    impl DeserializeArray for DateTime<Utc> {}
+}
+
+#[cfg(feature = "time")]
+mod time_impls {
+    use super::*;
+    use time::OffsetDateTime;
+
+    impl Serialize for OffsetDateTime {
+        fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+            let nanos =
+                i64::try_from(self.unix_timestamp_nanos()).or(Err(StatusCode::BAD_VALUE))?;
+            parcel.write(&nanos)
+        }
+    }
+
+    impl Deserialize for OffsetDateTime {
+        type UninitType = Self;
+        fn uninit() -> Self::UninitType {
+            OffsetDateTime::UNIX_EPOCH
+        }
+        fn from_init(value: Self) -> Self::UninitType {
+            value
+        }
+
+        fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+            let nanos: i64 = parcel.read()?;
+            OffsetDateTime::from_unix_timestamp_nanos(nanos.into()).or(Err(StatusCode::BAD_VALUE))
+        }
+    }
+
+    impl SerializeArray for OffsetDateTime {}
+    impl DeserializeArray for OffsetDateTime {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_std_time_round_trip() {
+        use super::Millis;
+        use std::time::{Duration, SystemTime};
+
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::new(1, 500);
+        let now = SystemTime::now();
+        let timeout = Duration::new(3, 4_000_001);
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&before_epoch).is_ok());
+        assert!(parcel.write(&now).is_ok());
+        assert!(parcel.write(&timeout).is_ok());
+        assert!(parcel.write(&Millis(before_epoch)).is_ok());
+        assert!(parcel.write(&Millis(timeout)).is_ok());
+        assert!(parcel.write(&-1i64).is_ok());
+        assert_eq!(parcel.write(&Duration::MAX), Err(crate::StatusCode::BAD_VALUE));
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<i64>().unwrap(), -1_000_000_500);
+        assert_eq!(parcel.read::<SystemTime>().unwrap(), now);
+        assert_eq!(parcel.read::<Duration>().unwrap(), timeout);
+        assert_eq!(parcel.read::<i64>().unwrap(), -1001);
+        assert_eq!(parcel.read::<Millis<Duration>>().unwrap(), Millis(Duration::from_millis(3004)));
+        assert!(parcel.read::<Duration>().is_err());
+    }
+
+    #[test]
+    fn test_millis_extremes() {
+        use super::Millis;
+        use std::time::{Duration, SystemTime};
+
+        let mut parcel = Parcel::new();
+        for millis in [i64::MAX, i64::MIN, i64::MAX, i64::MIN] {
+            assert!(parcel.write(&millis).is_ok());
+        }
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        let max = parcel.read::<Millis<Duration>>().unwrap();
+        assert_eq!(max, Millis(Duration::from_millis(i64::MAX as u64)));
+        assert_eq!(parcel.read::<Millis<Duration>>(), Err(crate::StatusCode::BAD_VALUE));
+        let latest = parcel.read::<Millis<SystemTime>>().unwrap();
+        let earliest = parcel.read::<Millis<SystemTime>>().unwrap();
+        assert_eq!(
+            latest.0.duration_since(SystemTime::UNIX_EPOCH),
+            Ok(Duration::from_millis(i64::MAX as u64))
+        );
+        assert_eq!(
+            SystemTime::UNIX_EPOCH.duration_since(earliest.0),
+            Ok(Duration::from_millis(i64::MIN.unsigned_abs()))
+        );
+
+        // They are written back unchanged.
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&max).is_ok());
+        assert!(parcel.write(&latest).is_ok());
+        assert!(parcel.write(&earliest).is_ok());
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<i64>(), Ok(i64::MAX));
+        assert_eq!(parcel.read::<i64>(), Ok(i64::MAX));
+        assert_eq!(parcel.read::<i64>(), Ok(i64::MIN));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_round_trip() {
+        use chrono::{DateTime, Utc};
+
+        let before_epoch = DateTime::from_timestamp(-1, 500).unwrap();
+        let now = Utc::now();
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&before_epoch).is_ok());
+        assert!(parcel.write(&now).is_ok());
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<i64>().unwrap(), -999_999_500);
+        assert_eq!(parcel.read::<DateTime<Utc>>().unwrap(), now);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_round_trip() {
+        use time::{OffsetDateTime, UtcOffset};
+
+        let now = OffsetDateTime::now_utc();
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&now.to_offset(UtcOffset::from_hms(2, 0, 0).unwrap())).is_ok());
+        assert!(parcel.write(&OffsetDateTime::UNIX_EPOCH).is_ok());
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        let read: OffsetDateTime = parcel.read().unwrap();
+        assert_eq!(read, now);
+        assert_eq!(read.offset(), UtcOffset::UTC);
+        assert_eq!(parcel.read::<i64>().unwrap(), 0);
+    }
+}
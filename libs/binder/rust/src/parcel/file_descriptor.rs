@@ -22,7 +22,8 @@ use crate::binder::AsNative;
 use crate::error::{status_result, Result, StatusCode};
 use crate::sys;
 
-use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 
 /// Rust version of the Java class android.os.ParcelFileDescriptor
 #[derive(Debug)]
@@ -33,6 +34,53 @@ impl ParcelFileDescriptor {
     pub fn new<F: Into<OwnedFd>>(fd: F) -> Self {
         Self(fd.into())
     }
+
+    /// Create a pipe, returning its read end and then its write end.
+    #[cfg(not(trusty))]
+    pub fn pipe() -> io::Result<(Self, Self)> {
+        let mut fds = [-1; 2];
+        // Safety: `fds` is valid to write two file descriptors to.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `pipe2` succeeded, so both file descriptors are newly opened
+        // and owned by nothing else.
+        Ok(unsafe { (Self::from_raw_fd(fds[0]), Self::from_raw_fd(fds[1])) })
+    }
+
+    /// Create a connected pair of Unix stream sockets, e.g. to send one end
+    /// to another process for two-way communication.
+    #[cfg(not(trusty))]
+    pub fn socketpair() -> io::Result<(Self, Self)> {
+        let mut fds = [-1; 2];
+        // Safety: `fds` is valid to write two file descriptors to.
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `socketpair` succeeded, so both file descriptors are newly
+        // opened and owned by nothing else.
+        Ok(unsafe { (Self::from_raw_fd(fds[0]), Self::from_raw_fd(fds[1])) })
+    }
+
+    /// Create a new `ParcelFileDescriptor` for the same file, by duplicating
+    /// the file descriptor.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        self.0.try_clone().map(Self)
+    }
+}
+
+impl From<OwnedFd> for ParcelFileDescriptor {
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
 }
 
 impl AsRef<OwnedFd> for ParcelFileDescriptor {
@@ -47,12 +95,26 @@ impl From<ParcelFileDescriptor> for OwnedFd {
     }
 }
 
+impl AsFd for ParcelFileDescriptor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
 impl AsRawFd for ParcelFileDescriptor {
     fn as_raw_fd(&self) -> RawFd {
         self.0.as_raw_fd()
     }
 }
 
+impl FromRawFd for ParcelFileDescriptor {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        // Safety: The caller guarantees that `fd` is open and owned by nothing
+        // else.
+        Self(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
 impl IntoRawFd for ParcelFileDescriptor {
     fn into_raw_fd(self) -> RawFd {
         self.0.into_raw_fd()
@@ -140,3 +202,49 @@ impl Deserialize for ParcelFileDescriptor {
 }
 
 impl DeserializeArray for ParcelFileDescriptor {}
+
+// Trusty has neither pipes nor Unix domain sockets.
+#[cfg(all(test, not(trusty)))]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_pipe_through_parcel() {
+        let (read, write) = ParcelFileDescriptor::pipe().unwrap();
+        let write_copy = write.try_clone().unwrap();
+        assert_ne!(write_copy, write);
+        drop(write);
+
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&write_copy).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        let received: ParcelFileDescriptor = parcel.read().unwrap();
+        // The parcel holds its own copy of the write end, which must be closed
+        // for the read end to see EOF.
+        drop(parcel);
+        drop(write_copy);
+
+        File::from(OwnedFd::from(received)).write_all(b"hello").unwrap();
+        let mut buf = String::new();
+        File::from(OwnedFd::from(read)).read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+    }
+
+    #[test]
+    fn test_socketpair() {
+        let (a, b) = ParcelFileDescriptor::socketpair().unwrap();
+        let mut a = UnixStream::from(OwnedFd::from(a));
+        let mut b = UnixStream::from(OwnedFd::from(b));
+        a.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -0,0 +1,264 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Limits on how deeply nested and how big the values read from parcels may
+//! be.
+//!
+//! A crafted parcel can nest parcelables deeply enough to overflow the stack
+//! of the thread reading it, or declare arrays big enough to exhaust memory
+//! before any of their elements are read. With limits set, reading such a
+//! parcel fails with [`StatusCode::BAD_VALUE`] instead, as it does for other
+//! malformed parcels:
+//!
+//! ```ignore
+//! binder::set_deserialization_limits(DeserializationLimits {
+//!     max_depth: Some(64),
+//!     max_elements: Some(1 << 20),
+//!     ..Default::default()
+//! });
+//! ```
+//!
+//! Depth counts the structured parcelables and array elements a value is
+//! nested in, and the element count is the total length of all the arrays and
+//! strings in one value read from a parcel, e.g. one argument of a
+//! transaction, with strings counted in bytes.
+
+use crate::error::{status_result, Result, StatusCode};
+use crate::sys;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Limits on the values read from parcels by any thread of the process, set
+/// with [`set_deserialization_limits`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeserializationLimits {
+    /// How deeply parcelables and arrays may be nested, or `None` for no
+    /// limit.
+    pub max_depth: Option<u32>,
+    /// How many array elements and string bytes one value may have in total,
+    /// including those of nested values, or `None` for no limit.
+    pub max_elements: Option<usize>,
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+// `MAX` means there is no limit.
+static MAX_DEPTH: AtomicU32 = AtomicU32::new(u32::MAX);
+static MAX_ELEMENTS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+thread_local! {
+    // How deeply the value being read is nested.
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+    // How many array elements the outermost value being read has so far.
+    static ELEMENTS: Cell<usize> = const { Cell::new(0) };
+    // Whether an array allocation was refused, so that the NDK's error can
+    // be reported as exceeding the limit.
+    static REFUSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the limits on the values read from parcels from now on. Both are off
+/// by default.
+pub fn set_deserialization_limits(limits: DeserializationLimits) {
+    MAX_DEPTH.store(limits.max_depth.unwrap_or(u32::MAX), Ordering::Relaxed);
+    MAX_ELEMENTS.store(limits.max_elements.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// The limits set with [`set_deserialization_limits`].
+pub fn deserialization_limits() -> DeserializationLimits {
+    let max_depth = MAX_DEPTH.load(Ordering::Relaxed);
+    let max_elements = MAX_ELEMENTS.load(Ordering::Relaxed);
+    DeserializationLimits {
+        max_depth: (max_depth != u32::MAX).then_some(max_depth),
+        max_elements: (max_elements != usize::MAX).then_some(max_elements),
+        _non_exhaustive: (),
+    }
+}
+
+/// Marks a nested value as being read, until it is dropped.
+pub(crate) struct NestingGuard(());
+
+impl NestingGuard {
+    /// Enter a nested value, failing if that is nested too deeply.
+    pub(crate) fn enter() -> Result<Self> {
+        Self::enter_within(MAX_DEPTH.load(Ordering::Relaxed))
+    }
+
+    fn enter_within(max_depth: u32) -> Result<Self> {
+        let depth = DEPTH.get();
+        if depth >= max_depth {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if depth == 0 {
+            // This starts a new outermost value.
+            ELEMENTS.set(0);
+        }
+        DEPTH.set(depth + 1);
+        Ok(Self(()))
+    }
+}
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        DEPTH.set(DEPTH.get() - 1);
+    }
+}
+
+/// Account for an array of `len` elements, failing if that takes the value
+/// being read over the limit.
+pub(crate) fn check_elements(len: usize) -> Result<()> {
+    check_elements_within(len, MAX_ELEMENTS.load(Ordering::Relaxed))
+}
+
+fn check_elements_within(len: usize, max: usize) -> Result<()> {
+    if max == usize::MAX {
+        return Ok(());
+    }
+    // An array which isn't nested in anything is a value of its own.
+    let before = if DEPTH.get() == 0 { 0 } else { ELEMENTS.get() };
+    let total = before.saturating_add(len);
+    ELEMENTS.set(total);
+    if total > max {
+        return Err(StatusCode::BAD_VALUE);
+    }
+    Ok(())
+}
+
+/// Like [`check_elements`], for NDK array allocators, which can only refuse
+/// to allocate. The refusal is remembered for [`array_result`].
+pub(crate) fn allow_allocation(len: usize) -> bool {
+    refuse_unless(check_elements(len).is_ok())
+}
+
+fn refuse_unless(allowed: bool) -> bool {
+    if !allowed {
+        REFUSED.set(true);
+    }
+    allowed
+}
+
+/// The result of an NDK array read, which fails with `BAD_VALUE`
+/// rather than the NDK's error if the allocation was refused because of the
+/// limits.
+pub(crate) fn array_result(status: sys::binder_status_t) -> Result<()> {
+    let result = status_result(status);
+    if REFUSED.replace(false) {
+        return Err(StatusCode::BAD_VALUE);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::{BorrowedParcel, Parcel, Parcelable};
+
+    use std::sync::Mutex;
+
+    // The limits are process-wide, so these use them explicitly rather than
+    // setting them under the feet of other tests, except for those which take
+    // this lock.
+    static LIMITS: Mutex<()> = Mutex::new(());
+
+    /// A parcelable which can be nested as deeply as a crafted parcel likes.
+    #[derive(Debug, Default)]
+    struct Nested {
+        children: Vec<Nested>,
+    }
+
+    impl Nested {
+        fn with_depth(depth: u32) -> Self {
+            let children = if depth > 1 { vec![Self::with_depth(depth - 1)] } else { vec![] };
+            Self { children }
+        }
+    }
+
+    impl Parcelable for Nested {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+            parcel.sized_write(|subparcel| subparcel.write(&self.children))
+        }
+
+        fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<()> {
+            parcel.sized_read(|subparcel| {
+                self.children = subparcel.read()?;
+                Ok(())
+            })
+        }
+    }
+
+    crate::impl_serialize_for_parcelable!(Nested);
+    crate::impl_deserialize_for_parcelable!(Nested);
+
+    #[test]
+    fn nesting_is_limited() {
+        let outer = NestingGuard::enter_within(2).unwrap();
+        let inner = NestingGuard::enter_within(2).unwrap();
+        assert_eq!(NestingGuard::enter_within(2).err(), Some(StatusCode::BAD_VALUE));
+        drop(inner);
+        assert!(NestingGuard::enter_within(2).is_ok());
+        drop(outer);
+        assert_eq!(DEPTH.get(), 0);
+    }
+
+    #[test]
+    fn nested_elements_add_up() {
+        {
+            let _value = NestingGuard::enter_within(u32::MAX).unwrap();
+            assert_eq!(check_elements_within(6, 10), Ok(()));
+            assert_eq!(check_elements_within(6, 10), Err(StatusCode::BAD_VALUE));
+        }
+        // The next value starts from zero.
+        assert_eq!(check_elements_within(10, 10), Ok(()));
+    }
+
+    #[test]
+    fn refused_allocations_exceed_the_limit() {
+        assert!(!refuse_unless(false));
+        assert_eq!(array_result(StatusCode::NO_MEMORY as i32), Err(StatusCode::BAD_VALUE));
+        assert_eq!(array_result(StatusCode::NO_MEMORY as i32), Err(StatusCode::NO_MEMORY));
+        assert_eq!(array_result(StatusCode::OK as i32), Ok(()));
+    }
+
+    #[test]
+    fn deeply_nested_parcels_are_rejected() {
+        let _lock = LIMITS.lock().unwrap();
+        let mut parcel = Parcel::new();
+        parcel.write(&Nested::with_depth(100)).unwrap();
+        let read = |parcel: &Parcel| {
+            // SAFETY: 0 is always a valid position.
+            unsafe { parcel.set_data_position(0).unwrap() };
+            parcel.read::<Nested>().map(|_| ())
+        };
+
+        set_deserialization_limits(DeserializationLimits {
+            max_depth: Some(64),
+            ..Default::default()
+        });
+        let limited = read(&parcel);
+        set_deserialization_limits(DeserializationLimits::default());
+        assert_eq!(limited, Err(StatusCode::BAD_VALUE));
+        assert_eq!(DEPTH.get(), 0);
+        assert_eq!(read(&parcel), Ok(()));
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let _lock = LIMITS.lock().unwrap();
+        assert_eq!(deserialization_limits(), DeserializationLimits::default());
+        assert!(NestingGuard::enter().is_ok());
+        assert_eq!(check_elements(usize::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parcel support for `HashMap` and `BTreeMap`.
+//!
+//! Maps are written as Java AIDL writes a `Map<K, V>`: an `int32` count of
+//! entries, or -1 for null, followed by each key and then its value, e.g. a
+//! `String` and a parcelable for `Map<String, Foo>`. As in Java, a key which
+//! appears twice replaces the earlier entry when read.
+//!
+//! This is not the format of the untyped `Parcel.writeMap` in Java, which tags
+//! every key and value with its type.
+
+use super::limits::{self, NestingGuard};
+use super::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Serialize, SerializeArray,
+    SerializeOption,
+};
+use crate::error::{Result, StatusCode};
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+fn serialize_entries<'a, K, V>(
+    len: usize,
+    entries: impl Iterator<Item = (&'a K, &'a V)>,
+    parcel: &mut BorrowedParcel<'_>,
+) -> Result<()>
+where
+    K: Serialize + 'a,
+    V: Serialize + 'a,
+{
+    let len: i32 = len.try_into().or(Err(StatusCode::BAD_VALUE))?;
+    parcel.write(&len)?;
+    for (key, value) in entries {
+        parcel.write(key)?;
+        parcel.write(value)?;
+    }
+    Ok(())
+}
+
+/// Reads the entries of a map into `insert`, or returns false if the map is
+/// null.
+fn deserialize_entries<K: Deserialize, V: Deserialize>(
+    parcel: &BorrowedParcel<'_>,
+    mut insert: impl FnMut(K, V),
+) -> Result<bool> {
+    let len: i32 = parcel.read()?;
+    if len < 0 {
+        return Ok(false);
+    }
+    limits::check_elements(len as usize)?;
+    for _ in 0..len {
+        let _nesting = NestingGuard::enter()?;
+        let key = parcel.read()?;
+        let value = parcel.read()?;
+        insert(key, value);
+    }
+    Ok(true)
+}
+
+impl<K: Serialize, V: Serialize, S> Serialize for HashMap<K, V, S> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        serialize_entries(self.len(), self.iter(), parcel)
+    }
+}
+
+impl<K: Serialize, V: Serialize, S> SerializeOption for HashMap<K, V, S> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        match this {
+            Some(map) => map.serialize(parcel),
+            None => parcel.write(&-1i32),
+        }
+    }
+}
+
+impl<K: Serialize, V: Serialize, S> SerializeArray for HashMap<K, V, S> {}
+
+impl<K, V, S> Deserialize for HashMap<K, V, S>
+where
+    K: Deserialize + Eq + Hash,
+    V: Deserialize,
+    S: BuildHasher + Default,
+{
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Self::default()
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        Self::deserialize_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+}
+
+impl<K, V, S> DeserializeOption for HashMap<K, V, S>
+where
+    K: Deserialize + Eq + Hash,
+    V: Deserialize,
+    S: BuildHasher + Default,
+{
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        let mut map = Self::default();
+        let present = deserialize_entries(parcel, |key, value| {
+            map.insert(key, value);
+        })?;
+        Ok(present.then_some(map))
+    }
+}
+
+impl<K, V, S> DeserializeArray for HashMap<K, V, S>
+where
+    K: Deserialize + Eq + Hash,
+    V: Deserialize,
+    S: BuildHasher + Default,
+{
+}
+
+impl<K: Serialize, V: Serialize> Serialize for BTreeMap<K, V> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        serialize_entries(self.len(), self.iter(), parcel)
+    }
+}
+
+impl<K: Serialize, V: Serialize> SerializeOption for BTreeMap<K, V> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        match this {
+            Some(map) => map.serialize(parcel),
+            None => parcel.write(&-1i32),
+        }
+    }
+}
+
+impl<K: Serialize, V: Serialize> SerializeArray for BTreeMap<K, V> {}
+
+impl<K: Deserialize + Ord, V: Deserialize> Deserialize for BTreeMap<K, V> {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Self::new()
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        Self::deserialize_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+}
+
+impl<K: Deserialize + Ord, V: Deserialize> DeserializeOption for BTreeMap<K, V> {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        let mut map = Self::new();
+        let present = deserialize_entries(parcel, |key, value| {
+            map.insert(key, value);
+        })?;
+        Ok(present.then_some(map))
+    }
+}
+
+impl<K: Deserialize + Ord, V: Deserialize> DeserializeArray for BTreeMap<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_map_round_trip() {
+        let hash_map = HashMap::from([("a".to_string(), 1i32), ("b".to_string(), 2)]);
+        let btree_map = BTreeMap::from([(1i64, vec!["x".to_string()]), (2, vec![])]);
+
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&hash_map).is_ok());
+        assert!(parcel.write(&btree_map).is_ok());
+        assert!(parcel.write(&None::<HashMap<String, i32>>).is_ok());
+        assert!(parcel.write(&Some(BTreeMap::<String, String>::new())).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<HashMap<String, i32>>(), Ok(hash_map));
+        assert_eq!(parcel.read::<BTreeMap<i64, Vec<String>>>(), Ok(btree_map));
+        assert_eq!(parcel.read::<Option<HashMap<String, i32>>>(), Ok(None));
+        assert_eq!(parcel.read::<Option<BTreeMap<String, String>>>(), Ok(Some(BTreeMap::new())));
+    }
+
+    #[test]
+    fn test_map_format() {
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&BTreeMap::from([(1i32, true), (2, false)])).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        let words: Vec<i32> = (0..5).map(|_| parcel.read().unwrap()).collect();
+        assert_eq!(words, [2, 1, 1, 2, 0]);
+
+        // A repeated key replaces the earlier entry, as in Java.
+        let mut parcel = Parcel::new();
+        for word in [2i32, 7, 1, 7, 2] {
+            assert!(parcel.write(&word).is_ok());
+        }
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<HashMap<i32, i32>>(), Ok(HashMap::from([(7, 2)])));
+    }
+}
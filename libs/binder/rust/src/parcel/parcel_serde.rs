@@ -0,0 +1,776 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parcel support for types implementing `serde::Serialize` and
+//! `serde::Deserialize`, through the [`Serde`] wrapper.
+//!
+//! The encoding is meant for serde types on both ends of a transaction, and
+//! is not in general the wire format of an equivalent AIDL parcelable:
+//!
+//! * Integers, floats and `bool` are written as the parcel primitive of the
+//!   same size, and `char` as an `int32` code point.
+//! * Strings are written as UTF-16 strings, and byte buffers as byte arrays.
+//! * `Option` is written as a parcelable flag, then the value if there is one,
+//!   whatever the type of the value. AIDL writes nullable strings and arrays
+//!   as a length of -1 instead, so `Option<String>` and `Option<Vec<T>>`
+//!   don't match AIDL's `@nullable String` and `@nullable T[]`.
+//! * Sequences and maps are written as an `int32` count, then each element,
+//!   or each key and then its value.
+//! * Structs are written as their fields in order, inside a size header like
+//!   the body of an AIDL parcelable, but without the flag AIDL writes before
+//!   each parcelable to say it is present. Fields missing from the end of a
+//!   struct written by an older version are left to `#[serde(default)]`, and
+//!   unknown fields added by a newer version are skipped.
+//! * Tuples and newtypes are written as their fields, with no header.
+//! * Enums are written as the `int32` index of the variant, then its fields.
+//!
+//! The encoding isn't self-describing, so `#[serde(untagged)]`, `flatten` and
+//! other features which need `deserialize_any` fail with `BAD_VALUE`.
+
+use super::limits::{self, NestingGuard};
+use super::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Serialize, SerializeArray,
+    SerializeOption, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
+};
+use crate::error::{Result, StatusCode};
+
+use serde::de::{self, Deserializer as _, IntoDeserializer};
+use serde::ser;
+use std::fmt;
+
+/// Wrapper to write any `serde::Serialize` type into a parcel, and read any
+/// `serde::Deserialize` type back.
+///
+/// ```ignore
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Config { name: String, retries: i32 }
+///
+/// parcel.write(&Serde(&config))?;
+/// let Serde(config): Serde<Config> = parcel.read()?;
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Serde<T>(pub T);
+
+impl<T: ser::Serialize> Serialize for Serde<T> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let mut serializer = Serializer { parcel };
+        ser::Serialize::serialize(&self.0, &mut serializer).map_err(|Error(status)| status)
+    }
+}
+
+impl<T: ser::Serialize> SerializeOption for Serde<T> {}
+impl<T: ser::Serialize> SerializeArray for Serde<T> {}
+
+impl<T: de::DeserializeOwned> Deserialize for Serde<T> {
+    type UninitType = Option<Self>;
+    fn uninit() -> Self::UninitType {
+        None
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        Some(value)
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        let deserializer = Deserializer { parcel };
+        <T as de::Deserialize>::deserialize(deserializer).map(Serde).map_err(|Error(status)| status)
+    }
+}
+
+impl<T: de::DeserializeOwned> DeserializeOption for Serde<T> {}
+impl<T: de::DeserializeOwned> DeserializeArray for Serde<T> {}
+
+/// A serde error, which only carries the status to return from the parcel
+/// traits.
+#[derive(Debug)]
+struct Error(StatusCode);
+
+impl From<StatusCode> for Error {
+    fn from(status: StatusCode) -> Self {
+        Self(status)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        Self(StatusCode::BAD_VALUE)
+    }
+}
+
+impl de::Error for Error {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        Self(StatusCode::BAD_VALUE)
+    }
+}
+
+type SerdeResult<T> = std::result::Result<T, Error>;
+
+fn variant_index(index: u32) -> SerdeResult<i32> {
+    index.try_into().or(Err(Error(StatusCode::BAD_VALUE)))
+}
+
+struct Serializer<'p, 'a> {
+    parcel: &'p mut BorrowedParcel<'a>,
+}
+
+impl<'p, 'a> Serializer<'p, 'a> {
+    fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        Ok(self.parcel.write(value)?)
+    }
+
+    /// Writes a placeholder for a header which is patched by
+    /// [`Compound::end`].
+    fn compound<'s>(&'s mut self, header: Header) -> SerdeResult<Compound<'s, 'p, 'a>> {
+        let start = self.parcel.get_data_position();
+        if !matches!(header, Header::None) {
+            self.write(&0i32)?;
+        }
+        Ok(Compound { ser: self, header, start, count: 0 })
+    }
+}
+
+enum Header {
+    /// No header, for tuples.
+    None,
+    /// The number of elements, for sequences and maps.
+    Count,
+    /// The size in bytes, including the header, for structs.
+    Size,
+}
+
+struct Compound<'s, 'p, 'a> {
+    ser: &'s mut Serializer<'p, 'a>,
+    header: Header,
+    start: i32,
+    count: i32,
+}
+
+impl Compound<'_, '_, '_> {
+    fn element<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        ser::Serialize::serialize(value, &mut *self.ser)?;
+        self.count = self.count.checked_add(1).ok_or(Error(StatusCode::BAD_VALUE))?;
+        Ok(())
+    }
+
+    fn end(self) -> SerdeResult<()> {
+        let parcel = &mut *self.ser.parcel;
+        let end = parcel.get_data_position();
+        let header = match self.header {
+            Header::None => return Ok(()),
+            Header::Count => self.count,
+            Header::Size => end - self.start,
+        };
+        // Safety: start is less than the current size of the parcel data
+        // buffer, because we got it with `get_data_position`.
+        unsafe {
+            parcel.set_data_position(self.start)?;
+        }
+        parcel.write(&header)?;
+        // Safety: end is less than the current size of the parcel data
+        // buffer, because we got it with `get_data_position`.
+        unsafe { Ok(parcel.set_data_position(end)?) }
+    }
+}
+
+impl<'s, 'p, 'a> ser::Serializer for &'s mut Serializer<'p, 'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'s, 'p, 'a>;
+    type SerializeTuple = Compound<'s, 'p, 'a>;
+    type SerializeTupleStruct = Compound<'s, 'p, 'a>;
+    type SerializeTupleVariant = Compound<'s, 'p, 'a>;
+    type SerializeMap = Compound<'s, 'p, 'a>;
+    type SerializeStruct = Compound<'s, 'p, 'a>;
+    type SerializeStructVariant = Compound<'s, 'p, 'a>;
+
+    fn serialize_bool(self, v: bool) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_i8(self, v: i8) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_i16(self, v: i16) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_i32(self, v: i32) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_i64(self, v: i64) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_u8(self, v: u8) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_u16(self, v: u16) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_u32(self, v: u32) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_u64(self, v: u64) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_f32(self, v: f32) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_f64(self, v: f64) -> SerdeResult<()> {
+        self.write(&v)
+    }
+    fn serialize_char(self, v: char) -> SerdeResult<()> {
+        self.write(&(v as i32))
+    }
+    fn serialize_str(self, v: &str) -> SerdeResult<()> {
+        self.write(v)
+    }
+    fn serialize_bytes(self, v: &[u8]) -> SerdeResult<()> {
+        self.write(v)
+    }
+
+    fn serialize_none(self) -> SerdeResult<()> {
+        self.write(&NULL_PARCELABLE_FLAG)
+    }
+    fn serialize_some<T: ser::Serialize + ?Sized>(self, value: &T) -> SerdeResult<()> {
+        self.write(&NON_NULL_PARCELABLE_FLAG)?;
+        ser::Serialize::serialize(value, self)
+    }
+
+    fn serialize_unit(self) -> SerdeResult<()> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> SerdeResult<()> {
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+    ) -> SerdeResult<()> {
+        self.write(&variant_index(index)?)
+    }
+    fn serialize_newtype_struct<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        ser::Serialize::serialize(value, self)
+    }
+    fn serialize_newtype_variant<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.write(&variant_index(index)?)?;
+        ser::Serialize::serialize(value, self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> SerdeResult<Self::SerializeSeq> {
+        self.compound(Header::Count)
+    }
+    fn serialize_tuple(self, _len: usize) -> SerdeResult<Self::SerializeTuple> {
+        self.compound(Header::None)
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeTupleStruct> {
+        self.compound(Header::None)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeTupleVariant> {
+        self.write(&variant_index(index)?)?;
+        self.compound(Header::None)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> SerdeResult<Self::SerializeMap> {
+        self.compound(Header::Count)
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeStruct> {
+        self.compound(Header::Size)
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> SerdeResult<Self::SerializeStructVariant> {
+        self.write(&variant_index(index)?)?;
+        self.compound(Header::Size)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_key<T: ser::Serialize + ?Sized>(&mut self, key: &T) -> SerdeResult<()> {
+        // Only keys are counted, so the header is the number of entries.
+        self.element(key)
+    }
+    fn serialize_value<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        ser::Serialize::serialize(value, &mut *self.ser)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.element(value)
+    }
+    fn end(self) -> SerdeResult<()> {
+        Compound::end(self)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Deserializer<'p, 'a> {
+    parcel: &'p BorrowedParcel<'a>,
+}
+
+impl Deserializer<'_, '_> {
+    fn read<T: Deserialize>(self) -> SerdeResult<T> {
+        Ok(self.parcel.read()?)
+    }
+
+    fn read_count(self) -> SerdeResult<usize> {
+        let count: i32 = self.read()?;
+        let count = count.try_into().or(Err(Error(StatusCode::BAD_VALUE)))?;
+        limits::check_elements(count)?;
+        Ok(count)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, '_> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> SerdeResult<V::Value> {
+        // Parcel data doesn't say what type it is.
+        Err(Error(StatusCode::BAD_VALUE))
+    }
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_bool(self.read()?)
+    }
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_i8(self.read()?)
+    }
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_i16(self.read()?)
+    }
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_i32(self.read()?)
+    }
+    fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_i64(self.read()?)
+    }
+    fn deserialize_u8<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_u8(self.read()?)
+    }
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_u16(self.read()?)
+    }
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_u32(self.read()?)
+    }
+    fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_u64(self.read()?)
+    }
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_f32(self.read()?)
+    }
+    fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_f64(self.read()?)
+    }
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        let code_point: i32 = self.read()?;
+        let c = char::from_u32(code_point as u32).ok_or(Error(StatusCode::BAD_VALUE))?;
+        visitor.visit_char(c)
+    }
+    fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_string(visitor)
+    }
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_string(self.read()?)
+    }
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_byte_buf(self.read()?)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match self.read::<i32>()? {
+            NULL_PARCELABLE_FLAG => visitor.visit_none(),
+            NON_NULL_PARCELABLE_FLAG => visitor.visit_some(self),
+            _ => Err(Error(StatusCode::BAD_VALUE)),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        visitor.visit_unit()
+    }
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        let _nesting = NestingGuard::enter()?;
+        let remaining = self.read_count()?;
+        visitor.visit_seq(Elements { de: self, remaining, end: None })
+    }
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        let _nesting = NestingGuard::enter()?;
+        visitor.visit_seq(Elements { de: self, remaining: len, end: None })
+    }
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        let _nesting = NestingGuard::enter()?;
+        let remaining = self.read_count()?;
+        visitor.visit_map(Elements { de: self, remaining, end: None })
+    }
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        let _nesting = NestingGuard::enter()?;
+        let start = self.parcel.get_data_position();
+        let size: i32 = self.read()?;
+        if size < 4 {
+            return Err(Error(StatusCode::BAD_VALUE));
+        }
+        let end = start.checked_add(size).ok_or(Error(StatusCode::BAD_VALUE))?;
+        if end > self.parcel.get_data_size() {
+            return Err(Error(StatusCode::NOT_ENOUGH_DATA));
+        }
+        let fields = Elements { de: self, remaining: fields.len(), end: Some(end) };
+        let value = visitor.visit_seq(fields)?;
+        if self.parcel.get_data_position() > end {
+            return Err(Error(StatusCode::BAD_VALUE));
+        }
+        // Skip any fields added by a newer version of the struct.
+        //
+        // Safety: end must be less than the current size of the parcel, because
+        // we checked above against `get_data_size`.
+        unsafe {
+            self.parcel.set_data_position(end)?;
+        }
+        Ok(value)
+    }
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        visitor.visit_enum(self)
+    }
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Reads the elements of a sequence, map, tuple or struct.
+struct Elements<'p, 'a> {
+    de: Deserializer<'p, 'a>,
+    remaining: usize,
+    /// The end of a struct, after which its remaining fields are missing.
+    end: Option<i32>,
+}
+
+impl Elements<'_, '_> {
+    fn next(&mut self) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        if self.end.is_some_and(|end| self.de.parcel.get_data_position() >= end) {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, '_> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> SerdeResult<Option<T::Value>> {
+        if !self.next() {
+            return Ok(None);
+        }
+        seed.deserialize(self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, '_> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> SerdeResult<Option<K::Value>> {
+        if !self.next() {
+            return Ok(None);
+        }
+        seed.deserialize(self.de).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> SerdeResult<V::Value> {
+        seed.deserialize(self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for Deserializer<'_, '_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> SerdeResult<(V::Value, Self::Variant)> {
+        let index: i32 = self.read()?;
+        let index: u32 = index.try_into().or(Err(Error(StatusCode::BAD_VALUE)))?;
+        let variant = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(index))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'_, '_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> SerdeResult<()> {
+        Ok(())
+    }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> SerdeResult<T::Value> {
+        seed.deserialize(self)
+    }
+    fn tuple_variant<V: de::Visitor<'de>>(self, len: usize, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        self.deserialize_struct("", fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { width: i32, height: i32 },
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Config {
+        name: String,
+        retries: u32,
+        tags: Vec<String>,
+        limits: BTreeMap<String, i64>,
+        shape: Option<Shape>,
+        point: (i8, char),
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let config = Config {
+            name: "service".to_string(),
+            retries: 3,
+            tags: vec!["a".to_string(), "b".to_string()],
+            limits: BTreeMap::from([("calls".to_string(), 100)]),
+            shape: Some(Shape::Rect { width: 2, height: 3 }),
+            point: (-1, 'é'),
+        };
+
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&Serde(&config)).is_ok());
+        assert!(parcel.write(&Serde(Shape::Empty)).is_ok());
+        assert!(parcel.write(&Serde(Shape::Circle(1.5))).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<Serde<Config>>(), Ok(Serde(config)));
+        assert_eq!(parcel.read::<Serde<Shape>>(), Ok(Serde(Shape::Empty)));
+        assert_eq!(parcel.read::<Serde<Shape>>(), Ok(Serde(Shape::Circle(1.5))));
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Old {
+        a: i32,
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct New {
+        a: i32,
+        #[serde(default)]
+        b: String,
+    }
+
+    #[test]
+    fn test_serde_struct_versions() {
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&Serde(Old { a: 1 })).is_ok());
+        assert!(parcel.write(&Serde(New { a: 2, b: "new".to_string() })).is_ok());
+        assert!(parcel.write(&7i32).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<Serde<New>>(), Ok(Serde(New { a: 1, b: String::new() })));
+        assert_eq!(parcel.read::<Serde<Old>>(), Ok(Serde(Old { a: 2 })));
+        assert_eq!(parcel.read::<i32>(), Ok(7));
+    }
+}
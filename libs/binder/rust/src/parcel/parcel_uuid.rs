@@ -0,0 +1,86 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parcel support for `uuid::Uuid`.
+//!
+//! A UUID is written as two `int64` values, 16 bytes of parcel data in all:
+//! first its most significant 64 bits, i.e. its first eight bytes read as a
+//! big-endian number, then its least significant 64 bits. Nothing else is
+//! written, not even a null flag or a size header.
+//!
+//! This is the body which Java's `ParcelUuid.writeToParcel` writes, so a Java
+//! peer can read it with `ParcelUuid.CREATOR.createFromParcel` or two calls to
+//! `readLong`. It isn't the layout of a `ParcelUuid` in an AIDL interface,
+//! which is preceded by a non-null flag, nor of a structured parcelable with
+//! two `long` fields, which is preceded by its size.
+
+use super::{BorrowedParcel, Deserialize, DeserializeArray, Serialize, SerializeArray};
+use crate::error::Result;
+use uuid::Uuid;
+
+impl Serialize for Uuid {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let (most_significant, least_significant) = self.as_u64_pair();
+        parcel.write(&(most_significant as i64))?;
+        parcel.write(&(least_significant as i64))
+    }
+}
+
+impl Deserialize for Uuid {
+    type UninitType = Self;
+    fn uninit() -> Self::UninitType {
+        Uuid::nil()
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        value
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        let most_significant: i64 = parcel.read()?;
+        let least_significant: i64 = parcel.read()?;
+        Ok(Uuid::from_u64_pair(most_significant as u64, least_significant as u64))
+    }
+}
+
+impl SerializeArray for Uuid {}
+impl DeserializeArray for Uuid {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_uuid_layout() {
+        let uuid = Uuid::parse_str("00112233-4455-6677-8899-aabbccddeeff").unwrap();
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&uuid).is_ok());
+        assert_eq!(parcel.get_data_size(), 16);
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<i64>().unwrap(), 0x0011223344556677);
+        assert_eq!(parcel.read::<i64>().unwrap(), 0x8899aabbccddeeffu64 as i64);
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<Uuid>().unwrap(), uuid);
+    }
+}
@@ -16,10 +16,12 @@
 
 use crate::binder::{AsNative, FromIBinder, Interface, Stability, Strong};
 use crate::error::{status_result, status_t, Result, Status, StatusCode};
-use crate::parcel::BorrowedParcel;
+use crate::parcel::limits::{self, NestingGuard};
+use crate::parcel::{try_collect, BorrowedParcel, ParcelableHolder};
 use crate::proxy::SpIBinder;
 use crate::sys;
 
+use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 use std::ffi::c_void;
 use std::mem::{self, ManuallyDrop};
@@ -142,6 +144,54 @@ pub trait SerializeArray: Serialize + Sized {
         };
         status_result(res)
     }
+
+    /// Serialize an array of references to this type into the given parcel.
+    ///
+    /// This must produce the same data as `serialize_array` would for the
+    /// referenced values, so types that override `serialize_array` with a
+    /// different format (e.g. `writeByteArray`) must override this as well.
+    fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        // Safety: Safe FFI, slice will always be a safe pointer to pass.
+        let res = unsafe {
+            sys::AParcel_writeParcelableArray(
+                parcel.as_native_mut(),
+                slice.as_ptr() as *const c_void,
+                slice.len().try_into().or(Err(StatusCode::BAD_VALUE))?,
+                Some(serialize_element::<&Self>),
+            )
+        };
+        status_result(res)
+    }
+
+    /// Serialize the values of an iterator as an array of this type, without
+    /// collecting them into a slice first.
+    ///
+    /// The default implementation writes a placeholder length and each
+    /// element, and then goes back to fill in the length. As for
+    /// `serialize_ref_array`, types that override `serialize_array` with a
+    /// different format must override this as well.
+    fn serialize_iter<I: Iterator<Item = Self>>(
+        values: I,
+        parcel: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        let start = parcel.get_data_position();
+        parcel.write(&0i32)?;
+        let mut len = 0i32;
+        for value in values {
+            value.serialize(parcel)?;
+            len = len.checked_add(1).ok_or(StatusCode::BAD_VALUE)?;
+        }
+        let end = parcel.get_data_position();
+        // Safety: start is less than the current size of the parcel data
+        // buffer, because we got it with `get_data_position`.
+        unsafe {
+            parcel.set_data_position(start)?;
+        }
+        parcel.write(&len)?;
+        // Safety: end is less than the current size of the parcel data
+        // buffer, because we got it with `get_data_position`.
+        unsafe { parcel.set_data_position(end) }
+    }
 }
 
 /// Callback to serialize an element of a generic parcelable array.
@@ -188,7 +238,7 @@ pub trait DeserializeArray: Deserialize {
                 Some(deserialize_element::<Self>),
             )
         };
-        status_result(res)?;
+        limits::array_result(res)?;
         // Safety: We are assuming that the NDK correctly initialized every
         // element of the vector by now, so we know that all the
         // UninitTypes are now properly initialized. We can transmute from
@@ -227,7 +277,7 @@ unsafe extern "C" fn deserialize_element<T: Deserialize>(
         None => return StatusCode::UNEXPECTED_NULL as status_t,
         Some(p) => p,
     };
-    let element = match parcel.read() {
+    let element = match NestingGuard::enter().and_then(|_nesting| parcel.read()) {
         Ok(e) => e,
         Err(code) => return code as status_t,
     };
@@ -327,6 +377,9 @@ unsafe extern "C" fn allocate_vec<T: Deserialize>(data: *mut c_void, len: i32) -
         *vec = None;
         return true;
     }
+    if !limits::allow_allocation(len as usize) {
+        return false;
+    }
 
     // Assert at compile time that `T` and `T::UninitType` have the same size and alignment.
     let _ = T::ASSERT_UNINIT_SIZE_AND_ALIGNMENT;
@@ -416,6 +469,11 @@ macro_rules! impl_parcelable {
                 };
                 status_result(status)
             }
+
+            fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+                let values: Vec<$ty> = try_collect(slice.iter().map(|value| **value))?;
+                Self::serialize_array(&values, parcel)
+            }
         }
     };
 
@@ -434,7 +492,7 @@ macro_rules! impl_parcelable {
                         Some(allocate_vec_with_buffer),
                     )
                 };
-                status_result(status)?;
+                limits::array_result(status)?;
                 // Safety: We are assuming that the NDK correctly
                 // initialized every element of the vector by now, so we
                 // know that all the UninitTypes are now properly
@@ -461,7 +519,6 @@ parcelable_primitives! {
 
     impl Serialize for i8 = sys::AParcel_writeByte;
     impl Deserialize for i8 = sys::AParcel_readByte;
-    impl SerializeArray for i8 = sys::AParcel_writeByteArray;
     impl DeserializeArray for i8 = sys::AParcel_readByteArray;
 
     impl Serialize for u16 = sys::AParcel_writeChar;
@@ -544,6 +601,51 @@ impl SerializeArray for u8 {
         };
         status_result(status)
     }
+
+    fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let values: Vec<Self> = try_collect(slice.iter().map(|value| **value))?;
+        Self::serialize_array(&values, parcel)
+    }
+
+    fn serialize_iter<I: Iterator<Item = Self>>(
+        values: I,
+        parcel: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        // Byte arrays are packed, rather than written an element at a time.
+        Self::serialize_array(&try_collect(values)?, parcel)
+    }
+}
+
+impl SerializeArray for i8 {
+    fn serialize_array(slice: &[Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        // Safety: `Parcel` always contains a valid pointer to an
+        // `AParcel`. If the slice is > 0 length, `slice.as_ptr()` will be a
+        // valid pointer to an array of elements of type `$ty`. If the slice
+        // length is 0, `slice.as_ptr()` may be dangling, but this is safe
+        // since the pointer is not dereferenced if the length parameter is
+        // 0.
+        let status = unsafe {
+            sys::AParcel_writeByteArray(
+                parcel.as_native_mut(),
+                slice.as_ptr(),
+                slice.len().try_into().or(Err(StatusCode::BAD_VALUE))?,
+            )
+        };
+        status_result(status)
+    }
+
+    fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let values: Vec<Self> = try_collect(slice.iter().map(|value| **value))?;
+        Self::serialize_array(&values, parcel)
+    }
+
+    fn serialize_iter<I: Iterator<Item = Self>>(
+        values: I,
+        parcel: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        // Byte arrays are packed, rather than written an element at a time.
+        Self::serialize_array(&try_collect(values)?, parcel)
+    }
 }
 
 impl Serialize for i16 {
@@ -583,6 +685,11 @@ impl SerializeArray for i16 {
         };
         status_result(status)
     }
+
+    fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let values: Vec<Self> = try_collect(slice.iter().map(|value| **value))?;
+        Self::serialize_array(&values, parcel)
+    }
 }
 
 impl SerializeOption for str {
@@ -659,7 +766,7 @@ impl Deserialize for Option<String> {
             )
         };
 
-        status_result(status)?;
+        limits::array_result(status)?;
         vec.map(|mut s| {
             // The vector includes a null-terminator and we don't want the
             // string to be null-terminated for Rust.
@@ -753,6 +860,27 @@ impl<T: SerializeArray, const N: usize> SerializeOption for [T; N] {
 
 impl<T: SerializeArray, const N: usize> SerializeArray for [T; N] {}
 
+// Lets `&[&T]` be written in the same format as `&[T]`, without cloning the
+// elements.
+impl<T: SerializeArray> SerializeArray for &T {
+    fn serialize_array(slice: &[Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        T::serialize_ref_array(slice, parcel)
+    }
+
+    fn serialize_ref_array(slice: &[&Self], parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let refs: Vec<&T> = try_collect(slice.iter().map(|value| **value))?;
+        T::serialize_ref_array(&refs, parcel)
+    }
+
+    fn serialize_iter<I: Iterator<Item = Self>>(
+        values: I,
+        parcel: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        // `T` may not be written an element at a time, and references are cheap to collect.
+        T::serialize_ref_array(&try_collect(values)?, parcel)
+    }
+}
+
 impl<T: DeserializeArray, const N: usize> Deserialize for [T; N] {
     type UninitType = [T::UninitType; N];
     fn uninit() -> Self::UninitType {
@@ -763,20 +891,33 @@ impl<T: DeserializeArray, const N: usize> Deserialize for [T; N] {
     }
 
     fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
-        let vec = DeserializeArray::deserialize_array(parcel)
-            .transpose()
-            .unwrap_or(Err(StatusCode::UNEXPECTED_NULL))?;
-        vec.try_into().or(Err(StatusCode::BAD_VALUE))
+        if peek_array_len(parcel)? < 0 {
+            return Err(StatusCode::UNEXPECTED_NULL);
+        }
+        Self::deserialize_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
     }
 }
 
 impl<T: DeserializeArray, const N: usize> DeserializeOption for [T; N] {
     fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        // Reject arrays of the wrong size before reading, and allocating, any
+        // of their elements.
+        let len = peek_array_len(parcel)?;
+        if len >= 0 && len as usize != N {
+            return Err(StatusCode::BAD_VALUE);
+        }
         let vec = DeserializeArray::deserialize_array(parcel)?;
         vec.map(|v| v.try_into().or(Err(StatusCode::BAD_VALUE))).transpose()
     }
 }
 
+/// Reads the length of the array at the current position of `parcel`,
+/// without moving past it.
+pub(crate) fn peek_array_len(parcel: &BorrowedParcel<'_>) -> Result<i32> {
+    let _saved = parcel.save_position();
+    parcel.read()
+}
+
 impl<T: DeserializeArray, const N: usize> DeserializeArray for [T; N] {}
 
 impl Serialize for Stability {
@@ -799,6 +940,12 @@ impl Deserialize for Stability {
     }
 }
 
+/// Marks the detail of a [`Status`], which follows the status header.
+///
+/// Other languages stop reading after the header, so this is only needed to
+/// tell a detail apart from whatever a non-Rust peer might have left there.
+const STATUS_DETAIL_MAGIC: i32 = i32::from_be_bytes(*b"SDTL");
+
 impl Serialize for Status {
     fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
         // Safety: `Parcel` always contains a valid pointer to an `AParcel`
@@ -806,7 +953,17 @@ impl Serialize for Status {
         // both parameters are valid and safe. This call does not take
         // ownership of either of its parameters.
         unsafe {
-            status_result(sys::AParcel_writeStatusHeader(parcel.as_native_mut(), self.as_native()))
+            status_result(sys::AParcel_writeStatusHeader(
+                parcel.as_native_mut(),
+                self.as_native(),
+            ))?;
+        }
+        match self.detail_holder() {
+            Some(detail) if !self.is_ok() => {
+                parcel.write(&STATUS_DETAIL_MAGIC)?;
+                parcel.write(detail)
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -833,7 +990,27 @@ impl Deserialize for Status {
         // Safety: At this point, the return status of the read call was ok,
         // so we know that `status_ptr` is a valid, owned pointer to an
         // `AStatus`, from which we can safely construct a `Status` object.
-        Ok(unsafe { Status::from_ptr(status_ptr) })
+        let mut status = unsafe { Status::from_ptr(status_ptr) };
+        if !status.is_ok() && parcel.get_data_avail() >= 4 {
+            let start = parcel.get_data_position();
+            let detail = if parcel.read::<i32>() == Ok(STATUS_DETAIL_MAGIC) {
+                parcel.read::<ParcelableHolder>().ok()
+            } else {
+                None
+            };
+            match detail {
+                Some(detail) => status.set_detail_holder(detail),
+                // A detail which can't be read shouldn't hide the error, nor
+                // leave the parcel part way through it.
+                None => {
+                    // Safety: `start` came from `get_data_position`.
+                    unsafe {
+                        parcel.set_data_position(start)?;
+                    }
+                }
+            }
+        }
+        Ok(status)
     }
 }
 
@@ -921,6 +1098,66 @@ impl<T: DeserializeOption> Deserialize for Option<T> {
     }
 }
 
+// Nested options, e.g. `Option<Option<T>>`, have no equivalent in AIDL, so
+// they are only readable by Rust. The outer option is written as a presence
+// flag (`NON_NULL_PARCELABLE_FLAG` or `NULL_PARCELABLE_FLAG`), followed by the
+// inner option in its usual format if present. This keeps `None` and
+// `Some(None)` distinct at every level, so they round trip exactly:
+//
+// | Value           | Data                                |
+// |-----------------|-------------------------------------|
+// | `None`          | `0`                                 |
+// | `Some(None)`    | `1`, then inner null (`-1` or `0`)  |
+// | `Some(Some(x))` | `1`, then `x` as a non-null value   |
+//
+// `Option<Vec<Option<T>>>` needs no special handling: the vector is written as
+// an array (the outer null is a length of -1), and each element carries its
+// own null marker.
+impl<T: SerializeOption> SerializeOption for Option<T> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        if let Some(inner) = this {
+            parcel.write(&NON_NULL_PARCELABLE_FLAG)?;
+            let start = parcel.get_data_position();
+            parcel.write(inner)?;
+            // If the inner option wrote nothing for `None`, `Some(None)` would
+            // be a bare presence flag, and readers would take whatever follows
+            // it as the inner value.
+            debug_assert!(
+                parcel.get_data_position() > start,
+                "{} wrote no null marker, so nesting it in an Option is ambiguous",
+                std::any::type_name::<Self>()
+            );
+            Ok(())
+        } else {
+            parcel.write(&NULL_PARCELABLE_FLAG)
+        }
+    }
+}
+
+impl<T: DeserializeOption> DeserializeOption for Option<T> {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        deserialize_nested_option(parcel)
+    }
+}
+
+impl DeserializeOption for Option<String> {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        deserialize_nested_option(parcel)
+    }
+}
+
+fn deserialize_nested_option<T: Deserialize>(parcel: &BorrowedParcel<'_>) -> Result<Option<T>> {
+    // Unlike for parcelables, any flag other than the two we write is an
+    // error. It most likely means that the data was written as a single
+    // level of nullability, e.g. by a non-Rust client, and guessing would
+    // silently collapse `None` and `Some(None)`.
+    match parcel.read::<i32>()? {
+        NULL_PARCELABLE_FLAG => Ok(None),
+        NON_NULL_PARCELABLE_FLAG => parcel.read().map(Some),
+        _ => Err(StatusCode::BAD_VALUE),
+    }
+}
+
 /// Implement `Serialize` trait and friends for a parcelable
 ///
 /// This is an internal macro used by the AIDL compiler to implement
@@ -1182,6 +1419,47 @@ impl<T: DeserializeOption> DeserializeOption for Box<T> {
     }
 }
 
+// A borrowed `Cow` is written straight from the borrowed data, so e.g. a
+// `Cow<[u8]>` pointing into an mmap is written with `writeByteArray` without
+// first being copied into a `Vec`.
+impl<T: Serialize + ToOwned + ?Sized> Serialize for Cow<'_, T> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        Serialize::serialize(&**self, parcel)
+    }
+}
+
+impl<T: SerializeOption + ToOwned + ?Sized> SerializeOption for Cow<'_, T> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        SerializeOption::serialize_option(this.map(|inner| &**inner), parcel)
+    }
+}
+
+impl<T: ToOwned + ?Sized> Deserialize for Cow<'_, T>
+where
+    T::Owned: Deserialize,
+{
+    type UninitType = Option<Self>;
+    fn uninit() -> Self::UninitType {
+        Self::UninitType::default()
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        Some(value)
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        Deserialize::deserialize(parcel).map(Cow::Owned)
+    }
+}
+
+impl<T: ToOwned + ?Sized> DeserializeOption for Cow<'_, T>
+where
+    T::Owned: DeserializeOption,
+{
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        DeserializeOption::deserialize_option(parcel).map(|t| t.map(Cow::Owned))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1552,4 +1830,192 @@ mod tests {
 
         assert_eq!(vec, strs);
     }
+
+    #[test]
+    fn test_cow_bytes() {
+        let data = [1u8, 2, 3, 4, 5];
+        let mut parcel = Parcel::new();
+        let start = parcel.get_data_position();
+
+        assert!(parcel.write(&Cow::Borrowed(&data[..])).is_ok());
+        assert!(parcel.write(&Cow::<[u8]>::Owned(vec![6, 7])).is_ok());
+        assert!(parcel.write(&None::<Cow<[u8]>>).is_ok());
+
+        // SAFETY: start is less than the current size of the parcel data buffer, because we haven't
+        // made it any shorter since we got the position.
+        unsafe {
+            assert!(parcel.set_data_position(start).is_ok());
+        }
+
+        assert_eq!(parcel.read::<Vec<u8>>().unwrap(), data);
+        let owned: Cow<[u8]> = parcel.read().unwrap();
+        assert_eq!(&*owned, &[6, 7]);
+        assert_eq!(parcel.read::<Option<Cow<[u8]>>>().unwrap(), None);
+    }
+
+    #[test]
+    fn test_serialize_ref_array() {
+        fn words(parcel: &Parcel) -> Vec<i32> {
+            // SAFETY: 0 is always a valid position.
+            unsafe {
+                assert!(parcel.set_data_position(0).is_ok());
+            }
+            (0..parcel.get_data_size() / 4).map(|_| parcel.read().unwrap()).collect()
+        }
+
+        fn assert_same_data<T: SerializeArray>(values: &[T]) {
+            let refs: Vec<&T> = values.iter().collect();
+            let mut owned = Parcel::new();
+            let mut borrowed = Parcel::new();
+            assert!(owned.write(values).is_ok());
+            assert!(borrowed.write(&refs[..]).is_ok());
+            assert!(borrowed.write(&refs.iter().collect::<Vec<_>>()).is_ok());
+            assert!(owned.write(values).is_ok());
+            assert_eq!(words(&owned), words(&borrowed));
+        }
+
+        assert_same_data(&[1u8, 2, 3, 4, 5]);
+        assert_same_data(&[-1i8, 2, -3]);
+        assert_same_data(&[1i16, -2, 3]);
+        assert_same_data(&[1i32, -2, 3]);
+        assert_same_data(&[true, false]);
+        assert_same_data(&["foo".to_string(), "bar".to_string()]);
+        assert_same_data(&[Some("foo".to_string()), None]);
+        assert_same_data(&[[1u8, 2], [3, 4]]);
+    }
+
+    #[test]
+    fn test_binder_enum_arrays() {
+        crate::declare_binder_enum! {
+            ByteEnum : [i8; 2] {
+                A = 1,
+                B = -2,
+            }
+        }
+        crate::declare_binder_enum! {
+            IntEnum : [i32; 2] {
+                A = 1,
+                B = 2,
+            }
+        }
+
+        fn assert_written_as<E: SerializeArray, B: SerializeArray>(values: &[E], backing: &[B]) {
+            let data = |write: &dyn Fn(&mut Parcel)| {
+                let mut parcel = Parcel::new();
+                write(&mut parcel);
+                // SAFETY: 0 is always a valid position.
+                unsafe {
+                    assert!(parcel.set_data_position(0).is_ok());
+                }
+                (0..parcel.get_data_size() / 4).map(|_| parcel.read::<i32>().unwrap()).collect()
+            };
+            let refs: Vec<&E> = values.iter().collect();
+            let expected: Vec<i32> = data(&|parcel| assert!(parcel.write(backing).is_ok()));
+            assert_eq!(data(&|parcel| assert!(parcel.write(values).is_ok())), expected);
+            assert_eq!(data(&|parcel| assert!(parcel.write(&refs[..]).is_ok())), expected);
+        }
+
+        assert_written_as(&[ByteEnum::A, ByteEnum::B, ByteEnum::A], &[1i8, -2, 1]);
+        assert_written_as(&[IntEnum::B, IntEnum::A], &[2i32, 1]);
+    }
+
+    #[test]
+    fn test_fixed_size_arrays() {
+        fn round_trip<T>(value: T)
+        where
+            T: Serialize + Deserialize + PartialEq + std::fmt::Debug,
+        {
+            let mut parcel = Parcel::new();
+            assert!(parcel.write(&value).is_ok());
+            // SAFETY: 0 is always a valid position.
+            unsafe {
+                assert!(parcel.set_data_position(0).is_ok());
+            }
+            assert_eq!(parcel.read::<T>().unwrap(), value);
+            assert_eq!(parcel.get_data_position(), parcel.get_data_size());
+        }
+
+        round_trip([7u8; 32]);
+        round_trip([[1i32, 2], [3, 4], [5, 6]]);
+        round_trip([Some("a".to_string()), None]);
+        round_trip::<Option<[i64; 2]>>(None);
+        round_trip(Some([1i64, 2]));
+        round_trip([Some([1u8, 2]), None]);
+
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&[1i32, 2, 3][..]).is_ok());
+        assert!(parcel.write(&None::<Vec<i32>>).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<[i32; 2]>(), Err(StatusCode::BAD_VALUE));
+        assert_eq!(parcel.read::<Option<[i32; 4]>>(), Err(StatusCode::BAD_VALUE));
+        // The rejected array is left unread.
+        assert_eq!(parcel.read::<[i32; 3]>(), Ok([1, 2, 3]));
+        assert_eq!(parcel.read::<[i32; 3]>(), Err(StatusCode::UNEXPECTED_NULL));
+    }
+
+    #[test]
+    fn test_nested_option_round_trip() {
+        fn round_trip<T>(value: T)
+        where
+            T: Serialize + Deserialize + PartialEq + std::fmt::Debug,
+        {
+            let mut parcel = Parcel::new();
+            assert!(parcel.write(&value).is_ok());
+            // SAFETY: 0 is always a valid position.
+            unsafe {
+                assert!(parcel.set_data_position(0).is_ok());
+            }
+            assert_eq!(parcel.read::<T>().unwrap(), value);
+            assert_eq!(parcel.get_data_position(), parcel.get_data_size());
+        }
+
+        round_trip::<Option<Option<String>>>(None);
+        round_trip::<Option<Option<String>>>(Some(None));
+        round_trip::<Option<Option<String>>>(Some(Some("nested".to_string())));
+        round_trip::<Option<Option<Vec<i32>>>>(Some(None));
+        round_trip::<Option<Option<Vec<i32>>>>(Some(Some(vec![1, 2])));
+        round_trip::<Option<Option<Option<String>>>>(Some(Some(None)));
+        round_trip::<Option<Vec<Option<String>>>>(None);
+        round_trip::<Option<Vec<Option<String>>>>(Some(vec![None, Some("x".to_string())]));
+        round_trip::<Vec<Option<Option<String>>>>(vec![None, Some(None), Some(Some("y".into()))]);
+    }
+
+    #[test]
+    fn test_nested_option_rejects_single_level_data() {
+        let mut parcel = Parcel::new();
+        assert!(parcel.write(&Some("not nested")).is_ok());
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            assert!(parcel.set_data_position(0).is_ok());
+        }
+        assert_eq!(parcel.read::<Option<Option<String>>>(), Err(StatusCode::BAD_VALUE));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "wrote no null marker")]
+    fn test_nested_option_asserts_inner_null_marker() {
+        struct Unmarked;
+
+        impl Serialize for Unmarked {
+            fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+                parcel.write(&0i32)
+            }
+        }
+
+        impl SerializeOption for Unmarked {
+            fn serialize_option(
+                this: Option<&Self>,
+                parcel: &mut BorrowedParcel<'_>,
+            ) -> Result<()> {
+                this.map_or(Ok(()), |inner| inner.serialize(parcel))
+            }
+        }
+
+        let mut parcel = Parcel::new();
+        let _ = parcel.write(&Some(None::<Unmarked>));
+    }
 }
@@ -23,6 +23,7 @@ use crate::parcel::{
 
 use downcast_rs::{impl_downcast, DowncastSync};
 use std::any::Any;
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
 /// Metadata that `ParcelableHolder` needs for all parcelables.
@@ -52,6 +53,39 @@ enum ParcelableHolderData {
     Parcel(Parcel),
 }
 
+/// Why [`ParcelableHolder::get_parcelable_checked`] couldn't return a
+/// parcelable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParcelableHolderError {
+    /// The holder is empty.
+    Empty,
+    /// The holder holds a parcelable with another descriptor, e.g. an
+    /// extension this version of the code doesn't know about.
+    WrongDescriptor {
+        /// The descriptor of the type asked for.
+        expected: &'static str,
+        /// The descriptor of the parcelable in the holder.
+        found: String,
+    },
+    /// The parcelable has the expected descriptor, but couldn't be read, or
+    /// was set as another Rust type with the same descriptor.
+    Status(StatusCode),
+}
+
+impl fmt::Display for ParcelableHolderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("ParcelableHolder is empty"),
+            Self::WrongDescriptor { expected, found } => {
+                write!(f, "ParcelableHolder holds {found}, not {expected}")
+            }
+            Self::Status(status) => write!(f, "can't get parcelable: {status:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ParcelableHolderError {}
+
 /// A container that can hold any arbitrary `Parcelable`.
 ///
 /// This type is currently used for AIDL parcelable fields.
@@ -155,12 +189,72 @@ impl ParcelableHolder {
         }
     }
 
+    /// Retrieve the parcelable stored in this `ParcelableHolder` as a `T`,
+    /// like [`get_parcelable`](Self::get_parcelable), but saying why if there
+    /// isn't one.
+    ///
+    /// In particular, a holder sent by a peer with another version of an
+    /// interface may hold an extension of another type, whose descriptor is
+    /// returned in [`ParcelableHolderError::WrongDescriptor`].
+    pub fn get_parcelable_checked<T>(&self) -> Result<Arc<T>, ParcelableHolderError>
+    where
+        T: Any + Parcelable + ParcelableMetadata + Default + std::fmt::Debug + Send + Sync,
+    {
+        let expected = T::get_descriptor();
+        match self.descriptor().map_err(ParcelableHolderError::Status)? {
+            None => return Err(ParcelableHolderError::Empty),
+            Some(found) if found != expected => {
+                return Err(ParcelableHolderError::WrongDescriptor { expected, found })
+            }
+            Some(_) => {}
+        }
+        match self.get_parcelable::<T>() {
+            Ok(Some(parcelable)) => Ok(parcelable),
+            Ok(None) => Err(ParcelableHolderError::Empty),
+            Err(status) => Err(ParcelableHolderError::Status(status)),
+        }
+    }
+
+    /// The descriptor of the parcelable in this `ParcelableHolder`, or `None`
+    /// if it is empty.
+    ///
+    /// This doesn't need to know the type of the parcelable, so it can be
+    /// used to log or dispatch on extensions before reading them.
+    pub fn descriptor(&self) -> Result<Option<String>, StatusCode> {
+        let data = self.data.lock().unwrap();
+        match *data {
+            ParcelableHolderData::Empty => Ok(None),
+            ParcelableHolderData::Parcelable { ref name, .. } => Ok(Some(name.clone())),
+            ParcelableHolderData::Parcel(ref parcel) => {
+                // Safety: 0 should always be a valid position.
+                unsafe {
+                    parcel.set_data_position(0)?;
+                }
+                parcel.read().map(Some)
+            }
+        }
+    }
+
+    /// Whether this `ParcelableHolder` is empty.
+    pub fn is_empty(&self) -> bool {
+        matches!(*self.data.lock().unwrap(), ParcelableHolderData::Empty)
+    }
+
+    /// Move the contents of this `ParcelableHolder` into a new one with the
+    /// same stability, leaving this one empty.
+    pub fn take(&mut self) -> ParcelableHolder {
+        let data = std::mem::replace(self.data.get_mut().unwrap(), ParcelableHolderData::Empty);
+        ParcelableHolder { data: Mutex::new(data), stability: self.stability }
+    }
+
     /// Return the stability value of this object.
     pub fn get_stability(&self) -> Stability {
         self.stability
     }
 }
 
+/// Clones share a parcelable which was set or already read, and copy the
+/// parcel data of one which hasn't been read yet.
 impl Clone for ParcelableHolder {
     fn clone(&self) -> ParcelableHolder {
         ParcelableHolder {
@@ -256,7 +350,7 @@ impl Parcelable for ParcelableHolder {
         let data_start = parcel.get_data_position();
         let data_end = data_start.checked_add(data_size).ok_or(StatusCode::BAD_VALUE)?;
 
-        let mut new_parcel = Parcel::new();
+        let mut new_parcel = Parcel::try_new()?;
         new_parcel.append_from(parcel, data_start, data_size)?;
         *self.data.get_mut().unwrap() = ParcelableHolderData::Parcel(new_parcel);
 
@@ -271,3 +365,162 @@ impl Parcelable for ParcelableHolder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Extension(i32);
+
+    impl Parcelable for Extension {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            parcel.write(&self.0)
+        }
+
+        fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            self.0 = parcel.read()?;
+            Ok(())
+        }
+    }
+
+    impl ParcelableMetadata for Extension {
+        fn get_descriptor() -> &'static str {
+            "android.binder.test.Extension"
+        }
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    struct OtherExtension(String);
+
+    impl Parcelable for OtherExtension {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            parcel.write(&self.0)
+        }
+
+        fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            self.0 = parcel.read()?;
+            Ok(())
+        }
+    }
+
+    impl ParcelableMetadata for OtherExtension {
+        fn get_descriptor() -> &'static str {
+            "android.binder.test.OtherExtension"
+        }
+    }
+
+    /// Another Rust type claiming the descriptor of `Extension`.
+    #[derive(Debug, Default, PartialEq)]
+    struct Impostor(i32);
+
+    impl Parcelable for Impostor {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            parcel.write(&self.0)
+        }
+
+        fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            self.0 = parcel.read()?;
+            Ok(())
+        }
+    }
+
+    impl ParcelableMetadata for Impostor {
+        fn get_descriptor() -> &'static str {
+            Extension::get_descriptor()
+        }
+    }
+
+    fn holder_with<T>(parcelable: T) -> ParcelableHolder
+    where
+        T: Any + Parcelable + ParcelableMetadata + std::fmt::Debug + Send + Sync,
+    {
+        let mut holder = ParcelableHolder::new(Stability::Local);
+        holder.set_parcelable(Arc::new(parcelable)).unwrap();
+        holder
+    }
+
+    /// Sends `holder` through a parcel, so that it holds the raw data of its
+    /// parcelable rather than a Rust object.
+    fn round_trip(holder: &ParcelableHolder) -> ParcelableHolder {
+        let mut parcel = Parcel::new();
+        parcel.write(holder).unwrap();
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        parcel.read().unwrap()
+    }
+
+    #[test]
+    fn empty_holder() {
+        let holder = ParcelableHolder::new(Stability::Local);
+        assert!(holder.is_empty());
+        assert_eq!(holder.descriptor(), Ok(None));
+        assert_eq!(holder.get_parcelable_checked::<Extension>(), Err(ParcelableHolderError::Empty));
+
+        let holder = round_trip(&holder);
+        assert!(holder.is_empty());
+        assert_eq!(holder.get_parcelable_checked::<Extension>(), Err(ParcelableHolderError::Empty));
+    }
+
+    #[test]
+    fn get_parcelable_checked() {
+        let holder = holder_with(Extension(42));
+        assert!(!holder.is_empty());
+        assert_eq!(holder.descriptor(), Ok(Some(Extension::get_descriptor().to_string())));
+        assert_eq!(holder.get_parcelable_checked::<Extension>().as_deref(), Ok(&Extension(42)));
+
+        let holder = round_trip(&holder);
+        assert!(!holder.is_empty());
+        assert_eq!(holder.descriptor(), Ok(Some(Extension::get_descriptor().to_string())));
+        assert_eq!(holder.get_parcelable_checked::<Extension>().as_deref(), Ok(&Extension(42)));
+        // Reading the parcelable again, now that it has been parsed, gives the same value.
+        assert_eq!(holder.get_parcelable_checked::<Extension>().as_deref(), Ok(&Extension(42)));
+    }
+
+    #[test]
+    fn get_parcelable_checked_with_wrong_descriptor() {
+        let expected = Err(ParcelableHolderError::WrongDescriptor {
+            expected: OtherExtension::get_descriptor(),
+            found: Extension::get_descriptor().to_string(),
+        });
+        let holder = holder_with(Extension(42));
+        assert_eq!(holder.get_parcelable_checked::<OtherExtension>(), expected);
+
+        let holder = round_trip(&holder);
+        assert_eq!(holder.get_parcelable_checked::<OtherExtension>(), expected);
+        // The holder still has its parcelable for the right type.
+        assert_eq!(holder.get_parcelable_checked::<Extension>().as_deref(), Ok(&Extension(42)));
+    }
+
+    #[test]
+    fn get_parcelable_checked_with_another_type_of_the_same_descriptor() {
+        let holder = holder_with(Extension(42));
+        assert_eq!(
+            holder.get_parcelable_checked::<Impostor>(),
+            Err(ParcelableHolderError::Status(StatusCode::BAD_VALUE))
+        );
+    }
+
+    #[test]
+    fn take() {
+        let mut holder = holder_with(OtherExtension("foo".to_string()));
+        let taken = holder.take();
+        assert!(holder.is_empty());
+        assert_eq!(holder.descriptor(), Ok(None));
+        assert_eq!(taken.get_stability(), Stability::Local);
+        assert_eq!(
+            taken.get_parcelable_checked::<OtherExtension>().as_deref(),
+            Ok(&OtherExtension("foo".to_string()))
+        );
+
+        let mut holder = round_trip(&taken);
+        let taken = holder.take();
+        assert!(holder.is_empty());
+        assert_eq!(
+            taken.get_parcelable_checked::<OtherExtension>().as_deref(),
+            Ok(&OtherExtension("foo".to_string()))
+        );
+    }
+}
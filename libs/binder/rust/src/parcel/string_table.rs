@@ -0,0 +1,174 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interned strings for hand-written parcelables.
+//!
+//! Large lists of records often repeat the same strings, such as package
+//! names. A [`StringTableWriter`] writes each distinct string once and
+//! replaces later occurrences with its index, and a [`StringTableReader`]
+//! reverses this. Both sides must read and write the strings in the same
+//! order, so a table should cover a single parcel, or a single section of
+//! one.
+//!
+//! Each string is written as an `int32` index. The first occurrence of a
+//! string gets the next unused index, starting at 0, and is followed by the
+//! string itself. A null string is written as -1.
+
+use super::BorrowedParcel;
+use crate::error::{Result, StatusCode};
+
+use std::collections::HashMap;
+
+/// Writes strings to a parcel, replacing repeats with references to their
+/// first occurrence.
+#[derive(Debug, Default)]
+pub struct StringTableWriter {
+    indices: HashMap<String, i32>,
+}
+
+impl StringTableWriter {
+    /// Create a writer with an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write `value` to `parcel`.
+    pub fn write(&mut self, parcel: &mut BorrowedParcel<'_>, value: &str) -> Result<()> {
+        if let Some(index) = self.indices.get(value) {
+            return parcel.write(index);
+        }
+        let index = i32::try_from(self.indices.len()).or(Err(StatusCode::BAD_VALUE))?;
+        // Allocate the table entry first, so that running out of memory
+        // doesn't leave a string in the parcel that the table doesn't know.
+        let mut key = String::new();
+        key.try_reserve_exact(value.len()).or(Err(StatusCode::NO_MEMORY))?;
+        key.push_str(value);
+        self.indices.try_reserve(1).or(Err(StatusCode::NO_MEMORY))?;
+        parcel.write(&index)?;
+        parcel.write(value)?;
+        self.indices.insert(key, index);
+        Ok(())
+    }
+
+    /// Write `value`, which may be null, to `parcel`.
+    pub fn write_option(
+        &mut self,
+        parcel: &mut BorrowedParcel<'_>,
+        value: Option<&str>,
+    ) -> Result<()> {
+        match value {
+            Some(value) => self.write(parcel, value),
+            None => parcel.write(&-1i32),
+        }
+    }
+
+    /// The number of distinct strings written so far.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Whether no strings have been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+/// Reads strings written by a [`StringTableWriter`].
+#[derive(Debug, Default)]
+pub struct StringTableReader {
+    strings: Vec<String>,
+}
+
+impl StringTableReader {
+    /// Create a reader with an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a string from `parcel`, failing with `UNEXPECTED_NULL` if it is
+    /// null.
+    pub fn read(&mut self, parcel: &BorrowedParcel<'_>) -> Result<String> {
+        self.read_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+
+    /// Read a string, which may be null, from `parcel`.
+    pub fn read_option(&mut self, parcel: &BorrowedParcel<'_>) -> Result<Option<String>> {
+        let index: i32 = parcel.read()?;
+        if index == -1 {
+            return Ok(None);
+        }
+        let index = usize::try_from(index).or(Err(StatusCode::BAD_VALUE))?;
+        match index.cmp(&self.strings.len()) {
+            std::cmp::Ordering::Less => Ok(Some(self.strings[index].clone())),
+            std::cmp::Ordering::Equal => {
+                let value: String = parcel.read()?;
+                self.strings.push(value.clone());
+                Ok(Some(value))
+            }
+            // Indices are assigned in order, so the writer cannot have
+            // skipped one.
+            std::cmp::Ordering::Greater => Err(StatusCode::BAD_VALUE),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_string_table_round_trip() {
+        let names = ["com.android.foo", "com.android.bar", "com.android.foo", "com.android.foo"];
+        let mut parcel = Parcel::new();
+        let mut writer = StringTableWriter::new();
+        for name in names {
+            writer.write(&mut parcel.borrowed(), name).unwrap();
+        }
+        writer.write_option(&mut parcel.borrowed(), None).unwrap();
+        assert_eq!(writer.len(), 2);
+
+        let mut plain = Parcel::new();
+        for name in names {
+            plain.write(name).unwrap();
+        }
+        assert!(parcel.get_data_size() < plain.get_data_size());
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        let mut reader = StringTableReader::new();
+        for name in names {
+            assert_eq!(reader.read(parcel.borrowed_ref()).unwrap(), name);
+        }
+        assert_eq!(reader.read_option(parcel.borrowed_ref()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_string_table_rejects_skipped_index() {
+        let mut parcel = Parcel::new();
+        parcel.write(&1i32).unwrap();
+        parcel.write("foo").unwrap();
+
+        // SAFETY: 0 is less than the current size of the parcel data buffer.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        let mut reader = StringTableReader::new();
+        assert_eq!(reader.read(parcel.borrowed_ref()), Err(StatusCode::BAD_VALUE));
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Checking that callers hold Android permissions.
+//!
+//! Permissions are checked with the `permission` service, as in libbinder's
+//! `checkPermission`. Services usually enforce them with the
+//! `#[requires_permission("...")]` attribute from `binder_macros`, which
+//! calls [`enforce_calling_permission`] at the start of the method. Putting
+//! `#[permission_table]` on the `impl` block as well records which permission
+//! each method needs in a [`PermissionTable`], so that they can be audited in
+//! one place.
+
+use crate::binder::{IBinder, IBinderInternal, Remotable, TransactionCode, FIRST_CALL_TRANSACTION};
+use crate::error::{ExceptionCode, Result, Status, StatusCode};
+use crate::native::Binder;
+use crate::parcel::BorrowedParcel;
+use crate::proxy::{AssociateClass, SpIBinder};
+use crate::service::check_service;
+use crate::state::ThreadState;
+
+use libc::{pid_t, uid_t};
+use std::ffi::CStr;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+type PermissionChecker = Arc<dyn Fn(&str, pid_t, uid_t) -> bool + Send + Sync>;
+
+static CHECKER: RwLock<Option<PermissionChecker>> = RwLock::new(None);
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+
+const CHECK_PERMISSION_TRANSACTION: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// How long to wait after failing to find the `permission` service before
+/// looking it up again, so that checks fail fast while it is unavailable.
+const CONTROLLER_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The permission a service method requires, as recorded by
+/// `#[permission_table]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MethodPermission {
+    /// The name of the method.
+    pub method: &'static str,
+    /// The permission the caller must hold, such as
+    /// `"android.permission.DUMP"`.
+    pub permission: &'static str,
+}
+
+/// The permissions the methods of an interface `I` require, implemented by
+/// `#[permission_table]` for a service type from the
+/// `#[requires_permission("...")]` attributes on its methods.
+///
+/// ```ignore
+/// #[permission_table]
+/// impl IFoo for FooService {
+///     #[requires_permission("android.permission.DUMP")]
+///     fn dumpState(&self) -> binder::Result<String> { ... }
+/// }
+///
+/// let permissions = <FooService as PermissionTable<dyn IFoo>>::PERMISSIONS;
+/// ```
+pub trait PermissionTable<I: ?Sized> {
+    /// Each method of `I` which requires a permission, with the permission. A
+    /// method requiring several permissions appears once for each of them.
+    const PERMISSIONS: &'static [MethodPermission];
+}
+
+/// The `permission` service, or when looking it up last failed.
+enum Controller {
+    Found(SpIBinder),
+    Missing(Instant),
+}
+
+/// Gives the permission controller proxy its interface class, so that
+/// transactions to it start with its interface token.
+struct PermissionController;
+
+impl Remotable for PermissionController {
+    fn get_descriptor() -> &'static str {
+        "android.os.IPermissionController"
+    }
+
+    fn on_transact(
+        &self,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        Err(StatusCode::UNKNOWN_TRANSACTION)
+    }
+
+    fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// Check permissions with `checker` instead of the `permission` service, e.g.
+/// in tests or processes which can't reach it.
+pub fn set_permission_checker<F>(checker: F)
+where
+    F: Fn(&str, pid_t, uid_t) -> bool + Send + Sync + 'static,
+{
+    *CHECKER.write().unwrap() = Some(Arc::new(checker));
+}
+
+/// Check permissions with the `permission` service again, undoing
+/// [`set_permission_checker`].
+pub fn clear_permission_checker() {
+    *CHECKER.write().unwrap() = None;
+}
+
+/// Whether the process `pid` running as `uid` holds `permission`.
+///
+/// This is false if the permission can't be checked, e.g. because the
+/// `permission` service is unavailable.
+pub fn check_permission(permission: &str, pid: pid_t, uid: uid_t) -> bool {
+    let checker = CHECKER.read().unwrap().clone();
+    if let Some(checker) = checker {
+        return checker(permission, pid, uid);
+    }
+    let Some(controller) = controller() else {
+        return false;
+    };
+    let reply = controller.transact(CHECK_PERMISSION_TRANSACTION, 0, |mut data| {
+        data.write(permission)?;
+        data.write(&pid)?;
+        data.write(&(uid as i32))
+    });
+    match reply {
+        Ok(reply) => {
+            reply.read::<Status>().is_ok_and(|status| status.is_ok())
+                && reply.read::<i32>().is_ok_and(|granted| granted != 0)
+        }
+        Err(_) => {
+            // Look the service up again next time if it died.
+            if !controller.is_binder_alive() {
+                CONTROLLER.lock().unwrap().take();
+            }
+            false
+        }
+    }
+}
+
+/// Whether the caller of the current transaction holds `permission`.
+pub fn check_calling_permission(permission: &str) -> bool {
+    check_permission(permission, ThreadState::get_calling_pid(), ThreadState::get_calling_uid())
+}
+
+/// Fail with a `SECURITY` exception, naming `method`, unless the caller of
+/// the current transaction holds `permission`.
+pub fn enforce_calling_permission(
+    method: &str,
+    permission: &str,
+) -> std::result::Result<(), Status> {
+    let (pid, uid) = (ThreadState::get_calling_pid(), ThreadState::get_calling_uid());
+    if check_permission(permission, pid, uid) {
+        Ok(())
+    } else {
+        Err(Status::new_exception_str(
+            ExceptionCode::SECURITY,
+            Some(format!("{method} requires {permission}, which uid {uid} pid {pid} lacks")),
+        ))
+    }
+}
+
+fn controller() -> Option<SpIBinder> {
+    match &*CONTROLLER.lock().unwrap() {
+        Some(Controller::Found(binder)) => return Some(binder.clone()),
+        Some(Controller::Missing(since)) if since.elapsed() < CONTROLLER_RETRY_INTERVAL => {
+            return None
+        }
+        _ => {}
+    }
+    // Look the service up without holding the lock, so that other checks
+    // aren't held up by a slow servicemanager.
+    let binder = check_service("permission").and_then(|mut binder| {
+        binder.associate_class(<PermissionController as Remotable>::get_class()).then_some(binder)
+    });
+    *CONTROLLER.lock().unwrap() = Some(match &binder {
+        Some(binder) => Controller::Found(binder.clone()),
+        None => Controller::Missing(Instant::now()),
+    });
+    binder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checker_decides_permissions() {
+        set_permission_checker(|permission, _pid, _uid| permission == "android.permission.FOO");
+        assert!(check_calling_permission("android.permission.FOO"));
+        assert_eq!(enforce_calling_permission("foo", "android.permission.FOO"), Ok(()));
+
+        let status = enforce_calling_permission("bar", "android.permission.BAR").unwrap_err();
+        assert_eq!(status.exception_code(), ExceptionCode::SECURITY);
+        assert!(status.get_description().contains("bar requires android.permission.BAR"));
+        clear_permission_checker();
+    }
+}
@@ -0,0 +1,608 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A Rust wrapper for `APersistableBundle`, the NDK version of
+//! `android.os.PersistableBundle`.
+//!
+//! `APersistableBundle` was added to the NDK in Android 15, so its functions
+//! are looked up at runtime, and creating or reading a bundle fails with
+//! `INVALID_OPERATION` on older devices.
+
+use crate::binder::AsNative;
+use crate::error::{status_result, Result, StatusCode};
+use crate::parcel::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Serialize, SerializeArray,
+    SerializeOption, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
+};
+use crate::sys::{self, APersistableBundle};
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::ptr::{self, NonNull};
+
+ndk_api! {
+    in "libbinder_ndk.so";
+    fn APersistableBundle_new() -> *mut APersistableBundle;
+    fn APersistableBundle_dup(*const APersistableBundle) -> *mut APersistableBundle;
+    fn APersistableBundle_delete(*mut APersistableBundle);
+    fn APersistableBundle_isEqual(*const APersistableBundle, *const APersistableBundle) -> bool;
+    fn APersistableBundle_readFromParcel(*const sys::AParcel, *mut *mut APersistableBundle) -> sys::binder_status_t;
+    fn APersistableBundle_writeToParcel(*const APersistableBundle, *mut sys::AParcel) -> sys::binder_status_t;
+    fn APersistableBundle_size(*const APersistableBundle) -> i32;
+    fn APersistableBundle_erase(*mut APersistableBundle, *const c_char) -> i32;
+    fn APersistableBundle_putBoolean(*mut APersistableBundle, *const c_char, bool);
+    fn APersistableBundle_putInt(*mut APersistableBundle, *const c_char, i32);
+    fn APersistableBundle_putLong(*mut APersistableBundle, *const c_char, i64);
+    fn APersistableBundle_putDouble(*mut APersistableBundle, *const c_char, f64);
+    fn APersistableBundle_putString(*mut APersistableBundle, *const c_char, *const c_char);
+    fn APersistableBundle_putBooleanVector(*mut APersistableBundle, *const c_char, *const bool, i32);
+    fn APersistableBundle_putIntVector(*mut APersistableBundle, *const c_char, *const i32, i32);
+    fn APersistableBundle_putLongVector(*mut APersistableBundle, *const c_char, *const i64, i32);
+    fn APersistableBundle_putDoubleVector(*mut APersistableBundle, *const c_char, *const f64, i32);
+    fn APersistableBundle_putStringVector(*mut APersistableBundle, *const c_char, *const *const c_char, i32);
+    fn APersistableBundle_putPersistableBundle(*mut APersistableBundle, *const c_char, *const APersistableBundle);
+    fn APersistableBundle_getBoolean(*const APersistableBundle, *const c_char, *mut bool) -> bool;
+    fn APersistableBundle_getInt(*const APersistableBundle, *const c_char, *mut i32) -> bool;
+    fn APersistableBundle_getLong(*const APersistableBundle, *const c_char, *mut i64) -> bool;
+    fn APersistableBundle_getDouble(*const APersistableBundle, *const c_char, *mut f64) -> bool;
+    fn APersistableBundle_getString(
+        *const APersistableBundle,
+        *const c_char,
+        *mut *mut c_char,
+        sys::APersistableBundle_stringAllocator,
+        *mut c_void,
+    ) -> i32;
+    fn APersistableBundle_getBooleanVector(*const APersistableBundle, *const c_char, *mut bool, i32) -> i32;
+    fn APersistableBundle_getIntVector(*const APersistableBundle, *const c_char, *mut i32, i32) -> i32;
+    fn APersistableBundle_getLongVector(*const APersistableBundle, *const c_char, *mut i64, i32) -> i32;
+    fn APersistableBundle_getDoubleVector(*const APersistableBundle, *const c_char, *mut f64, i32) -> i32;
+    fn APersistableBundle_getStringVector(
+        *const APersistableBundle,
+        *const c_char,
+        *mut *mut c_char,
+        i32,
+        sys::APersistableBundle_stringAllocator,
+        *mut c_void,
+    ) -> i32;
+    fn APersistableBundle_getPersistableBundle(*const APersistableBundle, *const c_char, *mut *mut APersistableBundle) -> bool;
+}
+
+/// Unwraps an NDK function used on an existing bundle.
+///
+/// The `APersistableBundle` functions were all added in Android 15, and every
+/// `PersistableBundle` comes from `APersistableBundle_new`, `_dup` or
+/// `_readFromParcel`, so the rest are there whenever a bundle is.
+fn present<F>(function: Option<F>) -> F {
+    function.expect("libbinder_ndk has bundles but not all APersistableBundle functions")
+}
+
+/// A mapping from string keys to values of several types, which can be sent
+/// to and from Java code as an `android.os.PersistableBundle`.
+///
+/// Each key maps to a value of one type; putting a value of another type for
+/// the same key replaces it.
+pub struct PersistableBundle(NonNull<APersistableBundle>);
+
+/// Safety: The `PersistableBundle` owns its `APersistableBundle`, which isn't
+/// tied to the thread which created it.
+unsafe impl Send for PersistableBundle {}
+
+/// Safety: The NDK doesn't mutate an `APersistableBundle` through a const
+/// pointer, and mutation here needs `&mut self`.
+unsafe impl Sync for PersistableBundle {}
+
+impl PersistableBundle {
+    /// Create a new empty `PersistableBundle`.
+    ///
+    /// Panics before Android 15, which has no bundles in the NDK.
+    #[cfg(not(feature = "strict"))]
+    pub fn new() -> Self {
+        Self::try_new().expect("Failed to create an APersistableBundle")
+    }
+
+    /// Create a new empty `PersistableBundle`, or fail with `NO_MEMORY` if the
+    /// NDK can't allocate one, or with `INVALID_OPERATION` before Android 15,
+    /// which has no bundles in the NDK.
+    pub fn try_new() -> Result<Self> {
+        let new = APersistableBundle_new().ok_or(StatusCode::INVALID_OPERATION)?;
+        // Safety: `APersistableBundle_new` has no preconditions, and returns
+        // either a new bundle owned by the caller or null.
+        let ptr = unsafe { new() };
+        NonNull::new(ptr).map(Self).ok_or(StatusCode::NO_MEMORY)
+    }
+
+    /// Create a deep copy of this bundle, or fail with `NO_MEMORY` if the NDK
+    /// can't allocate one.
+    pub fn try_clone(&self) -> Result<Self> {
+        // Safety: `self.0` is a valid bundle, and `APersistableBundle_dup`
+        // returns either a new bundle owned by the caller or null.
+        let ptr = unsafe { present(APersistableBundle_dup())(self.0.as_ptr()) };
+        NonNull::new(ptr).map(Self).ok_or(StatusCode::NO_MEMORY)
+    }
+
+    /// The number of keys in the bundle, of all types.
+    pub fn size(&self) -> usize {
+        // Safety: `self.0` is a valid bundle.
+        let size = unsafe { present(APersistableBundle_size())(self.0.as_ptr()) };
+        size.try_into().expect("APersistableBundle_size returned a negative size")
+    }
+
+    /// Whether the bundle has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    /// Remove the value of `key`, of whichever type, and return whether there
+    /// was one.
+    ///
+    /// Fails with `BAD_VALUE` if `key` contains a NUL byte, as do all methods
+    /// taking keys.
+    pub fn remove(&mut self, key: &str) -> Result<bool> {
+        let key = to_cstring(key)?;
+        // Safety: `self.0` is a valid bundle, and `key` is a valid C string.
+        Ok(unsafe { present(APersistableBundle_erase())(self.0.as_ptr(), key.as_ptr()) } != 0)
+    }
+
+    /// Map `key` to a `bool`.
+    pub fn insert_bool(&mut self, key: &str, value: bool) -> Result<()> {
+        let key = to_cstring(key)?;
+        // Safety: `self.0` is a valid bundle, and `key` is a valid C string
+        // which the NDK copies.
+        unsafe { present(APersistableBundle_putBoolean())(self.0.as_ptr(), key.as_ptr(), value) };
+        Ok(())
+    }
+
+    /// Map `key` to an `i32`.
+    pub fn insert_int(&mut self, key: &str, value: i32) -> Result<()> {
+        let key = to_cstring(key)?;
+        // Safety: As for `insert_bool`.
+        unsafe { present(APersistableBundle_putInt())(self.0.as_ptr(), key.as_ptr(), value) };
+        Ok(())
+    }
+
+    /// Map `key` to an `i64`.
+    pub fn insert_long(&mut self, key: &str, value: i64) -> Result<()> {
+        let key = to_cstring(key)?;
+        // Safety: As for `insert_bool`.
+        unsafe { present(APersistableBundle_putLong())(self.0.as_ptr(), key.as_ptr(), value) };
+        Ok(())
+    }
+
+    /// Map `key` to an `f64`.
+    pub fn insert_double(&mut self, key: &str, value: f64) -> Result<()> {
+        let key = to_cstring(key)?;
+        // Safety: As for `insert_bool`.
+        unsafe { present(APersistableBundle_putDouble())(self.0.as_ptr(), key.as_ptr(), value) };
+        Ok(())
+    }
+
+    /// Map `key` to a string, which must not contain NUL bytes.
+    pub fn insert_string(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = to_cstring(key)?;
+        let value = to_cstring(value)?;
+        // Safety: `self.0` is a valid bundle, and `key` and `value` are valid
+        // C strings which the NDK copies.
+        unsafe {
+            present(APersistableBundle_putString())(self.0.as_ptr(), key.as_ptr(), value.as_ptr())
+        };
+        Ok(())
+    }
+
+    /// Map `key` to a vector of `bool`s.
+    pub fn insert_bool_vec(&mut self, key: &str, value: &[bool]) -> Result<()> {
+        self.insert_vec(key, value, present(APersistableBundle_putBooleanVector()))
+    }
+
+    /// Map `key` to a vector of `i32`s.
+    pub fn insert_int_vec(&mut self, key: &str, value: &[i32]) -> Result<()> {
+        self.insert_vec(key, value, present(APersistableBundle_putIntVector()))
+    }
+
+    /// Map `key` to a vector of `i64`s.
+    pub fn insert_long_vec(&mut self, key: &str, value: &[i64]) -> Result<()> {
+        self.insert_vec(key, value, present(APersistableBundle_putLongVector()))
+    }
+
+    /// Map `key` to a vector of `f64`s.
+    pub fn insert_double_vec(&mut self, key: &str, value: &[f64]) -> Result<()> {
+        self.insert_vec(key, value, present(APersistableBundle_putDoubleVector()))
+    }
+
+    /// Map `key` to a vector of strings, none of which may contain NUL bytes.
+    pub fn insert_string_vec<S: AsRef<str>>(&mut self, key: &str, value: &[S]) -> Result<()> {
+        let key = to_cstring(key)?;
+        let strings =
+            value.iter().map(|s| to_cstring(s.as_ref())).collect::<Result<Vec<CString>>>()?;
+        let pointers: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+        let len = pointers.len().try_into().or(Err(StatusCode::BAD_VALUE))?;
+        // Safety: `self.0` is a valid bundle, `key` is a valid C string, and
+        // `pointers` holds `len` valid C strings, all of which the NDK copies.
+        unsafe {
+            present(APersistableBundle_putStringVector())(
+                self.0.as_ptr(),
+                key.as_ptr(),
+                pointers.as_ptr(),
+                len,
+            )
+        };
+        Ok(())
+    }
+
+    /// Map `key` to a copy of another bundle.
+    pub fn insert_persistable_bundle(
+        &mut self,
+        key: &str,
+        value: &PersistableBundle,
+    ) -> Result<()> {
+        let key = to_cstring(key)?;
+        // Safety: `self.0` and `value.0` are valid bundles, and `key` is a
+        // valid C string. The NDK copies both `key` and `value`.
+        unsafe {
+            present(APersistableBundle_putPersistableBundle())(
+                self.0.as_ptr(),
+                key.as_ptr(),
+                value.0.as_ptr(),
+            )
+        };
+        Ok(())
+    }
+
+    /// The `bool` value of `key`, or `None` if it has no `bool` value.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get_value(key, present(APersistableBundle_getBoolean()))
+    }
+
+    /// The `i32` value of `key`, or `None` if it has no `i32` value.
+    pub fn get_int(&self, key: &str) -> Result<Option<i32>> {
+        self.get_value(key, present(APersistableBundle_getInt()))
+    }
+
+    /// The `i64` value of `key`, or `None` if it has no `i64` value.
+    pub fn get_long(&self, key: &str) -> Result<Option<i64>> {
+        self.get_value(key, present(APersistableBundle_getLong()))
+    }
+
+    /// The `f64` value of `key`, or `None` if it has no `f64` value.
+    pub fn get_double(&self, key: &str) -> Result<Option<f64>> {
+        self.get_value(key, present(APersistableBundle_getDouble()))
+    }
+
+    /// The string value of `key`, or `None` if it has no string value.
+    pub fn get_string(&self, key: &str) -> Result<Option<String>> {
+        let key = to_cstring(key)?;
+        let mut strings = Vec::new();
+        let mut value = ptr::null_mut();
+        // Safety: `self.0` is a valid bundle, `key` is a valid C string, and
+        // `string_allocator` is given a valid `Vec<Vec<u8>>` as its context.
+        let status = unsafe {
+            present(APersistableBundle_getString())(
+                self.0.as_ptr(),
+                key.as_ptr(),
+                &mut value,
+                Some(string_allocator),
+                &mut strings as *mut Vec<Vec<u8>> as *mut c_void,
+            )
+        };
+        match status {
+            sys::APERSISTABLEBUNDLE_KEY_NOT_FOUND => Ok(None),
+            status if status < 0 => Err(StatusCode::NO_MEMORY),
+            _ => strings.pop().map(from_c_buffer).transpose(),
+        }
+    }
+
+    /// The vector of `bool`s for `key`, or `None` if it has no such value.
+    pub fn get_bool_vec(&self, key: &str) -> Result<Option<Vec<bool>>> {
+        self.get_vec(key, present(APersistableBundle_getBooleanVector()))
+    }
+
+    /// The vector of `i32`s for `key`, or `None` if it has no such value.
+    pub fn get_int_vec(&self, key: &str) -> Result<Option<Vec<i32>>> {
+        self.get_vec(key, present(APersistableBundle_getIntVector()))
+    }
+
+    /// The vector of `i64`s for `key`, or `None` if it has no such value.
+    pub fn get_long_vec(&self, key: &str) -> Result<Option<Vec<i64>>> {
+        self.get_vec(key, present(APersistableBundle_getLongVector()))
+    }
+
+    /// The vector of `f64`s for `key`, or `None` if it has no such value.
+    pub fn get_double_vec(&self, key: &str) -> Result<Option<Vec<f64>>> {
+        self.get_vec(key, present(APersistableBundle_getDoubleVector()))
+    }
+
+    /// The vector of strings for `key`, or `None` if it has no such value.
+    pub fn get_string_vec(&self, key: &str) -> Result<Option<Vec<String>>> {
+        let key = to_cstring(key)?;
+        let get = |buffer: &mut [*mut c_char], strings: &mut Vec<Vec<u8>>| -> Result<i32> {
+            let size = (buffer.len() * std::mem::size_of::<*mut c_char>())
+                .try_into()
+                .or(Err(StatusCode::BAD_VALUE))?;
+            // Safety: `self.0` is a valid bundle, `key` is a valid C string,
+            // `buffer` has room for `size` bytes, and `string_allocator` is
+            // given a valid `Vec<Vec<u8>>` as its context.
+            Ok(unsafe {
+                present(APersistableBundle_getStringVector())(
+                    self.0.as_ptr(),
+                    key.as_ptr(),
+                    buffer.as_mut_ptr(),
+                    size,
+                    Some(string_allocator),
+                    strings as *mut Vec<Vec<u8>> as *mut c_void,
+                )
+            })
+        };
+
+        let size = get(&mut [], &mut Vec::new())?;
+        if size == sys::APERSISTABLEBUNDLE_KEY_NOT_FOUND {
+            return Ok(None);
+        }
+        let len = usize::try_from(size).or(Err(StatusCode::NO_MEMORY))?
+            / std::mem::size_of::<*mut c_char>();
+        let mut buffer = vec![ptr::null_mut(); len];
+        let mut strings = Vec::with_capacity(len);
+        if get(&mut buffer, &mut strings)? < 0 {
+            return Err(StatusCode::NO_MEMORY);
+        }
+        strings.into_iter().map(from_c_buffer).collect::<Result<_>>().map(Some)
+    }
+
+    /// A copy of the bundle for `key`, or `None` if it has no bundle value.
+    pub fn get_persistable_bundle(&self, key: &str) -> Result<Option<PersistableBundle>> {
+        let key = to_cstring(key)?;
+        let mut value = ptr::null_mut();
+        // Safety: `self.0` is a valid bundle, and `key` is a valid C string.
+        // If it returns true, the NDK gives us ownership of a new bundle in
+        // `value`.
+        let found = unsafe {
+            present(APersistableBundle_getPersistableBundle())(
+                self.0.as_ptr(),
+                key.as_ptr(),
+                &mut value,
+            )
+        };
+        if !found {
+            return Ok(None);
+        }
+        NonNull::new(value).map(|ptr| Some(Self(ptr))).ok_or(StatusCode::NO_MEMORY)
+    }
+
+    fn insert_vec<T>(
+        &mut self,
+        key: &str,
+        value: &[T],
+        put: unsafe extern "C" fn(*mut APersistableBundle, *const c_char, *const T, i32),
+    ) -> Result<()> {
+        let key = to_cstring(key)?;
+        let len = value.len().try_into().or(Err(StatusCode::BAD_VALUE))?;
+        // Safety: `self.0` is a valid bundle, `key` is a valid C string, and
+        // `value` holds `len` elements, which the NDK copies.
+        unsafe { put(self.0.as_ptr(), key.as_ptr(), value.as_ptr(), len) };
+        Ok(())
+    }
+
+    fn get_value<T: Default>(
+        &self,
+        key: &str,
+        get: unsafe extern "C" fn(*const APersistableBundle, *const c_char, *mut T) -> bool,
+    ) -> Result<Option<T>> {
+        let key = to_cstring(key)?;
+        let mut value = T::default();
+        // Safety: `self.0` is a valid bundle, `key` is a valid C string, and
+        // `value` is a valid place for the NDK to write a `T`.
+        let found = unsafe { get(self.0.as_ptr(), key.as_ptr(), &mut value) };
+        Ok(found.then_some(value))
+    }
+
+    fn get_vec<T: Default + Clone>(
+        &self,
+        key: &str,
+        get: unsafe extern "C" fn(*const APersistableBundle, *const c_char, *mut T, i32) -> i32,
+    ) -> Result<Option<Vec<T>>> {
+        let key = to_cstring(key)?;
+        // Safety: `self.0` is a valid bundle, and `key` is a valid C string.
+        // A null buffer of size 0 asks for the size of the vector.
+        let size = unsafe { get(self.0.as_ptr(), key.as_ptr(), ptr::null_mut(), 0) };
+        if size == sys::APERSISTABLEBUNDLE_KEY_NOT_FOUND {
+            return Ok(None);
+        }
+        let len = usize::try_from(size).or(Err(StatusCode::BAD_VALUE))? / std::mem::size_of::<T>();
+        let mut value = vec![T::default(); len];
+        // Safety: `self.0` is a valid bundle, `key` is a valid C string, and
+        // `value` has room for `size` bytes.
+        unsafe { get(self.0.as_ptr(), key.as_ptr(), value.as_mut_ptr(), size) };
+        Ok(Some(value))
+    }
+}
+
+fn to_cstring(s: &str) -> Result<CString> {
+    CString::new(s).or(Err(StatusCode::BAD_VALUE))
+}
+
+/// Converts a NUL-terminated buffer from `string_allocator` to a `String`.
+fn from_c_buffer(buffer: Vec<u8>) -> Result<String> {
+    let s = CStr::from_bytes_until_nul(&buffer).or(Err(StatusCode::BAD_VALUE))?;
+    s.to_str().map(str::to_owned).or(Err(StatusCode::BAD_VALUE))
+}
+
+/// Allocates a buffer for a string from the NDK, which is kept in the
+/// `Vec<Vec<u8>>` passed as the context.
+///
+/// # Safety
+///
+/// `context` must be a valid `*mut Vec<Vec<u8>>`, not otherwise accessed for
+/// the duration of the call.
+unsafe extern "C" fn string_allocator(size: i32, context: *mut c_void) -> *mut c_char {
+    let Ok(size) = usize::try_from(size) else {
+        return ptr::null_mut();
+    };
+    // Safety: Our caller guarantees that `context` is a valid `Vec<Vec<u8>>`.
+    let strings = unsafe { &mut *(context as *mut Vec<Vec<u8>>) };
+    // The heap buffer of the new `Vec<u8>` doesn't move when `strings` grows.
+    strings.push(vec![0; size]);
+    strings.last_mut().unwrap().as_mut_ptr().cast()
+}
+
+impl Drop for PersistableBundle {
+    fn drop(&mut self) {
+        // Safety: We own the bundle, and nothing uses it after this.
+        unsafe { present(APersistableBundle_delete())(self.0.as_ptr()) }
+    }
+}
+
+/// Clones are deep copies, as for `PersistableBundle(PersistableBundle)` in
+/// Java.
+impl Clone for PersistableBundle {
+    fn clone(&self) -> Self {
+        self.try_clone().expect("APersistableBundle_dup returned null pointer")
+    }
+}
+
+#[cfg(not(feature = "strict"))]
+impl Default for PersistableBundle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for PersistableBundle {
+    fn eq(&self, other: &Self) -> bool {
+        // Safety: Both bundles are valid.
+        unsafe { present(APersistableBundle_isEqual())(self.0.as_ptr(), other.0.as_ptr()) }
+    }
+}
+
+impl fmt::Debug for PersistableBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PersistableBundle").field("size", &self.size()).finish_non_exhaustive()
+    }
+}
+
+impl Serialize for PersistableBundle {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        Self::serialize_option(Some(self), parcel)
+    }
+}
+
+impl SerializeOption for PersistableBundle {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let Some(this) = this else {
+            return parcel.write(&NULL_PARCELABLE_FLAG);
+        };
+        parcel.write(&NON_NULL_PARCELABLE_FLAG)?;
+        // Safety: `this.0` is a valid bundle, and `parcel` is a valid parcel.
+        let status = unsafe {
+            present(APersistableBundle_writeToParcel())(this.0.as_ptr(), parcel.as_native_mut())
+        };
+        status_result(status)
+    }
+}
+
+impl SerializeArray for PersistableBundle {}
+
+impl Deserialize for PersistableBundle {
+    type UninitType = Option<Self>;
+    fn uninit() -> Self::UninitType {
+        None
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        Some(value)
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        Self::deserialize_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+}
+
+impl DeserializeOption for PersistableBundle {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        if parcel.read::<i32>()? == NULL_PARCELABLE_FLAG {
+            return Ok(None);
+        }
+        // Before Android 15 there can't be a bundle to read one into.
+        let read = APersistableBundle_readFromParcel().ok_or(StatusCode::INVALID_OPERATION)?;
+        let mut bundle = ptr::null_mut();
+        // Safety: `parcel` is a valid parcel. On success, the NDK gives us
+        // ownership of a new bundle in `bundle`.
+        let status = unsafe { read(parcel.as_native(), &mut bundle) };
+        status_result(status)?;
+        NonNull::new(bundle).map(|ptr| Some(Self(ptr))).ok_or(StatusCode::NO_MEMORY)
+    }
+}
+
+impl DeserializeArray for PersistableBundle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_typed_values() {
+        let mut bundle = PersistableBundle::new();
+        assert!(bundle.is_empty());
+        bundle.insert_bool("bool", true).unwrap();
+        bundle.insert_int("int", 42).unwrap();
+        bundle.insert_long("long", -7).unwrap();
+        bundle.insert_double("double", 1.5).unwrap();
+        bundle.insert_string("string", "héllo").unwrap();
+        bundle.insert_int_vec("ints", &[1, 2, 3]).unwrap();
+        bundle.insert_bool_vec("bools", &[true, false]).unwrap();
+        bundle.insert_string_vec("strings", &["a", "", "bc"]).unwrap();
+        assert_eq!(bundle.size(), 8);
+
+        assert_eq!(bundle.get_bool("bool"), Ok(Some(true)));
+        assert_eq!(bundle.get_int("int"), Ok(Some(42)));
+        assert_eq!(bundle.get_long("long"), Ok(Some(-7)));
+        assert_eq!(bundle.get_double("double"), Ok(Some(1.5)));
+        assert_eq!(bundle.get_string("string"), Ok(Some("héllo".to_string())));
+        assert_eq!(bundle.get_int_vec("ints"), Ok(Some(vec![1, 2, 3])));
+        assert_eq!(bundle.get_bool_vec("bools"), Ok(Some(vec![true, false])));
+        assert_eq!(
+            bundle.get_string_vec("strings"),
+            Ok(Some(vec!["a".to_string(), String::new(), "bc".to_string()]))
+        );
+
+        // Values of other types, or of missing keys, aren't there.
+        assert_eq!(bundle.get_long("int"), Ok(None));
+        assert_eq!(bundle.get_string("missing"), Ok(None));
+        assert_eq!(bundle.get_long_vec("missing"), Ok(None));
+        assert_eq!(bundle.get_string_vec("missing"), Ok(None));
+        assert_eq!(bundle.get_int("bad\0key"), Err(StatusCode::BAD_VALUE));
+
+        assert_eq!(bundle.remove("int"), Ok(true));
+        assert_eq!(bundle.remove("int"), Ok(false));
+        assert_eq!(bundle.get_int("int"), Ok(None));
+    }
+
+    #[test]
+    fn test_nested_and_parcel() {
+        let mut inner = PersistableBundle::new();
+        inner.insert_long_vec("longs", &[i64::MIN, i64::MAX]).unwrap();
+        let mut bundle = PersistableBundle::new();
+        bundle.insert_persistable_bundle("inner", &inner).unwrap();
+        assert_eq!(bundle.get_persistable_bundle("inner"), Ok(Some(inner)));
+
+        let mut parcel = Parcel::new();
+        parcel.write(&bundle).unwrap();
+        parcel.write(&None::<PersistableBundle>).unwrap();
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        assert_eq!(parcel.read::<PersistableBundle>(), Ok(bundle.clone()));
+        assert_eq!(parcel.read::<Option<PersistableBundle>>(), Ok(None));
+    }
+}
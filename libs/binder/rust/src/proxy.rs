@@ -20,27 +20,96 @@ use crate::binder::{
     AsNative, FromIBinder, IBinder, IBinderInternal, Interface, InterfaceClass, Strong,
     TransactionCode, TransactionFlags,
 };
-use crate::error::{status_result, Result, StatusCode};
+use crate::error::{checked_cstring, status_result, Result, StatusCode};
 use crate::parcel::{
     BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel, Serialize,
     SerializeArray, SerializeOption,
 };
 use crate::sys;
 
+#[cfg(not(trusty))]
+use std::any::{Any, TypeId};
 use std::cmp::Ordering;
+#[cfg(not(trusty))]
+use std::collections::BTreeMap;
 use std::convert::TryInto;
-use std::ffi::{c_void, CString};
+use std::ffi::c_void;
 use std::fmt;
 use std::mem;
 use std::os::fd::AsRawFd;
 use std::ptr;
 use std::sync::Arc;
+#[cfg(not(trusty))]
+use std::sync::Mutex;
 
 /// A strong reference to a Binder remote object.
 ///
 /// This struct encapsulates the generic C++ `sp<IBinder>` class. This wrapper
 /// is untyped; typed interface access is implemented by the AIDL compiler.
-pub struct SpIBinder(ptr::NonNull<sys::AIBinder>);
+pub struct SpIBinder {
+    ptr: ptr::NonNull<sys::AIBinder>,
+    // Client-side layers applied to transactions sent through this handle (and
+    // its clones). These are local to this handle, not to the binder object,
+    // so they can't be attached to it, but they are kept behind a thin pointer
+    // so that a handle is only one word bigger than the `AIBinder` pointer.
+    layers: Option<TransactionLayers>,
+}
+
+const _: () = assert!(mem::size_of::<SpIBinder>() == 2 * mem::size_of::<usize>());
+
+ndk_api! {
+    in "libbinder_ndk.so";
+    #[cfg(not(android_vendor))]
+    fn AIBinder_isSystemStable(*mut sys::AIBinder) -> bool;
+    #[cfg(android_vendor)]
+    fn AIBinder_isVendorStable(*mut sys::AIBinder) -> bool;
+    #[cfg(not(trusty))]
+    fn AIBinder_getDebugPid(*mut sys::AIBinder, *mut libc::pid_t) -> sys::binder_status_t;
+    #[cfg(not(trusty))]
+    fn AIBinder_attachObject(
+        *mut sys::AIBinder,
+        *const c_void,
+        *mut c_void,
+        *mut c_void,
+        sys::AIBinder_Object_cleanup,
+    ) -> *mut c_void;
+    #[cfg(not(trusty))]
+    fn AIBinder_findObject(*mut sys::AIBinder, *const c_void) -> *mut c_void;
+}
+
+/// The stack of [`TransactionLayer`]s attached to a binder handle, outermost
+/// first.
+pub(crate) type TransactionLayers = Arc<Vec<Arc<dyn TransactionLayer>>>;
+
+// Unique addresses to attach objects of each type with, as a generic function
+// can't have its own static.
+#[cfg(not(trusty))]
+static OBJECT_IDS: Mutex<BTreeMap<TypeId, usize>> = Mutex::new(BTreeMap::new());
+
+/// The ID objects of type `T` are attached to binder objects with.
+#[cfg(not(trusty))]
+fn object_id<T: Any>() -> *const c_void {
+    let mut ids = OBJECT_IDS.lock().unwrap();
+    *ids.entry(TypeId::of::<T>()).or_insert_with(|| Box::into_raw(Box::new(0u8)) as usize)
+        as *const c_void
+}
+
+/// Releases the binder object's reference to an object attached with
+/// [`SpIBinder::attach_object`].
+///
+/// # Safety
+///
+/// `object` must come from `Arc::<T>::into_raw`, and this must be called at
+/// most once for it.
+#[cfg(not(trusty))]
+unsafe extern "C" fn cleanup_object<T>(
+    _id: *const c_void,
+    object: *mut c_void,
+    _cookie: *mut c_void,
+) {
+    // Safety: Our caller promised that this is a reference we leaked.
+    drop(unsafe { Arc::from_raw(object as *const T) });
+}
 
 impl fmt::Debug for SpIBinder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -74,7 +143,7 @@ impl SpIBinder {
     /// to an `AIBinder`, which will remain valid for the entire lifetime of the
     /// `SpIBinder` (we keep a strong reference, and only decrement on drop).
     pub(crate) unsafe fn from_raw(ptr: *mut sys::AIBinder) -> Option<Self> {
-        ptr::NonNull::new(ptr).map(Self)
+        ptr::NonNull::new(ptr).map(|ptr| Self { ptr, layers: None })
     }
 
     /// Extract a raw `AIBinder` pointer from this wrapper.
@@ -88,7 +157,16 @@ impl SpIBinder {
     /// The SpIBinder object retains ownership of the AIBinder and the caller
     /// should not attempt to free the returned pointer.
     pub unsafe fn as_raw(&self) -> *mut sys::AIBinder {
-        self.0.as_ptr()
+        self.ptr.as_ptr()
+    }
+
+    /// Returns a token identifying the binder object this refers to.
+    ///
+    /// All handles to the same object in this process have the same ID,
+    /// whichever interface they were obtained as, so this can be used as a key
+    /// to deduplicate callbacks or to store per-client state.
+    pub fn id(&self) -> BinderId {
+        BinderId(self.ptr.as_ptr() as usize)
     }
 
     /// Return true if this binder object is hosted in a different process than
@@ -99,6 +177,114 @@ impl SpIBinder {
         unsafe { sys::AIBinder_isRemote(self.as_native()) }
     }
 
+    /// Returns the PID of the process hosting this binder object.
+    ///
+    /// For a remote binder this asks the hosting process, so it costs a
+    /// transaction and trusts the answer. It is meant for diagnostics, such as
+    /// mapping proxies to processes in a debug dump, and must not be used for
+    /// access control. For a local binder, this is the PID of this process.
+    ///
+    /// Fails with `INVALID_OPERATION` before Android 16, which can't report
+    /// the PID.
+    #[cfg(not(trusty))]
+    pub fn remote_pid(&self) -> Result<libc::pid_t> {
+        let get_debug_pid = AIBinder_getDebugPid().ok_or(StatusCode::INVALID_OPERATION)?;
+        let mut pid = 0;
+        // Safety: `SpIBinder` guarantees that it always contains a valid
+        // `AIBinder` pointer, and `pid` is valid for writes for the duration
+        // of the call.
+        let status = unsafe { get_debug_pid(self.as_native() as *mut sys::AIBinder, &mut pid) };
+        status_result(status)?;
+        Ok(pid)
+    }
+
+    /// Attaches `object` to the binder object this refers to, until the
+    /// binder object is destroyed.
+    ///
+    /// Every handle to the binder object in this process sees the same
+    /// attached objects, so this can hold per-service or per-client state,
+    /// e.g. a session with a remote service, without a separate map keyed by
+    /// [`id`](Self::id). At most one object of each type can be attached, and
+    /// if there already is one, `object` is returned as the error.
+    ///
+    /// The binder object keeps `object` alive, so `object` must not own a
+    /// strong reference to the binder object it is attached to.
+    ///
+    /// Objects can't be attached before Android 16, so there this always
+    /// returns `object` as the error.
+    #[cfg(not(trusty))]
+    pub fn attach_object<T: Any + Send + Sync>(
+        &self,
+        object: Arc<T>,
+    ) -> std::result::Result<(), Arc<T>> {
+        let Some(attach_object) = AIBinder_attachObject() else {
+            return Err(object);
+        };
+        let raw = Arc::into_raw(object);
+        // Safety: `SpIBinder` guarantees that it always contains a valid
+        // `AIBinder` pointer, and the ID is a unique address for `T`. If the
+        // object is attached, the binder object owns the reference we leaked
+        // and releases it by calling `cleanup_object::<T>`.
+        let existing = unsafe {
+            attach_object(
+                self.as_native() as *mut sys::AIBinder,
+                object_id::<T>(),
+                raw as *mut c_void,
+                ptr::null_mut(),
+                Some(cleanup_object::<T>),
+            )
+        };
+        if existing.is_null() {
+            Ok(())
+        } else {
+            // Safety: The object wasn't attached, so we still own the
+            // reference we leaked.
+            Err(unsafe { Arc::from_raw(raw) })
+        }
+    }
+
+    /// Returns the object of type `T` attached to the binder object with
+    /// [`attach_object`](Self::attach_object), if any.
+    #[cfg(not(trusty))]
+    pub fn get_object<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let find_object = AIBinder_findObject()?;
+        // Safety: `SpIBinder` guarantees that it always contains a valid
+        // `AIBinder` pointer.
+        let object =
+            unsafe { find_object(self.as_native() as *mut sys::AIBinder, object_id::<T>()) };
+        if object.is_null() {
+            return None;
+        }
+        let object = object as *const T;
+        // Safety: Objects attached with the ID of `T` come from
+        // `Arc::<T>::into_raw`, and the binder object keeps its reference
+        // until it is destroyed, which can't happen while we hold `self`. So
+        // we can take another reference.
+        unsafe {
+            Arc::increment_strong_count(object);
+            Some(Arc::from_raw(object))
+        }
+    }
+
+    /// Returns the object of type `T` attached to the binder object, first
+    /// attaching the one `create` returns if there is none.
+    ///
+    /// Returns `None`, without calling `create`, before Android 16, where
+    /// objects can't be attached.
+    #[cfg(not(trusty))]
+    pub fn get_or_attach_object<T: Any + Send + Sync>(
+        &self,
+        create: impl FnOnce() -> T,
+    ) -> Option<Arc<T>> {
+        AIBinder_attachObject()?;
+        if let Some(object) = self.get_object() {
+            return Some(object);
+        }
+        // If another thread attached one first, that one is used instead.
+        let _ = self.attach_object(Arc::new(create()));
+        Some(self.get_object().expect("attached object is missing"))
+    }
+
     /// Try to convert this Binder object into a trait object for the given
     /// Binder interface.
     ///
@@ -122,12 +308,187 @@ impl SpIBinder {
         }
     }
 
+    /// Returns whether user transactions on this binder pass libbinder's
+    /// stability check from this process, i.e. whether it has at least the
+    /// stability of the vendor image when building vendor code, or of the
+    /// system image otherwise. Transactions on local binders aren't checked, so
+    /// this is always true for them.
+    ///
+    /// Devices older than Android 16 can't answer this, so it is also always
+    /// true there, and transactions which fail the check fail when they are
+    /// made instead.
+    pub fn has_partition_stability(&mut self) -> bool {
+        #[cfg(android_vendor)]
+        let is_stable = AIBinder_isVendorStable();
+        #[cfg(not(android_vendor))]
+        let is_stable = AIBinder_isSystemStable();
+        let Some(is_stable) = is_stable else {
+            return true;
+        };
+        // Safety: `SpIBinder` guarantees that it always contains a valid
+        // `AIBinder` pointer.
+        unsafe { is_stable(self.as_native_mut()) }
+    }
+
     /// Creates a new weak reference to this binder object.
+    ///
+    /// Transaction layers are not kept by the weak reference, so a binder
+    /// obtained by promoting it has none.
+    #[cfg(not(feature = "strict"))]
     pub fn downgrade(&mut self) -> WpIBinder {
-        WpIBinder::new(self)
+        WpIBinder::new(self).expect("Unexpected null pointer from AIBinder_Weak_new")
+    }
+
+    /// Creates a new weak reference to this binder object, or fails with
+    /// `NO_MEMORY` if the NDK can't allocate it.
+    ///
+    /// See [`downgrade`](Self::downgrade).
+    pub fn try_downgrade(&mut self) -> Result<WpIBinder> {
+        WpIBinder::new(self).ok_or(StatusCode::NO_MEMORY)
+    }
+
+    /// Returns a new handle to the same binder object which passes outgoing
+    /// transactions through `layer`.
+    ///
+    /// Layers already attached to this handle are kept and run before the new
+    /// one. This handle itself is not affected.
+    pub fn with_layer(&self, layer: Arc<dyn TransactionLayer>) -> SpIBinder {
+        let layers = self.layers.iter().flat_map(|layers| layers.iter().cloned());
+        let mut binder = self.clone();
+        binder.layers = Some(Arc::new(layers.chain(std::iter::once(layer)).collect()));
+        binder
+    }
+}
+
+/// A client-side interceptor for transactions sent to a binder object.
+///
+/// Layers are attached to a binder handle with [`SpIBinder::with_layer`] or
+/// [`Strong::with_layer`](crate::Strong::with_layer). Each layer sees every
+/// outgoing transaction, and decides whether and how to pass it on to the rest
+/// of the chain by calling [`NextLayer::transact`]. A layer may inspect or
+/// modify the transaction code, flags and data parcel on the way out, and the
+/// reply parcel or error on the way back.
+///
+/// Layers only run for transactions sent through the binder handle; calls on a
+/// local service object never go through a transaction and therefore bypass
+/// them.
+pub trait TransactionLayer: Send + Sync {
+    /// Handle an outgoing transaction.
+    ///
+    /// Implementations will usually call `next.transact(code, data, flags)`.
+    /// The data parcel must remain the one originally prepared for this
+    /// binder, though its contents may be modified.
+    fn transact(
+        &self,
+        code: TransactionCode,
+        data: Parcel,
+        flags: TransactionFlags,
+        next: NextLayer<'_>,
+    ) -> Result<Parcel>;
+}
+
+/// The remainder of a transaction layer chain, ending in the binder itself.
+pub struct NextLayer<'a> {
+    binder: *const sys::AIBinder,
+    layers: &'a [Arc<dyn TransactionLayer>],
+}
+
+impl<'a> NextLayer<'a> {
+    /// Pass the transaction on to the next layer, or to the binder if this was
+    /// the last layer.
+    pub fn transact(
+        self,
+        code: TransactionCode,
+        data: Parcel,
+        flags: TransactionFlags,
+    ) -> Result<Parcel> {
+        match self.layers.split_first() {
+            Some((layer, rest)) => {
+                layer.transact(code, data, flags, NextLayer { binder: self.binder, layers: rest })
+            }
+            // Safety: A `NextLayer` is only created by `submit_transact`, from
+            // a valid `AIBinder` pointer that is borrowed for at least `'a`.
+            None => unsafe { transact_timed(self.binder, code, data, flags) },
+        }
+    }
+}
+
+impl fmt::Debug for NextLayer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NextLayer").field("remaining_layers", &self.layers.len()).finish()
     }
 }
 
+/// Send a transaction to `binder`, bypassing any layers, but within the
+/// process's transaction timeout if there is one.
+///
+/// # Safety
+///
+/// `binder` must be a valid pointer to an `AIBinder` for the duration of the
+/// call.
+unsafe fn transact_timed(
+    binder: *const sys::AIBinder,
+    code: TransactionCode,
+    data: Parcel,
+    flags: TransactionFlags,
+) -> Result<Parcel> {
+    #[cfg(not(trusty))]
+    // Safety: Our caller guarantees that `binder` is valid for the call.
+    if let Some(timeout) = unsafe { crate::timeout::timeout_for(binder, flags) } {
+        // Safety: As above.
+        return unsafe {
+            crate::timeout::transact_with_timeout(binder, code, data, flags, timeout)
+        };
+    }
+    // Safety: As above.
+    unsafe { transact_raw(binder, code, data, flags) }
+}
+
+/// Send a transaction directly to `binder`, bypassing any layers.
+///
+/// # Safety
+///
+/// `binder` must be a valid pointer to an `AIBinder` for the duration of the
+/// call.
+pub(crate) unsafe fn transact_raw(
+    binder: *const sys::AIBinder,
+    code: TransactionCode,
+    data: Parcel,
+    flags: TransactionFlags,
+) -> Result<Parcel> {
+    let mut reply = ptr::null_mut();
+    // Safety: Our caller guarantees that `binder` is a valid pointer to an
+    // `AIBinder`. Although `IBinder::transact` is not a const method, it is
+    // still safe to cast our immutable pointer to mutable for the call.
+    // First, `IBinder::transact` is thread-safe, so concurrency is not an
+    // issue. The only way that `transact` can affect any visible, mutable
+    // state in the current process is by calling `onTransact` for a local
+    // service. However, in order for transactions to be thread-safe, this
+    // method must dynamically lock its data before modifying it. We enforce
+    // this property in Rust by requiring `Sync` for remotable objects and only
+    // providing `on_transact` with an immutable reference to `self`.
+    //
+    // This call takes ownership of the `data` parcel pointer, and passes
+    // ownership of the `reply` out parameter to its caller. It does not affect
+    // ownership of the `binder` parameter.
+    let status = unsafe {
+        sys::AIBinder_transact(
+            binder as *mut sys::AIBinder,
+            code,
+            &mut data.into_raw(),
+            &mut reply,
+            flags,
+        )
+    };
+    status_result(status)?;
+
+    // Safety: `reply` is either a valid `AParcel` pointer or null after the
+    // call to `AIBinder_transact` above, so we can construct a `Parcel` out of
+    // it. `AIBinder_transact` passes ownership of the `reply` parcel to Rust,
+    // so we need to construct an owned variant.
+    unsafe { Parcel::from_raw(reply).ok_or(StatusCode::UNEXPECTED_NULL) }
+}
+
 pub mod unstable_api {
     use super::{sys, SpIBinder};
 
@@ -172,11 +533,11 @@ impl Ord for SpIBinder {
         // Safety: SpIBinder always holds a valid `AIBinder` pointer, so this
         // pointer is always safe to pass to `AIBinder_lt` (null is also safe to
         // pass to this function, but we should never do that).
-        let less_than = unsafe { sys::AIBinder_lt(self.0.as_ptr(), other.0.as_ptr()) };
+        let less_than = unsafe { sys::AIBinder_lt(self.ptr.as_ptr(), other.ptr.as_ptr()) };
         // Safety: SpIBinder always holds a valid `AIBinder` pointer, so this
         // pointer is always safe to pass to `AIBinder_lt` (null is also safe to
         // pass to this function, but we should never do that).
-        let greater_than = unsafe { sys::AIBinder_lt(other.0.as_ptr(), self.0.as_ptr()) };
+        let greater_than = unsafe { sys::AIBinder_lt(other.ptr.as_ptr(), self.ptr.as_ptr()) };
         if !less_than && !greater_than {
             Ordering::Equal
         } else if less_than {
@@ -205,11 +566,11 @@ impl Clone for SpIBinder {
     fn clone(&self) -> Self {
         // Safety: Cloning a strong reference must increment the reference
         // count. We are guaranteed by the `SpIBinder` constructor
-        // invariants that `self.0` is always a valid `AIBinder` pointer.
+        // invariants that `self.ptr` is always a valid `AIBinder` pointer.
         unsafe {
-            sys::AIBinder_incStrong(self.0.as_ptr());
+            sys::AIBinder_incStrong(self.ptr.as_ptr());
         }
-        Self(self.0)
+        Self { ptr: self.ptr, layers: self.layers.clone() }
     }
 }
 
@@ -251,43 +612,46 @@ impl<T: AsNative<sys::AIBinder>> IBinderInternal for T {
     fn submit_transact(
         &self,
         code: TransactionCode,
-        data: Parcel,
+        mut data: Parcel,
         flags: TransactionFlags,
     ) -> Result<Parcel> {
-        let mut reply = ptr::null_mut();
-        // Safety: `SpIBinder` guarantees that `self` always contains a
-        // valid pointer to an `AIBinder`. Although `IBinder::transact` is
-        // not a const method, it is still safe to cast our immutable
-        // pointer to mutable for the call. First, `IBinder::transact` is
-        // thread-safe, so concurrency is not an issue. The only way that
-        // `transact` can affect any visible, mutable state in the current
-        // process is by calling `onTransact` for a local service. However,
-        // in order for transactions to be thread-safe, this method must
-        // dynamically lock its data before modifying it. We enforce this
-        // property in Rust by requiring `Sync` for remotable objects and
-        // only providing `on_transact` with an immutable reference to
-        // `self`.
-        //
-        // This call takes ownership of the `data` parcel pointer, and
-        // passes ownership of the `reply` out parameter to its caller. It
-        // does not affect ownership of the `binder` parameter.
-        let status = unsafe {
-            sys::AIBinder_transact(
-                self.as_native() as *mut sys::AIBinder,
-                code,
-                &mut data.into_raw(),
-                &mut reply,
-                flags,
-            )
+        let sensitive = flags & crate::binder::FLAG_CLEAR_BUF != 0;
+        if sensitive {
+            // As for `@SensitiveData` in AIDL, also zero our own copies of the
+            // data, not just the kernel's.
+            data.mark_sensitive();
+        }
+        let descriptor = || {
+            // Safety: `SpIBinder` guarantees that `self` always contains a
+            // valid pointer to an `AIBinder`. `AIBinder_getClass` does not
+            // modify the binder, and returns either a null pointer or a valid
+            // pointer to an `AIBinder_Class`.
+            unsafe {
+                let class = sys::AIBinder_getClass(self.as_native() as *mut sys::AIBinder);
+                class.as_ref().map(|p| InterfaceClass::from_ptr(p).get_descriptor())
+            }
         };
-        status_result(status)?;
-
-        // Safety: `reply` is either a valid `AParcel` pointer or null
-        // after the call to `AIBinder_transact` above, so we can
-        // construct a `Parcel` out of it. `AIBinder_transact` passes
-        // ownership of the `reply` parcel to Rust, so we need to
-        // construct an owned variant.
-        unsafe { Parcel::from_raw(reply).ok_or(StatusCode::UNEXPECTED_NULL) }
+        #[cfg(feature = "track_transactions")]
+        let _outgoing = (flags & crate::binder::FLAG_ONEWAY == 0)
+            .then(|| crate::debug::track_outgoing(descriptor(), code));
+        let reply = match self.transaction_layers() {
+            Some(layers) => {
+                NextLayer { binder: self.as_native(), layers: &layers }.transact(code, data, flags)
+            }
+            // Safety: `AsNative` guarantees that `self` always contains a
+            // valid pointer to an `AIBinder`, which it keeps alive for the
+            // duration of this call.
+            None => unsafe { transact_timed(self.as_native(), code, data, flags) },
+        };
+        if let Ok(reply) = &reply {
+            crate::debug::on_reply_received(descriptor, code, reply.borrowed_ref());
+        }
+        reply.map(|mut reply| {
+            if sensitive {
+                reply.mark_sensitive();
+            }
+            reply
+        })
     }
 
     fn is_binder_alive(&self) -> bool {
@@ -308,7 +672,7 @@ impl<T: AsNative<sys::AIBinder>> IBinderInternal for T {
     }
 
     fn dump<F: AsRawFd>(&mut self, fp: &F, args: &[&str]) -> Result<()> {
-        let args: Vec<_> = args.iter().map(|a| CString::new(*a).unwrap()).collect();
+        let args = args.iter().map(|a| checked_cstring(a)).collect::<Result<Vec<_>>>()?;
         let mut arg_ptrs: Vec<_> = args.iter().map(|a| a.as_ptr()).collect();
         // Safety: `SpIBinder` guarantees that `self` always contains a
         // valid pointer to an `AIBinder`. `AsRawFd` guarantees that the
@@ -446,6 +810,32 @@ impl fmt::Debug for WpIBinder {
     }
 }
 
+/// An opaque token identifying a binder object within this process.
+///
+/// The NDK keeps a single `AIBinder` for each binder object a process knows
+/// about, local or remote, so two handles have the same ID exactly when they
+/// compare equal. An ID is only meaningful while the object is alive: once
+/// every reference to it is dropped, its ID may be reused for another object.
+/// IDs are not meaningful to other processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BinderId(usize);
+
+impl From<&SpIBinder> for BinderId {
+    fn from(binder: &SpIBinder) -> Self {
+        binder.id()
+    }
+}
+
+/// Whether `a` and `b` refer to the same binder object.
+///
+/// The two handles need not be of the same type, e.g. a [`Strong`] to some
+/// interface can be compared with an [`SpIBinder`] received in a parcel.
+///
+/// [`Strong`]: crate::Strong
+pub fn same_binder(a: impl Into<BinderId>, b: impl Into<BinderId>) -> bool {
+    a.into() == b.into()
+}
+
 /// Safety: A `WpIBinder` is an immutable handle to a C++ IBinder, which is
 /// thread-safe.
 unsafe impl Send for WpIBinder {}
@@ -457,11 +847,19 @@ unsafe impl Sync for WpIBinder {}
 impl WpIBinder {
     /// Create a new weak reference from an object that can be converted into a
     /// raw `AIBinder` pointer.
-    fn new<B: AsNative<sys::AIBinder>>(binder: &mut B) -> WpIBinder {
+    fn new<B: AsNative<sys::AIBinder>>(binder: &mut B) -> Option<WpIBinder> {
         // Safety: `SpIBinder` guarantees that `binder` always contains a valid
         // pointer to an `AIBinder`.
         let ptr = unsafe { sys::AIBinder_Weak_new(binder.as_native_mut()) };
-        Self(ptr::NonNull::new(ptr).expect("Unexpected null pointer from AIBinder_Weak_new"))
+        ptr::NonNull::new(ptr).map(Self)
+    }
+
+    /// Returns the ID of the binder object this refers to, or `None` if it no
+    /// longer exists.
+    ///
+    /// See [`SpIBinder::id`].
+    pub fn id(&self) -> Option<BinderId> {
+        self.promote().map(|binder| binder.id())
     }
 
     /// Promote this weak reference to a strong reference to the binder object.
@@ -728,16 +1126,24 @@ unsafe impl<T: Proxy> AsNative<sys::AIBinder> for T {
     fn as_native_mut(&mut self) -> *mut sys::AIBinder {
         self.as_binder().as_native_mut()
     }
+
+    fn transaction_layers(&self) -> Option<TransactionLayers> {
+        self.as_binder().layers
+    }
 }
 
 /// Safety: `SpIBinder` guarantees that `binder` always contains a valid pointer
 /// to an `AIBinder`, so we can trivially extract this pointer here.
 unsafe impl AsNative<sys::AIBinder> for SpIBinder {
     fn as_native(&self) -> *const sys::AIBinder {
-        self.0.as_ptr()
+        self.ptr.as_ptr()
     }
 
     fn as_native_mut(&mut self) -> *mut sys::AIBinder {
-        self.0.as_ptr()
+        self.ptr.as_ptr()
+    }
+
+    fn transaction_layers(&self) -> Option<TransactionLayers> {
+        self.layers.clone()
     }
 }
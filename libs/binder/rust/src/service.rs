@@ -14,30 +14,145 @@
  * limitations under the License.
  */
 
-use crate::binder::{AsNative, FromIBinder, Strong};
-use crate::error::{status_result, Result, StatusCode};
+use crate::binder::{AsNative, FromIBinder, IBinder, Strong};
+use crate::error::{checked_cstring, status_result, Result, StatusCode};
 use crate::proxy::SpIBinder;
 use crate::sys;
+use crate::workers::WorkerPool;
 
 use std::ffi::{c_void, CStr, CString};
+use std::fmt;
 use std::os::raw::c_char;
-use std::sync::Mutex;
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Register a new service with the default service manager.
 ///
 /// Registers the given binder object with the given identifier. If successful,
 /// this service can then be retrieved using that identifier.
 ///
-/// This function will panic if the identifier contains a 0 byte (NUL).
+/// This function will panic if the identifier contains a 0 byte (NUL), or
+/// return `BAD_VALUE` with the `strict` feature.
 pub fn add_service(identifier: &str, mut binder: SpIBinder) -> Result<()> {
-    let instance = CString::new(identifier).unwrap();
+    let instance = checked_cstring(identifier)?;
     let status =
     // Safety: `AServiceManager_addService` expects valid `AIBinder` and C
     // string pointers. Caller retains ownership of both pointers.
     // `AServiceManager_addService` creates a new strong reference and copies
     // the string, so both pointers need only be valid until the call returns.
         unsafe { sys::AServiceManager_addService(binder.as_native_mut(), instance.as_ptr()) };
-    status_result(status)
+    status_result(status)?;
+    crate::debug::on_service_registered(identifier, binder.get_class().map(|c| c.get_descriptor()));
+    Ok(())
+}
+
+/// The priority at which `dumpsys` dumps a service.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DumpPriority {
+    /// The default dump priority.
+    #[default]
+    Default,
+    /// Dumped first, with a short timeout, in bugreports.
+    Critical,
+    /// Dumped before normal priority services.
+    High,
+    /// Dumped with the bulk of services.
+    Normal,
+}
+
+/// Options for registering a service with [`add_service_with_options`] or
+/// [`add_services`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AddServiceOptions {
+    /// Allow processes running as `AID_ISOLATED` to get this service.
+    ///
+    /// Services with methods that perform file IO, create sockets or
+    /// otherwise let data leave the process must not set this.
+    pub allow_isolated: bool,
+    /// The dumpsys priority of the service.
+    pub dump_priority: DumpPriority,
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+impl AddServiceOptions {
+    fn to_flags(self) -> sys::AServiceManager_AddServiceFlag {
+        let mut flags = match self.dump_priority {
+            DumpPriority::Default => {
+                sys::AServiceManager_AddServiceFlag_ADD_SERVICE_DUMP_FLAG_PRIORITY_DEFAULT
+            }
+            DumpPriority::Critical => {
+                sys::AServiceManager_AddServiceFlag_ADD_SERVICE_DUMP_FLAG_PRIORITY_CRITICAL
+            }
+            DumpPriority::High => {
+                sys::AServiceManager_AddServiceFlag_ADD_SERVICE_DUMP_FLAG_PRIORITY_HIGH
+            }
+            DumpPriority::Normal => {
+                sys::AServiceManager_AddServiceFlag_ADD_SERVICE_DUMP_FLAG_PRIORITY_NORMAL
+            }
+        };
+        if self.allow_isolated {
+            flags |= sys::AServiceManager_AddServiceFlag_ADD_SERVICE_ALLOW_ISOLATED;
+        }
+        flags
+    }
+}
+
+/// Register a new service with the default service manager, using the given
+/// options.
+///
+/// This function will panic if the identifier contains a 0 byte (NUL), or
+/// return `BAD_VALUE` with the `strict` feature.
+pub fn add_service_with_options(
+    identifier: &str,
+    mut binder: SpIBinder,
+    options: AddServiceOptions,
+) -> Result<()> {
+    let instance = checked_cstring(identifier)?;
+    // Safety: `AServiceManager_addServiceWithFlags` expects valid `AIBinder`
+    // and C string pointers, which it does not take ownership of. It takes its
+    // own strong reference and copies the string, so both need only be valid
+    // until the call returns.
+    let status = unsafe {
+        sys::AServiceManager_addServiceWithFlags(
+            binder.as_native_mut(),
+            instance.as_ptr(),
+            options.to_flags(),
+        )
+    };
+    status_result(status)?;
+    crate::debug::on_service_registered(identifier, binder.get_class().map(|c| c.get_descriptor()));
+    Ok(())
+}
+
+/// Register several services with the default service manager.
+///
+/// Every service is attempted, even if registering an earlier one fails. If
+/// any fail, the returned error lists each failed identifier together with its
+/// error, in the order they were given. Each registration is still a separate
+/// call to the service manager, so services registered before a failure remain
+/// registered.
+///
+/// This function will panic if any identifier contains a 0 byte (NUL). With
+/// the `strict` feature, such identifiers fail with `BAD_VALUE` instead.
+pub fn add_services(
+    services: &[(&str, SpIBinder, AddServiceOptions)],
+) -> std::result::Result<(), Vec<(String, StatusCode)>> {
+    let failures: Vec<(String, StatusCode)> = services
+        .iter()
+        .filter_map(|(identifier, binder, options)| {
+            add_service_with_options(identifier, binder.clone(), *options)
+                .err()
+                .map(|e| (identifier.to_string(), e))
+        })
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
 }
 
 /// Register a dynamic service via the LazyServiceRegistrar.
@@ -49,9 +164,10 @@ pub fn add_service(identifier: &str, mut binder: SpIBinder) -> Result<()> {
 /// If any service in the process is registered as lazy, all should be, otherwise
 /// the process may be shut down while a service is in use.
 ///
-/// This function will panic if the identifier contains a 0 byte (NUL).
+/// This function will panic if the identifier contains a 0 byte (NUL), or
+/// return `BAD_VALUE` with the `strict` feature.
 pub fn register_lazy_service(identifier: &str, mut binder: SpIBinder) -> Result<()> {
-    let instance = CString::new(identifier).unwrap();
+    let instance = checked_cstring(identifier)?;
     // Safety: `AServiceManager_registerLazyService` expects valid `AIBinder` and C
     // string pointers. Caller retains ownership of both
     // pointers. `AServiceManager_registerLazyService` creates a new strong reference
@@ -60,7 +176,9 @@ pub fn register_lazy_service(identifier: &str, mut binder: SpIBinder) -> Result<
     let status = unsafe {
         sys::AServiceManager_registerLazyService(binder.as_native_mut(), instance.as_ptr())
     };
-    status_result(status)
+    status_result(status)?;
+    crate::debug::on_service_registered(identifier, binder.get_class().map(|c| c.get_descriptor()));
+    Ok(())
 }
 
 /// Prevent a process which registers lazy services from being shut down even when none
@@ -134,7 +252,105 @@ pub fn is_handling_transaction() -> bool {
     unsafe { sys::AIBinder_isHandlingTransaction() }
 }
 
-fn interface_cast<T: FromIBinder + ?Sized>(service: Option<SpIBinder>) -> Result<Strong<T>> {
+/// The driver libbinder_ndk talks to. It is an LLNDK library, so vendor
+/// processes get the system libbinder behind it, which opens `/dev/binder`
+/// however the process was built; only processes using libbinder directly
+/// from the vendor partition default to `/dev/vndbinder`.
+const BINDER_DRIVER: &str = "/dev/binder";
+
+/// Describes why [`wait_for_service_manager`] gave up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceManagerUnavailable {
+    /// The binder driver node that was checked.
+    pub driver: &'static str,
+    /// Whether the driver node existed when the wait ended.
+    pub driver_present: bool,
+    /// How long was spent waiting.
+    pub waited: Duration,
+}
+
+impl fmt::Display for ServiceManagerUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.driver_present {
+            write!(f, "binder driver {} not present after {:?}", self.driver, self.waited)
+        } else {
+            write!(f, "servicemanager not responding on {} after {:?}", self.driver, self.waited)
+        }
+    }
+}
+
+impl std::error::Error for ServiceManagerUnavailable {}
+
+impl From<ServiceManagerUnavailable> for StatusCode {
+    fn from(e: ServiceManagerUnavailable) -> StatusCode {
+        if e.driver_present {
+            StatusCode::TIMED_OUT
+        } else {
+            StatusCode::NO_INIT
+        }
+    }
+}
+
+/// Block until the binder driver and the service manager are usable, or until
+/// `timeout` has elapsed.
+///
+/// This is meant for daemons that start early in boot and may race with
+/// servicemanager: rather than failing their first transaction with an opaque
+/// error, they can wait here and report why binder is unavailable.
+///
+/// The service manager is pinged until it responds. Reaching it can block
+/// inside libbinder, so the pings are sent one at a time from a helper
+/// thread. If the timeout expires during a ping, the ping keeps that thread
+/// until it finishes, and later calls wait for it rather than starting more.
+pub fn wait_for_service_manager(
+    timeout: Duration,
+) -> std::result::Result<(), ServiceManagerUnavailable> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+    static PROBES: WorkerPool = WorkerPool::new("binder_sm_probe", 1);
+
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let unavailable = |driver_present| ServiceManagerUnavailable {
+        driver: BINDER_DRIVER,
+        driver_present,
+        waited: start.elapsed(),
+    };
+
+    while !Path::new(BINDER_DRIVER).exists() {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(unavailable(false));
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+
+    loop {
+        let (sender, receiver) = mpsc::channel();
+        let probe = PROBES.execute(move || {
+            let responded = check_service("manager").is_some_and(|mut sm| sm.ping_binder().is_ok());
+            // The receiver may have timed out and gone away, which is fine.
+            let _ = sender.send(responded);
+        });
+        // If the pool is full, an earlier ping is still blocked, so wait and
+        // see whether it gets through before sending another.
+        if probe.is_ok() {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(_) => return Err(unavailable(true)),
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(unavailable(true));
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+pub(crate) fn interface_cast<T: FromIBinder + ?Sized>(
+    service: Option<SpIBinder>,
+) -> Result<Strong<T>> {
     if let Some(service) = service {
         FromIBinder::try_from(service)
     } else {
@@ -154,6 +370,10 @@ pub fn get_service(name: &str) -> Option<SpIBinder> {
 }
 
 /// Retrieve an existing service. Returns `None` immediately if the service is not available.
+///
+/// Services that are only reachable through an accessor (e.g. RPC binder
+/// services registered with `addAccessorProvider` in libbinder) are resolved by
+/// the underlying service manager, so callers get a usable binder either way.
 pub fn check_service(name: &str) -> Option<SpIBinder> {
     let name = CString::new(name).ok()?;
     // Safety: `AServiceManager_checkService` returns either a null pointer or
@@ -164,6 +384,9 @@ pub fn check_service(name: &str) -> Option<SpIBinder> {
 
 /// Retrieve an existing service, or start it if it is configured as a dynamic
 /// service and isn't yet started.
+///
+/// As with [`check_service`], accessor-backed services are connected to
+/// transparently.
 pub fn wait_for_service(name: &str) -> Option<SpIBinder> {
     let name = CString::new(name).ok()?;
     // Safety: `AServiceManager_waitforService` returns either a null pointer or
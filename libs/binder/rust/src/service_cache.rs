@@ -0,0 +1,273 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A cache of services which doesn't keep unused services alive.
+
+use crate::binder::{FromIBinder, IBinder, Strong};
+use crate::error::{Result, StatusCode};
+use crate::proxy::{SpIBinder, WpIBinder};
+use crate::service::wait_for_service;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    weak: WpIBinder,
+    /// Dropped once the service has been unused for the idle timeout, or the
+    /// cache is trimmed.
+    strong: Option<SpIBinder>,
+    last_used: Instant,
+}
+
+/// Caches services by name, holding strong references only to recently used
+/// ones.
+///
+/// A strong reference keeps a lazy service running, so a long-lived process
+/// which caches every service it has used keeps all of them running. This
+/// cache instead drops its strong reference once a service has been unused
+/// for the idle timeout, or when [`trim`](Self::trim) is called, e.g.
+/// under memory pressure, and keeps only a weak reference. If the service is
+/// still alive when it is next used, the weak reference is promoted instead
+/// of looking the service up again.
+pub struct WeakServiceCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    idle_timeout: Duration,
+    max_strong: usize,
+    lookup: Box<dyn Fn(&str) -> Option<SpIBinder> + Send + Sync>,
+}
+
+impl fmt::Debug for WeakServiceCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakServiceCache")
+            .field("entries", &self.entries.lock().unwrap().len())
+            .field("idle_timeout", &self.idle_timeout)
+            .field("max_strong", &self.max_strong)
+            .finish()
+    }
+}
+
+impl Default for WeakServiceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WeakServiceCache {
+    /// How long a service is kept alive after its last use, by default.
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+    /// Create a cache which looks services up with [`wait_for_service`],
+    /// with the default idle timeout and no limit on strong references.
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
+            max_strong: usize::MAX,
+            lookup: Box::new(wait_for_service),
+        }
+    }
+
+    /// Drop the strong reference to a service once it has been unused for
+    /// `idle_timeout`.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Hold strong references to at most `max_strong` services, dropping
+    /// those of the least recently used services first.
+    pub fn with_max_strong(mut self, max_strong: usize) -> Self {
+        self.max_strong = max_strong;
+        self
+    }
+
+    /// Look services up with `lookup` rather than [`wait_for_service`], e.g.
+    /// with [`check_service`](crate::check_service) to avoid blocking.
+    pub fn with_lookup<F>(mut self, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<SpIBinder> + Send + Sync + 'static,
+    {
+        self.lookup = Box::new(lookup);
+        self
+    }
+
+    /// Get the service registered under `name`, from the cache if it is still
+    /// alive, or else by looking it up.
+    pub fn get(&self, name: &str) -> Option<SpIBinder> {
+        let now = Instant::now();
+        if let Some(binder) = self.get_cached(name, now) {
+            return Some(binder);
+        }
+        // Look the service up without holding the lock, as it may block.
+        let mut binder = (self.lookup)(name)?;
+        let Ok(weak) = binder.try_downgrade() else {
+            // Still hand out the service, it just can't be cached.
+            return Some(binder);
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Entry { weak, strong: Some(binder.clone()), last_used: now };
+        entries.insert(name.to_owned(), entry);
+        self.evict(&mut entries, now);
+        Some(binder)
+    }
+
+    /// Get the service registered under `name` as interface `T`.
+    pub fn get_interface<T: FromIBinder + ?Sized>(&self, name: &str) -> Result<Strong<T>> {
+        FromIBinder::try_from(self.get(name).ok_or(StatusCode::NAME_NOT_FOUND)?)
+    }
+
+    /// Drop every strong reference, keeping only weak ones.
+    pub fn trim(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| {
+            entry.strong = None;
+            entry.weak.promote().is_some()
+        });
+    }
+
+    /// Drop strong references to services which have been unused for the idle
+    /// timeout, and forget services which have died. This also happens
+    /// whenever a service is looked up.
+    pub fn evict_idle(&self) {
+        self.evict(&mut self.entries.lock().unwrap(), Instant::now());
+    }
+
+    /// Forget the service registered under `name`.
+    pub fn remove(&self, name: &str) {
+        self.entries.lock().unwrap().remove(name);
+    }
+
+    /// The number of services the cache holds strong references to.
+    pub fn strong_count(&self) -> usize {
+        self.entries.lock().unwrap().values().filter(|entry| entry.strong.is_some()).count()
+    }
+
+    fn get_cached(&self, name: &str, now: Instant) -> Option<SpIBinder> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(name)?;
+        let Some(binder) = entry.strong.clone().or_else(|| entry.weak.promote()) else {
+            entries.remove(name);
+            return None;
+        };
+        if !binder.is_binder_alive() {
+            entries.remove(name);
+            return None;
+        }
+        entry.strong = Some(binder.clone());
+        entry.last_used = now;
+        self.evict(&mut entries, now);
+        Some(binder)
+    }
+
+    fn evict(&self, entries: &mut HashMap<String, Entry>, now: Instant) {
+        entries.retain(|_, entry| {
+            if now.saturating_duration_since(entry.last_used) >= self.idle_timeout {
+                entry.strong = None;
+            }
+            entry.strong.is_some() || entry.weak.promote().is_some()
+        });
+        let mut strong: Vec<_> =
+            entries.values_mut().filter(|entry| entry.strong.is_some()).collect();
+        if strong.len() > self.max_strong {
+            strong.sort_by_key(|entry| entry.last_used);
+            let excess = strong.len() - self.max_strong;
+            for entry in strong.into_iter().take(excess) {
+                entry.strong = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder::{Interface, Remotable, TransactionCode};
+    use crate::native::Binder;
+    use crate::parcel::BorrowedParcel;
+    use std::ffi::CStr;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Service;
+
+    impl Remotable for Service {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.ICached"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    /// A cache whose lookups create a new service each time, and count them.
+    fn counting_cache() -> (WeakServiceCache, Arc<AtomicUsize>) {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = lookups.clone();
+        let cache = WeakServiceCache::new().with_lookup(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(Binder::new(Service).as_binder())
+        });
+        (cache, lookups)
+    }
+
+    #[test]
+    fn idle_services_are_only_weakly_held() {
+        let (cache, lookups) = counting_cache();
+        let cache = cache.with_idle_timeout(Duration::ZERO);
+
+        let first = cache.get("foo").unwrap();
+        assert_eq!(cache.strong_count(), 0);
+        // The caller's reference keeps the service alive, so the weak
+        // reference is promoted.
+        assert_eq!(cache.get("foo").unwrap(), first);
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        drop(first);
+        cache.get("foo").unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn trim_and_limit_drop_strong_references() {
+        let (cache, lookups) = counting_cache();
+        let cache = cache.with_max_strong(1);
+        cache.get("foo").unwrap();
+        cache.get("bar").unwrap();
+        assert_eq!(cache.strong_count(), 1);
+        cache.get("bar").unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        cache.trim();
+        assert_eq!(cache.strong_count(), 0);
+        cache.get("bar").unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+    }
+}
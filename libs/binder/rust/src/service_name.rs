@@ -0,0 +1,226 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Service names which are checked at compile time.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::ops::Deref;
+
+/// The longest service name the service manager accepts.
+pub const MAX_SERVICE_NAME_LEN: usize = 127;
+
+/// A service name which is known to be valid, usually created with
+/// [`instance_name!`](crate::instance_name).
+///
+/// A `ServiceName` dereferences to `str`, so it can be passed to the service
+/// functions such as [`add_service`](crate::add_service) and
+/// [`get_interface`](crate::get_interface) directly.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceName {
+    // Includes a trailing NUL, so that it can be passed to the NDK without
+    // copying.
+    with_nul: &'static str,
+}
+
+impl ServiceName {
+    /// Check that `name` is a name the service manager accepts: non-empty, at
+    /// most [`MAX_SERVICE_NAME_LEN`] bytes of ASCII letters, digits, `_`, `-`,
+    /// `.` and `/`. A name with a `/` must be of the form
+    /// `descriptor/instance`, with a dot-separated descriptor and a
+    /// non-empty instance.
+    ///
+    /// Returns a description of the problem if it is invalid.
+    pub const fn validate(name: &str) -> Result<(), &'static str> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() {
+            return Err("service name is empty");
+        }
+        if bytes.len() > MAX_SERVICE_NAME_LEN {
+            return Err("service name is longer than 127 bytes");
+        }
+        let mut slash = None;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'-' | b'.' => {}
+                b'/' => {
+                    if slash.is_none() {
+                        slash = Some(i);
+                    }
+                }
+                _ => return Err("service name contains a character other than [a-zA-Z0-9_-./]"),
+            }
+            i += 1;
+        }
+        let Some(slash) = slash else {
+            return Ok(());
+        };
+        if slash == 0 {
+            return Err("descriptor before '/' is empty");
+        }
+        if slash == bytes.len() - 1 {
+            return Err("instance after '/' is empty");
+        }
+        if bytes[0] == b'.' || bytes[slash - 1] == b'.' {
+            return Err("descriptor starts or ends with '.'");
+        }
+        let mut dots = 0;
+        let mut i = 1;
+        while i < slash {
+            if bytes[i] == b'.' {
+                if bytes[i - 1] == b'.' {
+                    return Err("descriptor contains an empty component");
+                }
+                dots += 1;
+            }
+            i += 1;
+        }
+        if dots == 0 {
+            return Err("descriptor is not a dot-separated name");
+        }
+        Ok(())
+    }
+
+    /// Create a `ServiceName` from a string literal with a trailing NUL,
+    /// failing to compile in a const context if the name is invalid.
+    ///
+    /// Use [`instance_name!`](crate::instance_name) rather than calling this
+    /// directly.
+    #[doc(hidden)]
+    pub const fn from_literal_with_nul(with_nul: &'static str) -> Self {
+        let bytes = with_nul.as_bytes();
+        assert!(!bytes.is_empty() && bytes[bytes.len() - 1] == 0, "missing trailing NUL");
+        let (name, _) = bytes.split_at(bytes.len() - 1);
+        let Ok(name) = std::str::from_utf8(name) else {
+            unreachable!();
+        };
+        match Self::validate(name) {
+            Ok(()) => Self { with_nul },
+            Err(problem) => panic!("{}", problem),
+        }
+    }
+
+    /// The name as a string.
+    pub fn as_str(&self) -> &'static str {
+        &self.with_nul[..self.with_nul.len() - 1]
+    }
+
+    /// The name as a C string, without allocating.
+    pub fn as_c_str(&self) -> &'static CStr {
+        // Safety: `with_nul` ends in a NUL and, as a valid name, has no other NUL
+        // bytes.
+        unsafe { CStr::from_bytes_with_nul_unchecked(self.with_nul.as_bytes()) }
+    }
+
+    /// The descriptor part of a `descriptor/instance` name, or `None` for a
+    /// name without an instance.
+    pub fn descriptor(&self) -> Option<&'static str> {
+        self.as_str().split_once('/').map(|(descriptor, _)| descriptor)
+    }
+
+    /// The instance part of a `descriptor/instance` name, or `None` for a name
+    /// without an instance.
+    pub fn instance(&self) -> Option<&'static str> {
+        self.as_str().split_once('/').map(|(_, instance)| instance)
+    }
+}
+
+impl Deref for ServiceName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ServiceName {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for ServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Create a `&'static` [`ServiceName`] from a string literal, checking at
+/// compile time that it is a valid service name.
+///
+/// ```
+/// # use binder::instance_name;
+/// let name = instance_name!("android.hardware.foo.IFoo/default");
+/// assert_eq!(name.descriptor(), Some("android.hardware.foo.IFoo"));
+/// assert_eq!(name.instance(), Some("default"));
+/// ```
+///
+/// Invalid names are a compile error:
+///
+/// ```compile_fail
+/// # use binder::instance_name;
+/// let name = instance_name!("android.hardware.foo.IFoo/");
+/// ```
+#[macro_export]
+macro_rules! instance_name {
+    ($name:literal) => {{
+        static NAME: $crate::ServiceName =
+            $crate::ServiceName::from_literal_with_nul(concat!($name, "\0"));
+        &NAME
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in ["activity", "android.os.IFoo/default", "a.b/c/d", "android.hardware.x_y-z/1"] {
+            assert_eq!(ServiceName::validate(name), Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        let long = "a".repeat(MAX_SERVICE_NAME_LEN + 1);
+        for name in
+            ["", "foo bar", "foo\0", "IFoo/default", "/default", "a.b/", ".a.b/c", "a..b/c", &long]
+        {
+            assert!(ServiceName::validate(name).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn macro_splits_name() {
+        let name: &'static ServiceName = crate::instance_name!("android.os.IFoo/default");
+        assert_eq!(name.as_str(), "android.os.IFoo/default");
+        assert_eq!(name.as_c_str().to_bytes(), b"android.os.IFoo/default");
+        assert_eq!(name.descriptor(), Some("android.os.IFoo"));
+        assert_eq!(name.instance(), Some("default"));
+        assert_eq!(&**name, "android.os.IFoo/default");
+
+        let plain = crate::instance_name!("activity");
+        assert_eq!((plain.descriptor(), plain.instance()), (None, None));
+    }
+}
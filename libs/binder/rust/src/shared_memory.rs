@@ -0,0 +1,546 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shared memory regions which can be sent over binder, like
+//! `android.os.SharedMemory` in Java.
+
+use crate::binder::AsNative;
+use crate::error::{status_result, Result, StatusCode};
+use crate::parcel::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Serialize, SerializeArray,
+    SerializeOption, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
+};
+use crate::sys;
+
+use std::ffi::{c_char, c_int, CString, OsStr};
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::PathBuf;
+use std::ptr::NonNull;
+use std::sync::OnceLock;
+
+const ASHMEM_IOC: u32 = 0x77;
+const ASHMEM_GET_SIZE: u32 = ASHMEM_IOC << 8 | 4;
+const ASHMEM_SET_PROT_MASK: u32 =
+    1 << 30 | (std::mem::size_of::<libc::c_ulong>() as u32) << 16 | ASHMEM_IOC << 8 | 5;
+
+ndk_api! {
+    in "libandroid.so";
+    fn ASharedMemory_create(*const c_char, usize) -> c_int;
+}
+
+ndk_api! {
+    in "libbinder_ndk.so";
+    fn AParcel_readRawFileDescriptor(*const sys::AParcel, *mut c_int) -> sys::binder_status_t;
+    fn AParcel_writeRawFileDescriptor(*mut sys::AParcel, c_int) -> sys::binder_status_t;
+}
+
+/// A region of memory which can be shared with other processes by sending it
+/// over binder.
+///
+/// This is parceled as Java's `android.os.SharedMemory`, so the two can be
+/// sent to each other, which needs Android 16 or later. New regions are
+/// created with `ASharedMemory_create` where `libandroid` is available, so
+/// that they are the kind of region Java accepts, and with `memfd_create`
+/// otherwise, e.g. in vendor processes. Regions received from other processes
+/// may be either sealed memfds or ashmem regions.
+#[derive(Debug)]
+pub struct SharedMemory {
+    fd: OwnedFd,
+    size: usize,
+}
+
+impl SharedMemory {
+    /// Create a new region of `size` bytes, filled with zeros. The name is
+    /// only used for debugging, e.g. in `/proc/<pid>/maps`.
+    pub fn create(name: &str, size: usize) -> io::Result<Self> {
+        let name = CString::new(name).map_err(|_| io::ErrorKind::InvalidInput)?;
+        // `ASharedMemory_create` rejects empty regions, which Java doesn't
+        // allow either.
+        if let Some(create) = ASharedMemory_create().filter(|_| size > 0) {
+            // Safety: `name` is a valid C string.
+            let fd = unsafe { create(name.as_ptr(), size) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Safety: `ASharedMemory_create` returned a new file descriptor
+            // which nothing else owns.
+            return Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        let len = libc::off_t::try_from(size).map_err(|_| io::ErrorKind::InvalidInput)?;
+        // Safety: `name` is a valid C string, and the flags are valid.
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `memfd_create` returned a new file descriptor which nothing
+        // else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // Safety: `fd` is a valid file descriptor.
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The region can't grow or shrink once it is shared. It isn't sealed
+        // against further seals, so that `set_protection` can still add one.
+        add_seals(&fd, libc::F_SEAL_GROW | libc::F_SEAL_SHRINK)?;
+        Ok(Self { fd, size })
+    }
+
+    /// Take ownership of a memfd or ashmem file descriptor.
+    ///
+    /// This fails with `InvalidData` for any other kind of file, and for a
+    /// memfd which isn't sealed with `F_SEAL_SHRINK`, as regions made by
+    /// [`create`](Self::create) are. Otherwise the process which sent it could
+    /// shrink the file, and accessing our mapping of the missing pages would
+    /// crash with `SIGBUS`. Ashmem regions can't be resized once mapped.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        // Safety: `fd` is a valid file descriptor.
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals >= 0 {
+            if seals & libc::F_SEAL_SHRINK == 0 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let size = usize::try_from(fstat(&fd)?.st_size).or(Err(io::ErrorKind::InvalidData))?;
+            return Ok(Self { fd, size });
+        }
+        // Only send ashmem ioctls to files we know are ashmem, as other
+        // devices may give them different meanings.
+        if !is_ashmem(&fd)? {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        // Safety: `fd` is a valid ashmem file descriptor, and `ASHMEM_GET_SIZE`
+        // takes no argument.
+        let size = unsafe { libc::ioctl(fd.as_raw_fd(), ASHMEM_GET_SIZE as _) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd, size: size as usize })
+    }
+
+    /// The size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Restrict the ways in which the region can be mapped, by this and every
+    /// other process it is shared with, to `prot`.
+    ///
+    /// `prot` is a combination of `libc::PROT_READ`, `libc::PROT_WRITE` and
+    /// `libc::PROT_EXEC`, as for `ASharedMemory_setProt`. Protections can only
+    /// be removed, never added back, so this is typically used to make a
+    /// region read-only before sending it to untrusted processes. Mappings
+    /// made before the call are unaffected.
+    pub fn set_protection(&self, prot: libc::c_int) -> io::Result<()> {
+        // Safety: `self.fd` is a valid file descriptor.
+        let seals = unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            // Not a memfd, so an ashmem region, as `from_fd` checked.
+            let mask = prot as libc::c_ulong;
+            // Safety: `self.fd` is a valid file descriptor, and
+            // `ASHMEM_SET_PROT_MASK` takes an integer argument.
+            if unsafe { libc::ioctl(self.fd.as_raw_fd(), ASHMEM_SET_PROT_MASK as _, mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            return Ok(());
+        }
+        let write_sealed = seals & libc::F_SEAL_FUTURE_WRITE != 0;
+        if prot & libc::PROT_WRITE != 0 {
+            // Writing can't be allowed again once it has been sealed.
+            if write_sealed {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            return Ok(());
+        }
+        if write_sealed {
+            return Ok(());
+        }
+        // No further seals are needed once writing is sealed, and none can be
+        // removed anyway, so stop anyone from adding any.
+        add_seals(&self.fd, libc::F_SEAL_FUTURE_WRITE | libc::F_SEAL_SEAL)
+    }
+
+    /// Map the region for reading.
+    ///
+    /// Other processes with a writable mapping of the region may change it
+    /// while it is mapped, so its contents shouldn't be trusted or assumed to
+    /// stay the same, e.g. between validating and using a value; copy the
+    /// value out once and use the copy.
+    pub fn map(&self) -> io::Result<MemoryMapping<'_>> {
+        let ptr = self.mmap(libc::PROT_READ)?;
+        Ok(MemoryMapping { ptr, len: self.size, _memory: PhantomData })
+    }
+
+    /// Map the region for reading and writing. This fails if writing has been
+    /// disallowed with [`set_protection`](Self::set_protection).
+    pub fn map_mut(&mut self) -> io::Result<MemoryMappingMut<'_>> {
+        let ptr = self.mmap(libc::PROT_READ | libc::PROT_WRITE)?;
+        Ok(MemoryMappingMut(MemoryMapping { ptr, len: self.size, _memory: PhantomData }))
+    }
+
+    fn mmap(&self, prot: libc::c_int) -> io::Result<NonNull<u8>> {
+        if self.size == 0 {
+            // `mmap` rejects empty mappings.
+            return Ok(NonNull::dangling());
+        }
+        // Safety: We map a new region of `self.size` bytes of our file
+        // descriptor, which doesn't affect any existing memory.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                self.size,
+                prot,
+                libc::MAP_SHARED,
+                self.fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NonNull::new(ptr.cast()).expect("mmap returned null"))
+    }
+}
+
+fn add_seals(fd: &OwnedFd, seals: libc::c_int) -> io::Result<()> {
+    // Safety: `fd` is a valid file descriptor.
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn fstat(fd: &OwnedFd) -> io::Result<libc::stat> {
+    // Safety: `stat` is plain data, for which all zeros is valid.
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // Safety: `fd` is a valid file descriptor, and `stat` is valid to write to.
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+/// Whether `fd` is an ashmem region, i.e. the ashmem character device, as
+/// `ashmem_valid` in libcutils checks.
+fn is_ashmem(fd: &OwnedFd) -> io::Result<bool> {
+    let stat = fstat(fd)?;
+    if stat.st_mode & libc::S_IFMT != libc::S_IFCHR || stat.st_rdev == 0 {
+        return Ok(false);
+    }
+    Ok(ashmem_rdev() == Some(stat.st_rdev))
+}
+
+/// The device number of the ashmem device, if there is one.
+fn ashmem_rdev() -> Option<libc::dev_t> {
+    static RDEV: OnceLock<Option<libc::dev_t>> = OnceLock::new();
+    *RDEV.get_or_init(|| {
+        // Newer kernels name the device after the boot ID, so that apps can't
+        // guess it, as libcutils does.
+        let boot_id = std::fs::read("/proc/sys/kernel/random/boot_id").unwrap_or_default();
+        let mut named = b"/dev/ashmem".to_vec();
+        named.extend(boot_id.strip_suffix(b"\n").unwrap_or(&boot_id));
+        [PathBuf::from(OsStr::from_bytes(&named)), PathBuf::from("/dev/ashmem")]
+            .iter()
+            .find_map(|path| std::fs::metadata(path).ok())
+            .filter(|metadata| metadata.file_type().is_char_device())
+            .map(|metadata| metadata.rdev() as libc::dev_t)
+    })
+}
+
+impl AsFd for SharedMemory {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SharedMemory {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<SharedMemory> for OwnedFd {
+    fn from(memory: SharedMemory) -> OwnedFd {
+        memory.fd
+    }
+}
+
+/// A mapping of a [`SharedMemory`] region for reading, which is unmapped
+/// when dropped.
+///
+/// Other processes with a writable mapping of the region may change it at any
+/// time, so it can't be borrowed as a slice. Its contents are copied out with
+/// volatile reads instead, and the copy is what should be validated and used.
+#[derive(Debug)]
+pub struct MemoryMapping<'a> {
+    ptr: NonNull<u8>,
+    len: usize,
+    _memory: PhantomData<&'a SharedMemory>,
+}
+
+/// Safety: The mapping is only memory, which any thread can access.
+unsafe impl Send for MemoryMapping<'_> {}
+
+/// Safety: The mapping is only accessed with volatile reads through shared
+/// references.
+unsafe impl Sync for MemoryMapping<'_> {}
+
+impl MemoryMapping<'_> {
+    /// The length of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the mapping is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// A pointer to the start of the mapping, which is valid for `len` bytes
+    /// until the mapping is dropped. Other processes may write to the memory
+    /// at any time, so it must only be accessed with volatile or atomic
+    /// operations.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Copy `buf.len()` bytes starting `offset` bytes into the mapping into
+    /// `buf`. Fails with `InvalidInput` if they aren't all in the mapping.
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: usize) -> io::Result<()> {
+        self.check_range(offset, buf.len())?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // Safety: The byte is within the mapping, which stays valid until
+            // it is dropped. It may be written concurrently by another
+            // process, so it is read with a volatile read.
+            *byte = unsafe { self.ptr.as_ptr().add(offset + i).read_volatile() };
+        }
+        Ok(())
+    }
+
+    /// Copy the whole mapping into a new `Vec`.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.len];
+        self.read_exact_at(&mut bytes, 0).expect("the whole mapping is in range");
+        bytes
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+}
+
+impl Drop for MemoryMapping<'_> {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // Safety: We mapped `len` bytes at `ptr`, and nothing can refer to
+            // them once the mapping is gone.
+            unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+        }
+    }
+}
+
+/// A mapping of a [`SharedMemory`] region for reading and writing, which is
+/// unmapped when dropped.
+///
+/// As with [`MemoryMapping`], the memory is only accessed by copying in and
+/// out with volatile accesses, as other processes may access it at the same
+/// time.
+#[derive(Debug)]
+pub struct MemoryMappingMut<'a>(MemoryMapping<'a>);
+
+impl MemoryMappingMut<'_> {
+    /// A pointer to the start of the mapping, which is valid for `len` bytes
+    /// until the mapping is dropped. Other processes may access the memory at
+    /// any time, so it must only be accessed with volatile or atomic
+    /// operations.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.0.ptr.as_ptr()
+    }
+
+    /// Copy `buf` into the mapping, starting `offset` bytes into it. Fails
+    /// with `InvalidInput` if it doesn't all fit.
+    pub fn write_all_at(&mut self, buf: &[u8], offset: usize) -> io::Result<()> {
+        self.0.check_range(offset, buf.len())?;
+        for (i, &byte) in buf.iter().enumerate() {
+            // Safety: The byte is within the writable mapping, which stays
+            // valid until it is dropped. It may be accessed concurrently by
+            // another process, so it is written with a volatile write.
+            unsafe { self.0.ptr.as_ptr().add(offset + i).write_volatile(byte) };
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for MemoryMappingMut<'a> {
+    type Target = MemoryMapping<'a>;
+
+    fn deref(&self) -> &MemoryMapping<'a> {
+        &self.0
+    }
+}
+
+impl Serialize for SharedMemory {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        Self::serialize_option(Some(self), parcel)
+    }
+}
+
+impl SerializeOption for SharedMemory {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        let Some(this) = this else {
+            return parcel.write(&NULL_PARCELABLE_FLAG);
+        };
+        // Devices before Android 16 can't write the format Java expects.
+        let write_fd = AParcel_writeRawFileDescriptor().ok_or(StatusCode::INVALID_OPERATION)?;
+        parcel.write(&NON_NULL_PARCELABLE_FLAG)?;
+        // Safety: `Parcel` always contains a valid pointer to an `AParcel`,
+        // and the NDK duplicates the file descriptor rather than taking it.
+        let status = unsafe { write_fd(parcel.as_native_mut(), this.fd.as_raw_fd()) };
+        status_result(status)
+    }
+}
+
+impl SerializeArray for SharedMemory {}
+
+impl Deserialize for SharedMemory {
+    type UninitType = Option<Self>;
+    fn uninit() -> Self::UninitType {
+        None
+    }
+    fn from_init(value: Self) -> Self::UninitType {
+        Some(value)
+    }
+
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        Self::deserialize_option(parcel)?.ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+}
+
+impl DeserializeOption for SharedMemory {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        if parcel.read::<i32>()? == NULL_PARCELABLE_FLAG {
+            return Ok(None);
+        }
+        let read_fd = AParcel_readRawFileDescriptor().ok_or(StatusCode::INVALID_OPERATION)?;
+        let mut fd = -1;
+        // Safety: `Parcel` always contains a valid pointer to an `AParcel`,
+        // and `fd` is valid to write to.
+        status_result(unsafe { read_fd(parcel.as_native(), &mut fd) })?;
+        // Safety: On success, the NDK gives us ownership of a new file
+        // descriptor.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Self::from_fd(fd).map(Some).or(Err(StatusCode::BAD_VALUE))
+    }
+}
+
+impl DeserializeArray for SharedMemory {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parcel::Parcel;
+    use std::fs::File;
+
+    #[test]
+    fn test_map_and_protect() {
+        let mut memory = SharedMemory::create("test", 4096).unwrap();
+        assert_eq!(memory.size(), 4096);
+        memory.map_mut().unwrap().write_all_at(b"hello", 0).unwrap();
+        let mut hello = [0; 5];
+        memory.map().unwrap().read_exact_at(&mut hello, 0).unwrap();
+        assert_eq!(&hello, b"hello");
+
+        memory.set_protection(libc::PROT_READ).unwrap();
+        memory.set_protection(libc::PROT_READ).unwrap();
+        assert!(memory.map_mut().is_err());
+        assert_eq!(&memory.map().unwrap().to_vec()[..5], b"hello");
+        assert!(memory.set_protection(libc::PROT_READ | libc::PROT_WRITE).is_err());
+
+        let empty = SharedMemory::create("empty", 0).unwrap();
+        assert!(empty.map().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_accesses_out_of_range() {
+        let mut memory = SharedMemory::create("test", 10).unwrap();
+        let mut mapping = memory.map_mut().unwrap();
+        assert_eq!(mapping.len(), 10);
+        mapping.write_all_at(b"abc", 7).unwrap();
+        let error = mapping.write_all_at(b"abc", 8).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut buf = [0; 3];
+        mapping.read_exact_at(&mut buf, 7).unwrap();
+        assert_eq!(&buf, b"abc");
+        let error = mapping.read_exact_at(&mut buf, usize::MAX).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_from_fd_needs_shrink_seal() {
+        let name = CString::new("unsealed").unwrap();
+        // SAFETY: `name` is a valid C string, and the flags are valid.
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        // SAFETY: `memfd_create` returned a new file descriptor which nothing
+        // else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let error = SharedMemory::from_fd(fd).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let memory = SharedMemory::create("sealed", 10).unwrap();
+        let memory = SharedMemory::from_fd(OwnedFd::from(memory)).unwrap();
+        assert_eq!(memory.size(), 10);
+    }
+
+    #[test]
+    fn test_from_fd_rejects_other_files() {
+        // A regular file could be truncated by the process which sent it.
+        let file = File::open(std::env::current_exe().unwrap()).unwrap();
+        let error = SharedMemory::from_fd(file.into()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Other character devices must not be sent ashmem ioctls.
+        let device = File::open("/dev/null").unwrap();
+        let error = SharedMemory::from_fd(device.into()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parcel() {
+        let mut memory = SharedMemory::create("test", 100).unwrap();
+        memory.map_mut().unwrap().write_all_at(&[7], 99).unwrap();
+
+        let mut parcel = Parcel::new();
+        parcel.write(&memory).unwrap();
+        parcel.write(&None::<SharedMemory>).unwrap();
+        // SAFETY: 0 is always a valid position.
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        let copy: SharedMemory = parcel.read().unwrap();
+        assert_eq!(copy.size(), 100);
+        assert_eq!(copy.map().unwrap().to_vec()[99], 7);
+        assert_eq!(parcel.read::<Option<SharedMemory>>().map(|m| m.is_none()), Ok(true));
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Graceful shutdown of the local services in a process.
+//!
+//! A service process which wants to exit without failing the calls it is
+//! handling calls [`begin`], and exits once it returns:
+//!
+//! ```ignore
+//! let outcome = binder::shutdown::begin(Duration::from_secs(5));
+//! if outcome.unfinished_transactions > 0 {
+//!     log::warn!("Exiting with {} transactions in flight", outcome.unfinished_transactions);
+//! }
+//! std::process::exit(0);
+//! ```
+
+use crate::sys;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+// Only used to wait for `IN_FLIGHT` to drop; the count itself is atomic so
+// that handling a transaction doesn't take a process-wide lock.
+static IDLE_LOCK: Mutex<()> = Mutex::new(());
+static IDLE: Condvar = Condvar::new();
+
+thread_local! {
+    // The number of transactions this thread is handling, which is more than
+    // one for nested incoming calls.
+    static HANDLING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The result of [`begin`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShutdownOutcome {
+    /// The number of transactions still being handled when the timeout
+    /// expired, not counting any the calling thread is handling itself.
+    pub unfinished_transactions: usize,
+    /// Whether the lazy services of this process were unregistered from the
+    /// service manager. This is false if some clients still hold them, or the
+    /// process has no lazy services.
+    pub lazy_services_unregistered: bool,
+}
+
+/// Start shutting down the local services in this process.
+///
+/// From now on, every local binder in the process rejects new transactions
+/// with `DEAD_OBJECT`, which clients such as [`BinderClient`] treat as the
+/// service having gone away: they look it up again and retry. This then waits
+/// up to `timeout` for the transactions already being handled to finish, and
+/// tries to unregister the process's lazy services.
+///
+/// Services registered with [`add_service`] can't be unregistered explicitly;
+/// the service manager drops them when the process exits, which the caller
+/// should do as soon as this returns. Shutdown can't be cancelled.
+///
+/// This may be called from a transaction handler, in which case it doesn't
+/// wait for the transactions the calling thread is handling.
+///
+/// [`BinderClient`]: crate::BinderClient
+/// [`add_service`]: crate::add_service
+pub fn begin(timeout: Duration) -> ShutdownOutcome {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+
+    let deadline = Instant::now() + timeout;
+    let own = HANDLING.with(Cell::get);
+    let mut guard = IDLE_LOCK.lock().unwrap();
+    loop {
+        let in_flight = IN_FLIGHT.load(Ordering::SeqCst);
+        let now = Instant::now();
+        if in_flight <= own || now >= deadline {
+            drop(guard);
+            return ShutdownOutcome {
+                unfinished_transactions: in_flight.saturating_sub(own),
+                // Safety: This method is always safe to call.
+                lazy_services_unregistered: unsafe { sys::AServiceManager_tryUnregister() },
+            };
+        }
+        guard = IDLE.wait_timeout(guard, deadline - now).unwrap().0;
+    }
+}
+
+/// Whether [`begin`] has been called in this process.
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Acquire)
+}
+
+/// Marks a transaction being handled by a local binder, until dropped.
+pub(crate) struct InFlightTransaction {
+    _private: (),
+}
+
+/// Called by local binders before handling a transaction. Returns `None` if
+/// the process is shutting down and the transaction should be rejected.
+pub(crate) fn start_transaction() -> Option<InFlightTransaction> {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    HANDLING.with(|handling| handling.set(handling.get() + 1));
+    let transaction = InFlightTransaction { _private: () };
+    // `begin` sets the flag before reading the count, so either it sees this
+    // transaction or we see the flag.
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(transaction)
+}
+
+impl Drop for InFlightTransaction {
+    fn drop(&mut self) {
+        HANDLING.with(|handling| handling.set(handling.get() - 1));
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            // Taking the lock makes sure `begin` is either waiting already,
+            // or will see the new count before it waits.
+            let _guard = IDLE_LOCK.lock().unwrap();
+            IDLE.notify_all();
+        }
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Local services whose implementation can be replaced while they are in use.
+
+use crate::binder::{
+    FromIBinder, Interface, InterfaceClass, Remotable, Stability, Strong, TransactionCode,
+};
+use crate::error::Result;
+use crate::native::Binder;
+use crate::parcel::BorrowedParcel;
+use crate::proxy::SpIBinder;
+use crate::sys;
+
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The remotable object behind a [`SwappableBinder`], which forwards every
+/// transaction to the current implementation.
+struct Slot<R: Remotable>(RwLock<Arc<Binder<R>>>);
+
+impl<R: Remotable> Slot<R> {
+    fn current(&self) -> Arc<Binder<R>> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+// The classes of `Slot<R>` for each `R`, as a generic type can't have its own
+// static.
+static SLOT_CLASSES: Mutex<BTreeMap<TypeId, usize>> = Mutex::new(BTreeMap::new());
+
+impl<R: Remotable> Remotable for Slot<R> {
+    fn get_descriptor() -> &'static str {
+        R::get_descriptor()
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        // Holding our own reference lets the implementation be swapped while
+        // this transaction is still being handled by the old one.
+        R::on_transact(&self.current(), code, data, reply)
+    }
+
+    fn on_dump(&self, file: &mut dyn Write, args: &[&CStr]) -> Result<()> {
+        R::on_dump(&self.current(), file, args)
+    }
+
+    fn get_class() -> InterfaceClass {
+        let mut classes = SLOT_CLASSES.lock().unwrap();
+        let class = *classes.entry(TypeId::of::<R>()).or_insert_with(|| {
+            let class: *const sys::AIBinder_Class = InterfaceClass::new::<Binder<Self>>().into();
+            class as usize
+        });
+        // Safety: The pointer was returned by `AIBinder_Class_define`, and
+        // classes are never freed.
+        unsafe { InterfaceClass::from_ptr(class as *const sys::AIBinder_Class) }
+    }
+}
+
+/// A local binder object whose implementation can be replaced at any time,
+/// without clients noticing other than by the change in behaviour.
+///
+/// This is useful for services which reinitialize themselves when their
+/// configuration changes, or switch between implementations for an
+/// experiment, and don't want to make every client look the service up again.
+///
+/// `R` is the native type of the interface, e.g. `BnFoo`, and each
+/// implementation is a local service of that type:
+///
+/// ```ignore
+/// let service = SwappableBinder::<BnFoo>::new(&BnFoo::new_binder(FooV1, features))?;
+/// binder::add_service("foo", service.as_binder())?;
+/// // Later:
+/// service.swap(&BnFoo::new_binder(FooV2::new(config), features))?;
+/// ```
+///
+/// Only the binder returned by [`as_binder`](Interface::as_binder) should be
+/// handed out; the implementation binders are still separate objects. In this
+/// process, the interface obtained from it is a proxy which makes
+/// transactions rather than calling the implementation directly.
+pub struct SwappableBinder<R: Remotable> {
+    binder: Binder<Slot<R>>,
+}
+
+impl<R: Remotable> SwappableBinder<R> {
+    /// Create a binder object with the default stability which initially
+    /// forwards transactions to `initial`.
+    ///
+    /// Fails with `BAD_TYPE` if `initial` is not a local service of type `R`.
+    pub fn new<I: FromIBinder + ?Sized>(initial: &Strong<I>) -> Result<Self> {
+        Self::new_with_stability(initial, Stability::default())
+    }
+
+    /// Create a binder object with the given stability which initially
+    /// forwards transactions to `initial`.
+    ///
+    /// This should be the stability the interface is declared with, as that
+    /// of the implementations doesn't apply to this binder.
+    pub fn new_with_stability<I: FromIBinder + ?Sized>(
+        initial: &Strong<I>,
+        stability: Stability,
+    ) -> Result<Self> {
+        let slot = Slot(RwLock::new(Arc::new(local_service(initial)?)));
+        Ok(Self { binder: Binder::new_with_stability(slot, stability) })
+    }
+
+    /// Forward all transactions that arrive from now on to `implementation`.
+    ///
+    /// Transactions which the previous implementation is already handling
+    /// run to completion. Fails with `BAD_TYPE` if `implementation` is not a
+    /// local service of type `R`, in which case the current implementation
+    /// is kept.
+    pub fn swap<I: FromIBinder + ?Sized>(&self, implementation: &Strong<I>) -> Result<()> {
+        let implementation = Arc::new(local_service(implementation)?);
+        *self.binder.0.write().unwrap() = implementation;
+        Ok(())
+    }
+}
+
+impl<R: Remotable> Interface for SwappableBinder<R> {
+    fn as_binder(&self) -> SpIBinder {
+        self.binder.as_binder()
+    }
+}
+
+fn local_service<R, I>(service: &Strong<I>) -> Result<Binder<R>>
+where
+    R: Remotable,
+    I: FromIBinder + ?Sized,
+{
+    Binder::try_from(service.as_binder())
+}
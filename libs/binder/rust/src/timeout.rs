@@ -0,0 +1,246 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A process-wide timeout for synchronous calls to remote services.
+//!
+//! A binder transaction can't be cancelled once it has been sent, so a service
+//! which never replies blocks the calling thread forever. With a timeout, the
+//! transaction is sent from one of a bounded pool of helper threads instead,
+//! and the caller gets `TIMED_OUT` if the reply doesn't arrive in time:
+//!
+//! ```ignore
+//! binder::set_default_transaction_timeout(Some(Duration::from_secs(5)));
+//!
+//! // This one is known to be slow.
+//! let report = binder::with_transaction_timeout(Some(Duration::from_secs(30)), || {
+//!     diagnostics.collectReport()
+//! })?;
+//! ```
+//!
+//! A transaction which times out keeps running on its helper thread, and its
+//! reply is dropped when it arrives, so it still ties up a thread in the
+//! service. Only the caller is freed. Once every helper thread is waiting for
+//! a reply, and as many transactions again are waiting for a helper thread,
+//! further timed calls fail with `WOULD_BLOCK` without being sent.
+//!
+//! Oneway transactions and transactions to local services are never timed.
+//!
+//! Nor are transactions sent from a binder thread while it handles an
+//! incoming transaction, which covers every call a service makes from its
+//! `on_transact`. The kernel sends nested calls back to the thread waiting
+//! for a reply, which a helper thread would not be, so moving these calls to
+//! one could deadlock them. A service which calls out on a binder thread has
+//! to bound the time it spends itself, e.g. by handing the work to a thread
+//! of its own.
+
+use crate::binder::{AsNative, TransactionCode, TransactionFlags, FLAG_ONEWAY};
+use crate::error::{Result, StatusCode};
+use crate::parcel::Parcel;
+use crate::proxy::{transact_raw, SpIBinder};
+use crate::service::is_handling_transaction;
+use crate::sys;
+use crate::workers::WorkerPool;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+/// The helper threads which send timed transactions.
+static WORKERS: WorkerPool = WorkerPool::new("binder_timeout", 16);
+
+/// The default timeout in nanoseconds, or 0 if there is none.
+static DEFAULT_TIMEOUT_NANOS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // The timeout set by the innermost `with_transaction_timeout` scope, if
+    // any, which takes precedence over the default.
+    static TIMEOUT_OVERRIDE: Cell<Option<Option<Duration>>> = const { Cell::new(None) };
+}
+
+/// Set the timeout for synchronous calls to remote services made by any
+/// thread of this process, or `None` to wait for replies however long they
+/// take, which is the default.
+///
+/// A zero timeout is treated as `None`. Calls made while handling a
+/// transaction on a binder thread are not timed; see the module
+/// documentation.
+pub fn set_default_transaction_timeout(timeout: Option<Duration>) {
+    let nanos = timeout.map_or(0, |timeout| timeout.as_nanos().try_into().unwrap_or(u64::MAX));
+    DEFAULT_TIMEOUT_NANOS.store(nanos, Ordering::Relaxed);
+}
+
+/// The timeout set with [`set_default_transaction_timeout`], if any.
+pub fn default_transaction_timeout() -> Option<Duration> {
+    match DEFAULT_TIMEOUT_NANOS.load(Ordering::Relaxed) {
+        0 => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+/// Run `f` with `timeout` instead of the default for the calls it makes on
+/// this thread. `None` disables the timeout for them.
+pub fn with_transaction_timeout<T>(timeout: Option<Duration>, f: impl FnOnce() -> T) -> T {
+    let _scope =
+        OverrideScope { previous: TIMEOUT_OVERRIDE.with(|current| current.replace(Some(timeout))) };
+    f()
+}
+
+/// Restores the previous override of the thread when dropped.
+struct OverrideScope {
+    previous: Option<Option<Duration>>,
+}
+
+impl Drop for OverrideScope {
+    fn drop(&mut self) {
+        TIMEOUT_OVERRIDE.with(|current| current.set(self.previous));
+    }
+}
+
+/// The timeout which applies to calls made by the current thread.
+fn current_timeout() -> Option<Duration> {
+    TIMEOUT_OVERRIDE
+        .with(Cell::get)
+        .unwrap_or_else(default_transaction_timeout)
+        .filter(|timeout| !timeout.is_zero())
+}
+
+/// The timeout for a transaction with `flags` sent to `binder` now, if it
+/// should be timed.
+///
+/// # Safety
+///
+/// `binder` must be a valid pointer to an `AIBinder` for the duration of the
+/// call.
+pub(crate) unsafe fn timeout_for(
+    binder: *const sys::AIBinder,
+    flags: TransactionFlags,
+) -> Option<Duration> {
+    if flags & FLAG_ONEWAY != 0 {
+        return None;
+    }
+    let timeout = current_timeout()?;
+    // Safety: Our caller guarantees that `binder` is valid.
+    let is_remote = unsafe { sys::AIBinder_isRemote(binder) };
+    (is_remote && !is_handling_transaction()).then_some(timeout)
+}
+
+/// Send a transaction directly to `binder` from a helper thread, failing with
+/// `TIMED_OUT` if there is no reply within `timeout`, or `WOULD_BLOCK` if
+/// there are too many timed transactions already.
+///
+/// # Safety
+///
+/// `binder` must be a valid pointer to an `AIBinder` for the duration of the
+/// call.
+pub(crate) unsafe fn transact_with_timeout(
+    binder: *const sys::AIBinder,
+    code: TransactionCode,
+    data: Parcel,
+    flags: TransactionFlags,
+    timeout: Duration,
+) -> Result<Parcel> {
+    // Safety: Our caller guarantees that `binder` is valid, and the reference
+    // we add here is taken over by the `SpIBinder`, which keeps the binder
+    // alive on the helper thread for as long as it needs it.
+    let binder = unsafe {
+        sys::AIBinder_incStrong(binder as *mut sys::AIBinder);
+        SpIBinder::from_raw(binder as *mut sys::AIBinder)
+    }
+    .ok_or(StatusCode::UNEXPECTED_NULL)?;
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let job_abandoned = abandoned.clone();
+    WORKERS.execute(move || {
+        // The caller may have timed out while this waited for a helper
+        // thread, in which case the transaction isn't sent at all.
+        if job_abandoned.load(Ordering::Relaxed) {
+            return;
+        }
+        // Safety: `binder` is a valid `AIBinder`, which it keeps alive.
+        let result = unsafe { transact_raw(binder.as_native(), code, data, flags) };
+        // The caller may have timed out since, in which case the reply is
+        // dropped.
+        let _ = sender.send(result);
+    })?;
+    receiver.recv_timeout(timeout).unwrap_or_else(|_| {
+        abandoned.store(true, Ordering::Relaxed);
+        Err(StatusCode::TIMED_OUT)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder::{IBinderInternal, Interface, Remotable};
+    use crate::native::Binder;
+    use crate::parcel::BorrowedParcel;
+    use std::ffi::CStr;
+    use std::io::Write;
+    use std::thread;
+
+    struct Service(Duration);
+
+    impl Remotable for Service {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.ISlow"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            thread::sleep(self.0);
+            reply.write(&42i32)
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    fn transact(binder: &SpIBinder, timeout: Duration) -> Result<i32> {
+        let data = binder.prepare_transact()?;
+        // Safety: `binder` is a valid `AIBinder` for the duration of the call.
+        let reply = unsafe { transact_with_timeout(binder.as_native(), 1, data, 0, timeout) }?;
+        reply.read()
+    }
+
+    #[test]
+    fn overrides_nest_and_restore() {
+        assert_eq!(current_timeout(), default_transaction_timeout());
+        with_transaction_timeout(Some(Duration::from_secs(1)), || {
+            assert_eq!(current_timeout(), Some(Duration::from_secs(1)));
+            with_transaction_timeout(None, || assert_eq!(current_timeout(), None));
+            assert_eq!(current_timeout(), Some(Duration::from_secs(1)));
+        });
+        with_transaction_timeout(Some(Duration::ZERO), || assert_eq!(current_timeout(), None));
+        assert_eq!(current_timeout(), default_transaction_timeout());
+    }
+
+    #[test]
+    fn slow_reply_times_out() {
+        let slow = Binder::new(Service(Duration::from_millis(500))).as_binder();
+        assert_eq!(transact(&slow, Duration::from_millis(50)), Err(StatusCode::TIMED_OUT));
+
+        let fast = Binder::new(Service(Duration::ZERO)).as_binder();
+        assert_eq!(transact(&fast, Duration::from_secs(10)), Ok(42));
+    }
+}
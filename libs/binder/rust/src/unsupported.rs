@@ -0,0 +1,299 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fallback implementations of the service manager and process state APIs
+//! for targets without kernel binder IPC (e.g. Trusty).
+//!
+//! These mirror the public surface of the `service` and `state` modules so
+//! that code shared between device daemons and other targets compiles
+//! unchanged. Every operation that would need the kernel driver or the
+//! service manager fails at runtime instead: lookups return `None`,
+//! fallible calls return [`StatusCode::INVALID_OPERATION`], and queries
+//! about the current transaction report that there is none.
+//!
+//! [`StatusCode::INVALID_OPERATION`]: crate::StatusCode::INVALID_OPERATION
+
+pub mod service {
+    use crate::binder::{FromIBinder, Strong};
+    use crate::error::{Result, StatusCode};
+    use crate::proxy::SpIBinder;
+    use std::fmt;
+    use std::time::Duration;
+
+    /// Register a new service with the default service manager.
+    ///
+    /// There is no service manager on this target, so this always returns
+    /// `Err(StatusCode::INVALID_OPERATION)`.
+    pub fn add_service(_identifier: &str, _binder: SpIBinder) -> Result<()> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// The priority at which `dumpsys` dumps a service.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum DumpPriority {
+        /// The default dump priority.
+        #[default]
+        Default,
+        /// Dumped first, with a short timeout, in bugreports.
+        Critical,
+        /// Dumped before normal priority services.
+        High,
+        /// Dumped with the bulk of services.
+        Normal,
+    }
+
+    /// Options for registering a service.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct AddServiceOptions {
+        /// Allow processes running as `AID_ISOLATED` to get this service.
+        pub allow_isolated: bool,
+        /// The dumpsys priority of the service.
+        pub dump_priority: DumpPriority,
+        #[doc(hidden)]
+        pub _non_exhaustive: (),
+    }
+
+    /// Register a new service with the default service manager, using the given
+    /// options.
+    ///
+    /// There is no service manager on this target, so this always returns
+    /// `Err(StatusCode::INVALID_OPERATION)`.
+    pub fn add_service_with_options(
+        _identifier: &str,
+        _binder: SpIBinder,
+        _options: AddServiceOptions,
+    ) -> Result<()> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Register several services with the default service manager.
+    ///
+    /// Every service fails with `StatusCode::INVALID_OPERATION` on this target.
+    pub fn add_services(
+        services: &[(&str, SpIBinder, AddServiceOptions)],
+    ) -> std::result::Result<(), Vec<(String, StatusCode)>> {
+        if services.is_empty() {
+            Ok(())
+        } else {
+            Err(services
+                .iter()
+                .map(|(identifier, _, _)| (identifier.to_string(), StatusCode::INVALID_OPERATION))
+                .collect())
+        }
+    }
+
+    /// Register a dynamic service via the LazyServiceRegistrar.
+    ///
+    /// There is no service manager on this target, so this always returns
+    /// `Err(StatusCode::INVALID_OPERATION)`.
+    pub fn register_lazy_service(_identifier: &str, _binder: SpIBinder) -> Result<()> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Prevent a process which registers lazy services from being shut down.
+    ///
+    /// Lazy services cannot be registered on this target, so this does nothing.
+    pub fn force_lazy_services_persist(_persist: bool) {}
+
+    /// An RAII object to ensure a process which registers lazy services is not killed.
+    ///
+    /// Lazy services cannot be registered on this target, so this guard has no
+    /// effect.
+    #[must_use]
+    #[derive(Debug, Clone, Default)]
+    pub struct LazyServiceGuard {
+        // Prevent construction outside this module.
+        _private: (),
+    }
+
+    impl LazyServiceGuard {
+        /// Create a new LazyServiceGuard.
+        pub fn new() -> Self {
+            Self { _private: () }
+        }
+    }
+
+    /// Describes why [`wait_for_service_manager`] gave up.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ServiceManagerUnavailable {
+        /// The binder driver node that was checked.
+        pub driver: &'static str,
+        /// Whether the driver node existed when the wait ended.
+        pub driver_present: bool,
+        /// How long was spent waiting.
+        pub waited: Duration,
+    }
+
+    impl fmt::Display for ServiceManagerUnavailable {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "no binder driver or service manager on this target")
+        }
+    }
+
+    impl std::error::Error for ServiceManagerUnavailable {}
+
+    impl From<ServiceManagerUnavailable> for StatusCode {
+        fn from(_: ServiceManagerUnavailable) -> StatusCode {
+            StatusCode::NO_INIT
+        }
+    }
+
+    /// Block until the binder driver and the service manager are usable.
+    ///
+    /// Neither will ever be available on this target, so this fails
+    /// immediately without waiting.
+    pub fn wait_for_service_manager(
+        _timeout: Duration,
+    ) -> std::result::Result<(), ServiceManagerUnavailable> {
+        Err(ServiceManagerUnavailable { driver: "", driver_present: false, waited: Duration::ZERO })
+    }
+
+    /// Determine whether the current thread is currently executing an incoming
+    /// transaction.
+    ///
+    /// Always `false` on this target.
+    pub fn is_handling_transaction() -> bool {
+        false
+    }
+
+    /// Retrieve an existing service, blocking for a few seconds if it doesn't yet
+    /// exist.
+    ///
+    /// Always `None` on this target.
+    #[deprecated = "this polls 5s, use wait_for_service or check_service"]
+    pub fn get_service(_name: &str) -> Option<SpIBinder> {
+        None
+    }
+
+    /// Retrieve an existing service. Returns `None` immediately if the service is not available.
+    ///
+    /// Always `None` on this target.
+    pub fn check_service(_name: &str) -> Option<SpIBinder> {
+        None
+    }
+
+    /// Retrieve an existing service, or start it if it is configured as a dynamic
+    /// service and isn't yet started.
+    ///
+    /// Always `None` on this target.
+    pub fn wait_for_service(_name: &str) -> Option<SpIBinder> {
+        None
+    }
+
+    /// Retrieve an existing service for a particular interface, blocking for a few
+    /// seconds if it doesn't yet exist.
+    ///
+    /// Always returns `Err(StatusCode::INVALID_OPERATION)` on this target.
+    #[deprecated = "this polls 5s, use wait_for_interface or check_interface"]
+    pub fn get_interface<T: FromIBinder + ?Sized>(_name: &str) -> Result<Strong<T>> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Retrieve an existing service for a particular interface.
+    ///
+    /// Always returns `Err(StatusCode::INVALID_OPERATION)` on this target.
+    pub fn check_interface<T: FromIBinder + ?Sized>(_name: &str) -> Result<Strong<T>> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Retrieve an existing service for a particular interface, or start it if it
+    /// is configured as a dynamic service and isn't yet started.
+    ///
+    /// Always returns `Err(StatusCode::INVALID_OPERATION)` on this target.
+    pub fn wait_for_interface<T: FromIBinder + ?Sized>(_name: &str) -> Result<Strong<T>> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Check if a service is declared (e.g. in a VINTF manifest)
+    ///
+    /// Always returns `Err(StatusCode::INVALID_OPERATION)` on this target.
+    pub fn is_declared(_interface: &str) -> Result<bool> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
+    /// Retrieve all declared instances for a particular interface
+    ///
+    /// Always returns `Err(StatusCode::INVALID_OPERATION)` on this target.
+    pub fn get_declared_instances(_interface: &str) -> Result<Vec<String>> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+}
+
+pub mod state {
+    use libc::{pid_t, uid_t};
+
+    /// Static utility functions to manage Binder process state.
+    ///
+    /// There is no kernel binder thread pool on this target, so there is no
+    /// `join_thread_pool` either: it could only panic or block forever, and
+    /// services here must run their target's own event loop instead.
+    pub struct ProcessState;
+
+    impl ProcessState {
+        /// Starts the Binder IPC thread pool.
+        ///
+        /// Does nothing on this target.
+        pub fn start_thread_pool() {}
+
+        /// Sets the maximum number of threads that can be started in the
+        /// threadpool.
+        ///
+        /// Does nothing on this target.
+        pub fn set_thread_pool_max_thread_count(_num_threads: u32) {}
+    }
+
+    /// Static utility functions to manage Binder thread state.
+    ///
+    /// No kernel binder transactions are ever handled on this target, so
+    /// there is no `get_calling_uid` either: there is no caller whose UID it
+    /// could return, and no value that couldn't be mistaken for one.
+    pub struct ThreadState;
+
+    impl ThreadState {
+        /// Stands in for the calling UID within this crate, e.g. to key
+        /// per-caller state, where every transaction is treated as coming from
+        /// the same caller. `uid_t::MAX` (i.e. `-1`) does not correspond to any
+        /// real user.
+        pub(crate) fn get_calling_uid() -> uid_t {
+            uid_t::MAX
+        }
+
+        /// Returns the calling PID.
+        ///
+        /// There is never a calling process on this target, so this returns 0.
+        pub fn get_calling_pid() -> pid_t {
+            0
+        }
+
+        /// Determine whether the current thread is currently executing an incoming transaction.
+        ///
+        /// Always `false` on this target.
+        pub fn is_handling_transaction() -> bool {
+            false
+        }
+
+        /// Calls `check_permission` with the client's security context.
+        ///
+        /// There is never a security context on this target, so the callback
+        /// always receives `None`.
+        pub fn with_calling_sid<T, F>(check_permission: F) -> T
+        where
+            for<'a> F: FnOnce(Option<&'a std::ffi::CStr>) -> T,
+        {
+            check_permission(None)
+        }
+    }
+}
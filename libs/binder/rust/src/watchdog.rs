@@ -0,0 +1,374 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Periodic health checks of the services a process depends on.
+
+use crate::binder::IBinder;
+use crate::proxy::SpIBinder;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The health of a service watched by a [`ServiceWatchdog`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthState {
+    /// The service answered its last ping.
+    Healthy,
+    /// The service failed to answer a ping, but not yet often enough in a
+    /// row to be degraded.
+    Failing(u32),
+    /// The service failed enough pings in a row, and recovery didn't replace
+    /// it with one which answers.
+    Degraded,
+}
+
+type Recovery = Box<dyn Fn(&str) -> Option<SpIBinder> + Send + Sync>;
+type Ping = Box<dyn Fn(&SpIBinder) -> bool + Send + Sync>;
+
+struct Dependency {
+    binder: SpIBinder,
+    state: HealthState,
+    recovery: Option<Arc<Recovery>>,
+}
+
+struct Inner {
+    interval: Duration,
+    failure_threshold: u32,
+    dependencies: Mutex<BTreeMap<String, Dependency>>,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+    ping: Ping,
+}
+
+/// Pings a set of services a process depends on at a fixed interval, from a
+/// thread of its own.
+///
+/// A service which fails `failure_threshold` pings in a row is marked
+/// [`Degraded`](HealthState::Degraded) and has its recovery callback called,
+/// if it was given one. The callback can reconnect, e.g. by looking the
+/// service up again, and return the new binder to watch instead. Until the
+/// service answers a ping or the callback returns a replacement which does,
+/// it stays degraded and the callback is called again on every check, so that
+/// the process can report or work around the missing dependency meanwhile:
+///
+/// ```ignore
+/// let watchdog = ServiceWatchdog::new(Duration::from_secs(10), 3);
+/// watchdog.watch_with_recovery("android.hardware.foo.IFoo/default", foo, |name| {
+///     binder::check_service(name)
+/// });
+/// if watchdog.state("android.hardware.foo.IFoo/default") == Some(HealthState::Degraded) {
+///     // Fall back to something else.
+/// }
+/// ```
+///
+/// The thread stops when the watchdog is dropped.
+pub struct ServiceWatchdog {
+    inner: Arc<Inner>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for ServiceWatchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceWatchdog")
+            .field("interval", &self.inner.interval)
+            .field("failure_threshold", &self.inner.failure_threshold)
+            .field("states", &self.states())
+            .finish()
+    }
+}
+
+impl ServiceWatchdog {
+    /// Start a watchdog which pings every `interval`, and considers a service
+    /// degraded after `failure_threshold` failed pings in a row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure_threshold` is 0.
+    pub fn new(interval: Duration, failure_threshold: u32) -> Self {
+        let ping = |binder: &SpIBinder| binder.clone().ping_binder().is_ok();
+        Self::with_ping(interval, failure_threshold, Box::new(ping))
+    }
+
+    fn with_ping(interval: Duration, failure_threshold: u32, ping: Ping) -> Self {
+        assert!(failure_threshold > 0, "The failure threshold must be at least 1");
+        let inner = Arc::new(Inner {
+            interval,
+            failure_threshold,
+            dependencies: Mutex::new(BTreeMap::new()),
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+            ping,
+        });
+        let thread = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("binder_watchdog".to_owned())
+                .spawn(move || inner.run())
+                .expect("Failed to start watchdog thread")
+        };
+        Self { inner, thread: Some(thread) }
+    }
+
+    /// Watch `binder` under `name`, replacing anything already watched under
+    /// that name. It is marked degraded once it fails enough pings.
+    pub fn watch(&self, name: &str, binder: SpIBinder) {
+        self.insert(name, binder, None);
+    }
+
+    /// Watch `binder` under `name`, calling `recovery` with the name once it
+    /// fails enough pings. If `recovery` returns a binder, that is watched
+    /// instead.
+    pub fn watch_with_recovery<F>(&self, name: &str, binder: SpIBinder, recovery: F)
+    where
+        F: Fn(&str) -> Option<SpIBinder> + Send + Sync + 'static,
+    {
+        self.insert(name, binder, Some(Arc::new(Box::new(recovery))));
+    }
+
+    /// Stop watching the service under `name`.
+    pub fn unwatch(&self, name: &str) {
+        self.inner.dependencies.lock().unwrap().remove(name);
+    }
+
+    /// The health of the service watched under `name`, if any.
+    pub fn state(&self, name: &str) -> Option<HealthState> {
+        self.inner.dependencies.lock().unwrap().get(name).map(|dependency| dependency.state)
+    }
+
+    /// The health of every watched service, by name.
+    pub fn states(&self) -> BTreeMap<String, HealthState> {
+        let dependencies = self.inner.dependencies.lock().unwrap();
+        dependencies.iter().map(|(name, dependency)| (name.clone(), dependency.state)).collect()
+    }
+
+    /// The names of the degraded services.
+    pub fn degraded(&self) -> Vec<String> {
+        let dependencies = self.inner.dependencies.lock().unwrap();
+        dependencies
+            .iter()
+            .filter(|(_, dependency)| dependency.state == HealthState::Degraded)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Ping every watched service now, on the calling thread, rather than
+    /// waiting for the next interval.
+    pub fn check_now(&self) {
+        self.inner.check();
+    }
+
+    fn insert(&self, name: &str, binder: SpIBinder, recovery: Option<Arc<Recovery>>) {
+        let dependency = Dependency { binder, state: HealthState::Healthy, recovery };
+        self.inner.dependencies.lock().unwrap().insert(name.to_owned(), dependency);
+    }
+}
+
+impl Drop for ServiceWatchdog {
+    fn drop(&mut self) {
+        *self.inner.stopped.lock().unwrap() = true;
+        self.inner.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // The thread only panics if a callback did, which was reported
+            // already.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Inner {
+    fn run(&self) {
+        loop {
+            let stopped = self.stopped.lock().unwrap();
+            let (stopped, _) =
+                self.wake.wait_timeout_while(stopped, self.interval, |stopped| !*stopped).unwrap();
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+            self.check();
+        }
+    }
+
+    fn check(&self) {
+        // Ping without holding the lock, as pings can block.
+        let binders: Vec<(String, SpIBinder)> = {
+            let dependencies = self.dependencies.lock().unwrap();
+            dependencies.iter().map(|(name, d)| (name.clone(), d.binder.clone())).collect()
+        };
+        for (name, binder) in binders {
+            let healthy = (self.ping)(&binder);
+            let recovery = {
+                let mut dependencies = self.dependencies.lock().unwrap();
+                // Skip services which were unwatched or replaced meanwhile.
+                let Some(dependency) = dependencies.get_mut(&name) else {
+                    continue;
+                };
+                if dependency.binder != binder {
+                    continue;
+                }
+                dependency.state = match (healthy, dependency.state) {
+                    (true, _) => HealthState::Healthy,
+                    (false, HealthState::Degraded) => HealthState::Degraded,
+                    (false, HealthState::Healthy) => HealthState::Failing(1),
+                    (false, HealthState::Failing(failures)) => HealthState::Failing(failures + 1),
+                };
+                if let HealthState::Failing(failures) = dependency.state {
+                    if failures >= self.failure_threshold {
+                        dependency.state = HealthState::Degraded;
+                    }
+                }
+                // Keep trying to recover degraded services on every check, as
+                // a replacement may only come up later.
+                if dependency.state == HealthState::Degraded {
+                    dependency.recovery.clone()
+                } else {
+                    None
+                }
+            };
+            let Some(recovery) = recovery else {
+                continue;
+            };
+            // Recover and ping the replacement without holding the lock, as
+            // either may block, and the callback may use the watchdog.
+            let Some(replacement) = recovery(&name).filter(|replacement| (self.ping)(replacement))
+            else {
+                continue;
+            };
+            let mut dependencies = self.dependencies.lock().unwrap();
+            if let Some(dependency) = dependencies.get_mut(&name) {
+                if dependency.binder == binder {
+                    dependency.binder = replacement;
+                    dependency.state = HealthState::Healthy;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder::{Interface, Remotable, TransactionCode};
+    use crate::error::Result;
+    use crate::native::Binder;
+    use crate::parcel::BorrowedParcel;
+    use std::ffi::CStr;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    struct Service;
+
+    impl Remotable for Service {
+        fn get_descriptor() -> &'static str {
+            "android.os.test.IWatched"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    /// A watchdog which only checks when asked, whose pings of `failing`
+    /// binders fail while `failing` is set.
+    fn watchdog(failing: Arc<AtomicBool>, broken: SpIBinder) -> ServiceWatchdog {
+        ServiceWatchdog::with_ping(
+            Duration::from_secs(3600),
+            2,
+            Box::new(move |binder| !(failing.load(Ordering::SeqCst) && *binder == broken)),
+        )
+    }
+
+    #[test]
+    fn failures_degrade_until_pings_succeed() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let binder = Binder::new(Service).as_binder();
+        let watchdog = watchdog(failing.clone(), binder.clone());
+        watchdog.watch("foo", binder);
+
+        watchdog.check_now();
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Failing(1)));
+        watchdog.check_now();
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Degraded));
+        assert_eq!(watchdog.degraded(), ["foo"]);
+
+        failing.store(false, Ordering::SeqCst);
+        watchdog.check_now();
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Healthy));
+    }
+
+    #[test]
+    fn recovery_replaces_failing_binder() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let binder = Binder::new(Service).as_binder();
+        let replacement = Binder::new(Service).as_binder();
+        let watchdog = watchdog(failing, binder.clone());
+        let recoveries = Arc::new(AtomicUsize::new(0));
+        let counter = recoveries.clone();
+        let replacement_clone = replacement.clone();
+        watchdog.watch_with_recovery("foo", binder, move |name| {
+            assert_eq!(name, "foo");
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(replacement_clone.clone())
+        });
+
+        watchdog.check_now();
+        watchdog.check_now();
+        assert_eq!(recoveries.load(Ordering::SeqCst), 1);
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Healthy));
+        watchdog.check_now();
+        assert_eq!(recoveries.load(Ordering::SeqCst), 1);
+        assert_eq!(watchdog.inner.dependencies.lock().unwrap()["foo"].binder, replacement);
+    }
+
+    #[test]
+    fn recovery_is_retried_while_degraded() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let binder = Binder::new(Service).as_binder();
+        let replacement = Binder::new(Service).as_binder();
+        let watchdog = watchdog(failing, binder.clone());
+        let recoveries = Arc::new(AtomicUsize::new(0));
+        let counter = recoveries.clone();
+        let replacement_clone = replacement.clone();
+        watchdog.watch_with_recovery("foo", binder, move |_| {
+            // The replacement only comes up on the third attempt.
+            (counter.fetch_add(1, Ordering::SeqCst) >= 2).then(|| replacement_clone.clone())
+        });
+
+        watchdog.check_now();
+        watchdog.check_now();
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Degraded));
+        watchdog.check_now();
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Degraded));
+        watchdog.check_now();
+        assert_eq!(recoveries.load(Ordering::SeqCst), 3);
+        assert_eq!(watchdog.state("foo"), Some(HealthState::Healthy));
+        assert_eq!(watchdog.inner.dependencies.lock().unwrap()["foo"].binder, replacement);
+    }
+}
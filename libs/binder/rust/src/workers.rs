@@ -0,0 +1,159 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A bounded pool of helper threads, for blocking calls which the crate waits
+//! for with a timeout rather than making on the caller's thread.
+//!
+//! A binder call can't be cancelled once it has been sent, so a call which
+//! times out keeps its worker until the reply arrives. The pool bounds how
+//! many such calls there can be, rather than leaking a thread for each.
+
+use crate::error::{Result, StatusCode};
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// How long an idle worker waits for more work before exiting.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pool of up to `max_threads` workers, which are started as work arrives
+/// and exit once they have been idle for a while.
+///
+/// Once every worker is busy, up to `max_threads` jobs wait for one, and any
+/// more are rejected.
+pub(crate) struct WorkerPool {
+    name: &'static str,
+    max_threads: usize,
+    state: Mutex<State>,
+    // Signalled when a job is queued.
+    work_ready: Condvar,
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+impl WorkerPool {
+    /// Create a pool whose workers are named `name`.
+    pub(crate) const fn new(name: &'static str, max_threads: usize) -> Self {
+        Self {
+            name,
+            max_threads,
+            state: Mutex::new(State { queue: VecDeque::new(), threads: 0, idle: 0 }),
+            work_ready: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Run `job` on a worker, failing with `WOULD_BLOCK` if the pool is
+    /// full, or `NO_MEMORY` if a worker can't be started.
+    pub(crate) fn execute(&'static self, job: impl FnOnce() + Send + 'static) -> Result<()> {
+        let mut state = self.lock();
+        if state.idle > state.queue.len() {
+            state.queue.push_back(Box::new(job));
+            self.work_ready.notify_one();
+            return Ok(());
+        }
+        if state.threads < self.max_threads {
+            thread::Builder::new()
+                .name(self.name.to_owned())
+                .spawn(move || self.work())
+                .map_err(|_| StatusCode::NO_MEMORY)?;
+            state.threads += 1;
+            state.queue.push_back(Box::new(job));
+            return Ok(());
+        }
+        if state.queue.len() - state.idle < self.max_threads {
+            state.queue.push_back(Box::new(job));
+            return Ok(());
+        }
+        Err(StatusCode::WOULD_BLOCK)
+    }
+
+    /// The body of a worker thread.
+    fn work(&self) {
+        let mut state = self.lock();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.lock();
+                continue;
+            }
+            state.idle += 1;
+            let (guard, wait) = self.work_ready.wait_timeout(state, IDLE_TIMEOUT).unwrap();
+            state = guard;
+            state.idle -= 1;
+            if wait.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn threads(&self) -> usize {
+        self.lock().threads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn jobs_run_on_a_bounded_number_of_threads() {
+        static POOL: WorkerPool = WorkerPool::new("binder_test_pool", 2);
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        let (started, running) = mpsc::channel();
+        let job = |i| {
+            let blocked = blocked.clone();
+            let started = started.clone();
+            move || {
+                started.send(i).unwrap();
+                blocked.lock().unwrap().recv().unwrap();
+            }
+        };
+
+        // Two jobs take both workers, and two more wait for them.
+        POOL.execute(job(0)).unwrap();
+        POOL.execute(job(1)).unwrap();
+        let mut ran: Vec<i32> = running.iter().take(2).collect();
+        POOL.execute(job(2)).unwrap();
+        POOL.execute(job(3)).unwrap();
+        assert_eq!(POOL.threads(), 2);
+        assert_eq!(POOL.execute(|| ()), Err(StatusCode::WOULD_BLOCK));
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        ran.extend(running.iter().take(2));
+        ran.sort();
+        assert_eq!(ran, [0, 1, 2, 3]);
+        assert_eq!(POOL.threads(), 2);
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Benchmarks of the interface descriptor checks on the dispatch path.
+//!
+//! Usage: atest binderRustDescriptorBenchmark
+
+use binder::binder_impl::{
+    Binder, BorrowedParcel, IBinderInternal, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+};
+use binder::{declare_binder_interface, BinderFeatures, FromIBinder, Interface, StatusCode};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+pub trait IBench: Interface {}
+
+declare_binder_interface! {
+    IBench["android.os.IBench"] {
+        native: BnBench(on_transact),
+        proxy: BpBench,
+    }
+}
+
+/// Shares the descriptor of `IBench`, so that converting an `IBench` binder to
+/// it has to compare descriptors.
+pub trait IBenchSameDescriptor: Interface {}
+
+declare_binder_interface! {
+    IBenchSameDescriptor["android.os.IBench"] {
+        native: BnBenchSameDescriptor(on_transact),
+        proxy: BpBenchSameDescriptor,
+    }
+}
+
+fn on_transact<T: ?Sized>(
+    _service: &T,
+    _code: TransactionCode,
+    _data: &BorrowedParcel<'_>,
+    reply: &mut BorrowedParcel<'_>,
+) -> Result<(), StatusCode> {
+    reply.write(&0i32)
+}
+
+struct BenchService;
+
+impl Interface for BenchService {}
+impl IBench for BenchService {}
+impl IBench for BpBench {}
+impl IBench for Binder<BnBench> {}
+impl IBenchSameDescriptor for BpBenchSameDescriptor {}
+impl IBenchSameDescriptor for Binder<BnBenchSameDescriptor> {}
+
+fn dispatch(c: &mut Criterion) {
+    let service = BnBench::new_binder(BenchService, BinderFeatures::default());
+    let binder = service.as_binder();
+
+    // The NDK checks the interface token against the class's precomputed
+    // UTF-16 descriptor before calling on_transact.
+    c.bench_function("transact_checks_interface_token", |b| {
+        b.iter(|| binder.transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(())).unwrap())
+    });
+
+    let class = binder.get_class().unwrap();
+    let descriptor = <BnBench as Remotable>::get_descriptor();
+    c.bench_function("class_has_descriptor", |b| {
+        b.iter(|| class.has_descriptor(black_box(descriptor)))
+    });
+    c.bench_function("class_get_descriptor_eq", |b| {
+        b.iter(|| class.get_descriptor() == black_box(descriptor))
+    });
+
+    c.bench_function("try_from_same_descriptor", |b| {
+        b.iter(|| {
+            let converted: binder::Strong<dyn IBenchSameDescriptor> =
+                FromIBinder::try_from(binder.clone()).unwrap();
+            converted
+        })
+    });
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
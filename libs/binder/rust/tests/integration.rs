@@ -95,6 +95,8 @@ enum TestTransactionCode {
     GetDumpArgs,
     GetSelinuxContext,
     GetIsHandlingTransaction,
+    IsDataSensitive,
+    GetSensitiveReply,
 }
 
 impl TryFrom<u32> for TestTransactionCode {
@@ -112,6 +114,12 @@ impl TryFrom<u32> for TestTransactionCode {
             _ if c == TestTransactionCode::GetIsHandlingTransaction as u32 => {
                 Ok(TestTransactionCode::GetIsHandlingTransaction)
             }
+            _ if c == TestTransactionCode::IsDataSensitive as u32 => {
+                Ok(TestTransactionCode::IsDataSensitive)
+            }
+            _ if c == TestTransactionCode::GetSensitiveReply as u32 => {
+                Ok(TestTransactionCode::GetSensitiveReply)
+            }
             _ => Err(StatusCode::UNKNOWN_TRANSACTION),
         }
     }
@@ -189,7 +197,7 @@ declare_binder_interface! {
 fn on_transact(
     service: &dyn ITest,
     code: TransactionCode,
-    _data: &BorrowedParcel<'_>,
+    data: &BorrowedParcel<'_>,
     reply: &mut BorrowedParcel<'_>,
 ) -> Result<(), StatusCode> {
     match code.try_into()? {
@@ -199,6 +207,11 @@ fn on_transact(
         TestTransactionCode::GetIsHandlingTransaction => {
             reply.write(&service.get_is_handling_transaction()?)
         }
+        TestTransactionCode::IsDataSensitive => reply.write(&data.is_sensitive()),
+        TestTransactionCode::GetSensitiveReply => {
+            reply.mark_sensitive();
+            reply.write(&service.test()?)
+        }
     }
 }
 
@@ -379,21 +392,27 @@ mod tests {
     use std::process::{Child, Command};
     use std::ptr;
     use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::Arc;
+    use std::sync::{mpsc, Arc, Mutex};
     use std::thread;
     use std::time::Duration;
 
+    use binder::event_bus::{self, EventBus};
     use binder::{
         BinderFeatures, DeathRecipient, FromIBinder, IBinder, Interface, SpIBinder, StatusCode,
         Strong,
     };
     // Import from impl API for testing only, should not be necessary as long as
     // you are using AIDL.
-    use binder::binder_impl::{Binder, IBinderInternal, TransactionCode};
+    use binder::binder_impl::{
+        Binder, IBinderInternal, TransactionCode, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF,
+    };
 
     use binder_tokio::Tokio;
 
-    use super::{BnTest, IATest, ITest, ITestSameDescriptor, TestService, RUST_SERVICE_BINARY};
+    use super::{
+        BnTest, IATest, ITest, ITestSameDescriptor, TestService, TestTransactionCode,
+        RUST_SERVICE_BINARY,
+    };
 
     pub struct ScopedServiceProcess(Child);
 
@@ -419,6 +438,10 @@ mod tests {
             let child = command.spawn().expect("Could not start service");
             Self(child)
         }
+
+        pub fn pid(&self) -> u32 {
+            self.0.id()
+        }
     }
 
     impl Drop for ScopedServiceProcess {
@@ -579,6 +602,68 @@ mod tests {
         assert_eq!(test_client.test().unwrap(), "trivial_client_test");
     }
 
+    #[test]
+    fn remote_service_has_partition_stability() {
+        let service_name = "partition_stability_test";
+        let _process = ScopedServiceProcess::new(service_name);
+        let mut binder =
+            binder::wait_for_service(service_name).expect("Did not get manager binder service");
+        assert!(binder.has_partition_stability());
+        let mut local =
+            BnTest::new_binder(TestService::new("local"), BinderFeatures::default()).as_binder();
+        assert!(local.has_partition_stability());
+    }
+
+    #[test]
+    fn timed_client_gets_reply() {
+        let service_name = "timed_client_test";
+        let _process = ScopedServiceProcess::new(service_name);
+        let test_client: Strong<dyn ITest> =
+            binder::get_interface(service_name).expect("Did not get manager binder service");
+        let reply = binder::with_transaction_timeout(Some(Duration::from_secs(10)), || {
+            test_client.get_is_handling_transaction()
+        });
+        // The reply comes from the service process, not from a local call.
+        assert_eq!(reply, Ok(true));
+    }
+
+    #[test]
+    fn clear_buf_marks_data_and_reply_sensitive() {
+        let local =
+            BnTest::new_binder(TestService::new("local"), BinderFeatures::default()).as_binder();
+        for (flags, sensitive) in [(0, false), (FLAG_CLEAR_BUF, true)] {
+            let reply = local
+                .transact(
+                    TestTransactionCode::IsDataSensitive as TransactionCode,
+                    flags,
+                    |_| Ok(()),
+                )
+                .unwrap();
+            // The local service sees the very parcel the data was written to.
+            assert_eq!(reply.read::<bool>(), Ok(sensitive));
+            assert_eq!(reply.is_sensitive(), sensitive);
+        }
+    }
+
+    #[test]
+    fn sensitive_reply_is_sent_with_clear_buf() {
+        let service_name = "sensitive_reply_test";
+        let _process = ScopedServiceProcess::new(service_name);
+        let remote =
+            binder::get_service(service_name).expect("Did not get sensitive_reply_test service");
+
+        let reply = remote
+            .transact(TestTransactionCode::GetSensitiveReply as TransactionCode, 0, |_| Ok(()))
+            .unwrap();
+        assert_eq!(reply.read::<String>().as_deref(), Ok(service_name));
+        // Only a reply sent with TF_CLEAR_BUF is marked sensitive on this side.
+        assert!(reply.is_sensitive());
+
+        let reply =
+            remote.transact(TestTransactionCode::Test as TransactionCode, 0, |_| Ok(())).unwrap();
+        assert!(!reply.is_sensitive());
+    }
+
     #[tokio::test]
     async fn trivial_client_async() {
         let service_name = "trivial_client_test";
@@ -608,6 +693,53 @@ mod tests {
         assert_eq!(test_client.test().await.unwrap(), "wait_for_trivial_client_test");
     }
 
+    #[test]
+    fn layered_client() {
+        use binder::binder_impl::{NextLayer, Parcel, TransactionFlags, TransactionLayer};
+        use std::sync::atomic::AtomicUsize;
+
+        struct CountingLayer {
+            codes: Mutex<Vec<TransactionCode>>,
+            replies: AtomicUsize,
+        }
+
+        impl TransactionLayer for CountingLayer {
+            fn transact(
+                &self,
+                code: TransactionCode,
+                data: Parcel,
+                flags: TransactionFlags,
+                next: NextLayer<'_>,
+            ) -> Result<Parcel, StatusCode> {
+                self.codes.lock().unwrap().push(code);
+                let reply = next.transact(code, data, flags);
+                if reply.is_ok() {
+                    self.replies.fetch_add(1, Ordering::SeqCst);
+                }
+                reply
+            }
+        }
+
+        let service_name = "layered_client_test";
+        let _process = ScopedServiceProcess::new(service_name);
+        let test_client: Strong<dyn ITest> =
+            binder::wait_for_interface(service_name).expect("Did not get test binder service");
+
+        let layer =
+            Arc::new(CountingLayer { codes: Mutex::new(vec![]), replies: AtomicUsize::new(0) });
+        let layered_client = test_client.with_layer(layer.clone()).unwrap();
+
+        assert_eq!(test_client.test().unwrap(), "layered_client_test");
+        assert!(layer.codes.lock().unwrap().is_empty());
+
+        assert_eq!(layered_client.test().unwrap(), "layered_client_test");
+        // Clones keep their layers.
+        assert_eq!(layered_client.clone().test().unwrap(), "layered_client_test");
+        assert_eq!(layer.replies.load(Ordering::SeqCst), 2);
+        let expected_code = super::TestTransactionCode::Test as TransactionCode;
+        assert_eq!(*layer.codes.lock().unwrap(), vec![expected_code; 2]);
+    }
+
     fn get_expected_selinux_context() -> &'static str {
         // SAFETY: The pointer we pass to `getcon` is valid because it comes from a reference, and
         // `getcon` doesn't retain it after it returns. If `getcon` succeeds then `out_ptr` will
@@ -808,19 +940,51 @@ mod tests {
         bools.assert_dropped();
     }
 
+    /// Publishing to a topic with a dead remote subscriber should still
+    /// deliver the event to the other subscribers, and drop the dead one.
+    #[test]
+    fn event_bus_publishes_past_dead_subscriber() {
+        let service_name = "event_bus_publishes_past_dead_subscriber";
+        let service_process = ScopedServiceProcess::new(service_name);
+        let remote = binder::get_service(service_name).expect("Could not retrieve service");
+
+        let bus = EventBus::new();
+        bus.as_binder()
+            .transact(FIRST_CALL_TRANSACTION, 0, |mut data| {
+                data.write("level")?;
+                data.write(&remote)
+            })
+            .expect("Could not subscribe the remote service");
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let _subscription = event_bus::subscribe(&bus.as_binder(), "level", move |level: i32| {
+            sender.lock().unwrap().send(level).unwrap();
+        })
+        .expect("Could not subscribe locally");
+        assert_eq!(bus.subscriber_count("level"), 2);
+
+        drop(service_process);
+        remote.ping_binder().expect_err("Service should have died already");
+
+        assert_eq!(bus.publish("level", &87), Ok(1));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [87]);
+        assert_eq!(bus.subscriber_count("level"), 1);
+    }
+
     /// Test IBinder interface methods not exercised elsewhere.
     #[test]
     fn test_misc_ibinder() {
         let service_name = "rust_test_ibinder";
 
         {
-            let _process = ScopedServiceProcess::new(service_name);
+            let process = ScopedServiceProcess::new(service_name);
 
             let test_client: Strong<dyn ITest> =
                 binder::get_interface(service_name).expect("Did not get test binder service");
             let mut remote = test_client.as_binder();
             assert!(remote.is_binder_alive());
             remote.ping_binder().expect("Could not ping remote service");
+            assert_eq!(remote.remote_pid().map(|pid| pid as u32), Ok(process.pid()));
 
             let dump_args = ["dump", "args", "for", "testing"];
 
@@ -962,6 +1126,112 @@ mod tests {
         assert_eq!(service, clone_upgraded);
     }
 
+    #[test]
+    fn swappable_binder() {
+        let first = BnTest::new_binder(TestService::new("first"), BinderFeatures::default());
+        let swappable = binder::SwappableBinder::<BnTest>::new(&first).unwrap();
+        let client: Strong<dyn ITest> = swappable.as_binder().into_interface().unwrap();
+        assert_eq!(client.test().unwrap(), "first");
+
+        let second = BnTest::new_binder(TestService::new("second"), BinderFeatures::default());
+        swappable.swap(&second).unwrap();
+        assert_eq!(client.test().unwrap(), "second");
+
+        // The client is a proxy, not a local `BnTest`.
+        assert_eq!(swappable.swap(&client), Err(StatusCode::BAD_TYPE));
+        assert_eq!(client.test().unwrap(), "second");
+    }
+
+    #[test]
+    fn binder_identity() {
+        let service1 =
+            BnTest::new_binder(TestService::new("testing_service1"), BinderFeatures::default());
+        let service2 =
+            BnTest::new_binder(TestService::new("testing_service2"), BinderFeatures::default());
+        let weak = Strong::downgrade(&service1);
+
+        assert_eq!(Strong::id(&service1), service1.as_binder().id());
+        assert_eq!(weak.id(), Some(Strong::id(&service1)));
+        assert!(binder::same_binder(&service1, &service1.as_binder()));
+        assert!(binder::same_binder(&service1, &weak.upgrade().unwrap()));
+        assert!(!binder::same_binder(&service1, &service2));
+
+        drop(service1);
+        assert_eq!(weak.id(), None);
+    }
+
+    #[test]
+    fn topology_counts_proxies() {
+        let service_name = "rust_test_topology";
+        let _process = ScopedServiceProcess::new(service_name);
+
+        let _test_client: Strong<dyn ITest> =
+            binder::get_interface(service_name).expect("Did not get test binder service");
+        // Other tests create and drop proxies concurrently, so only check that
+        // ours is counted.
+        let topology = binder::debug::topology();
+        assert!(topology.proxies.get("android.os.ITest").is_some_and(|&count| count >= 1));
+    }
+
+    #[test]
+    fn undeclared_hal_has_no_instances() {
+        use binder::binder_impl::InterfaceDescriptor;
+
+        assert_eq!(<dyn ITest as InterfaceDescriptor>::descriptor(), "android.os.ITest");
+        let instances = binder::hal::instances_of::<dyn ITest>().expect("Could not list instances");
+        assert!(instances.is_empty());
+    }
+
+    #[test]
+    fn accessor_from_binder_checks_instance() {
+        let instance = "android.os.ITest/rust_test_accessor";
+        let accessor =
+            rpcbinder::Accessor::new(instance, |_| None).expect("Could not create accessor");
+        let binder = accessor.as_binder().expect("Accessor has no binder");
+
+        let wrapped = rpcbinder::Accessor::from_binder(instance, binder.clone())
+            .expect("Could not wrap accessor binder");
+        assert_eq!(wrapped.instance(), instance);
+        assert_eq!(wrapped.as_binder(), Some(binder.clone()));
+        assert!(rpcbinder::Accessor::from_binder("android.os.ITest/other", binder).is_none());
+
+        let service =
+            BnTest::new_binder(TestService::new("not_an_accessor"), BinderFeatures::default());
+        assert!(rpcbinder::Accessor::from_binder(instance, service.as_binder()).is_none());
+    }
+
+    #[test]
+    fn delegated_accessor_reports_new_instance() {
+        let instance = "android.os.ITest/rust_test_delegated";
+        let target = "android.os.ITest/rust_test_delegate_target";
+        let accessor =
+            rpcbinder::Accessor::new(target, |_| None).expect("Could not create accessor");
+
+        let delegator = accessor.delegate(instance).expect("Could not delegate accessor");
+        assert_eq!(delegator.instance(), instance);
+        let binder = delegator.as_binder().expect("Delegator has no binder");
+        assert!(rpcbinder::Accessor::from_binder(instance, binder).is_some());
+    }
+
+    #[test]
+    fn objects_attach_to_proxies() {
+        let service_name = "rust_test_attach_object";
+        let _process = ScopedServiceProcess::new(service_name);
+
+        struct Session(u32);
+        let first = binder::get_service(service_name).expect("Did not get test binder service");
+        assert!(first.get_object::<Session>().is_none());
+        assert!(first.attach_object(Arc::new(Session(1))).is_ok());
+        let rejected = first.attach_object(Arc::new(Session(2))).unwrap_err();
+        assert_eq!(rejected.0, 2);
+
+        // Another handle to the same proxy sees the same object.
+        let second = binder::get_service(service_name).expect("Did not get test binder service");
+        assert_eq!(second.get_object::<Session>().map(|session| session.0), Some(1));
+        assert_eq!(second.get_or_attach_object(|| Session(3)).map(|session| session.0), Some(1));
+        assert_eq!(second.get_or_attach_object(|| 7u64).as_deref(), Some(&7));
+    }
+
     #[test]
     #[allow(clippy::eq_op)]
     fn binder_ord() {
@@ -0,0 +1,297 @@
+/*
+ * Copyright (C) 2024 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests for the derive and attribute macros of `binder_macros`.
+
+use binder::binder_impl::{Deserialize, Parcel, Serialize};
+use binder::{ExceptionCode, MethodPermission, PermissionTable, Status, StatusCode};
+use binder_macros::{
+    permission_table, requires_permission, BinderStatus, Parcelable, ParcelableEnum,
+};
+use std::error::Error;
+
+#[derive(Debug, Default, PartialEq, Parcelable)]
+struct Config {
+    name: String,
+    retries: i32,
+    tags: Vec<String>,
+    child: Option<Child>,
+}
+
+#[derive(Debug, Default, PartialEq, Parcelable)]
+struct Child(i64, bool);
+
+#[derive(Debug, Default, PartialEq, Parcelable)]
+struct Empty;
+
+#[derive(Clone, Copy, Debug, PartialEq, ParcelableEnum)]
+#[repr(i8)]
+enum Level {
+    Low = -1,
+    High = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ParcelableEnum)]
+#[repr(i32)]
+enum Mode {
+    Off = 0,
+    On = 1,
+    #[parcel(unknown)]
+    Unknown(i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ParcelableEnum)]
+#[repr(i64)]
+enum Big {
+    Small = 1,
+    Huge = i64::MAX,
+}
+
+const NOT_FOUND: i32 = 3;
+
+#[derive(Debug, thiserror::Error, BinderStatus)]
+#[binder_status(exception = ILLEGAL_STATE)]
+enum StoreError {
+    #[error("no key named {0}")]
+    #[binder_status(code = NOT_FOUND)]
+    NotFound(String),
+    #[error("invalid key {key:?}")]
+    #[binder_status(exception = ILLEGAL_ARGUMENT)]
+    InvalidKey { key: String },
+    #[error("store is closed")]
+    Closed,
+    #[error(transparent)]
+    #[binder_status(transparent)]
+    Binder(#[from] Status),
+}
+
+#[derive(Debug, thiserror::Error, BinderStatus)]
+#[error("over quota by {0} bytes")]
+#[binder_status(code = NOT_FOUND + 1)]
+struct QuotaError(u64);
+
+trait IStore {
+    fn get(&self) -> binder::Result<i32>;
+    fn clear(&self) -> binder::Result<()>;
+    fn size(&self) -> binder::Result<i32>;
+}
+
+struct Store;
+
+#[permission_table]
+impl IStore for Store {
+    #[requires_permission("android.permission.READ_STORE")]
+    fn get(&self) -> binder::Result<i32> {
+        Ok(1)
+    }
+
+    #[requires_permission("android.permission.READ_STORE")]
+    #[requires_permission("android.permission.WRITE_STORE")]
+    fn clear(&self) -> binder::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> binder::Result<i32> {
+        Ok(1)
+    }
+}
+
+/// `Config` as an older version, without the fields added since.
+#[derive(Debug, Default, PartialEq, Parcelable)]
+struct OldConfig {
+    name: String,
+    retries: i32,
+}
+
+fn config() -> Config {
+    Config {
+        name: "foo".to_string(),
+        retries: 3,
+        tags: vec!["a".to_string(), "b".to_string()],
+        child: Some(Child(-7, true)),
+    }
+}
+
+/// Writes `value` to a parcel, followed by a marker, and reads it back as a `U`, checking that
+/// exactly what was written was read.
+fn round_trip<T: Serialize, U: Deserialize>(value: &T) -> Result<U, StatusCode> {
+    let mut parcel = Parcel::new();
+    parcel.write(value)?;
+    parcel.write(&0x1234_5678i32)?;
+    parcel.try_set_data_position(0)?;
+    let read = parcel.read()?;
+    assert_eq!(parcel.read::<i32>(), Ok(0x1234_5678));
+    Ok(read)
+}
+
+#[test]
+fn derived_parcelable_round_trips() {
+    assert_eq!(round_trip::<_, Config>(&config()), Ok(config()));
+    assert_eq!(round_trip::<_, Config>(&Config::default()), Ok(Config::default()));
+    assert_eq!(round_trip::<_, Child>(&Child(i64::MIN, false)), Ok(Child(i64::MIN, false)));
+    assert_eq!(round_trip::<_, Empty>(&Empty), Ok(Empty));
+}
+
+#[test]
+fn derived_parcelable_round_trips_in_options_and_arrays() {
+    assert_eq!(round_trip::<_, Option<Config>>(&Some(config())), Ok(Some(config())));
+    assert_eq!(round_trip::<_, Option<Config>>(&None::<Config>), Ok(None));
+
+    let configs = vec![config(), Config::default()];
+    assert_eq!(round_trip::<_, Vec<Config>>(&configs), Ok(configs));
+    let children = Some(vec![Some(Child(1, false)), None]);
+    assert_eq!(round_trip::<_, Option<Vec<Option<Child>>>>(&children), Ok(children));
+}
+
+#[test]
+fn derived_parcelable_keeps_defaults_for_fields_missing_from_older_versions() {
+    let old = OldConfig { name: "foo".to_string(), retries: 3 };
+    let expected = Config { name: "foo".to_string(), retries: 3, ..Default::default() };
+    assert_eq!(round_trip::<_, Config>(&old), Ok(expected));
+}
+
+#[test]
+fn derived_parcelable_skips_fields_added_by_newer_versions() {
+    let expected = OldConfig { name: "foo".to_string(), retries: 3 };
+    assert_eq!(round_trip::<_, OldConfig>(&config()), Ok(expected));
+    assert_eq!(round_trip::<_, Empty>(&config()), Ok(Empty));
+}
+
+#[test]
+fn derived_parcelable_rejects_null() {
+    assert_eq!(round_trip::<_, Config>(&None::<Config>), Err(StatusCode::UNEXPECTED_NULL));
+}
+
+#[test]
+fn derived_enum_round_trips() {
+    assert_eq!(round_trip::<_, Level>(&Level::Low), Ok(Level::Low));
+    assert_eq!(round_trip::<_, Level>(&Level::High), Ok(Level::High));
+    assert_eq!(round_trip::<_, Mode>(&Mode::On), Ok(Mode::On));
+    assert_eq!(round_trip::<_, Big>(&Big::Huge), Ok(Big::Huge));
+    assert_eq!(round_trip::<_, Big>(&Big::Small), Ok(Big::Small));
+
+    let modes = vec![Mode::Off, Mode::On, Mode::Unknown(7)];
+    assert_eq!(round_trip::<_, Vec<Mode>>(&modes), Ok(modes));
+    assert_eq!(round_trip::<_, Option<Vec<Big>>>(&None::<Vec<Big>>), Ok(None));
+}
+
+#[test]
+fn derived_enum_is_written_as_its_backing_type() {
+    assert_eq!(round_trip::<_, i8>(&Level::Low), Ok(-1));
+    assert_eq!(round_trip::<_, i32>(&Mode::On), Ok(1));
+    assert_eq!(round_trip::<_, i64>(&Big::Huge), Ok(i64::MAX));
+    assert_eq!(round_trip::<_, Vec<i8>>(&vec![Level::High, Level::Low]), Ok(vec![1, -1]));
+    assert_eq!(round_trip::<_, Level>(&1i8), Ok(Level::High));
+
+    assert_eq!(i32::from(Mode::Unknown(-3)), -3);
+    assert_eq!(Mode::try_from(0), Ok(Mode::Off));
+}
+
+#[test]
+fn derived_enum_rejects_unknown_discriminants() {
+    assert_eq!(round_trip::<_, Level>(&0i8), Err(StatusCode::BAD_VALUE));
+    assert_eq!(round_trip::<_, Big>(&2i64), Err(StatusCode::BAD_VALUE));
+    assert_eq!(round_trip::<_, Vec<Level>>(&vec![1i8, 5]), Err(StatusCode::BAD_VALUE));
+    assert_eq!(Level::try_from(i8::MIN), Err(StatusCode::BAD_VALUE));
+}
+
+#[test]
+fn derived_enum_keeps_unknown_discriminants_in_the_unknown_variant() {
+    assert_eq!(round_trip::<_, Mode>(&42i32), Ok(Mode::Unknown(42)));
+    assert_eq!(round_trip::<_, i32>(&Mode::Unknown(42)), Ok(42));
+    assert_eq!(round_trip::<_, Vec<Mode>>(&vec![1i32, -5]), Ok(vec![Mode::On, Mode::Unknown(-5)]));
+}
+
+#[test]
+fn derived_binder_status_uses_the_code_or_exception_of_each_variant() {
+    let status = Status::from(StoreError::NotFound("foo".to_string()));
+    assert_eq!(status.exception_code(), ExceptionCode::SERVICE_SPECIFIC);
+    assert_eq!(status.service_specific_error(), NOT_FOUND);
+    assert_eq!(status.get_description(), "Status(-8, EX_SERVICE_SPECIFIC): '3: no key named foo'");
+
+    let status = Status::from(StoreError::InvalidKey { key: "a b".to_string() });
+    assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_ARGUMENT);
+    assert_eq!(status.get_description(), "Status(-3, EX_ILLEGAL_ARGUMENT): 'invalid key \"a b\"'");
+}
+
+#[test]
+fn derived_binder_status_falls_back_to_the_attribute_on_the_enum() {
+    let status = Status::from(StoreError::Closed);
+    assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_STATE);
+    assert_eq!(status.get_description(), "Status(-5, EX_ILLEGAL_STATE): 'store is closed'");
+}
+
+#[test]
+fn derived_binder_status_passes_on_transparent_variants() {
+    let inner = Status::new_service_specific_error_str(9, Some("busy"));
+    let status = Status::from(StoreError::from(inner));
+    assert_eq!(status, Status::new_service_specific_error_str(9, Some("busy")));
+    assert!(status.source().is_none());
+
+    let status = Status::from(StoreError::Binder(StatusCode::DEAD_OBJECT.into()));
+    assert_eq!(status.transaction_error(), StatusCode::DEAD_OBJECT);
+}
+
+#[test]
+fn derived_binder_status_keeps_the_error_as_the_source() {
+    let status = Status::from(StoreError::NotFound("foo".to_string()));
+    let source = status.source().unwrap().downcast_ref::<StoreError>().unwrap();
+    assert!(matches!(source, StoreError::NotFound(key) if key == "foo"));
+}
+
+#[test]
+fn derived_binder_status_on_a_struct() {
+    let status = Status::from(QuotaError(512));
+    assert_eq!(status.service_specific_error(), NOT_FOUND + 1);
+    assert_eq!(
+        status.get_description(),
+        "Status(-8, EX_SERVICE_SPECIFIC): '4: over quota by 512 bytes'"
+    );
+    assert!(status.source().unwrap().downcast_ref::<QuotaError>().is_some());
+}
+
+#[test]
+fn derived_binder_status_works_with_the_question_mark_operator() {
+    fn lookup(key: &str) -> binder::Result<()> {
+        Err(StoreError::NotFound(key.to_string()))?
+    }
+    assert_eq!(lookup("foo").unwrap_err().service_specific_error(), NOT_FOUND);
+}
+
+#[test]
+fn permission_table_lists_the_permissions_of_each_method() {
+    assert_eq!(
+        <Store as PermissionTable<dyn IStore>>::PERMISSIONS,
+        &[
+            MethodPermission { method: "get", permission: "android.permission.READ_STORE" },
+            MethodPermission { method: "clear", permission: "android.permission.READ_STORE" },
+            MethodPermission { method: "clear", permission: "android.permission.WRITE_STORE" },
+        ]
+    );
+}
+
+#[test]
+fn permission_table_leaves_the_methods_enforcing_their_permissions() {
+    binder::set_permission_checker(|permission, _pid, _uid| {
+        permission == "android.permission.READ_STORE"
+    });
+    assert_eq!(Store.get(), Ok(1));
+    assert_eq!(Store.size(), Ok(1));
+    let status = Store.clear().unwrap_err();
+    assert_eq!(status.exception_code(), ExceptionCode::SECURITY);
+    assert!(status.get_description().contains("android.permission.WRITE_STORE"));
+    binder::clear_permission_checker();
+}
@@ -364,6 +364,7 @@ std::unique_ptr<ProcessSession> BinderRpc::createRpcTestSocketServerProcessEtc(
         session->setMaxIncomingThreads(numIncoming);
         session->setMaxOutgoingConnections(options.numOutgoingConnections);
         session->setFileDescriptorTransportMode(options.clientFileDescriptorTransportMode);
+        session->setIntegrityMode(options.clientIntegrityMode);
 
         sockaddr_storage addr{};
         socklen_t addrLen = 0;
@@ -964,6 +965,28 @@ TEST_P(BinderRpc, FileDescriptorTransportOptionalUnix) {
     EXPECT_EQ(status.transactionError(), FDS_NOT_ALLOWED) << status;
 }
 
+TEST_P(BinderRpc, IntegrityModeCrc32) {
+    if (socketType() == SocketType::TIPC) {
+        GTEST_SKIP() << "Integrity checks are not supported on Trusty (yet)";
+    }
+
+    auto proc = createRpcTestSocketServerProcess({
+            .clientIntegrityMode = RpcSession::IntegrityMode::CRC32,
+    });
+    EXPECT_EQ(RpcSession::IntegrityMode::CRC32,
+              proc.proc->sessions.at(0).session->getIntegrityMode());
+
+    std::string doubled;
+    EXPECT_OK(proc.rootIface->doubleString("aoeu", &doubled));
+    EXPECT_EQ("aoeuaoeu", doubled);
+
+    sp<IBinder> out;
+    EXPECT_OK(proc.rootIface->repeatBinder(proc.rootBinder, &out));
+    EXPECT_EQ(proc.rootBinder, out);
+
+    EXPECT_OK(proc.rootIface->sendString("oneway"));
+}
+
 TEST_P(BinderRpc, ReceiveFile) {
     if (socketType() == SocketType::TIPC) {
         GTEST_SKIP() << "File descriptor tests not supported on Trusty (yet)";
@@ -1287,6 +1310,57 @@ TEST_P(BinderRpcAccessor, InjectNoSockaddrProvided) {
     EXPECT_EQ(status, OK);
 }
 
+TEST(BinderRpcAccessorUnix, AbstractSocketAddress) {
+    if constexpr (!kEnableRpcThreads) {
+        GTEST_SKIP() << "Test skipped because threads were disabled at build time";
+    }
+    const String16 kInstanceName("super.cool.service/abstract");
+    const std::string kAbstractName = "binderRpcTest_accessor_" + std::to_string(getpid());
+
+    // The accessor only gets the sockaddr back from the provider, so the address length has to
+    // be recovered from the abstract name rather than measured with strlen.
+    sockaddr_un abstractAddr{.sun_family = AF_UNIX};
+    ASSERT_LT(kAbstractName.size() + 1, sizeof(abstractAddr.sun_path));
+    memcpy(abstractAddr.sun_path + 1, kAbstractName.data(), kAbstractName.size());
+    const socklen_t abstractAddrLen = offsetof(sockaddr_un, sun_path) + 1 + kAbstractName.size();
+
+    unique_fd serverFd(TEMP_FAILURE_RETRY(socket(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0)));
+    ASSERT_TRUE(serverFd.ok()) << strerror(errno);
+    ASSERT_EQ(0,
+              TEMP_FAILURE_RETRY(bind(serverFd.get(), reinterpret_cast<sockaddr*>(&abstractAddr),
+                                      abstractAddrLen)))
+            << strerror(errno);
+    ASSERT_EQ(0, TEMP_FAILURE_RETRY(listen(serverFd.get(), 1))) << strerror(errno);
+
+    auto server = RpcServer::make();
+    server->setRootObject(sp<BBinder>::make());
+    ASSERT_EQ(OK, server->setupRawSocketServer(std::move(serverFd)));
+    std::thread serverThread([server] { server->join(); });
+
+    auto receipt = addAccessorProvider([&](const String16& name) -> sp<IBinder> {
+        return createAccessor(name,
+                              [&](const String16& name, sockaddr* outAddr,
+                                  socklen_t addrSize) -> status_t {
+                                  if (outAddr == nullptr || addrSize < sizeof(abstractAddr)) {
+                                      return BAD_VALUE;
+                                  }
+                                  if (name != kInstanceName) return NAME_NOT_FOUND;
+                                  std::memcpy(outAddr, &abstractAddr, sizeof(abstractAddr));
+                                  return OK;
+                              });
+    });
+    EXPECT_FALSE(receipt.expired());
+
+    sp<IBinder> binder = defaultServiceManager()->checkService(kInstanceName);
+    ASSERT_NE(binder, nullptr);
+    EXPECT_EQ(OK, binder->pingBinder());
+    binder.clear();
+
+    EXPECT_EQ(OK, removeAccessorProvider(receipt));
+    EXPECT_TRUE(server->shutdown());
+    serverThread.join();
+}
+
 #endif // BINDER_WITH_KERNEL_IPC
 
 #ifdef BINDER_RPC_TO_TRUSTY_TEST
@@ -1644,6 +1718,131 @@ INSTANTIATE_TEST_SUITE_P(BinderRpc, BinderRpcServerOnly,
                                             ::testing::ValuesIn(testVersions())),
                          BinderRpcServerOnly::PrintTestParam);
 
+// Forwards the connections of a session to a server, and can flip a byte of the client's commands
+// on the way, to check that integrity checks catch it.
+class CorruptingProxy {
+public:
+    static constexpr int32_t kMarker = 0x12345678;
+    static constexpr int32_t kCorruptedMarker = kMarker ^ 0xFF;
+
+    explicit CorruptingProxy(std::string serverAddr) : mServerAddr(std::move(serverAddr)) {}
+    ~CorruptingProxy() {
+        for (auto& thread : mThreads) thread.join();
+    }
+
+    // Returns the client end of a new connection to the server.
+    unique_fd connect() {
+        unique_fd clientEnd, proxyEnd;
+        if (!binder::Socketpair(SOCK_STREAM, &clientEnd, &proxyEnd)) PLOGF("Failed socketpair()");
+        unique_fd serverEnd = connectTo(UnixSocketAddress(mServerAddr.c_str()));
+        std::lock_guard<std::mutex> _l(mMutex);
+        mThreads.emplace_back(&CorruptingProxy::forward, this, std::move(proxyEnd),
+                              std::move(serverEnd));
+        return clientEnd;
+    }
+
+    // Flip the lowest byte of the next kMarker the client sends.
+    void corruptNextMarker() { mCorrupt = true; }
+
+private:
+    void forward(unique_fd client, unique_fd server) {
+        pollfd pfd[]{{.fd = client.get(), .events = POLLIN},
+                     {.fd = server.get(), .events = POLLIN}};
+        while (TEMP_FAILURE_RETRY(poll(pfd, countof(pfd), -1)) > 0) {
+            bool fromClient = pfd[0].revents != 0;
+            int from = fromClient ? client.get() : server.get();
+            int to = fromClient ? server.get() : client.get();
+            uint8_t buf[4096];
+            ssize_t n = TEMP_FAILURE_RETRY(read(from, buf, sizeof(buf)));
+            if (n <= 0) break;
+            if (fromClient && mCorrupt) {
+                auto* marker = static_cast<uint8_t*>(memmem(buf, n, &kMarker, sizeof(kMarker)));
+                if (marker != nullptr && mCorrupt.exchange(false)) *marker ^= 0xFF;
+            }
+            if (!binder::WriteFully(to, buf, n)) break;
+        }
+        // Pass on the end of the connection in both directions.
+        shutdown(client.get(), SHUT_RDWR);
+        shutdown(server.get(), SHUT_RDWR);
+    }
+
+    const std::string mServerAddr;
+    std::atomic<bool> mCorrupt = false;
+    std::mutex mMutex;
+    std::vector<std::thread> mThreads;
+};
+
+// Records the int32 sent with each transaction.
+class MarkerRecorder : public BBinder {
+public:
+    std::atomic<int32_t> received = 0;
+
+    status_t onTransact(uint32_t code, const Parcel& data, Parcel* reply,
+                        uint32_t flags) override {
+        if (code != IBinder::FIRST_CALL_TRANSACTION) {
+            return BBinder::onTransact(code, data, reply, flags);
+        }
+        received = data.readInt32();
+        return OK;
+    }
+};
+
+// Sends kMarker to a new server through a CorruptingProxy which corrupts it, and returns the
+// result of the transaction. 'received' is what the server got, or 0 if it got nothing.
+static status_t sendCorruptedMarker(RpcSession::IntegrityMode mode, int32_t* received) {
+    auto addr = allocateSocketAddress();
+    auto server = RpcServer::make();
+    auto root = sp<MarkerRecorder>::make();
+    server->setRootObject(root);
+    server->setMaxThreads(1);
+    if (status_t status = server->setupUnixDomainServer(addr.c_str()); status != OK) {
+        return status;
+    }
+    std::thread serverThread([server] { server->join(); });
+
+    status_t status;
+    {
+        CorruptingProxy proxy(addr);
+        auto session = RpcSession::make();
+        session->setMaxOutgoingConnections(1);
+        session->setIntegrityMode(mode);
+        status = session->setupPreconnectedClient({}, [&] { return proxy.connect(); });
+        if (status == OK) {
+            sp<IBinder> binder = session->getRootObject();
+            // Uncorrupted commands go through the proxy unchanged.
+            status = binder->pingBinder();
+            if (status == OK) {
+                Parcel data, reply;
+                data.markForBinder(binder);
+                data.writeInt32(CorruptingProxy::kMarker);
+                proxy.corruptNextMarker();
+                status = binder->transact(IBinder::FIRST_CALL_TRANSACTION, data, &reply);
+            }
+        }
+        (void)session->shutdownAndWait(true);
+    }
+
+    EXPECT_TRUE(server->shutdown());
+    serverThread.join();
+    *received = root->received;
+    return status;
+}
+
+TEST(BinderRpcIntegrity, CorruptedCommandIsRejected) {
+    if constexpr (!kEnableRpcThreads) {
+        GTEST_SKIP() << "Test skipped because threads were disabled at build time";
+    }
+
+    // Without integrity checks, the corruption goes unnoticed.
+    int32_t received = 0;
+    EXPECT_EQ(OK, sendCorruptedMarker(RpcSession::IntegrityMode::NONE, &received));
+    EXPECT_EQ(CorruptingProxy::kCorruptedMarker, received);
+
+    // With them, the server drops the session instead of handling the command.
+    EXPECT_EQ(DEAD_OBJECT, sendCorruptedMarker(RpcSession::IntegrityMode::CRC32, &received));
+    EXPECT_EQ(0, received);
+}
+
 class RpcTransportTestUtils {
 public:
     // Only parameterized only server version because `RpcSession` is bypassed
@@ -169,6 +169,7 @@ struct BinderRpcOptions {
     std::vector<RpcSession::FileDescriptorTransportMode>
             serverSupportedFileDescriptorTransportModes = {
                     RpcSession::FileDescriptorTransportMode::NONE};
+    RpcSession::IntegrityMode clientIntegrityMode = RpcSession::IntegrityMode::NONE;
 
     // If true, connection failures will result in `ProcessSession::sessions` being empty
     // instead of a fatal error.
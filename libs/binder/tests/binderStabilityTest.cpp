@@ -272,6 +272,38 @@ TEST(BinderStability, CantCallVendorBinderInSystemContext) {
     EXPECT_EQ(BAD_TYPE, BadStableBinder::doUserTransaction(out));
 }
 
+TEST(BinderStability, AllowsTransactionsFromMatchesTransactionCheck) {
+    EXPECT_FALSE(Stability::allowsTransactionsFrom(nullptr, false /*vendor*/));
+
+    // Transactions on local binders aren't checked.
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(BadStableBinder::undef(), false /*vendor*/));
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(BadStableBinder::vendor(), false /*vendor*/));
+
+    LIBBINDER_IGNORE("-Wdeprecated-declarations")
+    sp<IBinder> serverBinder = android::defaultServiceManager()->getService(kSystemStabilityServer);
+    LIBBINDER_IGNORE_END()
+    auto server = interface_cast<IBinderStabilityTest>(serverBinder);
+    ASSERT_NE(nullptr, server.get());
+
+    sp<IBinder> out;
+    EXPECT_TRUE(server->returnLocalStabilityBinder(&out).isOk());
+    ASSERT_NE(nullptr, out->remoteBinder());
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(out, false /*vendor*/));
+    EXPECT_FALSE(Stability::allowsTransactionsFrom(out, true /*vendor*/));
+    EXPECT_TRUE(
+            AIBinder_isCompilationUnitStable(SpAIBinder(AIBinder_fromPlatformBinder(out)).get()));
+
+    EXPECT_TRUE(server->returnVintfStabilityBinder(&out).isOk());
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(out, false /*vendor*/));
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(out, true /*vendor*/));
+
+    EXPECT_TRUE(server->returnVendorStabilityBinder(&out).isOk());
+    EXPECT_FALSE(Stability::allowsTransactionsFrom(out, false /*vendor*/));
+    EXPECT_TRUE(Stability::allowsTransactionsFrom(out, true /*vendor*/));
+    EXPECT_FALSE(
+            AIBinder_isCompilationUnitStable(SpAIBinder(AIBinder_fromPlatformBinder(out)).get()));
+}
+
 // This is handwritten so that we can test different stability levels w/o having the AIDL
 // compiler assign them. Hand-writing binder interfaces is considered a bad practice
 // sanity reasons. YOU SHOULD DEFINE AN AIDL INTERFACE INSTEAD!
@@ -36,5 +36,6 @@ MODULE_BINDGEN_FLAGS += \
 	--blocklist-type="AIBinder" \
 	--raw-line="use binder_ndk_sys::AIBinder;" \
 	--rustified-enum="ARpcSession_FileDescriptorTransportMode" \
+	--rustified-enum="ARpcSession_IntegrityMode" \
 
 include make/library.mk
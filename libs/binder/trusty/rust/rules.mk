@@ -35,7 +35,7 @@ MODULE_RUSTFLAGS += \
 	--cfg 'android_vendor' \
 	--cfg 'trusty' \
 
-# Trusty does not have `ProcessState`, so there are a few
+# Trusty does not have `ProcessState::join_thread_pool`, so there are a few
 # doc links in `IBinder` that are still broken.
 MODULE_RUSTFLAGS += \
 	--allow rustdoc::broken-intra-doc-links \
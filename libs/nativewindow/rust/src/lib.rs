@@ -278,6 +278,8 @@ impl Debug for HardwareBuffer {
     }
 }
 
+/// Cloning a `HardwareBuffer` shares the same underlying buffer, incrementing its refcount, rather
+/// than allocating a new one.
 impl Clone for HardwareBuffer {
     fn clone(&self) -> Self {
         // SAFETY: ptr is guaranteed to be non-null and the acquire can not fail.
@@ -338,6 +340,7 @@ unsafe impl Sync for HardwareBuffer {}
 #[cfg(test)]
 mod test {
     use super::*;
+    use binder::binder_impl::Parcel;
 
     #[test]
     fn create_valid_buffer_returns_ok() {
@@ -488,4 +491,30 @@ mod test {
         assert_eq!(buffer.description(), buffer_description);
         assert_eq!(buffer2.description(), buffer_description);
     }
+
+    #[test]
+    fn parcel_round_trip() {
+        let buffer = HardwareBuffer::new(&HardwareBufferDescription::new(
+            1024,
+            512,
+            1,
+            AHardwareBuffer_Format::AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM,
+            AHardwareBuffer_UsageFlags::AHARDWAREBUFFER_USAGE_CPU_READ_OFTEN,
+            0,
+        ))
+        .expect("Buffer with some basic parameters was not created successfully");
+
+        let mut parcel = Parcel::new();
+        parcel.write(&buffer).expect("Failed to write buffer to parcel");
+        parcel.write(&None::<HardwareBuffer>).expect("Failed to write null buffer to parcel");
+        // SAFETY: 0 is always a valid position.
+        unsafe { parcel.set_data_position(0) }.expect("Failed to rewind parcel");
+
+        let buffer2: HardwareBuffer = parcel.read().expect("Failed to read buffer from parcel");
+        assert_eq!(buffer2.id(), buffer.id());
+        assert_eq!(buffer2.description(), buffer.description());
+        let null_buffer: Option<HardwareBuffer> =
+            parcel.read().expect("Failed to read null buffer from parcel");
+        assert!(null_buffer.is_none());
+    }
 }